@@ -1,3 +1,5 @@
+use crate::scsi::commands::ScsiStatus;
+
 /// A general error struct for the package.
 #[derive(Copy, Clone, Eq, PartialEq, Hash, Debug)]
 pub struct ScsiError {
@@ -9,7 +11,11 @@ pub struct ScsiError {
 /// The cause of a returned error. This is implemented
 /// as an enum with a large number of variants to reduce
 /// allocations.
+///
+/// Some variants only exist when a feature is enabled, so matches on this enum
+/// need a wildcard arm.
 #[derive(Copy, Clone, Eq, PartialEq, Hash, Debug)]
+#[non_exhaustive]
 pub enum ErrorCause {
     /// The error was caused because the library failed to parse a byte buffer
     /// into a struct correctly.
@@ -55,6 +61,92 @@ pub enum ErrorCause {
 
     /// The error was thrown because we tried connecting to a device we don't support.
     InvalidDeviceError,
+
+    /// The error was thrown because a single command was asked to transfer more
+    /// blocks than it can address.
+    TransferTooLargeError {
+        /// The number of blocks that was requested.
+        actual: usize,
+
+        /// The largest number of blocks the command can transfer.
+        max: usize,
+    },
+
+    /// The error was thrown because we tried to access blocks past the end of
+    /// the device.
+    BlockAddressOutOfRangeError {
+        /// The first block of the rejected access.
+        block_address: u64,
+
+        /// The number of blocks on the device.
+        num_blocks: u64,
+    },
+
+    /// The error was thrown because we tried to write to a device that only
+    /// supports reading, such as a CD-ROM drive.
+    ReadOnlyDeviceError,
+
+    /// The error was thrown because a compare and write found that the blocks
+    /// on the device did not hold the expected data, so nothing was written.
+    MiscompareError {
+        /// The offset of the first differing byte within the expected data,
+        /// if the device reported it.
+        offset: Option<usize>,
+    },
+
+    /// The error was thrown because the device is reserved by another
+    /// initiator, whose reservation keeps us from running the command.
+    ReservationConflictError,
+
+    /// The error was thrown because the device finished a command with a
+    /// SCSI status that has no sense data to explain it, such as BUSY or TASK
+    /// SET FULL.
+    ///
+    /// Reservation conflicts are reported as a `ReservationConflictError`
+    /// instead.
+    StatusError {
+        /// The status the command finished with.
+        status: ScsiStatus,
+    },
+
+    /// The error was thrown because we tried to queue another command while
+    /// the maximum number of commands were already in flight.
+    QueueFullError {
+        /// The number of commands that can be in flight at once.
+        depth: usize,
+    },
+
+    /// The error was thrown because a network target refused to log us in,
+    /// for example because of a bad CHAP secret.
+    LoginError {
+        /// The class of the failure; 1 for redirection, 2 for an initiator
+        /// error and 3 for a target error.
+        status_class: u8,
+
+        /// The specific reason for the failure within `status_class`.
+        status_detail: u8,
+    },
+
+    /// The error was thrown because the operating system could not deliver a
+    /// command to the device, for example because it was unplugged or the
+    /// command timed out.
+    HostAdapterError {
+        /// The status reported by the host adapter driver, such as Linux's
+        /// `DID_*` codes.
+        host_status: u16,
+
+        /// The status reported by the mid-level driver, such as Linux's
+        /// `DRIVER_*` codes.
+        driver_status: u16,
+    },
+
+    /// The error was caused by a failed I/O operation from the standard library,
+    /// such as reading from a backing file.
+    #[cfg(feature = "std")]
+    IoError {
+        /// The kind of I/O error that occurred.
+        kind: ::std::io::ErrorKind,
+    },
 }
 
 /// The direction that the USB transfer was going when it errored.
@@ -76,3 +168,25 @@ impl ScsiError {
         ScsiError { cause }
     }
 }
+
+#[cfg(feature = "std")]
+impl From<::std::io::Error> for ScsiError {
+    fn from(err: ::std::io::Error) -> ScsiError {
+        ScsiError::from_cause(ErrorCause::IoError { kind: err.kind() })
+    }
+}
+
+#[cfg(feature = "std")]
+impl From<ScsiError> for ::std::io::Error {
+    fn from(err: ScsiError) -> ::std::io::Error {
+        let kind = match err.cause {
+            ErrorCause::IoError { kind } => kind,
+            ErrorCause::ReadOnlyDeviceError => ::std::io::ErrorKind::PermissionDenied,
+            ErrorCause::NonBlocksizeMultipleLengthError { .. }
+            | ErrorCause::BlockAddressOutOfRangeError { .. }
+            | ErrorCause::TransferTooLargeError { .. } => ::std::io::ErrorKind::InvalidInput,
+            _ => ::std::io::ErrorKind::Other,
+        };
+        ::std::io::Error::new(kind, format!("{:?}", err.cause))
+    }
+}
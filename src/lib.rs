@@ -4,13 +4,56 @@
 //! Currently the main focus of this crate is Bulk Only USB Mass Storage Device
 //! compatibility, since that comprises a significant chunk of use cases. However,
 //! more functionality can be requested and/or PRed as necessary or desired.
+//! Hosts talking to USB Attached SCSI devices can queue several commands at
+//! once through the `scsi::uas` module.
+//!
+//! Hosts and devices running on an async executor can implement
+//! `AsyncCommunicationChannel` instead of `CommunicationChannel`, and use
+//! `AsyncScsiBlockDevice` or `ScsiResponder::process_command_async`; neither
+//! needs `std` or an allocator. Device stacks that hand over bulk packets from
+//! interrupt handlers can instead feed them to a `BulkOnlyTransport`.
+//!
+//! The crate is `no_std` by default; enabling the `std` feature adds
+//! implementations that rely on the standard library, such as serving disc
+//! images straight from a `std::fs::File` or accessing an `ScsiBlockDevice`
+//! through `std::io::{Read, Write, Seek}`, which is what crates such as `fatfs`
+//! expect. It also enables the `scsi::iscsi` module for reaching targets over
+//! a network, the `scsi::usbip` module for exporting a responder as a virtual
+//! USB device, the `scsi::nbd` module for exporting an `ScsiBlockDevice` as a
+//! network block device, and on Linux, `SgDevice` for sending commands to any
+//! SCSI device the kernel knows about.
+//!
+//! The `rusb` feature adds `RusbChannel`, which talks to USB Mass Storage
+//! devices through libusb so that they can be used as an `ScsiBlockDevice`.
+//!
+//! The `embedded-sdmmc` feature implements that crate's `BlockDevice` trait
+//! for `ScsiBlockDevice`s, so FAT volumes can be mounted without `std`.
+//!
+//! The `usb-device` feature adds `UsbMassStorageClass`, which presents an
+//! `ScsiResponder` as a USB Mass Storage device on any `usb-device` bus, and
+//! the `embedded-io` feature adds `EmbeddedIoChannel`, a
+//! `CommunicationChannel` over `embedded_io::{Read, Write}` streams.
 
 #![warn(missing_docs)]
-#![cfg_attr(not(test), no_std)]
+#![cfg_attr(not(any(test, feature = "std")), no_std)]
 extern crate byteorder;
+#[cfg(any(test, feature = "std"))]
+extern crate core;
+#[cfg(feature = "embedded-io")]
+extern crate embedded_io;
+#[cfg(feature = "embedded-sdmmc")]
+extern crate embedded_sdmmc;
+#[cfg(test)]
+extern crate fatfs;
+#[cfg(all(feature = "std", target_os = "linux"))]
+extern crate libc;
+#[cfg(feature = "rusb")]
+extern crate rusb;
+#[cfg(feature = "usb-device")]
+extern crate usb_device;
 mod error;
 pub mod scsi;
 mod traits;
 
-pub use error::*;
-pub use traits::*;
+pub use crate::error::*;
+pub use crate::traits::*;
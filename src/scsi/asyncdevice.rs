@@ -0,0 +1,1495 @@
+use crate::error::{ErrorCause, ScsiError, UsbTransferDirection};
+use crate::scsi::commands::{BlockLimitsPage, Command, CommandStatusWrapper, Direction};
+use crate::scsi::commands::{BufferDescriptor, EchoBufferDescriptor};
+use crate::scsi::commands::{CompareAndWriteCommand, MicrocodeSegments, UnmapCommand};
+use crate::scsi::commands::{
+    ErrorCounterKind, ErrorCounterPage, InformationalExceptionsPage, LogPage, LogPageControl,
+    LogSelectCommand, LogSenseCommand, SelfTestResultsPage, SolidStateMediaPage,
+    StartStopCyclePage, SupportedLogPages, TemperaturePage,
+};
+use crate::scsi::commands::{FormatParameters, FormatUnitCommand};
+use crate::scsi::commands::{
+    InquiryCommand, InquiryResponse, PeripheralDeviceType, VpdInquiryCommand,
+};
+use crate::scsi::commands::{OverwriteParameters, SanitizeCommand};
+use crate::scsi::commands::{
+    PersistentReserveInCommand, PersistentReserveInServiceAction, PersistentReserveOutCommand,
+    PersistentReserveOutParameters, PersistentReserveOutServiceAction,
+};
+use crate::scsi::commands::{Read10Command, Read16Command, Write10Command, Write16Command};
+use crate::scsi::commands::{ReadBufferCommand, ReadBufferMode};
+use crate::scsi::commands::{ReadCapacity16Command, ReadCapacity16Response};
+use crate::scsi::commands::{ReadCapacityCommand, ReadCapacityResponse};
+use crate::scsi::commands::{Release6Command, Reserve6Command};
+use crate::scsi::commands::{RequestSenseCommand, SynchronizeCache10Command, TestUnitReady};
+use crate::scsi::commands::{
+    RequestSenseResponse, Verify10Command, Verify16Command, VerifyByteCheck,
+};
+use crate::scsi::commands::{ReservationCapabilitiesResponse, ReservationKeysResponse};
+use crate::scsi::commands::{ReservationResponse, ReservationType};
+use crate::scsi::commands::{WriteBufferCommand, WriteBufferMode, WriteSame16Command};
+use crate::scsi::device::{
+    abort_transfer, check_pushed, check_status, data_length, finish_transfer, parse_csw,
+    take_prev_tag, DeviceInfo, DiscardBatches, Scrub, ScrubEvent, TEST_UNIT_READY_ATTEMPTS,
+};
+use crate::scsi::DeviceTypePolicy;
+use crate::traits::{AsyncCommunicationChannel, BufferPullable, BufferPushable};
+
+/// The asynchronous counterpart of `ScsiBlockDevice`, which awaits every
+/// transfer on an `AsyncCommunicationChannel` instead of blocking.
+///
+/// Every method behaves the same way as its `ScsiBlockDevice` namesake. No
+/// allocation is needed, so this can be used from `no_std` executors such as
+/// `embassy` as well as from `tokio`.
+pub struct AsyncScsiBlockDevice<CommType: AsyncCommunicationChannel> {
+    /// The raw communication channel this device is using.
+    ///
+    /// This is a public field to allow for the user to still manipulate the
+    /// backing device as necessary; however, this pattern is not generally
+    /// recommended nor required in a standard use case.
+    pub comm_channel: CommType,
+    info: DeviceInfo,
+
+    /// The `CommandStatusWrapper` returned from the SCSI device after the last
+    /// method ran; see `ScsiBlockDevice::prev_csw`.
+    pub prev_csw: Option<CommandStatusWrapper>,
+}
+
+impl<CommType: AsyncCommunicationChannel> AsyncScsiBlockDevice<CommType> {
+    /// Constructs a new `AsyncScsiBlockDevice`.
+    ///
+    /// Only direct-access devices are accepted; use `with_policy` to connect
+    /// to other kinds of block devices.
+    /// # Parameters
+    /// *  `comm_channel` is the communication channel to be used to send out commands and read the responses.
+    /// *  `scratch_buffer` is a buffer that will be used for the initialization commands and responses; it will not be used outside of this method itself.
+    pub async fn new(comm_channel: CommType, scratch_buffer: &mut [u8]) -> Result<Self, ScsiError> {
+        AsyncScsiBlockDevice::with_policy(
+            comm_channel,
+            scratch_buffer,
+            DeviceTypePolicy::DirectAccessOnly,
+        )
+        .await
+    }
+
+    /// Constructs a new `AsyncScsiBlockDevice`, accepting any device whose
+    /// peripheral device type is allowed by `policy`.
+    ///
+    /// MMC (CD/DVD) devices are always accessed read-only using 2048 byte
+    /// blocks, regardless of what they report in their capacity data.
+    /// # Parameters
+    /// *  `comm_channel` is the communication channel to be used to send out commands and read the responses.
+    /// *  `scratch_buffer` is a buffer that will be used for the initialization commands and responses; it will not be used outside of this method itself.
+    /// *  `policy` decides which peripheral device types are accepted.
+    ///
+    /// # Errors
+    /// Returns an `InvalidDeviceError` if the device's type is rejected by `policy`.
+    pub async fn with_policy(
+        mut comm_channel: CommType,
+        scratch_buffer: &mut [u8],
+        policy: DeviceTypePolicy<'_>,
+    ) -> Result<Self, ScsiError> {
+        if scratch_buffer.len() < 31 {
+            return Err(ScsiError::from_cause(ErrorCause::BufferTooSmallError {
+                expected: 31,
+                actual: scratch_buffer.len(),
+            }));
+        }
+        let inquiry = InquiryCommand::new(scratch_buffer.len().min(36) as u8);
+        transfer_in_command(&mut comm_channel, &inquiry, &mut scratch_buffer[..]).await?;
+        let inquiry_resp = InquiryResponse::pull_from_buffer(&scratch_buffer)?;
+        let device_type = policy.check(&inquiry_resp)?;
+
+        let test_unit = TestUnitReady::new();
+        let mut attempts = 0;
+        loop {
+            attempts += 1;
+            match transfer_out_command(&mut comm_channel, &test_unit, &scratch_buffer[..]).await {
+                Ok(_) => break,
+                Err(ScsiError {
+                    cause: ErrorCause::FlagError { .. },
+                }) if attempts < TEST_UNIT_READY_ATTEMPTS => {
+                    // Clear the pending condition before trying again.
+                    let request_sense = RequestSenseCommand::new(18);
+                    transfer_in_command(&mut comm_channel, &request_sense, &mut scratch_buffer[..])
+                        .await?;
+                }
+                Err(e) => return Err(e),
+            }
+        }
+
+        let read_capacity = ReadCapacityCommand::new();
+        let (_, mut csw_rcc) =
+            transfer_in_command(&mut comm_channel, &read_capacity, &mut scratch_buffer[..]).await?;
+        let mut capacity_resp: ReadCapacity16Response =
+            ReadCapacityResponse::pull_from_buffer(&scratch_buffer)?.into();
+        if capacity_resp.logical_block_address == u64::from(u32::MAX) {
+            // The device is too large for READ CAPACITY(10) to describe.
+            let read_capacity = ReadCapacity16Command {
+                allocation_length: scratch_buffer.len().min(ReadCapacity16Response::SIZE) as u32,
+            };
+            (_, csw_rcc) =
+                transfer_in_command(&mut comm_channel, &read_capacity, &mut scratch_buffer[..])
+                    .await?;
+            capacity_resp = ReadCapacity16Response::pull_from_buffer(&scratch_buffer)?;
+        }
+        csw_rcc.tag = 2;
+        Ok(AsyncScsiBlockDevice {
+            comm_channel,
+            info: DeviceInfo::new(device_type, &capacity_resp),
+            prev_csw: Some(csw_rcc),
+        })
+    }
+
+    /// Reads bytes starting at `offset` into the provided `dest` buffer, returning
+    /// the number of bytes read on success.
+    ///
+    /// Reads longer than `max_transfer_blocks` blocks are split into several commands.
+    pub async fn read<B: AsMut<[u8]>>(
+        &mut self,
+        offset: u32,
+        mut dest: B,
+    ) -> Result<usize, ScsiError> {
+        let buffer = dest.as_mut();
+        if buffer.is_empty() {
+            self.prev_csw = None;
+            return Ok(0);
+        }
+        let (lba, count) = match self.info.offset_to_blocks(offset, buffer.len()) {
+            Ok(range) => range,
+            Err(e) => {
+                self.prev_csw = None;
+                return Err(e);
+            }
+        };
+        self.read_blocks(lba, count, buffer).await
+    }
+
+    /// Reads `count` logical blocks starting at block `lba` into the provided
+    /// `dest` buffer, returning the number of bytes read on success.
+    ///
+    /// # Errors
+    /// Returns a `BufferTooSmallError` if `dest` cannot hold `count` blocks, or a
+    /// `BlockAddressOutOfRangeError` if the blocks are past the end of the device.
+    pub async fn read_blocks<B: AsMut<[u8]>>(
+        &mut self,
+        lba: u64,
+        count: u32,
+        mut dest: B,
+    ) -> Result<usize, ScsiError> {
+        let prev_tag = take_prev_tag(&mut self.prev_csw);
+        self.info.check_range(lba, count)?;
+        let block_size = self.info.block_size as usize;
+        let buffer = dest.as_mut();
+        self.info.check_buffer(count, buffer.len())?;
+        let max_blocks = self.info.max_transfer_blocks();
+        let mut done = 0;
+        let mut read = 0;
+        let mut residue = 0;
+        let mut last_csw = None;
+        while done < count {
+            let blocks = (count - done).min(max_blocks);
+            let start = done as usize * block_size;
+            let end = start + blocks as usize * block_size;
+            let block_address = lba + u64::from(done);
+            let data = &mut buffer[start..end];
+            let (r, csw) = match u32::try_from(block_address) {
+                Ok(block_address) => {
+                    let read_command = Read10Command {
+                        block_address,
+                        block_size: self.info.block_size,
+                        transfer_blocks: blocks as u16,
+                    };
+                    transfer_in_command(&mut self.comm_channel, &read_command, data).await?
+                }
+                Err(_) => {
+                    let read_command =
+                        Read16Command::new(block_address, blocks, self.info.block_size);
+                    transfer_in_command(&mut self.comm_channel, &read_command, data).await?
+                }
+            };
+            read += r;
+            residue += csw.data_residue as usize;
+            last_csw = Some(csw);
+            done += blocks;
+        }
+        finish_transfer(&mut self.prev_csw, prev_tag, last_csw, residue);
+        Ok(read.saturating_sub(residue))
+    }
+
+    /// Writes bytes starting at `offset` from the provided buffer `src`, returning the
+    /// number of bytes written on success.
+    ///
+    /// Writes longer than `max_transfer_blocks` blocks are split into several commands.
+    ///
+    /// # Errors
+    /// Returns a `ReadOnlyDeviceError` if the device is read-only; see `is_read_only`.
+    pub async fn write<B: AsRef<[u8]>>(&mut self, offset: u32, src: B) -> Result<usize, ScsiError> {
+        let buffer = src.as_ref();
+        if buffer.is_empty() {
+            self.prev_csw = None;
+            return Ok(0);
+        }
+        let (lba, count) = match self.info.offset_to_blocks(offset, buffer.len()) {
+            Ok(range) => range,
+            Err(e) => {
+                self.prev_csw = None;
+                return Err(e);
+            }
+        };
+        self.write_blocks(lba, count, buffer).await
+    }
+
+    /// Writes `count` logical blocks starting at block `lba` from the provided
+    /// `src` buffer, returning the number of bytes written on success.
+    ///
+    /// # Errors
+    /// Returns a `BufferTooSmallError` if `src` holds fewer than `count` blocks,
+    /// a `BlockAddressOutOfRangeError` if the blocks are past the end of the
+    /// device, or a `ReadOnlyDeviceError` if the device is read-only.
+    pub async fn write_blocks<B: AsRef<[u8]>>(
+        &mut self,
+        lba: u64,
+        count: u32,
+        src: B,
+    ) -> Result<usize, ScsiError> {
+        let prev_tag = take_prev_tag(&mut self.prev_csw);
+        if self.info.is_read_only() {
+            return Err(ScsiError::from_cause(ErrorCause::ReadOnlyDeviceError));
+        }
+        self.info.check_range(lba, count)?;
+        let block_size = self.info.block_size as usize;
+        let buffer = src.as_ref();
+        self.info.check_buffer(count, buffer.len())?;
+        let max_blocks = self.info.max_transfer_blocks();
+        let mut done = 0;
+        let mut written = 0;
+        let mut residue = 0;
+        let mut last_csw = None;
+        while done < count {
+            let blocks = (count - done).min(max_blocks);
+            let start = done as usize * block_size;
+            let end = start + blocks as usize * block_size;
+            let block_address = lba + u64::from(done);
+            let data = &buffer[start..end];
+            let (w, csw) = match u32::try_from(block_address) {
+                Ok(block_address) => {
+                    let write_command = Write10Command {
+                        block_address,
+                        block_size: self.info.block_size,
+                        transfer_blocks: blocks as u16,
+                    };
+                    transfer_out_command(&mut self.comm_channel, &write_command, data).await?
+                }
+                Err(_) => {
+                    let write_command =
+                        Write16Command::new(block_address, blocks, self.info.block_size);
+                    transfer_out_command(&mut self.comm_channel, &write_command, data).await?
+                }
+            };
+            written += w;
+            residue += csw.data_residue as usize;
+            last_csw = Some(csw);
+            done += blocks;
+        }
+        finish_transfer(&mut self.prev_csw, prev_tag, last_csw, residue);
+        Ok(written.saturating_sub(residue))
+    }
+
+    /// Asks the device to write any cached data for the whole medium to
+    /// permanent storage, returning once it has done so.
+    pub async fn synchronize_cache(&mut self) -> Result<(), ScsiError> {
+        let prev_tag = take_prev_tag(&mut self.prev_csw);
+        let command = SynchronizeCache10Command::new();
+        let (_, csw) = transfer_out_command(&mut self.comm_channel, &command, &[][..]).await?;
+        finish_transfer(&mut self.prev_csw, prev_tag, Some(csw), 0);
+        Ok(())
+    }
+
+    /// Tells the device that the `count` logical blocks starting at block
+    /// `lba` no longer hold data that is needed, using a single `UnmapCommand`.
+    ///
+    /// # Errors
+    /// Returns a `BlockAddressOutOfRangeError` if the blocks are past the end
+    /// of the device, or a `ReadOnlyDeviceError` if the device is read-only.
+    pub async fn unmap(&mut self, lba: u64, count: u32) -> Result<(), ScsiError> {
+        let prev_tag = take_prev_tag(&mut self.prev_csw);
+        if self.info.is_read_only() {
+            return Err(ScsiError::from_cause(ErrorCause::ReadOnlyDeviceError));
+        }
+        self.info.check_range(lba, count)?;
+        if count == 0 {
+            return Ok(());
+        }
+        let (command, buffer) = UnmapCommand::single_range(lba, count)?;
+        let (_, csw) = transfer_out_command(&mut self.comm_channel, &command, &buffer[..]).await?;
+        finish_transfer(
+            &mut self.prev_csw,
+            prev_tag,
+            Some(csw),
+            csw.data_residue as usize,
+        );
+        Ok(())
+    }
+
+    /// Tells the device that the `count` logical blocks starting at block
+    /// `lba` no longer hold data that is needed, using as few `UnmapCommand`s
+    /// as the device's unmap limits allow, and returns the number of blocks
+    /// discarded.
+    ///
+    /// # Errors
+    /// Returns a `BlockAddressOutOfRangeError` if the blocks are past the end
+    /// of the device, a `ReadOnlyDeviceError` if the device is read-only, or
+    /// an `UnsupportedOperationError` if its Block Limits page says it does
+    /// not support UNMAP.
+    pub async fn discard(&mut self, lba: u64, count: u64) -> Result<u64, ScsiError> {
+        let prev_tag = take_prev_tag(&mut self.prev_csw);
+        let mut batches = DiscardBatches::new(&self.info, lba, count)?;
+        let blocks = batches.blocks();
+        let mut last_csw = None;
+        while let Some((command, buffer)) = batches.next_batch()? {
+            let length = command.parameter_list_length();
+            let (_, csw) =
+                transfer_out_command(&mut self.comm_channel, &command, &buffer[..length]).await?;
+            last_csw = Some(csw);
+        }
+        finish_transfer(&mut self.prev_csw, prev_tag, last_csw, 0);
+        Ok(blocks)
+    }
+
+    /// Writes the first block of `block` to each of the `count` blocks
+    /// starting at `lba` using `WriteSame16Command`s, letting the device unmap
+    /// them instead if `unmap` is set.
+    ///
+    /// # Errors
+    /// Returns a `BlockAddressOutOfRangeError` if the blocks are past the end
+    /// of the device, a `ReadOnlyDeviceError` if the device is read-only, or a
+    /// `BufferTooSmallError` if `block` is shorter than a block.
+    pub async fn write_same(
+        &mut self,
+        lba: u64,
+        count: u64,
+        block: &[u8],
+        unmap: bool,
+    ) -> Result<(), ScsiError> {
+        let prev_tag = take_prev_tag(&mut self.prev_csw);
+        if self.info.is_read_only() {
+            return Err(ScsiError::from_cause(ErrorCause::ReadOnlyDeviceError));
+        }
+        self.info.check_extent(lba, count)?;
+        let block = &block[..self.info.check_buffer(1, block.len())?];
+        let max_blocks = u64::from(self.info.max_write_same_blocks());
+        let mut last_csw = None;
+        let mut done = 0;
+        while done < count {
+            let blocks = (count - done).min(max_blocks);
+            let mut command =
+                WriteSame16Command::new(lba + done, blocks as u32, self.info.block_size);
+            command.unmap = unmap;
+            let (_, csw) = transfer_out_command(&mut self.comm_channel, &command, block).await?;
+            last_csw = Some(csw);
+            done += blocks;
+        }
+        finish_transfer(&mut self.prev_csw, prev_tag, last_csw, 0);
+        Ok(())
+    }
+
+    /// Asks the device to check that the `count` logical blocks starting at
+    /// block `lba` can be read back from its medium, comparing them against
+    /// `data` if it is given; see `ScsiBlockDevice::verify`.
+    ///
+    /// # Errors
+    /// Returns a `BlockAddressOutOfRangeError` if the blocks are past the end
+    /// of the device, or a `BufferTooSmallError` if `data` holds fewer than
+    /// `count` blocks.
+    pub async fn verify(
+        &mut self,
+        lba: u64,
+        count: u32,
+        data: Option<&[u8]>,
+    ) -> Result<(), ScsiError> {
+        let prev = self.prev_csw;
+        let prev_tag = take_prev_tag(&mut self.prev_csw);
+        self.info.check_range(lba, count)?;
+        if let Some(data) = data {
+            self.info.check_buffer(count, data.len())?;
+        }
+        let block_size = self.info.block_size as usize;
+        let max_blocks = self.max_transfer_blocks();
+        let mut done = 0;
+        let mut last_csw = None;
+        while done < count {
+            let blocks = (count - done).min(max_blocks);
+            let (byte_check, data) = match data {
+                Some(data) => {
+                    let start = done as usize * block_size;
+                    let end = start + blocks as usize * block_size;
+                    (VerifyByteCheck::Compare, &data[start..end])
+                }
+                None => (VerifyByteCheck::None, &[][..]),
+            };
+            let mut command =
+                Verify16Command::new(lba + u64::from(done), blocks, self.info.block_size);
+            command.byte_check = byte_check;
+            let result = match u32::try_from(command.block_address) {
+                Ok(block_address) => {
+                    let command = Verify10Command {
+                        block_address,
+                        block_size: command.block_size,
+                        transfer_blocks: blocks as u16,
+                        byte_check,
+                    };
+                    transfer_out_command(&mut self.comm_channel, &command, data).await
+                }
+                Err(_) => transfer_out_command(&mut self.comm_channel, &command, data).await,
+            };
+            match result {
+                Ok((_, csw)) => last_csw = Some(csw),
+                Err(error) => {
+                    abort_transfer(&mut self.prev_csw, prev, last_csw);
+                    return Err(error);
+                }
+            }
+            done += blocks;
+        }
+        finish_transfer(&mut self.prev_csw, prev_tag, last_csw, 0);
+        Ok(())
+    }
+
+    /// Verifies every block on the device, `chunk_blocks` blocks at a time,
+    /// calling `report` after every chunk and for every bad block found, and
+    /// returns the number of bad blocks; see `ScsiBlockDevice::scrub`.
+    ///
+    /// # Errors
+    /// Returns a `FlagError` if the device fails a chunk for a reason other
+    /// than a medium or hardware error, such as not supporting VERIFY.
+    pub async fn scrub<F: FnMut(ScrubEvent)>(
+        &mut self,
+        chunk_blocks: u32,
+        mut report: F,
+    ) -> Result<u64, ScsiError> {
+        let mut scrub = Scrub::new(&self.info, chunk_blocks);
+        while let Some((lba, blocks)) = scrub.next_chunk() {
+            match self.verify(lba, blocks, None).await {
+                Ok(()) => report(scrub.passed(blocks)),
+                Err(
+                    error @ ScsiError {
+                        cause: ErrorCause::FlagError { .. },
+                    },
+                ) => {
+                    let sense = self.request_sense().await?;
+                    if let Some(event) = scrub.failed(blocks, sense, error)? {
+                        report(event);
+                    }
+                }
+                Err(error) => return Err(error),
+            }
+        }
+        Ok(scrub.bad_blocks())
+    }
+
+    /// Asks the device to low-level format its medium, erasing every block.
+    ///
+    /// Without `parameters` the device formats with its default settings. If
+    /// the parameters' `immediate` flag is set the device returns before it
+    /// has finished, and `format_progress` can be polled until it is done;
+    /// otherwise this only returns once the format is complete. Only the
+    /// parameter list header is sent, so its `defect_list_length` should be 0.
+    ///
+    /// # Errors
+    /// Returns a `ReadOnlyDeviceError` if the device is read-only, or a
+    /// `BufferTooSmallError` if `parameters` has a nonzero
+    /// `defect_list_length`.
+    pub async fn format_unit(
+        &mut self,
+        parameters: Option<&FormatParameters>,
+    ) -> Result<(), ScsiError> {
+        let prev_tag = take_prev_tag(&mut self.prev_csw);
+        if self.info.is_read_only() {
+            return Err(ScsiError::from_cause(ErrorCause::ReadOnlyDeviceError));
+        }
+        let mut header = [0; FormatUnitCommand::SHORT_HEADER_SIZE];
+        let (command, header) = match parameters {
+            Some(parameters) => {
+                let pushed = parameters.push_to_buffer(&mut header[..])?;
+                let command = FormatUnitCommand::with_parameters(parameters);
+                if command.parameter_list_length as usize != pushed {
+                    return Err(ScsiError::from_cause(ErrorCause::BufferTooSmallError {
+                        expected: command.parameter_list_length as usize,
+                        actual: pushed,
+                    }));
+                }
+                (command, &header[..pushed])
+            }
+            None => (FormatUnitCommand::new(), &header[..0]),
+        };
+        let (_, csw) = transfer_out_command(&mut self.comm_channel, &command, header).await?;
+        finish_transfer(&mut self.prev_csw, prev_tag, Some(csw), 0);
+        Ok(())
+    }
+
+    /// Sends a `SanitizeCommand`, along with `parameters` if it is an
+    /// overwrite, making all of the data on the device unrecoverable.
+    ///
+    /// The command's parameter list length is set to match `parameters`. If
+    /// its `immediate` flag is set this returns once the device has accepted
+    /// the command, and `sanitize_progress` can be used to wait for it to
+    /// finish.
+    ///
+    /// # Errors
+    /// Returns a `ReadOnlyDeviceError` if the device is write protected, or a
+    /// `TransferTooLargeError` if the overwrite pattern is longer than 512
+    /// bytes.
+    pub async fn sanitize(
+        &mut self,
+        mut command: SanitizeCommand,
+        parameters: Option<&OverwriteParameters<'_>>,
+    ) -> Result<(), ScsiError> {
+        let prev_tag = take_prev_tag(&mut self.prev_csw);
+        if self.info.is_read_only() {
+            return Err(ScsiError::from_cause(ErrorCause::ReadOnlyDeviceError));
+        }
+        let mut buffer = [0; SanitizeCommand::MAX_PARAMETERS_SIZE];
+        let list = command.push_parameter_list(parameters, &mut buffer)?;
+        let (_, csw) = transfer_out_command(&mut self.comm_channel, &command, list).await?;
+        finish_transfer(&mut self.prev_csw, prev_tag, Some(csw), 0);
+        Ok(())
+    }
+
+    /// Atomically replaces the blocks starting at `lba` with `new`, but only if
+    /// they currently hold `expected`; both are a whole number of blocks long.
+    ///
+    /// Nothing is written if the comparison fails, which is reported as a
+    /// `MiscompareError` with the offset of the first differing byte in
+    /// `expected`, read from the device's sense data.
+    ///
+    /// # Errors
+    /// Besides `MiscompareError`, returns a `ReadOnlyDeviceError` if the
+    /// device is read-only, a `BufferTooSmallError` if `new` is not as long as
+    /// `expected`, a `TransferTooLargeError` if they cover more blocks than the
+    /// device's maximum compare and write length, or a
+    /// `BlockAddressOutOfRangeError` if the blocks are past the end of the
+    /// device.
+    pub async fn compare_and_write(
+        &mut self,
+        lba: u64,
+        expected: &[u8],
+        new: &[u8],
+    ) -> Result<(), ScsiError> {
+        let prev_tag = take_prev_tag(&mut self.prev_csw);
+        let command = self.info.compare_and_write_command(lba, expected, new)?;
+        match transfer_out_parts(&mut self.comm_channel, &command, &[expected, new]).await {
+            Ok((_, csw)) => {
+                finish_transfer(&mut self.prev_csw, prev_tag, Some(csw), 0);
+                Ok(())
+            }
+            Err(
+                error @ ScsiError {
+                    cause: ErrorCause::FlagError { .. },
+                },
+            ) => {
+                let sense = self.request_sense().await?;
+                Err(CompareAndWriteCommand::miscompare(error, &sense))
+            }
+            Err(error) => Err(error),
+        }
+    }
+
+    /// Reads every reservation key registered with the device.
+    pub async fn read_reservation_keys(&mut self) -> Result<ReservationKeysResponse, ScsiError> {
+        let mut buffer = [0; ReservationKeysResponse::MAX_SIZE];
+        let action = PersistentReserveInServiceAction::ReadKeys;
+        let length = self.persistent_reserve_in(action, &mut buffer).await?;
+        ReservationKeysResponse::pull_from_buffer(&buffer[..length])
+    }
+
+    /// Reads the device's current persistent reservation.
+    pub async fn read_reservation(&mut self) -> Result<ReservationResponse, ScsiError> {
+        let mut buffer = [0; ReservationKeysResponse::MAX_SIZE];
+        let action = PersistentReserveInServiceAction::ReadReservation;
+        let length = self.persistent_reserve_in(action, &mut buffer).await?;
+        ReservationResponse::pull_from_buffer(&buffer[..length])
+    }
+
+    /// Asks the device which persistent reservation features it supports.
+    pub async fn report_reservation_capabilities(
+        &mut self,
+    ) -> Result<ReservationCapabilitiesResponse, ScsiError> {
+        let prev_tag = take_prev_tag(&mut self.prev_csw);
+        let mut buffer = [0; ReservationCapabilitiesResponse::SIZE];
+        let mut command =
+            PersistentReserveInCommand::new(PersistentReserveInServiceAction::ReportCapabilities);
+        command.allocation_length = ReservationCapabilitiesResponse::SIZE as u16;
+        let (_, csw) =
+            transfer_in_command(&mut self.comm_channel, &command, &mut buffer[..]).await?;
+        finish_transfer(
+            &mut self.prev_csw,
+            prev_tag,
+            Some(csw),
+            csw.data_residue as usize,
+        );
+        ReservationCapabilitiesResponse::pull_from_buffer(&buffer[..])
+    }
+
+    /// Reads the header of a READ KEYS or READ RESERVATION response and then
+    /// the whole response into `buffer`, returning its length.
+    async fn persistent_reserve_in(
+        &mut self,
+        service_action: PersistentReserveInServiceAction,
+        buffer: &mut [u8; ReservationKeysResponse::MAX_SIZE],
+    ) -> Result<usize, ScsiError> {
+        let mut length = ReservationKeysResponse::HEADER_SIZE;
+        loop {
+            let prev_tag = take_prev_tag(&mut self.prev_csw);
+            let mut command = PersistentReserveInCommand::new(service_action);
+            command.allocation_length = length as u16;
+            let (_, csw) =
+                transfer_in_command(&mut self.comm_channel, &command, &mut buffer[..length])
+                    .await?;
+            finish_transfer(
+                &mut self.prev_csw,
+                prev_tag,
+                Some(csw),
+                csw.data_residue as usize,
+            );
+            let full_length = ReservationKeysResponse::response_length(&buffer[..]);
+            if full_length <= length {
+                return Ok(length);
+            }
+            length = full_length;
+        }
+    }
+
+    /// Sends a `PersistentReserveOutCommand` along with `parameters`, changing
+    /// the device's persistent reservation state.
+    ///
+    /// The command's parameter list length is set to match `parameters`.
+    /// Commands the device refuses, including those that conflict with
+    /// another initiator's reservation, fail with a `FlagError`. Transports
+    /// that carry the full SCSI status, such as iSCSI and SG_IO, report
+    /// conflicts as a `ReservationConflictError` instead.
+    pub async fn persistent_reserve_out(
+        &mut self,
+        mut command: PersistentReserveOutCommand,
+        parameters: &PersistentReserveOutParameters,
+    ) -> Result<(), ScsiError> {
+        let prev_tag = take_prev_tag(&mut self.prev_csw);
+        let mut list = [0; PersistentReserveOutParameters::SIZE];
+        let pushed = parameters.push_to_buffer(&mut list[..])?;
+        command.parameter_list_length = pushed as u32;
+        let (_, csw) =
+            transfer_out_command(&mut self.comm_channel, &command, &list[..pushed]).await?;
+        finish_transfer(&mut self.prev_csw, prev_tag, Some(csw), 0);
+        Ok(())
+    }
+
+    /// Registers `new_key` as this initiator's reservation key in place of
+    /// `key`, which is 0 if it has not registered one yet; a `new_key` of 0
+    /// unregisters it.
+    pub async fn register(&mut self, key: u64, new_key: u64) -> Result<(), ScsiError> {
+        let command =
+            PersistentReserveOutCommand::new(PersistentReserveOutServiceAction::Register, None);
+        let parameters = PersistentReserveOutParameters::new(key, new_key);
+        self.persistent_reserve_out(command, &parameters).await
+    }
+
+    /// Takes out a persistent reservation of type `reservation_type` for this
+    /// initiator, registered with `key`.
+    pub async fn reserve(
+        &mut self,
+        key: u64,
+        reservation_type: ReservationType,
+    ) -> Result<(), ScsiError> {
+        let command = PersistentReserveOutCommand::new(
+            PersistentReserveOutServiceAction::Reserve,
+            Some(reservation_type),
+        );
+        let parameters = PersistentReserveOutParameters::new(key, 0);
+        self.persistent_reserve_out(command, &parameters).await
+    }
+
+    /// Gives up the persistent reservation of type `reservation_type` this
+    /// initiator, registered with `key`, holds.
+    pub async fn release(
+        &mut self,
+        key: u64,
+        reservation_type: ReservationType,
+    ) -> Result<(), ScsiError> {
+        let command = PersistentReserveOutCommand::new(
+            PersistentReserveOutServiceAction::Release,
+            Some(reservation_type),
+        );
+        let parameters = PersistentReserveOutParameters::new(key, 0);
+        self.persistent_reserve_out(command, &parameters).await
+    }
+
+    /// Removes every registration and the reservation from the device, on
+    /// behalf of this initiator registered with `key`.
+    pub async fn clear_reservations(&mut self, key: u64) -> Result<(), ScsiError> {
+        let command =
+            PersistentReserveOutCommand::new(PersistentReserveOutServiceAction::Clear, None);
+        let parameters = PersistentReserveOutParameters::new(key, 0);
+        self.persistent_reserve_out(command, &parameters).await
+    }
+
+    /// Removes the registrations of `preempted_key`, on behalf of this
+    /// initiator registered with `key`, taking over their reservation with
+    /// type `reservation_type` if they held it.
+    ///
+    /// If `abort` is set, the device also aborts the commands the preempted
+    /// initiators have in flight.
+    pub async fn preempt(
+        &mut self,
+        key: u64,
+        preempted_key: u64,
+        reservation_type: Option<ReservationType>,
+        abort: bool,
+    ) -> Result<(), ScsiError> {
+        let service_action = if abort {
+            PersistentReserveOutServiceAction::PreemptAndAbort
+        } else {
+            PersistentReserveOutServiceAction::Preempt
+        };
+        let command = PersistentReserveOutCommand::new(service_action, reservation_type);
+        let parameters = PersistentReserveOutParameters::new(key, preempted_key);
+        self.persistent_reserve_out(command, &parameters).await
+    }
+
+    /// Reserves the whole device for this initiator with a `Reserve6Command`,
+    /// for devices and hosts that predate persistent reservations.
+    ///
+    /// Other initiators cannot access the medium until the reservation is
+    /// dropped with `release_unit`, or the device is reset.
+    pub async fn reserve_unit(&mut self) -> Result<(), ScsiError> {
+        let prev_tag = take_prev_tag(&mut self.prev_csw);
+        let command = Reserve6Command::new();
+        let (_, csw) = transfer_out_command(&mut self.comm_channel, &command, &[][..]).await?;
+        finish_transfer(&mut self.prev_csw, prev_tag, Some(csw), 0);
+        Ok(())
+    }
+
+    /// Drops a reservation taken with `reserve_unit`, using a
+    /// `Release6Command`.
+    pub async fn release_unit(&mut self) -> Result<(), ScsiError> {
+        let prev_tag = take_prev_tag(&mut self.prev_csw);
+        let command = Release6Command::new();
+        let (_, csw) = transfer_out_command(&mut self.comm_channel, &command, &[][..]).await?;
+        finish_transfer(&mut self.prev_csw, prev_tag, Some(csw), 0);
+        Ok(())
+    }
+
+    /// Sends a `WriteBufferCommand` in `mode`, writing `data` to the device's
+    /// buffer `buffer_id` at `offset`.
+    pub async fn write_buffer(
+        &mut self,
+        mode: WriteBufferMode,
+        buffer_id: u8,
+        offset: u32,
+        data: &[u8],
+    ) -> Result<(), ScsiError> {
+        let prev_tag = take_prev_tag(&mut self.prev_csw);
+        let command = WriteBufferCommand::with_length(mode, buffer_id, offset, data.len())?;
+        let (_, csw) = transfer_out_command(&mut self.comm_channel, &command, data).await?;
+        finish_transfer(&mut self.prev_csw, prev_tag, Some(csw), 0);
+        Ok(())
+    }
+
+    /// Downloads the firmware `image` to the device's buffer `buffer_id`
+    /// using WRITE BUFFER commands in `mode`, in segments of `segment_size`
+    /// bytes if the mode takes offsets.
+    pub async fn download_microcode(
+        &mut self,
+        mode: WriteBufferMode,
+        buffer_id: u8,
+        image: &[u8],
+        segment_size: usize,
+    ) -> Result<(), ScsiError> {
+        for (command, segment) in MicrocodeSegments::new(mode, buffer_id, image, segment_size)? {
+            let prev_tag = take_prev_tag(&mut self.prev_csw);
+            let (_, csw) = transfer_out_command(&mut self.comm_channel, &command, segment).await?;
+            finish_transfer(&mut self.prev_csw, prev_tag, Some(csw), 0);
+        }
+        Ok(())
+    }
+
+    /// Activates firmware downloaded with
+    /// `WriteBufferMode::DownloadMicrocodeWithOffsetsSaveAndDefer`.
+    pub async fn activate_deferred_microcode(&mut self) -> Result<(), ScsiError> {
+        self.write_buffer(WriteBufferMode::ActivateDeferredMicrocode, 0, 0, &[])
+            .await
+    }
+
+    /// Fills `dest` from the device's buffer `buffer_id`, starting at
+    /// `offset`, returning the number of bytes read.
+    pub async fn read_buffer(
+        &mut self,
+        buffer_id: u8,
+        offset: u32,
+        dest: &mut [u8],
+    ) -> Result<usize, ScsiError> {
+        self.read_buffer_data(ReadBufferMode::Data, buffer_id, offset, dest)
+            .await
+    }
+
+    /// Asks the device for the size of its buffer `buffer_id` and the offsets
+    /// it accepts.
+    pub async fn read_buffer_descriptor(
+        &mut self,
+        buffer_id: u8,
+    ) -> Result<BufferDescriptor, ScsiError> {
+        let mut buffer = [0; BufferDescriptor::SIZE];
+        self.read_buffer_data(ReadBufferMode::Descriptor, buffer_id, 0, &mut buffer)
+            .await?;
+        BufferDescriptor::pull_from_buffer(&buffer[..])
+    }
+
+    /// Fills `dest` from the device's echo buffer, returning the number of
+    /// bytes read.
+    pub async fn read_echo_buffer(&mut self, dest: &mut [u8]) -> Result<usize, ScsiError> {
+        self.read_buffer_data(ReadBufferMode::EchoBuffer, 0, 0, dest)
+            .await
+    }
+
+    /// Asks the device for the size of its echo buffer.
+    pub async fn read_echo_buffer_descriptor(&mut self) -> Result<EchoBufferDescriptor, ScsiError> {
+        let mut buffer = [0; EchoBufferDescriptor::SIZE];
+        self.read_buffer_data(ReadBufferMode::EchoBufferDescriptor, 0, 0, &mut buffer)
+            .await?;
+        EchoBufferDescriptor::pull_from_buffer(&buffer[..])
+    }
+
+    /// Sends a `ReadBufferCommand` in `mode`, filling `dest` with the
+    /// response.
+    async fn read_buffer_data(
+        &mut self,
+        mode: ReadBufferMode,
+        buffer_id: u8,
+        offset: u32,
+        dest: &mut [u8],
+    ) -> Result<usize, ScsiError> {
+        let prev_tag = take_prev_tag(&mut self.prev_csw);
+        let command = ReadBufferCommand::with_length(mode, buffer_id, offset, dest.len())?;
+        let (read, csw) = transfer_in_command(&mut self.comm_channel, &command, dest).await?;
+        finish_transfer(
+            &mut self.prev_csw,
+            prev_tag,
+            Some(csw),
+            csw.data_residue as usize,
+        );
+        Ok(read)
+    }
+
+    /// Reads log page `page_code` into `dest`, returning its length.
+    ///
+    /// This works for any page, including vendor-specific ones, whose
+    /// parameters can be walked with `LogParameters`. Pages longer than
+    /// `dest` are cut short.
+    pub async fn log_sense(&mut self, page_code: u8, dest: &mut [u8]) -> Result<usize, ScsiError> {
+        let mut length = LogPage::HEADER_SIZE.min(dest.len());
+        loop {
+            let prev_tag = take_prev_tag(&mut self.prev_csw);
+            let command = LogSenseCommand::new(page_code, length as u16);
+            let (read, csw) =
+                transfer_in_command(&mut self.comm_channel, &command, &mut dest[..length]).await?;
+            finish_transfer(
+                &mut self.prev_csw,
+                prev_tag,
+                Some(csw),
+                csw.data_residue as usize,
+            );
+            let full_length = LogPage::length(dest);
+            if read < length || full_length <= length {
+                return Ok(read.min(full_length));
+            }
+            length = full_length;
+        }
+    }
+
+    /// Reads and parses log page `page_code`.
+    ///
+    /// # Errors
+    /// Returns a `ParseError` if this crate has no parser for the page.
+    pub async fn read_log_page(&mut self, page_code: u8) -> Result<LogPage, ScsiError> {
+        self.read_parsed_log_page(page_code).await
+    }
+
+    /// Reads the list of log pages the device has.
+    pub async fn supported_log_pages(&mut self) -> Result<SupportedLogPages, ScsiError> {
+        self.read_parsed_log_page(SupportedLogPages::PAGE_CODE)
+            .await
+    }
+
+    /// Reads the device's write, read or verify error counters.
+    pub async fn error_counters(
+        &mut self,
+        kind: ErrorCounterKind,
+    ) -> Result<ErrorCounterPage, ScsiError> {
+        self.read_parsed_log_page(kind.page_code()).await
+    }
+
+    /// Reads the device's temperature.
+    pub async fn temperature(&mut self) -> Result<TemperaturePage, ScsiError> {
+        self.read_parsed_log_page(TemperaturePage::PAGE_CODE).await
+    }
+
+    /// Reads how many start-stop and load-unload cycles the device has been
+    /// through.
+    pub async fn start_stop_cycles(&mut self) -> Result<StartStopCyclePage, ScsiError> {
+        self.read_parsed_log_page(StartStopCyclePage::PAGE_CODE)
+            .await
+    }
+
+    /// Reads the outcomes of the device's most recent self-tests.
+    pub async fn self_test_results(&mut self) -> Result<SelfTestResultsPage, ScsiError> {
+        self.read_parsed_log_page(SelfTestResultsPage::PAGE_CODE)
+            .await
+    }
+
+    /// Reads how worn out the device's flash is.
+    pub async fn solid_state_media(&mut self) -> Result<SolidStateMediaPage, ScsiError> {
+        self.read_parsed_log_page(SolidStateMediaPage::PAGE_CODE)
+            .await
+    }
+
+    /// Reads whether the device predicts that it is going to fail.
+    pub async fn informational_exceptions(
+        &mut self,
+    ) -> Result<InformationalExceptionsPage, ScsiError> {
+        self.read_parsed_log_page(InformationalExceptionsPage::PAGE_CODE)
+            .await
+    }
+
+    /// Sends a `LogSelectCommand` along with `parameters`, which hold log
+    /// pages in the format `log_sense` returns them.
+    ///
+    /// The command's parameter list length is set to match `parameters`.
+    ///
+    /// # Errors
+    /// Returns a `TransferTooLargeError` if `parameters` is longer than the
+    /// command can carry.
+    pub async fn log_select(
+        &mut self,
+        command: LogSelectCommand,
+        parameters: &[u8],
+    ) -> Result<(), ScsiError> {
+        let command = command.with_parameter_list(parameters)?;
+        let prev_tag = take_prev_tag(&mut self.prev_csw);
+        let (_, csw) = transfer_out_command(&mut self.comm_channel, &command, parameters).await?;
+        finish_transfer(&mut self.prev_csw, prev_tag, Some(csw), 0);
+        Ok(())
+    }
+
+    /// Resets the device's cumulative log parameters, such as its error
+    /// counters.
+    pub async fn reset_log_parameters(&mut self) -> Result<(), ScsiError> {
+        let command = LogSelectCommand::reset(LogPageControl::CumulativeValues);
+        self.log_select(command, &[]).await
+    }
+
+    /// Reads log page `page_code` and parses it as a `P`.
+    async fn read_parsed_log_page<P: BufferPullable>(
+        &mut self,
+        page_code: u8,
+    ) -> Result<P, ScsiError> {
+        let mut buffer = [0; LogPage::MAX_SIZE];
+        let length = self.log_sense(page_code, &mut buffer).await?;
+        P::pull_from_buffer(&buffer[..length])
+    }
+
+    /// Polls a sanitize started with the `immediate` flag, returning how far
+    /// along it is as a fraction out of 65536, or `None` once the device is
+    /// no longer sanitizing.
+    ///
+    /// Like `format_progress`, this clears the device's sense data.
+    pub async fn sanitize_progress(&mut self) -> Result<Option<u16>, ScsiError> {
+        let sense = self.request_sense().await?;
+        if !sense.is_sanitize_in_progress() {
+            return Ok(None);
+        }
+        Ok(Some(sense.progress().unwrap_or(0)))
+    }
+
+    /// Polls a format started with the `immediate` flag, returning how far
+    /// along it is as a fraction out of 65536, or `None` once the device is
+    /// no longer formatting.
+    ///
+    /// The progress is read from the device's sense data, which this clears;
+    /// devices that do not report progress are treated as just starting.
+    pub async fn format_progress(&mut self) -> Result<Option<u16>, ScsiError> {
+        let sense = self.request_sense().await?;
+        if !sense.is_format_in_progress() {
+            return Ok(None);
+        }
+        Ok(Some(sense.progress().unwrap_or(0)))
+    }
+
+    /// Asks the device for the sense data describing why the previous command
+    /// failed, clearing it.
+    pub async fn request_sense(&mut self) -> Result<RequestSenseResponse, ScsiError> {
+        let prev_tag = take_prev_tag(&mut self.prev_csw);
+        let mut buffer = [0; RequestSenseResponse::SIZE];
+        let command = RequestSenseCommand::new(RequestSenseResponse::SIZE as u8);
+        let (_, csw) =
+            transfer_in_command(&mut self.comm_channel, &command, &mut buffer[..]).await?;
+        finish_transfer(
+            &mut self.prev_csw,
+            prev_tag,
+            Some(csw),
+            csw.data_residue as usize,
+        );
+        RequestSenseResponse::pull_from_buffer(&buffer[..])
+    }
+
+    /// Asks the device for its Block Limits vital product data page, limiting
+    /// future reads and writes to its maximum transfer length and discards to
+    /// its unmap limits.
+    pub async fn read_block_limits(&mut self) -> Result<BlockLimitsPage, ScsiError> {
+        let prev_tag = take_prev_tag(&mut self.prev_csw);
+        let mut buffer = [0; BlockLimitsPage::SIZE];
+        let inquiry =
+            VpdInquiryCommand::new(BlockLimitsPage::PAGE_CODE, BlockLimitsPage::SIZE as u8);
+        let (_, csw) =
+            transfer_in_command(&mut self.comm_channel, &inquiry, &mut buffer[..]).await?;
+        finish_transfer(
+            &mut self.prev_csw,
+            prev_tag,
+            Some(csw),
+            csw.data_residue as usize,
+        );
+        let page = BlockLimitsPage::pull_from_buffer(&buffer[..])?;
+        self.info.block_limits = Some(page);
+        Ok(page)
+    }
+
+    /// The largest number of blocks sent in a single read or write command.
+    pub fn max_transfer_blocks(&self) -> u32 {
+        self.info.max_transfer_blocks()
+    }
+
+    /// Caps the number of blocks sent in a single read or write command; a
+    /// `max_blocks` of 0 removes the cap.
+    pub fn set_max_transfer_blocks(&mut self, max_blocks: u32) {
+        self.info.user_max_transfer_blocks = max_blocks;
+    }
+
+    /// The size of the read/write blocks for this device.
+    pub fn block_size(&self) -> u32 {
+        self.info.block_size
+    }
+
+    /// The number of logical blocks on the device.
+    pub fn num_blocks(&self) -> u64 {
+        self.info.num_blocks
+    }
+
+    /// The total capacity of the device in bytes.
+    pub fn capacity_bytes(&self) -> u64 {
+        self.info.capacity_bytes()
+    }
+
+    /// The peripheral device type the device reported when it was connected.
+    pub fn device_type(&self) -> PeripheralDeviceType {
+        self.info.device_type
+    }
+
+    /// Whether or not the device can only be read from.
+    pub fn is_read_only(&self) -> bool {
+        self.info.is_read_only()
+    }
+}
+
+async fn read_csw<C: AsyncCommunicationChannel>(
+    comm_channel: &mut C,
+) -> Result<CommandStatusWrapper, ScsiError> {
+    let mut scratch_buffer = [0; CommandStatusWrapper::SIZE as usize];
+    let read_count = comm_channel.in_transfer(&mut scratch_buffer).await?;
+    parse_csw(&scratch_buffer, read_count)
+}
+
+async fn push_command<C: AsyncCommunicationChannel, Cmd: Command>(
+    comm_channel: &mut C,
+    command: &Cmd,
+) -> Result<usize, ScsiError> {
+    let mut scratch_buffer = [0; 31];
+    command.push_to_buffer(&mut scratch_buffer)?;
+    let pushed_bytes = comm_channel.out_transfer(scratch_buffer).await?;
+    check_pushed(pushed_bytes)
+}
+
+async fn transfer_out_command<Usb: AsyncCommunicationChannel, C: Command>(
+    comm_channel: &mut Usb,
+    command: &C,
+    out_buffer: &[u8],
+) -> Result<(usize, CommandStatusWrapper), ScsiError> {
+    transfer_out_parts(comm_channel, command, &[out_buffer]).await
+}
+
+async fn transfer_out_parts<Usb: AsyncCommunicationChannel, C: Command>(
+    comm_channel: &mut Usb,
+    command: &C,
+    parts: &[&[u8]],
+) -> Result<(usize, CommandStatusWrapper), ScsiError> {
+    push_command(comm_channel, command).await?;
+
+    let total = parts.iter().map(|part| part.len()).sum();
+    let length = data_length(command, total, Direction::IN)?;
+    let mut written = 0;
+    for part in parts {
+        let part = &part[..part.len().min(length - written)];
+        let mut sent = 0;
+        while sent < part.len() {
+            let cur = comm_channel.out_transfer(&part[sent..]).await?;
+            if cur == 0 {
+                return Err(ScsiError::from_cause(ErrorCause::UsbTransferError {
+                    direction: UsbTransferDirection::Out,
+                }));
+            }
+            sent += cur;
+        }
+        written += sent;
+    }
+    let csw = read_csw(comm_channel).await?;
+    Ok((
+        written,
+        check_status(command, csw, comm_channel.scsi_status())?,
+    ))
+}
+
+async fn transfer_in_command<Usb: AsyncCommunicationChannel, C: Command>(
+    comm_channel: &mut Usb,
+    command: &C,
+    in_buffer: &mut [u8],
+) -> Result<(usize, CommandStatusWrapper), ScsiError> {
+    push_command(comm_channel, command).await?;
+
+    let length = data_length(command, in_buffer.len(), Direction::OUT)?;
+    let in_buffer = &mut in_buffer[..length];
+    let mut read = 0;
+    while read < in_buffer.len() {
+        let cur = comm_channel.in_transfer(&mut in_buffer[read..]).await?;
+        if cur == 0 {
+            return Err(ScsiError::from_cause(ErrorCause::UsbTransferError {
+                direction: UsbTransferDirection::In,
+            }));
+        }
+        read += cur;
+    }
+    let csw = read_csw(comm_channel).await?;
+    Ok((
+        read,
+        check_status(command, csw, comm_channel.scsi_status())?,
+    ))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{AsyncScsiBlockDevice, FormatParameters, SanitizeCommand, ScrubEvent};
+    use crate::error::ErrorCause;
+    use crate::error::ScsiError;
+    use crate::scsi::commands::{
+        LogPage, ReservationType, SanitizeServiceAction, SelfTestResultsPage, WriteBufferMode,
+    };
+    use crate::scsi::responder::tests::{block_on, AsyncLoopbackChannel, MemoryResponder};
+    use crate::scsi::RamDiskResponder;
+    use crate::traits::AsyncCommunicationChannel;
+    use byteorder::{ByteOrder, LE};
+    use std::vec::Vec;
+
+    #[derive(Clone, Copy, Eq, PartialEq, Debug)]
+    enum CswFault {
+        WrongTag,
+        Truncated,
+    }
+
+    /// A loopback channel whose data trickles in, which can spoil the next
+    /// CSW and can be unplugged after a set number of bytes.
+    struct FaultyChannel {
+        inner: AsyncLoopbackChannel<MemoryResponder>,
+        max_in: usize,
+        remaining: usize,
+        fault: Option<CswFault>,
+    }
+
+    impl FaultyChannel {
+        fn new() -> FaultyChannel {
+            FaultyChannel {
+                inner: AsyncLoopbackChannel::new(MemoryResponder::default()),
+                max_in: 100,
+                remaining: usize::MAX,
+                fault: None,
+            }
+        }
+    }
+
+    impl AsyncCommunicationChannel for FaultyChannel {
+        async fn out_transfer<B: AsRef<[u8]>>(&mut self, bytes: B) -> Result<usize, ScsiError> {
+            let bytes = bytes.as_ref();
+            let length = bytes.len().min(self.remaining);
+            if length == 0 {
+                return Ok(0);
+            }
+            let sent = self.inner.out_transfer(&bytes[..length]).await?;
+            self.remaining -= sent;
+            Ok(sent)
+        }
+
+        async fn in_transfer<B: AsMut<[u8]>>(&mut self, mut buffer: B) -> Result<usize, ScsiError> {
+            let buffer = buffer.as_mut();
+            let is_csw = buffer.len() == 13;
+            let mut length = buffer.len().min(self.remaining);
+            if !is_csw {
+                length = length.min(self.max_in);
+            }
+            if length == 0 {
+                return Ok(0);
+            }
+            let read = self.inner.in_transfer(&mut buffer[..length]).await?;
+            self.remaining -= read;
+            match self.fault.take() {
+                Some(CswFault::WrongTag) if is_csw => {
+                    let tag = LE::read_u32(&buffer[4..]);
+                    LE::write_u32(&mut buffer[4..], tag.wrapping_add(1));
+                    Ok(read)
+                }
+                Some(CswFault::Truncated) if is_csw => Ok(read - 1),
+                fault => {
+                    self.fault = fault;
+                    Ok(read)
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn test_async_block_device() {
+        block_on(async {
+            let channel = AsyncLoopbackChannel::new(MemoryResponder::default());
+            let mut scratch = [0; 64];
+            let mut device = AsyncScsiBlockDevice::new(channel, &mut scratch)
+                .await
+                .unwrap();
+            assert_eq!((device.block_size(), device.num_blocks()), (256, 1024));
+            assert_eq!(
+                device
+                    .read_block_limits()
+                    .await
+                    .unwrap()
+                    .max_transfer_length,
+                16
+            );
+
+            let data: Vec<u8> = (0..20 * 256).map(|idx| (idx / 256) as u8).collect();
+            assert_eq!(device.write(512, &data[..]).await.unwrap(), data.len());
+            device.synchronize_cache().await.unwrap();
+            let mut readback = vec![0; data.len()];
+            assert_eq!(
+                device.read_blocks(2, 20, &mut readback[..]).await.unwrap(),
+                data.len()
+            );
+            assert_eq!(readback, data);
+            assert!(device.comm_channel.waits() > 0);
+
+            let err = device.read_blocks(1020, 8, &mut readback[..]).await;
+            assert_eq!(
+                err.err().unwrap().cause,
+                ErrorCause::BlockAddressOutOfRangeError {
+                    block_address: 1020,
+                    num_blocks: 1024,
+                }
+            );
+
+            assert_eq!(device.discard(2, 20).await.unwrap(), 20);
+            device.write_same(4, 2, &[7; 256], true).await.unwrap();
+            device.read_blocks(2, 20, &mut readback[..]).await.unwrap();
+            assert!(readback[..2 * 256].iter().all(|&byte| byte == 0));
+            assert!(readback[2 * 256..4 * 256].iter().all(|&byte| byte == 7));
+            assert!(readback[4 * 256..].iter().all(|&byte| byte == 0));
+
+            device.verify(2, 20, Some(&readback[..])).await.unwrap();
+            device.comm_channel.responder.bad_blocks = vec![3];
+            let mut bad = Vec::new();
+            let found = device
+                .scrub(64, |event| {
+                    if let ScrubEvent::BadBlock { lba, .. } = event {
+                        bad.push(lba);
+                    }
+                })
+                .await
+                .unwrap();
+            assert_eq!((found, bad), (1, vec![3]));
+
+            // A failed verify leaves the tags for REQUEST SENSE to carry on.
+            let tag = device.prev_csw.unwrap().tag;
+            let err = device.verify(2, 4, None).await.unwrap_err();
+            assert_eq!(err.cause, ErrorCause::FlagError { flags: 1 });
+            assert_eq!(device.prev_csw.unwrap().tag, tag);
+            device.request_sense().await.unwrap();
+            assert_eq!(device.prev_csw.unwrap().tag, tag + 1);
+            device.comm_channel.responder.bad_blocks.clear();
+
+            let parameters = FormatParameters {
+                immediate: true,
+                ..Default::default()
+            };
+            device.format_unit(Some(&parameters)).await.unwrap();
+            assert_eq!(device.format_progress().await.unwrap(), Some(0));
+            assert_eq!(device.format_progress().await.unwrap(), Some(0x8000));
+            assert_eq!(device.format_progress().await.unwrap(), None);
+
+            let mut command = SanitizeCommand::new(SanitizeServiceAction::CryptographicErase);
+            command.immediate = true;
+            device.sanitize(command, None).await.unwrap();
+            assert_eq!(device.sanitize_progress().await.unwrap(), Some(0));
+            assert_eq!(device.sanitize_progress().await.unwrap(), Some(0x8000));
+            assert_eq!(device.sanitize_progress().await.unwrap(), None);
+
+            let lock = [0x4c; 256];
+            device.compare_and_write(2, &[0; 256], &lock).await.unwrap();
+            let err = device.compare_and_write(2, &[0; 256], &lock).await;
+            assert_eq!(
+                err.err().unwrap().cause,
+                ErrorCause::MiscompareError { offset: Some(0) }
+            );
+
+            let image = [0x3c; 600];
+            let mode = WriteBufferMode::DownloadMicrocodeWithOffsetsSaveAndDefer;
+            device
+                .download_microcode(mode, 0, &image, 512)
+                .await
+                .unwrap();
+            assert!(device.comm_channel.responder.active_firmware.is_empty());
+            device.activate_deferred_microcode().await.unwrap();
+            assert_eq!(device.comm_channel.responder.active_firmware, image);
+            let descriptor = device.read_buffer_descriptor(0).await.unwrap();
+            assert_eq!(descriptor.capacity, 0x1_0000);
+            let mut readback = [0; 88];
+            device.read_buffer(0, 512, &mut readback).await.unwrap();
+            assert_eq!(readback, [0x3c; 88]);
+            device
+                .write_buffer(WriteBufferMode::EchoBuffer, 0, 0, &[7; 16])
+                .await
+                .unwrap();
+            let mut echoed = [0; 16];
+            device.read_echo_buffer(&mut echoed).await.unwrap();
+            assert_eq!(echoed, [7; 16]);
+            let descriptor = device.read_echo_buffer_descriptor().await.unwrap();
+            assert_eq!(descriptor.capacity, 256);
+
+            device.comm_channel.responder.log_pages =
+                vec![LogPage::SelfTestResults(SelfTestResultsPage {
+                    result_count: 1,
+                    ..Default::default()
+                })];
+            let supported = device.supported_log_pages().await.unwrap();
+            assert_eq!(supported.page_codes(), &[0x00, 0x10]);
+            let results = device.self_test_results().await.unwrap();
+            assert!(results.latest().unwrap().passed());
+            device.reset_log_parameters().await.unwrap();
+        });
+    }
+    #[test]
+    fn test_async_large_device() {
+        let mut responder = MemoryResponder::default();
+        responder.base_lba = 0xffff_fc04;
+        block_on(async {
+            let channel = AsyncLoopbackChannel::new(responder);
+            let mut scratch = [0; 64];
+            let mut device = AsyncScsiBlockDevice::new(channel, &mut scratch)
+                .await
+                .unwrap();
+            assert_eq!(device.num_blocks(), 0x1_0000_0004);
+            device.set_max_transfer_blocks(2);
+
+            let data: Vec<u8> = (0..6 * 256).map(|idx| (idx / 256) as u8 + 1).collect();
+            let lba = 0xffff_fffe;
+            assert_eq!(
+                device.write_blocks(lba, 6, &data[..]).await.unwrap(),
+                data.len()
+            );
+            let mut readback = vec![0; data.len()];
+            assert_eq!(
+                device.read_blocks(lba, 6, &mut readback[..]).await.unwrap(),
+                data.len()
+            );
+            assert_eq!(readback, data);
+            device.verify(lba, 6, Some(&data[..])).await.unwrap();
+        });
+    }
+
+    #[test]
+    fn test_async_reservations() {
+        let responder = RamDiskResponder::new(vec![0; 512 * 4]);
+        block_on(async {
+            let mut scratch = [0; 64];
+            let channel = AsyncLoopbackChannel::new(responder);
+            let mut device = AsyncScsiBlockDevice::new(channel, &mut scratch)
+                .await
+                .unwrap();
+            let capabilities = device.report_reservation_capabilities().await.unwrap();
+            assert!(capabilities.supports(ReservationType::WriteExclusive));
+            device.register(0, 0x77).await.unwrap();
+            device
+                .reserve(0x77, ReservationType::WriteExclusive)
+                .await
+                .unwrap();
+            assert_eq!(
+                device.read_reservation_keys().await.unwrap().keys(),
+                &[0x77]
+            );
+            let reservation = device.read_reservation().await.unwrap().reservation;
+            assert_eq!(reservation.unwrap().key, 0x77);
+            assert!(device.preempt(0x77, 0x99, None, false).await.is_err());
+            device
+                .release(0x77, ReservationType::WriteExclusive)
+                .await
+                .unwrap();
+            device.clear_reservations(0x77).await.unwrap();
+            assert_eq!(device.read_reservation().await.unwrap().reservation, None);
+        });
+    }
+
+    #[test]
+    fn test_async_transfer_errors() {
+        block_on(async {
+            let mut scratch = [0; 64];
+            let mut device = AsyncScsiBlockDevice::new(FaultyChannel::new(), &mut scratch)
+                .await
+                .unwrap();
+
+            // Data that arrives a little at a time is gathered up.
+            let data: Vec<u8> = (0..4 * 256).map(|idx| (idx % 251) as u8).collect();
+            device.write_blocks(8, 4, &data[..]).await.unwrap();
+            let mut readback = vec![0; data.len()];
+            device.read_blocks(8, 4, &mut readback[..]).await.unwrap();
+            assert_eq!(readback, data);
+
+            // A CSW for some other command, or one cut short, fails the
+            // command without upsetting the ones after it.
+            device.comm_channel.fault = Some(CswFault::WrongTag);
+            let err = device.read_blocks(8, 1, &mut readback[..256]).await;
+            assert_eq!(err.unwrap_err().cause, ErrorCause::ParseError);
+            device.comm_channel.fault = Some(CswFault::Truncated);
+            let err = device.read_blocks(8, 1, &mut readback[..256]).await;
+            assert!(matches!(
+                err.unwrap_err().cause,
+                ErrorCause::UsbTransferError { .. }
+            ));
+            device.synchronize_cache().await.unwrap();
+
+            // A device that goes away partway through a data phase.
+            device.comm_channel.remaining = 31 + 300;
+            let err = device.read_blocks(8, 4, &mut readback[..]).await;
+            assert!(matches!(
+                err.unwrap_err().cause,
+                ErrorCause::UsbTransferError { .. }
+            ));
+            device.comm_channel.remaining = 31 + 300;
+            let err = device.write_blocks(8, 4, &data[..]).await;
+            assert!(matches!(
+                err.unwrap_err().cause,
+                ErrorCause::UsbTransferError { .. }
+            ));
+            assert!(device.synchronize_cache().await.is_err());
+        });
+    }
+}
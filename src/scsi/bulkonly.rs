@@ -0,0 +1,1069 @@
+use crate::error::{ErrorCause, ScsiError, UsbTransferDirection};
+use crate::scsi::commands::{
+    CommandBlockWrapper, CommandStatusWrapper, Direction, ReadBufferCommand, ReadBufferMode,
+    Verify16Command, VerifyByteCheck, WriteBufferCommand,
+};
+use crate::scsi::responder::{
+    dispatch_command, fail_illegal_request, reject_command, DataPhase, ScsiCommand,
+    RESPONSE_BUFFER_SIZE,
+};
+use crate::scsi::{MediumAccess, ScsiResponder};
+use crate::traits::{BufferPullable, BufferPushable};
+
+/// The size of a CBW, which always arrives in a packet of its own.
+const CBW_SIZE: usize = 31;
+
+/// Where a `BulkOnlyTransport` is in the current command.
+#[derive(Clone, Copy, Debug)]
+enum State {
+    /// Waiting for the next CBW.
+    Command,
+
+    /// Sending READ(10) data out of the block buffer, which holds `filled`
+    /// bytes of which `taken` have been sent. `status` is set once the
+    /// responder has finished.
+    ReadBlocks {
+        filled: usize,
+        taken: usize,
+        status: Option<CommandStatusWrapper>,
+    },
+
+    /// Sending READ BUFFER data out of the block buffer, which is refilled
+    /// from the responder's `read_buffer` a chunk at a time and holds
+    /// `filled` bytes of which `taken` have been sent. `status` is set once
+    /// the responder has ended the command early.
+    ReadBuffer {
+        command: ReadBufferCommand,
+        filled: usize,
+        taken: usize,
+        status: Option<CommandStatusWrapper>,
+    },
+
+    /// Sending the first `length` bytes of the response buffer.
+    Response { length: usize },
+
+    /// Receiving data for `sink` into the block buffer, which is handed to
+    /// the responder every time `chunk` bytes have arrived. `accepted` counts
+    /// the bytes handed over before the responder ended the command.
+    ReceiveChunks {
+        sink: DataSink,
+        filled: usize,
+        chunk: usize,
+        accepted: usize,
+        status: Option<CommandStatusWrapper>,
+    },
+
+    /// Receiving the data sent along with any other command into the
+    /// block buffer, before the command is run. `command` is `None` if the
+    /// command has already been failed.
+    Parameters { command: Option<ScsiCommand> },
+
+    /// Sending a zero-length packet to end a data phase that was cut short.
+    EndOfData,
+
+    /// Sending the CSW.
+    Status,
+
+    /// Waiting for a reset after an invalid CBW.
+    Stalled,
+}
+
+/// Where the data of a streamed command from the host goes.
+#[derive(Clone, Copy, Debug)]
+enum DataSink {
+    /// WRITE(10) or WRITE(16) blocks, passed to `write_block`.
+    Blocks,
+
+    /// WRITE BUFFER data, passed to `write_buffer` for the buffer `offset`.
+    Buffer {
+        command: WriteBufferCommand,
+        offset: u32,
+    },
+
+    /// Data compared by a byte-checked VERIFY, passed to `verify` for the
+    /// block at `block_address`.
+    Verify {
+        command: Verify16Command,
+        block_address: u64,
+    },
+}
+
+impl DataSink {
+    /// Hands a chunk of data to the responder, returning the CSW if the
+    /// responder has ended the command.
+    fn take<R: ScsiResponder>(
+        &mut self,
+        responder: &mut R,
+        data: &[u8],
+    ) -> Result<Option<CommandStatusWrapper>, ScsiError> {
+        match self {
+            DataSink::Blocks => responder.write_block(data),
+            DataSink::Buffer { command, offset } => {
+                let status = responder.write_buffer(*command, *offset, data)?;
+                *offset += data.len() as u32;
+                Ok(status)
+            }
+            DataSink::Verify {
+                command,
+                block_address,
+            } => {
+                // A single block is compared against every block in range.
+                let repeats = match command.byte_check {
+                    VerifyByteCheck::CompareSingleBlock => u64::from(command.transfer_blocks),
+                    _ => 1,
+                };
+                let mut status = None;
+                let mut compared = 0;
+                while status.is_none() && compared < repeats {
+                    status = responder.verify(*command, *block_address, data)?;
+                    *block_address += 1;
+                    compared += 1;
+                }
+                Ok(status)
+            }
+        }
+    }
+
+    /// Ends the command once all of its data has been taken without the
+    /// responder ending it early.
+    fn end<R: ScsiResponder>(&self, responder: &mut R) -> Result<CommandStatusWrapper, ScsiError> {
+        let status = match *self {
+            DataSink::Blocks => return responder.write_end(),
+            DataSink::Buffer { command, offset } => responder.write_buffer(command, offset, &[])?,
+            DataSink::Verify {
+                command,
+                block_address,
+            } => responder.verify(command, block_address, &[])?,
+        };
+        Ok(status.unwrap_or_default())
+    }
+
+    /// The number of bytes the command transferred, out of the `received`
+    /// bytes of which the responder `accepted` some before ending it.
+    fn transferred(&self, received: usize, accepted: usize) -> usize {
+        match self {
+            DataSink::Blocks => accepted,
+            _ => received,
+        }
+    }
+}
+
+/// The device side of the USB Bulk-Only Transport as a state machine, for
+/// USB stacks that deliver packets one at a time rather than through a
+/// blocking `CommunicationChannel`.
+///
+/// Every packet arriving on the bulk OUT endpoint is passed to
+/// `receive_packet`, and whenever the bulk IN endpoint can take a packet,
+/// `next_packet` provides it. Both run the same `ScsiResponder` hooks as
+/// `ScsiResponder::process_command`, a little at a time: the data of READ,
+/// WRITE, READ BUFFER, WRITE BUFFER and byte-checked VERIFY commands is
+/// streamed through the responder's block buffer, and is combined or split
+/// as needed to fill packets of the endpoints' maximum packet size. Data sent
+/// to the host that ends short of the length in the CBW on a packet boundary
+/// is ended with a zero-length packet.
+///
+/// Any other command sending data to the device is run once all of its data
+/// has arrived, which has to fit in the block buffer, or in two of them for
+/// COMPARE AND WRITE. A command sending more is failed without being run,
+/// after its data has been received, with ILLEGAL REQUEST sense data and a
+/// PARAMETER LIST LENGTH ERROR additional sense code passed to
+/// `ScsiResponder::command_rejected`.
+///
+/// Only logical unit 0 is served. If a responder method returns an error, the
+/// command fails with a CSW reporting `COMMAND_FAILED`. An invalid CBW stalls
+/// the transport until the host performs a Reset Recovery, which should end
+/// with a call to `reset`.
+pub struct BulkOnlyTransport<R: ScsiResponder> {
+    max_packet_size: usize,
+    block: R::BlockType,
+    /// A second block buffer, taken from the responder for the second half
+    /// of a COMPARE AND WRITE.
+    spare: Option<R::BlockType>,
+    response: [u8; RESPONSE_BUFFER_SIZE],
+    state: State,
+    cbw: CommandBlockWrapper,
+    /// The number of bytes moved in the current data phase so far.
+    moved: usize,
+    /// The CSW for the current command, once it is known.
+    csw: CommandStatusWrapper,
+}
+
+impl<R: ScsiResponder> BulkOnlyTransport<R> {
+    /// Constructs a transport for bulk endpoints with the given maximum packet
+    /// size, usually 64 bytes at full speed and 512 at high speed.
+    pub fn new(responder: &mut R, max_packet_size: usize) -> BulkOnlyTransport<R> {
+        BulkOnlyTransport {
+            max_packet_size,
+            block: responder.memory_buffer(),
+            spare: None,
+            response: [0; RESPONSE_BUFFER_SIZE],
+            state: State::Command,
+            cbw: CommandBlockWrapper::new(0, Direction::NONE, 0, 0),
+            moved: 0,
+            csw: CommandStatusWrapper::default(),
+        }
+    }
+
+    /// The maximum packet size of the bulk endpoints.
+    pub fn max_packet_size(&self) -> usize {
+        self.max_packet_size
+    }
+
+    /// Whether the transport is waiting for a CBW, in between commands.
+    pub fn is_idle(&self) -> bool {
+        matches!(self.state, State::Command)
+    }
+
+    /// Whether an invalid CBW was received, in which case both bulk endpoints
+    /// should be stalled until the host resets the device.
+    pub fn is_stalled(&self) -> bool {
+        matches!(self.state, State::Stalled)
+    }
+
+    /// Abandons the current command and waits for the next CBW, as needed
+    /// after a Bulk-Only Mass Storage Reset.
+    pub fn reset(&mut self) {
+        self.state = State::Command;
+        self.spare = None;
+    }
+
+    /// Handles a packet received on the bulk OUT endpoint.
+    ///
+    /// # Errors
+    /// Returns a `ParseError` for an invalid CBW, after which the transport
+    /// is stalled, or a `UsbTransferError` if no data was expected from the
+    /// host.
+    pub fn receive_packet(&mut self, responder: &mut R, packet: &[u8]) -> Result<(), ScsiError> {
+        match self.state {
+            // Stray zero-length packets in between commands are harmless.
+            State::Command if packet.is_empty() => Ok(()),
+            State::Command => self.receive_command(responder, packet),
+            State::ReceiveChunks { .. } => {
+                self.receive_blocks(responder, packet);
+                Ok(())
+            }
+            State::Parameters { .. } => {
+                self.receive_parameters(responder, packet);
+                Ok(())
+            }
+            _ => Err(ScsiError::from_cause(ErrorCause::UsbTransferError {
+                direction: UsbTransferDirection::In,
+            })),
+        }
+    }
+
+    /// Writes the next packet for the bulk IN endpoint to `buffer`, returning
+    /// its length, or `None` if there is nothing to send until more packets
+    /// are received. A length of 0 is a zero-length packet, which still has to
+    /// be sent.
+    ///
+    /// The packet counts as sent once it has been returned, so callers that
+    /// cannot send it straight away have to hold on to it.
+    ///
+    /// # Errors
+    /// Returns a `BufferTooSmallError` if `buffer` is shorter than the maximum
+    /// packet size.
+    pub fn next_packet(
+        &mut self,
+        responder: &mut R,
+        buffer: &mut [u8],
+    ) -> Result<Option<usize>, ScsiError> {
+        if buffer.len() < self.max_packet_size {
+            return Err(ScsiError::from_cause(ErrorCause::BufferTooSmallError {
+                expected: self.max_packet_size,
+                actual: buffer.len(),
+            }));
+        }
+        match self.state {
+            State::ReadBlocks { .. } | State::ReadBuffer { .. } | State::Response { .. } => {
+                let length = self.fill_packet(responder, &mut buffer[..self.max_packet_size]);
+                if length > 0 {
+                    self.moved += length;
+                    return Ok(Some(length));
+                }
+                self.end_data_in(responder);
+                self.next_packet(responder, buffer)
+            }
+            State::EndOfData => {
+                self.state = State::Status;
+                Ok(Some(0))
+            }
+            State::Status => {
+                let length = self.csw.push_to_buffer(&mut buffer[..])?;
+                self.state = State::Command;
+                Ok(Some(length))
+            }
+            _ => Ok(None),
+        }
+    }
+
+    /// Starts the command in a CBW.
+    fn receive_command(&mut self, responder: &mut R, packet: &[u8]) -> Result<(), ScsiError> {
+        let cbw = if packet.len() == CBW_SIZE {
+            CommandBlockWrapper::pull_from_buffer(packet).ok()
+        } else {
+            None
+        };
+        let cbw = match cbw {
+            Some(cbw) => cbw,
+            None => {
+                self.state = State::Stalled;
+                return Err(ScsiError::from_cause(ErrorCause::ParseError));
+            }
+        };
+        self.cbw = cbw;
+        self.moved = 0;
+        let command = if cbw.lun != 0 {
+            // Logical unit not supported.
+            fail_illegal_request(responder, 0x25);
+            None
+        } else {
+            match ScsiCommand::pull_from_buffer(packet) {
+                Ok(command) => Some(command),
+                Err(err) => {
+                    reject_command(responder, &err);
+                    None
+                }
+            }
+        };
+        let expected = self.expected();
+        let data_in = expected > 0 && cbw.direction == Direction::IN;
+        let data_out = expected > 0 && cbw.direction == Direction::OUT;
+        match command {
+            Some(ScsiCommand::Read10(command)) if data_in => {
+                // Reads and writes are streamed rather than dispatched, so
+                // they are checked against reservations here.
+                let started = responder
+                    .check_access(MediumAccess::Read)
+                    .and_then(|_| responder.read10_start(command));
+                self.start_read(started);
+            }
+            Some(ScsiCommand::Read16(command)) if data_in => {
+                let started = responder
+                    .check_access(MediumAccess::Read)
+                    .and_then(|_| responder.read16_start(command));
+                self.start_read(started);
+            }
+            Some(ScsiCommand::ReadBuffer(command))
+                if data_in
+                    && matches!(
+                        command.mode,
+                        ReadBufferMode::Data | ReadBufferMode::EchoBuffer
+                    ) =>
+            {
+                // Streamed like a read, as the data can be larger than the
+                // response buffer.
+                let started = responder.check_access(MediumAccess::Read);
+                self.state = State::ReadBuffer {
+                    command,
+                    filled: 0,
+                    taken: 0,
+                    status: started.err().map(|_| failed()),
+                };
+            }
+            Some(ScsiCommand::Write10(command)) if data_out => {
+                let started = responder
+                    .check_access(MediumAccess::Write)
+                    .and_then(|_| responder.write10_start(command));
+                self.start_write(DataSink::Blocks, started);
+            }
+            Some(ScsiCommand::Write16(command)) if data_out => {
+                let started = responder
+                    .check_access(MediumAccess::Write)
+                    .and_then(|_| responder.write16_start(command));
+                self.start_write(DataSink::Blocks, started);
+            }
+            Some(ScsiCommand::WriteBuffer(command)) if data_out => {
+                // Streamed like a write, as firmware images are usually much
+                // larger than the block buffer.
+                let started = responder.check_access(MediumAccess::Write);
+                let offset = command.buffer_offset;
+                self.start_write(DataSink::Buffer { command, offset }, started);
+            }
+            Some(ScsiCommand::Verify10(command)) if data_out => {
+                let started = responder.check_access(MediumAccess::Read);
+                self.start_verify(command.into(), started);
+            }
+            Some(ScsiCommand::Verify16(command)) if data_out => {
+                let started = responder.check_access(MediumAccess::Read);
+                self.start_verify(command, started);
+            }
+            _ if data_out => {
+                let mut capacity = self.block.as_ref().len();
+                if let Some(ScsiCommand::CompareAndWrite(_)) = command {
+                    let spare = self.spare.insert(responder.memory_buffer());
+                    capacity += spare.as_ref().len();
+                }
+                if command.is_some() && expected > capacity {
+                    // Parameter list length error; running the command with
+                    // only part of its data would make it fail later anyway,
+                    // or worse, act on what did fit.
+                    fail_illegal_request(responder, 0x1a);
+                    self.state = State::Parameters { command: None };
+                } else {
+                    self.state = State::Parameters { command };
+                }
+            }
+            Some(command) => {
+                let mut data = ResponseData {
+                    buffer: &mut self.response,
+                    length: 0,
+                };
+                let result = dispatch_command(responder, command, expected, &mut data);
+                let length = data.length;
+                self.csw = match result {
+                    Ok((csw, _)) => csw,
+                    Err(err) => rejected(responder, &err),
+                };
+                self.state = State::Response { length };
+            }
+            None => {
+                self.csw = failed();
+                self.state = State::Response { length: 0 };
+            }
+        }
+        Ok(())
+    }
+
+    /// Copies as much data as fits into `packet` from the current source,
+    /// returning the number of bytes copied.
+    fn fill_packet(&mut self, responder: &mut R, packet: &mut [u8]) -> usize {
+        let expected = self.expected();
+        let remaining = expected - self.moved;
+        let packet_length = packet.len().min(remaining);
+        let mut length = 0;
+        while length < packet_length {
+            let wanted = packet_length - length;
+            let copied = match &mut self.state {
+                State::Response { length: available } => {
+                    let start = self.moved + length;
+                    let copied = wanted.min(*available - start.min(*available));
+                    packet[length..length + copied]
+                        .copy_from_slice(&self.response[start..start + copied]);
+                    copied
+                }
+                State::ReadBlocks {
+                    filled,
+                    taken,
+                    status,
+                } => {
+                    if *taken == *filled && status.is_none() {
+                        *filled = 0;
+                        *taken = 0;
+                        match responder.read_block(self.block.as_mut()) {
+                            Ok(None) => *filled = self.block.as_ref().len(),
+                            Ok(Some(csw)) => *status = Some(csw),
+                            Err(_) => *status = Some(failed()),
+                        }
+                    }
+                    let copied = wanted.min(*filled - *taken);
+                    packet[length..length + copied]
+                        .copy_from_slice(&self.block.as_ref()[*taken..*taken + copied]);
+                    *taken += copied;
+                    copied
+                }
+                State::ReadBuffer {
+                    command,
+                    filled,
+                    taken,
+                    status,
+                } => {
+                    if *taken == *filled && status.is_none() {
+                        let sent = self.moved + length;
+                        let chunk = self.block.as_ref().len().min(expected - sent);
+                        let offset = command.buffer_offset + sent as u32;
+                        let buffer = &mut self.block.as_mut()[..chunk];
+                        *filled = 0;
+                        *taken = 0;
+                        match responder.read_buffer(*command, offset, buffer) {
+                            Ok(None) => *filled = chunk,
+                            Ok(Some(csw)) => *status = Some(csw),
+                            Err(err) => *status = Some(rejected(responder, &err)),
+                        }
+                    }
+                    let copied = wanted.min(*filled - *taken);
+                    packet[length..length + copied]
+                        .copy_from_slice(&self.block.as_ref()[*taken..*taken + copied]);
+                    *taken += copied;
+                    copied
+                }
+                _ => 0,
+            };
+            if copied == 0 {
+                break;
+            }
+            length += copied;
+        }
+        length
+    }
+
+    /// Finishes a data phase towards the host once there is no more data to
+    /// send, moving on to the status.
+    fn end_data_in(&mut self, responder: &mut R) {
+        if let State::ReadBlocks { mut status, .. } = self.state {
+            // Like `process_command`, keep reading until the responder says
+            // it is done, dropping anything past the length the host wanted.
+            while status.is_none() {
+                status = match responder.read_block(self.block.as_mut()) {
+                    Ok(status) => status,
+                    Err(_) => Some(failed()),
+                };
+            }
+            self.csw = status.unwrap_or_default();
+        }
+        if let State::ReadBuffer { status, .. } = self.state {
+            self.csw = status.unwrap_or_default();
+        }
+        let expected = self.expected();
+        let moved = self.moved;
+        self.finish(moved);
+        self.state = if moved < expected && moved % self.max_packet_size == 0 {
+            State::EndOfData
+        } else {
+            State::Status
+        };
+    }
+
+    /// Passes streamed data on to the responder a block buffer at a time.
+    fn receive_blocks(&mut self, responder: &mut R, mut packet: &[u8]) {
+        let expected = self.expected();
+        packet = &packet[..packet.len().min(expected - self.moved)];
+        if let State::ReceiveChunks {
+            sink,
+            filled,
+            chunk,
+            accepted,
+            status,
+        } = &mut self.state
+        {
+            while !packet.is_empty() {
+                let copied = packet.len().min(*chunk - *filled);
+                self.block.as_mut()[*filled..*filled + copied].copy_from_slice(&packet[..copied]);
+                packet = &packet[copied..];
+                *filled += copied;
+                self.moved += copied;
+                if *filled < *chunk {
+                    continue;
+                }
+                if status.is_none() {
+                    *status = match sink.take(responder, &self.block.as_ref()[..*chunk]) {
+                        Ok(status) => status,
+                        Err(err) => Some(rejected(responder, &err)),
+                    };
+                    *accepted += *chunk;
+                }
+                *filled = 0;
+                *chunk = self.block.as_ref().len().min(expected - self.moved);
+            }
+            if self.moved == expected {
+                let csw = match status.take() {
+                    Some(csw) => csw,
+                    None => sink
+                        .end(responder)
+                        .unwrap_or_else(|err| rejected(responder, &err)),
+                };
+                let transferred = sink.transferred(self.moved, *accepted);
+                self.csw = csw;
+                self.finish(transferred);
+                self.state = State::Status;
+            }
+        }
+    }
+
+    /// Collects the data sent along with a command that is not streamed,
+    /// running the command once all of it has arrived. The data of a command
+    /// that has already been failed is dropped.
+    fn receive_parameters(&mut self, responder: &mut R, packet: &[u8]) {
+        let expected = self.expected();
+        let packet = &packet[..packet.len().min(expected - self.moved)];
+        let command = match self.state {
+            State::Parameters { command } => command,
+            _ => None,
+        };
+        if command.is_some() {
+            // `receive_command` made sure everything fits.
+            let first = self.block.as_mut();
+            let second = self
+                .spare
+                .as_mut()
+                .map_or(&mut [][..], |spare| spare.as_mut());
+            for (offset, &byte) in (self.moved..).zip(packet) {
+                match offset.checked_sub(first.len()) {
+                    None => first[offset] = byte,
+                    Some(offset) => second[offset] = byte,
+                }
+            }
+        }
+        self.moved += packet.len();
+        if self.moved < expected {
+            return;
+        }
+        let first = &self.block.as_ref()[..expected.min(self.block.as_ref().len())];
+        let second = match &self.spare {
+            Some(spare) => &spare.as_ref()[..expected - first.len()],
+            None => &[][..],
+        };
+        let mut data = ParameterData {
+            data: [first, second],
+            read: 0,
+        };
+        let result = match command {
+            Some(command) => dispatch_command(responder, command, expected, &mut data),
+            None => Ok((failed(), 0)),
+        };
+        self.spare = None;
+        let transferred = match result {
+            Ok((csw, transferred)) => {
+                self.csw = csw;
+                transferred
+            }
+            Err(err) => {
+                self.csw = rejected(responder, &err);
+                0
+            }
+        };
+        self.finish(transferred);
+        self.state = State::Status;
+    }
+
+    /// Sets the CSW's tag and residue to match the CBW, given the number of
+    /// bytes the command actually transferred.
+    fn finish(&mut self, transferred: usize) {
+        let expected = self.expected();
+        self.csw.tag = self.cbw.tag;
+        self.csw.data_residue = (expected - transferred.min(expected)) as u32;
+    }
+
+    /// Moves on to streaming the blocks of a read, failing it if it could not
+    /// be `started`.
+    fn start_read(&mut self, started: Result<(), ScsiError>) {
+        self.state = State::ReadBlocks {
+            filled: 0,
+            taken: 0,
+            status: started.err().map(|_| failed()),
+        };
+    }
+
+    /// Moves on to receiving the data of a byte-checked verify, failing it if
+    /// it could not be `started`.
+    fn start_verify(&mut self, command: Verify16Command, started: Result<(), ScsiError>) {
+        let block_address = command.block_address;
+        self.start_write(
+            DataSink::Verify {
+                command,
+                block_address,
+            },
+            started,
+        );
+    }
+
+    /// Moves on to receiving the data of a write for `sink`, failing it if it
+    /// could not be `started`.
+    fn start_write(&mut self, sink: DataSink, started: Result<(), ScsiError>) {
+        self.state = State::ReceiveChunks {
+            sink,
+            filled: 0,
+            chunk: self.block.as_ref().len().min(self.expected()),
+            accepted: 0,
+            status: started.err().map(|_| failed()),
+        };
+    }
+
+    /// The length of the current command's data phase.
+    fn expected(&self) -> usize {
+        self.cbw.data_transfer_length as usize
+    }
+}
+
+/// The CSW of a command that failed.
+fn failed() -> CommandStatusWrapper {
+    CommandStatusWrapper {
+        status: CommandStatusWrapper::COMMAND_FAILED,
+        ..CommandStatusWrapper::default()
+    }
+}
+
+/// The CSW of a command whose hook failed with `err`, telling the responder
+/// why if it does not implement the command.
+fn rejected<R: ScsiResponder>(responder: &mut R, err: &ScsiError) -> CommandStatusWrapper {
+    if err.cause == ErrorCause::UnsupportedOperationError {
+        reject_command(responder, err);
+    }
+    failed()
+}
+
+/// Collects a command's response, to be sent to the host afterwards.
+struct ResponseData<'a> {
+    buffer: &'a mut [u8],
+    length: usize,
+}
+
+impl DataPhase for ResponseData<'_> {
+    fn send(&mut self, data: &[u8]) -> Result<usize, ScsiError> {
+        let copied = data.len().min(self.buffer.len() - self.length);
+        self.buffer[self.length..self.length + copied].copy_from_slice(&data[..copied]);
+        self.length += copied;
+        Ok(copied)
+    }
+
+    fn receive(&mut self, _buffer: &mut [u8]) -> Result<usize, ScsiError> {
+        Err(ScsiError::from_cause(ErrorCause::UsbTransferError {
+            direction: UsbTransferDirection::In,
+        }))
+    }
+}
+
+/// Hands a command the data the host already sent along with it, which
+/// may have been split over two buffers.
+struct ParameterData<'a> {
+    data: [&'a [u8]; 2],
+    read: usize,
+}
+
+impl DataPhase for ParameterData<'_> {
+    fn send(&mut self, _data: &[u8]) -> Result<usize, ScsiError> {
+        Err(ScsiError::from_cause(ErrorCause::UsbTransferError {
+            direction: UsbTransferDirection::Out,
+        }))
+    }
+
+    fn receive(&mut self, buffer: &mut [u8]) -> Result<usize, ScsiError> {
+        let [first, second] = self.data;
+        let data = match self.read.checked_sub(first.len()) {
+            None => &first[self.read..],
+            Some(offset) => &second[offset..],
+        };
+        let copied = buffer.len().min(data.len());
+        buffer[..copied].copy_from_slice(&data[..copied]);
+        self.read += copied;
+        if copied == 0 && !buffer.is_empty() {
+            return Err(ScsiError::from_cause(ErrorCause::UsbTransferError {
+                direction: UsbTransferDirection::In,
+            }));
+        }
+        Ok(copied)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::BulkOnlyTransport;
+    use crate::error::{ErrorCause, ScsiError};
+    use crate::scsi::commands::{
+        CommandBlockWrapper, CommandStatusWrapper, Direction, InquiryCommand, LogSelectCommand,
+        PersistentReserveInCommand, PersistentReserveInServiceAction, RequestSenseCommand,
+        RequestSenseResponse, WriteBufferMode,
+    };
+    use crate::scsi::responder::tests::MemoryResponder;
+    use crate::scsi::ScsiBlockDevice;
+    use crate::traits::{BufferPullable, BufferPushable, CommunicationChannel};
+    use std::vec::Vec;
+
+    /// Moves transfers through a `BulkOnlyTransport` a packet at a time, the
+    /// way a host controller would.
+    struct PacketChannel {
+        transport: BulkOnlyTransport<MemoryResponder>,
+        responder: MemoryResponder,
+        packets: Vec<usize>,
+    }
+
+    impl PacketChannel {
+        fn new(max_packet_size: usize) -> PacketChannel {
+            let mut responder = MemoryResponder::default();
+            PacketChannel {
+                transport: BulkOnlyTransport::new(&mut responder, max_packet_size),
+                responder,
+                packets: Vec::new(),
+            }
+        }
+    }
+
+    impl CommunicationChannel for PacketChannel {
+        fn out_transfer<B: AsRef<[u8]>>(&mut self, bytes: B) -> Result<usize, ScsiError> {
+            let bytes = bytes.as_ref();
+            for packet in bytes.chunks(self.transport.max_packet_size()) {
+                self.transport.receive_packet(&mut self.responder, packet)?;
+            }
+            Ok(bytes.len())
+        }
+
+        fn in_transfer<B: AsMut<[u8]>>(&mut self, mut buffer: B) -> Result<usize, ScsiError> {
+            let buffer = buffer.as_mut();
+            let max_packet_size = self.transport.max_packet_size();
+            let mut packet = vec![0; max_packet_size];
+            let mut read = 0;
+            while read < buffer.len() {
+                let length = self
+                    .transport
+                    .next_packet(&mut self.responder, &mut packet)?
+                    .unwrap();
+                self.packets.push(length);
+                assert!(read + length <= buffer.len(), "babble");
+                buffer[read..read + length].copy_from_slice(&packet[..length]);
+                read += length;
+                if length < max_packet_size {
+                    break;
+                }
+            }
+            Ok(read)
+        }
+    }
+
+    #[test]
+    fn test_bulk_only_block_device() {
+        for &max_packet_size in &[64, 512] {
+            let channel = PacketChannel::new(max_packet_size);
+            let mut scratch = [0; 64];
+            let mut device = ScsiBlockDevice::new(channel, &mut scratch[..]).unwrap();
+            assert_eq!(device.num_blocks(), 1024);
+            device.read_block_limits().unwrap();
+
+            // 256 byte blocks are split into, or combined into, whole packets.
+            let data: Vec<u8> = (0..23 * 256).map(|idx| (idx % 253) as u8).collect();
+            assert_eq!(device.write_blocks(5, 23, &data).unwrap(), data.len());
+            let mut readback = vec![0; data.len()];
+            device.comm_channel.packets.clear();
+            assert_eq!(
+                device.read_blocks(5, 23, &mut readback).unwrap(),
+                data.len()
+            );
+            assert_eq!(readback, data);
+            let packets = &device.comm_channel.packets;
+            // Only the last data packet before each CSW may be short.
+            assert!(packets
+                .windows(2)
+                .all(|pair| pair[0] == max_packet_size || pair[0] == 13 || pair[1] == 13));
+            assert!(device.comm_channel.transport.is_idle());
+        }
+    }
+
+    #[test]
+    fn test_bulk_only_read_buffer() {
+        let channel = PacketChannel::new(64);
+        let mut scratch = [0; 64];
+        let mut device = ScsiBlockDevice::new(channel, &mut scratch[..]).unwrap();
+        let segment: Vec<u8> = (0..256).map(|idx| idx as u8).collect();
+        device
+            .write_buffer(WriteBufferMode::Data, 0, 512, &segment)
+            .unwrap();
+
+        // READ BUFFER data is streamed, however much larger it is than the
+        // response buffer.
+        let mut readback = vec![0xff; 1024];
+        assert_eq!(device.read_buffer(0, 0, &mut readback).unwrap(), 1024);
+        assert!(readback[..512].iter().all(|&byte| byte == 0));
+        assert_eq!(&readback[512..768], &segment[..]);
+        assert!(readback[768..].iter().all(|&byte| byte == 0));
+        assert!(device.comm_channel.transport.is_idle());
+    }
+
+    #[test]
+    fn test_bulk_only_write_buffer() {
+        let channel = PacketChannel::new(64);
+        let mut scratch = [0; 64];
+        let mut device = ScsiBlockDevice::new(channel, &mut scratch[..]).unwrap();
+
+        // WRITE BUFFER data is streamed too, so an image several times the
+        // size of the block buffer arrives whole, including a short last
+        // chunk.
+        let image: Vec<u8> = (0..1000).map(|idx| (idx % 251) as u8 + 1).collect();
+        device
+            .write_buffer(WriteBufferMode::Data, 0, 256, &image)
+            .unwrap();
+        let mut readback = vec![0xff; 1280];
+        assert_eq!(device.read_buffer(0, 0, &mut readback).unwrap(), 1280);
+        assert!(readback[..256].iter().all(|&byte| byte == 0));
+        assert_eq!(&readback[256..1256], &image[..]);
+        assert!(device.comm_channel.transport.is_idle());
+    }
+
+    #[test]
+    fn test_bulk_only_verify() {
+        let channel = PacketChannel::new(64);
+        let mut scratch = [0; 64];
+        let mut device = ScsiBlockDevice::new(channel, &mut scratch[..]).unwrap();
+        let mut data: Vec<u8> = (0..4 * 256).map(|idx| (idx % 249) as u8).collect();
+        device.write_blocks(7, 4, &data).unwrap();
+
+        // Byte-checked VERIFY data is compared a block buffer at a time.
+        device.verify(7, 4, Some(&data)).unwrap();
+        data[2 * 256 + 5] ^= 0xff;
+        let err = device.verify(7, 4, Some(&data)).unwrap_err();
+        assert!(matches!(err.cause, ErrorCause::FlagError { .. }));
+        let sense = device.request_sense().unwrap();
+        assert_eq!(sense.sense_key, RequestSenseResponse::MISCOMPARE);
+        assert_eq!(sense.information, 2 * 256 + 5);
+        assert!(device.comm_channel.transport.is_idle());
+    }
+
+    #[test]
+    fn test_bulk_only_parameters() {
+        let channel = PacketChannel::new(64);
+        let mut scratch = [0; 64];
+        let mut device = ScsiBlockDevice::new(channel, &mut scratch[..]).unwrap();
+        device.read_block_limits().unwrap();
+
+        // COMPARE AND WRITE data fills a block buffer with each half.
+        let old = [0x11; 256];
+        let new = [0x22; 256];
+        device.write_blocks(3, 1, &old[..]).unwrap();
+        device.compare_and_write(3, &old, &new).unwrap();
+        let mut readback = [0; 256];
+        device.read_blocks(3, 1, &mut readback[..]).unwrap();
+        assert_eq!(readback, new);
+
+        // Parameter lists longer than the block buffer are failed without
+        // being run, rather than cut short.
+        let command = LogSelectCommand::new(0x0d, 0);
+        assert!(device.log_select(command, &[0; 512]).is_err());
+        let sense = device.request_sense().unwrap();
+        assert_eq!(
+            (sense.sense_key, sense.additional_sense_code),
+            (RequestSenseResponse::ILLEGAL_REQUEST, 0x1a)
+        );
+        assert!(device.comm_channel.transport.is_idle());
+        assert!(device.comm_channel.transport.spare.is_none());
+
+        // Commands that were already refused keep the reason they were.
+        let mut cbw = CommandBlockWrapper::new(512, Direction::OUT, 0, 6);
+        let mut command = [0; 31];
+        cbw.tag = 5;
+        cbw.push_to_buffer(&mut command[..]).unwrap();
+        command[15] = 0xee;
+        let channel = &mut device.comm_channel;
+        channel.out_transfer(&command[..]).unwrap();
+        channel.out_transfer(&[0; 512][..]).unwrap();
+        let mut status = [0; 13];
+        channel.in_transfer(&mut status[..]).unwrap();
+        let csw = CommandStatusWrapper::pull_from_buffer(&status[..]).unwrap();
+        assert_eq!((csw.tag, csw.status), (5, 1));
+        let sense = device.request_sense().unwrap();
+        assert_eq!(sense.additional_sense_code, 0x20);
+    }
+
+    #[test]
+    fn test_bulk_only_short_data() {
+        let mut responder = MemoryResponder::default();
+        let mut transport = BulkOnlyTransport::new(&mut responder, 64);
+        let mut packet = [0; 64];
+
+        // Responses shorter than the host asked for end with a short packet.
+        let mut cbw = [0; 31];
+        InquiryCommand::new(128)
+            .push_to_buffer(&mut cbw[..])
+            .unwrap();
+        cbw[4] = 7;
+        cbw[8] = 128;
+        assert_eq!(
+            transport.next_packet(&mut responder, &mut packet).unwrap(),
+            None
+        );
+        transport.receive_packet(&mut responder, &cbw).unwrap();
+        assert_eq!(
+            transport.next_packet(&mut responder, &mut packet).unwrap(),
+            Some(36)
+        );
+        assert_eq!(
+            transport.next_packet(&mut responder, &mut packet).unwrap(),
+            Some(13)
+        );
+        let csw = CommandStatusWrapper::pull_from_buffer(&packet[..]).unwrap();
+        assert_eq!((csw.tag, csw.data_residue, csw.status), (7, 92, 0));
+        assert!(transport.is_idle());
+
+        // Commands the responder does not know fail, and if they end the data
+        // phase on a packet boundary it is closed by a zero-length packet.
+        let mut unknown = CommandBlockWrapper::new(128, Direction::IN, 0, 6);
+        unknown.tag = 9;
+        unknown.push_to_buffer(&mut cbw[..]).unwrap();
+        cbw[15] = 0xee;
+        transport.receive_packet(&mut responder, &cbw).unwrap();
+        assert_eq!(
+            transport.next_packet(&mut responder, &mut packet).unwrap(),
+            Some(0)
+        );
+        assert_eq!(
+            transport.next_packet(&mut responder, &mut packet).unwrap(),
+            Some(13)
+        );
+        let csw = CommandStatusWrapper::pull_from_buffer(&packet[..]).unwrap();
+        assert_eq!((csw.tag, csw.data_residue, csw.status), (9, 128, 1));
+
+        // Any data the host sends along with them is dropped.
+        cbw[12] = 0;
+        transport.receive_packet(&mut responder, &cbw).unwrap();
+        assert_eq!(
+            transport.next_packet(&mut responder, &mut packet).unwrap(),
+            None
+        );
+        transport.receive_packet(&mut responder, &[0; 64]).unwrap();
+        transport.receive_packet(&mut responder, &[0; 64]).unwrap();
+        assert_eq!(
+            transport.next_packet(&mut responder, &mut packet).unwrap(),
+            Some(13)
+        );
+        let csw = CommandStatusWrapper::pull_from_buffer(&packet[..]).unwrap();
+        assert_eq!((csw.data_residue, csw.status), (128, 1));
+
+        // So do commands whose hook the responder leaves unimplemented, and
+        // both are explained by the sense data that follows.
+        let request_sense = |transport: &mut BulkOnlyTransport<MemoryResponder>,
+                             responder: &mut MemoryResponder| {
+            let mut cbw = [0; 31];
+            let mut packet = [0; 64];
+            RequestSenseCommand::new(18)
+                .push_to_buffer(&mut cbw[..])
+                .unwrap();
+            transport.receive_packet(responder, &cbw).unwrap();
+            let length = transport.next_packet(responder, &mut packet).unwrap();
+            assert_eq!(length, Some(18));
+            let sense = RequestSenseResponse::pull_from_buffer(&packet[..]).unwrap();
+            transport.next_packet(responder, &mut packet).unwrap();
+            (sense.sense_key, sense.additional_sense_code)
+        };
+        assert_eq!(
+            request_sense(&mut transport, &mut responder),
+            (RequestSenseResponse::ILLEGAL_REQUEST, 0x20)
+        );
+        PersistentReserveInCommand::new(PersistentReserveInServiceAction::ReadKeys)
+            .push_to_buffer(&mut cbw[..])
+            .unwrap();
+        transport.receive_packet(&mut responder, &cbw).unwrap();
+        assert_eq!(
+            transport.next_packet(&mut responder, &mut packet).unwrap(),
+            Some(0)
+        );
+        assert_eq!(
+            transport.next_packet(&mut responder, &mut packet).unwrap(),
+            Some(13)
+        );
+        let csw = CommandStatusWrapper::pull_from_buffer(&packet[..]).unwrap();
+        assert_eq!(csw.status, CommandStatusWrapper::COMMAND_FAILED);
+        assert_eq!(
+            request_sense(&mut transport, &mut responder),
+            (RequestSenseResponse::ILLEGAL_REQUEST, 0x20)
+        );
+
+        // Invalid CBWs stall the transport until it is reset.
+        let err = transport.receive_packet(&mut responder, &cbw[..30]).err();
+        assert_eq!(err.unwrap().cause, ErrorCause::ParseError);
+        assert!(transport.is_stalled());
+        assert_eq!(
+            transport.next_packet(&mut responder, &mut packet).unwrap(),
+            None
+        );
+        assert!(transport.receive_packet(&mut responder, &cbw).is_err());
+        transport.reset();
+        assert!(transport.is_idle());
+        let err = transport
+            .next_packet(&mut responder, &mut packet[..13])
+            .err();
+        assert_eq!(
+            err.unwrap().cause,
+            ErrorCause::BufferTooSmallError {
+                expected: 64,
+                actual: 13,
+            }
+        );
+    }
+}
@@ -0,0 +1,773 @@
+use crate::scsi::commands::{
+    lba_to_msf, CommandStatusWrapper, DiscInformationResponse, EventStatusResponse,
+    FeatureDescriptor, GetConfigurationCommand, GetConfigurationResponse,
+    GetEventStatusNotificationCommand, InquiryCommand, InquiryIdentification, InquiryResponse,
+    MediaEventDescriptor, PreventAllowMediumRemovalCommand, Read10Command, ReadCapacityCommand,
+    ReadCapacityResponse, ReadDiscInformationCommand, ReadTocCommand, RequestSenseCommand,
+    RequestSenseResponse, StartStopUnitCommand, TestUnitReady, TocResponse, TocTrackDescriptor,
+    Write10Command,
+};
+use crate::scsi::ScsiResponder;
+use crate::ScsiError;
+
+use byteorder::{ByteOrder, BE};
+
+const CDROM_BLOCK_SIZE: usize = 2048;
+
+/// A source of disc image data, such as an ISO 9660 image, to be served by a
+/// `CdromResponder`.
+pub trait ImageSource {
+    /// Returns the total size of the image in bytes.
+    fn size(&mut self) -> Result<u64, ScsiError>;
+
+    /// Reads bytes starting at `offset` into `buffer`, returning the number of
+    /// bytes read. Reads past the end of the image may return fewer bytes than
+    /// requested.
+    fn read_at(&mut self, offset: u64, buffer: &mut [u8]) -> Result<usize, ScsiError>;
+}
+
+impl ImageSource for &[u8] {
+    fn size(&mut self) -> Result<u64, ScsiError> {
+        Ok(self.len() as u64)
+    }
+
+    fn read_at(&mut self, offset: u64, buffer: &mut [u8]) -> Result<usize, ScsiError> {
+        if offset >= self.len() as u64 {
+            return Ok(0);
+        }
+        let src = &self[offset as usize..];
+        let len = src.len().min(buffer.len());
+        buffer[..len].copy_from_slice(&src[..len]);
+        Ok(len)
+    }
+}
+
+#[cfg(feature = "std")]
+impl ImageSource for ::std::fs::File {
+    fn size(&mut self) -> Result<u64, ScsiError> {
+        Ok(self.metadata()?.len())
+    }
+
+    fn read_at(&mut self, offset: u64, buffer: &mut [u8]) -> Result<usize, ScsiError> {
+        use std::io::{Read, Seek, SeekFrom};
+        self.seek(SeekFrom::Start(offset))?;
+        let mut read = 0;
+        while read < buffer.len() {
+            let cur = self.read(&mut buffer[read..])?;
+            if cur == 0 {
+                break;
+            }
+            read += cur;
+        }
+        Ok(read)
+    }
+}
+
+/// The block buffer used by a `CdromResponder`, sized to a single 2048 byte
+/// CD-ROM sector.
+pub struct CdromBlock(pub [u8; CDROM_BLOCK_SIZE]);
+
+impl AsRef<[u8]> for CdromBlock {
+    fn as_ref(&self) -> &[u8] {
+        &self.0
+    }
+}
+
+impl AsMut<[u8]> for CdromBlock {
+    fn as_mut(&mut self) -> &mut [u8] {
+        &mut self.0
+    }
+}
+
+/// A `ScsiResponder` that emulates a read-only optical drive serving a disc
+/// image, such as an `.iso` file, as a single-session, single-track CD-ROM.
+///
+/// The responder reports itself as peripheral device type `0x05`, uses 2048
+/// byte blocks, and implements the MMC commands hosts use to probe optical
+/// drives. Media changes made through `insert`, `eject` and `request_eject`,
+/// or by the host through `StartStopUnitCommand`, are reported to the host
+/// both as media events and as unit attention conditions.
+pub struct CdromResponder<I: ImageSource> {
+    image: Option<I>,
+    tray_open: bool,
+    prevent_removal: bool,
+    unit_attention: bool,
+    pending_event: Option<u8>,
+    sense: RequestSenseResponse,
+    read_status: Option<CommandStatusWrapper>,
+    read_cursor: u32,
+    read_remaining: u16,
+}
+
+impl<I: ImageSource> CdromResponder<I> {
+    /// The size of a single CD-ROM sector in bytes.
+    pub const BLOCK_SIZE: u32 = CDROM_BLOCK_SIZE as u32;
+
+    /// Constructs a new `CdromResponder`, optionally with a disc already
+    /// inserted.
+    pub fn new(image: Option<I>) -> CdromResponder<I> {
+        CdromResponder {
+            tray_open: image.is_none(),
+            image,
+            prevent_removal: false,
+            unit_attention: false,
+            pending_event: None,
+            sense: RequestSenseResponse::default(),
+            read_status: None,
+            read_cursor: 0,
+            read_remaining: 0,
+        }
+    }
+
+    /// Inserts a new disc into the drive, closing the tray and notifying the
+    /// host. Returns the previously inserted disc, if any.
+    pub fn insert(&mut self, image: I) -> Option<I> {
+        let previous = self.image.take();
+        self.image = Some(image);
+        self.tray_open = false;
+        self.unit_attention = true;
+        self.pending_event = Some(if previous.is_some() {
+            MediaEventDescriptor::MEDIA_CHANGED
+        } else {
+            MediaEventDescriptor::NEW_MEDIA
+        });
+        previous
+    }
+
+    /// Forcibly removes the disc from the drive, regardless of whether the host
+    /// has locked it in place. Returns the removed disc, if any.
+    pub fn eject(&mut self) -> Option<I> {
+        let previous = self.image.take();
+        self.tray_open = true;
+        if previous.is_some() {
+            self.pending_event = Some(MediaEventDescriptor::MEDIA_REMOVAL);
+        }
+        previous
+    }
+
+    /// Emulates the user pressing the drive's eject button.
+    ///
+    /// If the host has not locked the medium the tray is opened immediately and
+    /// `true` is returned; otherwise an eject request event is queued for the
+    /// host to act on and `false` is returned.
+    pub fn request_eject(&mut self) -> bool {
+        if self.prevent_removal {
+            self.pending_event = Some(MediaEventDescriptor::EJECT_REQUEST);
+            false
+        } else {
+            self.open_tray();
+            true
+        }
+    }
+
+    /// Whether there is currently a readable disc in the drive.
+    pub fn is_medium_present(&self) -> bool {
+        self.image.is_some() && !self.tray_open
+    }
+
+    /// Whether the host has locked the medium in place.
+    pub fn is_locked(&self) -> bool {
+        self.prevent_removal
+    }
+
+    /// A reference to the currently inserted disc image, if any.
+    pub fn image(&self) -> Option<&I> {
+        self.image.as_ref()
+    }
+
+    fn open_tray(&mut self) {
+        if !self.tray_open && self.image.is_some() {
+            self.pending_event = Some(MediaEventDescriptor::MEDIA_REMOVAL);
+        }
+        self.tray_open = true;
+    }
+
+    fn close_tray(&mut self) {
+        if self.tray_open && self.image.is_some() {
+            self.pending_event = Some(MediaEventDescriptor::NEW_MEDIA);
+            self.unit_attention = true;
+        }
+        self.tray_open = false;
+    }
+
+    fn fail(&mut self, sense_key: u8, asc: u8, ascq: u8) -> CommandStatusWrapper {
+        self.sense = RequestSenseResponse::new(sense_key, asc, ascq);
+        CommandStatusWrapper {
+            status: CommandStatusWrapper::COMMAND_FAILED,
+            ..Default::default()
+        }
+    }
+
+    fn pass(&mut self) -> CommandStatusWrapper {
+        self.sense = RequestSenseResponse::default();
+        CommandStatusWrapper::default()
+    }
+
+    /// Checks that a disc can be accessed, returning the failing CSW if not.
+    fn check_ready(&mut self) -> Option<CommandStatusWrapper> {
+        if !self.is_medium_present() {
+            let ascq = if self.tray_open { 0x02 } else { 0x01 };
+            return Some(self.fail(RequestSenseResponse::NOT_READY, 0x3a, ascq));
+        }
+        if self.unit_attention {
+            self.unit_attention = false;
+            return Some(self.fail(RequestSenseResponse::UNIT_ATTENTION, 0x28, 0x00));
+        }
+        None
+    }
+
+    fn block_count(&mut self) -> Result<u32, ScsiError> {
+        let size = match self.image {
+            Some(ref mut image) => image.size()?,
+            None => 0,
+        };
+        let blocks = size.div_ceil(u64::from(Self::BLOCK_SIZE));
+        Ok(blocks.min(u64::from(u32::MAX)) as u32)
+    }
+
+    fn build_configuration(&mut self) -> Result<GetConfigurationResponse, ScsiError> {
+        let present = self.is_medium_present();
+        let current_profile = if present {
+            GetConfigurationResponse::PROFILE_CD_ROM
+        } else {
+            0
+        };
+        let mut profile_list = [0; 4];
+        BE::write_u16(&mut profile_list, GetConfigurationResponse::PROFILE_CD_ROM);
+        profile_list[2] = if present { 1 } else { 0 };
+        let mut random_readable = [0; 8];
+        BE::write_u32(&mut random_readable, Self::BLOCK_SIZE);
+        BE::write_u16(&mut random_readable[4..], 1);
+
+        let mut response = GetConfigurationResponse {
+            current_profile,
+            ..Default::default()
+        };
+        // Profile List
+        response.push_feature(FeatureDescriptor::new(
+            0x0000,
+            0,
+            true,
+            true,
+            &profile_list,
+        )?)?;
+        // Core, with a physical interface standard of USB
+        response.push_feature(FeatureDescriptor::new(
+            0x0001,
+            1,
+            true,
+            true,
+            &[0, 0, 0, 0x08, 0, 0, 0, 0],
+        )?)?;
+        // Removable Medium, with a tray loading mechanism that supports eject and lock
+        response.push_feature(FeatureDescriptor::new(
+            0x0003,
+            0,
+            true,
+            true,
+            &[0x29, 0, 0, 0],
+        )?)?;
+        // Random Readable
+        response.push_feature(FeatureDescriptor::new(
+            0x0010,
+            0,
+            false,
+            present,
+            &random_readable,
+        )?)?;
+        // CD Read
+        response.push_feature(FeatureDescriptor::new(0x001e, 0, false, present, &[0; 4])?)?;
+        Ok(response)
+    }
+}
+
+impl<I: ImageSource> ScsiResponder for CdromResponder<I> {
+    type BlockType = CdromBlock;
+
+    fn read_capacity(
+        &mut self,
+        _command: ReadCapacityCommand,
+    ) -> Result<(ReadCapacityResponse, CommandStatusWrapper), ScsiError> {
+        let response = ReadCapacityResponse {
+            logical_block_address: 0,
+            block_length: Self::BLOCK_SIZE,
+        };
+        if let Some(csw) = self.check_ready() {
+            return Ok((response, csw));
+        }
+        let blocks = self.block_count()?;
+        let response = ReadCapacityResponse {
+            logical_block_address: blocks.saturating_sub(1),
+            ..response
+        };
+        Ok((response, self.pass()))
+    }
+
+    fn inquiry(
+        &mut self,
+        _command: InquiryCommand,
+    ) -> Result<(InquiryResponse, CommandStatusWrapper), ScsiError> {
+        let response = InquiryResponse {
+            device_qualifier: 0,
+            device_type: 0x05,
+            removable_flags: 0x80,
+            spc_version: 0x05,
+            response_format: 0x02,
+        };
+        Ok((response, CommandStatusWrapper::default()))
+    }
+
+    fn identification(&mut self) -> InquiryIdentification {
+        InquiryIdentification {
+            vendor_identification: *b"SCSI-RS ",
+            product_identification: *b"Virtual CD-ROM  ",
+            product_revision_level: *b"0.2 ",
+        }
+    }
+
+    fn request_sense(
+        &mut self,
+        _command: RequestSenseCommand,
+    ) -> Result<CommandStatusWrapper, ScsiError> {
+        Ok(CommandStatusWrapper::default())
+    }
+
+    fn sense_data(
+        &mut self,
+        _command: RequestSenseCommand,
+    ) -> Result<(RequestSenseResponse, CommandStatusWrapper), ScsiError> {
+        let mut sense = self.sense;
+        self.sense = RequestSenseResponse::default();
+        if sense.sense_key == RequestSenseResponse::NO_SENSE && self.unit_attention {
+            self.unit_attention = false;
+            sense = RequestSenseResponse::new(RequestSenseResponse::UNIT_ATTENTION, 0x28, 0x00);
+        }
+        Ok((sense, CommandStatusWrapper::default()))
+    }
+
+    fn command_rejected(&mut self, sense: RequestSenseResponse) {
+        self.sense = sense;
+    }
+
+    fn test_unit_ready(
+        &mut self,
+        _command: TestUnitReady,
+    ) -> Result<CommandStatusWrapper, ScsiError> {
+        match self.check_ready() {
+            Some(csw) => Ok(csw),
+            None => Ok(self.pass()),
+        }
+    }
+
+    fn read10_start(&mut self, command: Read10Command) -> Result<(), ScsiError> {
+        self.read_status = None;
+        self.read_remaining = 0;
+        if let Some(csw) = self.check_ready() {
+            self.read_status = Some(csw);
+            return Ok(());
+        }
+        let blocks = self.block_count()?;
+        let end = u64::from(command.block_address) + u64::from(command.transfer_blocks);
+        if end > u64::from(blocks) {
+            let csw = self.fail(RequestSenseResponse::ILLEGAL_REQUEST, 0x21, 0x00);
+            self.read_status = Some(csw);
+            return Ok(());
+        }
+        self.read_cursor = command.block_address;
+        self.read_remaining = command.transfer_blocks;
+        Ok(())
+    }
+
+    fn read_block(&mut self, buffer: &mut [u8]) -> Result<Option<CommandStatusWrapper>, ScsiError> {
+        if let Some(csw) = self.read_status.take() {
+            return Ok(Some(csw));
+        }
+        if self.read_remaining == 0 {
+            return Ok(Some(self.pass()));
+        }
+        let offset = u64::from(self.read_cursor) * u64::from(Self::BLOCK_SIZE);
+        let read = match self.image {
+            Some(ref mut image) => image.read_at(offset, buffer)?,
+            None => 0,
+        };
+        for byte in &mut buffer[read..] {
+            *byte = 0;
+        }
+        self.read_cursor += 1;
+        self.read_remaining -= 1;
+        Ok(None)
+    }
+
+    fn write10_start(&mut self, _command: Write10Command) -> Result<(), ScsiError> {
+        Ok(())
+    }
+
+    fn write_block(&mut self, _buffer: &[u8]) -> Result<Option<CommandStatusWrapper>, ScsiError> {
+        self.write_end().map(Some)
+    }
+
+    fn write_end(&mut self) -> Result<CommandStatusWrapper, ScsiError> {
+        if let Some(csw) = self.check_ready() {
+            return Ok(csw);
+        }
+        Ok(self.fail(RequestSenseResponse::DATA_PROTECT, 0x27, 0x00))
+    }
+
+    fn memory_buffer(&mut self) -> Self::BlockType {
+        CdromBlock([0; CDROM_BLOCK_SIZE])
+    }
+
+    fn start_stop_unit(
+        &mut self,
+        command: StartStopUnitCommand,
+    ) -> Result<CommandStatusWrapper, ScsiError> {
+        if command.power_condition != 0 || !command.load_eject {
+            return Ok(self.pass());
+        }
+        if command.start {
+            self.close_tray();
+        } else if self.prevent_removal {
+            return Ok(self.fail(RequestSenseResponse::ILLEGAL_REQUEST, 0x53, 0x02));
+        } else {
+            self.open_tray();
+        }
+        Ok(self.pass())
+    }
+
+    fn prevent_allow_medium_removal(
+        &mut self,
+        command: PreventAllowMediumRemovalCommand,
+    ) -> Result<CommandStatusWrapper, ScsiError> {
+        self.prevent_removal = command.prevent;
+        Ok(self.pass())
+    }
+
+    fn read_toc(
+        &mut self,
+        command: ReadTocCommand,
+    ) -> Result<(TocResponse, CommandStatusWrapper), ScsiError> {
+        if let Some(csw) = self.check_ready() {
+            return Ok((TocResponse::default(), csw));
+        }
+        let address = |lba: u32| if command.msf { lba_to_msf(lba) } else { lba };
+        let data_track = TocTrackDescriptor {
+            adr_control: 0x14,
+            track_number: 1,
+            start_address: address(0),
+        };
+        let mut response = TocResponse {
+            first_track_number: 1,
+            last_track_number: 1,
+            ..Default::default()
+        };
+        match command.format {
+            ReadTocCommand::FORMAT_TOC => {
+                if command.track_number > 1
+                    && command.track_number != ReadTocCommand::LEAD_OUT_TRACK
+                {
+                    let csw = self.fail(RequestSenseResponse::ILLEGAL_REQUEST, 0x24, 0x00);
+                    return Ok((response, csw));
+                }
+                if command.track_number <= 1 {
+                    response.push_descriptor(data_track)?;
+                }
+                let lead_out = self.block_count()?;
+                response.push_descriptor(TocTrackDescriptor {
+                    adr_control: 0x14,
+                    track_number: ReadTocCommand::LEAD_OUT_TRACK,
+                    start_address: address(lead_out),
+                })?;
+            }
+            ReadTocCommand::FORMAT_SESSION_INFO => {
+                response.push_descriptor(data_track)?;
+            }
+            _ => {
+                let csw = self.fail(RequestSenseResponse::ILLEGAL_REQUEST, 0x24, 0x00);
+                return Ok((response, csw));
+            }
+        }
+        Ok((response, self.pass()))
+    }
+
+    fn get_configuration(
+        &mut self,
+        command: GetConfigurationCommand,
+    ) -> Result<(GetConfigurationResponse, CommandStatusWrapper), ScsiError> {
+        let all = self.build_configuration()?;
+        let mut response = GetConfigurationResponse {
+            current_profile: all.current_profile,
+            ..Default::default()
+        };
+        for feature in &all.features[..all.feature_count] {
+            let wanted = match command.request_type {
+                GetConfigurationCommand::RT_ALL => feature.feature_code >= command.starting_feature,
+                GetConfigurationCommand::RT_CURRENT => {
+                    feature.current && feature.feature_code >= command.starting_feature
+                }
+                GetConfigurationCommand::RT_ONE => feature.feature_code == command.starting_feature,
+                _ => {
+                    let csw = self.fail(RequestSenseResponse::ILLEGAL_REQUEST, 0x24, 0x00);
+                    return Ok((response, csw));
+                }
+            };
+            if wanted {
+                response.push_feature(*feature)?;
+            }
+        }
+        Ok((response, self.pass()))
+    }
+
+    fn get_event_status_notification(
+        &mut self,
+        command: GetEventStatusNotificationCommand,
+    ) -> Result<(EventStatusResponse, CommandStatusWrapper), ScsiError> {
+        let mut response = EventStatusResponse {
+            supported_event_classes: EventStatusResponse::CLASS_MEDIA,
+            media_event: None,
+        };
+        if !command.polled {
+            let csw = self.fail(RequestSenseResponse::ILLEGAL_REQUEST, 0x24, 0x00);
+            return Ok((response, csw));
+        }
+        if command.notification_class_request & EventStatusResponse::CLASS_MEDIA != 0 {
+            response.media_event = Some(MediaEventDescriptor {
+                event_code: self
+                    .pending_event
+                    .take()
+                    .unwrap_or(MediaEventDescriptor::NO_CHANGE),
+                media_present: self.is_medium_present(),
+                tray_open: self.tray_open,
+            });
+        }
+        Ok((response, self.pass()))
+    }
+
+    fn read_disc_information(
+        &mut self,
+        command: ReadDiscInformationCommand,
+    ) -> Result<(DiscInformationResponse, CommandStatusWrapper), ScsiError> {
+        if let Some(csw) = self.check_ready() {
+            return Ok((DiscInformationResponse::default(), csw));
+        }
+        if command.data_type != 0 {
+            let csw = self.fail(RequestSenseResponse::ILLEGAL_REQUEST, 0x24, 0x00);
+            return Ok((DiscInformationResponse::default(), csw));
+        }
+        Ok((DiscInformationResponse::default(), self.pass()))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::CdromResponder;
+    use crate::scsi::commands::{
+        CommandBlockWrapper, CommandStatusWrapper, Direction, EventStatusResponse,
+        GetConfigurationCommand, GetConfigurationResponse, GetEventStatusNotificationCommand,
+        InquiryCommand, MediaEventDescriptor, PreventAllowMediumRemovalCommand, Read10Command,
+        ReadCapacityCommand, ReadCapacityResponse, ReadTocCommand, RequestSenseCommand,
+        RequestSenseResponse, StandardInquiryData, StartStopUnitCommand, TestUnitReady,
+        TocResponse, Write10Command,
+    };
+    use crate::scsi::responder::tests::TestDualChannel;
+    use crate::scsi::ScsiResponder;
+    use crate::{BufferPullable, BufferPushable, CommunicationChannel, ScsiError};
+    use std::vec::Vec;
+
+    fn exchange<C: BufferPushable>(
+        dev: &mut CdromResponder<&[u8]>,
+        command: &C,
+        data_out: &[u8],
+    ) -> (Vec<u8>, CommandStatusWrapper) {
+        let mut host = TestDualChannel::default();
+        let mut device = host.reversed();
+        let mut command_buff = [0; 31];
+        command.push_to_buffer(&mut command_buff[..]).unwrap();
+        host.out_transfer(command_buff).unwrap();
+        host.out_transfer(data_out).unwrap();
+        dev.process_command(&mut device).unwrap();
+        let mut output = host.recv_buff.lock().unwrap().clone();
+        let csw_start = output.len() - CommandStatusWrapper::SIZE as usize;
+        let csw = CommandStatusWrapper::pull_from_buffer(&output[csw_start..]).unwrap();
+        output.truncate(csw_start);
+        (output, csw)
+    }
+
+    fn sense(dev: &mut CdromResponder<&[u8]>) -> RequestSenseResponse {
+        let (data, csw) = exchange(dev, &RequestSenseCommand::new(18), &[]);
+        assert_eq!(csw.status, CommandStatusWrapper::COMMAND_PASSED);
+        RequestSenseResponse::pull_from_buffer(data).unwrap()
+    }
+
+    /// A command the responder has no support for, pushed as is.
+    struct RawCommand([u8; 31]);
+
+    impl BufferPushable for RawCommand {
+        fn push_to_buffer<B: AsMut<[u8]>>(&self, mut buffer: B) -> Result<usize, ScsiError> {
+            buffer.as_mut()[..31].copy_from_slice(&self.0);
+            Ok(31)
+        }
+    }
+
+    fn media_event(dev: &mut CdromResponder<&[u8]>) -> MediaEventDescriptor {
+        let command = GetEventStatusNotificationCommand::default();
+        let (data, csw) = exchange(dev, &command, &[]);
+        assert_eq!(csw.status, CommandStatusWrapper::COMMAND_PASSED);
+        EventStatusResponse::pull_from_buffer(data)
+            .unwrap()
+            .media_event
+            .unwrap()
+    }
+
+    #[test]
+    fn test_cdrom_reads() {
+        let mut image = Vec::new();
+        for idx in 0..(2048 * 2 + 1024) {
+            image.push((idx / 2048 + 1) as u8);
+        }
+        let mut dev = CdromResponder::new(Some(&image[..]));
+
+        let (data, _) = exchange(&mut dev, &InquiryCommand::new(36), &[]);
+        let inquiry = StandardInquiryData::pull_from_buffer(data).unwrap();
+        assert_eq!(inquiry.response.device_type, 0x05);
+        assert_eq!(inquiry.response.removable_flags, 0x80);
+        assert_eq!(
+            &inquiry.identification.product_identification,
+            b"Virtual CD-ROM  "
+        );
+
+        let (_, csw) = exchange(&mut dev, &TestUnitReady::new(), &[]);
+        assert_eq!(csw.status, CommandStatusWrapper::COMMAND_PASSED);
+
+        // Hosts probe MODE SENSE(10) for the capabilities page while
+        // attaching, which is failed rather than left without a CSW.
+        let mut mode_sense = [0; 31];
+        CommandBlockWrapper::new(32, Direction::IN, 0, 10)
+            .push_to_buffer(&mut mode_sense[..])
+            .unwrap();
+        mode_sense[15..25].copy_from_slice(&[0x5a, 0, 0x2a, 0, 0, 0, 0, 0, 32, 0]);
+        let (data, csw) = exchange(&mut dev, &RawCommand(mode_sense), &[]);
+        assert_eq!(csw.status, CommandStatusWrapper::COMMAND_FAILED);
+        assert_eq!(csw.data_residue, 32);
+        assert!(data.is_empty());
+        let rejected = sense(&mut dev);
+        assert_eq!(rejected.sense_key, RequestSenseResponse::ILLEGAL_REQUEST);
+        assert_eq!(rejected.additional_sense_code, 0x20);
+
+        let (data, _) = exchange(&mut dev, &ReadCapacityCommand::new(), &[]);
+        let capacity = ReadCapacityResponse::pull_from_buffer(data).unwrap();
+        assert_eq!(capacity.logical_block_address, 2);
+        assert_eq!(capacity.block_length, 2048);
+
+        let read = Read10Command::new(2048, 2 * 2048, 2048).unwrap();
+        let (data, csw) = exchange(&mut dev, &read, &[]);
+        assert_eq!(csw.status, CommandStatusWrapper::COMMAND_PASSED);
+        assert_eq!(csw.data_residue, 0);
+        assert_eq!(data.len(), 2 * 2048);
+        assert!(data[..2048].iter().all(|b| *b == 2));
+        assert!(data[2048..3072].iter().all(|b| *b == 3));
+        assert!(data[3072..].iter().all(|b| *b == 0));
+
+        let read = Read10Command::new(2 * 2048, 2 * 2048, 2048).unwrap();
+        let (data, csw) = exchange(&mut dev, &read, &[]);
+        assert_eq!(csw.status, CommandStatusWrapper::COMMAND_FAILED);
+        assert_eq!(csw.data_residue, 2 * 2048);
+        assert!(data.is_empty());
+        assert_eq!(sense(&mut dev).additional_sense_code, 0x21);
+
+        let toc = ReadTocCommand::default();
+        let (data, _) = exchange(&mut dev, &toc, &[]);
+        let toc = TocResponse::pull_from_buffer(data).unwrap();
+        assert_eq!(toc.descriptor_count, 2);
+        assert_eq!(toc.descriptors[0].track_number, 1);
+        assert_eq!(
+            toc.descriptors[1].track_number,
+            ReadTocCommand::LEAD_OUT_TRACK
+        );
+        assert_eq!(toc.descriptors[1].start_address, 3);
+
+        let config = GetConfigurationCommand::new(GetConfigurationCommand::RT_ONE, 0x10, 64);
+        let (data, _) = exchange(&mut dev, &config, &[]);
+        let config = GetConfigurationResponse::pull_from_buffer(data).unwrap();
+        assert_eq!(
+            config.current_profile,
+            GetConfigurationResponse::PROFILE_CD_ROM
+        );
+        assert_eq!(config.feature_count, 1);
+        assert_eq!(config.features[0].data()[..4], [0, 0, 8, 0]);
+
+        let write = Write10Command::new(0, 2048, 2048).unwrap();
+        let (_, csw) = exchange(&mut dev, &write, &[0xff; 2048]);
+        assert_eq!(csw.status, CommandStatusWrapper::COMMAND_FAILED);
+        assert_eq!(
+            sense(&mut dev).sense_key,
+            RequestSenseResponse::DATA_PROTECT
+        );
+        assert!(image[..2048].iter().all(|b| *b == 1));
+    }
+
+    #[test]
+    fn test_cdrom_media_events() {
+        let first = [1u8; 4096];
+        let second = [2u8; 8192];
+        let mut dev = CdromResponder::new(Some(&first[..]));
+        assert_eq!(
+            media_event(&mut dev).event_code,
+            MediaEventDescriptor::NO_CHANGE
+        );
+
+        let lock = PreventAllowMediumRemovalCommand::new(true);
+        exchange(&mut dev, &lock, &[]);
+        let (_, csw) = exchange(&mut dev, &StartStopUnitCommand::eject(), &[]);
+        assert_eq!(csw.status, CommandStatusWrapper::COMMAND_FAILED);
+        let locked_sense = sense(&mut dev);
+        assert_eq!(
+            locked_sense.sense_key,
+            RequestSenseResponse::ILLEGAL_REQUEST
+        );
+        assert_eq!(locked_sense.additional_sense_code, 0x53);
+        assert!(!dev.request_eject());
+        assert_eq!(
+            media_event(&mut dev).event_code,
+            MediaEventDescriptor::EJECT_REQUEST
+        );
+
+        exchange(&mut dev, &PreventAllowMediumRemovalCommand::new(false), &[]);
+        let (_, csw) = exchange(&mut dev, &StartStopUnitCommand::eject(), &[]);
+        assert_eq!(csw.status, CommandStatusWrapper::COMMAND_PASSED);
+        let event = media_event(&mut dev);
+        assert_eq!(event.event_code, MediaEventDescriptor::MEDIA_REMOVAL);
+        assert!(event.tray_open);
+        assert!(!event.media_present);
+
+        let (_, csw) = exchange(&mut dev, &TestUnitReady::new(), &[]);
+        assert_eq!(csw.status, CommandStatusWrapper::COMMAND_FAILED);
+        let not_ready = sense(&mut dev);
+        assert_eq!(not_ready.sense_key, RequestSenseResponse::NOT_READY);
+        assert_eq!(not_ready.additional_sense_code, 0x3a);
+
+        assert_eq!(dev.insert(&second[..]).unwrap().len(), 4096);
+        let event = media_event(&mut dev);
+        assert_eq!(event.event_code, MediaEventDescriptor::MEDIA_CHANGED);
+        assert!(event.media_present);
+
+        let (_, csw) = exchange(&mut dev, &TestUnitReady::new(), &[]);
+        assert_eq!(csw.status, CommandStatusWrapper::COMMAND_FAILED);
+        assert_eq!(
+            sense(&mut dev).sense_key,
+            RequestSenseResponse::UNIT_ATTENTION
+        );
+        let (_, csw) = exchange(&mut dev, &TestUnitReady::new(), &[]);
+        assert_eq!(csw.status, CommandStatusWrapper::COMMAND_PASSED);
+
+        let (data, _) = exchange(&mut dev, &ReadCapacityCommand::new(), &[]);
+        let capacity = ReadCapacityResponse::pull_from_buffer(data).unwrap();
+        assert_eq!(capacity.logical_block_address, 3);
+
+        assert!(dev.eject().is_some());
+        assert!(!dev.is_medium_present());
+        assert_eq!(
+            media_event(&mut dev).event_code,
+            MediaEventDescriptor::MEDIA_REMOVAL
+        );
+    }
+}
@@ -0,0 +1,167 @@
+use crate::error::{ErrorCause, ScsiError};
+use crate::traits::{BufferPullable, BufferPushable};
+use byteorder::{ByteOrder, BE};
+
+/// The Block Limits vital product data page, returned in response to an
+/// `InquiryCommand` created with `VpdInquiryCommand::new(BlockLimitsPage::PAGE_CODE, ..)`.
+///
+/// All lengths are in units of logical blocks, and a value of 0 means that the
+/// device does not report a limit.
+#[derive(Clone, Copy, Eq, PartialEq, Debug, Default)]
+pub struct BlockLimitsPage {
+    /// The peripheral qualifier and device type, as in the `InquiryResponse`.
+    pub peripheral: u8,
+
+    /// The largest number of blocks a single compare and write may cover.
+    pub max_compare_and_write_length: u8,
+
+    /// The preferred granularity of transfer lengths.
+    pub optimal_transfer_length_granularity: u16,
+
+    /// The largest number of blocks a single read or write may transfer.
+    pub max_transfer_length: u32,
+
+    /// The preferred number of blocks per read or write.
+    pub optimal_transfer_length: u32,
+
+    /// The largest number of blocks a single prefetch may cover.
+    pub max_prefetch_length: u32,
+
+    /// The largest number of blocks a single unmap may release.
+    pub max_unmap_lba_count: u32,
+
+    /// The largest number of block descriptors in a single unmap.
+    pub max_unmap_block_descriptor_count: u32,
+
+    /// The preferred granularity of unmapped regions.
+    pub optimal_unmap_granularity: u32,
+
+    /// The first block of the first whole unit of `optimal_unmap_granularity`,
+    /// if the device reports one; otherwise units start at block 0.
+    pub unmap_granularity_alignment: Option<u32>,
+
+    /// The largest number of blocks a single write same may cover.
+    pub max_write_same_length: u64,
+}
+
+impl BlockLimitsPage {
+    /// The vital product data page code of the Block Limits page.
+    pub const PAGE_CODE: u8 = 0xb0;
+
+    /// The size of the Block Limits page, in bytes.
+    pub const SIZE: usize = 64;
+
+    /// The size of the Block Limits page sent by older devices, which stops
+    /// after `optimal_transfer_length`.
+    const MIN_SIZE: usize = 16;
+}
+
+/// The bit marking `unmap_granularity_alignment` as valid.
+const UGAVALID: u32 = 0x8000_0000;
+
+impl BufferPushable for BlockLimitsPage {
+    fn push_to_buffer<B: AsMut<[u8]>>(&self, mut buffer: B) -> Result<usize, ScsiError> {
+        let buffer = buffer.as_mut();
+        if buffer.len() < BlockLimitsPage::SIZE {
+            return Err(ScsiError::from_cause(ErrorCause::BufferTooSmallError {
+                expected: BlockLimitsPage::SIZE,
+                actual: buffer.len(),
+            }));
+        }
+        for byte in &mut buffer[..BlockLimitsPage::SIZE] {
+            *byte = 0;
+        }
+        buffer[0] = self.peripheral;
+        buffer[1] = BlockLimitsPage::PAGE_CODE;
+        BE::write_u16(&mut buffer[2..], (BlockLimitsPage::SIZE - 4) as u16);
+        buffer[5] = self.max_compare_and_write_length;
+        BE::write_u16(&mut buffer[6..], self.optimal_transfer_length_granularity);
+        BE::write_u32(&mut buffer[8..], self.max_transfer_length);
+        BE::write_u32(&mut buffer[12..], self.optimal_transfer_length);
+        BE::write_u32(&mut buffer[16..], self.max_prefetch_length);
+        BE::write_u32(&mut buffer[20..], self.max_unmap_lba_count);
+        BE::write_u32(&mut buffer[24..], self.max_unmap_block_descriptor_count);
+        BE::write_u32(&mut buffer[28..], self.optimal_unmap_granularity);
+        if let Some(alignment) = self.unmap_granularity_alignment {
+            BE::write_u32(&mut buffer[32..], alignment | UGAVALID);
+        }
+        BE::write_u64(&mut buffer[36..], self.max_write_same_length);
+        Ok(BlockLimitsPage::SIZE)
+    }
+}
+
+impl BufferPullable for BlockLimitsPage {
+    fn pull_from_buffer<B: AsRef<[u8]>>(buffer: B) -> Result<Self, ScsiError> {
+        let buffer = buffer.as_ref();
+        if buffer.len() < BlockLimitsPage::MIN_SIZE {
+            return Err(ScsiError::from_cause(ErrorCause::BufferTooSmallError {
+                expected: BlockLimitsPage::MIN_SIZE,
+                actual: buffer.len(),
+            }));
+        }
+        if buffer[1] != BlockLimitsPage::PAGE_CODE {
+            return Err(ScsiError::from_cause(ErrorCause::ParseError));
+        }
+        let available = (usize::from(BE::read_u16(&buffer[2..])) + 4).min(buffer.len());
+        let mut page = BlockLimitsPage {
+            peripheral: buffer[0],
+            max_compare_and_write_length: buffer[5],
+            optimal_transfer_length_granularity: BE::read_u16(&buffer[6..]),
+            max_transfer_length: BE::read_u32(&buffer[8..]),
+            optimal_transfer_length: BE::read_u32(&buffer[12..]),
+            ..Default::default()
+        };
+        if available >= 32 {
+            page.max_prefetch_length = BE::read_u32(&buffer[16..]);
+            page.max_unmap_lba_count = BE::read_u32(&buffer[20..]);
+            page.max_unmap_block_descriptor_count = BE::read_u32(&buffer[24..]);
+            page.optimal_unmap_granularity = BE::read_u32(&buffer[28..]);
+        }
+        if available >= 36 && buffer[32] & 0x80 != 0 {
+            page.unmap_granularity_alignment = Some(BE::read_u32(&buffer[32..]) & !UGAVALID);
+        }
+        if available >= 44 {
+            page.max_write_same_length = BE::read_u64(&buffer[36..]);
+        }
+        Ok(page)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::BlockLimitsPage;
+    use crate::{BufferPullable, BufferPushable};
+
+    #[test]
+    pub fn test_blocklimitspage() {
+        let expected: [u8; 16] = [
+            0x00, 0xb0, 0x00, 0x3c, 0x00, 0x01, 0x00, 0x08, 0x00, 0x00, 0x01, 0x00, 0x00, 0x00,
+            0x00, 0x80,
+        ];
+        let mut buff = [0xff; 70];
+        let page = BlockLimitsPage {
+            max_compare_and_write_length: 1,
+            optimal_transfer_length_granularity: 8,
+            max_transfer_length: 0x100,
+            optimal_transfer_length: 0x80,
+            max_unmap_lba_count: 0x1000,
+            optimal_unmap_granularity: 8,
+            unmap_granularity_alignment: Some(3),
+            max_write_same_length: 0x2000,
+            ..Default::default()
+        };
+        let pushed = page.push_to_buffer(&mut buff[..]).unwrap();
+        assert_eq!(pushed, 64);
+        assert_eq!(&buff[0..16], &expected);
+        assert_eq!(&buff[32..36], &[0x80, 0x00, 0x00, 0x03]);
+
+        let pulled = BlockLimitsPage::pull_from_buffer(&buff[..pushed]).unwrap();
+        assert_eq!(pulled, page);
+
+        // Older devices only send the first 16 bytes.
+        buff[3] = 0x0c;
+        let pulled = BlockLimitsPage::pull_from_buffer(&buff[..16]).unwrap();
+        assert_eq!(pulled.max_transfer_length, 0x100);
+        assert_eq!(pulled.max_unmap_lba_count, 0);
+    }
+}
@@ -0,0 +1,162 @@
+use crate::error::{ErrorCause, ScsiError};
+use crate::scsi::commands::{Command, CommandBlockWrapper, Direction, RequestSenseResponse};
+use crate::traits::{BufferPullable, BufferPushable};
+
+use byteorder::{ByteOrder, BE};
+
+/// The bit in the second byte of a COMPARE AND WRITE command asking for the
+/// written blocks to reach the medium before the command completes.
+const FORCE_UNIT_ACCESS_FLAG: u8 = 0x8;
+
+/// Atomically compares a range of blocks against data sent along with the
+/// command and, only if every byte matches, overwrites them with more data
+/// sent after it.
+///
+/// The data sent along with the command holds the blocks to compare against
+/// followed by the blocks to write, so it is twice the length of the range.
+/// Clustered file systems use this as a test-and-set on a lock block. A
+/// failed comparison fails the command with a `MISCOMPARE` sense key whose
+/// `information` is the offset of the first differing byte in the compared
+/// half, and leaves the blocks untouched.
+#[derive(Clone, Copy, Eq, PartialEq, Debug, Default)]
+pub struct CompareAndWriteCommand {
+    /// The first block to compare and write.
+    pub block_address: u64,
+
+    /// The number of bytes in a single block.
+    pub block_size: u32,
+
+    /// The number of blocks to compare and write.
+    pub transfer_blocks: u8,
+
+    /// Whether the written blocks must reach the medium, rather than just the
+    /// device's cache, before the command completes.
+    pub force_unit_access: bool,
+}
+
+impl CompareAndWriteCommand {
+    /// Constructs a command comparing and writing the `transfer_blocks` blocks
+    /// starting at `block_address`.
+    pub fn new(block_address: u64, transfer_blocks: u8, block_size: u32) -> CompareAndWriteCommand {
+        CompareAndWriteCommand {
+            block_address,
+            block_size,
+            transfer_blocks,
+            force_unit_access: false,
+        }
+    }
+
+    /// The length of each half of the data sent along with the command, in
+    /// bytes.
+    pub fn half_length(&self) -> u32 {
+        u32::from(self.transfer_blocks) * self.block_size
+    }
+
+    /// Turns the failure of a compare and write into a `MiscompareError` if
+    /// `sense` says the comparison failed, or returns `error` otherwise.
+    pub(crate) fn miscompare(error: ScsiError, sense: &RequestSenseResponse) -> ScsiError {
+        if sense.sense_key != RequestSenseResponse::MISCOMPARE {
+            return error;
+        }
+        let offset = sense.valid.then_some(sense.information as usize);
+        ScsiError::from_cause(ErrorCause::MiscompareError { offset })
+    }
+}
+
+impl Command for CompareAndWriteCommand {
+    fn opcode() -> u8 {
+        0x89
+    }
+    fn length() -> u8 {
+        16
+    }
+    fn wrapper(&self) -> CommandBlockWrapper {
+        CommandBlockWrapper::new(
+            2 * self.half_length(),
+            Direction::OUT,
+            0,
+            CompareAndWriteCommand::length(),
+        )
+    }
+}
+
+impl BufferPushable for CompareAndWriteCommand {
+    fn push_to_buffer<B: AsMut<[u8]>>(&self, mut buffer: B) -> Result<usize, ScsiError> {
+        let rval = self.wrapper().push_to_buffer(buffer.as_mut())?;
+        let buffer = &mut buffer.as_mut()[rval..];
+        buffer[0] = CompareAndWriteCommand::opcode();
+        buffer[1] = if self.force_unit_access {
+            FORCE_UNIT_ACCESS_FLAG
+        } else {
+            0
+        };
+        BE::write_u64(&mut buffer[2..], self.block_address);
+        for byte in &mut buffer[10..13] {
+            *byte = 0;
+        }
+        buffer[13] = self.transfer_blocks;
+        buffer[14] = 0;
+        buffer[15] = 0;
+        Ok(rval + 16)
+    }
+}
+
+impl BufferPullable for CompareAndWriteCommand {
+    fn pull_from_buffer<B: AsRef<[u8]>>(buffer: B) -> Result<Self, ScsiError> {
+        let wrapper: CommandBlockWrapper = CommandBlockWrapper::pull_from_buffer(buffer.as_ref())?;
+        if wrapper.cb_length != CompareAndWriteCommand::length()
+            || (wrapper.data_transfer_length != 0 && wrapper.direction != Direction::OUT)
+        {
+            return Err(ScsiError::from_cause(ErrorCause::ParseError));
+        }
+        let buffer = &buffer.as_ref()[15..];
+        if buffer[0] != CompareAndWriteCommand::opcode() {
+            return Err(ScsiError::from_cause(ErrorCause::ParseError));
+        }
+        let transfer_blocks = buffer[13];
+        let block_size = wrapper
+            .data_transfer_length
+            .checked_div(2 * u32::from(transfer_blocks))
+            .unwrap_or(0);
+        Ok(CompareAndWriteCommand {
+            block_address: BE::read_u64(&buffer[2..]),
+            block_size,
+            transfer_blocks,
+            force_unit_access: buffer[1] & FORCE_UNIT_ACCESS_FLAG != 0,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::CompareAndWriteCommand;
+    use crate::scsi::commands::{Command, Direction};
+    use crate::{BufferPullable, BufferPushable};
+
+    #[test]
+    pub fn test_compareandwrite() {
+        let expected: [u8; 31] = [
+            0x55, 0x53, 0x42, 0x43, 0x00, 0x00, 0x00, 0x00, 0x00, 0x08, 0x00, 0x00, 0x00, 0x00,
+            0x10, 0x89, 0x08, 0x00, 0x00, 0x00, 0x01, 0x00, 0x00, 0x00, 0x20, 0x00, 0x00, 0x00,
+            0x02, 0x00, 0x00,
+        ];
+        let mut buff = [0; 32];
+        let mut command = CompareAndWriteCommand::new(0x0001_0000_0020, 2, 512);
+        command.force_unit_access = true;
+        assert_eq!(command.half_length(), 1024);
+        assert_eq!(command.wrapper().direction, Direction::OUT);
+        let pushed = command.push_to_buffer(&mut buff).unwrap();
+        assert_eq!(pushed, 31);
+        assert_eq!(&buff[0..pushed], &expected[0..pushed]);
+        assert_eq!(
+            CompareAndWriteCommand::pull_from_buffer(buff).unwrap(),
+            command
+        );
+
+        // Without any blocks there is no data to work the block size out from.
+        let command = CompareAndWriteCommand::new(7, 0, 512);
+        command.push_to_buffer(&mut buff).unwrap();
+        let pulled = CompareAndWriteCommand::pull_from_buffer(buff).unwrap();
+        assert_eq!((pulled.block_address, pulled.block_size), (7, 0));
+    }
+}
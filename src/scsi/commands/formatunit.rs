@@ -0,0 +1,319 @@
+use crate::error::{ErrorCause, ScsiError};
+use crate::scsi::commands::{Command, CommandBlockWrapper, Direction};
+use crate::traits::{BufferPullable, BufferPushable};
+
+use byteorder::{ByteOrder, BE};
+
+/// The bit in the second byte of a FORMAT UNIT command saying that a
+/// parameter list is sent along with it.
+const FORMAT_DATA_FLAG: u8 = 0x10;
+
+/// The bit in the second byte of a FORMAT UNIT command saying that the sent
+/// defect list replaces the device's grown defect list.
+const COMPLETE_LIST_FLAG: u8 = 0x8;
+
+/// The bit in the second byte of a FORMAT UNIT command saying that the
+/// parameter list uses the long header.
+const LONG_LIST_FLAG: u8 = 0x20;
+
+/// Asks the device to low-level format its medium, which erases every block.
+///
+/// Formatting can take a long time. If the parameter list's `immediate` flag
+/// is set, the device reports the command's status straight away and keeps
+/// formatting in the background; until it is done, it fails commands that
+/// access the medium with a `NOT_READY` sense key, and REQUEST SENSE reports
+/// how far along it is (see `RequestSenseResponse::progress`).
+#[derive(Clone, Copy, Eq, PartialEq, Debug, Default)]
+pub struct FormatUnitCommand {
+    /// Whether a `FormatParameters` list is sent along with the command.
+    pub format_data: bool,
+
+    /// Whether the defect list following the parameter list header replaces
+    /// the device's grown defect list, rather than adding to it.
+    pub complete_list: bool,
+
+    /// The format of the defect list following the parameter list header.
+    pub defect_list_format: u8,
+
+    /// Whether the parameter list uses the 8 byte long header rather than the
+    /// 4 byte short one.
+    pub long_list: bool,
+
+    /// The number of parameter bytes sent along with the command; this is only
+    /// carried in the CBW.
+    pub parameter_list_length: u32,
+}
+
+impl FormatUnitCommand {
+    /// The size of the short parameter list header, in bytes.
+    pub const SHORT_HEADER_SIZE: usize = 4;
+
+    /// The size of the long parameter list header, in bytes.
+    pub const LONG_HEADER_SIZE: usize = 8;
+
+    /// Constructs a command formatting the medium with the device's default
+    /// settings, without a parameter list.
+    pub fn new() -> FormatUnitCommand {
+        FormatUnitCommand::default()
+    }
+
+    /// Constructs a command sending `parameters` along with it, using the
+    /// short parameter list header.
+    pub fn with_parameters(parameters: &FormatParameters) -> FormatUnitCommand {
+        FormatUnitCommand {
+            format_data: true,
+            parameter_list_length: (FormatUnitCommand::SHORT_HEADER_SIZE as u32)
+                + parameters.defect_list_length,
+            ..FormatUnitCommand::default()
+        }
+    }
+
+    /// Parses the header of the parameter list sent along with the command,
+    /// returning `None` if the command has no parameter list.
+    ///
+    /// # Errors
+    /// Returns a `BufferTooSmallError` if `buffer` does not hold the whole
+    /// header.
+    pub fn pull_parameter_list(
+        &self,
+        buffer: &[u8],
+    ) -> Result<Option<FormatParameters>, ScsiError> {
+        if !self.format_data {
+            return Ok(None);
+        }
+        let header_size = if self.long_list {
+            FormatUnitCommand::LONG_HEADER_SIZE
+        } else {
+            FormatUnitCommand::SHORT_HEADER_SIZE
+        };
+        if buffer.len() < header_size {
+            return Err(ScsiError::from_cause(ErrorCause::BufferTooSmallError {
+                expected: header_size,
+                actual: buffer.len(),
+            }));
+        }
+        let mut parameters = FormatParameters::pull_from_buffer(buffer)?;
+        if self.long_list {
+            parameters.defect_list_length = BE::read_u32(&buffer[4..]);
+        }
+        Ok(Some(parameters))
+    }
+}
+
+impl Command for FormatUnitCommand {
+    fn opcode() -> u8 {
+        0x04
+    }
+    fn length() -> u8 {
+        6
+    }
+    fn wrapper(&self) -> CommandBlockWrapper {
+        let direction = if self.parameter_list_length == 0 {
+            Direction::NONE
+        } else {
+            Direction::OUT
+        };
+        CommandBlockWrapper::new(
+            self.parameter_list_length,
+            direction,
+            0,
+            FormatUnitCommand::length(),
+        )
+    }
+}
+
+impl BufferPushable for FormatUnitCommand {
+    fn push_to_buffer<B: AsMut<[u8]>>(&self, mut buffer: B) -> Result<usize, ScsiError> {
+        let rval = self.wrapper().push_to_buffer(buffer.as_mut())?;
+        let buffer = &mut buffer.as_mut()[rval..];
+        buffer[0] = FormatUnitCommand::opcode();
+        let mut flags = self.defect_list_format & 0x7;
+        if self.format_data {
+            flags |= FORMAT_DATA_FLAG;
+        }
+        if self.complete_list {
+            flags |= COMPLETE_LIST_FLAG;
+        }
+        if self.long_list {
+            flags |= LONG_LIST_FLAG;
+        }
+        buffer[1] = flags;
+        for byte in &mut buffer[2..6] {
+            *byte = 0;
+        }
+        Ok(rval + 6)
+    }
+}
+
+impl BufferPullable for FormatUnitCommand {
+    fn pull_from_buffer<B: AsRef<[u8]>>(buffer: B) -> Result<Self, ScsiError> {
+        let wrapper: CommandBlockWrapper = CommandBlockWrapper::pull_from_buffer(buffer.as_ref())?;
+        if wrapper.cb_length != FormatUnitCommand::length()
+            || (wrapper.data_transfer_length != 0 && wrapper.direction != Direction::OUT)
+        {
+            return Err(ScsiError::from_cause(ErrorCause::ParseError));
+        }
+        let buffer = &buffer.as_ref()[15..];
+        if buffer[0] != FormatUnitCommand::opcode() {
+            return Err(ScsiError::from_cause(ErrorCause::ParseError));
+        }
+        let flags = buffer[1];
+        Ok(FormatUnitCommand {
+            format_data: flags & FORMAT_DATA_FLAG != 0,
+            complete_list: flags & COMPLETE_LIST_FLAG != 0,
+            defect_list_format: flags & 0x7,
+            long_list: flags & LONG_LIST_FLAG != 0,
+            parameter_list_length: wrapper.data_transfer_length,
+        })
+    }
+}
+
+/// The header of the parameter list sent along with a `FormatUnitCommand`.
+///
+/// Any defect list or initialization pattern following the header has to be
+/// sent by the caller.
+#[derive(Clone, Copy, Eq, PartialEq, Debug, Default)]
+pub struct FormatParameters {
+    /// Whether the `disable_primary` and `disable_certification` flags
+    /// should be used, rather than the device's defaults.
+    pub format_options_valid: bool,
+
+    /// Whether the device should ignore its primary defect list.
+    pub disable_primary: bool,
+
+    /// Whether the device should skip certifying the medium.
+    pub disable_certification: bool,
+
+    /// Whether the device should fail the format if a defect list it needs
+    /// cannot be found.
+    pub stop_format: bool,
+
+    /// Whether an initialization pattern descriptor follows the header.
+    pub initialization_pattern: bool,
+
+    /// Whether the device should report the command's status before it has
+    /// finished formatting.
+    pub immediate: bool,
+
+    /// The length of the defect list following the header, in bytes.
+    pub defect_list_length: u32,
+}
+
+impl BufferPushable for FormatParameters {
+    fn push_to_buffer<B: AsMut<[u8]>>(&self, mut buffer: B) -> Result<usize, ScsiError> {
+        let buffer = buffer.as_mut();
+        if buffer.len() < FormatUnitCommand::SHORT_HEADER_SIZE {
+            return Err(ScsiError::from_cause(ErrorCause::BufferTooSmallError {
+                expected: FormatUnitCommand::SHORT_HEADER_SIZE,
+                actual: buffer.len(),
+            }));
+        }
+        if self.defect_list_length > u32::from(u16::MAX) {
+            return Err(ScsiError::from_cause(ErrorCause::TransferTooLargeError {
+                actual: self.defect_list_length as usize,
+                max: usize::from(u16::MAX),
+            }));
+        }
+        let flags = [
+            (self.format_options_valid, 0x80),
+            (self.disable_primary, 0x40),
+            (self.disable_certification, 0x20),
+            (self.stop_format, 0x10),
+            (self.initialization_pattern, 0x8),
+            (self.immediate, 0x2),
+        ];
+        buffer[0] = 0;
+        buffer[1] = flags
+            .iter()
+            .filter(|(set, _)| *set)
+            .fold(0, |byte, (_, bit)| byte | bit);
+        BE::write_u16(&mut buffer[2..], self.defect_list_length as u16);
+        Ok(FormatUnitCommand::SHORT_HEADER_SIZE)
+    }
+}
+
+impl BufferPullable for FormatParameters {
+    fn pull_from_buffer<B: AsRef<[u8]>>(buffer: B) -> Result<Self, ScsiError> {
+        let buffer = buffer.as_ref();
+        if buffer.len() < FormatUnitCommand::SHORT_HEADER_SIZE {
+            return Err(ScsiError::from_cause(ErrorCause::BufferTooSmallError {
+                expected: FormatUnitCommand::SHORT_HEADER_SIZE,
+                actual: buffer.len(),
+            }));
+        }
+        let flags = buffer[1];
+        Ok(FormatParameters {
+            format_options_valid: flags & 0x80 != 0,
+            disable_primary: flags & 0x40 != 0,
+            disable_certification: flags & 0x20 != 0,
+            stop_format: flags & 0x10 != 0,
+            initialization_pattern: flags & 0x8 != 0,
+            immediate: flags & 0x2 != 0,
+            defect_list_length: u32::from(BE::read_u16(&buffer[2..])),
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{FormatParameters, FormatUnitCommand};
+    use crate::scsi::commands::{Command, Direction};
+    use crate::{BufferPullable, BufferPushable};
+
+    #[test]
+    pub fn test_formatunit() {
+        let expected: [u8; 31] = [
+            0x55, 0x53, 0x42, 0x43, 0x00, 0x00, 0x00, 0x00, 0x04, 0x00, 0x00, 0x00, 0x00, 0x00,
+            0x06, 0x04, 0x10, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00,
+            0x00, 0x00, 0x00,
+        ];
+        let parameters = FormatParameters {
+            format_options_valid: true,
+            disable_certification: true,
+            immediate: true,
+            ..Default::default()
+        };
+        let mut buff = [0; 32];
+        let command = FormatUnitCommand::with_parameters(&parameters);
+        let pushed = command.push_to_buffer(&mut buff).unwrap();
+        assert_eq!(pushed, 21);
+        assert_eq!(&buff[0..pushed], &expected[0..pushed]);
+        assert_eq!(FormatUnitCommand::pull_from_buffer(buff).unwrap(), command);
+
+        let mut list = [0xff; 4];
+        assert_eq!(parameters.push_to_buffer(&mut list).unwrap(), 4);
+        assert_eq!(list, [0x00, 0xa2, 0x00, 0x00]);
+        assert_eq!(
+            command.pull_parameter_list(&list).unwrap(),
+            Some(parameters)
+        );
+        assert!(command.pull_parameter_list(&list[..2]).is_err());
+
+        let command = FormatUnitCommand::new();
+        assert_eq!(command.wrapper().direction, Direction::NONE);
+        command.push_to_buffer(&mut buff).unwrap();
+        assert_eq!(buff[16], 0);
+        let pulled = FormatUnitCommand::pull_from_buffer(buff).unwrap();
+        assert_eq!(pulled, command);
+        assert_eq!(pulled.pull_parameter_list(&[]).unwrap(), None);
+    }
+
+    #[test]
+    pub fn test_format_long_list() {
+        let command = FormatUnitCommand {
+            format_data: true,
+            long_list: true,
+            parameter_list_length: 16,
+            ..Default::default()
+        };
+        let mut buff = [0; 32];
+        command.push_to_buffer(&mut buff).unwrap();
+        assert_eq!(buff[16], 0x30);
+        assert_eq!(FormatUnitCommand::pull_from_buffer(buff).unwrap(), command);
+
+        let list = [0x00, 0x40, 0x00, 0x00, 0x00, 0x00, 0x00, 0x08];
+        let parameters = command.pull_parameter_list(&list).unwrap().unwrap();
+        assert!(parameters.disable_primary);
+        assert_eq!(parameters.defect_list_length, 8);
+    }
+}
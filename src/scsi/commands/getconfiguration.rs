@@ -0,0 +1,320 @@
+use crate::error::{ErrorCause, ScsiError};
+use crate::scsi::commands::{Command, CommandBlockWrapper, Direction};
+use crate::traits::{BufferPullable, BufferPushable};
+use byteorder::{ByteOrder, BE};
+
+/// Asks a multimedia device which features and profiles it supports
+/// (GET CONFIGURATION).
+#[derive(Clone, Copy, Eq, PartialEq, Debug)]
+pub struct GetConfigurationCommand {
+    /// Which features to return; see the `GetConfigurationCommand::RT_*`
+    /// constants.
+    pub request_type: u8,
+
+    /// The first feature code the host is interested in.
+    pub starting_feature: u16,
+
+    /// The maximum number of bytes the host is willing to receive.
+    pub allocation_length: u16,
+}
+
+impl GetConfigurationCommand {
+    /// Return all features with a code of at least `starting_feature`.
+    pub const RT_ALL: u8 = 0x0;
+    /// Return only the current features with a code of at least `starting_feature`.
+    pub const RT_CURRENT: u8 = 0x1;
+    /// Return only the feature identified by `starting_feature`.
+    pub const RT_ONE: u8 = 0x2;
+
+    /// Constructs a new `GetConfigurationCommand`.
+    pub fn new(
+        request_type: u8,
+        starting_feature: u16,
+        allocation_length: u16,
+    ) -> GetConfigurationCommand {
+        GetConfigurationCommand {
+            request_type,
+            starting_feature,
+            allocation_length,
+        }
+    }
+}
+
+impl Default for GetConfigurationCommand {
+    fn default() -> Self {
+        GetConfigurationCommand::new(GetConfigurationCommand::RT_ALL, 0, 0xfffe)
+    }
+}
+
+impl Command for GetConfigurationCommand {
+    fn opcode() -> u8 {
+        0x46
+    }
+    fn length() -> u8 {
+        10
+    }
+    fn wrapper(&self) -> CommandBlockWrapper {
+        CommandBlockWrapper::new(
+            u32::from(self.allocation_length),
+            Direction::IN,
+            0,
+            GetConfigurationCommand::length(),
+        )
+    }
+}
+
+impl BufferPushable for GetConfigurationCommand {
+    fn push_to_buffer<B: AsMut<[u8]>>(&self, mut buffer: B) -> Result<usize, ScsiError> {
+        let rval = self.wrapper().push_to_buffer(buffer.as_mut())?;
+        let buffer = &mut buffer.as_mut()[rval..];
+        buffer[0] = GetConfigurationCommand::opcode();
+        buffer[1] = self.request_type & 0x3;
+        BE::write_u16(&mut buffer[2..], self.starting_feature);
+        buffer[4] = 0;
+        buffer[5] = 0;
+        buffer[6] = 0;
+        BE::write_u16(&mut buffer[7..], self.allocation_length);
+        buffer[9] = 0;
+        Ok(rval + 10)
+    }
+}
+
+impl BufferPullable for GetConfigurationCommand {
+    fn pull_from_buffer<B: AsRef<[u8]>>(buffer: B) -> Result<Self, ScsiError> {
+        let wrapper: CommandBlockWrapper = CommandBlockWrapper::pull_from_buffer(buffer.as_ref())?;
+        if wrapper.direction != Direction::IN
+            || wrapper.cb_length != GetConfigurationCommand::length()
+        {
+            return Err(ScsiError::from_cause(ErrorCause::ParseError));
+        }
+        let buffer = &buffer.as_ref()[15..];
+        if buffer[0] != GetConfigurationCommand::opcode() {
+            return Err(ScsiError::from_cause(ErrorCause::ParseError));
+        }
+        Ok(GetConfigurationCommand {
+            request_type: buffer[1] & 0x3,
+            starting_feature: BE::read_u16(&buffer[2..]),
+            allocation_length: BE::read_u16(&buffer[7..]),
+        })
+    }
+}
+
+/// A single feature descriptor in a `GetConfigurationResponse`.
+#[derive(Clone, Copy, Eq, PartialEq, Debug, Default)]
+pub struct FeatureDescriptor {
+    /// The feature code, eg `0x0001` for the Core feature.
+    pub feature_code: u16,
+
+    /// The version of the feature descriptor.
+    pub version: u8,
+
+    /// Whether the feature is always current, regardless of the medium.
+    pub persistent: bool,
+
+    /// Whether the feature is currently usable.
+    pub current: bool,
+
+    /// The feature-dependent data; only the first `data_length` bytes are valid.
+    pub data: [u8; FeatureDescriptor::MAX_DATA_LENGTH],
+
+    /// The number of valid bytes in `data`; must be a multiple of 4.
+    pub data_length: usize,
+}
+
+impl FeatureDescriptor {
+    /// The largest amount of feature-dependent data a descriptor can carry.
+    pub const MAX_DATA_LENGTH: usize = 16;
+
+    /// Constructs a new `FeatureDescriptor` from its code, flags and data.
+    ///
+    /// # Errors
+    /// Returns an error if `data` is longer than `MAX_DATA_LENGTH` bytes.
+    pub fn new(
+        feature_code: u16,
+        version: u8,
+        persistent: bool,
+        current: bool,
+        data: &[u8],
+    ) -> Result<FeatureDescriptor, ScsiError> {
+        if data.len() > FeatureDescriptor::MAX_DATA_LENGTH {
+            return Err(ScsiError::from_cause(ErrorCause::BufferTooSmallError {
+                expected: data.len(),
+                actual: FeatureDescriptor::MAX_DATA_LENGTH,
+            }));
+        }
+        let mut descriptor = FeatureDescriptor {
+            feature_code,
+            version,
+            persistent,
+            current,
+            data_length: data.len(),
+            ..Default::default()
+        };
+        descriptor.data[..data.len()].copy_from_slice(data);
+        Ok(descriptor)
+    }
+
+    /// The valid portion of the feature-dependent data.
+    pub fn data(&self) -> &[u8] {
+        &self.data[..self.data_length]
+    }
+}
+
+/// The data sent in response to a `GetConfigurationCommand`.
+#[derive(Clone, Copy, Eq, PartialEq, Debug, Default)]
+pub struct GetConfigurationResponse {
+    /// The profile the device is currently operating under, eg `0x0008` for
+    /// CD-ROM, or `0` if there is no medium.
+    pub current_profile: u16,
+
+    /// The feature descriptors; only the first `feature_count` are valid.
+    pub features: [FeatureDescriptor; GetConfigurationResponse::MAX_FEATURES],
+
+    /// The number of valid entries in `features`.
+    pub feature_count: usize,
+}
+
+impl GetConfigurationResponse {
+    /// The maximum number of features a `GetConfigurationResponse` can carry.
+    pub const MAX_FEATURES: usize = 8;
+
+    /// The profile number of a CD-ROM.
+    pub const PROFILE_CD_ROM: u16 = 0x0008;
+
+    /// Appends a feature to the response, failing if it is already full.
+    pub fn push_feature(&mut self, feature: FeatureDescriptor) -> Result<(), ScsiError> {
+        if self.feature_count >= GetConfigurationResponse::MAX_FEATURES {
+            return Err(ScsiError::from_cause(ErrorCause::BufferTooSmallError {
+                expected: self.feature_count + 1,
+                actual: GetConfigurationResponse::MAX_FEATURES,
+            }));
+        }
+        self.features[self.feature_count] = feature;
+        self.feature_count += 1;
+        Ok(())
+    }
+
+    /// Looks up a feature in the response by its code.
+    pub fn feature(&self, feature_code: u16) -> Option<&FeatureDescriptor> {
+        self.features[..self.feature_count]
+            .iter()
+            .find(|f| f.feature_code == feature_code)
+    }
+}
+
+impl BufferPushable for GetConfigurationResponse {
+    fn push_to_buffer<B: AsMut<[u8]>>(&self, mut buffer: B) -> Result<usize, ScsiError> {
+        let buffer = buffer.as_mut();
+        let total = self.features[..self.feature_count]
+            .iter()
+            .fold(8, |acc, f| acc + 4 + f.data_length);
+        if buffer.len() < total {
+            return Err(ScsiError::from_cause(ErrorCause::BufferTooSmallError {
+                expected: total,
+                actual: buffer.len(),
+            }));
+        }
+        BE::write_u32(buffer, (total - 4) as u32);
+        buffer[4] = 0;
+        buffer[5] = 0;
+        BE::write_u16(&mut buffer[6..], self.current_profile);
+        let mut cur = 8;
+        for feature in &self.features[..self.feature_count] {
+            BE::write_u16(&mut buffer[cur..], feature.feature_code);
+            let persistent_bit = if feature.persistent { 0x2 } else { 0 };
+            let current_bit = if feature.current { 0x1 } else { 0 };
+            buffer[cur + 2] = ((feature.version & 0xf) << 2) | persistent_bit | current_bit;
+            buffer[cur + 3] = feature.data_length as u8;
+            buffer[cur + 4..cur + 4 + feature.data_length].copy_from_slice(feature.data());
+            cur += 4 + feature.data_length;
+        }
+        Ok(total)
+    }
+}
+
+impl BufferPullable for GetConfigurationResponse {
+    fn pull_from_buffer<B: AsRef<[u8]>>(buffer: B) -> Result<Self, ScsiError> {
+        let buffer = buffer.as_ref();
+        if buffer.len() < 8 {
+            return Err(ScsiError::from_cause(ErrorCause::BufferTooSmallError {
+                expected: 8,
+                actual: buffer.len(),
+            }));
+        }
+        let available = (BE::read_u32(buffer) as usize + 4).min(buffer.len());
+        let mut response = GetConfigurationResponse {
+            current_profile: BE::read_u16(&buffer[6..]),
+            ..Default::default()
+        };
+        let mut cur = 8;
+        while cur + 4 <= available
+            && response.feature_count < GetConfigurationResponse::MAX_FEATURES
+        {
+            let data_length = buffer[cur + 3] as usize;
+            if cur + 4 + data_length > available {
+                break;
+            }
+            let data_end = cur + 4 + data_length.min(FeatureDescriptor::MAX_DATA_LENGTH);
+            response.push_feature(FeatureDescriptor::new(
+                BE::read_u16(&buffer[cur..]),
+                (buffer[cur + 2] >> 2) & 0xf,
+                buffer[cur + 2] & 0x2 != 0,
+                buffer[cur + 2] & 0x1 != 0,
+                &buffer[cur + 4..data_end],
+            )?)?;
+            cur += 4 + data_length;
+        }
+        Ok(response)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{FeatureDescriptor, GetConfigurationCommand, GetConfigurationResponse};
+    use crate::{BufferPullable, BufferPushable};
+
+    #[test]
+    pub fn test_getconfiguration() {
+        let expected: [u8; 31] = [
+            0x55, 0x53, 0x42, 0x43, 0x00, 0x00, 0x00, 0x00, 0x08, 0x00, 0x00, 0x00, 0x80, 0x00,
+            0x0a, 0x46, 0x02, 0x00, 0x10, 0x00, 0x00, 0x00, 0x00, 0x08, 0x00, 0x00, 0x00, 0x00,
+            0x00, 0x00, 0x00,
+        ];
+        let mut buff = [0; 32];
+        let command = GetConfigurationCommand::new(GetConfigurationCommand::RT_ONE, 0x10, 8);
+        let pushed = command.push_to_buffer(&mut buff).unwrap();
+        assert_eq!(pushed, 25);
+        assert_eq!(&buff[0..pushed], &expected[0..pushed]);
+
+        let pulled = GetConfigurationCommand::pull_from_buffer(buff).unwrap();
+        assert_eq!(pulled, command);
+    }
+
+    #[test]
+    pub fn test_getconfigurationresponse() {
+        let expected: [u8; 20] = [
+            0x00, 0x00, 0x00, 0x10, 0x00, 0x00, 0x00, 0x08, 0x00, 0x00, 0x03, 0x04, 0x00, 0x08,
+            0x01, 0x00, 0x00, 0x1e, 0x05, 0x00,
+        ];
+        let mut buff = [0; 32];
+        let mut response = GetConfigurationResponse {
+            current_profile: GetConfigurationResponse::PROFILE_CD_ROM,
+            ..Default::default()
+        };
+        response
+            .push_feature(
+                FeatureDescriptor::new(0, 0, true, true, &[0x00, 0x08, 0x01, 0x00]).unwrap(),
+            )
+            .unwrap();
+        response
+            .push_feature(FeatureDescriptor::new(0x1e, 1, false, true, &[]).unwrap())
+            .unwrap();
+        let pushed = response.push_to_buffer(&mut buff).unwrap();
+        assert_eq!(pushed, 20);
+        assert_eq!(&buff[0..pushed], &expected);
+
+        let pulled = GetConfigurationResponse::pull_from_buffer(&buff[..pushed]).unwrap();
+        assert_eq!(pulled, response);
+        assert_eq!(pulled.feature(0x1e).unwrap().version, 1);
+    }
+}
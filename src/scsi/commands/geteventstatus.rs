@@ -0,0 +1,257 @@
+use crate::error::{ErrorCause, ScsiError};
+use crate::scsi::commands::{Command, CommandBlockWrapper, Direction};
+use crate::traits::{BufferPullable, BufferPushable};
+use byteorder::{ByteOrder, BE};
+
+/// Polls a multimedia device for asynchronous events, such as media being
+/// inserted or the eject button being pressed (GET EVENT STATUS NOTIFICATION).
+#[derive(Clone, Copy, Eq, PartialEq, Debug)]
+pub struct GetEventStatusNotificationCommand {
+    /// Whether the host is polling; asynchronous notification is not supported
+    /// over most transports, so this is nearly always `true`.
+    pub polled: bool,
+
+    /// A bitmask of the event classes the host is interested in; see the
+    /// `EventStatusResponse::CLASS_*` constants.
+    pub notification_class_request: u8,
+
+    /// The maximum number of bytes the host is willing to receive.
+    pub allocation_length: u16,
+}
+
+impl GetEventStatusNotificationCommand {
+    /// Constructs a new, polled `GetEventStatusNotificationCommand`.
+    pub fn new(
+        notification_class_request: u8,
+        allocation_length: u16,
+    ) -> GetEventStatusNotificationCommand {
+        GetEventStatusNotificationCommand {
+            polled: true,
+            notification_class_request,
+            allocation_length,
+        }
+    }
+}
+
+impl Default for GetEventStatusNotificationCommand {
+    fn default() -> Self {
+        GetEventStatusNotificationCommand::new(EventStatusResponse::CLASS_MEDIA, 8)
+    }
+}
+
+impl Command for GetEventStatusNotificationCommand {
+    fn opcode() -> u8 {
+        0x4a
+    }
+    fn length() -> u8 {
+        10
+    }
+    fn wrapper(&self) -> CommandBlockWrapper {
+        CommandBlockWrapper::new(
+            u32::from(self.allocation_length),
+            Direction::IN,
+            0,
+            GetEventStatusNotificationCommand::length(),
+        )
+    }
+}
+
+impl BufferPushable for GetEventStatusNotificationCommand {
+    fn push_to_buffer<B: AsMut<[u8]>>(&self, mut buffer: B) -> Result<usize, ScsiError> {
+        let rval = self.wrapper().push_to_buffer(buffer.as_mut())?;
+        let buffer = &mut buffer.as_mut()[rval..];
+        buffer[0] = GetEventStatusNotificationCommand::opcode();
+        buffer[1] = if self.polled { 1 } else { 0 };
+        buffer[2] = 0;
+        buffer[3] = 0;
+        buffer[4] = self.notification_class_request;
+        buffer[5] = 0;
+        buffer[6] = 0;
+        BE::write_u16(&mut buffer[7..], self.allocation_length);
+        buffer[9] = 0;
+        Ok(rval + 10)
+    }
+}
+
+impl BufferPullable for GetEventStatusNotificationCommand {
+    fn pull_from_buffer<B: AsRef<[u8]>>(buffer: B) -> Result<Self, ScsiError> {
+        let wrapper: CommandBlockWrapper = CommandBlockWrapper::pull_from_buffer(buffer.as_ref())?;
+        if wrapper.direction != Direction::IN
+            || wrapper.cb_length != GetEventStatusNotificationCommand::length()
+        {
+            return Err(ScsiError::from_cause(ErrorCause::ParseError));
+        }
+        let buffer = &buffer.as_ref()[15..];
+        if buffer[0] != GetEventStatusNotificationCommand::opcode() {
+            return Err(ScsiError::from_cause(ErrorCause::ParseError));
+        }
+        Ok(GetEventStatusNotificationCommand {
+            polled: buffer[1] & 0x1 != 0,
+            notification_class_request: buffer[4],
+            allocation_length: BE::read_u16(&buffer[7..]),
+        })
+    }
+}
+
+/// The media status event descriptor of an `EventStatusResponse`.
+#[derive(Clone, Copy, Eq, PartialEq, Debug, Default)]
+pub struct MediaEventDescriptor {
+    /// What happened to the medium since the last poll; see the
+    /// `MediaEventDescriptor::*` constants.
+    pub event_code: u8,
+
+    /// Whether there is currently a medium in the device.
+    pub media_present: bool,
+
+    /// Whether the tray or door of the device is open.
+    pub tray_open: bool,
+}
+
+impl MediaEventDescriptor {
+    /// Nothing has changed since the last poll.
+    pub const NO_CHANGE: u8 = 0x0;
+    /// The user has asked for the medium to be ejected.
+    pub const EJECT_REQUEST: u8 = 0x1;
+    /// A new medium has been loaded.
+    pub const NEW_MEDIA: u8 = 0x2;
+    /// The medium has been removed.
+    pub const MEDIA_REMOVAL: u8 = 0x3;
+    /// The medium has been swapped for another one.
+    pub const MEDIA_CHANGED: u8 = 0x4;
+}
+
+/// The data sent in response to a `GetEventStatusNotificationCommand`.
+#[derive(Clone, Copy, Eq, PartialEq, Debug, Default)]
+pub struct EventStatusResponse {
+    /// A bitmask of the event classes the device supports.
+    pub supported_event_classes: u8,
+
+    /// The media event being reported, or `None` if none of the requested
+    /// classes have an event available.
+    pub media_event: Option<MediaEventDescriptor>,
+}
+
+impl EventStatusResponse {
+    /// The bit for the operational change event class.
+    pub const CLASS_OPERATIONAL_CHANGE: u8 = 0x2;
+    /// The bit for the power management event class.
+    pub const CLASS_POWER_MANAGEMENT: u8 = 0x4;
+    /// The bit for the external request event class.
+    pub const CLASS_EXTERNAL_REQUEST: u8 = 0x8;
+    /// The bit for the media event class.
+    pub const CLASS_MEDIA: u8 = 0x10;
+    /// The bit for the multi-host event class.
+    pub const CLASS_MULTI_HOST: u8 = 0x20;
+    /// The bit for the device busy event class.
+    pub const CLASS_DEVICE_BUSY: u8 = 0x40;
+
+    const MEDIA_CLASS_NUMBER: u8 = 4;
+}
+
+impl BufferPushable for EventStatusResponse {
+    fn push_to_buffer<B: AsMut<[u8]>>(&self, mut buffer: B) -> Result<usize, ScsiError> {
+        let buffer = buffer.as_mut();
+        let total = if self.media_event.is_some() { 8 } else { 4 };
+        if buffer.len() < total {
+            return Err(ScsiError::from_cause(ErrorCause::BufferTooSmallError {
+                expected: total,
+                actual: buffer.len(),
+            }));
+        }
+        BE::write_u16(buffer, (total - 2) as u16);
+        buffer[2] = match self.media_event {
+            Some(_) => EventStatusResponse::MEDIA_CLASS_NUMBER,
+            None => 0x80,
+        };
+        buffer[3] = self.supported_event_classes;
+        if let Some(ref event) = self.media_event {
+            let present_bit = if event.media_present { 0x2 } else { 0 };
+            let open_bit = if event.tray_open { 0x1 } else { 0 };
+            buffer[4] = event.event_code & 0xf;
+            buffer[5] = present_bit | open_bit;
+            buffer[6] = 0;
+            buffer[7] = 0;
+        }
+        Ok(total)
+    }
+}
+
+impl BufferPullable for EventStatusResponse {
+    fn pull_from_buffer<B: AsRef<[u8]>>(buffer: B) -> Result<Self, ScsiError> {
+        let buffer = buffer.as_ref();
+        if buffer.len() < 4 {
+            return Err(ScsiError::from_cause(ErrorCause::BufferTooSmallError {
+                expected: 4,
+                actual: buffer.len(),
+            }));
+        }
+        let no_event_available = buffer[2] & 0x80 != 0;
+        let class = buffer[2] & 0x7;
+        let media_event = if !no_event_available
+            && class == EventStatusResponse::MEDIA_CLASS_NUMBER
+            && buffer.len() >= 8
+        {
+            Some(MediaEventDescriptor {
+                event_code: buffer[4] & 0xf,
+                media_present: buffer[5] & 0x2 != 0,
+                tray_open: buffer[5] & 0x1 != 0,
+            })
+        } else {
+            None
+        };
+        Ok(EventStatusResponse {
+            supported_event_classes: buffer[3],
+            media_event,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{EventStatusResponse, GetEventStatusNotificationCommand, MediaEventDescriptor};
+    use crate::{BufferPullable, BufferPushable};
+
+    #[test]
+    pub fn test_geteventstatus() {
+        let expected: [u8; 31] = [
+            0x55, 0x53, 0x42, 0x43, 0x00, 0x00, 0x00, 0x00, 0x08, 0x00, 0x00, 0x00, 0x80, 0x00,
+            0x0a, 0x4a, 0x01, 0x00, 0x00, 0x10, 0x00, 0x00, 0x00, 0x08, 0x00, 0x00, 0x00, 0x00,
+            0x00, 0x00, 0x00,
+        ];
+        let mut buff = [0; 32];
+        let command = GetEventStatusNotificationCommand::default();
+        let pushed = command.push_to_buffer(&mut buff).unwrap();
+        assert_eq!(pushed, 25);
+        assert_eq!(&buff[0..pushed], &expected[0..pushed]);
+
+        let pulled = GetEventStatusNotificationCommand::pull_from_buffer(buff).unwrap();
+        assert_eq!(pulled, command);
+    }
+
+    #[test]
+    pub fn test_eventstatusresponse() {
+        let expected: [u8; 8] = [0x00, 0x06, 0x04, 0x10, 0x02, 0x02, 0x00, 0x00];
+        let mut buff = [0; 32];
+        let response = EventStatusResponse {
+            supported_event_classes: EventStatusResponse::CLASS_MEDIA,
+            media_event: Some(MediaEventDescriptor {
+                event_code: MediaEventDescriptor::NEW_MEDIA,
+                media_present: true,
+                tray_open: false,
+            }),
+        };
+        let pushed = response.push_to_buffer(&mut buff).unwrap();
+        assert_eq!(pushed, 8);
+        assert_eq!(&buff[0..pushed], &expected);
+        let pulled = EventStatusResponse::pull_from_buffer(buff).unwrap();
+        assert_eq!(pulled, response);
+
+        let empty = EventStatusResponse {
+            supported_event_classes: EventStatusResponse::CLASS_MEDIA,
+            media_event: None,
+        };
+        assert_eq!(empty.push_to_buffer(&mut buff).unwrap(), 4);
+        assert_eq!(&buff[0..4], &[0x00, 0x02, 0x80, 0x10]);
+        assert_eq!(EventStatusResponse::pull_from_buffer(buff).unwrap(), empty);
+    }
+}
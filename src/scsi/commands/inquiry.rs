@@ -1,7 +1,7 @@
+use crate::error::{ErrorCause, ScsiError};
+use crate::scsi::commands::{Command, CommandBlockWrapper, Direction};
+use crate::traits::{BufferPullable, BufferPushable};
 use byteorder::{ByteOrder, BE};
-use error::{ErrorCause, ScsiError};
-use scsi::commands::{Command, CommandBlockWrapper, Direction};
-use traits::{BufferPullable, BufferPushable};
 
 /// A command to get information about an SCSI device.
 #[derive(Clone, Copy, Eq, PartialEq, Debug)]
@@ -82,10 +82,187 @@ impl Command for InquiryCommand {
     }
 }
 
+/// An `InquiryCommand` with the EVPD bit set, asking for one of the device's
+/// vital product data pages instead of the standard inquiry data.
+#[derive(Clone, Copy, Eq, PartialEq, Debug)]
+pub struct VpdInquiryCommand {
+    /// The vital product data page to return, eg `BlockLimitsPage::PAGE_CODE`.
+    pub page_code: u8,
+
+    /// The size of the response that should be returned.
+    pub allocation_length: u8,
+}
+
+impl VpdInquiryCommand {
+    /// Constructs a new `VpdInquiryCommand` requesting the vital product data
+    /// page `page_code`.
+    pub fn new(page_code: u8, allocation_length: u8) -> VpdInquiryCommand {
+        VpdInquiryCommand {
+            page_code,
+            allocation_length,
+        }
+    }
+
+    /// Whether the INQUIRY in the command block `buffer` has the EVPD bit set,
+    /// making it a `VpdInquiryCommand` rather than an `InquiryCommand`.
+    pub(crate) fn is_vpd(buffer: &[u8]) -> bool {
+        buffer[16] & 0x1 != 0
+    }
+}
+
+impl BufferPushable for VpdInquiryCommand {
+    fn push_to_buffer<B: AsMut<[u8]>>(&self, mut buffer: B) -> Result<usize, ScsiError> {
+        let mut buffer = buffer.as_mut();
+        let cur_idx = self.wrapper().push_to_buffer(&mut buffer)?;
+        buffer[cur_idx] = VpdInquiryCommand::opcode();
+        buffer[cur_idx + 1] = 0x1;
+        buffer[cur_idx + 2] = self.page_code;
+        buffer[cur_idx + 3] = 0;
+        buffer[cur_idx + 4] = self.allocation_length;
+        Ok(cur_idx + 5)
+    }
+}
+
+impl BufferPullable for VpdInquiryCommand {
+    fn pull_from_buffer<B: AsRef<[u8]>>(buffer: B) -> Result<Self, ScsiError> {
+        let buffer = buffer.as_ref();
+        let header = CommandBlockWrapper::pull_from_buffer(buffer)?;
+        if buffer[15] != VpdInquiryCommand::opcode()
+            || !VpdInquiryCommand::is_vpd(buffer)
+            || header.direction != Direction::IN
+            || header.cb_length != VpdInquiryCommand::length()
+        {
+            return Err(ScsiError::from_cause(ErrorCause::ParseError));
+        }
+        Ok(VpdInquiryCommand::new(buffer[17], buffer[19]))
+    }
+}
+
+impl Command for VpdInquiryCommand {
+    fn wrapper(&self) -> CommandBlockWrapper {
+        CommandBlockWrapper::new(
+            u32::from(self.allocation_length),
+            Direction::IN,
+            0,
+            VpdInquiryCommand::length(),
+        )
+    }
+
+    fn opcode() -> u8 {
+        InquiryCommand::opcode()
+    }
+
+    fn length() -> u8 {
+        InquiryCommand::length()
+    }
+}
+
+/// The peripheral device type reported in an `InquiryResponse`, describing
+/// which command set the device speaks.
+///
+/// For the full list of values, see the [SCSI Peripheral Device Type](https://en.wikipedia.org/wiki/SCSI_Peripheral_Device_Type)
+/// list; values without a dedicated variant are kept in `Other`.
+#[derive(Clone, Copy, Eq, PartialEq, Hash, Debug)]
+pub enum PeripheralDeviceType {
+    /// A randomly accessable block storage device (SBC), eg a flash drive.
+    DirectAccess,
+    /// A tape drive (SSC).
+    SequentialAccess,
+    /// A printer.
+    Printer,
+    /// A processor device.
+    Processor,
+    /// A write-once device, such as some optical disc drives.
+    WriteOnce,
+    /// A CD or DVD drive (MMC).
+    CdDvd,
+    /// An optical memory device, such as a magneto-optical drive.
+    OpticalMemory,
+    /// A medium changer, such as a tape library.
+    MediumChanger,
+    /// A storage array controller.
+    StorageArrayController,
+    /// An enclosure services device.
+    EnclosureServices,
+    /// A simplified direct access device (RBC).
+    SimplifiedDirectAccess,
+    /// An optical card reader or writer.
+    OpticalCardReader,
+    /// An object-based storage device.
+    ObjectStorage,
+    /// A well-known logical unit.
+    WellKnownLogicalUnit,
+    /// An unknown or missing device.
+    Unknown,
+    /// Any other device type code.
+    Other(u8),
+}
+
+impl PeripheralDeviceType {
+    /// Whether or not the device type stores data in logical blocks that can be
+    /// accessed with `Read10Command`s.
+    pub fn is_block_device(self) -> bool {
+        matches!(
+            self,
+            PeripheralDeviceType::DirectAccess
+                | PeripheralDeviceType::WriteOnce
+                | PeripheralDeviceType::CdDvd
+                | PeripheralDeviceType::OpticalMemory
+                | PeripheralDeviceType::SimplifiedDirectAccess
+        )
+    }
+}
+
+impl From<u8> for PeripheralDeviceType {
+    fn from(code: u8) -> PeripheralDeviceType {
+        match code & 0x1f {
+            0x00 => PeripheralDeviceType::DirectAccess,
+            0x01 => PeripheralDeviceType::SequentialAccess,
+            0x02 => PeripheralDeviceType::Printer,
+            0x03 => PeripheralDeviceType::Processor,
+            0x04 => PeripheralDeviceType::WriteOnce,
+            0x05 => PeripheralDeviceType::CdDvd,
+            0x07 => PeripheralDeviceType::OpticalMemory,
+            0x08 => PeripheralDeviceType::MediumChanger,
+            0x0c => PeripheralDeviceType::StorageArrayController,
+            0x0d => PeripheralDeviceType::EnclosureServices,
+            0x0e => PeripheralDeviceType::SimplifiedDirectAccess,
+            0x0f => PeripheralDeviceType::OpticalCardReader,
+            0x11 => PeripheralDeviceType::ObjectStorage,
+            0x1e => PeripheralDeviceType::WellKnownLogicalUnit,
+            0x1f => PeripheralDeviceType::Unknown,
+            other => PeripheralDeviceType::Other(other),
+        }
+    }
+}
+
+impl From<PeripheralDeviceType> for u8 {
+    fn from(device_type: PeripheralDeviceType) -> u8 {
+        match device_type {
+            PeripheralDeviceType::DirectAccess => 0x00,
+            PeripheralDeviceType::SequentialAccess => 0x01,
+            PeripheralDeviceType::Printer => 0x02,
+            PeripheralDeviceType::Processor => 0x03,
+            PeripheralDeviceType::WriteOnce => 0x04,
+            PeripheralDeviceType::CdDvd => 0x05,
+            PeripheralDeviceType::OpticalMemory => 0x07,
+            PeripheralDeviceType::MediumChanger => 0x08,
+            PeripheralDeviceType::StorageArrayController => 0x0c,
+            PeripheralDeviceType::EnclosureServices => 0x0d,
+            PeripheralDeviceType::SimplifiedDirectAccess => 0x0e,
+            PeripheralDeviceType::OpticalCardReader => 0x0f,
+            PeripheralDeviceType::ObjectStorage => 0x11,
+            PeripheralDeviceType::WellKnownLogicalUnit => 0x1e,
+            PeripheralDeviceType::Unknown => 0x1f,
+            PeripheralDeviceType::Other(other) => other & 0x1f,
+        }
+    }
+}
+
 /// The data sent in response to an `InquiryCommand`.
 ///
-/// Currently does not include all data that the device responds with; finishing
-/// this is a TODO item.
+/// Only the header of the standard inquiry data is included; the vendor,
+/// product and revision strings that follow it are in `StandardInquiryData`.
 #[derive(Clone, Copy, Eq, PartialEq, Debug, Default)]
 pub struct InquiryResponse {
     /// 3 bit flag set to determine the SCSI device's current accessibility. For
@@ -95,17 +272,17 @@ pub struct InquiryResponse {
     /// The flag bits are as follows:
     ///
     /// * If the least significant bit, `0x20`, is set, then the "SCSI task router"
-    /// in use is currently unable to access the logical unit specified via the LUN
-    /// field in the CBW.
+    ///   in use is currently unable to access the logical unit specified via the LUN
+    ///   field in the CBW.
     ///
     /// * If the next bit, `0x40`, is set, then specified LUN cannot be accessed
-    /// by the current "SCSI task router". Note that this flag being set implies
-    /// that the previous bit must also be set, and that the returned value of
-    /// `device_type` is `0x1F` to indicate a value of `Unknown`.
+    ///   by the current "SCSI task router". Note that this flag being set implies
+    ///   that the previous bit must also be set, and that the returned value of
+    ///   `device_type` is `0x1F` to indicate a value of `Unknown`.
     ///
     /// * If the most significant bit, `0x80`, is set, then this flat set must be
-    /// interpretted through the device vendor's documentation instead of the information
-    /// given here; the previous 2 bullets no longer apply.
+    ///   interpretted through the device vendor's documentation instead of the information
+    ///   given here; the previous 2 bullets no longer apply.
     pub device_qualifier: u8,
 
     /// The type of SCSI device this is. Usually 0 to indicate a
@@ -137,6 +314,13 @@ pub struct InquiryResponse {
     pub response_format: u8,
 }
 
+impl InquiryResponse {
+    /// The typed form of `device_type`.
+    pub fn peripheral_device_type(&self) -> PeripheralDeviceType {
+        PeripheralDeviceType::from(self.device_type)
+    }
+}
+
 impl BufferPullable for InquiryResponse {
     fn pull_from_buffer<B: AsRef<[u8]>>(buffer: B) -> Result<InquiryResponse, ScsiError> {
         let buffer = buffer.as_ref();
@@ -167,9 +351,109 @@ impl BufferPushable for InquiryResponse {
         Ok(4)
     }
 }
+
+/// The vendor, product and revision strings a device reports in its standard
+/// inquiry data.
+#[derive(Clone, Copy, Eq, PartialEq, Debug)]
+pub struct InquiryIdentification {
+    /// The name of the device's vendor in ASCII, padded with spaces.
+    pub vendor_identification: [u8; 8],
+
+    /// The name of the product in ASCII, padded with spaces.
+    pub product_identification: [u8; 16],
+
+    /// The revision of the product in ASCII, padded with spaces.
+    pub product_revision_level: [u8; 4],
+}
+
+impl Default for InquiryIdentification {
+    fn default() -> Self {
+        InquiryIdentification {
+            vendor_identification: [b' '; 8],
+            product_identification: [b' '; 16],
+            product_revision_level: [b' '; 4],
+        }
+    }
+}
+
+/// The full 36 bytes of standard inquiry data: the `InquiryResponse` header
+/// followed by the device's `InquiryIdentification`.
+#[derive(Clone, Copy, Eq, PartialEq, Debug, Default)]
+pub struct StandardInquiryData {
+    /// The header shared with the short form of the response.
+    pub response: InquiryResponse,
+
+    /// The vendor, product and revision strings.
+    pub identification: InquiryIdentification,
+}
+
+impl StandardInquiryData {
+    /// The size of the standard inquiry data, in bytes.
+    pub const SIZE: usize = 36;
+
+    /// Constructs a new `StandardInquiryData` from its two halves.
+    pub fn new(
+        response: InquiryResponse,
+        identification: InquiryIdentification,
+    ) -> StandardInquiryData {
+        StandardInquiryData {
+            response,
+            identification,
+        }
+    }
+}
+
+impl BufferPullable for StandardInquiryData {
+    fn pull_from_buffer<B: AsRef<[u8]>>(buffer: B) -> Result<StandardInquiryData, ScsiError> {
+        let buffer = buffer.as_ref();
+        if buffer.len() < StandardInquiryData::SIZE {
+            return Err(ScsiError::from_cause(ErrorCause::BufferTooSmallError {
+                expected: StandardInquiryData::SIZE,
+                actual: buffer.len(),
+            }));
+        }
+        let response = InquiryResponse::pull_from_buffer(buffer)?;
+        let mut identification = InquiryIdentification::default();
+        identification
+            .vendor_identification
+            .copy_from_slice(&buffer[8..16]);
+        identification
+            .product_identification
+            .copy_from_slice(&buffer[16..32]);
+        identification
+            .product_revision_level
+            .copy_from_slice(&buffer[32..36]);
+        Ok(StandardInquiryData::new(response, identification))
+    }
+}
+
+impl BufferPushable for StandardInquiryData {
+    fn push_to_buffer<B: AsMut<[u8]>>(&self, mut buffer: B) -> Result<usize, ScsiError> {
+        let buffer = buffer.as_mut();
+        if buffer.len() < StandardInquiryData::SIZE {
+            return Err(ScsiError::from_cause(ErrorCause::BufferTooSmallError {
+                expected: StandardInquiryData::SIZE,
+                actual: buffer.len(),
+            }));
+        }
+        self.response.push_to_buffer(&mut *buffer)?;
+        buffer[4] = (StandardInquiryData::SIZE - 5) as u8;
+        buffer[5] = 0;
+        buffer[6] = 0;
+        buffer[7] = 0;
+        let identification = &self.identification;
+        buffer[8..16].copy_from_slice(&identification.vendor_identification);
+        buffer[16..32].copy_from_slice(&identification.product_identification);
+        buffer[32..36].copy_from_slice(&identification.product_revision_level);
+        Ok(StandardInquiryData::SIZE)
+    }
+}
 #[cfg(test)]
 mod tests {
-    use super::{InquiryCommand, InquiryResponse};
+    use super::{
+        InquiryCommand, InquiryIdentification, InquiryResponse, PeripheralDeviceType,
+        StandardInquiryData, VpdInquiryCommand,
+    };
     use crate::{BufferPullable, BufferPushable};
 
     #[test]
@@ -185,9 +469,25 @@ mod tests {
         assert_eq!(pushed, 20);
         assert_eq!(&buff[0..pushed], &expected[0..pushed]);
 
-        let pulled = InquiryCommand::pull_from_buffer(&mut buff).unwrap();
+        let pulled = InquiryCommand::pull_from_buffer(buff).unwrap();
         assert_eq!(pulled, inquiry_command);
     }
+    #[test]
+    pub fn test_vpdinquirycommand() {
+        let mut buff = [0; 32];
+        let command = VpdInquiryCommand::new(0xb0, 64);
+        let pushed = command.push_to_buffer(&mut buff).unwrap();
+        assert_eq!(pushed, 20);
+        assert_eq!(&buff[15..20], &[0x12, 0x01, 0xb0, 0x00, 0x40]);
+
+        let pulled = VpdInquiryCommand::pull_from_buffer(buff).unwrap();
+        assert_eq!(pulled, command);
+        assert!(VpdInquiryCommand::pull_from_buffer(&buff[..20]).is_ok());
+
+        InquiryCommand::new(64).push_to_buffer(&mut buff).unwrap();
+        assert!(VpdInquiryCommand::pull_from_buffer(buff).is_err());
+    }
+
     #[test]
     pub fn test_inquiryresponse() {
         let expected: [u8; 31] = [
@@ -207,7 +507,45 @@ mod tests {
         assert_eq!(pushed, 4);
         assert_eq!(&buff[0..pushed], &expected[0..pushed]);
 
-        let pulled = InquiryResponse::pull_from_buffer(&mut buff).unwrap();
+        let pulled = InquiryResponse::pull_from_buffer(buff).unwrap();
         assert_eq!(pulled, inquiry_command);
     }
+
+    #[test]
+    pub fn test_standardinquirydata() {
+        let mut buff = [0; 40];
+        let data = StandardInquiryData::new(
+            InquiryResponse {
+                device_type: 0x05,
+                removable_flags: 0x80,
+                ..Default::default()
+            },
+            InquiryIdentification {
+                vendor_identification: *b"VENDOR  ",
+                product_identification: *b"PRODUCT         ",
+                product_revision_level: *b"1.00",
+            },
+        );
+        let pushed = data.push_to_buffer(&mut buff[..]).unwrap();
+        assert_eq!(pushed, 36);
+        assert_eq!(buff[4], 31);
+        assert_eq!(&buff[8..16], b"VENDOR  ");
+
+        let pulled = StandardInquiryData::pull_from_buffer(&buff[..]).unwrap();
+        assert_eq!(pulled, data);
+        assert_eq!(
+            pulled.response.peripheral_device_type(),
+            PeripheralDeviceType::CdDvd
+        );
+        assert!(StandardInquiryData::pull_from_buffer(&buff[..8]).is_err());
+    }
+
+    #[test]
+    pub fn test_peripheraldevicetype() {
+        for code in 0..0x20u8 {
+            assert_eq!(u8::from(PeripheralDeviceType::from(code)), code);
+        }
+        assert!(PeripheralDeviceType::from(0x0e).is_block_device());
+        assert!(!PeripheralDeviceType::from(0x01).is_block_device());
+    }
 }
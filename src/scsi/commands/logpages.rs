@@ -0,0 +1,1091 @@
+use crate::error::{ErrorCause, ScsiError};
+use crate::traits::{BufferPullable, BufferPushable};
+use byteorder::{ByteOrder, BE};
+
+/// The size of the header at the start of every log page.
+const HEADER_SIZE: usize = 4;
+
+/// The size of the header at the start of every log parameter.
+const PARAMETER_HEADER_SIZE: usize = 4;
+
+/// The parameter control byte of a counter that stops at its maximum value.
+const BOUNDED_COUNTER: u8 = 0x00;
+
+/// The parameter control byte of a parameter holding ASCII text.
+const ASCII_LIST: u8 = 0x01;
+
+/// The parameter control byte of a parameter holding binary data.
+const BINARY_LIST: u8 = 0x03;
+
+/// A single parameter of a log page.
+#[derive(Clone, Copy, Eq, PartialEq, Debug)]
+pub struct LogParameter<'a> {
+    /// Which parameter this is within its page.
+    pub parameter_code: u16,
+
+    /// The parameter control byte, which says how the value is formatted and
+    /// updated.
+    pub control: u8,
+
+    /// The value of the parameter.
+    pub value: &'a [u8],
+}
+
+/// Iterates over the parameters of a log page, including pages this crate has
+/// no parser for, such as vendor-specific ones.
+///
+/// Parameters cut off by the end of the buffer are left out.
+#[derive(Clone, Debug)]
+pub struct LogParameters<'a> {
+    page: &'a [u8],
+    cur: usize,
+}
+
+impl<'a> LogParameters<'a> {
+    /// Starts iterating over the parameters of the log page at the start of
+    /// `page`.
+    ///
+    /// # Errors
+    /// Returns a `BufferTooSmallError` if `page` is too short to hold the
+    /// page header.
+    pub fn new(page: &'a [u8]) -> Result<LogParameters<'a>, ScsiError> {
+        check_size(page, HEADER_SIZE)?;
+        let length = (HEADER_SIZE + usize::from(BE::read_u16(&page[2..]))).min(page.len());
+        Ok(LogParameters {
+            page: &page[..length],
+            cur: HEADER_SIZE,
+        })
+    }
+}
+
+impl<'a> Iterator for LogParameters<'a> {
+    type Item = LogParameter<'a>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        let header = self.page.get(self.cur..self.cur + PARAMETER_HEADER_SIZE)?;
+        let start = self.cur + PARAMETER_HEADER_SIZE;
+        let value = self.page.get(start..start + usize::from(header[3]))?;
+        self.cur = start + value.len();
+        Some(LogParameter {
+            parameter_code: BE::read_u16(header),
+            control: header[2],
+            value,
+        })
+    }
+}
+
+fn check_size(buffer: &[u8], size: usize) -> Result<(), ScsiError> {
+    if buffer.len() < size {
+        return Err(ScsiError::from_cause(ErrorCause::BufferTooSmallError {
+            expected: size,
+            actual: buffer.len(),
+        }));
+    }
+    Ok(())
+}
+
+/// Checks that the log page at the start of `buffer` is the one with
+/// `page_code`, returning its parameters.
+fn parameters(buffer: &[u8], page_code: u8) -> Result<LogParameters<'_>, ScsiError> {
+    let parameters = LogParameters::new(buffer)?;
+    if buffer[0] & 0x3f != page_code {
+        return Err(ScsiError::from_cause(ErrorCause::ParseError));
+    }
+    Ok(parameters)
+}
+
+/// Reads a counter of any length up to 8 bytes.
+fn read_counter(value: &[u8]) -> u64 {
+    let start = value.len().saturating_sub(8);
+    value[start..]
+        .iter()
+        .fold(0, |acc, &byte| (acc << 8) | u64::from(byte))
+}
+
+/// Serializes a log page whose size has already been checked.
+struct PageWriter<'b> {
+    buffer: &'b mut [u8],
+    length: usize,
+}
+
+impl<'b> PageWriter<'b> {
+    fn new(buffer: &'b mut [u8], page_code: u8, size: usize) -> Result<Self, ScsiError> {
+        check_size(buffer, size)?;
+        buffer[0] = page_code;
+        buffer[1] = 0;
+        Ok(PageWriter {
+            buffer,
+            length: HEADER_SIZE,
+        })
+    }
+
+    fn bytes(&mut self, bytes: &[u8]) {
+        self.buffer[self.length..self.length + bytes.len()].copy_from_slice(bytes);
+        self.length += bytes.len();
+    }
+
+    fn parameter(&mut self, parameter_code: u16, control: u8, value: &[u8]) {
+        let mut header = [0; PARAMETER_HEADER_SIZE];
+        BE::write_u16(&mut header, parameter_code);
+        header[2] = control;
+        header[3] = value.len() as u8;
+        self.bytes(&header);
+        self.bytes(value);
+    }
+
+    fn finish(self) -> usize {
+        BE::write_u16(&mut self.buffer[2..], (self.length - HEADER_SIZE) as u16);
+        self.length
+    }
+}
+
+/// The Supported Log Pages page, which lists the log pages the device has.
+#[derive(Clone, Copy, Eq, PartialEq, Debug)]
+pub struct SupportedLogPages {
+    /// The page codes of the supported pages; only the first `page_count`
+    /// are valid.
+    pub page_codes: [u8; SupportedLogPages::MAX_PAGES],
+
+    /// The number of valid entries in `page_codes`.
+    pub page_count: usize,
+}
+
+impl Default for SupportedLogPages {
+    fn default() -> Self {
+        SupportedLogPages {
+            page_codes: [0; SupportedLogPages::MAX_PAGES],
+            page_count: 0,
+        }
+    }
+}
+
+impl SupportedLogPages {
+    /// The log page code of the Supported Log Pages page.
+    pub const PAGE_CODE: u8 = 0x00;
+
+    /// The largest number of pages the list can hold, one for every page
+    /// code.
+    pub const MAX_PAGES: usize = 64;
+
+    /// Appends a page code to the list, failing if it is already full.
+    pub fn push_page(&mut self, page_code: u8) -> Result<(), ScsiError> {
+        if self.page_count >= SupportedLogPages::MAX_PAGES {
+            return Err(ScsiError::from_cause(ErrorCause::BufferTooSmallError {
+                expected: self.page_count + 1,
+                actual: SupportedLogPages::MAX_PAGES,
+            }));
+        }
+        self.page_codes[self.page_count] = page_code;
+        self.page_count += 1;
+        Ok(())
+    }
+
+    /// The valid portion of `page_codes`.
+    pub fn page_codes(&self) -> &[u8] {
+        &self.page_codes[..self.page_count]
+    }
+
+    /// Whether the device has the page with `page_code`.
+    pub fn supports(&self, page_code: u8) -> bool {
+        self.page_codes().contains(&page_code)
+    }
+
+    fn size(&self) -> usize {
+        HEADER_SIZE + self.page_count
+    }
+}
+
+impl BufferPushable for SupportedLogPages {
+    fn push_to_buffer<B: AsMut<[u8]>>(&self, mut buffer: B) -> Result<usize, ScsiError> {
+        let mut writer =
+            PageWriter::new(buffer.as_mut(), SupportedLogPages::PAGE_CODE, self.size())?;
+        writer.bytes(self.page_codes());
+        Ok(writer.finish())
+    }
+}
+
+impl BufferPullable for SupportedLogPages {
+    fn pull_from_buffer<B: AsRef<[u8]>>(buffer: B) -> Result<Self, ScsiError> {
+        let buffer = buffer.as_ref();
+        // The page codes take the place of parameters.
+        let parameters = parameters(buffer, SupportedLogPages::PAGE_CODE)?;
+        let mut page = SupportedLogPages::default();
+        for &page_code in &parameters.page[HEADER_SIZE..] {
+            page.push_page(page_code & 0x3f)?;
+        }
+        Ok(page)
+    }
+}
+
+/// Which of the error counter pages an `ErrorCounterPage` is.
+#[derive(Clone, Copy, Eq, PartialEq, Debug, Default)]
+pub enum ErrorCounterKind {
+    /// The Write Error Counter page.
+    #[default]
+    Write,
+
+    /// The Read Error Counter page.
+    Read,
+
+    /// The Verify Error Counter page.
+    Verify,
+}
+
+impl ErrorCounterKind {
+    fn from_page_code(page_code: u8) -> Result<ErrorCounterKind, ScsiError> {
+        match page_code {
+            0x02 => Ok(ErrorCounterKind::Write),
+            0x03 => Ok(ErrorCounterKind::Read),
+            0x05 => Ok(ErrorCounterKind::Verify),
+            _ => Err(ScsiError::from_cause(ErrorCause::ParseError)),
+        }
+    }
+
+    /// The log page code of this error counter page.
+    pub fn page_code(self) -> u8 {
+        match self {
+            ErrorCounterKind::Write => 0x02,
+            ErrorCounterKind::Read => 0x03,
+            ErrorCounterKind::Verify => 0x05,
+        }
+    }
+}
+
+/// The Write, Read or Verify Error Counter page, which counts the errors the
+/// device has run into while accessing the medium.
+///
+/// Counters the device does not report are `None`.
+#[derive(Clone, Copy, Eq, PartialEq, Debug, Default)]
+pub struct ErrorCounterPage {
+    /// Which of the error counter pages this is.
+    pub kind: ErrorCounterKind,
+
+    /// The number of errors corrected without any delay.
+    pub corrected_without_delay: Option<u64>,
+
+    /// The number of errors corrected with a possible delay, such as by
+    /// retrying.
+    pub corrected_with_delay: Option<u64>,
+
+    /// The number of times data was written or read again.
+    pub total_retries: Option<u64>,
+
+    /// The total number of errors that were corrected.
+    pub total_corrected: Option<u64>,
+
+    /// The number of times the error correction algorithm was run.
+    pub correction_algorithm_runs: Option<u64>,
+
+    /// The number of bytes written, read or verified.
+    pub bytes_processed: Option<u64>,
+
+    /// The number of errors that could not be corrected.
+    pub total_uncorrected: Option<u64>,
+}
+
+impl ErrorCounterPage {
+    /// The number of counters the page can hold.
+    const COUNTERS: usize = 7;
+
+    /// The size of each counter sent by `push_to_buffer`.
+    const COUNTER_SIZE: usize = 8;
+
+    /// The counters in parameter code order.
+    fn counters(&self) -> [Option<u64>; ErrorCounterPage::COUNTERS] {
+        [
+            self.corrected_without_delay,
+            self.corrected_with_delay,
+            self.total_retries,
+            self.total_corrected,
+            self.correction_algorithm_runs,
+            self.bytes_processed,
+            self.total_uncorrected,
+        ]
+    }
+
+    fn counter_mut(&mut self, parameter_code: u16) -> Option<&mut Option<u64>> {
+        match parameter_code {
+            0x0000 => Some(&mut self.corrected_without_delay),
+            0x0001 => Some(&mut self.corrected_with_delay),
+            0x0002 => Some(&mut self.total_retries),
+            0x0003 => Some(&mut self.total_corrected),
+            0x0004 => Some(&mut self.correction_algorithm_runs),
+            0x0005 => Some(&mut self.bytes_processed),
+            0x0006 => Some(&mut self.total_uncorrected),
+            _ => None,
+        }
+    }
+}
+
+impl BufferPushable for ErrorCounterPage {
+    fn push_to_buffer<B: AsMut<[u8]>>(&self, mut buffer: B) -> Result<usize, ScsiError> {
+        let counters = self.counters();
+        let reported = counters.iter().flatten().count();
+        let size =
+            HEADER_SIZE + reported * (PARAMETER_HEADER_SIZE + ErrorCounterPage::COUNTER_SIZE);
+        let mut writer = PageWriter::new(buffer.as_mut(), self.kind.page_code(), size)?;
+        for (parameter_code, counter) in counters.iter().enumerate() {
+            if let Some(counter) = counter {
+                let mut value = [0; ErrorCounterPage::COUNTER_SIZE];
+                BE::write_u64(&mut value, *counter);
+                writer.parameter(parameter_code as u16, BOUNDED_COUNTER, &value);
+            }
+        }
+        Ok(writer.finish())
+    }
+}
+
+impl BufferPullable for ErrorCounterPage {
+    fn pull_from_buffer<B: AsRef<[u8]>>(buffer: B) -> Result<Self, ScsiError> {
+        let buffer = buffer.as_ref();
+        check_size(buffer, HEADER_SIZE)?;
+        let kind = ErrorCounterKind::from_page_code(buffer[0] & 0x3f)?;
+        let mut page = ErrorCounterPage {
+            kind,
+            ..Default::default()
+        };
+        for parameter in parameters(buffer, kind.page_code())? {
+            if let Some(counter) = page.counter_mut(parameter.parameter_code) {
+                *counter = Some(read_counter(parameter.value));
+            }
+        }
+        Ok(page)
+    }
+}
+
+/// The Temperature page, with temperatures in degrees Celsius.
+#[derive(Clone, Copy, Eq, PartialEq, Debug, Default)]
+pub struct TemperaturePage {
+    /// The current temperature of the device, if it knows it.
+    pub temperature: Option<u8>,
+
+    /// The highest temperature the device is designed to run at, if it
+    /// reports one.
+    pub reference_temperature: Option<u8>,
+}
+
+impl TemperaturePage {
+    /// The log page code of the Temperature page.
+    pub const PAGE_CODE: u8 = 0x0d;
+
+    /// The size of the Temperature page, in bytes.
+    pub const SIZE: usize = HEADER_SIZE + 2 * (PARAMETER_HEADER_SIZE + 2);
+
+    /// The temperature reported when it is not known.
+    const UNKNOWN: u8 = 0xff;
+}
+
+impl BufferPushable for TemperaturePage {
+    fn push_to_buffer<B: AsMut<[u8]>>(&self, mut buffer: B) -> Result<usize, ScsiError> {
+        let mut writer = PageWriter::new(
+            buffer.as_mut(),
+            TemperaturePage::PAGE_CODE,
+            TemperaturePage::SIZE,
+        )?;
+        let temperatures = [self.temperature, self.reference_temperature];
+        for (parameter_code, temperature) in temperatures.iter().enumerate() {
+            let value = [0, temperature.unwrap_or(TemperaturePage::UNKNOWN)];
+            writer.parameter(parameter_code as u16, BINARY_LIST, &value);
+        }
+        Ok(writer.finish())
+    }
+}
+
+impl BufferPullable for TemperaturePage {
+    fn pull_from_buffer<B: AsRef<[u8]>>(buffer: B) -> Result<Self, ScsiError> {
+        let mut page = TemperaturePage::default();
+        for parameter in parameters(buffer.as_ref(), TemperaturePage::PAGE_CODE)? {
+            let temperature = parameter
+                .value
+                .get(1)
+                .copied()
+                .filter(|&temperature| temperature != TemperaturePage::UNKNOWN);
+            match parameter.parameter_code {
+                0x0000 => page.temperature = temperature,
+                0x0001 => page.reference_temperature = temperature,
+                _ => {}
+            }
+        }
+        Ok(page)
+    }
+}
+
+/// A date in a log page, given as a week of a year.
+#[derive(Clone, Copy, Eq, PartialEq, Debug, Default)]
+pub struct LogDate {
+    /// The year, such as 2024.
+    pub year: u16,
+
+    /// The week of the year, from 1 to 53.
+    pub week: u8,
+}
+
+impl LogDate {
+    /// The size of a date in a log page, which holds the year and week as six
+    /// ASCII digits.
+    const SIZE: usize = 6;
+
+    fn to_ascii(date: Option<LogDate>) -> [u8; LogDate::SIZE] {
+        let date = match date {
+            Some(date) => date,
+            // Unknown dates are left blank.
+            None => return [b' '; LogDate::SIZE],
+        };
+        let digits = [
+            date.year / 1000 % 10,
+            date.year / 100 % 10,
+            date.year / 10 % 10,
+            date.year % 10,
+            u16::from(date.week) / 10 % 10,
+            u16::from(date.week) % 10,
+        ];
+        let mut ascii = [0; LogDate::SIZE];
+        for (byte, digit) in ascii.iter_mut().zip(digits) {
+            *byte = b'0' + digit as u8;
+        }
+        ascii
+    }
+
+    fn from_ascii(ascii: &[u8]) -> Option<LogDate> {
+        if ascii.len() < LogDate::SIZE || !ascii[..LogDate::SIZE].iter().all(u8::is_ascii_digit) {
+            return None;
+        }
+        let number = |digits: &[u8]| {
+            digits
+                .iter()
+                .fold(0, |acc, &digit| acc * 10 + u16::from(digit - b'0'))
+        };
+        Some(LogDate {
+            year: number(&ascii[..4]),
+            week: number(&ascii[4..6]) as u8,
+        })
+    }
+}
+
+/// The Start-Stop Cycle Counter page, which tracks how often the device has
+/// been spun up and down, or had its heads loaded and unloaded, against how
+/// often it was designed to be.
+///
+/// Values the device does not report are `None`.
+#[derive(Clone, Copy, Eq, PartialEq, Debug, Default)]
+pub struct StartStopCyclePage {
+    /// When the device was made.
+    pub date_of_manufacture: Option<LogDate>,
+
+    /// When the device was put into service.
+    pub accounting_date: Option<LogDate>,
+
+    /// The number of start-stop cycles the device is designed to survive.
+    pub specified_start_stop_cycles: Option<u32>,
+
+    /// The number of start-stop cycles the device has been through.
+    pub accumulated_start_stop_cycles: Option<u32>,
+
+    /// The number of load-unload cycles the device is designed to survive.
+    pub specified_load_unload_cycles: Option<u32>,
+
+    /// The number of load-unload cycles the device has been through.
+    pub accumulated_load_unload_cycles: Option<u32>,
+}
+
+impl StartStopCyclePage {
+    /// The log page code of the Start-Stop Cycle Counter page.
+    pub const PAGE_CODE: u8 = 0x0e;
+
+    /// The counts in parameter code order, starting at parameter 3.
+    fn counts(&self) -> [Option<u32>; 4] {
+        [
+            self.specified_start_stop_cycles,
+            self.accumulated_start_stop_cycles,
+            self.specified_load_unload_cycles,
+            self.accumulated_load_unload_cycles,
+        ]
+    }
+}
+
+impl BufferPushable for StartStopCyclePage {
+    fn push_to_buffer<B: AsMut<[u8]>>(&self, mut buffer: B) -> Result<usize, ScsiError> {
+        let counts = self.counts();
+        let size = HEADER_SIZE
+            + 2 * (PARAMETER_HEADER_SIZE + LogDate::SIZE)
+            + counts.iter().flatten().count() * (PARAMETER_HEADER_SIZE + 4);
+        let mut writer = PageWriter::new(buffer.as_mut(), StartStopCyclePage::PAGE_CODE, size)?;
+        writer.parameter(
+            0x0001,
+            ASCII_LIST,
+            &LogDate::to_ascii(self.date_of_manufacture),
+        );
+        writer.parameter(0x0002, ASCII_LIST, &LogDate::to_ascii(self.accounting_date));
+        for (idx, count) in counts.iter().enumerate() {
+            if let Some(count) = count {
+                let mut value = [0; 4];
+                BE::write_u32(&mut value, *count);
+                writer.parameter(0x0003 + idx as u16, BINARY_LIST, &value);
+            }
+        }
+        Ok(writer.finish())
+    }
+}
+
+impl BufferPullable for StartStopCyclePage {
+    fn pull_from_buffer<B: AsRef<[u8]>>(buffer: B) -> Result<Self, ScsiError> {
+        let mut page = StartStopCyclePage::default();
+        for parameter in parameters(buffer.as_ref(), StartStopCyclePage::PAGE_CODE)? {
+            let count = Some(read_counter(parameter.value) as u32);
+            match parameter.parameter_code {
+                0x0001 => page.date_of_manufacture = LogDate::from_ascii(parameter.value),
+                0x0002 => page.accounting_date = LogDate::from_ascii(parameter.value),
+                0x0003 => page.specified_start_stop_cycles = count,
+                0x0004 => page.accumulated_start_stop_cycles = count,
+                0x0005 => page.specified_load_unload_cycles = count,
+                0x0006 => page.accumulated_load_unload_cycles = count,
+                _ => {}
+            }
+        }
+        Ok(page)
+    }
+}
+
+/// The outcome of a single device self-test, as listed in the
+/// `SelfTestResultsPage`.
+#[derive(Clone, Copy, Eq, PartialEq, Debug, Default)]
+pub struct SelfTestResult {
+    /// The kind of self-test that was run, as sent in the SEND DIAGNOSTIC
+    /// command that started it.
+    pub self_test_code: u8,
+
+    /// How the self-test ended: 0 if it passed, 1 to 2 if it was aborted,
+    /// 3 to 7 if it failed and 0xf if it is still running.
+    pub result: u8,
+
+    /// The number of the segment of the self-test that failed, if it did.
+    pub self_test_number: u8,
+
+    /// The device's power-on hours when the self-test ended.
+    pub power_on_hours: u16,
+
+    /// The address of the first block that failed the self-test, if any.
+    pub first_failure_address: Option<u64>,
+
+    /// The sense key describing the failure, if any.
+    pub sense_key: u8,
+
+    /// The additional sense code describing the failure.
+    pub additional_sense_code: u8,
+
+    /// Further detail about `additional_sense_code`.
+    pub additional_sense_code_qualifier: u8,
+}
+
+impl SelfTestResult {
+    /// The size of a self-test result parameter's value.
+    const SIZE: usize = 16;
+
+    /// The failure address reported by self-tests without one.
+    const NO_ADDRESS: u64 = u64::MAX;
+
+    /// The `result` of a self-test that has not finished yet.
+    const IN_PROGRESS: u8 = 0xf;
+
+    /// Whether the self-test passed.
+    pub fn passed(&self) -> bool {
+        self.result == 0
+    }
+
+    /// Whether the self-test is still running.
+    pub fn is_in_progress(&self) -> bool {
+        self.result == SelfTestResult::IN_PROGRESS
+    }
+
+    fn push(&self) -> [u8; SelfTestResult::SIZE] {
+        let mut value = [0; SelfTestResult::SIZE];
+        value[0] = (self.self_test_code << 5) | (self.result & 0xf);
+        value[1] = self.self_test_number;
+        BE::write_u16(&mut value[2..], self.power_on_hours);
+        BE::write_u64(
+            &mut value[4..],
+            self.first_failure_address
+                .unwrap_or(SelfTestResult::NO_ADDRESS),
+        );
+        value[12] = self.sense_key & 0xf;
+        value[13] = self.additional_sense_code;
+        value[14] = self.additional_sense_code_qualifier;
+        value
+    }
+
+    fn pull(value: &[u8]) -> Option<SelfTestResult> {
+        // Unused entries are zeroed.
+        if value.len() < SelfTestResult::SIZE || value.iter().all(|&byte| byte == 0) {
+            return None;
+        }
+        let address = BE::read_u64(&value[4..]);
+        Some(SelfTestResult {
+            self_test_code: value[0] >> 5,
+            result: value[0] & 0xf,
+            self_test_number: value[1],
+            power_on_hours: BE::read_u16(&value[2..]),
+            first_failure_address: Some(address).filter(|&a| a != SelfTestResult::NO_ADDRESS),
+            sense_key: value[12] & 0xf,
+            additional_sense_code: value[13],
+            additional_sense_code_qualifier: value[14],
+        })
+    }
+}
+
+/// The Self-Test Results page, which lists the outcomes of the device's most
+/// recent self-tests, newest first.
+#[derive(Clone, Copy, Eq, PartialEq, Debug, Default)]
+pub struct SelfTestResultsPage {
+    /// The self-test results; only the first `result_count` are valid.
+    pub results: [SelfTestResult; SelfTestResultsPage::MAX_RESULTS],
+
+    /// The number of valid entries in `results`.
+    pub result_count: usize,
+}
+
+impl SelfTestResultsPage {
+    /// The log page code of the Self-Test Results page.
+    pub const PAGE_CODE: u8 = 0x10;
+
+    /// The number of self-test results the page holds.
+    pub const MAX_RESULTS: usize = 20;
+
+    /// The size of the Self-Test Results page, in bytes.
+    pub const SIZE: usize = HEADER_SIZE
+        + SelfTestResultsPage::MAX_RESULTS * (PARAMETER_HEADER_SIZE + SelfTestResult::SIZE);
+
+    /// Appends a result to the list, which should be older than the ones
+    /// already in it, failing if it is already full.
+    pub fn push_result(&mut self, result: SelfTestResult) -> Result<(), ScsiError> {
+        if self.result_count >= SelfTestResultsPage::MAX_RESULTS {
+            return Err(ScsiError::from_cause(ErrorCause::BufferTooSmallError {
+                expected: self.result_count + 1,
+                actual: SelfTestResultsPage::MAX_RESULTS,
+            }));
+        }
+        self.results[self.result_count] = result;
+        self.result_count += 1;
+        Ok(())
+    }
+
+    /// The valid portion of `results`.
+    pub fn results(&self) -> &[SelfTestResult] {
+        &self.results[..self.result_count]
+    }
+
+    /// The most recent self-test result, if the device has run one.
+    pub fn latest(&self) -> Option<&SelfTestResult> {
+        self.results().first()
+    }
+}
+
+impl BufferPushable for SelfTestResultsPage {
+    fn push_to_buffer<B: AsMut<[u8]>>(&self, mut buffer: B) -> Result<usize, ScsiError> {
+        let mut writer = PageWriter::new(
+            buffer.as_mut(),
+            SelfTestResultsPage::PAGE_CODE,
+            SelfTestResultsPage::SIZE,
+        )?;
+        // Every entry is sent, with the unused ones zeroed.
+        for idx in 0..SelfTestResultsPage::MAX_RESULTS {
+            let value = match self.results().get(idx) {
+                Some(result) => result.push(),
+                None => [0; SelfTestResult::SIZE],
+            };
+            writer.parameter(idx as u16 + 1, BINARY_LIST, &value);
+        }
+        Ok(writer.finish())
+    }
+}
+
+impl BufferPullable for SelfTestResultsPage {
+    fn pull_from_buffer<B: AsRef<[u8]>>(buffer: B) -> Result<Self, ScsiError> {
+        let mut page = SelfTestResultsPage::default();
+        for parameter in parameters(buffer.as_ref(), SelfTestResultsPage::PAGE_CODE)? {
+            if page.result_count == SelfTestResultsPage::MAX_RESULTS {
+                break;
+            }
+            if let Some(result) = SelfTestResult::pull(parameter.value) {
+                page.push_result(result)?;
+            }
+        }
+        Ok(page)
+    }
+}
+
+/// The Solid State Media page, which reports how worn out a flash-based
+/// device is.
+#[derive(Clone, Copy, Eq, PartialEq, Debug, Default)]
+pub struct SolidStateMediaPage {
+    /// An estimate of how much of the device's endurance has been used up, as
+    /// a percentage; values over 100 mean it has outlived its rating.
+    pub percentage_used: Option<u8>,
+}
+
+impl SolidStateMediaPage {
+    /// The log page code of the Solid State Media page.
+    pub const PAGE_CODE: u8 = 0x11;
+}
+
+impl BufferPushable for SolidStateMediaPage {
+    fn push_to_buffer<B: AsMut<[u8]>>(&self, mut buffer: B) -> Result<usize, ScsiError> {
+        let size = match self.percentage_used {
+            Some(_) => HEADER_SIZE + PARAMETER_HEADER_SIZE + 4,
+            None => HEADER_SIZE,
+        };
+        let mut writer = PageWriter::new(buffer.as_mut(), SolidStateMediaPage::PAGE_CODE, size)?;
+        if let Some(percentage) = self.percentage_used {
+            writer.parameter(0x0001, BINARY_LIST, &[0, 0, 0, percentage]);
+        }
+        Ok(writer.finish())
+    }
+}
+
+impl BufferPullable for SolidStateMediaPage {
+    fn pull_from_buffer<B: AsRef<[u8]>>(buffer: B) -> Result<Self, ScsiError> {
+        let mut page = SolidStateMediaPage::default();
+        for parameter in parameters(buffer.as_ref(), SolidStateMediaPage::PAGE_CODE)? {
+            if parameter.parameter_code == 0x0001 {
+                page.percentage_used = parameter.value.get(3).copied();
+            }
+        }
+        Ok(page)
+    }
+}
+
+/// The Informational Exceptions page, which reports whether the device
+/// predicts that it is going to fail.
+#[derive(Clone, Copy, Eq, PartialEq, Debug, Default)]
+pub struct InformationalExceptionsPage {
+    /// The additional sense code of the most recent informational exception,
+    /// or 0 if there has been none; for example, `0x5d` means a failure
+    /// prediction threshold was exceeded.
+    pub additional_sense_code: u8,
+
+    /// Further detail about `additional_sense_code`.
+    pub additional_sense_code_qualifier: u8,
+
+    /// The most recent temperature reading in degrees Celsius, if the device
+    /// reports one.
+    pub most_recent_temperature: Option<u8>,
+}
+
+impl InformationalExceptionsPage {
+    /// The log page code of the Informational Exceptions page.
+    pub const PAGE_CODE: u8 = 0x2f;
+
+    /// The size of the Informational Exceptions page, in bytes.
+    pub const SIZE: usize = HEADER_SIZE + PARAMETER_HEADER_SIZE + 3;
+
+    /// Whether the device has reported no informational exception.
+    pub fn is_healthy(&self) -> bool {
+        self.additional_sense_code == 0
+    }
+}
+
+impl BufferPushable for InformationalExceptionsPage {
+    fn push_to_buffer<B: AsMut<[u8]>>(&self, mut buffer: B) -> Result<usize, ScsiError> {
+        let mut writer = PageWriter::new(
+            buffer.as_mut(),
+            InformationalExceptionsPage::PAGE_CODE,
+            InformationalExceptionsPage::SIZE,
+        )?;
+        let value = [
+            self.additional_sense_code,
+            self.additional_sense_code_qualifier,
+            self.most_recent_temperature
+                .unwrap_or(TemperaturePage::UNKNOWN),
+        ];
+        writer.parameter(0x0000, BINARY_LIST, &value);
+        Ok(writer.finish())
+    }
+}
+
+impl BufferPullable for InformationalExceptionsPage {
+    fn pull_from_buffer<B: AsRef<[u8]>>(buffer: B) -> Result<Self, ScsiError> {
+        let mut page = InformationalExceptionsPage::default();
+        for parameter in parameters(buffer.as_ref(), InformationalExceptionsPage::PAGE_CODE)? {
+            if parameter.parameter_code == 0x0000 && parameter.value.len() >= 2 {
+                page.additional_sense_code = parameter.value[0];
+                page.additional_sense_code_qualifier = parameter.value[1];
+                page.most_recent_temperature = parameter
+                    .value
+                    .get(2)
+                    .copied()
+                    .filter(|&temperature| temperature != TemperaturePage::UNKNOWN);
+            }
+        }
+        Ok(page)
+    }
+}
+
+/// Any of the log pages this crate can parse, as returned in response to a
+/// `LogSenseCommand`.
+// Without an allocator the Self-Test Results page cannot be boxed, so every
+// page is as large as it is.
+#[allow(clippy::large_enum_variant)]
+#[derive(Clone, Copy, Eq, PartialEq, Debug)]
+pub enum LogPage {
+    /// The Supported Log Pages page.
+    SupportedPages(SupportedLogPages),
+
+    /// The Write, Read or Verify Error Counter page.
+    ErrorCounter(ErrorCounterPage),
+
+    /// The Temperature page.
+    Temperature(TemperaturePage),
+
+    /// The Start-Stop Cycle Counter page.
+    StartStopCycle(StartStopCyclePage),
+
+    /// The Self-Test Results page.
+    SelfTestResults(SelfTestResultsPage),
+
+    /// The Solid State Media page.
+    SolidStateMedia(SolidStateMediaPage),
+
+    /// The Informational Exceptions page.
+    InformationalExceptions(InformationalExceptionsPage),
+}
+
+impl LogPage {
+    /// The size of the largest log page this crate can parse, the Self-Test
+    /// Results page.
+    pub const MAX_SIZE: usize = SelfTestResultsPage::SIZE;
+
+    /// The size of the header at the start of every log page.
+    pub(crate) const HEADER_SIZE: usize = HEADER_SIZE;
+
+    /// The length of the log page whose header is at the start of `buffer`,
+    /// capped at the size of `buffer` and the largest allocation length a LOG
+    /// SENSE command can carry.
+    ///
+    /// Like the PERSISTENT RESERVE IN responses, log pages are read header
+    /// first.
+    pub(crate) fn length(buffer: &[u8]) -> usize {
+        if buffer.len() < HEADER_SIZE {
+            return buffer.len();
+        }
+        let length = HEADER_SIZE + usize::from(BE::read_u16(&buffer[2..]));
+        length.min(buffer.len()).min(usize::from(u16::MAX))
+    }
+
+    /// The log page code of this page.
+    pub fn page_code(&self) -> u8 {
+        match self {
+            LogPage::SupportedPages(_) => SupportedLogPages::PAGE_CODE,
+            LogPage::ErrorCounter(p) => p.kind.page_code(),
+            LogPage::Temperature(_) => TemperaturePage::PAGE_CODE,
+            LogPage::StartStopCycle(_) => StartStopCyclePage::PAGE_CODE,
+            LogPage::SelfTestResults(_) => SelfTestResultsPage::PAGE_CODE,
+            LogPage::SolidStateMedia(_) => SolidStateMediaPage::PAGE_CODE,
+            LogPage::InformationalExceptions(_) => InformationalExceptionsPage::PAGE_CODE,
+        }
+    }
+}
+
+impl BufferPushable for LogPage {
+    fn push_to_buffer<B: AsMut<[u8]>>(&self, buffer: B) -> Result<usize, ScsiError> {
+        match self {
+            LogPage::SupportedPages(p) => p.push_to_buffer(buffer),
+            LogPage::ErrorCounter(p) => p.push_to_buffer(buffer),
+            LogPage::Temperature(p) => p.push_to_buffer(buffer),
+            LogPage::StartStopCycle(p) => p.push_to_buffer(buffer),
+            LogPage::SelfTestResults(p) => p.push_to_buffer(buffer),
+            LogPage::SolidStateMedia(p) => p.push_to_buffer(buffer),
+            LogPage::InformationalExceptions(p) => p.push_to_buffer(buffer),
+        }
+    }
+}
+
+impl BufferPullable for LogPage {
+    /// Parses the log page at the start of `buffer`, failing with a
+    /// `ParseError` if there is no parser for its page code.
+    fn pull_from_buffer<B: AsRef<[u8]>>(buffer: B) -> Result<Self, ScsiError> {
+        let buffer = buffer.as_ref();
+        check_size(buffer, HEADER_SIZE)?;
+        match buffer[0] & 0x3f {
+            SupportedLogPages::PAGE_CODE => Ok(LogPage::SupportedPages(
+                SupportedLogPages::pull_from_buffer(buffer)?,
+            )),
+            TemperaturePage::PAGE_CODE => Ok(LogPage::Temperature(
+                TemperaturePage::pull_from_buffer(buffer)?,
+            )),
+            StartStopCyclePage::PAGE_CODE => Ok(LogPage::StartStopCycle(
+                StartStopCyclePage::pull_from_buffer(buffer)?,
+            )),
+            SelfTestResultsPage::PAGE_CODE => Ok(LogPage::SelfTestResults(
+                SelfTestResultsPage::pull_from_buffer(buffer)?,
+            )),
+            SolidStateMediaPage::PAGE_CODE => Ok(LogPage::SolidStateMedia(
+                SolidStateMediaPage::pull_from_buffer(buffer)?,
+            )),
+            InformationalExceptionsPage::PAGE_CODE => Ok(LogPage::InformationalExceptions(
+                InformationalExceptionsPage::pull_from_buffer(buffer)?,
+            )),
+            _ => Ok(LogPage::ErrorCounter(ErrorCounterPage::pull_from_buffer(
+                buffer,
+            )?)),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{
+        ErrorCounterKind, ErrorCounterPage, InformationalExceptionsPage, LogDate, LogPage,
+        LogParameters, SelfTestResult, SelfTestResultsPage, SolidStateMediaPage,
+        StartStopCyclePage, SupportedLogPages, TemperaturePage,
+    };
+    use crate::{BufferPullable, BufferPushable, ErrorCause};
+
+    #[test]
+    pub fn test_logparameters() {
+        let page: [u8; 17] = [
+            0x0d, 0x00, 0x00, 0x0c, 0x00, 0x00, 0x03, 0x02, 0x00, 0x2a, 0x00, 0x01, 0x03, 0x02,
+            0x00, 0x46, 0xee,
+        ];
+        let parameters: Vec<_> = LogParameters::new(&page[..]).unwrap().collect();
+        assert_eq!(parameters.len(), 2);
+        assert_eq!(parameters[1].parameter_code, 1);
+        assert_eq!(parameters[1].control, 0x03);
+        assert_eq!(parameters[1].value, &[0x00, 0x46]);
+
+        // A parameter cut off by the end of the buffer is left out.
+        assert_eq!(LogParameters::new(&page[..15]).unwrap().count(), 1);
+        assert!(LogParameters::new(&page[..3]).is_err());
+
+        let pulled = TemperaturePage::pull_from_buffer(page).unwrap();
+        assert_eq!(pulled.temperature, Some(42));
+        assert_eq!(pulled.reference_temperature, Some(70));
+        let mut buff = [0; 32];
+        assert_eq!(pulled.push_to_buffer(&mut buff[..]).unwrap(), 16);
+        assert_eq!(&buff[..16], &page[..16]);
+        assert!(SolidStateMediaPage::pull_from_buffer(page).is_err());
+    }
+
+    #[test]
+    pub fn test_logpages() {
+        let mut buff = [0xaa; LogPage::MAX_SIZE];
+
+        let mut supported = SupportedLogPages::default();
+        for page_code in [0x00, 0x02, 0x0d, 0x2f] {
+            supported.push_page(page_code).unwrap();
+        }
+        let pushed = supported.push_to_buffer(&mut buff[..]).unwrap();
+        assert_eq!(
+            &buff[..pushed],
+            &[0x00, 0x00, 0x00, 0x04, 0x00, 0x02, 0x0d, 0x2f]
+        );
+        let pulled = SupportedLogPages::pull_from_buffer(&buff[..pushed]).unwrap();
+        assert!(pulled.supports(0x0d));
+        assert!(!pulled.supports(0x10));
+
+        let counters = ErrorCounterPage {
+            kind: ErrorCounterKind::Read,
+            total_corrected: Some(12),
+            bytes_processed: Some(0x1_0000_0000),
+            total_uncorrected: Some(0),
+            ..Default::default()
+        };
+        let pushed = counters.push_to_buffer(&mut buff[..]).unwrap();
+        assert_eq!(pushed, 4 + 3 * 12);
+        assert_eq!(
+            &buff[..8],
+            &[0x03, 0x00, 0x00, 0x24, 0x00, 0x03, 0x00, 0x08]
+        );
+        assert_eq!(
+            LogPage::pull_from_buffer(&buff[..pushed]).unwrap(),
+            LogPage::ErrorCounter(counters)
+        );
+        // Devices may use shorter counters.
+        let short = [0x05, 0x00, 0x00, 0x06, 0x00, 0x06, 0x00, 0x02, 0x01, 0x02];
+        let pulled = ErrorCounterPage::pull_from_buffer(short).unwrap();
+        assert_eq!(pulled.kind, ErrorCounterKind::Verify);
+        assert_eq!(pulled.total_uncorrected, Some(0x102));
+        assert_eq!(pulled.total_corrected, None);
+
+        let cycles = StartStopCyclePage {
+            date_of_manufacture: Some(LogDate {
+                year: 2023,
+                week: 7,
+            }),
+            specified_start_stop_cycles: Some(50_000),
+            accumulated_start_stop_cycles: Some(1234),
+            ..Default::default()
+        };
+        let pushed = cycles.push_to_buffer(&mut buff[..]).unwrap();
+        assert_eq!(&buff[8..14], b"202307");
+        assert_eq!(&buff[18..24], b"      ");
+        assert_eq!(
+            StartStopCyclePage::pull_from_buffer(&buff[..pushed]).unwrap(),
+            cycles
+        );
+
+        let mut self_tests = SelfTestResultsPage::default();
+        self_tests
+            .push_result(SelfTestResult {
+                self_test_code: 2,
+                result: 7,
+                self_test_number: 1,
+                power_on_hours: 9000,
+                first_failure_address: Some(0x1234),
+                sense_key: 0x3,
+                additional_sense_code: 0x11,
+                additional_sense_code_qualifier: 0x00,
+            })
+            .unwrap();
+        self_tests
+            .push_result(SelfTestResult {
+                self_test_code: 1,
+                power_on_hours: 8000,
+                ..Default::default()
+            })
+            .unwrap();
+        let pushed = self_tests.push_to_buffer(&mut buff[..]).unwrap();
+        assert_eq!(pushed, SelfTestResultsPage::SIZE);
+        assert_eq!(
+            &buff[..8],
+            &[0x10, 0x00, 0x01, 0x90, 0x00, 0x01, 0x03, 0x10]
+        );
+        let pulled = SelfTestResultsPage::pull_from_buffer(&buff[..pushed]).unwrap();
+        assert_eq!(pulled, self_tests);
+        assert!(!pulled.latest().unwrap().passed());
+        assert!(pulled.results()[1].passed());
+        assert_eq!(pulled.results()[1].first_failure_address, None);
+
+        let wear = SolidStateMediaPage {
+            percentage_used: Some(3),
+        };
+        let pushed = wear.push_to_buffer(&mut buff[..]).unwrap();
+        assert_eq!(
+            LogPage::pull_from_buffer(&buff[..pushed]).unwrap(),
+            LogPage::SolidStateMedia(wear)
+        );
+
+        let exceptions = InformationalExceptionsPage {
+            additional_sense_code: 0x5d,
+            additional_sense_code_qualifier: 0x10,
+            most_recent_temperature: Some(38),
+        };
+        let pushed = exceptions.push_to_buffer(&mut buff[..]).unwrap();
+        assert_eq!(pushed, InformationalExceptionsPage::SIZE);
+        let pulled = InformationalExceptionsPage::pull_from_buffer(&buff[..pushed]).unwrap();
+        assert_eq!(pulled, exceptions);
+        assert!(!pulled.is_healthy());
+
+        buff[0] = 0x18;
+        let err = LogPage::pull_from_buffer(&buff[..pushed]).unwrap_err();
+        assert_eq!(err.cause, ErrorCause::ParseError);
+        let err = TemperaturePage::default()
+            .push_to_buffer(&mut buff[..8])
+            .unwrap_err();
+        assert_eq!(
+            err.cause,
+            ErrorCause::BufferTooSmallError {
+                expected: 16,
+                actual: 8,
+            }
+        );
+    }
+}
@@ -0,0 +1,316 @@
+use crate::error::{ErrorCause, ScsiError};
+use crate::scsi::commands::{Command, CommandBlockWrapper, Direction};
+use crate::traits::{BufferPullable, BufferPushable};
+
+use byteorder::{ByteOrder, BE};
+
+/// Which set of values a `LogSenseCommand` or `LogSelectCommand` acts on.
+#[derive(Clone, Copy, Eq, PartialEq, Debug, Default)]
+pub enum LogPageControl {
+    /// The values the device has accumulated so far, such as its error
+    /// counters.
+    #[default]
+    CumulativeValues,
+
+    /// The thresholds at which the device reports that a value has crossed a
+    /// limit.
+    ThresholdValues,
+
+    /// The values the cumulative values are reset to.
+    DefaultCumulativeValues,
+
+    /// The thresholds the device starts with.
+    DefaultThresholdValues,
+}
+
+impl LogPageControl {
+    fn from_code(code: u8) -> LogPageControl {
+        match code & 0x3 {
+            0 => LogPageControl::ThresholdValues,
+            1 => LogPageControl::CumulativeValues,
+            2 => LogPageControl::DefaultThresholdValues,
+            _ => LogPageControl::DefaultCumulativeValues,
+        }
+    }
+
+    fn code(self) -> u8 {
+        match self {
+            LogPageControl::ThresholdValues => 0,
+            LogPageControl::CumulativeValues => 1,
+            LogPageControl::DefaultThresholdValues => 2,
+            LogPageControl::DefaultCumulativeValues => 3,
+        }
+    }
+}
+
+/// Asks the device for one of its log pages, which hold statistics such as
+/// error counters and the device's temperature.
+#[derive(Clone, Copy, Eq, PartialEq, Debug, Default)]
+pub struct LogSenseCommand {
+    /// Whether the device should save its log parameters to non-volatile
+    /// storage after sending them.
+    pub save_parameters: bool,
+
+    /// Which set of values to return.
+    pub page_control: LogPageControl,
+
+    /// The log page to return, such as `TemperaturePage::PAGE_CODE`.
+    pub page_code: u8,
+
+    /// The subpage of `page_code` to return; 0 for the page itself.
+    pub subpage_code: u8,
+
+    /// The parameter code the returned page starts at; parameters with
+    /// lower codes are left out.
+    pub parameter_pointer: u16,
+
+    /// The largest number of bytes the device may return.
+    pub allocation_length: u16,
+}
+
+impl LogSenseCommand {
+    /// Constructs a command asking for the cumulative values of log page
+    /// `page_code`.
+    pub fn new(page_code: u8, allocation_length: u16) -> LogSenseCommand {
+        LogSenseCommand {
+            page_code,
+            allocation_length,
+            ..Default::default()
+        }
+    }
+}
+
+impl Command for LogSenseCommand {
+    fn opcode() -> u8 {
+        0x4d
+    }
+    fn length() -> u8 {
+        10
+    }
+    fn wrapper(&self) -> CommandBlockWrapper {
+        CommandBlockWrapper::new(
+            u32::from(self.allocation_length),
+            Direction::IN,
+            0,
+            LogSenseCommand::length(),
+        )
+    }
+}
+
+impl BufferPushable for LogSenseCommand {
+    fn push_to_buffer<B: AsMut<[u8]>>(&self, mut buffer: B) -> Result<usize, ScsiError> {
+        let rval = self.wrapper().push_to_buffer(buffer.as_mut())?;
+        let buffer = &mut buffer.as_mut()[rval..];
+        buffer[0] = LogSenseCommand::opcode();
+        buffer[1] = u8::from(self.save_parameters);
+        buffer[2] = (self.page_control.code() << 6) | (self.page_code & 0x3f);
+        buffer[3] = self.subpage_code;
+        buffer[4] = 0;
+        BE::write_u16(&mut buffer[5..], self.parameter_pointer);
+        BE::write_u16(&mut buffer[7..], self.allocation_length);
+        buffer[9] = 0;
+        Ok(rval + 10)
+    }
+}
+
+impl BufferPullable for LogSenseCommand {
+    fn pull_from_buffer<B: AsRef<[u8]>>(buffer: B) -> Result<Self, ScsiError> {
+        let wrapper: CommandBlockWrapper = CommandBlockWrapper::pull_from_buffer(buffer.as_ref())?;
+        if wrapper.cb_length != LogSenseCommand::length()
+            || (wrapper.data_transfer_length != 0 && wrapper.direction != Direction::IN)
+        {
+            return Err(ScsiError::from_cause(ErrorCause::ParseError));
+        }
+        let buffer = &buffer.as_ref()[15..];
+        if buffer[0] != LogSenseCommand::opcode() {
+            return Err(ScsiError::from_cause(ErrorCause::ParseError));
+        }
+        Ok(LogSenseCommand {
+            save_parameters: buffer[1] & 0x1 != 0,
+            page_control: LogPageControl::from_code(buffer[2] >> 6),
+            page_code: buffer[2] & 0x3f,
+            subpage_code: buffer[3],
+            parameter_pointer: BE::read_u16(&buffer[5..]),
+            allocation_length: BE::read_u16(&buffer[7..]),
+        })
+    }
+}
+
+/// Changes or resets the parameters in the device's log pages.
+///
+/// With `parameter_code_reset` set, every log parameter in the set chosen by
+/// `page_control` is reset and no parameter list is sent. Otherwise the
+/// parameter list holds log pages in the same format LOG SENSE returns them,
+/// whose parameters replace the device's.
+#[derive(Clone, Copy, Eq, PartialEq, Debug, Default)]
+pub struct LogSelectCommand {
+    /// Whether every log parameter should be reset to its default value.
+    pub parameter_code_reset: bool,
+
+    /// Whether the device should save the new parameters to non-volatile
+    /// storage.
+    pub save_parameters: bool,
+
+    /// Which set of values to change.
+    pub page_control: LogPageControl,
+
+    /// The log page the parameter list holds, or 0 if it may hold several.
+    pub page_code: u8,
+
+    /// The subpage of `page_code` the parameter list holds.
+    pub subpage_code: u8,
+
+    /// The number of bytes sent along with the command.
+    pub parameter_list_length: u16,
+}
+
+impl LogSelectCommand {
+    /// Constructs a command replacing parameters of log page `page_code` with
+    /// the `parameter_list_length` bytes sent along with it.
+    pub fn new(page_code: u8, parameter_list_length: u16) -> LogSelectCommand {
+        LogSelectCommand {
+            page_code,
+            parameter_list_length,
+            ..Default::default()
+        }
+    }
+
+    /// Sets the command's parameter list length to match `parameters`.
+    ///
+    /// # Errors
+    /// Returns a `TransferTooLargeError` if `parameters` is longer than the
+    /// 16 bit parameter list length can describe.
+    pub(crate) fn with_parameter_list(
+        mut self,
+        parameters: &[u8],
+    ) -> Result<LogSelectCommand, ScsiError> {
+        self.parameter_list_length = u16::try_from(parameters.len()).map_err(|_| {
+            ScsiError::from_cause(ErrorCause::TransferTooLargeError {
+                actual: parameters.len(),
+                max: usize::from(u16::MAX),
+            })
+        })?;
+        Ok(self)
+    }
+
+    /// Constructs a command resetting every log parameter in the set chosen
+    /// by `page_control`, such as the device's error counters.
+    pub fn reset(page_control: LogPageControl) -> LogSelectCommand {
+        LogSelectCommand {
+            parameter_code_reset: true,
+            page_control,
+            ..Default::default()
+        }
+    }
+}
+
+impl Command for LogSelectCommand {
+    fn opcode() -> u8 {
+        0x4c
+    }
+    fn length() -> u8 {
+        10
+    }
+    fn wrapper(&self) -> CommandBlockWrapper {
+        let direction = if self.parameter_list_length == 0 {
+            Direction::NONE
+        } else {
+            Direction::OUT
+        };
+        CommandBlockWrapper::new(
+            u32::from(self.parameter_list_length),
+            direction,
+            0,
+            LogSelectCommand::length(),
+        )
+    }
+}
+
+impl BufferPushable for LogSelectCommand {
+    fn push_to_buffer<B: AsMut<[u8]>>(&self, mut buffer: B) -> Result<usize, ScsiError> {
+        let rval = self.wrapper().push_to_buffer(buffer.as_mut())?;
+        let buffer = &mut buffer.as_mut()[rval..];
+        buffer[0] = LogSelectCommand::opcode();
+        let reset_bit = if self.parameter_code_reset { 0x2 } else { 0 };
+        buffer[1] = reset_bit | u8::from(self.save_parameters);
+        buffer[2] = (self.page_control.code() << 6) | (self.page_code & 0x3f);
+        buffer[3] = self.subpage_code;
+        for byte in &mut buffer[4..7] {
+            *byte = 0;
+        }
+        BE::write_u16(&mut buffer[7..], self.parameter_list_length);
+        buffer[9] = 0;
+        Ok(rval + 10)
+    }
+}
+
+impl BufferPullable for LogSelectCommand {
+    fn pull_from_buffer<B: AsRef<[u8]>>(buffer: B) -> Result<Self, ScsiError> {
+        let wrapper: CommandBlockWrapper = CommandBlockWrapper::pull_from_buffer(buffer.as_ref())?;
+        if wrapper.cb_length != LogSelectCommand::length()
+            || (wrapper.data_transfer_length != 0 && wrapper.direction != Direction::OUT)
+        {
+            return Err(ScsiError::from_cause(ErrorCause::ParseError));
+        }
+        let buffer = &buffer.as_ref()[15..];
+        if buffer[0] != LogSelectCommand::opcode() {
+            return Err(ScsiError::from_cause(ErrorCause::ParseError));
+        }
+        Ok(LogSelectCommand {
+            parameter_code_reset: buffer[1] & 0x2 != 0,
+            save_parameters: buffer[1] & 0x1 != 0,
+            page_control: LogPageControl::from_code(buffer[2] >> 6),
+            page_code: buffer[2] & 0x3f,
+            subpage_code: buffer[3],
+            parameter_list_length: BE::read_u16(&buffer[7..]),
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{LogPageControl, LogSelectCommand, LogSenseCommand};
+    use crate::{BufferPullable, BufferPushable};
+
+    #[test]
+    pub fn test_logsense() {
+        let expected: [u8; 25] = [
+            0x55, 0x53, 0x42, 0x43, 0x00, 0x00, 0x00, 0x00, 0x94, 0x01, 0x00, 0x00, 0x80, 0x00,
+            0x0a, 0x4d, 0x00, 0x50, 0x00, 0x00, 0x00, 0x00, 0x01, 0x94, 0x00,
+        ];
+        let mut buff = [0; 32];
+        let command = LogSenseCommand::new(0x10, 0x194);
+        let pushed = command.push_to_buffer(&mut buff).unwrap();
+        assert_eq!(pushed, 25);
+        assert_eq!(&buff[0..pushed], &expected[..]);
+        assert_eq!(LogSenseCommand::pull_from_buffer(buff).unwrap(), command);
+        assert!(LogSelectCommand::pull_from_buffer(buff).is_err());
+
+        let command = LogSenseCommand {
+            page_control: LogPageControl::ThresholdValues,
+            parameter_pointer: 0x0003,
+            ..LogSenseCommand::new(0x0d, 16)
+        };
+        command.push_to_buffer(&mut buff).unwrap();
+        assert_eq!(buff[17], 0x0d);
+        assert_eq!(&buff[20..22], &[0x00, 0x03]);
+        assert_eq!(LogSenseCommand::pull_from_buffer(buff).unwrap(), command);
+    }
+
+    #[test]
+    pub fn test_logselect() {
+        let mut buff = [0; 32];
+        let command = LogSelectCommand::reset(LogPageControl::CumulativeValues);
+        let pushed = command.push_to_buffer(&mut buff).unwrap();
+        assert_eq!(pushed, 25);
+        assert_eq!(buff[12], 0x00);
+        assert_eq!(&buff[15..19], &[0x4c, 0x02, 0x40, 0x00]);
+        assert_eq!(LogSelectCommand::pull_from_buffer(buff).unwrap(), command);
+
+        let command = LogSelectCommand::new(0x2f, 12);
+        command.push_to_buffer(&mut buff).unwrap();
+        assert_eq!(&buff[15..18], &[0x4c, 0x00, 0x6f]);
+        assert_eq!(&buff[22..24], &[0x00, 0x0c]);
+        assert_eq!(LogSelectCommand::pull_from_buffer(buff).unwrap(), command);
+    }
+}
@@ -1,18 +1,62 @@
+mod blocklimits;
+pub use self::blocklimits::*;
+mod compareandwrite;
+pub use self::compareandwrite::*;
+mod formatunit;
+pub use self::formatunit::*;
+mod getconfiguration;
+pub use self::getconfiguration::*;
+mod geteventstatus;
+pub use self::geteventstatus::*;
 mod inquiry;
 pub use self::inquiry::*;
+mod logpages;
+pub use self::logpages::*;
+mod logsense;
+pub use self::logsense::*;
+mod persistentreserve;
+pub use self::persistentreserve::*;
+mod preventallow;
+pub use self::preventallow::*;
 mod read10;
 pub use self::read10::*;
+mod read16;
+pub use self::read16::*;
+mod readbuffer;
+pub use self::readbuffer::*;
 mod readcapacity;
 pub use self::readcapacity::*;
+mod readdiscinfo;
+pub use self::readdiscinfo::*;
+mod readtoc;
+pub use self::readtoc::*;
 mod requestsense;
 pub use self::requestsense::*;
+mod reserverelease;
+pub use self::reserverelease::*;
+mod sanitize;
+pub use self::sanitize::*;
+mod startstop;
+pub use self::startstop::*;
+mod synccache;
+pub use self::synccache::*;
 mod testunit;
 pub use self::testunit::*;
+mod unmap;
+pub use self::unmap::*;
+mod verify;
+pub use self::verify::*;
 mod write10;
 pub use self::write10::*;
+mod write16;
+pub use self::write16::*;
+mod writebuffer;
+pub use self::writebuffer::*;
+mod writesame;
+pub use self::writesame::*;
 
-use error::{ErrorCause, ScsiError};
-use traits::{BufferPullable, BufferPushable};
+use crate::error::{ErrorCause, ScsiError};
+use crate::traits::{BufferPullable, BufferPushable};
 
 use byteorder::{ByteOrder, LE};
 
@@ -108,8 +152,8 @@ impl CommandBlockWrapper {
 
 impl BufferPushable for CommandBlockWrapper {
     fn push_to_buffer<B: AsMut<[u8]>>(&self, mut buffer: B) -> Result<usize, ScsiError> {
-        let mut buffer = buffer.as_mut();
-        LE::write_u32(&mut buffer, CommandBlockWrapper::D_CBW_SIGNATURE);
+        let buffer = buffer.as_mut();
+        LE::write_u32(buffer, CommandBlockWrapper::D_CBW_SIGNATURE);
         LE::write_u32(&mut buffer[4..], self.tag);
         LE::write_u32(&mut buffer[8..], self.data_transfer_length);
 
@@ -161,6 +205,23 @@ pub trait Command: BufferPushable + BufferPullable {
     fn length() -> u8;
 }
 
+/// The offset of the command block in a serialized command, right after its
+/// `CommandBlockWrapper`.
+const COMMAND_BLOCK_OFFSET: usize = 15;
+
+/// Serializes `command` without its `CommandBlockWrapper`, for transports such
+/// as UAS and iSCSI that carry bare command blocks.
+///
+/// Returns the command block, zero padded to 16 bytes, along with its length.
+pub(crate) fn command_block<C: Command>(command: &C) -> Result<([u8; 16], usize), ScsiError> {
+    let mut buffer = [0; COMMAND_BLOCK_OFFSET + 16];
+    command.push_to_buffer(&mut buffer[..])?;
+    let length = usize::from(command.wrapper().cb_length).min(16);
+    let mut cdb = [0; 16];
+    cdb[..length].copy_from_slice(&buffer[COMMAND_BLOCK_OFFSET..COMMAND_BLOCK_OFFSET + length]);
+    Ok((cdb, length))
+}
+
 /// This struct prefaces all responses from the SCSI device when a command
 /// requires a response.
 #[derive(Clone, Copy, Eq, PartialEq, Debug, Default)]
@@ -194,6 +255,27 @@ impl CommandStatusWrapper {
 
     /// A magic number that should preface the Command Status Wrapper on the buffer.
     pub const D_CSW_SIGNATURE: u32 = 0x5342_5355;
+
+    /// The SCSI status this CSW reports.
+    ///
+    /// Bulk-Only devices only report `COMMAND_PASSED` or `COMMAND_FAILED`,
+    /// which are read as `ScsiStatus::Good` and `ScsiStatus::CheckCondition`.
+    /// Channels emulating Bulk-Only on top of a transport that carries the
+    /// full status, such as `SgDevice`, report it through
+    /// `CommunicationChannel::scsi_status` instead.
+    ///
+    /// # Errors
+    /// Returns a `FlagError` holding the status for a `PHASE_ERROR` or any
+    /// other value.
+    pub fn scsi_status(&self) -> Result<ScsiStatus, ScsiError> {
+        match self.status {
+            CommandStatusWrapper::COMMAND_PASSED => Ok(ScsiStatus::Good),
+            CommandStatusWrapper::COMMAND_FAILED => Ok(ScsiStatus::CheckCondition),
+            status => Err(ScsiError::from_cause(ErrorCause::FlagError {
+                flags: u32::from(status),
+            })),
+        }
+    }
 }
 
 impl BufferPullable for CommandStatusWrapper {
@@ -226,10 +308,129 @@ impl BufferPushable for CommandStatusWrapper {
     }
 }
 
+/// The SCSI status a device finishes a command with.
+///
+/// Transports such as UAS and iSCSI carry this status as is, while Bulk-Only
+/// can only tell the host whether the command passed or failed; see
+/// `ScsiStatus::bulk_only_status`.
+#[derive(Clone, Copy, Eq, PartialEq, Hash, Debug, Default)]
+pub enum ScsiStatus {
+    /// The command completed successfully.
+    #[default]
+    Good,
+
+    /// The command failed; the sense data describes why.
+    CheckCondition,
+
+    /// A command that searches or prefetches data completed successfully and
+    /// its condition was met.
+    ConditionMet,
+
+    /// The logical unit is busy and the command should be retried later.
+    Busy,
+
+    /// The command conflicts with another initiator's reservation.
+    ReservationConflict,
+
+    /// The device's command queue is full.
+    TaskSetFull,
+
+    /// The command was refused because an earlier one left an auto contingent
+    /// allegiance condition in place.
+    AcaActive,
+
+    /// The command was aborted on behalf of another initiator.
+    TaskAborted,
+}
+
+impl ScsiStatus {
+    /// Parses a status byte.
+    ///
+    /// # Errors
+    /// Returns a `FlagError` containing the byte if it is not a known status.
+    pub fn from_code(code: u8) -> Result<ScsiStatus, ScsiError> {
+        match code {
+            0x00 => Ok(ScsiStatus::Good),
+            0x02 => Ok(ScsiStatus::CheckCondition),
+            0x04 => Ok(ScsiStatus::ConditionMet),
+            0x08 => Ok(ScsiStatus::Busy),
+            0x18 => Ok(ScsiStatus::ReservationConflict),
+            0x28 => Ok(ScsiStatus::TaskSetFull),
+            0x30 => Ok(ScsiStatus::AcaActive),
+            0x40 => Ok(ScsiStatus::TaskAborted),
+            _ => Err(ScsiError::from_cause(ErrorCause::FlagError {
+                flags: u32::from(code),
+            })),
+        }
+    }
+
+    /// The status byte sent over the wire.
+    pub fn code(self) -> u8 {
+        match self {
+            ScsiStatus::Good => 0x00,
+            ScsiStatus::CheckCondition => 0x02,
+            ScsiStatus::ConditionMet => 0x04,
+            ScsiStatus::Busy => 0x08,
+            ScsiStatus::ReservationConflict => 0x18,
+            ScsiStatus::TaskSetFull => 0x28,
+            ScsiStatus::AcaActive => 0x30,
+            ScsiStatus::TaskAborted => 0x40,
+        }
+    }
+
+    /// Whether the command completed successfully.
+    pub fn is_good(self) -> bool {
+        matches!(self, ScsiStatus::Good | ScsiStatus::ConditionMet)
+    }
+
+    /// The value of a Bulk-Only `CommandStatusWrapper::status` reporting this
+    /// status, which is either `COMMAND_PASSED` or `COMMAND_FAILED`.
+    pub fn bulk_only_status(self) -> u8 {
+        if self.is_good() {
+            CommandStatusWrapper::COMMAND_PASSED
+        } else {
+            CommandStatusWrapper::COMMAND_FAILED
+        }
+    }
+
+    /// The status a device reports when one of its `ScsiResponder` hooks
+    /// fails with `err`, if that error stands for a status of its own.
+    ///
+    /// A `ReservationConflictError` becomes `ReservationConflict` and a
+    /// `StatusError` becomes the status it carries.
+    pub fn from_error(err: &ScsiError) -> Option<ScsiStatus> {
+        match err.cause {
+            ErrorCause::ReservationConflictError => Some(ScsiStatus::ReservationConflict),
+            ErrorCause::StatusError { status } => Some(status),
+            _ => None,
+        }
+    }
+
+    /// The error a host reports when a command finishes with this status, or
+    /// `None` for statuses that pass or that come with sense data.
+    ///
+    /// This is the reverse of `ScsiStatus::from_error`.
+    pub fn to_error(self) -> Option<ScsiError> {
+        match self {
+            ScsiStatus::Good | ScsiStatus::ConditionMet | ScsiStatus::CheckCondition => None,
+            ScsiStatus::ReservationConflict => {
+                Some(ScsiError::from_cause(ErrorCause::ReservationConflictError))
+            }
+            status => Some(ScsiError::from_cause(ErrorCause::StatusError { status })),
+        }
+    }
+}
+
+impl From<ScsiStatus> for u8 {
+    fn from(status: ScsiStatus) -> u8 {
+        status.code()
+    }
+}
+
 #[cfg(test)]
 mod tests {
-    use super::{CommandBlockWrapper, CommandStatusWrapper, Direction};
-    use crate::{BufferPullable, BufferPushable};
+    use super::{CommandBlockWrapper, CommandStatusWrapper, Direction, ScsiStatus};
+    use crate::{BufferPullable, BufferPushable, ErrorCause, ScsiError};
 
     #[test]
     pub fn test_cbw() {
@@ -244,7 +445,7 @@ mod tests {
         assert_eq!(pushed, 15);
         assert_eq!(&buff[0..pushed], &expected[0..pushed]);
 
-        let pulled = CommandBlockWrapper::pull_from_buffer(&mut buff).unwrap();
+        let pulled = CommandBlockWrapper::pull_from_buffer(buff).unwrap();
         assert_eq!(pulled, cbw);
     }
     #[test]
@@ -264,7 +465,43 @@ mod tests {
         assert_eq!(pushed, 13);
         assert_eq!(&buff[0..pushed], &expected[0..pushed]);
 
-        let pulled = CommandStatusWrapper::pull_from_buffer(&mut buff).unwrap();
+        let pulled = CommandStatusWrapper::pull_from_buffer(buff).unwrap();
         assert_eq!(pulled, csw);
     }
+
+    #[test]
+    pub fn test_scsi_status() {
+        for code in 0..=0xff {
+            if let Ok(status) = ScsiStatus::from_code(code) {
+                assert_eq!(u8::from(status), code);
+            }
+        }
+        assert_eq!(
+            ScsiStatus::from_code(0x18),
+            Ok(ScsiStatus::ReservationConflict)
+        );
+        assert!(ScsiStatus::from_code(0x10).is_err());
+        assert_eq!(ScsiStatus::ConditionMet.bulk_only_status(), 0);
+        assert_eq!(ScsiStatus::Busy.bulk_only_status(), 1);
+
+        // Statuses without sense data map to errors and back.
+        assert_eq!(ScsiStatus::CheckCondition.to_error(), None);
+        for status in [ScsiStatus::ReservationConflict, ScsiStatus::TaskSetFull] {
+            let err = status.to_error().unwrap();
+            assert_eq!(ScsiStatus::from_error(&err), Some(status));
+        }
+        let err = ScsiError::from_cause(ErrorCause::ParseError);
+        assert_eq!(ScsiStatus::from_error(&err), None);
+
+        let csw = |status| CommandStatusWrapper {
+            tag: 0,
+            data_residue: 0,
+            status,
+        };
+        assert_eq!(csw(0).scsi_status(), Ok(ScsiStatus::Good));
+        assert_eq!(csw(1).scsi_status(), Ok(ScsiStatus::CheckCondition));
+        assert!(csw(2).scsi_status().is_err());
+        assert!(csw(0x18).scsi_status().is_err());
+        assert!(csw(0x10).scsi_status().is_err());
+    }
 }
@@ -0,0 +1,859 @@
+use crate::error::{ErrorCause, ScsiError};
+use crate::scsi::commands::{Command, CommandBlockWrapper, Direction};
+use crate::traits::{BufferPullable, BufferPushable};
+
+use byteorder::{ByteOrder, BE};
+
+/// The bit in the flags byte of a PERSISTENT RESERVE OUT parameter list
+/// asking for a registration to apply to every port of the target.
+const ALL_TARGET_PORTS_FLAG: u8 = 0x04;
+
+/// The bit in the flags byte of a PERSISTENT RESERVE OUT parameter list
+/// asking for the reservation state to survive a loss of power.
+const PERSIST_THROUGH_POWER_LOSS_FLAG: u8 = 0x01;
+
+/// The information a `PersistentReserveInCommand` asks for.
+#[derive(Clone, Copy, Eq, PartialEq, Debug, Default)]
+pub enum PersistentReserveInServiceAction {
+    /// Asks for every registered reservation key, answered with a
+    /// `ReservationKeysResponse`.
+    #[default]
+    ReadKeys,
+
+    /// Asks for the current reservation, answered with a
+    /// `ReservationResponse`.
+    ReadReservation,
+
+    /// Asks which reservation features the device supports, answered with a
+    /// `ReservationCapabilitiesResponse`.
+    ReportCapabilities,
+}
+
+impl PersistentReserveInServiceAction {
+    fn from_code(code: u8) -> Result<PersistentReserveInServiceAction, ScsiError> {
+        match code {
+            0x00 => Ok(PersistentReserveInServiceAction::ReadKeys),
+            0x01 => Ok(PersistentReserveInServiceAction::ReadReservation),
+            0x02 => Ok(PersistentReserveInServiceAction::ReportCapabilities),
+            _ => Err(ScsiError::from_cause(ErrorCause::ParseError)),
+        }
+    }
+
+    fn code(self) -> u8 {
+        match self {
+            PersistentReserveInServiceAction::ReadKeys => 0x00,
+            PersistentReserveInServiceAction::ReadReservation => 0x01,
+            PersistentReserveInServiceAction::ReportCapabilities => 0x02,
+        }
+    }
+}
+
+/// Reads the persistent reservation state of the device, which is shared by
+/// every initiator talking to it.
+#[derive(Clone, Copy, Eq, PartialEq, Debug, Default)]
+pub struct PersistentReserveInCommand {
+    /// The information to return.
+    pub service_action: PersistentReserveInServiceAction,
+
+    /// The maximum number of bytes the host is willing to receive.
+    pub allocation_length: u16,
+}
+
+impl PersistentReserveInCommand {
+    /// Constructs a command performing `service_action`, with room for the
+    /// largest response this library can parse.
+    pub fn new(service_action: PersistentReserveInServiceAction) -> PersistentReserveInCommand {
+        PersistentReserveInCommand {
+            service_action,
+            allocation_length: ReservationKeysResponse::MAX_SIZE as u16,
+        }
+    }
+}
+
+impl Command for PersistentReserveInCommand {
+    fn opcode() -> u8 {
+        0x5e
+    }
+    fn length() -> u8 {
+        10
+    }
+    fn wrapper(&self) -> CommandBlockWrapper {
+        CommandBlockWrapper::new(
+            u32::from(self.allocation_length),
+            Direction::IN,
+            0,
+            PersistentReserveInCommand::length(),
+        )
+    }
+}
+
+impl BufferPushable for PersistentReserveInCommand {
+    fn push_to_buffer<B: AsMut<[u8]>>(&self, mut buffer: B) -> Result<usize, ScsiError> {
+        let rval = self.wrapper().push_to_buffer(buffer.as_mut())?;
+        let buffer = &mut buffer.as_mut()[rval..];
+        buffer[0] = PersistentReserveInCommand::opcode();
+        buffer[1] = self.service_action.code();
+        for byte in &mut buffer[2..7] {
+            *byte = 0;
+        }
+        BE::write_u16(&mut buffer[7..], self.allocation_length);
+        buffer[9] = 0;
+        Ok(rval + 10)
+    }
+}
+
+impl BufferPullable for PersistentReserveInCommand {
+    fn pull_from_buffer<B: AsRef<[u8]>>(buffer: B) -> Result<Self, ScsiError> {
+        let wrapper: CommandBlockWrapper = CommandBlockWrapper::pull_from_buffer(buffer.as_ref())?;
+        if wrapper.direction != Direction::IN
+            || wrapper.cb_length != PersistentReserveInCommand::length()
+        {
+            return Err(ScsiError::from_cause(ErrorCause::ParseError));
+        }
+        let buffer = &buffer.as_ref()[15..];
+        if buffer[0] != PersistentReserveInCommand::opcode() {
+            return Err(ScsiError::from_cause(ErrorCause::ParseError));
+        }
+        Ok(PersistentReserveInCommand {
+            service_action: PersistentReserveInServiceAction::from_code(buffer[1] & 0x1f)?,
+            allocation_length: BE::read_u16(&buffer[7..]),
+        })
+    }
+}
+
+/// The change a `PersistentReserveOutCommand` makes to the reservation
+/// state.
+#[derive(Clone, Copy, Eq, PartialEq, Debug, Default)]
+pub enum PersistentReserveOutServiceAction {
+    /// Registers the parameters' `service_action_key` for the initiator,
+    /// replaces its existing key, or unregisters it if the new key is 0.
+    #[default]
+    Register,
+
+    /// Takes out a reservation of the command's type.
+    Reserve,
+
+    /// Gives up the reservation the initiator holds.
+    Release,
+
+    /// Removes every registration along with the reservation.
+    Clear,
+
+    /// Removes the registrations holding the parameters'
+    /// `service_action_key`, taking over their reservation if they held it.
+    Preempt,
+
+    /// Like `Preempt`, but also aborts the commands the preempted initiators
+    /// have in flight.
+    PreemptAndAbort,
+
+    /// Like `Register`, but without checking the initiator's current key.
+    RegisterAndIgnoreExistingKey,
+}
+
+impl PersistentReserveOutServiceAction {
+    fn from_code(code: u8) -> Result<PersistentReserveOutServiceAction, ScsiError> {
+        match code {
+            0x00 => Ok(PersistentReserveOutServiceAction::Register),
+            0x01 => Ok(PersistentReserveOutServiceAction::Reserve),
+            0x02 => Ok(PersistentReserveOutServiceAction::Release),
+            0x03 => Ok(PersistentReserveOutServiceAction::Clear),
+            0x04 => Ok(PersistentReserveOutServiceAction::Preempt),
+            0x05 => Ok(PersistentReserveOutServiceAction::PreemptAndAbort),
+            0x06 => Ok(PersistentReserveOutServiceAction::RegisterAndIgnoreExistingKey),
+            _ => Err(ScsiError::from_cause(ErrorCause::ParseError)),
+        }
+    }
+
+    fn code(self) -> u8 {
+        match self {
+            PersistentReserveOutServiceAction::Register => 0x00,
+            PersistentReserveOutServiceAction::Reserve => 0x01,
+            PersistentReserveOutServiceAction::Release => 0x02,
+            PersistentReserveOutServiceAction::Clear => 0x03,
+            PersistentReserveOutServiceAction::Preempt => 0x04,
+            PersistentReserveOutServiceAction::PreemptAndAbort => 0x05,
+            PersistentReserveOutServiceAction::RegisterAndIgnoreExistingKey => 0x06,
+        }
+    }
+}
+
+/// The kind of access a persistent reservation keeps from initiators that do
+/// not hold it.
+#[derive(Clone, Copy, Eq, PartialEq, Debug)]
+pub enum ReservationType {
+    /// Only the holder may write; anyone may read.
+    WriteExclusive,
+
+    /// Only the holder may read or write.
+    ExclusiveAccess,
+
+    /// Only registered initiators may write, and the reservation is held by
+    /// the initiator that took it out.
+    WriteExclusiveRegistrantsOnly,
+
+    /// Only registered initiators may read or write, and the reservation is
+    /// held by the initiator that took it out.
+    ExclusiveAccessRegistrantsOnly,
+
+    /// Only registered initiators may write, and every one of them holds the
+    /// reservation.
+    WriteExclusiveAllRegistrants,
+
+    /// Only registered initiators may read or write, and every one of them
+    /// holds the reservation.
+    ExclusiveAccessAllRegistrants,
+}
+
+impl ReservationType {
+    /// Returns the type with the code `code`, or `None` for the code 0 sent
+    /// by service actions that do not take a type.
+    fn from_code(code: u8) -> Result<Option<ReservationType>, ScsiError> {
+        match code {
+            0x0 => Ok(None),
+            0x1 => Ok(Some(ReservationType::WriteExclusive)),
+            0x3 => Ok(Some(ReservationType::ExclusiveAccess)),
+            0x5 => Ok(Some(ReservationType::WriteExclusiveRegistrantsOnly)),
+            0x6 => Ok(Some(ReservationType::ExclusiveAccessRegistrantsOnly)),
+            0x7 => Ok(Some(ReservationType::WriteExclusiveAllRegistrants)),
+            0x8 => Ok(Some(ReservationType::ExclusiveAccessAllRegistrants)),
+            _ => Err(ScsiError::from_cause(ErrorCause::ParseError)),
+        }
+    }
+
+    fn code(reservation_type: Option<ReservationType>) -> u8 {
+        match reservation_type {
+            None => 0x0,
+            Some(ReservationType::WriteExclusive) => 0x1,
+            Some(ReservationType::ExclusiveAccess) => 0x3,
+            Some(ReservationType::WriteExclusiveRegistrantsOnly) => 0x5,
+            Some(ReservationType::ExclusiveAccessRegistrantsOnly) => 0x6,
+            Some(ReservationType::WriteExclusiveAllRegistrants) => 0x7,
+            Some(ReservationType::ExclusiveAccessAllRegistrants) => 0x8,
+        }
+    }
+
+    /// The bit standing for this type in a
+    /// `ReservationCapabilitiesResponse::type_mask`.
+    pub fn mask_bit(self) -> u16 {
+        match self {
+            ReservationType::WriteExclusiveAllRegistrants => 0x8000,
+            ReservationType::ExclusiveAccessRegistrantsOnly => 0x4000,
+            ReservationType::WriteExclusiveRegistrantsOnly => 0x2000,
+            ReservationType::ExclusiveAccess => 0x0800,
+            ReservationType::WriteExclusive => 0x0200,
+            ReservationType::ExclusiveAccessAllRegistrants => 0x0001,
+        }
+    }
+
+    /// Whether the reservation keeps initiators that do not hold it from
+    /// reading as well as writing.
+    pub fn is_exclusive_access(self) -> bool {
+        matches!(
+            self,
+            ReservationType::ExclusiveAccess
+                | ReservationType::ExclusiveAccessRegistrantsOnly
+                | ReservationType::ExclusiveAccessAllRegistrants
+        )
+    }
+
+    /// Whether every registered initiator gets the same access as the
+    /// reservation's holder.
+    pub fn admits_registrants(self) -> bool {
+        !matches!(
+            self,
+            ReservationType::WriteExclusive | ReservationType::ExclusiveAccess
+        )
+    }
+
+    /// Whether every registered initiator holds the reservation, so that it
+    /// has no single holder or key.
+    pub fn is_all_registrants(self) -> bool {
+        matches!(
+            self,
+            ReservationType::WriteExclusiveAllRegistrants
+                | ReservationType::ExclusiveAccessAllRegistrants
+        )
+    }
+}
+
+/// Changes the persistent reservation state of the device.
+///
+/// Every service action is sent along with a `PersistentReserveOutParameters`
+/// whose `reservation_key` must match the key the initiator registered;
+/// otherwise the device reports a reservation conflict.
+#[derive(Clone, Copy, Eq, PartialEq, Debug, Default)]
+pub struct PersistentReserveOutCommand {
+    /// The change to make.
+    pub service_action: PersistentReserveOutServiceAction,
+
+    /// The type of reservation to take out, for `Reserve`, `Release` and the
+    /// preempting service actions.
+    pub reservation_type: Option<ReservationType>,
+
+    /// The length of the parameter list sent along with the command.
+    pub parameter_list_length: u32,
+}
+
+impl PersistentReserveOutCommand {
+    /// Constructs a command performing `service_action`, sent along with a
+    /// basic parameter list.
+    pub fn new(
+        service_action: PersistentReserveOutServiceAction,
+        reservation_type: Option<ReservationType>,
+    ) -> PersistentReserveOutCommand {
+        PersistentReserveOutCommand {
+            service_action,
+            reservation_type,
+            parameter_list_length: PersistentReserveOutParameters::SIZE as u32,
+        }
+    }
+}
+
+impl Command for PersistentReserveOutCommand {
+    fn opcode() -> u8 {
+        0x5f
+    }
+    fn length() -> u8 {
+        10
+    }
+    fn wrapper(&self) -> CommandBlockWrapper {
+        CommandBlockWrapper::new(
+            self.parameter_list_length,
+            Direction::OUT,
+            0,
+            PersistentReserveOutCommand::length(),
+        )
+    }
+}
+
+impl BufferPushable for PersistentReserveOutCommand {
+    fn push_to_buffer<B: AsMut<[u8]>>(&self, mut buffer: B) -> Result<usize, ScsiError> {
+        let rval = self.wrapper().push_to_buffer(buffer.as_mut())?;
+        let buffer = &mut buffer.as_mut()[rval..];
+        buffer[0] = PersistentReserveOutCommand::opcode();
+        buffer[1] = self.service_action.code();
+        // Only the logical unit scope, 0, is defined.
+        buffer[2] = ReservationType::code(self.reservation_type);
+        buffer[3] = 0;
+        buffer[4] = 0;
+        BE::write_u32(&mut buffer[5..], self.parameter_list_length);
+        buffer[9] = 0;
+        Ok(rval + 10)
+    }
+}
+
+impl BufferPullable for PersistentReserveOutCommand {
+    fn pull_from_buffer<B: AsRef<[u8]>>(buffer: B) -> Result<Self, ScsiError> {
+        let wrapper: CommandBlockWrapper = CommandBlockWrapper::pull_from_buffer(buffer.as_ref())?;
+        if wrapper.cb_length != PersistentReserveOutCommand::length()
+            || (wrapper.data_transfer_length != 0 && wrapper.direction != Direction::OUT)
+        {
+            return Err(ScsiError::from_cause(ErrorCause::ParseError));
+        }
+        let buffer = &buffer.as_ref()[15..];
+        if buffer[0] != PersistentReserveOutCommand::opcode() {
+            return Err(ScsiError::from_cause(ErrorCause::ParseError));
+        }
+        Ok(PersistentReserveOutCommand {
+            service_action: PersistentReserveOutServiceAction::from_code(buffer[1] & 0x1f)?,
+            reservation_type: ReservationType::from_code(buffer[2] & 0xf)?,
+            parameter_list_length: BE::read_u32(&buffer[5..]),
+        })
+    }
+}
+
+/// The parameter list sent along with a `PersistentReserveOutCommand`.
+#[derive(Clone, Copy, Eq, PartialEq, Debug, Default)]
+pub struct PersistentReserveOutParameters {
+    /// The key the initiator has registered, or 0 if it has not registered
+    /// one.
+    pub reservation_key: u64,
+
+    /// The new key for the registering service actions, or the key of the
+    /// registrations to remove for the preempting ones.
+    pub service_action_key: u64,
+
+    /// Whether a registration applies to every port of the target rather
+    /// than just the one it was received on.
+    pub all_target_ports: bool,
+
+    /// Whether the reservation state should survive a loss of power.
+    pub persist_through_power_loss: bool,
+}
+
+impl PersistentReserveOutParameters {
+    /// The size of the parameter list, in bytes.
+    pub const SIZE: usize = 24;
+
+    /// Constructs a parameter list for an initiator registered with
+    /// `reservation_key`.
+    pub fn new(reservation_key: u64, service_action_key: u64) -> PersistentReserveOutParameters {
+        PersistentReserveOutParameters {
+            reservation_key,
+            service_action_key,
+            ..Default::default()
+        }
+    }
+}
+
+impl BufferPushable for PersistentReserveOutParameters {
+    fn push_to_buffer<B: AsMut<[u8]>>(&self, mut buffer: B) -> Result<usize, ScsiError> {
+        let buffer = buffer.as_mut();
+        if buffer.len() < PersistentReserveOutParameters::SIZE {
+            return Err(ScsiError::from_cause(ErrorCause::BufferTooSmallError {
+                expected: PersistentReserveOutParameters::SIZE,
+                actual: buffer.len(),
+            }));
+        }
+        BE::write_u64(buffer, self.reservation_key);
+        BE::write_u64(&mut buffer[8..], self.service_action_key);
+        for byte in &mut buffer[16..PersistentReserveOutParameters::SIZE] {
+            *byte = 0;
+        }
+        if self.all_target_ports {
+            buffer[20] |= ALL_TARGET_PORTS_FLAG;
+        }
+        if self.persist_through_power_loss {
+            buffer[20] |= PERSIST_THROUGH_POWER_LOSS_FLAG;
+        }
+        Ok(PersistentReserveOutParameters::SIZE)
+    }
+}
+
+impl BufferPullable for PersistentReserveOutParameters {
+    fn pull_from_buffer<B: AsRef<[u8]>>(buffer: B) -> Result<Self, ScsiError> {
+        let buffer = buffer.as_ref();
+        if buffer.len() < PersistentReserveOutParameters::SIZE {
+            return Err(ScsiError::from_cause(ErrorCause::BufferTooSmallError {
+                expected: PersistentReserveOutParameters::SIZE,
+                actual: buffer.len(),
+            }));
+        }
+        Ok(PersistentReserveOutParameters {
+            reservation_key: BE::read_u64(buffer),
+            service_action_key: BE::read_u64(&buffer[8..]),
+            all_target_ports: buffer[20] & ALL_TARGET_PORTS_FLAG != 0,
+            persist_through_power_loss: buffer[20] & PERSIST_THROUGH_POWER_LOSS_FLAG != 0,
+        })
+    }
+}
+
+/// Reads the 8 byte header every persistent reservation response starts
+/// with, returning the generation and the length of the data after it.
+fn pull_header(buffer: &[u8]) -> Result<(u32, usize), ScsiError> {
+    if buffer.len() < 8 {
+        return Err(ScsiError::from_cause(ErrorCause::BufferTooSmallError {
+            expected: 8,
+            actual: buffer.len(),
+        }));
+    }
+    Ok((BE::read_u32(buffer), BE::read_u32(&buffer[4..]) as usize))
+}
+
+/// The data sent in response to a `PersistentReserveInCommand` reading the
+/// registered keys.
+#[derive(Clone, Copy, Eq, PartialEq, Debug, Default)]
+pub struct ReservationKeysResponse {
+    /// A counter the device increments every time the registrations change.
+    pub generation: u32,
+
+    /// The registered keys; only the first `key_count` are valid.
+    pub keys: [u64; ReservationKeysResponse::MAX_KEYS],
+
+    /// The number of valid entries in `keys`.
+    pub key_count: usize,
+}
+
+impl ReservationKeysResponse {
+    /// The maximum number of keys a `ReservationKeysResponse` can carry.
+    pub const MAX_KEYS: usize = 16;
+
+    /// The size of the largest response, in bytes.
+    pub const MAX_SIZE: usize = 8 + 8 * ReservationKeysResponse::MAX_KEYS;
+
+    /// The size of the header at the start of the READ KEYS and READ
+    /// RESERVATION responses.
+    pub(crate) const HEADER_SIZE: usize = 8;
+
+    /// The length of the READ KEYS or READ RESERVATION response whose header
+    /// is at the start of `buffer`, capped at the largest response that can
+    /// be parsed.
+    ///
+    /// The responses are read in two steps, first the header and then the
+    /// whole response, since a device sending less data than the host
+    /// expects could leave the host reading its status as data.
+    pub(crate) fn response_length(buffer: &[u8]) -> usize {
+        let length = BE::read_u32(&buffer[4..]) as usize;
+        (ReservationKeysResponse::HEADER_SIZE + length).min(ReservationKeysResponse::MAX_SIZE)
+    }
+
+    /// Appends a key to the response, failing if it is already full.
+    pub fn push_key(&mut self, key: u64) -> Result<(), ScsiError> {
+        if self.key_count >= ReservationKeysResponse::MAX_KEYS {
+            return Err(ScsiError::from_cause(ErrorCause::BufferTooSmallError {
+                expected: self.key_count + 1,
+                actual: ReservationKeysResponse::MAX_KEYS,
+            }));
+        }
+        self.keys[self.key_count] = key;
+        self.key_count += 1;
+        Ok(())
+    }
+
+    /// The valid keys in the response.
+    pub fn keys(&self) -> &[u64] {
+        &self.keys[..self.key_count]
+    }
+}
+
+impl BufferPushable for ReservationKeysResponse {
+    fn push_to_buffer<B: AsMut<[u8]>>(&self, mut buffer: B) -> Result<usize, ScsiError> {
+        let buffer = buffer.as_mut();
+        let total = 8 + 8 * self.key_count;
+        if buffer.len() < total {
+            return Err(ScsiError::from_cause(ErrorCause::BufferTooSmallError {
+                expected: total,
+                actual: buffer.len(),
+            }));
+        }
+        BE::write_u32(buffer, self.generation);
+        BE::write_u32(&mut buffer[4..], (total - 8) as u32);
+        for (idx, key) in self.keys().iter().enumerate() {
+            BE::write_u64(&mut buffer[8 + 8 * idx..], *key);
+        }
+        Ok(total)
+    }
+}
+
+impl BufferPullable for ReservationKeysResponse {
+    fn pull_from_buffer<B: AsRef<[u8]>>(buffer: B) -> Result<Self, ScsiError> {
+        let buffer = buffer.as_ref();
+        let (generation, length) = pull_header(buffer)?;
+        let available = (8 + length).min(buffer.len());
+        let mut response = ReservationKeysResponse {
+            generation,
+            ..Default::default()
+        };
+        let mut cur = 8;
+        while cur + 8 <= available && response.key_count < ReservationKeysResponse::MAX_KEYS {
+            response.push_key(BE::read_u64(&buffer[cur..]))?;
+            cur += 8;
+        }
+        Ok(response)
+    }
+}
+
+/// A persistent reservation, as reported by a `ReservationResponse`.
+#[derive(Clone, Copy, Eq, PartialEq, Debug)]
+pub struct Reservation {
+    /// The key of the initiator holding the reservation, or 0 for the
+    /// all-registrants types.
+    pub key: u64,
+
+    /// The type of the reservation.
+    pub reservation_type: ReservationType,
+}
+
+/// The data sent in response to a `PersistentReserveInCommand` reading the
+/// current reservation.
+#[derive(Clone, Copy, Eq, PartialEq, Debug, Default)]
+pub struct ReservationResponse {
+    /// A counter the device increments every time the registrations change.
+    pub generation: u32,
+
+    /// The current reservation, if there is one.
+    pub reservation: Option<Reservation>,
+}
+
+impl BufferPushable for ReservationResponse {
+    fn push_to_buffer<B: AsMut<[u8]>>(&self, mut buffer: B) -> Result<usize, ScsiError> {
+        let buffer = buffer.as_mut();
+        let total = if self.reservation.is_some() { 24 } else { 8 };
+        if buffer.len() < total {
+            return Err(ScsiError::from_cause(ErrorCause::BufferTooSmallError {
+                expected: total,
+                actual: buffer.len(),
+            }));
+        }
+        BE::write_u32(buffer, self.generation);
+        BE::write_u32(&mut buffer[4..], (total - 8) as u32);
+        if let Some(reservation) = self.reservation {
+            BE::write_u64(&mut buffer[8..], reservation.key);
+            for byte in &mut buffer[16..24] {
+                *byte = 0;
+            }
+            buffer[21] = ReservationType::code(Some(reservation.reservation_type));
+        }
+        Ok(total)
+    }
+}
+
+impl BufferPullable for ReservationResponse {
+    fn pull_from_buffer<B: AsRef<[u8]>>(buffer: B) -> Result<Self, ScsiError> {
+        let buffer = buffer.as_ref();
+        let (generation, length) = pull_header(buffer)?;
+        if length < 16 {
+            return Ok(ReservationResponse {
+                generation,
+                reservation: None,
+            });
+        }
+        if buffer.len() < 24 {
+            return Err(ScsiError::from_cause(ErrorCause::BufferTooSmallError {
+                expected: 24,
+                actual: buffer.len(),
+            }));
+        }
+        let reservation_type = ReservationType::from_code(buffer[21] & 0xf)?
+            .ok_or_else(|| ScsiError::from_cause(ErrorCause::ParseError))?;
+        Ok(ReservationResponse {
+            generation,
+            reservation: Some(Reservation {
+                key: BE::read_u64(&buffer[8..]),
+                reservation_type,
+            }),
+        })
+    }
+}
+
+/// The data sent in response to a `PersistentReserveInCommand` asking for
+/// the device's capabilities.
+#[derive(Clone, Copy, Eq, PartialEq, Debug, Default)]
+pub struct ReservationCapabilitiesResponse {
+    /// Whether the device handles the legacy RESERVE and RELEASE commands as
+    /// if they were persistent reservations.
+    pub compatible_reservation_handling: bool,
+
+    /// Whether a registration can name other initiators to register too.
+    pub specify_initiator_ports_capable: bool,
+
+    /// Whether a registration can apply to every port of the target.
+    pub all_target_ports_capable: bool,
+
+    /// Whether the reservation state can survive a loss of power.
+    pub persist_through_power_loss_capable: bool,
+
+    /// Whether the reservation state is currently kept through a loss of
+    /// power.
+    pub persist_through_power_loss_activated: bool,
+
+    /// The reservation types the device supports, as an OR of
+    /// `ReservationType::mask_bit`s, or `None` if it does not say.
+    pub type_mask: Option<u16>,
+}
+
+impl ReservationCapabilitiesResponse {
+    /// The size of the response, in bytes.
+    pub const SIZE: usize = 8;
+
+    /// Whether the device reports supporting reservations of type
+    /// `reservation_type`.
+    pub fn supports(&self, reservation_type: ReservationType) -> bool {
+        self.type_mask
+            .is_some_and(|mask| mask & reservation_type.mask_bit() != 0)
+    }
+}
+
+impl BufferPushable for ReservationCapabilitiesResponse {
+    fn push_to_buffer<B: AsMut<[u8]>>(&self, mut buffer: B) -> Result<usize, ScsiError> {
+        let buffer = buffer.as_mut();
+        if buffer.len() < ReservationCapabilitiesResponse::SIZE {
+            return Err(ScsiError::from_cause(ErrorCause::BufferTooSmallError {
+                expected: ReservationCapabilitiesResponse::SIZE,
+                actual: buffer.len(),
+            }));
+        }
+        BE::write_u16(buffer, ReservationCapabilitiesResponse::SIZE as u16);
+        buffer[2] = 0;
+        if self.compatible_reservation_handling {
+            buffer[2] |= 0x10;
+        }
+        if self.specify_initiator_ports_capable {
+            buffer[2] |= 0x08;
+        }
+        if self.all_target_ports_capable {
+            buffer[2] |= 0x04;
+        }
+        if self.persist_through_power_loss_capable {
+            buffer[2] |= 0x01;
+        }
+        buffer[3] = 0;
+        if self.type_mask.is_some() {
+            buffer[3] |= 0x80;
+        }
+        if self.persist_through_power_loss_activated {
+            buffer[3] |= 0x01;
+        }
+        BE::write_u16(&mut buffer[4..], self.type_mask.unwrap_or(0));
+        buffer[6] = 0;
+        buffer[7] = 0;
+        Ok(ReservationCapabilitiesResponse::SIZE)
+    }
+}
+
+impl BufferPullable for ReservationCapabilitiesResponse {
+    fn pull_from_buffer<B: AsRef<[u8]>>(buffer: B) -> Result<Self, ScsiError> {
+        let buffer = buffer.as_ref();
+        if buffer.len() < ReservationCapabilitiesResponse::SIZE {
+            return Err(ScsiError::from_cause(ErrorCause::BufferTooSmallError {
+                expected: ReservationCapabilitiesResponse::SIZE,
+                actual: buffer.len(),
+            }));
+        }
+        Ok(ReservationCapabilitiesResponse {
+            compatible_reservation_handling: buffer[2] & 0x10 != 0,
+            specify_initiator_ports_capable: buffer[2] & 0x08 != 0,
+            all_target_ports_capable: buffer[2] & 0x04 != 0,
+            persist_through_power_loss_capable: buffer[2] & 0x01 != 0,
+            persist_through_power_loss_activated: buffer[3] & 0x01 != 0,
+            type_mask: if buffer[3] & 0x80 != 0 {
+                Some(BE::read_u16(&buffer[4..]))
+            } else {
+                None
+            },
+        })
+    }
+}
+
+/// Any of the responses to a `PersistentReserveInCommand`, depending on its
+/// service action.
+#[derive(Clone, Copy, Eq, PartialEq, Debug)]
+pub enum PersistentReserveInResponse {
+    /// The response to `PersistentReserveInServiceAction::ReadKeys`.
+    Keys(ReservationKeysResponse),
+
+    /// The response to `PersistentReserveInServiceAction::ReadReservation`.
+    Reservation(ReservationResponse),
+
+    /// The response to `PersistentReserveInServiceAction::ReportCapabilities`.
+    Capabilities(ReservationCapabilitiesResponse),
+}
+
+impl BufferPushable for PersistentReserveInResponse {
+    fn push_to_buffer<B: AsMut<[u8]>>(&self, buffer: B) -> Result<usize, ScsiError> {
+        match self {
+            PersistentReserveInResponse::Keys(r) => r.push_to_buffer(buffer),
+            PersistentReserveInResponse::Reservation(r) => r.push_to_buffer(buffer),
+            PersistentReserveInResponse::Capabilities(r) => r.push_to_buffer(buffer),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{
+        PersistentReserveInCommand, PersistentReserveInServiceAction, PersistentReserveOutCommand,
+        PersistentReserveOutParameters, PersistentReserveOutServiceAction, Reservation,
+        ReservationCapabilitiesResponse, ReservationKeysResponse, ReservationResponse,
+        ReservationType,
+    };
+    use crate::{BufferPullable, BufferPushable};
+
+    #[test]
+    pub fn test_persistentreserve() {
+        let expected: [u8; 31] = [
+            0x55, 0x53, 0x42, 0x43, 0x00, 0x00, 0x00, 0x00, 0x88, 0x00, 0x00, 0x00, 0x80, 0x00,
+            0x0a, 0x5e, 0x01, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x88, 0x00, 0x00, 0x00, 0x00,
+            0x00, 0x00, 0x00,
+        ];
+        let mut buff = [0; 32];
+        let command =
+            PersistentReserveInCommand::new(PersistentReserveInServiceAction::ReadReservation);
+        let pushed = command.push_to_buffer(&mut buff).unwrap();
+        assert_eq!(pushed, 25);
+        assert_eq!(&buff[0..pushed], &expected[0..pushed]);
+        assert_eq!(
+            PersistentReserveInCommand::pull_from_buffer(buff).unwrap(),
+            command
+        );
+
+        let expected: [u8; 31] = [
+            0x55, 0x53, 0x42, 0x43, 0x00, 0x00, 0x00, 0x00, 0x18, 0x00, 0x00, 0x00, 0x00, 0x00,
+            0x0a, 0x5f, 0x04, 0x03, 0x00, 0x00, 0x00, 0x00, 0x00, 0x18, 0x00, 0x00, 0x00, 0x00,
+            0x00, 0x00, 0x00,
+        ];
+        let command = PersistentReserveOutCommand::new(
+            PersistentReserveOutServiceAction::Preempt,
+            Some(ReservationType::ExclusiveAccess),
+        );
+        let pushed = command.push_to_buffer(&mut buff).unwrap();
+        assert_eq!(pushed, 25);
+        assert_eq!(&buff[0..pushed], &expected[0..pushed]);
+        assert_eq!(
+            PersistentReserveOutCommand::pull_from_buffer(buff).unwrap(),
+            command
+        );
+
+        let mut parameters = PersistentReserveOutParameters::new(0x1122, 0x3344);
+        parameters.persist_through_power_loss = true;
+        let mut list = [0xff; PersistentReserveOutParameters::SIZE];
+        assert_eq!(parameters.push_to_buffer(&mut list[..]).unwrap(), 24);
+        assert_eq!(&list[6..8], &[0x11, 0x22]);
+        assert_eq!(&list[14..16], &[0x33, 0x44]);
+        assert_eq!(list[20], 0x01);
+        assert_eq!(
+            PersistentReserveOutParameters::pull_from_buffer(list).unwrap(),
+            parameters
+        );
+        assert!(PersistentReserveOutParameters::pull_from_buffer(&list[..16]).is_err());
+    }
+
+    #[test]
+    pub fn test_reservation_responses() {
+        let mut buff = [0; ReservationKeysResponse::MAX_SIZE];
+        let mut keys = ReservationKeysResponse {
+            generation: 3,
+            ..Default::default()
+        };
+        keys.push_key(0xaa).unwrap();
+        keys.push_key(0xbb).unwrap();
+        assert_eq!(keys.push_to_buffer(&mut buff[..]).unwrap(), 24);
+        assert_eq!(&buff[..8], &[0, 0, 0, 3, 0, 0, 0, 16]);
+        assert_eq!(
+            ReservationKeysResponse::pull_from_buffer(&buff[..24]).unwrap(),
+            keys
+        );
+        // A truncated response only yields the keys that fit.
+        let truncated = ReservationKeysResponse::pull_from_buffer(&buff[..20]).unwrap();
+        assert_eq!(truncated.keys(), &[0xaa]);
+
+        let response = ReservationResponse {
+            generation: 3,
+            reservation: Some(Reservation {
+                key: 0xaa,
+                reservation_type: ReservationType::WriteExclusiveRegistrantsOnly,
+            }),
+        };
+        assert_eq!(response.push_to_buffer(&mut buff[..]).unwrap(), 24);
+        assert_eq!(buff[21], 0x05);
+        assert_eq!(
+            ReservationResponse::pull_from_buffer(&buff[..24]).unwrap(),
+            response
+        );
+        let response = ReservationResponse {
+            generation: 4,
+            reservation: None,
+        };
+        assert_eq!(response.push_to_buffer(&mut buff[..]).unwrap(), 8);
+        assert_eq!(
+            ReservationResponse::pull_from_buffer(&buff[..8]).unwrap(),
+            response
+        );
+
+        let capabilities = ReservationCapabilitiesResponse {
+            all_target_ports_capable: true,
+            type_mask: Some(
+                ReservationType::WriteExclusive.mask_bit()
+                    | ReservationType::ExclusiveAccessAllRegistrants.mask_bit(),
+            ),
+            ..Default::default()
+        };
+        assert_eq!(capabilities.push_to_buffer(&mut buff[..]).unwrap(), 8);
+        assert_eq!(&buff[..8], &[0, 8, 0x04, 0x80, 0x02, 0x01, 0, 0]);
+        let pulled = ReservationCapabilitiesResponse::pull_from_buffer(&buff[..8]).unwrap();
+        assert_eq!(pulled, capabilities);
+        assert!(pulled.supports(ReservationType::WriteExclusive));
+        assert!(!pulled.supports(ReservationType::ExclusiveAccess));
+    }
+}
@@ -0,0 +1,90 @@
+use crate::error::{ErrorCause, ScsiError};
+use crate::scsi::commands::{Command, CommandBlockWrapper, Direction};
+use crate::traits::{BufferPullable, BufferPushable};
+
+/// Locks or unlocks the removable medium of the device in place.
+///
+/// While removal is prevented, requests to eject the medium should be
+/// rejected by the device.
+#[derive(Clone, Copy, Eq, PartialEq, Debug, Default)]
+pub struct PreventAllowMediumRemovalCommand {
+    /// Whether removal of the medium should be prevented.
+    pub prevent: bool,
+}
+
+impl PreventAllowMediumRemovalCommand {
+    /// Constructs a new `PreventAllowMediumRemovalCommand`.
+    pub fn new(prevent: bool) -> PreventAllowMediumRemovalCommand {
+        PreventAllowMediumRemovalCommand { prevent }
+    }
+}
+
+impl Command for PreventAllowMediumRemovalCommand {
+    fn opcode() -> u8 {
+        0x1e
+    }
+    fn length() -> u8 {
+        0x6
+    }
+    fn wrapper(&self) -> CommandBlockWrapper {
+        CommandBlockWrapper::new(
+            0,
+            Direction::NONE,
+            0,
+            PreventAllowMediumRemovalCommand::length(),
+        )
+    }
+}
+
+impl BufferPushable for PreventAllowMediumRemovalCommand {
+    fn push_to_buffer<B: AsMut<[u8]>>(&self, mut buffer: B) -> Result<usize, ScsiError> {
+        let rval = self.wrapper().push_to_buffer(buffer.as_mut())?;
+        let buffer = &mut buffer.as_mut()[rval..];
+        buffer[0] = PreventAllowMediumRemovalCommand::opcode();
+        buffer[1] = 0;
+        buffer[2] = 0;
+        buffer[3] = 0;
+        buffer[4] = if self.prevent { 1 } else { 0 };
+        buffer[5] = 0;
+        Ok(rval + 6)
+    }
+}
+
+impl BufferPullable for PreventAllowMediumRemovalCommand {
+    fn pull_from_buffer<B: AsRef<[u8]>>(buffer: B) -> Result<Self, ScsiError> {
+        let wrapper: CommandBlockWrapper = CommandBlockWrapper::pull_from_buffer(buffer.as_ref())?;
+        if wrapper.data_transfer_length != 0
+            || wrapper.cb_length != PreventAllowMediumRemovalCommand::length()
+        {
+            return Err(ScsiError::from_cause(ErrorCause::ParseError));
+        }
+        let buffer = &buffer.as_ref()[15..];
+        if buffer[0] != PreventAllowMediumRemovalCommand::opcode() {
+            return Err(ScsiError::from_cause(ErrorCause::ParseError));
+        }
+        Ok(PreventAllowMediumRemovalCommand::new(buffer[4] & 0x3 != 0))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::PreventAllowMediumRemovalCommand;
+    use crate::{BufferPullable, BufferPushable};
+
+    #[test]
+    pub fn test_preventallow() {
+        let expected: [u8; 31] = [
+            0x55, 0x53, 0x42, 0x43, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00,
+            0x06, 0x1e, 0x00, 0x00, 0x00, 0x01, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00,
+            0x00, 0x00, 0x00,
+        ];
+        let mut buff = [0; 32];
+        let command = PreventAllowMediumRemovalCommand::new(true);
+        let pushed = command.push_to_buffer(&mut buff).unwrap();
+        assert_eq!(pushed, 21);
+        assert_eq!(&buff[0..pushed], &expected[0..pushed]);
+
+        let pulled = PreventAllowMediumRemovalCommand::pull_from_buffer(buff).unwrap();
+        assert_eq!(pulled, command);
+    }
+}
@@ -1,7 +1,7 @@
-use scsi::commands::{Command, CommandBlockWrapper, Direction};
+use crate::scsi::commands::{Command, CommandBlockWrapper, Direction};
 
-use error::{ErrorCause, ScsiError};
-use traits::{BufferPullable, BufferPushable};
+use crate::error::{ErrorCause, ScsiError};
+use crate::traits::{BufferPullable, BufferPushable};
 
 use byteorder::{ByteOrder, BE};
 
@@ -34,6 +34,19 @@ pub struct Read10Command {
 }
 
 impl Read10Command {
+    /// The largest number of blocks a single command can transfer.
+    pub const MAX_TRANSFER_BLOCKS: u32 = 0xffff;
+
+    fn checked_transfer_blocks(blocks: u32) -> Result<u16, ScsiError> {
+        if blocks > Read10Command::MAX_TRANSFER_BLOCKS {
+            return Err(ScsiError::from_cause(ErrorCause::TransferTooLargeError {
+                actual: blocks as usize,
+                max: Read10Command::MAX_TRANSFER_BLOCKS as usize,
+            }));
+        }
+        Ok(blocks as u16)
+    }
+
     /// Creates a new Read10 command.
     ///
     /// Executing the created command will attempt to read `transfer_bytes` bytes starting
@@ -42,7 +55,8 @@ impl Read10Command {
     /// # Errors
     /// This function returns an error if either `offset` or `transfer_bytes` are
     /// not an integer multiple of `block_size`, since SCSI cannot address at lower
-    /// than block resolution.
+    /// than block resolution, or if the read is longer than
+    /// `Read10Command::MAX_TRANSFER_BLOCKS` blocks.
     pub fn new(
         offset: u32,
         transfer_bytes: u32,
@@ -56,7 +70,7 @@ impl Read10Command {
                 },
             ));
         } else {
-            (transfer_bytes / block_size) as u16
+            Read10Command::checked_transfer_blocks(transfer_bytes / block_size)?
         };
         if offset % block_size != 0 {
             return Err(ScsiError::from_cause(
@@ -109,7 +123,10 @@ impl BufferPullable for Read10Command {
         Ok(Read10Command {
             block_address,
             transfer_blocks,
-            block_size: wrapper.data_transfer_length / u32::from(transfer_blocks),
+            block_size: wrapper
+                .data_transfer_length
+                .checked_div(u32::from(transfer_blocks))
+                .unwrap_or(0),
         })
     }
 }
@@ -135,7 +152,7 @@ impl Command for Read10Command {
 #[cfg(test)]
 mod tests {
     use super::Read10Command;
-    use crate::{BufferPullable, BufferPushable};
+    use crate::{BufferPullable, BufferPushable, ErrorCause};
 
     #[test]
     pub fn test_read10() {
@@ -149,9 +166,23 @@ mod tests {
         assert_eq!(read_command.transfer_blocks, 1);
         let pushed = read_command.push_to_buffer(&mut buff).unwrap();
         assert_eq!(pushed, 25);
-        assert_eq!(&buff[0..expected.len() as usize], &expected);
+        assert_eq!(&buff[0..expected.len()], &expected);
 
-        let pulled = Read10Command::pull_from_buffer(&mut buff).unwrap();
+        let pulled = Read10Command::pull_from_buffer(buff).unwrap();
         assert_eq!(pulled, read_command);
     }
+
+    #[test]
+    pub fn test_read10_too_large() {
+        let err = Read10Command::new(0, 0x10000 * 512, 512).err().unwrap();
+        assert_eq!(
+            err.cause,
+            ErrorCause::TransferTooLargeError {
+                actual: 0x10000,
+                max: 0xffff,
+            }
+        );
+        let command = Read10Command::new(0, 0xffff * 512, 512).unwrap();
+        assert_eq!(command.transfer_blocks, 0xffff);
+    }
 }
@@ -0,0 +1,331 @@
+use crate::error::{ErrorCause, ScsiError};
+use crate::scsi::commands::{Command, CommandBlockWrapper, Direction};
+use crate::traits::{BufferPullable, BufferPushable};
+
+use byteorder::{ByteOrder, BE};
+
+/// What a `ReadBufferCommand` returns.
+#[derive(Clone, Copy, Eq, PartialEq, Debug, Default)]
+pub enum ReadBufferMode {
+    /// The contents of the buffer, starting at the command's `buffer_offset`.
+    #[default]
+    Data,
+
+    /// A `BufferDescriptor` describing the buffer.
+    Descriptor,
+
+    /// The data most recently written by `WriteBufferMode::EchoBuffer`.
+    EchoBuffer,
+
+    /// An `EchoBufferDescriptor` describing the echo buffer.
+    EchoBufferDescriptor,
+}
+
+impl ReadBufferMode {
+    fn from_code(code: u8) -> Result<ReadBufferMode, ScsiError> {
+        match code {
+            0x02 => Ok(ReadBufferMode::Data),
+            0x03 => Ok(ReadBufferMode::Descriptor),
+            0x0a => Ok(ReadBufferMode::EchoBuffer),
+            0x0b => Ok(ReadBufferMode::EchoBufferDescriptor),
+            _ => Err(ScsiError::from_cause(ErrorCause::ParseError)),
+        }
+    }
+
+    fn code(self) -> u8 {
+        match self {
+            ReadBufferMode::Data => 0x02,
+            ReadBufferMode::Descriptor => 0x03,
+            ReadBufferMode::EchoBuffer => 0x0a,
+            ReadBufferMode::EchoBufferDescriptor => 0x0b,
+        }
+    }
+}
+
+/// Reads data from one of the device's buffers, or a description of it.
+#[derive(Clone, Copy, Eq, PartialEq, Debug, Default)]
+pub struct ReadBufferCommand {
+    /// What the device should return.
+    pub mode: ReadBufferMode,
+
+    /// The buffer to read from; devices with a single buffer use 0.
+    pub buffer_id: u8,
+
+    /// Where the read starts within the buffer, for `ReadBufferMode::Data`.
+    pub buffer_offset: u32,
+
+    /// The largest number of bytes the device may return.
+    pub allocation_length: u32,
+}
+
+impl ReadBufferCommand {
+    /// The largest `buffer_offset` or `allocation_length` a command can carry,
+    /// as both are 24 bit fields.
+    pub const MAX_LENGTH: u32 = 0xff_ffff;
+
+    /// Constructs a command reading up to `allocation_length` bytes from
+    /// buffer `buffer_id` at `buffer_offset`.
+    ///
+    /// # Errors
+    /// Returns a `TransferTooLargeError` if `buffer_offset` or
+    /// `allocation_length` does not fit in its 24 bit field.
+    pub fn new(
+        mode: ReadBufferMode,
+        buffer_id: u8,
+        buffer_offset: u32,
+        allocation_length: u32,
+    ) -> Result<ReadBufferCommand, ScsiError> {
+        let largest = buffer_offset.max(allocation_length);
+        if largest > ReadBufferCommand::MAX_LENGTH {
+            return Err(ScsiError::from_cause(ErrorCause::TransferTooLargeError {
+                actual: largest as usize,
+                max: ReadBufferCommand::MAX_LENGTH as usize,
+            }));
+        }
+        Ok(ReadBufferCommand {
+            mode,
+            buffer_id,
+            buffer_offset,
+            allocation_length,
+        })
+    }
+
+    /// Constructs a command reading up to `length` bytes, where lengths too
+    /// long for the command are rejected like those that overflow its 24 bit
+    /// field.
+    pub(crate) fn with_length(
+        mode: ReadBufferMode,
+        buffer_id: u8,
+        buffer_offset: u32,
+        length: usize,
+    ) -> Result<ReadBufferCommand, ScsiError> {
+        let length = u32::try_from(length).unwrap_or(u32::MAX);
+        ReadBufferCommand::new(mode, buffer_id, buffer_offset, length)
+    }
+}
+
+impl Command for ReadBufferCommand {
+    fn opcode() -> u8 {
+        0x3c
+    }
+    fn length() -> u8 {
+        10
+    }
+    fn wrapper(&self) -> CommandBlockWrapper {
+        CommandBlockWrapper::new(
+            self.allocation_length,
+            Direction::IN,
+            0,
+            ReadBufferCommand::length(),
+        )
+    }
+}
+
+impl BufferPushable for ReadBufferCommand {
+    fn push_to_buffer<B: AsMut<[u8]>>(&self, mut buffer: B) -> Result<usize, ScsiError> {
+        let rval = self.wrapper().push_to_buffer(buffer.as_mut())?;
+        let buffer = &mut buffer.as_mut()[rval..];
+        buffer[0] = ReadBufferCommand::opcode();
+        buffer[1] = self.mode.code();
+        buffer[2] = self.buffer_id;
+        BE::write_u24(&mut buffer[3..], self.buffer_offset);
+        BE::write_u24(&mut buffer[6..], self.allocation_length);
+        buffer[9] = 0;
+        Ok(rval + 10)
+    }
+}
+
+impl BufferPullable for ReadBufferCommand {
+    fn pull_from_buffer<B: AsRef<[u8]>>(buffer: B) -> Result<Self, ScsiError> {
+        let wrapper: CommandBlockWrapper = CommandBlockWrapper::pull_from_buffer(buffer.as_ref())?;
+        if wrapper.cb_length != ReadBufferCommand::length()
+            || (wrapper.data_transfer_length != 0 && wrapper.direction != Direction::IN)
+        {
+            return Err(ScsiError::from_cause(ErrorCause::ParseError));
+        }
+        let buffer = &buffer.as_ref()[15..];
+        if buffer[0] != ReadBufferCommand::opcode() {
+            return Err(ScsiError::from_cause(ErrorCause::ParseError));
+        }
+        Ok(ReadBufferCommand {
+            mode: ReadBufferMode::from_code(buffer[1] & 0x1f)?,
+            buffer_id: buffer[2],
+            buffer_offset: BE::read_u24(&buffer[3..]),
+            allocation_length: BE::read_u24(&buffer[6..]),
+        })
+    }
+}
+
+/// Response to a `ReadBufferCommand` in `ReadBufferMode::Descriptor`, which
+/// describes one of the device's buffers.
+#[derive(Clone, Copy, Eq, PartialEq, Debug, Default)]
+pub struct BufferDescriptor {
+    /// The power of two that buffer offsets must be a multiple of, or `0xff`
+    /// if the buffer only accepts an offset of 0.
+    pub offset_boundary: u8,
+
+    /// The size of the buffer, in bytes.
+    pub capacity: u32,
+}
+
+impl BufferDescriptor {
+    /// The size of the descriptor, in bytes.
+    pub const SIZE: usize = 4;
+
+    /// The number of bytes buffer offsets must be a multiple of, or `None` if
+    /// the buffer only accepts an offset of 0.
+    pub fn offset_alignment(&self) -> Option<u32> {
+        match self.offset_boundary {
+            0xff => None,
+            boundary => 1u32.checked_shl(u32::from(boundary)),
+        }
+    }
+}
+
+impl BufferPushable for BufferDescriptor {
+    fn push_to_buffer<B: AsMut<[u8]>>(&self, mut buffer: B) -> Result<usize, ScsiError> {
+        let buffer = buffer.as_mut();
+        if buffer.len() < BufferDescriptor::SIZE {
+            return Err(ScsiError::from_cause(ErrorCause::BufferTooSmallError {
+                expected: BufferDescriptor::SIZE,
+                actual: buffer.len(),
+            }));
+        }
+        buffer[0] = self.offset_boundary;
+        BE::write_u24(&mut buffer[1..], self.capacity.min(0xff_ffff));
+        Ok(BufferDescriptor::SIZE)
+    }
+}
+
+impl BufferPullable for BufferDescriptor {
+    fn pull_from_buffer<B: AsRef<[u8]>>(buffer: B) -> Result<Self, ScsiError> {
+        let buffer = buffer.as_ref();
+        if buffer.len() < BufferDescriptor::SIZE {
+            return Err(ScsiError::from_cause(ErrorCause::BufferTooSmallError {
+                expected: BufferDescriptor::SIZE,
+                actual: buffer.len(),
+            }));
+        }
+        Ok(BufferDescriptor {
+            offset_boundary: buffer[0],
+            capacity: BE::read_u24(&buffer[1..]),
+        })
+    }
+}
+
+/// Response to a `ReadBufferCommand` in `ReadBufferMode::EchoBufferDescriptor`,
+/// which describes the device's echo buffer.
+#[derive(Clone, Copy, Eq, PartialEq, Debug, Default)]
+pub struct EchoBufferDescriptor {
+    /// Whether the device reports when another initiator has overwritten the
+    /// echo buffer between our write and read.
+    pub overwritten_supported: bool,
+
+    /// The size of the echo buffer, in bytes.
+    pub capacity: u16,
+}
+
+impl EchoBufferDescriptor {
+    /// The size of the descriptor, in bytes.
+    pub const SIZE: usize = 4;
+
+    /// The largest echo buffer capacity the descriptor can report.
+    const MAX_CAPACITY: u16 = 0x1fff;
+}
+
+impl BufferPushable for EchoBufferDescriptor {
+    fn push_to_buffer<B: AsMut<[u8]>>(&self, mut buffer: B) -> Result<usize, ScsiError> {
+        let buffer = buffer.as_mut();
+        if buffer.len() < EchoBufferDescriptor::SIZE {
+            return Err(ScsiError::from_cause(ErrorCause::BufferTooSmallError {
+                expected: EchoBufferDescriptor::SIZE,
+                actual: buffer.len(),
+            }));
+        }
+        buffer[0] = u8::from(self.overwritten_supported);
+        buffer[1] = 0;
+        BE::write_u16(
+            &mut buffer[2..],
+            self.capacity.min(EchoBufferDescriptor::MAX_CAPACITY),
+        );
+        Ok(EchoBufferDescriptor::SIZE)
+    }
+}
+
+impl BufferPullable for EchoBufferDescriptor {
+    fn pull_from_buffer<B: AsRef<[u8]>>(buffer: B) -> Result<Self, ScsiError> {
+        let buffer = buffer.as_ref();
+        if buffer.len() < EchoBufferDescriptor::SIZE {
+            return Err(ScsiError::from_cause(ErrorCause::BufferTooSmallError {
+                expected: EchoBufferDescriptor::SIZE,
+                actual: buffer.len(),
+            }));
+        }
+        Ok(EchoBufferDescriptor {
+            overwritten_supported: buffer[0] & 0x01 != 0,
+            capacity: BE::read_u16(&buffer[2..]) & EchoBufferDescriptor::MAX_CAPACITY,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{BufferDescriptor, EchoBufferDescriptor, ReadBufferCommand, ReadBufferMode};
+    use crate::{BufferPullable, BufferPushable};
+
+    #[test]
+    pub fn test_readbuffer() {
+        let expected: [u8; 25] = [
+            0x55, 0x53, 0x42, 0x43, 0x00, 0x00, 0x00, 0x00, 0x00, 0x02, 0x00, 0x00, 0x80, 0x00,
+            0x0a, 0x3c, 0x02, 0x03, 0x00, 0x04, 0x00, 0x00, 0x02, 0x00, 0x00,
+        ];
+        let mut buff = [0; 32];
+        let command = ReadBufferCommand::new(ReadBufferMode::Data, 3, 0x400, 0x200).unwrap();
+        let pushed = command.push_to_buffer(&mut buff).unwrap();
+        assert_eq!(pushed, 25);
+        assert_eq!(&buff[0..pushed], &expected[..]);
+        assert_eq!(ReadBufferCommand::pull_from_buffer(buff).unwrap(), command);
+
+        let command =
+            ReadBufferCommand::new(ReadBufferMode::EchoBufferDescriptor, 0, 0, 4).unwrap();
+        command.push_to_buffer(&mut buff).unwrap();
+        assert_eq!(buff[16], 0x0b);
+        assert_eq!(ReadBufferCommand::pull_from_buffer(buff).unwrap(), command);
+
+        assert!(ReadBufferCommand::new(ReadBufferMode::Data, 0, 0x100_0000, 0).is_err());
+    }
+
+    #[test]
+    pub fn test_bufferdescriptors() {
+        let mut buff = [0xff; 8];
+        let descriptor = BufferDescriptor {
+            offset_boundary: 9,
+            capacity: 0x10_0000,
+        };
+        assert_eq!(descriptor.push_to_buffer(&mut buff[..]).unwrap(), 4);
+        assert_eq!(&buff[0..4], &[0x09, 0x10, 0x00, 0x00]);
+        assert_eq!(
+            BufferDescriptor::pull_from_buffer(buff).unwrap(),
+            descriptor
+        );
+        assert_eq!(descriptor.offset_alignment(), Some(512));
+        let fixed = BufferDescriptor {
+            offset_boundary: 0xff,
+            capacity: 0,
+        };
+        assert_eq!(fixed.offset_alignment(), None);
+
+        let descriptor = EchoBufferDescriptor {
+            overwritten_supported: true,
+            capacity: 0x100,
+        };
+        buff = [0xff; 8];
+        assert_eq!(descriptor.push_to_buffer(&mut buff[..]).unwrap(), 4);
+        assert_eq!(&buff[0..4], &[0x01, 0x00, 0x01, 0x00]);
+        assert_eq!(
+            EchoBufferDescriptor::pull_from_buffer(buff).unwrap(),
+            descriptor
+        );
+        assert!(EchoBufferDescriptor::pull_from_buffer(&buff[..3]).is_err());
+    }
+}
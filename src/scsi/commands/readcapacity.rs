@@ -1,6 +1,6 @@
-use error::{ErrorCause, ScsiError};
-use scsi::commands::{Command, CommandBlockWrapper, Direction};
-use traits::{BufferPullable, BufferPushable};
+use crate::error::{ErrorCause, ScsiError};
+use crate::scsi::commands::{Command, CommandBlockWrapper, Direction};
+use crate::traits::{BufferPullable, BufferPushable};
 
 use byteorder::{ByteOrder, BE};
 
@@ -84,9 +84,146 @@ impl BufferPushable for ReadCapacityResponse {
     }
 }
 
+/// The 16 byte version of `ReadCapacityCommand`, sent as the READ CAPACITY
+/// service action of SERVICE ACTION IN(16).
+///
+/// Devices with more blocks than `ReadCapacityResponse` can describe report
+/// an address of 0xFFFFFFFF there, and only give their real capacity here.
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+pub struct ReadCapacity16Command {
+    /// The number of bytes the host has room for.
+    pub allocation_length: u32,
+}
+
+impl ReadCapacity16Command {
+    /// The service action of SERVICE ACTION IN(16) that reads the capacity.
+    pub const SERVICE_ACTION: u8 = 0x10;
+
+    /// Constructs a new `ReadCapacity16Command` with room for the full
+    /// response.
+    pub fn new() -> ReadCapacity16Command {
+        ReadCapacity16Command {
+            allocation_length: ReadCapacity16Response::SIZE as u32,
+        }
+    }
+}
+
+impl Default for ReadCapacity16Command {
+    fn default() -> Self {
+        ReadCapacity16Command::new()
+    }
+}
+
+impl Command for ReadCapacity16Command {
+    fn opcode() -> u8 {
+        0x9e
+    }
+    fn length() -> u8 {
+        16
+    }
+    fn wrapper(&self) -> CommandBlockWrapper {
+        CommandBlockWrapper::new(
+            self.allocation_length,
+            Direction::IN,
+            0,
+            ReadCapacity16Command::length(),
+        )
+    }
+}
+
+impl BufferPushable for ReadCapacity16Command {
+    fn push_to_buffer<B: AsMut<[u8]>>(&self, mut buffer: B) -> Result<usize, ScsiError> {
+        let rval = self.wrapper().push_to_buffer(buffer.as_mut())?;
+        let buffer = &mut buffer.as_mut()[rval..];
+        buffer[0] = ReadCapacity16Command::opcode();
+        buffer[1] = ReadCapacity16Command::SERVICE_ACTION;
+        buffer[2..10].fill(0);
+        BE::write_u32(&mut buffer[10..], self.allocation_length);
+        buffer[14] = 0;
+        buffer[15] = 0;
+        Ok(rval + 16)
+    }
+}
+
+impl BufferPullable for ReadCapacity16Command {
+    fn pull_from_buffer<B: AsRef<[u8]>>(buffer: B) -> Result<Self, ScsiError> {
+        let wrapper: CommandBlockWrapper = CommandBlockWrapper::pull_from_buffer(buffer.as_ref())?;
+        let buffer = &buffer.as_ref()[15..];
+        if wrapper.direction != Direction::IN
+            || wrapper.cb_length != ReadCapacity16Command::length()
+            || buffer[0] != ReadCapacity16Command::opcode()
+            || buffer[1] & 0x1f != ReadCapacity16Command::SERVICE_ACTION
+        {
+            return Err(ScsiError::from_cause(ErrorCause::ParseError));
+        }
+        Ok(ReadCapacity16Command {
+            allocation_length: BE::read_u32(&buffer[10..]),
+        })
+    }
+}
+
+/// Response from an executed `ReadCapacity16Command`.
+#[derive(Clone, Copy, Eq, PartialEq, Debug)]
+pub struct ReadCapacity16Response {
+    /// The address of the final block on the disk.
+    pub logical_block_address: u64,
+
+    /// The number of bytes in a single block for this device.
+    pub block_length: u32,
+}
+
+impl ReadCapacity16Response {
+    /// The length of the response; everything past the block length is
+    /// sent as zeros.
+    pub const SIZE: usize = 32;
+}
+
+impl BufferPullable for ReadCapacity16Response {
+    fn pull_from_buffer<B: AsRef<[u8]>>(buffer: B) -> Result<ReadCapacity16Response, ScsiError> {
+        let buffer = buffer.as_ref();
+        if buffer.len() < 12 {
+            return Err(ScsiError::from_cause(ErrorCause::BufferTooSmallError {
+                expected: 12,
+                actual: buffer.len(),
+            }));
+        }
+        Ok(ReadCapacity16Response {
+            logical_block_address: BE::read_u64(buffer),
+            block_length: BE::read_u32(&buffer[8..]),
+        })
+    }
+}
+
+impl BufferPushable for ReadCapacity16Response {
+    fn push_to_buffer<B: AsMut<[u8]>>(&self, mut buffer: B) -> Result<usize, ScsiError> {
+        let buffer = buffer.as_mut();
+        if buffer.len() < ReadCapacity16Response::SIZE {
+            return Err(ScsiError::from_cause(ErrorCause::BufferTooSmallError {
+                expected: ReadCapacity16Response::SIZE,
+                actual: buffer.len(),
+            }));
+        }
+        BE::write_u64(&mut buffer[0..], self.logical_block_address);
+        BE::write_u32(&mut buffer[8..], self.block_length);
+        buffer[12..ReadCapacity16Response::SIZE].fill(0);
+        Ok(ReadCapacity16Response::SIZE)
+    }
+}
+
+impl From<ReadCapacityResponse> for ReadCapacity16Response {
+    fn from(response: ReadCapacityResponse) -> ReadCapacity16Response {
+        ReadCapacity16Response {
+            logical_block_address: u64::from(response.logical_block_address),
+            block_length: response.block_length,
+        }
+    }
+}
+
 #[cfg(test)]
 mod tests {
-    use super::{ReadCapacityCommand, ReadCapacityResponse};
+    use super::{
+        ReadCapacity16Command, ReadCapacity16Response, ReadCapacityCommand, ReadCapacityResponse,
+    };
     use crate::{BufferPullable, BufferPushable};
 
     #[test]
@@ -100,9 +237,9 @@ mod tests {
         let read_command = ReadCapacityCommand::new();
         let pushed = read_command.push_to_buffer(&mut buff).unwrap();
         assert_eq!(pushed, 16);
-        assert_eq!(&buff[0..expected.len() as usize], &expected);
+        assert_eq!(&buff[0..expected.len()], &expected);
 
-        let pulled = ReadCapacityCommand::pull_from_buffer(&mut buff).unwrap();
+        let pulled = ReadCapacityCommand::pull_from_buffer(buff).unwrap();
         assert_eq!(pulled, read_command);
     }
     #[test]
@@ -118,9 +255,55 @@ mod tests {
         };
         let pushed = read_response.push_to_buffer(&mut buff).unwrap();
         assert_eq!(pushed, 8);
-        assert_eq!(&buff[0..pushed as usize], &expected[0..pushed as usize]);
+        assert_eq!(&buff[0..pushed], &expected[0..pushed]);
 
-        let pulled = ReadCapacityResponse::pull_from_buffer(&mut buff).unwrap();
+        let pulled = ReadCapacityResponse::pull_from_buffer(buff).unwrap();
         assert_eq!(pulled, read_response);
     }
+
+    #[test]
+    pub fn test_readcapacity16command() {
+        let expected: [u8; 31] = [
+            0x55, 0x53, 0x42, 0x43, 0x00, 0x00, 0x00, 0x00, 0x20, 0x00, 0x00, 0x00, 0x80, 0x00,
+            0x10, 0x9e, 0x10, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00,
+            0x20, 0x00, 0x00,
+        ];
+        let mut buff = [0; 32];
+        let command = ReadCapacity16Command::new();
+        let pushed = command.push_to_buffer(&mut buff).unwrap();
+        assert_eq!(pushed, 31);
+        assert_eq!(&buff[0..pushed], &expected[0..pushed]);
+        assert_eq!(
+            ReadCapacity16Command::pull_from_buffer(buff).unwrap(),
+            command
+        );
+
+        // Other service actions of the same opcode are not this command.
+        buff[16] = 0x11;
+        assert!(ReadCapacity16Command::pull_from_buffer(buff).is_err());
+    }
+
+    #[test]
+    pub fn test_readcapacity16response() {
+        let mut buff = [0xff; 32];
+        let response = ReadCapacity16Response {
+            logical_block_address: 0x0001_0000_0000,
+            block_length: 512,
+        };
+        assert_eq!(response.push_to_buffer(&mut buff).unwrap(), 32);
+        assert_eq!(&buff[..12], &[0, 0, 0, 1, 0, 0, 0, 0, 0, 0, 2, 0]);
+        assert!(buff[12..].iter().all(|&b| b == 0));
+        assert_eq!(
+            ReadCapacity16Response::pull_from_buffer(buff).unwrap(),
+            response
+        );
+        assert!(response.push_to_buffer(&mut buff[..31]).is_err());
+
+        let narrow = ReadCapacityResponse {
+            logical_block_address: 1023,
+            block_length: 256,
+        };
+        let wide = ReadCapacity16Response::from(narrow);
+        assert_eq!((wide.logical_block_address, wide.block_length), (1023, 256));
+    }
 }
@@ -0,0 +1,248 @@
+use crate::error::{ErrorCause, ScsiError};
+use crate::scsi::commands::{Command, CommandBlockWrapper, Direction};
+use crate::traits::{BufferPullable, BufferPushable};
+use byteorder::{ByteOrder, BE};
+
+/// Reads general information about the medium in an optical drive
+/// (READ DISC INFORMATION).
+#[derive(Clone, Copy, Eq, PartialEq, Debug)]
+pub struct ReadDiscInformationCommand {
+    /// Which kind of disc information to return; 0 requests the standard
+    /// disc information block.
+    pub data_type: u8,
+
+    /// The maximum number of bytes the host is willing to receive.
+    pub allocation_length: u16,
+}
+
+impl ReadDiscInformationCommand {
+    /// Constructs a new `ReadDiscInformationCommand` for the standard disc
+    /// information block.
+    pub fn new(allocation_length: u16) -> ReadDiscInformationCommand {
+        ReadDiscInformationCommand {
+            data_type: 0,
+            allocation_length,
+        }
+    }
+}
+
+impl Default for ReadDiscInformationCommand {
+    fn default() -> Self {
+        ReadDiscInformationCommand::new(DiscInformationResponse::SIZE as u16)
+    }
+}
+
+impl Command for ReadDiscInformationCommand {
+    fn opcode() -> u8 {
+        0x51
+    }
+    fn length() -> u8 {
+        10
+    }
+    fn wrapper(&self) -> CommandBlockWrapper {
+        CommandBlockWrapper::new(
+            u32::from(self.allocation_length),
+            Direction::IN,
+            0,
+            ReadDiscInformationCommand::length(),
+        )
+    }
+}
+
+impl BufferPushable for ReadDiscInformationCommand {
+    fn push_to_buffer<B: AsMut<[u8]>>(&self, mut buffer: B) -> Result<usize, ScsiError> {
+        let rval = self.wrapper().push_to_buffer(buffer.as_mut())?;
+        let buffer = &mut buffer.as_mut()[rval..];
+        buffer[0] = ReadDiscInformationCommand::opcode();
+        buffer[1] = self.data_type & 0x7;
+        for byte in &mut buffer[2..7] {
+            *byte = 0;
+        }
+        BE::write_u16(&mut buffer[7..], self.allocation_length);
+        buffer[9] = 0;
+        Ok(rval + 10)
+    }
+}
+
+impl BufferPullable for ReadDiscInformationCommand {
+    fn pull_from_buffer<B: AsRef<[u8]>>(buffer: B) -> Result<Self, ScsiError> {
+        let wrapper: CommandBlockWrapper = CommandBlockWrapper::pull_from_buffer(buffer.as_ref())?;
+        if wrapper.direction != Direction::IN
+            || wrapper.cb_length != ReadDiscInformationCommand::length()
+        {
+            return Err(ScsiError::from_cause(ErrorCause::ParseError));
+        }
+        let buffer = &buffer.as_ref()[15..];
+        if buffer[0] != ReadDiscInformationCommand::opcode() {
+            return Err(ScsiError::from_cause(ErrorCause::ParseError));
+        }
+        Ok(ReadDiscInformationCommand {
+            data_type: buffer[1] & 0x7,
+            allocation_length: BE::read_u16(&buffer[7..]),
+        })
+    }
+}
+
+/// The standard disc information block sent in response to a
+/// `ReadDiscInformationCommand`.
+#[derive(Clone, Copy, Eq, PartialEq, Debug)]
+pub struct DiscInformationResponse {
+    /// Whether the medium is rewritable.
+    pub erasable: bool,
+
+    /// The state of the last session; 3 means the session is complete.
+    pub last_session_state: u8,
+
+    /// The state of the whole disc; 2 means the disc is finalized.
+    pub disc_status: u8,
+
+    /// The number of the first track on the disc.
+    pub first_track_number: u8,
+
+    /// The number of sessions on the disc.
+    pub number_of_sessions: u16,
+
+    /// The number of the first track in the last session.
+    pub first_track_in_last_session: u16,
+
+    /// The number of the last track in the last session.
+    pub last_track_in_last_session: u16,
+
+    /// Whether the disc may be used without restrictions.
+    pub unrestricted_use: bool,
+
+    /// The type of the disc, eg 0 for CD-DA or CD-ROM.
+    pub disc_type: u8,
+
+    /// The start of the lead-in of the last session, or `0xFFFFFFFF` if the
+    /// disc is complete.
+    pub last_session_lead_in_start: u32,
+
+    /// The last possible start of the lead-out, or `0xFFFFFFFF` if the disc
+    /// is complete.
+    pub last_possible_lead_out_start: u32,
+}
+
+impl DiscInformationResponse {
+    /// The size of the standard disc information block, in bytes.
+    pub const SIZE: usize = 34;
+
+    /// The `last_session_state` of a closed session.
+    pub const SESSION_COMPLETE: u8 = 0x3;
+
+    /// The `disc_status` of a finalized disc.
+    pub const DISC_FINALIZED: u8 = 0x2;
+}
+
+impl Default for DiscInformationResponse {
+    fn default() -> Self {
+        DiscInformationResponse {
+            erasable: false,
+            last_session_state: DiscInformationResponse::SESSION_COMPLETE,
+            disc_status: DiscInformationResponse::DISC_FINALIZED,
+            first_track_number: 1,
+            number_of_sessions: 1,
+            first_track_in_last_session: 1,
+            last_track_in_last_session: 1,
+            unrestricted_use: true,
+            disc_type: 0,
+            last_session_lead_in_start: 0xffff_ffff,
+            last_possible_lead_out_start: 0xffff_ffff,
+        }
+    }
+}
+
+impl BufferPushable for DiscInformationResponse {
+    fn push_to_buffer<B: AsMut<[u8]>>(&self, mut buffer: B) -> Result<usize, ScsiError> {
+        let buffer = buffer.as_mut();
+        if buffer.len() < DiscInformationResponse::SIZE {
+            return Err(ScsiError::from_cause(ErrorCause::BufferTooSmallError {
+                expected: DiscInformationResponse::SIZE,
+                actual: buffer.len(),
+            }));
+        }
+        for byte in &mut buffer[..DiscInformationResponse::SIZE] {
+            *byte = 0;
+        }
+        BE::write_u16(buffer, (DiscInformationResponse::SIZE - 2) as u16);
+        let erasable_bit = if self.erasable { 0x10 } else { 0 };
+        buffer[2] =
+            erasable_bit | ((self.last_session_state & 0x3) << 2) | (self.disc_status & 0x3);
+        buffer[3] = self.first_track_number;
+        buffer[4] = self.number_of_sessions as u8;
+        buffer[5] = self.first_track_in_last_session as u8;
+        buffer[6] = self.last_track_in_last_session as u8;
+        buffer[7] = if self.unrestricted_use { 0x20 } else { 0 };
+        buffer[8] = self.disc_type;
+        buffer[9] = (self.number_of_sessions >> 8) as u8;
+        buffer[10] = (self.first_track_in_last_session >> 8) as u8;
+        buffer[11] = (self.last_track_in_last_session >> 8) as u8;
+        BE::write_u32(&mut buffer[16..], self.last_session_lead_in_start);
+        BE::write_u32(&mut buffer[20..], self.last_possible_lead_out_start);
+        Ok(DiscInformationResponse::SIZE)
+    }
+}
+
+impl BufferPullable for DiscInformationResponse {
+    fn pull_from_buffer<B: AsRef<[u8]>>(buffer: B) -> Result<Self, ScsiError> {
+        let buffer = buffer.as_ref();
+        if buffer.len() < 24 {
+            return Err(ScsiError::from_cause(ErrorCause::BufferTooSmallError {
+                expected: 24,
+                actual: buffer.len(),
+            }));
+        }
+        Ok(DiscInformationResponse {
+            erasable: buffer[2] & 0x10 != 0,
+            last_session_state: (buffer[2] >> 2) & 0x3,
+            disc_status: buffer[2] & 0x3,
+            first_track_number: buffer[3],
+            number_of_sessions: (u16::from(buffer[9]) << 8) | u16::from(buffer[4]),
+            first_track_in_last_session: (u16::from(buffer[10]) << 8) | u16::from(buffer[5]),
+            last_track_in_last_session: (u16::from(buffer[11]) << 8) | u16::from(buffer[6]),
+            unrestricted_use: buffer[7] & 0x20 != 0,
+            disc_type: buffer[8],
+            last_session_lead_in_start: BE::read_u32(&buffer[16..]),
+            last_possible_lead_out_start: BE::read_u32(&buffer[20..]),
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{DiscInformationResponse, ReadDiscInformationCommand};
+    use crate::{BufferPullable, BufferPushable};
+
+    #[test]
+    pub fn test_readdiscinformation() {
+        let expected: [u8; 31] = [
+            0x55, 0x53, 0x42, 0x43, 0x00, 0x00, 0x00, 0x00, 0x22, 0x00, 0x00, 0x00, 0x80, 0x00,
+            0x0a, 0x51, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x22, 0x00, 0x00, 0x00, 0x00,
+            0x00, 0x00, 0x00,
+        ];
+        let mut buff = [0; 32];
+        let command = ReadDiscInformationCommand::default();
+        let pushed = command.push_to_buffer(&mut buff).unwrap();
+        assert_eq!(pushed, 25);
+        assert_eq!(&buff[0..pushed], &expected[0..pushed]);
+
+        let pulled = ReadDiscInformationCommand::pull_from_buffer(buff).unwrap();
+        assert_eq!(pulled, command);
+    }
+
+    #[test]
+    pub fn test_discinformationresponse() {
+        let expected: [u8; 12] = [
+            0x00, 0x20, 0x0e, 0x01, 0x01, 0x01, 0x01, 0x20, 0x00, 0x00, 0x00, 0x00,
+        ];
+        let mut buff = [0xaa; 40];
+        let response = DiscInformationResponse::default();
+        let pushed = response.push_to_buffer(&mut buff[..]).unwrap();
+        assert_eq!(pushed, 34);
+        assert_eq!(&buff[0..12], &expected);
+        assert_eq!(&buff[16..24], &[0xff; 8]);
+
+        let pulled = DiscInformationResponse::pull_from_buffer(&buff[..]).unwrap();
+        assert_eq!(pulled, response);
+    }
+}
@@ -0,0 +1,293 @@
+use crate::error::{ErrorCause, ScsiError};
+use crate::scsi::commands::{Command, CommandBlockWrapper, Direction};
+use crate::traits::{BufferPullable, BufferPushable};
+use byteorder::{ByteOrder, BE};
+
+/// Reads the table of contents of an optical medium (READ TOC/PMA/ATIP).
+#[derive(Clone, Copy, Eq, PartialEq, Debug)]
+pub struct ReadTocCommand {
+    /// Whether addresses should be returned in minute/second/frame form
+    /// instead of as logical block addresses.
+    pub msf: bool,
+
+    /// Which table to return; see the `ReadTocCommand::FORMAT_*` constants.
+    pub format: u8,
+
+    /// The first track (or, for `FORMAT_SESSION_INFO`, session) to return.
+    pub track_number: u8,
+
+    /// The maximum number of bytes the host is willing to receive.
+    pub allocation_length: u16,
+}
+
+impl ReadTocCommand {
+    /// Requests the track descriptors of the medium.
+    pub const FORMAT_TOC: u8 = 0x0;
+    /// Requests the first track of the last complete session.
+    pub const FORMAT_SESSION_INFO: u8 = 0x1;
+    /// Requests the raw Q sub-channel data of the lead-in.
+    pub const FORMAT_FULL_TOC: u8 = 0x2;
+
+    /// The track number used to address the lead-out area of the medium.
+    pub const LEAD_OUT_TRACK: u8 = 0xaa;
+
+    /// Constructs a new `ReadTocCommand`.
+    pub fn new(msf: bool, format: u8, track_number: u8, allocation_length: u16) -> ReadTocCommand {
+        ReadTocCommand {
+            msf,
+            format,
+            track_number,
+            allocation_length,
+        }
+    }
+}
+
+impl Default for ReadTocCommand {
+    fn default() -> Self {
+        ReadTocCommand::new(false, ReadTocCommand::FORMAT_TOC, 0, 0xfffe)
+    }
+}
+
+impl Command for ReadTocCommand {
+    fn opcode() -> u8 {
+        0x43
+    }
+    fn length() -> u8 {
+        10
+    }
+    fn wrapper(&self) -> CommandBlockWrapper {
+        CommandBlockWrapper::new(
+            u32::from(self.allocation_length),
+            Direction::IN,
+            0,
+            ReadTocCommand::length(),
+        )
+    }
+}
+
+impl BufferPushable for ReadTocCommand {
+    fn push_to_buffer<B: AsMut<[u8]>>(&self, mut buffer: B) -> Result<usize, ScsiError> {
+        let rval = self.wrapper().push_to_buffer(buffer.as_mut())?;
+        let buffer = &mut buffer.as_mut()[rval..];
+        buffer[0] = ReadTocCommand::opcode();
+        buffer[1] = if self.msf { 0x2 } else { 0 };
+        buffer[2] = self.format & 0xf;
+        buffer[3] = 0;
+        buffer[4] = 0;
+        buffer[5] = 0;
+        buffer[6] = self.track_number;
+        BE::write_u16(&mut buffer[7..], self.allocation_length);
+        buffer[9] = 0;
+        Ok(rval + 10)
+    }
+}
+
+impl BufferPullable for ReadTocCommand {
+    fn pull_from_buffer<B: AsRef<[u8]>>(buffer: B) -> Result<Self, ScsiError> {
+        let wrapper: CommandBlockWrapper = CommandBlockWrapper::pull_from_buffer(buffer.as_ref())?;
+        if wrapper.direction != Direction::IN || wrapper.cb_length != ReadTocCommand::length() {
+            return Err(ScsiError::from_cause(ErrorCause::ParseError));
+        }
+        let buffer = &buffer.as_ref()[15..];
+        if buffer[0] != ReadTocCommand::opcode() {
+            return Err(ScsiError::from_cause(ErrorCause::ParseError));
+        }
+        Ok(ReadTocCommand {
+            msf: buffer[1] & 0x2 != 0,
+            format: buffer[2] & 0xf,
+            track_number: buffer[6],
+            allocation_length: BE::read_u16(&buffer[7..]),
+        })
+    }
+}
+
+/// Converts a logical block address into the `0x00MMSSFF` minute/second/frame
+/// form used by optical media, including the standard 2 second pre-gap.
+/// Addresses past the end of the MSF range are clamped to 255 minutes.
+pub fn lba_to_msf(lba: u32) -> u32 {
+    let frames = lba.saturating_add(150);
+    let minutes = frames / (75 * 60);
+    let seconds = (frames / 75) % 60;
+    let frame = frames % 75;
+    (minutes.min(0xff) << 16) | (seconds << 8) | frame
+}
+
+/// A single track entry in a `TocResponse`.
+#[derive(Clone, Copy, Eq, PartialEq, Debug, Default)]
+pub struct TocTrackDescriptor {
+    /// The ADR field in the high nibble and the CONTROL field in the low
+    /// nibble; a data track is usually `0x14`.
+    pub adr_control: u8,
+
+    /// The number of the track, or `ReadTocCommand::LEAD_OUT_TRACK`.
+    pub track_number: u8,
+
+    /// The start of the track, either as an LBA or in the form returned by
+    /// `lba_to_msf`, depending on the command's `msf` flag.
+    pub start_address: u32,
+}
+
+impl TocTrackDescriptor {
+    /// The size of a single descriptor on the wire.
+    pub const SIZE: usize = 8;
+}
+
+/// The data sent in response to a `ReadTocCommand` using either the
+/// `FORMAT_TOC` or `FORMAT_SESSION_INFO` formats.
+#[derive(Clone, Copy, Eq, PartialEq, Debug, Default)]
+pub struct TocResponse {
+    /// The first track (or session) number on the medium.
+    pub first_track_number: u8,
+
+    /// The last track (or session) number on the medium.
+    pub last_track_number: u8,
+
+    /// The track descriptors; only the first `descriptor_count` are valid.
+    pub descriptors: [TocTrackDescriptor; TocResponse::MAX_DESCRIPTORS],
+
+    /// The number of valid entries in `descriptors`.
+    pub descriptor_count: usize,
+}
+
+impl TocResponse {
+    /// The maximum number of descriptors a `TocResponse` can carry.
+    pub const MAX_DESCRIPTORS: usize = 4;
+
+    /// Appends a descriptor to the response, failing if it is already full.
+    pub fn push_descriptor(&mut self, descriptor: TocTrackDescriptor) -> Result<(), ScsiError> {
+        if self.descriptor_count >= TocResponse::MAX_DESCRIPTORS {
+            return Err(ScsiError::from_cause(ErrorCause::BufferTooSmallError {
+                expected: self.descriptor_count + 1,
+                actual: TocResponse::MAX_DESCRIPTORS,
+            }));
+        }
+        self.descriptors[self.descriptor_count] = descriptor;
+        self.descriptor_count += 1;
+        Ok(())
+    }
+}
+
+impl BufferPushable for TocResponse {
+    fn push_to_buffer<B: AsMut<[u8]>>(&self, mut buffer: B) -> Result<usize, ScsiError> {
+        let buffer = buffer.as_mut();
+        let total = 4 + self.descriptor_count * TocTrackDescriptor::SIZE;
+        if buffer.len() < total {
+            return Err(ScsiError::from_cause(ErrorCause::BufferTooSmallError {
+                expected: total,
+                actual: buffer.len(),
+            }));
+        }
+        BE::write_u16(buffer, (total - 2) as u16);
+        buffer[2] = self.first_track_number;
+        buffer[3] = self.last_track_number;
+        for (idx, descriptor) in self.descriptors[..self.descriptor_count].iter().enumerate() {
+            let cur = &mut buffer[4 + idx * TocTrackDescriptor::SIZE..];
+            cur[0] = 0;
+            cur[1] = descriptor.adr_control;
+            cur[2] = descriptor.track_number;
+            cur[3] = 0;
+            BE::write_u32(&mut cur[4..], descriptor.start_address);
+        }
+        Ok(total)
+    }
+}
+
+impl BufferPullable for TocResponse {
+    fn pull_from_buffer<B: AsRef<[u8]>>(buffer: B) -> Result<Self, ScsiError> {
+        let buffer = buffer.as_ref();
+        if buffer.len() < 4 {
+            return Err(ScsiError::from_cause(ErrorCause::BufferTooSmallError {
+                expected: 4,
+                actual: buffer.len(),
+            }));
+        }
+        let data_length = BE::read_u16(buffer) as usize + 2;
+        let available = data_length.min(buffer.len());
+        let mut response = TocResponse {
+            first_track_number: buffer[2],
+            last_track_number: buffer[3],
+            ..Default::default()
+        };
+        let mut cur = 4;
+        while cur + TocTrackDescriptor::SIZE <= available
+            && response.descriptor_count < TocResponse::MAX_DESCRIPTORS
+        {
+            response.push_descriptor(TocTrackDescriptor {
+                adr_control: buffer[cur + 1],
+                track_number: buffer[cur + 2],
+                start_address: BE::read_u32(&buffer[cur + 4..]),
+            })?;
+            cur += TocTrackDescriptor::SIZE;
+        }
+        Ok(response)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{lba_to_msf, ReadTocCommand, TocResponse, TocTrackDescriptor};
+    use crate::{BufferPullable, BufferPushable};
+
+    #[test]
+    pub fn test_readtoc() {
+        let expected: [u8; 31] = [
+            0x55, 0x53, 0x42, 0x43, 0x00, 0x00, 0x00, 0x00, 0x14, 0x00, 0x00, 0x00, 0x80, 0x00,
+            0x0a, 0x43, 0x02, 0x01, 0x00, 0x00, 0x00, 0xaa, 0x00, 0x14, 0x00, 0x00, 0x00, 0x00,
+            0x00, 0x00, 0x00,
+        ];
+        let mut buff = [0; 32];
+        let command = ReadTocCommand::new(
+            true,
+            ReadTocCommand::FORMAT_SESSION_INFO,
+            ReadTocCommand::LEAD_OUT_TRACK,
+            0x14,
+        );
+        let pushed = command.push_to_buffer(&mut buff).unwrap();
+        assert_eq!(pushed, 25);
+        assert_eq!(&buff[0..pushed], &expected[0..pushed]);
+
+        let pulled = ReadTocCommand::pull_from_buffer(buff).unwrap();
+        assert_eq!(pulled, command);
+    }
+
+    #[test]
+    pub fn test_tocresponse() {
+        let expected: [u8; 20] = [
+            0x00, 0x12, 0x01, 0x01, 0x00, 0x14, 0x01, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x14,
+            0xaa, 0x00, 0x00, 0x00, 0x12, 0x34,
+        ];
+        let mut buff = [0; 32];
+        let mut response = TocResponse {
+            first_track_number: 1,
+            last_track_number: 1,
+            ..Default::default()
+        };
+        response
+            .push_descriptor(TocTrackDescriptor {
+                adr_control: 0x14,
+                track_number: 1,
+                start_address: 0,
+            })
+            .unwrap();
+        response
+            .push_descriptor(TocTrackDescriptor {
+                adr_control: 0x14,
+                track_number: ReadTocCommand::LEAD_OUT_TRACK,
+                start_address: 0x1234,
+            })
+            .unwrap();
+        let pushed = response.push_to_buffer(&mut buff).unwrap();
+        assert_eq!(pushed, 20);
+        assert_eq!(&buff[0..pushed], &expected);
+
+        let pulled = TocResponse::pull_from_buffer(buff).unwrap();
+        assert_eq!(pulled, response);
+    }
+
+    #[test]
+    pub fn test_lba_to_msf() {
+        assert_eq!(lba_to_msf(0), 0x00_02_00);
+        assert_eq!(lba_to_msf(75 * 60 - 150 + 76), 0x01_01_01);
+        assert_eq!(lba_to_msf(u32::MAX) >> 16, 0xff);
+    }
+}
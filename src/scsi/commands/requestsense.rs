@@ -1,12 +1,12 @@
-use error::{ErrorCause, ScsiError};
-use scsi::commands::{Command, CommandBlockWrapper, Direction};
-use traits::{BufferPullable, BufferPushable};
+use crate::error::{ErrorCause, ScsiError};
+use crate::scsi::commands::{Command, CommandBlockWrapper, Direction};
+use crate::traits::{BufferPullable, BufferPushable};
+use byteorder::{ByteOrder, BE};
 
 /// Requests "sense"-style status information about the device.
 ///
-/// Currently the no `RequestSenseResponse` is implemented; therefore,
-/// while this command can technically be sent successfully, little meaningful
-/// use can be derived from this struct.
+/// The device responds with a `RequestSenseResponse` describing why the
+/// previous command failed, if it did.
 #[derive(Clone, Copy, Eq, PartialEq, Debug)]
 pub struct RequestSenseCommand {
     allocation_length: u8,
@@ -18,12 +18,17 @@ impl RequestSenseCommand {
     pub fn new(allocation_length: u8) -> Self {
         RequestSenseCommand { allocation_length }
     }
+
+    /// The maximum number of sense bytes the host is willing to receive.
+    pub fn allocation_length(&self) -> u8 {
+        self.allocation_length
+    }
 }
 
 impl Default for RequestSenseCommand {
     fn default() -> Self {
         // According to the spec teh default is 252 so all of the sense information
-        // can be returned in a single transfer. 
+        // can be returned in a single transfer.
         RequestSenseCommand::new(252)
     }
 }
@@ -36,7 +41,12 @@ impl Command for RequestSenseCommand {
         0x6
     }
     fn wrapper(&self) -> CommandBlockWrapper {
-        CommandBlockWrapper::new(u32::from(self.allocation_length), Direction::IN, 0, RequestSenseCommand::length())
+        CommandBlockWrapper::new(
+            u32::from(self.allocation_length),
+            Direction::IN,
+            0,
+            RequestSenseCommand::length(),
+        )
     }
 }
 
@@ -56,9 +66,8 @@ impl BufferPushable for RequestSenseCommand {
 impl BufferPullable for RequestSenseCommand {
     fn pull_from_buffer<B: AsRef<[u8]>>(buffer: B) -> Result<Self, ScsiError> {
         let wrapper: CommandBlockWrapper = CommandBlockWrapper::pull_from_buffer(buffer.as_ref())?;
-        if wrapper.data_transfer_length != 0
-            || !(wrapper.direction == Direction::NONE || wrapper.direction == Direction::OUT)
-            || wrapper.cb_length != RequestSenseCommand::length()
+        if wrapper.cb_length != RequestSenseCommand::length()
+            || (wrapper.data_transfer_length != 0 && wrapper.direction != Direction::IN)
         {
             return Err(ScsiError::from_cause(ErrorCause::ParseError));
         }
@@ -72,15 +81,223 @@ impl BufferPullable for RequestSenseCommand {
     }
 }
 
+/// The fixed-format sense data returned in response to a `RequestSenseCommand`.
+///
+/// Sense data describes the reason the previous command ended with a failed
+/// status; a device that has nothing to report returns a response with a
+/// `sense_key` of `NO_SENSE`.
+#[derive(Clone, Copy, Eq, PartialEq, Debug)]
+pub struct RequestSenseResponse {
+    /// Whether or not the `information` field contains meaningful data.
+    pub valid: bool,
+
+    /// The format of the sense data; `0x70` for errors on the current command
+    /// and `0x71` for deferred errors.
+    ///
+    /// Descriptor-format sense data (`0x72` and `0x73`) can be parsed, but
+    /// only the sense key and additional sense codes are extracted from it.
+    pub response_code: u8,
+
+    /// The general category of the error; see the `RequestSenseResponse::*`
+    /// constants for the known values.
+    pub sense_key: u8,
+
+    /// Command-dependent information, such as the first failing LBA of a read.
+    pub information: u32,
+
+    /// Additional command-dependent information.
+    pub command_specific_information: u32,
+
+    /// The specific error within the category given by `sense_key`.
+    pub additional_sense_code: u8,
+
+    /// Further detail about `additional_sense_code`.
+    pub additional_sense_code_qualifier: u8,
+
+    /// The raw sense-key-specific bytes; the most significant bit of the first
+    /// byte marks them as valid.
+    pub sense_key_specific: [u8; 3],
+}
+
+impl RequestSenseResponse {
+    /// There is no error to report.
+    pub const NO_SENSE: u8 = 0x0;
+    /// The command succeeded after some recovery action by the device.
+    pub const RECOVERED_ERROR: u8 = 0x1;
+    /// The logical unit cannot be accessed right now, eg because there is no medium.
+    pub const NOT_READY: u8 = 0x2;
+    /// The command failed due to a flaw in the medium or its data.
+    pub const MEDIUM_ERROR: u8 = 0x3;
+    /// The device encountered a non-recoverable hardware failure.
+    pub const HARDWARE_ERROR: u8 = 0x4;
+    /// The command or its parameters were invalid.
+    pub const ILLEGAL_REQUEST: u8 = 0x5;
+    /// The device was reset or its medium may have changed.
+    pub const UNIT_ATTENTION: u8 = 0x6;
+    /// The medium is write protected.
+    pub const DATA_PROTECT: u8 = 0x7;
+    /// The command was aborted by the device.
+    pub const ABORTED_COMMAND: u8 = 0xB;
+    /// The source data did not match the data read from the medium.
+    pub const MISCOMPARE: u8 = 0xE;
+
+    /// The size of a fixed-format sense data response, in bytes.
+    pub const SIZE: usize = 18;
+
+    /// Constructs a new, current-error `RequestSenseResponse` with the given
+    /// sense key and additional sense code and qualifier.
+    pub fn new(sense_key: u8, asc: u8, ascq: u8) -> RequestSenseResponse {
+        RequestSenseResponse {
+            sense_key,
+            additional_sense_code: asc,
+            additional_sense_code_qualifier: ascq,
+            ..Default::default()
+        }
+    }
+
+    /// Constructs the sense data a device reports while it is formatting its
+    /// medium, `progress` being the fraction of the format done so far out of
+    /// 65536.
+    pub fn format_in_progress(progress: u16) -> RequestSenseResponse {
+        let [high, low] = progress.to_be_bytes();
+        RequestSenseResponse {
+            sense_key_specific: [0x80, high, low],
+            ..RequestSenseResponse::new(RequestSenseResponse::NOT_READY, 0x04, 0x04)
+        }
+    }
+
+    /// Constructs the sense data a device reports while it is sanitizing its
+    /// medium, `progress` being the fraction of the sanitize done so far out
+    /// of 65536.
+    pub fn sanitize_in_progress(progress: u16) -> RequestSenseResponse {
+        RequestSenseResponse {
+            additional_sense_code_qualifier: 0x1b,
+            ..RequestSenseResponse::format_in_progress(progress)
+        }
+    }
+
+    /// Whether the device reported that it is busy sanitizing its medium.
+    pub fn is_sanitize_in_progress(&self) -> bool {
+        self.sense_key == RequestSenseResponse::NOT_READY
+            && self.additional_sense_code == 0x04
+            && self.additional_sense_code_qualifier == 0x1b
+    }
+
+    /// Whether the device reported that it is busy formatting its medium.
+    pub fn is_format_in_progress(&self) -> bool {
+        self.sense_key == RequestSenseResponse::NOT_READY
+            && self.additional_sense_code == 0x04
+            && self.additional_sense_code_qualifier == 0x04
+    }
+
+    /// The progress indication in the sense-key-specific bytes, as a fraction
+    /// out of 65536, if the device included one.
+    ///
+    /// Devices report progress this way while a long-running operation such as
+    /// a format is underway, using the `NOT_READY` or `NO_SENSE` sense keys.
+    pub fn progress(&self) -> Option<u16> {
+        let reports_progress = self.sense_key == RequestSenseResponse::NOT_READY
+            || self.sense_key == RequestSenseResponse::NO_SENSE;
+        if !reports_progress || self.sense_key_specific[0] & 0x80 == 0 {
+            return None;
+        }
+        Some(BE::read_u16(&self.sense_key_specific[1..]))
+    }
+}
+
+impl Default for RequestSenseResponse {
+    fn default() -> Self {
+        RequestSenseResponse {
+            valid: false,
+            response_code: 0x70,
+            sense_key: RequestSenseResponse::NO_SENSE,
+            information: 0,
+            command_specific_information: 0,
+            additional_sense_code: 0,
+            additional_sense_code_qualifier: 0,
+            sense_key_specific: [0; 3],
+        }
+    }
+}
+
+impl BufferPushable for RequestSenseResponse {
+    fn push_to_buffer<B: AsMut<[u8]>>(&self, mut buffer: B) -> Result<usize, ScsiError> {
+        let buffer = buffer.as_mut();
+        if buffer.len() < RequestSenseResponse::SIZE {
+            return Err(ScsiError::from_cause(ErrorCause::BufferTooSmallError {
+                expected: RequestSenseResponse::SIZE,
+                actual: buffer.len(),
+            }));
+        }
+        let valid_bit = if self.valid { 0x80 } else { 0 };
+        buffer[0] = valid_bit | (self.response_code & 0x7f);
+        buffer[1] = 0;
+        buffer[2] = self.sense_key & 0xf;
+        BE::write_u32(&mut buffer[3..], self.information);
+        buffer[7] = (RequestSenseResponse::SIZE - 8) as u8;
+        BE::write_u32(&mut buffer[8..], self.command_specific_information);
+        buffer[12] = self.additional_sense_code;
+        buffer[13] = self.additional_sense_code_qualifier;
+        buffer[14] = 0;
+        buffer[15..18].copy_from_slice(&self.sense_key_specific);
+        Ok(RequestSenseResponse::SIZE)
+    }
+}
+
+impl BufferPullable for RequestSenseResponse {
+    fn pull_from_buffer<B: AsRef<[u8]>>(buffer: B) -> Result<Self, ScsiError> {
+        let buffer = buffer.as_ref();
+        if buffer.len() < 4 {
+            return Err(ScsiError::from_cause(ErrorCause::BufferTooSmallError {
+                expected: 4,
+                actual: buffer.len(),
+            }));
+        }
+        let response_code = buffer[0] & 0x7f;
+        match response_code {
+            0x70 | 0x71 => {
+                if buffer.len() < 14 {
+                    return Err(ScsiError::from_cause(ErrorCause::BufferTooSmallError {
+                        expected: 14,
+                        actual: buffer.len(),
+                    }));
+                }
+                let mut sense_key_specific = [0; 3];
+                if buffer.len() >= RequestSenseResponse::SIZE {
+                    sense_key_specific.copy_from_slice(&buffer[15..18]);
+                }
+                Ok(RequestSenseResponse {
+                    valid: buffer[0] & 0x80 != 0,
+                    response_code,
+                    sense_key: buffer[2] & 0xf,
+                    information: BE::read_u32(&buffer[3..]),
+                    command_specific_information: BE::read_u32(&buffer[8..]),
+                    additional_sense_code: buffer[12],
+                    additional_sense_code_qualifier: buffer[13],
+                    sense_key_specific,
+                })
+            }
+            0x72 | 0x73 => Ok(RequestSenseResponse {
+                response_code,
+                sense_key: buffer[1] & 0xf,
+                additional_sense_code: buffer[2],
+                additional_sense_code_qualifier: buffer[3],
+                ..Default::default()
+            }),
+            _ => Err(ScsiError::from_cause(ErrorCause::ParseError)),
+        }
+    }
+}
+
 #[cfg(test)]
 mod tests {
-    use super::RequestSenseCommand;
+    use super::{RequestSenseCommand, RequestSenseResponse};
     use crate::{BufferPullable, BufferPushable};
 
     #[test]
     pub fn test_requestsense() {
         let expected: [u8; 31] = [
-            0x55, 0x53, 0x42, 0x43, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x0, 0x00,
+            0x55, 0x53, 0x42, 0x43, 0x00, 0x00, 0x00, 0x00, 0x0A, 0x00, 0x00, 0x00, 0x80, 0x00,
             0x06, 0x03, 0x00, 0x00, 0x00, 0x0A, 0x0, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00,
             0x00, 0x00, 0x00,
         ];
@@ -90,7 +307,43 @@ mod tests {
         assert_eq!(pushed, 20);
         assert_eq!(&buff[0..pushed], &expected[0..pushed]);
 
-        let pulled = RequestSenseCommand::pull_from_buffer(&mut buff).unwrap();
+        let pulled = RequestSenseCommand::pull_from_buffer(buff).unwrap();
         assert_eq!(pulled, tur_command);
     }
+
+    #[test]
+    pub fn test_requestsenseresponse() {
+        let expected: [u8; 18] = [
+            0xf0, 0x00, 0x02, 0x12, 0x34, 0x56, 0x78, 0x0a, 0x00, 0x00, 0x00, 0x00, 0x3a, 0x01,
+            0x00, 0x80, 0x00, 0x00,
+        ];
+        let mut buff = [0; 32];
+        let response = RequestSenseResponse {
+            valid: true,
+            information: 0x1234_5678,
+            sense_key_specific: [0x80, 0, 0],
+            ..RequestSenseResponse::new(RequestSenseResponse::NOT_READY, 0x3a, 0x01)
+        };
+        let pushed = response.push_to_buffer(&mut buff).unwrap();
+        assert_eq!(pushed, 18);
+        assert_eq!(&buff[0..pushed], &expected);
+
+        let pulled = RequestSenseResponse::pull_from_buffer(buff).unwrap();
+        assert_eq!(pulled, response);
+        assert_eq!(pulled.progress(), Some(0));
+        assert!(!pulled.is_format_in_progress());
+
+        let formatting = RequestSenseResponse::format_in_progress(0x4000);
+        formatting.push_to_buffer(&mut buff).unwrap();
+        assert_eq!(&buff[12..18], &[0x04, 0x04, 0x00, 0x80, 0x40, 0x00]);
+        let pulled = RequestSenseResponse::pull_from_buffer(buff).unwrap();
+        assert!(pulled.is_format_in_progress());
+        assert_eq!(pulled.progress(), Some(0x4000));
+        assert_eq!(RequestSenseResponse::default().progress(), None);
+
+        let sanitizing = RequestSenseResponse::sanitize_in_progress(0x100);
+        assert!(sanitizing.is_sanitize_in_progress());
+        assert!(!sanitizing.is_format_in_progress());
+        assert_eq!(sanitizing.progress(), Some(0x100));
+    }
 }
@@ -0,0 +1,142 @@
+use crate::error::{ErrorCause, ScsiError};
+use crate::scsi::commands::{Command, CommandBlockWrapper, Direction};
+use crate::traits::{BufferPullable, BufferPushable};
+
+/// Reserves the whole logical unit for the initiator sending the command,
+/// using the reservation model that predates persistent reservations.
+///
+/// While the reservation is held, commands from other initiators that touch
+/// the medium fail with a RESERVATION CONFLICT status. The reservation is lost
+/// when the device is reset or powered off, and it cannot be taken while any
+/// initiator holds a persistent reservation registration.
+#[derive(Clone, Copy, Eq, PartialEq, Debug, Default)]
+pub struct Reserve6Command {}
+
+impl Reserve6Command {
+    /// Constructs a new `Reserve6Command`.
+    pub fn new() -> Reserve6Command {
+        Reserve6Command {}
+    }
+}
+
+impl Command for Reserve6Command {
+    fn opcode() -> u8 {
+        0x16
+    }
+    fn length() -> u8 {
+        0x6
+    }
+    fn wrapper(&self) -> CommandBlockWrapper {
+        CommandBlockWrapper::new(0, Direction::NONE, 0, Reserve6Command::length())
+    }
+}
+
+impl BufferPushable for Reserve6Command {
+    fn push_to_buffer<B: AsMut<[u8]>>(&self, mut buffer: B) -> Result<usize, ScsiError> {
+        let rval = self.wrapper().push_to_buffer(buffer.as_mut())?;
+        push_command_block(&mut buffer.as_mut()[rval..], Reserve6Command::opcode());
+        Ok(rval + 6)
+    }
+}
+
+impl BufferPullable for Reserve6Command {
+    fn pull_from_buffer<B: AsRef<[u8]>>(buffer: B) -> Result<Self, ScsiError> {
+        pull_command_block(
+            buffer.as_ref(),
+            Reserve6Command::opcode(),
+            Reserve6Command::length(),
+        )?;
+        Ok(Reserve6Command::new())
+    }
+}
+
+/// Releases a reservation taken with `Reserve6Command`.
+///
+/// Releasing a logical unit the initiator has not reserved succeeds without
+/// doing anything.
+#[derive(Clone, Copy, Eq, PartialEq, Debug, Default)]
+pub struct Release6Command {}
+
+impl Release6Command {
+    /// Constructs a new `Release6Command`.
+    pub fn new() -> Release6Command {
+        Release6Command {}
+    }
+}
+
+impl Command for Release6Command {
+    fn opcode() -> u8 {
+        0x17
+    }
+    fn length() -> u8 {
+        0x6
+    }
+    fn wrapper(&self) -> CommandBlockWrapper {
+        CommandBlockWrapper::new(0, Direction::NONE, 0, Release6Command::length())
+    }
+}
+
+impl BufferPushable for Release6Command {
+    fn push_to_buffer<B: AsMut<[u8]>>(&self, mut buffer: B) -> Result<usize, ScsiError> {
+        let rval = self.wrapper().push_to_buffer(buffer.as_mut())?;
+        push_command_block(&mut buffer.as_mut()[rval..], Release6Command::opcode());
+        Ok(rval + 6)
+    }
+}
+
+impl BufferPullable for Release6Command {
+    fn pull_from_buffer<B: AsRef<[u8]>>(buffer: B) -> Result<Self, ScsiError> {
+        pull_command_block(
+            buffer.as_ref(),
+            Release6Command::opcode(),
+            Release6Command::length(),
+        )?;
+        Ok(Release6Command::new())
+    }
+}
+
+/// Writes a command block that holds nothing but its opcode; the third-party
+/// and extent fields of both commands are obsolete.
+fn push_command_block(buffer: &mut [u8], opcode: u8) {
+    buffer[0] = opcode;
+    for byte in &mut buffer[1..6] {
+        *byte = 0;
+    }
+}
+
+fn pull_command_block(buffer: &[u8], opcode: u8, length: u8) -> Result<(), ScsiError> {
+    let wrapper = CommandBlockWrapper::pull_from_buffer(buffer)?;
+    if wrapper.data_transfer_length != 0 || wrapper.cb_length != length {
+        return Err(ScsiError::from_cause(ErrorCause::ParseError));
+    }
+    if buffer[15] != opcode {
+        return Err(ScsiError::from_cause(ErrorCause::ParseError));
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{Release6Command, Reserve6Command};
+    use crate::{BufferPullable, BufferPushable};
+
+    #[test]
+    pub fn test_reserverelease() {
+        let expected: [u8; 21] = [
+            0x55, 0x53, 0x42, 0x43, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00,
+            0x06, 0x16, 0x00, 0x00, 0x00, 0x00, 0x00,
+        ];
+        let mut buff = [0; 32];
+        let command = Reserve6Command::new();
+        let pushed = command.push_to_buffer(&mut buff).unwrap();
+        assert_eq!(pushed, 21);
+        assert_eq!(&buff[0..pushed], &expected[..]);
+        assert_eq!(Reserve6Command::pull_from_buffer(buff).unwrap(), command);
+        assert!(Release6Command::pull_from_buffer(buff).is_err());
+
+        let command = Release6Command::new();
+        command.push_to_buffer(&mut buff).unwrap();
+        assert_eq!(buff[15], 0x17);
+        assert_eq!(Release6Command::pull_from_buffer(buff).unwrap(), command);
+    }
+}
@@ -0,0 +1,364 @@
+use crate::error::{ErrorCause, ScsiError};
+use crate::scsi::commands::{Command, CommandBlockWrapper, Direction};
+use crate::traits::{BufferPullable, BufferPushable};
+
+use byteorder::{ByteOrder, BE};
+
+/// The bit in the second byte of a SANITIZE command asking the device to
+/// report the command's status before it has finished.
+const IMMEDIATE_FLAG: u8 = 0x80;
+
+/// The bit in the second byte of a SANITIZE command allowing a later EXIT
+/// FAILURE MODE to leave the device usable after a failed sanitize.
+const ALLOW_UNRESTRICTED_EXIT_FLAG: u8 = 0x20;
+
+/// The kind of sanitize a `SanitizeCommand` performs.
+#[derive(Clone, Copy, Eq, PartialEq, Debug, Default)]
+pub enum SanitizeServiceAction {
+    /// Overwrite every block with the pattern in the command's
+    /// `OverwriteParameters`.
+    #[default]
+    Overwrite,
+
+    /// Erase every block at the level of the device's medium, such as by
+    /// erasing every flash block.
+    BlockErase,
+
+    /// Make the data on the medium unreadable by changing the keys it was
+    /// encrypted with.
+    CryptographicErase,
+
+    /// Leave the failure mode a device enters after a sanitize fails.
+    ExitFailureMode,
+}
+
+impl SanitizeServiceAction {
+    fn from_code(code: u8) -> Result<SanitizeServiceAction, ScsiError> {
+        match code {
+            0x01 => Ok(SanitizeServiceAction::Overwrite),
+            0x02 => Ok(SanitizeServiceAction::BlockErase),
+            0x03 => Ok(SanitizeServiceAction::CryptographicErase),
+            0x1f => Ok(SanitizeServiceAction::ExitFailureMode),
+            _ => Err(ScsiError::from_cause(ErrorCause::ParseError)),
+        }
+    }
+
+    fn code(self) -> u8 {
+        match self {
+            SanitizeServiceAction::Overwrite => 0x01,
+            SanitizeServiceAction::BlockErase => 0x02,
+            SanitizeServiceAction::CryptographicErase => 0x03,
+            SanitizeServiceAction::ExitFailureMode => 0x1f,
+        }
+    }
+}
+
+/// Asks the device to make all of the data on its medium, including blocks
+/// that are no longer mapped, permanently unrecoverable.
+///
+/// Sanitizing can take a long time. If `immediate` is set, the device reports
+/// the command's status straight away and keeps going in the background;
+/// until it is done, it fails commands that access the medium with a
+/// `NOT_READY` sense key, and REQUEST SENSE reports how far along it is (see
+/// `RequestSenseResponse::progress`). A sanitize that fails leaves the device
+/// in a failure mode until it is retried or left with
+/// `SanitizeServiceAction::ExitFailureMode`.
+#[derive(Clone, Copy, Eq, PartialEq, Debug, Default)]
+pub struct SanitizeCommand {
+    /// The kind of sanitize to perform.
+    pub service_action: SanitizeServiceAction,
+
+    /// Whether the device should report the command's status before it has
+    /// finished sanitizing.
+    pub immediate: bool,
+
+    /// Whether an `ExitFailureMode` should be allowed to leave the device
+    /// usable if this sanitize fails.
+    pub allow_unrestricted_exit: bool,
+
+    /// The length of the parameter list sent along with the command, which
+    /// only overwrites have.
+    pub parameter_list_length: u16,
+}
+
+impl SanitizeCommand {
+    /// Constructs a command performing `service_action`, without a parameter
+    /// list.
+    pub fn new(service_action: SanitizeServiceAction) -> SanitizeCommand {
+        SanitizeCommand {
+            service_action,
+            ..SanitizeCommand::default()
+        }
+    }
+
+    /// Constructs a command overwriting the medium as described by
+    /// `parameters`.
+    pub fn overwrite(parameters: &OverwriteParameters<'_>) -> SanitizeCommand {
+        SanitizeCommand {
+            service_action: SanitizeServiceAction::Overwrite,
+            parameter_list_length: parameters.length() as u16,
+            ..SanitizeCommand::default()
+        }
+    }
+
+    /// The longest overwrite pattern `push_parameter_list` sends, in bytes.
+    pub(crate) const MAX_PATTERN: usize = 512;
+
+    /// The size of the largest parameter list `push_parameter_list` sends.
+    pub(crate) const MAX_PARAMETERS_SIZE: usize =
+        OverwriteParameters::HEADER_SIZE + SanitizeCommand::MAX_PATTERN;
+
+    /// Serializes `parameters` to `buffer`, setting the command's parameter
+    /// list length to match, and returns the parameter list.
+    ///
+    /// # Errors
+    /// Returns a `TransferTooLargeError` if the overwrite pattern is longer
+    /// than `SanitizeCommand::MAX_PATTERN` bytes.
+    pub(crate) fn push_parameter_list<'b>(
+        &mut self,
+        parameters: Option<&OverwriteParameters<'_>>,
+        buffer: &'b mut [u8; SanitizeCommand::MAX_PARAMETERS_SIZE],
+    ) -> Result<&'b [u8], ScsiError> {
+        let Some(parameters) = parameters else {
+            self.parameter_list_length = 0;
+            return Ok(&buffer[..0]);
+        };
+        if parameters.length() > buffer.len() {
+            return Err(ScsiError::from_cause(ErrorCause::TransferTooLargeError {
+                actual: parameters.pattern.len(),
+                max: SanitizeCommand::MAX_PATTERN,
+            }));
+        }
+        let pushed = parameters.push_to_buffer(&mut buffer[..])?;
+        self.parameter_list_length = pushed as u16;
+        Ok(&buffer[..pushed])
+    }
+
+    /// Parses the parameter list sent along with the command, returning
+    /// `None` for service actions other than `Overwrite`.
+    ///
+    /// # Errors
+    /// Returns a `ParseError` if the initialization pattern is empty, or a
+    /// `BufferTooSmallError` if `buffer` does not hold the whole list.
+    pub fn pull_parameter_list<'a>(
+        &self,
+        buffer: &'a [u8],
+    ) -> Result<Option<OverwriteParameters<'a>>, ScsiError> {
+        if self.service_action != SanitizeServiceAction::Overwrite {
+            return Ok(None);
+        }
+        OverwriteParameters::pull_from_slice(buffer).map(Some)
+    }
+}
+
+impl Command for SanitizeCommand {
+    fn opcode() -> u8 {
+        0x48
+    }
+    fn length() -> u8 {
+        10
+    }
+    fn wrapper(&self) -> CommandBlockWrapper {
+        let direction = if self.parameter_list_length == 0 {
+            Direction::NONE
+        } else {
+            Direction::OUT
+        };
+        CommandBlockWrapper::new(
+            u32::from(self.parameter_list_length),
+            direction,
+            0,
+            SanitizeCommand::length(),
+        )
+    }
+}
+
+impl BufferPushable for SanitizeCommand {
+    fn push_to_buffer<B: AsMut<[u8]>>(&self, mut buffer: B) -> Result<usize, ScsiError> {
+        let rval = self.wrapper().push_to_buffer(buffer.as_mut())?;
+        let buffer = &mut buffer.as_mut()[rval..];
+        buffer[0] = SanitizeCommand::opcode();
+        buffer[1] = self.service_action.code();
+        if self.immediate {
+            buffer[1] |= IMMEDIATE_FLAG;
+        }
+        if self.allow_unrestricted_exit {
+            buffer[1] |= ALLOW_UNRESTRICTED_EXIT_FLAG;
+        }
+        for byte in &mut buffer[2..7] {
+            *byte = 0;
+        }
+        BE::write_u16(&mut buffer[7..], self.parameter_list_length);
+        buffer[9] = 0;
+        Ok(rval + 10)
+    }
+}
+
+impl BufferPullable for SanitizeCommand {
+    fn pull_from_buffer<B: AsRef<[u8]>>(buffer: B) -> Result<Self, ScsiError> {
+        let wrapper: CommandBlockWrapper = CommandBlockWrapper::pull_from_buffer(buffer.as_ref())?;
+        if wrapper.cb_length != SanitizeCommand::length()
+            || (wrapper.data_transfer_length != 0 && wrapper.direction != Direction::OUT)
+        {
+            return Err(ScsiError::from_cause(ErrorCause::ParseError));
+        }
+        let buffer = &buffer.as_ref()[15..];
+        if buffer[0] != SanitizeCommand::opcode() {
+            return Err(ScsiError::from_cause(ErrorCause::ParseError));
+        }
+        Ok(SanitizeCommand {
+            service_action: SanitizeServiceAction::from_code(buffer[1] & 0x1f)?,
+            immediate: buffer[1] & IMMEDIATE_FLAG != 0,
+            allow_unrestricted_exit: buffer[1] & ALLOW_UNRESTRICTED_EXIT_FLAG != 0,
+            parameter_list_length: BE::read_u16(&buffer[7..]),
+        })
+    }
+}
+
+/// The parameter list of an overwriting `SanitizeCommand`.
+#[derive(Clone, Copy, Eq, PartialEq, Debug)]
+pub struct OverwriteParameters<'a> {
+    /// Whether the pattern should be inverted between consecutive passes.
+    pub invert: bool,
+
+    /// The number of times the medium should be overwritten, from 1 to 31.
+    pub overwrite_count: u8,
+
+    /// The pattern written to every block, repeated as many times as needed to
+    /// fill it; it should be no longer than a block.
+    pub pattern: &'a [u8],
+}
+
+impl<'a> OverwriteParameters<'a> {
+    /// The size of the parameter list header, in bytes.
+    pub const HEADER_SIZE: usize = 4;
+
+    /// Describes a single pass writing `pattern` to every block.
+    pub fn new(pattern: &'a [u8]) -> OverwriteParameters<'a> {
+        OverwriteParameters {
+            invert: false,
+            overwrite_count: 1,
+            pattern,
+        }
+    }
+
+    /// The length of the parameter list, in bytes.
+    pub fn length(&self) -> usize {
+        OverwriteParameters::HEADER_SIZE + self.pattern.len()
+    }
+
+    /// Parses a parameter list, borrowing the pattern from `buffer`.
+    ///
+    /// # Errors
+    /// Returns a `ParseError` if the pattern is empty, or a
+    /// `BufferTooSmallError` if `buffer` does not hold the whole list.
+    pub fn pull_from_slice(buffer: &'a [u8]) -> Result<OverwriteParameters<'a>, ScsiError> {
+        if buffer.len() < OverwriteParameters::HEADER_SIZE {
+            return Err(ScsiError::from_cause(ErrorCause::BufferTooSmallError {
+                expected: OverwriteParameters::HEADER_SIZE,
+                actual: buffer.len(),
+            }));
+        }
+        let pattern_length = usize::from(BE::read_u16(&buffer[2..]));
+        if pattern_length == 0 {
+            return Err(ScsiError::from_cause(ErrorCause::ParseError));
+        }
+        let end = OverwriteParameters::HEADER_SIZE + pattern_length;
+        if buffer.len() < end {
+            return Err(ScsiError::from_cause(ErrorCause::BufferTooSmallError {
+                expected: end,
+                actual: buffer.len(),
+            }));
+        }
+        Ok(OverwriteParameters {
+            invert: buffer[0] & 0x80 != 0,
+            overwrite_count: buffer[0] & 0x1f,
+            pattern: &buffer[OverwriteParameters::HEADER_SIZE..end],
+        })
+    }
+}
+
+impl BufferPushable for OverwriteParameters<'_> {
+    fn push_to_buffer<B: AsMut<[u8]>>(&self, mut buffer: B) -> Result<usize, ScsiError> {
+        let buffer = buffer.as_mut();
+        let length = self.length();
+        if buffer.len() < length {
+            return Err(ScsiError::from_cause(ErrorCause::BufferTooSmallError {
+                expected: length,
+                actual: buffer.len(),
+            }));
+        }
+        if self.pattern.len() > usize::from(u16::MAX) {
+            return Err(ScsiError::from_cause(ErrorCause::TransferTooLargeError {
+                actual: self.pattern.len(),
+                max: usize::from(u16::MAX),
+            }));
+        }
+        buffer[0] = (self.overwrite_count & 0x1f) | if self.invert { 0x80 } else { 0 };
+        buffer[1] = 0;
+        BE::write_u16(&mut buffer[2..], self.pattern.len() as u16);
+        buffer[OverwriteParameters::HEADER_SIZE..length].copy_from_slice(self.pattern);
+        Ok(length)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{OverwriteParameters, SanitizeCommand, SanitizeServiceAction};
+    use crate::scsi::commands::{Command, Direction};
+    use crate::{BufferPullable, BufferPushable};
+
+    #[test]
+    pub fn test_sanitize() {
+        let expected: [u8; 31] = [
+            0x55, 0x53, 0x42, 0x43, 0x00, 0x00, 0x00, 0x00, 0x08, 0x00, 0x00, 0x00, 0x00, 0x00,
+            0x0a, 0x48, 0x81, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x08, 0x00, 0x00, 0x00, 0x00,
+            0x00, 0x00, 0x00,
+        ];
+        let parameters = OverwriteParameters {
+            invert: true,
+            overwrite_count: 3,
+            pattern: &[0xde, 0xad, 0xbe, 0xef],
+        };
+        let mut buff = [0; 32];
+        let mut command = SanitizeCommand::overwrite(&parameters);
+        command.immediate = true;
+        let pushed = command.push_to_buffer(&mut buff).unwrap();
+        assert_eq!(pushed, 25);
+        assert_eq!(&buff[0..pushed], &expected[0..pushed]);
+        assert_eq!(SanitizeCommand::pull_from_buffer(buff).unwrap(), command);
+
+        let mut list = [0; 8];
+        assert_eq!(parameters.push_to_buffer(&mut list).unwrap(), 8);
+        assert_eq!(list, [0x83, 0x00, 0x00, 0x04, 0xde, 0xad, 0xbe, 0xef]);
+        assert_eq!(
+            command.pull_parameter_list(&list).unwrap(),
+            Some(parameters)
+        );
+        assert!(command.pull_parameter_list(&list[..6]).is_err());
+        assert!(command.pull_parameter_list(&[0x01, 0, 0, 0]).is_err());
+    }
+
+    #[test]
+    pub fn test_sanitize_erase() {
+        let mut buff = [0; 32];
+        for (action, code) in [
+            (SanitizeServiceAction::BlockErase, 0x02),
+            (SanitizeServiceAction::CryptographicErase, 0x03),
+            (SanitizeServiceAction::ExitFailureMode, 0x1f),
+        ] {
+            let mut command = SanitizeCommand::new(action);
+            command.allow_unrestricted_exit = true;
+            assert_eq!(command.wrapper().direction, Direction::NONE);
+            command.push_to_buffer(&mut buff).unwrap();
+            assert_eq!(buff[16], 0x20 | code);
+            let pulled = SanitizeCommand::pull_from_buffer(buff).unwrap();
+            assert_eq!(pulled, command);
+            assert_eq!(pulled.pull_parameter_list(&[]).unwrap(), None);
+        }
+
+        // Service action 0x04 is reserved.
+        buff[16] = 0x04;
+        assert!(SanitizeCommand::pull_from_buffer(buff).is_err());
+    }
+}
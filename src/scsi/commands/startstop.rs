@@ -0,0 +1,117 @@
+use crate::error::{ErrorCause, ScsiError};
+use crate::scsi::commands::{Command, CommandBlockWrapper, Direction};
+use crate::traits::{BufferPullable, BufferPushable};
+
+/// Asks the device to change its power state, or to load or eject its medium.
+///
+/// With `load_eject` set, `start` chooses between loading (`true`) and
+/// ejecting (`false`) the medium; otherwise it spins the medium up or down.
+#[derive(Clone, Copy, Eq, PartialEq, Debug, Default)]
+pub struct StartStopUnitCommand {
+    /// If set, the device may report completion before the operation finishes.
+    pub immediate: bool,
+
+    /// The requested power condition; 0 means that `start` and `load_eject`
+    /// are used instead.
+    pub power_condition: u8,
+
+    /// Whether the medium should be loaded or ejected.
+    pub load_eject: bool,
+
+    /// Whether the device should be started (or the medium loaded) rather
+    /// than stopped (or the medium ejected).
+    pub start: bool,
+}
+
+impl StartStopUnitCommand {
+    /// Constructs a new `StartStopUnitCommand` with the given `start` and
+    /// `load_eject` bits.
+    pub fn new(start: bool, load_eject: bool) -> StartStopUnitCommand {
+        StartStopUnitCommand {
+            start,
+            load_eject,
+            ..Default::default()
+        }
+    }
+
+    /// Constructs a command that ejects the medium.
+    pub fn eject() -> StartStopUnitCommand {
+        StartStopUnitCommand::new(false, true)
+    }
+
+    /// Constructs a command that loads the medium.
+    pub fn load() -> StartStopUnitCommand {
+        StartStopUnitCommand::new(true, true)
+    }
+}
+
+impl Command for StartStopUnitCommand {
+    fn opcode() -> u8 {
+        0x1b
+    }
+    fn length() -> u8 {
+        0x6
+    }
+    fn wrapper(&self) -> CommandBlockWrapper {
+        CommandBlockWrapper::new(0, Direction::NONE, 0, StartStopUnitCommand::length())
+    }
+}
+
+impl BufferPushable for StartStopUnitCommand {
+    fn push_to_buffer<B: AsMut<[u8]>>(&self, mut buffer: B) -> Result<usize, ScsiError> {
+        let rval = self.wrapper().push_to_buffer(buffer.as_mut())?;
+        let buffer = &mut buffer.as_mut()[rval..];
+        buffer[0] = StartStopUnitCommand::opcode();
+        buffer[1] = if self.immediate { 1 } else { 0 };
+        buffer[2] = 0;
+        buffer[3] = 0;
+        let load_eject_bit = if self.load_eject { 0x2 } else { 0 };
+        let start_bit = if self.start { 0x1 } else { 0 };
+        buffer[4] = (self.power_condition << 4) | load_eject_bit | start_bit;
+        buffer[5] = 0;
+        Ok(rval + 6)
+    }
+}
+
+impl BufferPullable for StartStopUnitCommand {
+    fn pull_from_buffer<B: AsRef<[u8]>>(buffer: B) -> Result<Self, ScsiError> {
+        let wrapper: CommandBlockWrapper = CommandBlockWrapper::pull_from_buffer(buffer.as_ref())?;
+        if wrapper.data_transfer_length != 0 || wrapper.cb_length != StartStopUnitCommand::length()
+        {
+            return Err(ScsiError::from_cause(ErrorCause::ParseError));
+        }
+        let buffer = &buffer.as_ref()[15..];
+        if buffer[0] != StartStopUnitCommand::opcode() {
+            return Err(ScsiError::from_cause(ErrorCause::ParseError));
+        }
+        Ok(StartStopUnitCommand {
+            immediate: buffer[1] & 0x1 != 0,
+            power_condition: buffer[4] >> 4,
+            load_eject: buffer[4] & 0x2 != 0,
+            start: buffer[4] & 0x1 != 0,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::StartStopUnitCommand;
+    use crate::{BufferPullable, BufferPushable};
+
+    #[test]
+    pub fn test_startstopunit() {
+        let expected: [u8; 31] = [
+            0x55, 0x53, 0x42, 0x43, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00,
+            0x06, 0x1b, 0x00, 0x00, 0x00, 0x02, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00,
+            0x00, 0x00, 0x00,
+        ];
+        let mut buff = [0; 32];
+        let command = StartStopUnitCommand::eject();
+        let pushed = command.push_to_buffer(&mut buff).unwrap();
+        assert_eq!(pushed, 21);
+        assert_eq!(&buff[0..pushed], &expected[0..pushed]);
+
+        let pulled = StartStopUnitCommand::pull_from_buffer(buff).unwrap();
+        assert_eq!(pulled, command);
+    }
+}
@@ -0,0 +1,108 @@
+use crate::error::{ErrorCause, ScsiError};
+use crate::scsi::commands::{Command, CommandBlockWrapper, Direction};
+use crate::traits::{BufferPullable, BufferPushable};
+
+use byteorder::{ByteOrder, BE};
+
+/// Asks the device to write any data it has cached for a range of blocks to
+/// its medium.
+///
+/// A `block_count` of 0 covers every block from `block_address` to the end of
+/// the device, so the default command flushes the whole cache.
+#[derive(Clone, Copy, Eq, PartialEq, Debug, Default)]
+pub struct SynchronizeCache10Command {
+    /// If set, the device may report completion before the data is written.
+    pub immediate: bool,
+
+    /// The first block to synchronize.
+    pub block_address: u32,
+
+    /// The number of blocks to synchronize.
+    pub block_count: u16,
+}
+
+impl SynchronizeCache10Command {
+    /// Constructs a command that synchronizes the whole cache.
+    pub fn new() -> SynchronizeCache10Command {
+        SynchronizeCache10Command::default()
+    }
+
+    /// Constructs a command that synchronizes `block_count` blocks starting
+    /// at `block_address`.
+    pub fn range(block_address: u32, block_count: u16) -> SynchronizeCache10Command {
+        SynchronizeCache10Command {
+            immediate: false,
+            block_address,
+            block_count,
+        }
+    }
+}
+
+impl Command for SynchronizeCache10Command {
+    fn opcode() -> u8 {
+        0x35
+    }
+    fn length() -> u8 {
+        10
+    }
+    fn wrapper(&self) -> CommandBlockWrapper {
+        CommandBlockWrapper::new(0, Direction::NONE, 0, SynchronizeCache10Command::length())
+    }
+}
+
+impl BufferPushable for SynchronizeCache10Command {
+    fn push_to_buffer<B: AsMut<[u8]>>(&self, mut buffer: B) -> Result<usize, ScsiError> {
+        let rval = self.wrapper().push_to_buffer(buffer.as_mut())?;
+        let buffer = &mut buffer.as_mut()[rval..];
+        buffer[0] = SynchronizeCache10Command::opcode();
+        buffer[1] = if self.immediate { 0x2 } else { 0 };
+        BE::write_u32(&mut buffer[2..], self.block_address);
+        buffer[6] = 0;
+        BE::write_u16(&mut buffer[7..], self.block_count);
+        buffer[9] = 0;
+        Ok(rval + 10)
+    }
+}
+
+impl BufferPullable for SynchronizeCache10Command {
+    fn pull_from_buffer<B: AsRef<[u8]>>(buffer: B) -> Result<Self, ScsiError> {
+        let wrapper: CommandBlockWrapper = CommandBlockWrapper::pull_from_buffer(buffer.as_ref())?;
+        if wrapper.data_transfer_length != 0
+            || wrapper.cb_length != SynchronizeCache10Command::length()
+        {
+            return Err(ScsiError::from_cause(ErrorCause::ParseError));
+        }
+        let buffer = &buffer.as_ref()[15..];
+        if buffer[0] != SynchronizeCache10Command::opcode() {
+            return Err(ScsiError::from_cause(ErrorCause::ParseError));
+        }
+        Ok(SynchronizeCache10Command {
+            immediate: buffer[1] & 0x2 != 0,
+            block_address: BE::read_u32(&buffer[2..]),
+            block_count: BE::read_u16(&buffer[7..]),
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::SynchronizeCache10Command;
+    use crate::{BufferPullable, BufferPushable};
+
+    #[test]
+    pub fn test_synchronizecache10() {
+        let expected: [u8; 31] = [
+            0x55, 0x53, 0x42, 0x43, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00,
+            0x0a, 0x35, 0x00, 0x00, 0x01, 0x02, 0x03, 0x00, 0x00, 0x10, 0x00, 0x00, 0x00, 0x00,
+            0x00, 0x00, 0x00,
+        ];
+        let mut buff = [0; 32];
+        let command = SynchronizeCache10Command::range(0x0001_0203, 16);
+        let pushed = command.push_to_buffer(&mut buff).unwrap();
+        assert_eq!(pushed, 25);
+        assert_eq!(&buff[0..pushed], &expected[0..pushed]);
+
+        let pulled = SynchronizeCache10Command::pull_from_buffer(buff).unwrap();
+        assert_eq!(pulled, command);
+    }
+}
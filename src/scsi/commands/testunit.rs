@@ -1,6 +1,6 @@
-use error::{ErrorCause, ScsiError};
-use scsi::commands::{Command, CommandBlockWrapper, Direction};
-use traits::{BufferPullable, BufferPushable};
+use crate::error::{ErrorCause, ScsiError};
+use crate::scsi::commands::{Command, CommandBlockWrapper, Direction};
+use crate::traits::{BufferPullable, BufferPushable};
 
 /// Asks the device whether or not it is ready for usage.
 ///
@@ -72,7 +72,7 @@ mod tests {
         assert_eq!(pushed, 16);
         assert_eq!(&buff[0..pushed], &expected[0..pushed]);
 
-        let pulled = TestUnitReady::pull_from_buffer(&mut buff).unwrap();
+        let pulled = TestUnitReady::pull_from_buffer(buff).unwrap();
         assert_eq!(pulled, tur_command);
     }
 }
@@ -0,0 +1,335 @@
+use crate::error::{ErrorCause, ScsiError};
+use crate::scsi::commands::{Command, CommandBlockWrapper, Direction};
+use crate::traits::{BufferPullable, BufferPushable};
+
+use byteorder::{ByteOrder, BE};
+
+/// Asks the device to release ranges of blocks that no longer hold useful
+/// data, such as after files are deleted from a thin-provisioned disk or an
+/// SSD.
+///
+/// The ranges are sent in the command's parameter list, which consists of an
+/// 8 byte header followed by `descriptor_count` `UnmapBlockDescriptor`s and is
+/// built with `push_parameter_list`.
+#[derive(Clone, Copy, Eq, PartialEq, Debug, Default)]
+pub struct UnmapCommand {
+    /// Whether the blocks should be anchored, keeping their storage
+    /// allocated, rather than deallocated.
+    pub anchor: bool,
+
+    /// The number of block descriptors in the parameter list.
+    pub descriptor_count: u16,
+}
+
+impl UnmapCommand {
+    /// The size of the parameter list header, in bytes.
+    pub const HEADER_SIZE: usize = 8;
+
+    /// The most block descriptors that fit in a single parameter list.
+    pub const MAX_DESCRIPTORS: u16 =
+        ((0xffff - UnmapCommand::HEADER_SIZE) / UnmapBlockDescriptor::SIZE) as u16;
+
+    /// Constructs a command that unmaps `descriptor_count` ranges.
+    ///
+    /// # Errors
+    /// Returns a `TransferTooLargeError` if there are more than
+    /// `UnmapCommand::MAX_DESCRIPTORS` ranges.
+    pub fn new(descriptor_count: u16) -> Result<UnmapCommand, ScsiError> {
+        if descriptor_count > UnmapCommand::MAX_DESCRIPTORS {
+            return Err(ScsiError::from_cause(ErrorCause::TransferTooLargeError {
+                actual: usize::from(descriptor_count),
+                max: usize::from(UnmapCommand::MAX_DESCRIPTORS),
+            }));
+        }
+        Ok(UnmapCommand {
+            anchor: false,
+            descriptor_count,
+        })
+    }
+
+    /// The size of a parameter list holding a single block descriptor.
+    pub(crate) const SINGLE_RANGE_SIZE: usize =
+        UnmapCommand::HEADER_SIZE + UnmapBlockDescriptor::SIZE;
+
+    /// Builds a command unmapping the `count` blocks starting at `lba`, along
+    /// with its parameter list.
+    pub(crate) fn single_range(
+        lba: u64,
+        count: u32,
+    ) -> Result<(UnmapCommand, [u8; UnmapCommand::SINGLE_RANGE_SIZE]), ScsiError> {
+        let command = UnmapCommand::new(1)?;
+        let descriptor = UnmapBlockDescriptor {
+            block_address: lba,
+            block_count: count,
+        };
+        let mut buffer = [0; UnmapCommand::SINGLE_RANGE_SIZE];
+        command.push_parameter_list(&[descriptor], &mut buffer[..])?;
+        Ok((command, buffer))
+    }
+
+    /// The length of the command's parameter list, in bytes.
+    pub fn parameter_list_length(&self) -> usize {
+        UnmapCommand::HEADER_SIZE + usize::from(self.descriptor_count) * UnmapBlockDescriptor::SIZE
+    }
+
+    /// Serializes the parameter list holding `descriptors` to `buffer`,
+    /// returning the number of bytes written.
+    ///
+    /// # Errors
+    /// Returns a `ParseError` if the number of descriptors does not match
+    /// `descriptor_count`, or a `BufferTooSmallError` if `buffer` cannot hold
+    /// the parameter list.
+    pub fn push_parameter_list<B: AsMut<[u8]>>(
+        &self,
+        descriptors: &[UnmapBlockDescriptor],
+        mut buffer: B,
+    ) -> Result<usize, ScsiError> {
+        let buffer = buffer.as_mut();
+        if descriptors.len() != usize::from(self.descriptor_count) {
+            return Err(ScsiError::from_cause(ErrorCause::ParseError));
+        }
+        let length = self.parameter_list_length();
+        if buffer.len() < length {
+            return Err(ScsiError::from_cause(ErrorCause::BufferTooSmallError {
+                expected: length,
+                actual: buffer.len(),
+            }));
+        }
+        BE::write_u16(buffer, (length - 2) as u16);
+        BE::write_u16(
+            &mut buffer[2..],
+            (length - UnmapCommand::HEADER_SIZE) as u16,
+        );
+        for byte in &mut buffer[4..UnmapCommand::HEADER_SIZE] {
+            *byte = 0;
+        }
+        for (idx, descriptor) in descriptors.iter().enumerate() {
+            let start = UnmapCommand::HEADER_SIZE + idx * UnmapBlockDescriptor::SIZE;
+            descriptor.push_to_buffer(&mut buffer[start..])?;
+        }
+        Ok(length)
+    }
+
+    /// Parses the parameter list sent along with the command, returning its
+    /// block descriptors.
+    ///
+    /// An empty list holds no descriptors.
+    ///
+    /// # Errors
+    /// Returns a `ParseError` if the list's lengths are inconsistent, or a
+    /// `BufferTooSmallError` if `buffer` does not hold the whole list.
+    pub fn pull_parameter_list(buffer: &[u8]) -> Result<UnmapBlockDescriptors<'_>, ScsiError> {
+        if buffer.is_empty() {
+            return Ok(UnmapBlockDescriptors { remaining: buffer });
+        }
+        if buffer.len() < UnmapCommand::HEADER_SIZE {
+            return Err(ScsiError::from_cause(ErrorCause::BufferTooSmallError {
+                expected: UnmapCommand::HEADER_SIZE,
+                actual: buffer.len(),
+            }));
+        }
+        let list_length = usize::from(BE::read_u16(buffer)) + 2;
+        let descriptors_length = usize::from(BE::read_u16(&buffer[2..]));
+        if list_length < UnmapCommand::HEADER_SIZE + descriptors_length
+            || descriptors_length % UnmapBlockDescriptor::SIZE != 0
+        {
+            return Err(ScsiError::from_cause(ErrorCause::ParseError));
+        }
+        let end = UnmapCommand::HEADER_SIZE + descriptors_length;
+        if buffer.len() < end {
+            return Err(ScsiError::from_cause(ErrorCause::BufferTooSmallError {
+                expected: end,
+                actual: buffer.len(),
+            }));
+        }
+        Ok(UnmapBlockDescriptors {
+            remaining: &buffer[UnmapCommand::HEADER_SIZE..end],
+        })
+    }
+}
+
+impl Command for UnmapCommand {
+    fn opcode() -> u8 {
+        0x42
+    }
+    fn length() -> u8 {
+        10
+    }
+    fn wrapper(&self) -> CommandBlockWrapper {
+        CommandBlockWrapper::new(
+            self.parameter_list_length() as u32,
+            Direction::OUT,
+            0,
+            UnmapCommand::length(),
+        )
+    }
+}
+
+impl BufferPushable for UnmapCommand {
+    fn push_to_buffer<B: AsMut<[u8]>>(&self, mut buffer: B) -> Result<usize, ScsiError> {
+        let rval = self.wrapper().push_to_buffer(buffer.as_mut())?;
+        let buffer = &mut buffer.as_mut()[rval..];
+        buffer[0] = UnmapCommand::opcode();
+        buffer[1] = if self.anchor { 0x1 } else { 0 };
+        for byte in &mut buffer[2..7] {
+            *byte = 0;
+        }
+        BE::write_u16(&mut buffer[7..], self.parameter_list_length() as u16);
+        buffer[9] = 0;
+        Ok(rval + 10)
+    }
+}
+
+impl BufferPullable for UnmapCommand {
+    fn pull_from_buffer<B: AsRef<[u8]>>(buffer: B) -> Result<Self, ScsiError> {
+        let wrapper: CommandBlockWrapper = CommandBlockWrapper::pull_from_buffer(buffer.as_ref())?;
+        if wrapper.cb_length != UnmapCommand::length() {
+            return Err(ScsiError::from_cause(ErrorCause::ParseError));
+        }
+        let buffer = &buffer.as_ref()[15..];
+        if buffer[0] != UnmapCommand::opcode() {
+            return Err(ScsiError::from_cause(ErrorCause::ParseError));
+        }
+        let list_length = usize::from(BE::read_u16(&buffer[7..]));
+        let descriptors = list_length.saturating_sub(UnmapCommand::HEADER_SIZE);
+        if list_length != 0 && descriptors % UnmapBlockDescriptor::SIZE != 0 {
+            return Err(ScsiError::from_cause(ErrorCause::ParseError));
+        }
+        Ok(UnmapCommand {
+            anchor: buffer[1] & 0x1 != 0,
+            descriptor_count: (descriptors / UnmapBlockDescriptor::SIZE) as u16,
+        })
+    }
+}
+
+/// A range of blocks in the parameter list of an `UnmapCommand`.
+#[derive(Clone, Copy, Eq, PartialEq, Debug, Default)]
+pub struct UnmapBlockDescriptor {
+    /// The first block of the range.
+    pub block_address: u64,
+
+    /// The number of blocks in the range.
+    pub block_count: u32,
+}
+
+impl UnmapBlockDescriptor {
+    /// The size of a block descriptor, in bytes.
+    pub const SIZE: usize = 16;
+}
+
+impl BufferPushable for UnmapBlockDescriptor {
+    fn push_to_buffer<B: AsMut<[u8]>>(&self, mut buffer: B) -> Result<usize, ScsiError> {
+        let buffer = buffer.as_mut();
+        if buffer.len() < UnmapBlockDescriptor::SIZE {
+            return Err(ScsiError::from_cause(ErrorCause::BufferTooSmallError {
+                expected: UnmapBlockDescriptor::SIZE,
+                actual: buffer.len(),
+            }));
+        }
+        BE::write_u64(buffer, self.block_address);
+        BE::write_u32(&mut buffer[8..], self.block_count);
+        for byte in &mut buffer[12..UnmapBlockDescriptor::SIZE] {
+            *byte = 0;
+        }
+        Ok(UnmapBlockDescriptor::SIZE)
+    }
+}
+
+impl BufferPullable for UnmapBlockDescriptor {
+    fn pull_from_buffer<B: AsRef<[u8]>>(buffer: B) -> Result<Self, ScsiError> {
+        let buffer = buffer.as_ref();
+        if buffer.len() < UnmapBlockDescriptor::SIZE {
+            return Err(ScsiError::from_cause(ErrorCause::BufferTooSmallError {
+                expected: UnmapBlockDescriptor::SIZE,
+                actual: buffer.len(),
+            }));
+        }
+        Ok(UnmapBlockDescriptor {
+            block_address: BE::read_u64(buffer),
+            block_count: BE::read_u32(&buffer[8..]),
+        })
+    }
+}
+
+/// The block descriptors of a parsed UNMAP parameter list, returned by
+/// `UnmapCommand::pull_parameter_list`.
+#[derive(Clone, Debug)]
+pub struct UnmapBlockDescriptors<'a> {
+    remaining: &'a [u8],
+}
+
+impl Iterator for UnmapBlockDescriptors<'_> {
+    type Item = UnmapBlockDescriptor;
+
+    fn next(&mut self) -> Option<UnmapBlockDescriptor> {
+        let descriptor = UnmapBlockDescriptor::pull_from_buffer(self.remaining).ok()?;
+        self.remaining = &self.remaining[UnmapBlockDescriptor::SIZE..];
+        Some(descriptor)
+    }
+
+    fn size_hint(&self) -> (usize, Option<usize>) {
+        let count = self.remaining.len() / UnmapBlockDescriptor::SIZE;
+        (count, Some(count))
+    }
+}
+
+impl ExactSizeIterator for UnmapBlockDescriptors<'_> {}
+
+#[cfg(test)]
+mod tests {
+    use super::{UnmapBlockDescriptor, UnmapCommand};
+    use crate::{BufferPullable, BufferPushable};
+
+    #[test]
+    pub fn test_unmap() {
+        let expected: [u8; 31] = [
+            0x55, 0x53, 0x42, 0x43, 0x00, 0x00, 0x00, 0x00, 0x28, 0x00, 0x00, 0x00, 0x00, 0x00,
+            0x0a, 0x42, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x28, 0x00, 0x00, 0x00, 0x00,
+            0x00, 0x00, 0x00,
+        ];
+        let mut buff = [0; 32];
+        let command = UnmapCommand::new(2).unwrap();
+        let pushed = command.push_to_buffer(&mut buff).unwrap();
+        assert_eq!(pushed, 25);
+        assert_eq!(&buff[0..pushed], &expected[0..pushed]);
+        let pulled = UnmapCommand::pull_from_buffer(buff).unwrap();
+        assert_eq!(pulled, command);
+
+        let descriptors = [
+            UnmapBlockDescriptor {
+                block_address: 0x0102_0304_0506,
+                block_count: 8,
+            },
+            UnmapBlockDescriptor {
+                block_address: 64,
+                block_count: 0x1_0000,
+            },
+        ];
+        let mut list = [0xff; 40];
+        assert_eq!(
+            command
+                .push_parameter_list(&descriptors, &mut list[..])
+                .unwrap(),
+            40
+        );
+        assert_eq!(&list[..8], &[0, 38, 0, 32, 0, 0, 0, 0]);
+        assert_eq!(
+            &list[8..24],
+            &[0, 0, 1, 2, 3, 4, 5, 6, 0, 0, 0, 8, 0, 0, 0, 0]
+        );
+        let pulled = UnmapBlockDescriptor::pull_from_buffer(&list[24..]).unwrap();
+        assert_eq!(pulled, descriptors[1]);
+        let parsed = UnmapCommand::pull_parameter_list(&list[..]).unwrap();
+        assert_eq!(parsed.len(), 2);
+        assert!(parsed.eq(descriptors.iter().copied()));
+        assert_eq!(UnmapCommand::pull_parameter_list(&[]).unwrap().len(), 0);
+        assert!(UnmapCommand::pull_parameter_list(&list[..30]).is_err());
+        list[3] = 31;
+        assert!(UnmapCommand::pull_parameter_list(&list[..]).is_err());
+
+        assert!(command
+            .push_parameter_list(&descriptors[..1], &mut list[..])
+            .is_err());
+        assert!(UnmapCommand::new(UnmapCommand::MAX_DESCRIPTORS + 1).is_err());
+    }
+}
@@ -0,0 +1,306 @@
+use crate::error::{ErrorCause, ScsiError};
+use crate::scsi::commands::{Command, CommandBlockWrapper, Direction};
+use crate::traits::{BufferPullable, BufferPushable};
+
+use byteorder::{ByteOrder, BE};
+
+/// What, if anything, a VERIFY command compares the medium against; this is
+/// the command's BYTCHK field.
+#[derive(Clone, Copy, Eq, PartialEq, Debug, Default)]
+pub enum VerifyByteCheck {
+    /// Only check that the blocks can be read back, without sending any data
+    /// along with the command.
+    #[default]
+    None,
+
+    /// Compare every block against the data sent along with the command,
+    /// which holds one block for each block being verified.
+    Compare,
+
+    /// Compare every block against the single block sent along with the
+    /// command.
+    CompareSingleBlock,
+}
+
+impl VerifyByteCheck {
+    /// Parses the BYTCHK field out of the second byte of a VERIFY command.
+    pub(crate) fn from_bits(flags: u8) -> Result<VerifyByteCheck, ScsiError> {
+        match (flags >> 1) & 0x3 {
+            0 => Ok(VerifyByteCheck::None),
+            1 => Ok(VerifyByteCheck::Compare),
+            3 => Ok(VerifyByteCheck::CompareSingleBlock),
+            _ => Err(ScsiError::from_cause(ErrorCause::ParseError)),
+        }
+    }
+
+    fn bits(self) -> u8 {
+        match self {
+            VerifyByteCheck::None => 0,
+            VerifyByteCheck::Compare => 0x2,
+            VerifyByteCheck::CompareSingleBlock => 0x6,
+        }
+    }
+
+    /// The length and direction of the data sent along with a command
+    /// verifying `blocks` blocks of `block_size` bytes.
+    pub(crate) fn data_phase(self, blocks: u32, block_size: u32) -> (u32, Direction) {
+        match self {
+            VerifyByteCheck::None => (0, Direction::NONE),
+            VerifyByteCheck::Compare => (blocks * block_size, Direction::OUT),
+            VerifyByteCheck::CompareSingleBlock => (block_size, Direction::OUT),
+        }
+    }
+
+    /// Works out the block size from the length of the data sent along with a
+    /// command verifying `blocks` blocks.
+    fn block_size(self, blocks: u32, wrapper: &CommandBlockWrapper) -> Result<u32, ScsiError> {
+        if self != VerifyByteCheck::None && wrapper.direction != Direction::OUT {
+            return Err(ScsiError::from_cause(ErrorCause::ParseError));
+        }
+        Ok(match self {
+            VerifyByteCheck::None => 0,
+            VerifyByteCheck::Compare => wrapper
+                .data_transfer_length
+                .checked_div(blocks)
+                .unwrap_or(0),
+            VerifyByteCheck::CompareSingleBlock => wrapper.data_transfer_length,
+        })
+    }
+}
+
+/// Asks the device to check that a range of blocks can be read back from its
+/// medium, and optionally that they hold the data sent along with the
+/// command, without transferring the blocks themselves to the host.
+///
+/// A block that cannot be read fails the command with a `MEDIUM_ERROR` sense
+/// key whose `information` is the address of the block; a failed comparison
+/// uses the `MISCOMPARE` sense key, with the offset of the first differing
+/// byte in the sent data as its `information`.
+#[derive(Clone, Copy, Eq, PartialEq, Debug, Default)]
+pub struct Verify10Command {
+    /// The first block to verify.
+    pub block_address: u32,
+
+    /// The number of bytes in a single block; only used when data is sent
+    /// along with the command.
+    pub block_size: u32,
+
+    /// The number of blocks to verify.
+    pub transfer_blocks: u16,
+
+    /// What the blocks are compared against.
+    pub byte_check: VerifyByteCheck,
+}
+
+impl Verify10Command {
+    /// Constructs a command verifying the `transfer_blocks` blocks starting at
+    /// `block_address` without comparing them against any data.
+    pub fn new(block_address: u32, transfer_blocks: u16, block_size: u32) -> Verify10Command {
+        Verify10Command {
+            block_address,
+            block_size,
+            transfer_blocks,
+            byte_check: VerifyByteCheck::None,
+        }
+    }
+}
+
+impl Command for Verify10Command {
+    fn opcode() -> u8 {
+        0x2f
+    }
+    fn length() -> u8 {
+        10
+    }
+    fn wrapper(&self) -> CommandBlockWrapper {
+        let (length, direction) = self
+            .byte_check
+            .data_phase(u32::from(self.transfer_blocks), self.block_size);
+        CommandBlockWrapper::new(length, direction, 0, Verify10Command::length())
+    }
+}
+
+impl BufferPushable for Verify10Command {
+    fn push_to_buffer<B: AsMut<[u8]>>(&self, mut buffer: B) -> Result<usize, ScsiError> {
+        let rval = self.wrapper().push_to_buffer(buffer.as_mut())?;
+        let buffer = &mut buffer.as_mut()[rval..];
+        buffer[0] = Verify10Command::opcode();
+        buffer[1] = self.byte_check.bits();
+        BE::write_u32(&mut buffer[2..], self.block_address);
+        buffer[6] = 0;
+        BE::write_u16(&mut buffer[7..], self.transfer_blocks);
+        buffer[9] = 0;
+        Ok(rval + 10)
+    }
+}
+
+impl BufferPullable for Verify10Command {
+    fn pull_from_buffer<B: AsRef<[u8]>>(buffer: B) -> Result<Self, ScsiError> {
+        let wrapper: CommandBlockWrapper = CommandBlockWrapper::pull_from_buffer(buffer.as_ref())?;
+        if wrapper.cb_length != Verify10Command::length() {
+            return Err(ScsiError::from_cause(ErrorCause::ParseError));
+        }
+        let buffer = &buffer.as_ref()[15..];
+        if buffer[0] != Verify10Command::opcode() {
+            return Err(ScsiError::from_cause(ErrorCause::ParseError));
+        }
+        let byte_check = VerifyByteCheck::from_bits(buffer[1])?;
+        let transfer_blocks = BE::read_u16(&buffer[7..]);
+        Ok(Verify10Command {
+            block_address: BE::read_u32(&buffer[2..]),
+            block_size: byte_check.block_size(u32::from(transfer_blocks), &wrapper)?,
+            transfer_blocks,
+            byte_check,
+        })
+    }
+}
+
+/// The 16 byte version of `Verify10Command`, which can address every block
+/// of large devices.
+#[derive(Clone, Copy, Eq, PartialEq, Debug, Default)]
+pub struct Verify16Command {
+    /// The first block to verify.
+    pub block_address: u64,
+
+    /// The number of bytes in a single block; only used when data is sent
+    /// along with the command.
+    pub block_size: u32,
+
+    /// The number of blocks to verify.
+    pub transfer_blocks: u32,
+
+    /// What the blocks are compared against.
+    pub byte_check: VerifyByteCheck,
+}
+
+impl Verify16Command {
+    /// Constructs a command verifying the `transfer_blocks` blocks starting at
+    /// `block_address` without comparing them against any data.
+    pub fn new(block_address: u64, transfer_blocks: u32, block_size: u32) -> Verify16Command {
+        Verify16Command {
+            block_address,
+            block_size,
+            transfer_blocks,
+            byte_check: VerifyByteCheck::None,
+        }
+    }
+}
+
+impl Command for Verify16Command {
+    fn opcode() -> u8 {
+        0x8f
+    }
+    fn length() -> u8 {
+        16
+    }
+    fn wrapper(&self) -> CommandBlockWrapper {
+        let (length, direction) = self
+            .byte_check
+            .data_phase(self.transfer_blocks, self.block_size);
+        CommandBlockWrapper::new(length, direction, 0, Verify16Command::length())
+    }
+}
+
+impl BufferPushable for Verify16Command {
+    fn push_to_buffer<B: AsMut<[u8]>>(&self, mut buffer: B) -> Result<usize, ScsiError> {
+        let rval = self.wrapper().push_to_buffer(buffer.as_mut())?;
+        let buffer = &mut buffer.as_mut()[rval..];
+        buffer[0] = Verify16Command::opcode();
+        buffer[1] = self.byte_check.bits();
+        BE::write_u64(&mut buffer[2..], self.block_address);
+        BE::write_u32(&mut buffer[10..], self.transfer_blocks);
+        buffer[14] = 0;
+        buffer[15] = 0;
+        Ok(rval + 16)
+    }
+}
+
+impl BufferPullable for Verify16Command {
+    fn pull_from_buffer<B: AsRef<[u8]>>(buffer: B) -> Result<Self, ScsiError> {
+        let wrapper: CommandBlockWrapper = CommandBlockWrapper::pull_from_buffer(buffer.as_ref())?;
+        if wrapper.cb_length != Verify16Command::length() {
+            return Err(ScsiError::from_cause(ErrorCause::ParseError));
+        }
+        let buffer = &buffer.as_ref()[15..];
+        if buffer[0] != Verify16Command::opcode() {
+            return Err(ScsiError::from_cause(ErrorCause::ParseError));
+        }
+        let byte_check = VerifyByteCheck::from_bits(buffer[1])?;
+        let transfer_blocks = BE::read_u32(&buffer[10..]);
+        Ok(Verify16Command {
+            block_address: BE::read_u64(&buffer[2..]),
+            block_size: byte_check.block_size(transfer_blocks, &wrapper)?,
+            transfer_blocks,
+            byte_check,
+        })
+    }
+}
+
+impl From<Verify10Command> for Verify16Command {
+    fn from(command: Verify10Command) -> Verify16Command {
+        Verify16Command {
+            block_address: u64::from(command.block_address),
+            block_size: command.block_size,
+            transfer_blocks: u32::from(command.transfer_blocks),
+            byte_check: command.byte_check,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{Verify10Command, Verify16Command, VerifyByteCheck};
+    use crate::scsi::commands::{Command, Direction};
+    use crate::{BufferPullable, BufferPushable};
+
+    #[test]
+    pub fn test_verify10() {
+        let expected: [u8; 31] = [
+            0x55, 0x53, 0x42, 0x43, 0x00, 0x00, 0x00, 0x00, 0x00, 0x08, 0x00, 0x00, 0x00, 0x00,
+            0x0a, 0x2f, 0x02, 0x00, 0x00, 0x10, 0x00, 0x00, 0x00, 0x04, 0x00, 0x00, 0x00, 0x00,
+            0x00, 0x00, 0x00,
+        ];
+        let mut buff = [0; 32];
+        let mut command = Verify10Command::new(0x1000, 4, 512);
+        command.byte_check = VerifyByteCheck::Compare;
+        let pushed = command.push_to_buffer(&mut buff).unwrap();
+        assert_eq!(pushed, 25);
+        assert_eq!(&buff[0..pushed], &expected[0..pushed]);
+        assert_eq!(Verify10Command::pull_from_buffer(buff).unwrap(), command);
+
+        // Without a byte check no data is sent, so the block size is lost.
+        let command = Verify10Command::new(7, 3, 512);
+        assert_eq!(command.wrapper().direction, Direction::NONE);
+        command.push_to_buffer(&mut buff).unwrap();
+        assert_eq!(&buff[8..12], &[0; 4]);
+        let pulled = Verify10Command::pull_from_buffer(buff).unwrap();
+        assert_eq!((pulled.block_address, pulled.transfer_blocks), (7, 3));
+        assert_eq!(pulled.byte_check, VerifyByteCheck::None);
+
+        // A BYTCHK of 2 is reserved.
+        buff[16] = 0x4;
+        assert!(Verify10Command::pull_from_buffer(buff).is_err());
+    }
+
+    #[test]
+    pub fn test_verify16() {
+        let expected: [u8; 31] = [
+            0x55, 0x53, 0x42, 0x43, 0x00, 0x00, 0x00, 0x00, 0x00, 0x10, 0x00, 0x00, 0x00, 0x00,
+            0x10, 0x8f, 0x06, 0x00, 0x00, 0x01, 0x02, 0x03, 0x04, 0x05, 0x06, 0x00, 0x01, 0x00,
+            0x00, 0x00, 0x00,
+        ];
+        let mut buff = [0; 32];
+        let mut command = Verify16Command::new(0x0102_0304_0506, 0x0001_0000, 4096);
+        command.byte_check = VerifyByteCheck::CompareSingleBlock;
+        let pushed = command.push_to_buffer(&mut buff).unwrap();
+        assert_eq!(pushed, 31);
+        assert_eq!(&buff[0..pushed], &expected[0..pushed]);
+        assert_eq!(Verify16Command::pull_from_buffer(buff).unwrap(), command);
+
+        let mut narrow = Verify10Command::new(9, 2, 512);
+        narrow.byte_check = VerifyByteCheck::Compare;
+        let widened = Verify16Command::from(narrow);
+        assert_eq!((widened.block_address, widened.transfer_blocks), (9, 2));
+        assert_eq!(widened.wrapper().data_transfer_length, 1024);
+    }
+}
@@ -1,6 +1,6 @@
-use error::{ErrorCause, ScsiError};
-use scsi::commands::{Command, CommandBlockWrapper, Direction};
-use traits::{BufferPullable, BufferPushable};
+use crate::error::{ErrorCause, ScsiError};
+use crate::scsi::commands::{Command, CommandBlockWrapper, Direction};
+use crate::traits::{BufferPullable, BufferPushable};
 
 use byteorder::{ByteOrder, BE};
 
@@ -33,6 +33,19 @@ pub struct Write10Command {
 }
 
 impl Write10Command {
+    /// The largest number of blocks a single command can transfer.
+    pub const MAX_TRANSFER_BLOCKS: u32 = 0xffff;
+
+    fn checked_transfer_blocks(blocks: u32) -> Result<u16, ScsiError> {
+        if blocks > Write10Command::MAX_TRANSFER_BLOCKS {
+            return Err(ScsiError::from_cause(ErrorCause::TransferTooLargeError {
+                actual: blocks as usize,
+                max: Write10Command::MAX_TRANSFER_BLOCKS as usize,
+            }));
+        }
+        Ok(blocks as u16)
+    }
+
     /// Creates a new Write10 command.
     ///
     /// Executing the created command will attempt to write `transfer_bytes` bytes starting
@@ -41,7 +54,8 @@ impl Write10Command {
     /// # Errors
     /// This function returns an error if either `offset` or `transfer_bytes` are
     /// not an integer multiple of `block_size`, since SCSI cannot address at lower
-    /// than block resolution.
+    /// than block resolution, or if the write is longer than
+    /// `Write10Command::MAX_TRANSFER_BLOCKS` blocks.
     pub fn new(
         offset: u32,
         transfer_bytes: u32,
@@ -55,7 +69,7 @@ impl Write10Command {
                 },
             ));
         }
-        let transfer_blocks = (transfer_bytes / block_size) as u16;
+        let transfer_blocks = Write10Command::checked_transfer_blocks(transfer_bytes / block_size)?;
         if offset % block_size != 0 {
             return Err(ScsiError::from_cause(
                 ErrorCause::NonBlocksizeMultipleLengthError {
@@ -119,7 +133,10 @@ impl BufferPullable for Write10Command {
         Ok(Write10Command {
             block_address,
             transfer_blocks,
-            block_size: wrapper.data_transfer_length / u32::from(transfer_blocks),
+            block_size: wrapper
+                .data_transfer_length
+                .checked_div(u32::from(transfer_blocks))
+                .unwrap_or(0),
         })
     }
 }
@@ -140,9 +157,9 @@ mod tests {
         assert_eq!(read_command.transfer_blocks, 1);
         let pushed = read_command.push_to_buffer(&mut buff).unwrap();
         assert_eq!(pushed, 25);
-        assert_eq!(&buff[0..pushed as usize], &expected[0..pushed]);
+        assert_eq!(&buff[0..pushed], &expected[0..pushed]);
 
-        let pulled = Write10Command::pull_from_buffer(&mut buff).unwrap();
+        let pulled = Write10Command::pull_from_buffer(buff).unwrap();
         assert_eq!(pulled, read_command);
     }
 }
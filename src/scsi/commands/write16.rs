@@ -0,0 +1,147 @@
+use crate::scsi::commands::{Command, CommandBlockWrapper, Direction, Write10Command};
+
+use crate::error::{ErrorCause, ScsiError};
+use crate::traits::{BufferPullable, BufferPushable};
+
+use byteorder::{ByteOrder, BE};
+
+/// The 16 byte version of `Write10Command`, whose 64 bit block address reaches
+/// every block of devices too large for the 10 byte form.
+#[derive(Clone, Copy, Eq, PartialEq, Debug, Default)]
+pub struct Write16Command {
+    /// The start address of the write, in units of device blocks.
+    pub block_address: u64,
+
+    /// The number of bytes in a single block.
+    pub block_size: u32,
+
+    /// The size of the write, in units of blocks.
+    pub transfer_blocks: u32,
+}
+
+impl Write16Command {
+    /// Constructs a command writing the `transfer_blocks` blocks of
+    /// `block_size` bytes starting at `block_address`.
+    pub fn new(block_address: u64, transfer_blocks: u32, block_size: u32) -> Write16Command {
+        Write16Command {
+            block_address,
+            block_size,
+            transfer_blocks,
+        }
+    }
+}
+
+impl Command for Write16Command {
+    fn opcode() -> u8 {
+        0x8a
+    }
+    fn length() -> u8 {
+        16
+    }
+    fn wrapper(&self) -> CommandBlockWrapper {
+        CommandBlockWrapper::new(
+            self.transfer_blocks * self.block_size,
+            Direction::OUT,
+            0,
+            Write16Command::length(),
+        )
+    }
+}
+
+impl BufferPushable for Write16Command {
+    fn push_to_buffer<B: AsMut<[u8]>>(&self, mut buffer: B) -> Result<usize, ScsiError> {
+        let rval = self.wrapper().push_to_buffer(buffer.as_mut())?;
+        let buffer = &mut buffer.as_mut()[rval..];
+        buffer[0] = Write16Command::opcode();
+        buffer[1] = 0;
+        BE::write_u64(&mut buffer[2..], self.block_address);
+        BE::write_u32(&mut buffer[10..], self.transfer_blocks);
+        buffer[14] = 0;
+        buffer[15] = 0;
+        Ok(rval + 16)
+    }
+}
+
+impl BufferPullable for Write16Command {
+    fn pull_from_buffer<B: AsRef<[u8]>>(buffer: B) -> Result<Self, ScsiError> {
+        let wrapper: CommandBlockWrapper = CommandBlockWrapper::pull_from_buffer(buffer.as_ref())?;
+        if wrapper.direction != Direction::OUT || wrapper.cb_length != Write16Command::length() {
+            return Err(ScsiError::from_cause(ErrorCause::ParseError));
+        }
+        let buffer = &buffer.as_ref()[15..];
+        if buffer[0] != Write16Command::opcode() {
+            return Err(ScsiError::from_cause(ErrorCause::ParseError));
+        }
+        let transfer_blocks = BE::read_u32(&buffer[10..]);
+        Ok(Write16Command {
+            block_address: BE::read_u64(&buffer[2..]),
+            block_size: wrapper
+                .data_transfer_length
+                .checked_div(transfer_blocks)
+                .unwrap_or(0),
+            transfer_blocks,
+        })
+    }
+}
+
+impl From<Write10Command> for Write16Command {
+    fn from(command: Write10Command) -> Write16Command {
+        Write16Command {
+            block_address: u64::from(command.block_address),
+            block_size: command.block_size,
+            transfer_blocks: u32::from(command.transfer_blocks),
+        }
+    }
+}
+
+impl TryFrom<Write16Command> for Write10Command {
+    type Error = ScsiError;
+
+    /// Narrows the command for responders that only handle `Write10Command`s.
+    ///
+    /// # Errors
+    /// Returns an `UnsupportedOperationError` if the block address or count do
+    /// not fit in the 10 byte form.
+    fn try_from(command: Write16Command) -> Result<Write10Command, ScsiError> {
+        match (
+            u32::try_from(command.block_address),
+            u16::try_from(command.transfer_blocks),
+        ) {
+            (Ok(block_address), Ok(transfer_blocks)) => Ok(Write10Command {
+                block_address,
+                block_size: command.block_size,
+                transfer_blocks,
+            }),
+            _ => Err(ScsiError::from_cause(ErrorCause::UnsupportedOperationError)),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::Write16Command;
+    use crate::scsi::commands::Write10Command;
+    use crate::{BufferPullable, BufferPushable, ErrorCause};
+
+    #[test]
+    pub fn test_write16() {
+        let expected: [u8; 31] = [
+            0x55, 0x53, 0x42, 0x43, 0x00, 0x00, 0x00, 0x00, 0x00, 0x04, 0x00, 0x00, 0x00, 0x00,
+            0x10, 0x8a, 0x00, 0x00, 0x00, 0x00, 0x01, 0x00, 0x00, 0x00, 0x08, 0x00, 0x00, 0x00,
+            0x02, 0x00, 0x00,
+        ];
+        let mut buff = [0; 32];
+        let command = Write16Command::new(0x1_0000_0008, 2, 512);
+        let pushed = command.push_to_buffer(&mut buff).unwrap();
+        assert_eq!(pushed, 31);
+        assert_eq!(&buff[0..pushed], &expected[0..pushed]);
+        assert_eq!(Write16Command::pull_from_buffer(buff).unwrap(), command);
+
+        let err = Write10Command::try_from(command).err().unwrap();
+        assert_eq!(err.cause, ErrorCause::UnsupportedOperationError);
+        let narrow = Write10Command::new(8 * 512, 2 * 512, 512).unwrap();
+        let wide = Write16Command::from(narrow);
+        assert_eq!(wide, Write16Command::new(8, 2, 512));
+        assert_eq!(Write10Command::try_from(wide).unwrap(), narrow);
+    }
+}
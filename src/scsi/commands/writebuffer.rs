@@ -0,0 +1,320 @@
+use crate::error::{ErrorCause, ScsiError};
+use crate::scsi::commands::{Command, CommandBlockWrapper, Direction};
+use crate::traits::{BufferPullable, BufferPushable};
+
+use byteorder::{ByteOrder, BE};
+
+/// What a `WriteBufferCommand` does with the data it sends.
+#[derive(Clone, Copy, Eq, PartialEq, Debug, Default)]
+pub enum WriteBufferMode {
+    /// Writes the data into the buffer at the command's `buffer_offset`.
+    #[default]
+    Data,
+
+    /// Downloads a whole microcode image in a single command, saves it and
+    /// activates it.
+    DownloadMicrocodeSaveAndActivate,
+
+    /// Downloads one segment of a microcode image at the command's
+    /// `buffer_offset`, saving and activating the image once the device
+    /// has all of it.
+    DownloadMicrocodeWithOffsetsSaveAndActivate,
+
+    /// Writes the data into the echo buffer, which a later
+    /// `ReadBufferMode::EchoBuffer` returns unchanged.
+    EchoBuffer,
+
+    /// Downloads one segment of a microcode image at the command's
+    /// `buffer_offset`, saving the image once the device has all of it but
+    /// leaving it inactive until an `ActivateDeferredMicrocode`.
+    DownloadMicrocodeWithOffsetsSaveAndDefer,
+
+    /// Activates a microcode image saved by
+    /// `DownloadMicrocodeWithOffsetsSaveAndDefer`. Commands in this mode
+    /// carry no data.
+    ActivateDeferredMicrocode,
+}
+
+impl WriteBufferMode {
+    fn from_code(code: u8) -> Result<WriteBufferMode, ScsiError> {
+        match code {
+            0x02 => Ok(WriteBufferMode::Data),
+            0x05 => Ok(WriteBufferMode::DownloadMicrocodeSaveAndActivate),
+            0x07 => Ok(WriteBufferMode::DownloadMicrocodeWithOffsetsSaveAndActivate),
+            0x0a => Ok(WriteBufferMode::EchoBuffer),
+            0x0e => Ok(WriteBufferMode::DownloadMicrocodeWithOffsetsSaveAndDefer),
+            0x0f => Ok(WriteBufferMode::ActivateDeferredMicrocode),
+            _ => Err(ScsiError::from_cause(ErrorCause::ParseError)),
+        }
+    }
+
+    fn code(self) -> u8 {
+        match self {
+            WriteBufferMode::Data => 0x02,
+            WriteBufferMode::DownloadMicrocodeSaveAndActivate => 0x05,
+            WriteBufferMode::DownloadMicrocodeWithOffsetsSaveAndActivate => 0x07,
+            WriteBufferMode::EchoBuffer => 0x0a,
+            WriteBufferMode::DownloadMicrocodeWithOffsetsSaveAndDefer => 0x0e,
+            WriteBufferMode::ActivateDeferredMicrocode => 0x0f,
+        }
+    }
+
+    /// Whether commands in this mode place their data at a `buffer_offset`,
+    /// so that large images can be sent over several commands.
+    pub fn takes_offset(self) -> bool {
+        matches!(
+            self,
+            WriteBufferMode::Data
+                | WriteBufferMode::DownloadMicrocodeWithOffsetsSaveAndActivate
+                | WriteBufferMode::DownloadMicrocodeWithOffsetsSaveAndDefer
+        )
+    }
+
+    /// Whether this mode downloads a microcode image.
+    pub fn is_microcode_download(self) -> bool {
+        matches!(
+            self,
+            WriteBufferMode::DownloadMicrocodeSaveAndActivate
+                | WriteBufferMode::DownloadMicrocodeWithOffsetsSaveAndActivate
+                | WriteBufferMode::DownloadMicrocodeWithOffsetsSaveAndDefer
+        )
+    }
+}
+
+/// Writes data into one of the device's buffers, which is mostly used to
+/// download new firmware ("microcode") to it.
+///
+/// Large images are sent over several commands in one of the modes with
+/// offsets, each carrying the segment that starts at its `buffer_offset`.
+/// The segments must be sent in order, and their offsets must be multiples of
+/// the boundary reported by READ BUFFER in descriptor mode.
+#[derive(Clone, Copy, Eq, PartialEq, Debug, Default)]
+pub struct WriteBufferCommand {
+    /// What the device should do with the data.
+    pub mode: WriteBufferMode,
+
+    /// The buffer to write to; devices with a single buffer use 0.
+    pub buffer_id: u8,
+
+    /// Where the data goes within the buffer, for modes that take an offset.
+    pub buffer_offset: u32,
+
+    /// The number of bytes sent along with the command.
+    pub parameter_list_length: u32,
+}
+
+impl WriteBufferCommand {
+    /// The largest `buffer_offset` or `parameter_list_length` a command can
+    /// carry, as both are 24 bit fields.
+    pub const MAX_LENGTH: u32 = 0xff_ffff;
+
+    /// Constructs a command writing `parameter_list_length` bytes to buffer
+    /// `buffer_id` at `buffer_offset`.
+    ///
+    /// # Errors
+    /// Returns a `TransferTooLargeError` if `buffer_offset` or
+    /// `parameter_list_length` does not fit in its 24 bit field.
+    pub fn new(
+        mode: WriteBufferMode,
+        buffer_id: u8,
+        buffer_offset: u32,
+        parameter_list_length: u32,
+    ) -> Result<WriteBufferCommand, ScsiError> {
+        let largest = buffer_offset.max(parameter_list_length);
+        if largest > WriteBufferCommand::MAX_LENGTH {
+            return Err(ScsiError::from_cause(ErrorCause::TransferTooLargeError {
+                actual: largest as usize,
+                max: WriteBufferCommand::MAX_LENGTH as usize,
+            }));
+        }
+        Ok(WriteBufferCommand {
+            mode,
+            buffer_id,
+            buffer_offset,
+            parameter_list_length,
+        })
+    }
+
+    /// Constructs a command writing `length` bytes, where lengths too long
+    /// for the command are rejected like those that overflow its 24 bit field.
+    pub(crate) fn with_length(
+        mode: WriteBufferMode,
+        buffer_id: u8,
+        buffer_offset: u32,
+        length: usize,
+    ) -> Result<WriteBufferCommand, ScsiError> {
+        let length = u32::try_from(length).unwrap_or(u32::MAX);
+        WriteBufferCommand::new(mode, buffer_id, buffer_offset, length)
+    }
+}
+
+impl Command for WriteBufferCommand {
+    fn opcode() -> u8 {
+        0x3b
+    }
+    fn length() -> u8 {
+        10
+    }
+    fn wrapper(&self) -> CommandBlockWrapper {
+        let direction = if self.parameter_list_length == 0 {
+            Direction::NONE
+        } else {
+            Direction::OUT
+        };
+        CommandBlockWrapper::new(
+            self.parameter_list_length,
+            direction,
+            0,
+            WriteBufferCommand::length(),
+        )
+    }
+}
+
+impl BufferPushable for WriteBufferCommand {
+    fn push_to_buffer<B: AsMut<[u8]>>(&self, mut buffer: B) -> Result<usize, ScsiError> {
+        let rval = self.wrapper().push_to_buffer(buffer.as_mut())?;
+        let buffer = &mut buffer.as_mut()[rval..];
+        buffer[0] = WriteBufferCommand::opcode();
+        buffer[1] = self.mode.code();
+        buffer[2] = self.buffer_id;
+        BE::write_u24(&mut buffer[3..], self.buffer_offset);
+        BE::write_u24(&mut buffer[6..], self.parameter_list_length);
+        buffer[9] = 0;
+        Ok(rval + 10)
+    }
+}
+
+impl BufferPullable for WriteBufferCommand {
+    fn pull_from_buffer<B: AsRef<[u8]>>(buffer: B) -> Result<Self, ScsiError> {
+        let wrapper: CommandBlockWrapper = CommandBlockWrapper::pull_from_buffer(buffer.as_ref())?;
+        if wrapper.cb_length != WriteBufferCommand::length()
+            || (wrapper.data_transfer_length != 0 && wrapper.direction != Direction::OUT)
+        {
+            return Err(ScsiError::from_cause(ErrorCause::ParseError));
+        }
+        let buffer = &buffer.as_ref()[15..];
+        if buffer[0] != WriteBufferCommand::opcode() {
+            return Err(ScsiError::from_cause(ErrorCause::ParseError));
+        }
+        Ok(WriteBufferCommand {
+            mode: WriteBufferMode::from_code(buffer[1] & 0x1f)?,
+            buffer_id: buffer[2],
+            buffer_offset: BE::read_u24(&buffer[3..]),
+            parameter_list_length: BE::read_u24(&buffer[6..]),
+        })
+    }
+}
+
+/// Splits a microcode image into the WRITE BUFFER commands that download it.
+pub(crate) struct MicrocodeSegments<'i> {
+    mode: WriteBufferMode,
+    buffer_id: u8,
+    image: &'i [u8],
+    sent: usize,
+    segment_size: usize,
+}
+
+impl<'i> MicrocodeSegments<'i> {
+    /// Checks that `image` can be downloaded in `mode`, in segments of
+    /// `segment_size` bytes if the mode takes offsets.
+    pub(crate) fn new(
+        mode: WriteBufferMode,
+        buffer_id: u8,
+        image: &'i [u8],
+        segment_size: usize,
+    ) -> Result<Self, ScsiError> {
+        if !mode.is_microcode_download() {
+            return Err(ScsiError::from_cause(ErrorCause::UnsupportedOperationError));
+        }
+        let max = WriteBufferCommand::MAX_LENGTH as usize;
+        let segment_size = if mode.takes_offset() && segment_size != 0 {
+            segment_size
+        } else {
+            image.len()
+        };
+        // The last segment has to start at an offset the command can carry.
+        let last_offset = image.len().saturating_sub(1) / segment_size.max(1) * segment_size;
+        let largest = segment_size.max(last_offset);
+        if largest > max {
+            return Err(ScsiError::from_cause(ErrorCause::TransferTooLargeError {
+                actual: largest,
+                max,
+            }));
+        }
+        Ok(MicrocodeSegments {
+            mode,
+            buffer_id,
+            image,
+            sent: 0,
+            segment_size,
+        })
+    }
+}
+
+impl<'i> Iterator for MicrocodeSegments<'i> {
+    type Item = (WriteBufferCommand, &'i [u8]);
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if self.sent >= self.image.len() {
+            return None;
+        }
+        let length = self.segment_size.min(self.image.len() - self.sent);
+        let segment = &self.image[self.sent..self.sent + length];
+        // Both fields were checked to fit when the image was split.
+        let command = WriteBufferCommand {
+            mode: self.mode,
+            buffer_id: self.buffer_id,
+            buffer_offset: self.sent as u32,
+            parameter_list_length: length as u32,
+        };
+        self.sent += length;
+        Some((command, segment))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{WriteBufferCommand, WriteBufferMode};
+    use crate::{BufferPullable, BufferPushable, ErrorCause};
+
+    #[test]
+    pub fn test_writebuffer() {
+        let expected: [u8; 25] = [
+            0x55, 0x53, 0x42, 0x43, 0x00, 0x00, 0x00, 0x00, 0x00, 0x10, 0x00, 0x00, 0x00, 0x00,
+            0x0a, 0x3b, 0x0e, 0x01, 0x02, 0x00, 0x00, 0x00, 0x10, 0x00, 0x00,
+        ];
+        let mut buff = [0; 32];
+        let command = WriteBufferCommand::new(
+            WriteBufferMode::DownloadMicrocodeWithOffsetsSaveAndDefer,
+            1,
+            0x2_0000,
+            0x1000,
+        )
+        .unwrap();
+        let pushed = command.push_to_buffer(&mut buff).unwrap();
+        assert_eq!(pushed, 25);
+        assert_eq!(&buff[0..pushed], &expected[..]);
+        assert_eq!(WriteBufferCommand::pull_from_buffer(buff).unwrap(), command);
+
+        // Activating needs no data.
+        let command =
+            WriteBufferCommand::new(WriteBufferMode::ActivateDeferredMicrocode, 0, 0, 0).unwrap();
+        command.push_to_buffer(&mut buff).unwrap();
+        assert_eq!(buff[12], 0);
+        assert_eq!(buff[16], 0x0f);
+        assert_eq!(WriteBufferCommand::pull_from_buffer(buff).unwrap(), command);
+
+        // Unknown modes are rejected.
+        buff[16] = 0x01;
+        assert!(WriteBufferCommand::pull_from_buffer(buff).is_err());
+
+        let err = WriteBufferCommand::new(WriteBufferMode::Data, 0, 0, 0x100_0000).unwrap_err();
+        assert_eq!(
+            err.cause,
+            ErrorCause::TransferTooLargeError {
+                actual: 0x100_0000,
+                max: 0xff_ffff,
+            }
+        );
+    }
+}
@@ -0,0 +1,295 @@
+use crate::error::{ErrorCause, ScsiError};
+use crate::scsi::commands::{Command, CommandBlockWrapper, Direction};
+use crate::traits::{BufferPullable, BufferPushable};
+
+use byteorder::{ByteOrder, BE};
+
+/// The bit in the second byte of a WRITE SAME command asking the device to
+/// unmap the blocks if it can, rather than writing them.
+const UNMAP_FLAG: u8 = 0x8;
+
+/// The bit in the second byte of a WRITE SAME command asking for unmapped
+/// blocks to be anchored.
+const ANCHOR_FLAG: u8 = 0x10;
+
+/// The bit in the second byte of a WRITE SAME(16) command saying that no
+/// block is sent, and the blocks should be filled with zeroes.
+const NO_DATA_OUT_FLAG: u8 = 0x1;
+
+/// Writes a single block, sent along with the command, to a range of blocks.
+///
+/// With `unmap` set, the device may release the blocks instead, as long as
+/// they read back the same as the written block afterwards, which makes a
+/// WRITE SAME of zeroes a way to discard blocks on devices without UNMAP.
+#[derive(Clone, Copy, Eq, PartialEq, Debug, Default)]
+pub struct WriteSame10Command {
+    /// The first block to write.
+    pub block_address: u32,
+
+    /// The number of bytes in a single block.
+    pub block_size: u32,
+
+    /// The number of blocks to write; 0 asks for every block up to the end of
+    /// the device, which not all devices allow.
+    pub transfer_blocks: u16,
+
+    /// Whether the device should unmap the blocks if it can.
+    pub unmap: bool,
+
+    /// Whether unmapped blocks should be anchored, keeping their storage
+    /// allocated.
+    pub anchor: bool,
+}
+
+impl WriteSame10Command {
+    /// Constructs a command writing the same block to the `transfer_blocks`
+    /// blocks starting at `block_address`.
+    pub fn new(block_address: u32, transfer_blocks: u16, block_size: u32) -> WriteSame10Command {
+        WriteSame10Command {
+            block_address,
+            block_size,
+            transfer_blocks,
+            unmap: false,
+            anchor: false,
+        }
+    }
+}
+
+impl Command for WriteSame10Command {
+    fn opcode() -> u8 {
+        0x41
+    }
+    fn length() -> u8 {
+        10
+    }
+    fn wrapper(&self) -> CommandBlockWrapper {
+        CommandBlockWrapper::new(
+            self.block_size,
+            Direction::OUT,
+            0,
+            WriteSame10Command::length(),
+        )
+    }
+}
+
+impl BufferPushable for WriteSame10Command {
+    fn push_to_buffer<B: AsMut<[u8]>>(&self, mut buffer: B) -> Result<usize, ScsiError> {
+        let rval = self.wrapper().push_to_buffer(buffer.as_mut())?;
+        let buffer = &mut buffer.as_mut()[rval..];
+        buffer[0] = WriteSame10Command::opcode();
+        buffer[1] = flags(self.unmap, self.anchor);
+        BE::write_u32(&mut buffer[2..], self.block_address);
+        buffer[6] = 0;
+        BE::write_u16(&mut buffer[7..], self.transfer_blocks);
+        buffer[9] = 0;
+        Ok(rval + 10)
+    }
+}
+
+impl BufferPullable for WriteSame10Command {
+    fn pull_from_buffer<B: AsRef<[u8]>>(buffer: B) -> Result<Self, ScsiError> {
+        let wrapper: CommandBlockWrapper = CommandBlockWrapper::pull_from_buffer(buffer.as_ref())?;
+        if wrapper.direction != Direction::OUT || wrapper.cb_length != WriteSame10Command::length()
+        {
+            return Err(ScsiError::from_cause(ErrorCause::ParseError));
+        }
+        let buffer = &buffer.as_ref()[15..];
+        if buffer[0] != WriteSame10Command::opcode() {
+            return Err(ScsiError::from_cause(ErrorCause::ParseError));
+        }
+        Ok(WriteSame10Command {
+            block_address: BE::read_u32(&buffer[2..]),
+            block_size: wrapper.data_transfer_length,
+            transfer_blocks: BE::read_u16(&buffer[7..]),
+            unmap: buffer[1] & UNMAP_FLAG != 0,
+            anchor: buffer[1] & ANCHOR_FLAG != 0,
+        })
+    }
+}
+
+/// The 16 byte version of `WriteSame10Command`, which can address every
+/// block of large devices.
+#[derive(Clone, Copy, Eq, PartialEq, Debug, Default)]
+pub struct WriteSame16Command {
+    /// The first block to write.
+    pub block_address: u64,
+
+    /// The number of bytes in a single block.
+    pub block_size: u32,
+
+    /// The number of blocks to write; 0 asks for every block up to the end of
+    /// the device, which not all devices allow.
+    pub transfer_blocks: u32,
+
+    /// Whether the device should unmap the blocks if it can.
+    pub unmap: bool,
+
+    /// Whether unmapped blocks should be anchored, keeping their storage
+    /// allocated.
+    pub anchor: bool,
+
+    /// If set, no block is sent along with the command, and the blocks are
+    /// filled with zeroes instead.
+    pub no_data_out: bool,
+}
+
+impl WriteSame16Command {
+    /// Constructs a command writing the same block to the `transfer_blocks`
+    /// blocks starting at `block_address`.
+    pub fn new(block_address: u64, transfer_blocks: u32, block_size: u32) -> WriteSame16Command {
+        WriteSame16Command {
+            block_address,
+            block_size,
+            transfer_blocks,
+            unmap: false,
+            anchor: false,
+            no_data_out: false,
+        }
+    }
+
+    /// Constructs a command asking the device to unmap the `transfer_blocks`
+    /// blocks starting at `block_address`, leaving them reading back as
+    /// zeroes, without sending a block along with it.
+    pub fn zero_unmap(block_address: u64, transfer_blocks: u32) -> WriteSame16Command {
+        WriteSame16Command {
+            unmap: true,
+            no_data_out: true,
+            ..WriteSame16Command::new(block_address, transfer_blocks, 0)
+        }
+    }
+}
+
+impl Command for WriteSame16Command {
+    fn opcode() -> u8 {
+        0x93
+    }
+    fn length() -> u8 {
+        16
+    }
+    fn wrapper(&self) -> CommandBlockWrapper {
+        let (length, direction) = if self.no_data_out {
+            (0, Direction::NONE)
+        } else {
+            (self.block_size, Direction::OUT)
+        };
+        CommandBlockWrapper::new(length, direction, 0, WriteSame16Command::length())
+    }
+}
+
+impl BufferPushable for WriteSame16Command {
+    fn push_to_buffer<B: AsMut<[u8]>>(&self, mut buffer: B) -> Result<usize, ScsiError> {
+        let rval = self.wrapper().push_to_buffer(buffer.as_mut())?;
+        let buffer = &mut buffer.as_mut()[rval..];
+        buffer[0] = WriteSame16Command::opcode();
+        buffer[1] = flags(self.unmap, self.anchor);
+        if self.no_data_out {
+            buffer[1] |= NO_DATA_OUT_FLAG;
+        }
+        BE::write_u64(&mut buffer[2..], self.block_address);
+        BE::write_u32(&mut buffer[10..], self.transfer_blocks);
+        buffer[14] = 0;
+        buffer[15] = 0;
+        Ok(rval + 16)
+    }
+}
+
+impl BufferPullable for WriteSame16Command {
+    fn pull_from_buffer<B: AsRef<[u8]>>(buffer: B) -> Result<Self, ScsiError> {
+        let wrapper: CommandBlockWrapper = CommandBlockWrapper::pull_from_buffer(buffer.as_ref())?;
+        if wrapper.cb_length != WriteSame16Command::length() {
+            return Err(ScsiError::from_cause(ErrorCause::ParseError));
+        }
+        let buffer = &buffer.as_ref()[15..];
+        if buffer[0] != WriteSame16Command::opcode() {
+            return Err(ScsiError::from_cause(ErrorCause::ParseError));
+        }
+        let no_data_out = buffer[1] & NO_DATA_OUT_FLAG != 0;
+        if !no_data_out && wrapper.direction != Direction::OUT {
+            return Err(ScsiError::from_cause(ErrorCause::ParseError));
+        }
+        Ok(WriteSame16Command {
+            block_address: BE::read_u64(&buffer[2..]),
+            block_size: wrapper.data_transfer_length,
+            transfer_blocks: BE::read_u32(&buffer[10..]),
+            unmap: buffer[1] & UNMAP_FLAG != 0,
+            anchor: buffer[1] & ANCHOR_FLAG != 0,
+            no_data_out,
+        })
+    }
+}
+
+impl From<WriteSame10Command> for WriteSame16Command {
+    fn from(command: WriteSame10Command) -> WriteSame16Command {
+        WriteSame16Command {
+            block_address: u64::from(command.block_address),
+            block_size: command.block_size,
+            transfer_blocks: u32::from(command.transfer_blocks),
+            unmap: command.unmap,
+            anchor: command.anchor,
+            no_data_out: false,
+        }
+    }
+}
+
+/// The second byte shared by both WRITE SAME commands.
+fn flags(unmap: bool, anchor: bool) -> u8 {
+    let mut flags = 0;
+    if unmap {
+        flags |= UNMAP_FLAG;
+    }
+    if anchor {
+        flags |= ANCHOR_FLAG;
+    }
+    flags
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{WriteSame10Command, WriteSame16Command};
+    use crate::{BufferPullable, BufferPushable};
+
+    #[test]
+    pub fn test_writesame10() {
+        let expected: [u8; 31] = [
+            0x55, 0x53, 0x42, 0x43, 0x00, 0x00, 0x00, 0x00, 0x00, 0x02, 0x00, 0x00, 0x00, 0x00,
+            0x0a, 0x41, 0x08, 0x00, 0x01, 0x02, 0x03, 0x00, 0x00, 0x40, 0x00, 0x00, 0x00, 0x00,
+            0x00, 0x00, 0x00,
+        ];
+        let mut buff = [0; 32];
+        let mut command = WriteSame10Command::new(0x0001_0203, 64, 512);
+        command.unmap = true;
+        let pushed = command.push_to_buffer(&mut buff).unwrap();
+        assert_eq!(pushed, 25);
+        assert_eq!(&buff[0..pushed], &expected[0..pushed]);
+        let pulled = WriteSame10Command::pull_from_buffer(buff).unwrap();
+        assert_eq!(pulled, command);
+
+        let widened = WriteSame16Command::from(command);
+        assert_eq!(widened.block_address, 0x0001_0203);
+        assert_eq!(widened.transfer_blocks, 64);
+        assert!(widened.unmap && !widened.no_data_out);
+    }
+
+    #[test]
+    pub fn test_writesame16() {
+        let expected: [u8; 31] = [
+            0x55, 0x53, 0x42, 0x43, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00,
+            0x10, 0x93, 0x09, 0x00, 0x00, 0x01, 0x02, 0x03, 0x04, 0x05, 0x06, 0x00, 0x01, 0x00,
+            0x00, 0x00, 0x00,
+        ];
+        let mut buff = [0; 32];
+        let command = WriteSame16Command::zero_unmap(0x0102_0304_0506, 0x0001_0000);
+        let pushed = command.push_to_buffer(&mut buff).unwrap();
+        assert_eq!(pushed, 31);
+        assert_eq!(&buff[0..pushed], &expected[0..pushed]);
+        let pulled = WriteSame16Command::pull_from_buffer(buff).unwrap();
+        assert_eq!(pulled, command);
+
+        let mut command = WriteSame16Command::new(7, 3, 4096);
+        command.anchor = true;
+        command.push_to_buffer(&mut buff).unwrap();
+        assert_eq!(&buff[8..12], &[0x00, 0x10, 0x00, 0x00]);
+        assert_eq!(buff[12], 0);
+        assert_eq!(WriteSame16Command::pull_from_buffer(buff).unwrap(), command);
+    }
+}
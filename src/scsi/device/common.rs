@@ -0,0 +1,528 @@
+use crate::error::{ErrorCause, ScsiError, UsbTransferDirection};
+use crate::scsi::commands::ScsiStatus;
+use crate::scsi::commands::UnmapBlockDescriptor;
+use crate::scsi::commands::{BlockLimitsPage, Command, CommandStatusWrapper, Direction};
+use crate::scsi::commands::{CompareAndWriteCommand, Read10Command, UnmapCommand};
+use crate::scsi::commands::{PeripheralDeviceType, ReadCapacity16Response, RequestSenseResponse};
+use crate::traits::BufferPullable;
+
+/// The number of times `TestUnitReady` is sent during initialization before
+/// giving up; devices commonly fail the first one with a unit attention after
+/// a reset or media change.
+pub(crate) const TEST_UNIT_READY_ATTEMPTS: usize = 3;
+
+/// The logical block size used by MMC (CD/DVD) devices for `Read10Command`s.
+const MMC_BLOCK_SIZE: u32 = 2048;
+
+/// What a block device learned about its device while connecting, along with
+/// the limits placed on its transfers since.
+#[derive(Clone, Copy, Debug)]
+pub(crate) struct DeviceInfo {
+    pub(crate) block_size: u32,
+    pub(crate) num_blocks: u64,
+    pub(crate) device_type: PeripheralDeviceType,
+    pub(crate) block_limits: Option<BlockLimitsPage>,
+    pub(crate) user_max_transfer_blocks: u32,
+}
+
+impl DeviceInfo {
+    /// Describes a device of type `device_type` with the given capacity.
+    ///
+    /// MMC (CD/DVD) devices always use 2048 byte blocks, regardless of what
+    /// they report.
+    pub(crate) fn new(
+        device_type: PeripheralDeviceType,
+        capacity: &ReadCapacity16Response,
+    ) -> Self {
+        let block_size = if device_type == PeripheralDeviceType::CdDvd {
+            MMC_BLOCK_SIZE
+        } else {
+            capacity.block_length
+        };
+        DeviceInfo {
+            block_size,
+            num_blocks: capacity.logical_block_address.saturating_add(1),
+            device_type,
+            block_limits: None,
+            user_max_transfer_blocks: 0,
+        }
+    }
+
+    /// The largest number of blocks sent in a single read or write command.
+    pub(crate) fn max_transfer_blocks(&self) -> u32 {
+        let mut max_blocks = Read10Command::MAX_TRANSFER_BLOCKS;
+        if self.user_max_transfer_blocks != 0 {
+            max_blocks = max_blocks.min(self.user_max_transfer_blocks);
+        }
+        if let Some(page) = self.block_limits {
+            if page.max_transfer_length != 0 {
+                max_blocks = max_blocks.min(page.max_transfer_length);
+            }
+        }
+        max_blocks
+    }
+
+    /// The largest number of blocks covered by a single WRITE SAME command.
+    pub(crate) fn max_write_same_blocks(&self) -> u32 {
+        match self.block_limits {
+            Some(page) if page.max_write_same_length != 0 => {
+                page.max_write_same_length.min(u64::from(u32::MAX)) as u32
+            }
+            _ => u32::MAX,
+        }
+    }
+
+    /// The largest number of blocks covered by a single COMPARE AND WRITE
+    /// command.
+    pub(crate) fn max_compare_and_write_blocks(&self) -> u32 {
+        match self.block_limits {
+            Some(page) if page.max_compare_and_write_length != 0 => {
+                u32::from(page.max_compare_and_write_length)
+            }
+            _ => u32::from(u8::MAX),
+        }
+    }
+
+    /// The total capacity of the device in bytes, saturating at `u64::MAX`
+    /// for devices that report more than that.
+    pub(crate) fn capacity_bytes(&self) -> u64 {
+        self.num_blocks.saturating_mul(u64::from(self.block_size))
+    }
+
+    /// Whether or not the device can only be read from.
+    pub(crate) fn is_read_only(&self) -> bool {
+        self.device_type == PeripheralDeviceType::CdDvd
+    }
+
+    /// Converts a byte offset and length into a block address and count.
+    pub(crate) fn offset_to_blocks(
+        &self,
+        offset: u32,
+        length: usize,
+    ) -> Result<(u64, u32), ScsiError> {
+        let block_size = self.block_size as usize;
+        if length % block_size != 0 {
+            return Err(ScsiError::from_cause(
+                ErrorCause::NonBlocksizeMultipleLengthError {
+                    actual: length,
+                    block_size,
+                },
+            ));
+        }
+        if (offset as usize) % block_size != 0 {
+            return Err(ScsiError::from_cause(
+                ErrorCause::NonBlocksizeMultipleLengthError {
+                    actual: offset as usize,
+                    block_size,
+                },
+            ));
+        }
+        let count = length / block_size;
+        if count > u32::MAX as usize {
+            return Err(ScsiError::from_cause(ErrorCause::TransferTooLargeError {
+                actual: count,
+                max: u32::MAX as usize,
+            }));
+        }
+        Ok((u64::from(offset / self.block_size), count as u32))
+    }
+
+    /// Checks that the `count` blocks starting at `lba` are on the device.
+    pub(crate) fn check_range(&self, lba: u64, count: u32) -> Result<(), ScsiError> {
+        self.check_extent(lba, u64::from(count))
+    }
+
+    /// Checks that the `count` blocks starting at `lba` are on the device,
+    /// for operations that are not limited to 32 bit block counts.
+    pub(crate) fn check_extent(&self, lba: u64, count: u64) -> Result<(), ScsiError> {
+        let in_range = lba
+            .checked_add(count)
+            .is_some_and(|end| end <= self.num_blocks);
+        if !in_range {
+            return Err(ScsiError::from_cause(
+                ErrorCause::BlockAddressOutOfRangeError {
+                    block_address: lba,
+                    num_blocks: self.num_blocks,
+                },
+            ));
+        }
+        Ok(())
+    }
+
+    /// Checks that `buffer_length` bytes can hold `count` blocks, returning
+    /// the length of the blocks in bytes.
+    pub(crate) fn check_buffer(
+        &self,
+        count: u32,
+        buffer_length: usize,
+    ) -> Result<usize, ScsiError> {
+        let length = count as usize * self.block_size as usize;
+        if buffer_length < length {
+            return Err(ScsiError::from_cause(ErrorCause::BufferTooSmallError {
+                expected: length,
+                actual: buffer_length,
+            }));
+        }
+        Ok(length)
+    }
+
+    /// Checks the arguments of a compare and write, building the command for it.
+    pub(crate) fn compare_and_write_command(
+        &self,
+        lba: u64,
+        expected: &[u8],
+        new: &[u8],
+    ) -> Result<CompareAndWriteCommand, ScsiError> {
+        if self.is_read_only() {
+            return Err(ScsiError::from_cause(ErrorCause::ReadOnlyDeviceError));
+        }
+        let block_size = self.block_size as usize;
+        if expected.len() % block_size != 0 {
+            return Err(ScsiError::from_cause(
+                ErrorCause::NonBlocksizeMultipleLengthError {
+                    actual: expected.len(),
+                    block_size,
+                },
+            ));
+        }
+        if new.len() != expected.len() {
+            return Err(ScsiError::from_cause(ErrorCause::BufferTooSmallError {
+                expected: expected.len(),
+                actual: new.len(),
+            }));
+        }
+        let blocks = expected.len() / block_size;
+        let max = self.max_compare_and_write_blocks() as usize;
+        if blocks > max {
+            return Err(ScsiError::from_cause(ErrorCause::TransferTooLargeError {
+                actual: blocks,
+                max,
+            }));
+        }
+        self.check_range(lba, blocks as u32)?;
+        Ok(CompareAndWriteCommand::new(
+            lba,
+            blocks as u8,
+            self.block_size,
+        ))
+    }
+}
+
+/// Clears `prev_csw` before a new operation, returning the tag it held.
+pub(crate) fn take_prev_tag(prev_csw: &mut Option<CommandStatusWrapper>) -> u32 {
+    prev_csw.take().map_or(0, |csw| csw.tag)
+}
+
+/// Records the status of a finished operation in `prev_csw`.
+pub(crate) fn finish_transfer(
+    prev_csw: &mut Option<CommandStatusWrapper>,
+    prev_tag: u32,
+    last_csw: Option<CommandStatusWrapper>,
+    residue: usize,
+) {
+    *prev_csw = last_csw.map(|mut csw| {
+        csw.tag = prev_tag + 1;
+        csw.data_residue = residue as u32;
+        csw
+    });
+}
+
+/// Records the status of an operation that stopped at a failed command: that
+/// of the last command that passed, or `prev` if none did, so the tags of
+/// later commands carry on from it.
+pub(crate) fn abort_transfer(
+    prev_csw: &mut Option<CommandStatusWrapper>,
+    prev: Option<CommandStatusWrapper>,
+    last_csw: Option<CommandStatusWrapper>,
+) {
+    match last_csw {
+        Some(_) => finish_transfer(prev_csw, prev.map_or(0, |csw| csw.tag), last_csw, 0),
+        None => *prev_csw = prev,
+    }
+}
+
+/// The most block descriptors `discard` puts in a single UNMAP command.
+const DISCARD_DESCRIPTORS: usize = 8;
+
+/// The size of the largest UNMAP parameter list sent by `discard`.
+pub(crate) const DISCARD_PARAMETERS_SIZE: usize =
+    UnmapCommand::HEADER_SIZE + DISCARD_DESCRIPTORS * UnmapBlockDescriptor::SIZE;
+
+/// Splits a discard into UNMAP commands that stay within the limits in the
+/// device's Block Limits page.
+pub(crate) struct DiscardBatches {
+    next: u64,
+    end: u64,
+    max_blocks: u64,
+    max_descriptors: usize,
+}
+
+impl DiscardBatches {
+    /// Plans the discard of the `count` blocks starting at `lba`, shrunk to
+    /// the whole units of the device's optimal unmap granularity they hold.
+    ///
+    /// # Errors
+    /// Returns a `ReadOnlyDeviceError` for read-only devices, a
+    /// `BlockAddressOutOfRangeError` if the blocks are past the end of the
+    /// device, or an `UnsupportedOperationError` if the device's Block Limits
+    /// page says it does not support UNMAP.
+    pub(crate) fn new(info: &DeviceInfo, lba: u64, count: u64) -> Result<Self, ScsiError> {
+        if info.is_read_only() {
+            return Err(ScsiError::from_cause(ErrorCause::ReadOnlyDeviceError));
+        }
+        info.check_extent(lba, count)?;
+        let end = lba + count;
+        let page = info.block_limits.unwrap_or(BlockLimitsPage {
+            max_unmap_lba_count: u32::MAX,
+            max_unmap_block_descriptor_count: DISCARD_DESCRIPTORS as u32,
+            ..Default::default()
+        });
+        if count != 0 && page.max_unmap_lba_count == 0 {
+            return Err(ScsiError::from_cause(ErrorCause::UnsupportedOperationError));
+        }
+        let granularity = u64::from(page.optimal_unmap_granularity.max(1));
+        let alignment = u64::from(page.unmap_granularity_alignment.unwrap_or(0)) % granularity;
+        // How far each block is past the start of its granularity unit.
+        let unit_offset = |block: u64| (block + granularity - alignment) % granularity;
+        let first = match unit_offset(lba) {
+            0 => lba,
+            offset => lba + (granularity - offset),
+        };
+        // A range ending before its first whole unit has nothing to discard.
+        let last = end.checked_sub(unit_offset(end)).unwrap_or(first);
+        // Keep every descriptor but the last a whole number of units long.
+        let max_blocks = u64::from(page.max_unmap_lba_count);
+        let max_blocks = if max_blocks >= granularity {
+            max_blocks - max_blocks % granularity
+        } else {
+            max_blocks
+        };
+        Ok(DiscardBatches {
+            next: first,
+            end: last.max(first),
+            max_blocks,
+            max_descriptors: (page.max_unmap_block_descriptor_count as usize)
+                .clamp(1, DISCARD_DESCRIPTORS),
+        })
+    }
+
+    /// The number of blocks the discard covers.
+    pub(crate) fn blocks(&self) -> u64 {
+        self.end - self.next
+    }
+
+    /// Builds the next `UnmapCommand` along with its parameter list, or
+    /// returns `None` once the whole range has been covered.
+    pub(crate) fn next_batch(
+        &mut self,
+    ) -> Result<Option<(UnmapCommand, [u8; DISCARD_PARAMETERS_SIZE])>, ScsiError> {
+        let mut descriptors = [UnmapBlockDescriptor::default(); DISCARD_DESCRIPTORS];
+        let mut count = 0;
+        while count < self.max_descriptors && self.next < self.end {
+            let blocks = (self.end - self.next).min(self.max_blocks);
+            descriptors[count] = UnmapBlockDescriptor {
+                block_address: self.next,
+                block_count: blocks as u32,
+            };
+            self.next += blocks;
+            count += 1;
+        }
+        if count == 0 {
+            return Ok(None);
+        }
+        let command = UnmapCommand::new(count as u16)?;
+        let mut buffer = [0; DISCARD_PARAMETERS_SIZE];
+        command.push_parameter_list(&descriptors[..count], &mut buffer[..])?;
+        Ok(Some((command, buffer)))
+    }
+}
+
+/// Something that happened during a `ScsiBlockDevice::scrub`.
+#[derive(Clone, Copy, Eq, PartialEq, Debug)]
+pub enum ScrubEvent {
+    /// The first `checked` of the device's `total` blocks have been checked.
+    Progress {
+        /// The number of blocks checked so far.
+        checked: u64,
+
+        /// The number of blocks on the device.
+        total: u64,
+    },
+
+    /// The block at `lba` could not be verified.
+    BadBlock {
+        /// The address of the bad block.
+        lba: u64,
+
+        /// The sense data the device reported for the block.
+        sense: RequestSenseResponse,
+    },
+}
+
+/// Walks a scrub across the whole device, falling back to checking one block
+/// at a time whenever the device fails a chunk without saying which of its
+/// blocks was bad.
+pub(crate) struct Scrub {
+    next: u64,
+    end: u64,
+    chunk_blocks: u32,
+    single_until: u64,
+    bad_blocks: u64,
+}
+
+impl Scrub {
+    /// Plans a scrub verifying `chunk_blocks` blocks per chunk, or the most
+    /// blocks a single command may cover if it is 0.
+    pub(crate) fn new(info: &DeviceInfo, chunk_blocks: u32) -> Self {
+        let chunk_blocks = match chunk_blocks {
+            0 => info.max_transfer_blocks(),
+            blocks => blocks,
+        };
+        Scrub {
+            next: 0,
+            end: info.num_blocks,
+            chunk_blocks,
+            single_until: 0,
+            bad_blocks: 0,
+        }
+    }
+
+    /// The next chunk to verify, or `None` once the whole device has been
+    /// checked.
+    pub(crate) fn next_chunk(&self) -> Option<(u64, u32)> {
+        if self.next >= self.end {
+            return None;
+        }
+        let blocks = if self.next < self.single_until {
+            1
+        } else {
+            (self.end - self.next).min(u64::from(self.chunk_blocks)) as u32
+        };
+        Some((self.next, blocks))
+    }
+
+    /// Records that the chunk returned by `next_chunk` was verified.
+    pub(crate) fn passed(&mut self, blocks: u32) -> ScrubEvent {
+        self.next += u64::from(blocks);
+        ScrubEvent::Progress {
+            checked: self.next,
+            total: self.end,
+        }
+    }
+
+    /// Records that the chunk returned by `next_chunk` failed with `error`,
+    /// returning the bad block the device's `sense` data points to, if any.
+    ///
+    /// # Errors
+    /// Returns `error` if the sense data does not describe a medium or
+    /// hardware error, such as when the device does not support VERIFY.
+    pub(crate) fn failed(
+        &mut self,
+        blocks: u32,
+        sense: RequestSenseResponse,
+        error: ScsiError,
+    ) -> Result<Option<ScrubEvent>, ScsiError> {
+        if sense.sense_key != RequestSenseResponse::MEDIUM_ERROR
+            && sense.sense_key != RequestSenseResponse::HARDWARE_ERROR
+        {
+            return Err(error);
+        }
+        let start = self.next;
+        let end = start + u64::from(blocks);
+        let information = u64::from(sense.information);
+        let lba = if sense.valid && (start..end).contains(&information) {
+            information
+        } else if blocks > 1 {
+            self.single_until = end;
+            return Ok(None);
+        } else {
+            start
+        };
+        self.next = lba + 1;
+        self.bad_blocks += 1;
+        Ok(Some(ScrubEvent::BadBlock { lba, sense }))
+    }
+
+    /// The number of bad blocks found so far.
+    pub(crate) fn bad_blocks(&self) -> u64 {
+        self.bad_blocks
+    }
+}
+
+/// Parses a CSW read from the device, checking that all of it arrived.
+pub(crate) fn parse_csw(
+    buffer: &[u8; CommandStatusWrapper::SIZE as usize],
+    read_count: usize,
+) -> Result<CommandStatusWrapper, ScsiError> {
+    if read_count != CommandStatusWrapper::SIZE as usize {
+        return Err(ScsiError::from_cause(ErrorCause::UsbTransferError {
+            direction: UsbTransferDirection::In,
+        }));
+    }
+    CommandStatusWrapper::pull_from_buffer(buffer)
+}
+
+/// Checks that a whole CBW was sent.
+pub(crate) fn check_pushed(pushed_bytes: usize) -> Result<usize, ScsiError> {
+    if pushed_bytes != 31 {
+        Err(ScsiError::from_cause(ErrorCause::UsbTransferError {
+            direction: UsbTransferDirection::Out,
+        }))
+    } else {
+        Ok(pushed_bytes)
+    }
+}
+
+/// The number of bytes in `command`'s data phase, checked against the
+/// `buffer_length` bytes available for it. Commands moving data in the
+/// `rejected` direction cannot be run with the buffer.
+pub(crate) fn data_length<C: Command>(
+    command: &C,
+    buffer_length: usize,
+    rejected: Direction,
+) -> Result<usize, ScsiError> {
+    let transfer_length = command.wrapper().data_transfer_length as usize;
+    if transfer_length == 0 {
+        Ok(0)
+    } else if command.wrapper().direction == rejected {
+        Err(ScsiError::from_cause(ErrorCause::UnsupportedOperationError))
+    } else if buffer_length < transfer_length {
+        Err(ScsiError::from_cause(ErrorCause::BufferTooSmallError {
+            expected: transfer_length,
+            actual: buffer_length,
+        }))
+    } else {
+        Ok(transfer_length)
+    }
+}
+
+/// Checks that `csw` answers `command` and reports success, going by the
+/// channel's full SCSI `status` if it has one.
+///
+/// A command that failed with sense data to explain it is reported as a
+/// `FlagError` holding the CSW's status, leaving the sense data for REQUEST
+/// SENSE. Statuses without sense data are reported as the error for that
+/// status, such as a `ReservationConflictError`; see
+/// `CommunicationChannel::scsi_status`.
+pub(crate) fn check_status<C: Command>(
+    command: &C,
+    csw: CommandStatusWrapper,
+    status: Option<ScsiStatus>,
+) -> Result<CommandStatusWrapper, ScsiError> {
+    if csw.tag != command.wrapper().tag {
+        return Err(ScsiError::from_cause(ErrorCause::ParseError));
+    }
+    let status = match status {
+        Some(status) => status,
+        None => csw.scsi_status()?,
+    };
+    if let Some(err) = status.to_error() {
+        Err(err)
+    } else if !status.is_good() {
+        Err(ScsiError::from_cause(ErrorCause::FlagError {
+            flags: u32::from(csw.status),
+        }))
+    } else {
+        Ok(csw)
+    }
+}
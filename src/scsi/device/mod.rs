@@ -0,0 +1,1860 @@
+mod common;
+pub use self::common::*;
+
+use crate::error::{ErrorCause, ScsiError, UsbTransferDirection};
+use crate::scsi::commands::CompareAndWriteCommand;
+use crate::scsi::commands::SynchronizeCache10Command;
+use crate::scsi::commands::TestUnitReady;
+use crate::scsi::commands::{BlockLimitsPage, Command, CommandStatusWrapper, Direction};
+use crate::scsi::commands::{BufferDescriptor, EchoBufferDescriptor};
+use crate::scsi::commands::{
+    ErrorCounterKind, ErrorCounterPage, InformationalExceptionsPage, LogPage, LogPageControl,
+    LogSelectCommand, LogSenseCommand, SelfTestResultsPage, SolidStateMediaPage,
+    StartStopCyclePage, SupportedLogPages, TemperaturePage,
+};
+use crate::scsi::commands::{FormatParameters, FormatUnitCommand};
+use crate::scsi::commands::{
+    InquiryCommand, InquiryResponse, PeripheralDeviceType, VpdInquiryCommand,
+};
+use crate::scsi::commands::{MicrocodeSegments, WriteBufferCommand, WriteBufferMode};
+use crate::scsi::commands::{OverwriteParameters, SanitizeCommand};
+use crate::scsi::commands::{
+    PersistentReserveInCommand, PersistentReserveInServiceAction, PersistentReserveOutCommand,
+    PersistentReserveOutParameters, PersistentReserveOutServiceAction,
+};
+use crate::scsi::commands::{Read10Command, Read16Command};
+use crate::scsi::commands::{ReadBufferCommand, ReadBufferMode};
+use crate::scsi::commands::{ReadCapacity16Command, ReadCapacity16Response};
+use crate::scsi::commands::{ReadCapacityCommand, ReadCapacityResponse};
+use crate::scsi::commands::{Release6Command, Reserve6Command};
+use crate::scsi::commands::{RequestSenseCommand, RequestSenseResponse};
+use crate::scsi::commands::{ReservationCapabilitiesResponse, ReservationKeysResponse};
+use crate::scsi::commands::{ReservationResponse, ReservationType};
+use crate::scsi::commands::{UnmapCommand, WriteSame16Command};
+use crate::scsi::commands::{Verify10Command, Verify16Command, VerifyByteCheck};
+use crate::scsi::commands::{Write10Command, Write16Command};
+use crate::traits::{BufferPullable, BufferPushable, CommunicationChannel};
+
+/// Decides which peripheral device types a `ScsiBlockDevice` is willing to
+/// connect to.
+#[derive(Clone, Copy, Eq, PartialEq, Debug)]
+pub enum DeviceTypePolicy<'a> {
+    /// Only allow direct-access block devices, such as flash drives. This is
+    /// the policy used by `ScsiBlockDevice::new`.
+    DirectAccessOnly,
+
+    /// Allow any device type that stores data in logical blocks accessible
+    /// via `Read10Command`s; see `PeripheralDeviceType::is_block_device`.
+    BlockDevices,
+
+    /// Only allow the listed device types.
+    Only(&'a [PeripheralDeviceType]),
+}
+
+impl<'a> DeviceTypePolicy<'a> {
+    /// Whether or not this policy allows connecting to a device of type
+    /// `device_type`.
+    pub fn allows(&self, device_type: PeripheralDeviceType) -> bool {
+        match self {
+            DeviceTypePolicy::DirectAccessOnly => device_type == PeripheralDeviceType::DirectAccess,
+            DeviceTypePolicy::BlockDevices => device_type.is_block_device(),
+            DeviceTypePolicy::Only(allowed) => allowed.contains(&device_type),
+        }
+    }
+
+    /// Checks a device's inquiry data against the policy, returning its
+    /// peripheral device type if it is accepted.
+    pub(crate) fn check(
+        &self,
+        inquiry: &InquiryResponse,
+    ) -> Result<PeripheralDeviceType, ScsiError> {
+        let device_type = inquiry.peripheral_device_type();
+        if inquiry.device_qualifier != 0 || !self.allows(device_type) {
+            return Err(ScsiError::from_cause(ErrorCause::InvalidDeviceError));
+        }
+        Ok(device_type)
+    }
+}
+
+/// A struct that provides a simple, block-device-like interface around an SCSI device.
+/// This allows for reading and writing to the device at static offests, allowing for
+/// easy interaction with any file system crate.
+pub struct ScsiBlockDevice<CommType: CommunicationChannel> {
+    /// The raw communication channel this device is using.
+    ///
+    /// This is a public field to allow for the user to still manipulate the
+    /// backing device as necessary; however, this pattern is not generally
+    /// recommended nor required in a standard use case.
+    pub comm_channel: CommType,
+    info: DeviceInfo,
+
+    /// The `CommandStatusWrapper` returned from the SCSI device after the last
+    /// method ran. This can be used to check for error sitations or other
+    /// general status information.
+    ///
+    /// This can be `None` if either the device is in the middle of a command
+    /// (though currently this can only happen in a multi-threaded context),
+    /// or if the previous action the user attempted short-circuited before
+    /// the command itself was actually passed to the SCSI reponder (eg, if
+    /// the user attempted to call a `read` or `write` with an empty buffer).
+    pub prev_csw: Option<CommandStatusWrapper>,
+}
+
+impl<CommType: CommunicationChannel> ScsiBlockDevice<CommType> {
+    /// Constructs a new `ScsiBlockDevice`.
+    ///
+    /// Only direct-access devices are accepted; use `with_policy` to connect
+    /// to other kinds of block devices.
+    /// # Parameters
+    /// *  `comm_channel` is the communication channel to be used to send out commands and read the responses.  
+    /// *  `scratch_buffer` is a buffer that will be used for the initialization commands and responses; it will not be used outside of this method itself.
+    pub fn new(comm_channel: CommType, scratch_buffer: &mut [u8]) -> Result<Self, ScsiError> {
+        ScsiBlockDevice::with_policy(
+            comm_channel,
+            scratch_buffer,
+            DeviceTypePolicy::DirectAccessOnly,
+        )
+    }
+
+    /// Constructs a new `ScsiBlockDevice`, accepting any device whose
+    /// peripheral device type is allowed by `policy`.
+    ///
+    /// MMC (CD/DVD) devices are always accessed read-only using 2048 byte
+    /// blocks, regardless of what they report in their capacity data.
+    /// # Parameters
+    /// *  `comm_channel` is the communication channel to be used to send out commands and read the responses.  
+    /// *  `scratch_buffer` is a buffer that will be used for the initialization commands and responses; it will not be used outside of this method itself.
+    /// *  `policy` decides which peripheral device types are accepted.
+    ///
+    /// # Errors
+    /// Returns an `InvalidDeviceError` if the device's type is rejected by `policy`.
+    pub fn with_policy(
+        mut comm_channel: CommType,
+        mut scratch_buffer: &mut [u8],
+        policy: DeviceTypePolicy,
+    ) -> Result<Self, ScsiError> {
+        if scratch_buffer.len() < 31 {
+            return Err(ScsiError::from_cause(ErrorCause::BufferTooSmallError {
+                expected: 31,
+                actual: scratch_buffer.len(),
+            }));
+        }
+        let inquiry = InquiryCommand::new(scratch_buffer.len().min(36) as u8);
+        let (_ir, _csw_ic) = transfer_in_command(&mut comm_channel, &inquiry, &mut scratch_buffer)?;
+        let inquiry_resp = InquiryResponse::pull_from_buffer(&scratch_buffer)?;
+        let device_type = policy.check(&inquiry_resp)?;
+
+        let test_unit = TestUnitReady::new();
+        let mut attempts = 0;
+        loop {
+            attempts += 1;
+            match transfer_out_command(&mut comm_channel, &test_unit, &scratch_buffer) {
+                Ok(_) => break,
+                Err(ScsiError {
+                    cause: ErrorCause::FlagError { .. },
+                }) if attempts < TEST_UNIT_READY_ATTEMPTS => {
+                    // Clear the pending condition before trying again.
+                    let request_sense = RequestSenseCommand::new(18);
+                    transfer_in_command(&mut comm_channel, &request_sense, &mut scratch_buffer)?;
+                }
+                Err(e) => return Err(e),
+            }
+        }
+
+        let read_capacity = ReadCapacityCommand::new();
+        let (_, mut csw_rcc) =
+            transfer_in_command(&mut comm_channel, &read_capacity, &mut scratch_buffer)?;
+        let mut capacity_resp: ReadCapacity16Response =
+            ReadCapacityResponse::pull_from_buffer(&scratch_buffer)?.into();
+        if capacity_resp.logical_block_address == u64::from(u32::MAX) {
+            // The device is too large for READ CAPACITY(10) to describe.
+            let read_capacity = ReadCapacity16Command {
+                allocation_length: scratch_buffer.len().min(ReadCapacity16Response::SIZE) as u32,
+            };
+            (_, csw_rcc) =
+                transfer_in_command(&mut comm_channel, &read_capacity, &mut scratch_buffer)?;
+            capacity_resp = ReadCapacity16Response::pull_from_buffer(&scratch_buffer)?;
+        }
+        csw_rcc.tag = 2;
+        let rval = ScsiBlockDevice {
+            comm_channel,
+            info: DeviceInfo::new(device_type, &capacity_resp),
+            prev_csw: Some(csw_rcc),
+        };
+        Ok(rval)
+    }
+
+    /// Reads bytes starting at `offset` into the provided `dest` buffer, returning
+    /// the number of bytes read on success.
+    ///
+    /// Reads longer than `max_transfer_blocks` blocks are split into several commands.
+    pub fn read<B: AsMut<[u8]>>(&mut self, offset: u32, mut dest: B) -> Result<usize, ScsiError> {
+        let buffer = dest.as_mut();
+        if buffer.is_empty() {
+            self.prev_csw = None;
+            return Ok(0);
+        }
+        let (lba, count) = match self.offset_to_blocks(offset, buffer.len()) {
+            Ok(range) => range,
+            Err(e) => {
+                self.prev_csw = None;
+                return Err(e);
+            }
+        };
+        self.read_blocks(lba, count, buffer)
+    }
+
+    /// Reads `count` logical blocks starting at block `lba` into the provided
+    /// `dest` buffer, returning the number of bytes read on success.
+    ///
+    /// Reads longer than `max_transfer_blocks` blocks are split into several
+    /// commands; `prev_csw` then holds the status of the final command, with its
+    /// `data_residue` set to the total residue across all of them. Blocks past
+    /// the reach of `Read10Command`s are read with `Read16Command`s.
+    ///
+    /// # Errors
+    /// Returns a `BufferTooSmallError` if `dest` cannot hold `count` blocks, or a
+    /// `BlockAddressOutOfRangeError` if the blocks are past the end of the device.
+    pub fn read_blocks<B: AsMut<[u8]>>(
+        &mut self,
+        lba: u64,
+        count: u32,
+        mut dest: B,
+    ) -> Result<usize, ScsiError> {
+        let prev_tag = self.take_prev_tag();
+        self.info.check_range(lba, count)?;
+        let block_size = self.info.block_size as usize;
+        let buffer = dest.as_mut();
+        self.info.check_buffer(count, buffer.len())?;
+        let max_blocks = self.max_transfer_blocks();
+        let mut done = 0;
+        let mut read = 0;
+        let mut residue = 0;
+        let mut last_csw = None;
+        while done < count {
+            let blocks = (count - done).min(max_blocks);
+            let start = done as usize * block_size;
+            let end = start + blocks as usize * block_size;
+            let block_address = lba + u64::from(done);
+            let (r, csw) = match u32::try_from(block_address) {
+                Ok(block_address) => {
+                    let read_command = Read10Command {
+                        block_address,
+                        block_size: self.info.block_size,
+                        transfer_blocks: blocks as u16,
+                    };
+                    transfer_in_command(
+                        &mut self.comm_channel,
+                        &read_command,
+                        &mut buffer[start..end],
+                    )?
+                }
+                Err(_) => {
+                    let read_command =
+                        Read16Command::new(block_address, blocks, self.info.block_size);
+                    transfer_in_command(
+                        &mut self.comm_channel,
+                        &read_command,
+                        &mut buffer[start..end],
+                    )?
+                }
+            };
+            read += r;
+            residue += csw.data_residue as usize;
+            last_csw = Some(csw);
+            done += blocks;
+        }
+        self.finish_transfer(prev_tag, last_csw, residue);
+        Ok(read.saturating_sub(residue))
+    }
+
+    /// Writes bytes starting at `offset` from the provided buffer `src`, returning the
+    /// number of bytes written on success.
+    ///
+    /// Writes longer than `max_transfer_blocks` blocks are split into several commands.
+    ///
+    /// # Errors
+    /// Returns a `ReadOnlyDeviceError` if the device is read-only; see `is_read_only`.
+    pub fn write<B: AsMut<[u8]>>(&mut self, offset: u32, mut src: B) -> Result<usize, ScsiError> {
+        let buffer = src.as_mut();
+        if buffer.is_empty() {
+            self.prev_csw = None;
+            return Ok(0);
+        }
+        let (lba, count) = match self.offset_to_blocks(offset, buffer.len()) {
+            Ok(range) => range,
+            Err(e) => {
+                self.prev_csw = None;
+                return Err(e);
+            }
+        };
+        self.write_blocks(lba, count, &*buffer)
+    }
+
+    /// Writes `count` logical blocks starting at block `lba` from the provided
+    /// `src` buffer, returning the number of bytes written on success.
+    ///
+    /// Writes longer than `max_transfer_blocks` blocks are split into several
+    /// commands, the same way as in `read_blocks`, and blocks past the reach
+    /// of `Write10Command`s are written with `Write16Command`s.
+    ///
+    /// # Errors
+    /// Returns a `BufferTooSmallError` if `src` holds fewer than `count` blocks,
+    /// a `BlockAddressOutOfRangeError` if the blocks are past the end of the
+    /// device, or a `ReadOnlyDeviceError` if the device is read-only.
+    pub fn write_blocks<B: AsRef<[u8]>>(
+        &mut self,
+        lba: u64,
+        count: u32,
+        src: B,
+    ) -> Result<usize, ScsiError> {
+        let prev_tag = self.take_prev_tag();
+        if self.is_read_only() {
+            return Err(ScsiError::from_cause(ErrorCause::ReadOnlyDeviceError));
+        }
+        self.info.check_range(lba, count)?;
+        let block_size = self.info.block_size as usize;
+        let buffer = src.as_ref();
+        self.info.check_buffer(count, buffer.len())?;
+        let max_blocks = self.max_transfer_blocks();
+        let mut done = 0;
+        let mut written = 0;
+        let mut residue = 0;
+        let mut last_csw = None;
+        while done < count {
+            let blocks = (count - done).min(max_blocks);
+            let start = done as usize * block_size;
+            let end = start + blocks as usize * block_size;
+            let block_address = lba + u64::from(done);
+            let data = &buffer[start..end];
+            let (w, csw) = match u32::try_from(block_address) {
+                Ok(block_address) => {
+                    let write_command = Write10Command {
+                        block_address,
+                        block_size: self.info.block_size,
+                        transfer_blocks: blocks as u16,
+                    };
+                    transfer_out_command(&mut self.comm_channel, &write_command, data)?
+                }
+                Err(_) => {
+                    let write_command =
+                        Write16Command::new(block_address, blocks, self.info.block_size);
+                    transfer_out_command(&mut self.comm_channel, &write_command, data)?
+                }
+            };
+            written += w;
+            residue += csw.data_residue as usize;
+            last_csw = Some(csw);
+            done += blocks;
+        }
+        self.finish_transfer(prev_tag, last_csw, residue);
+        Ok(written.saturating_sub(residue))
+    }
+
+    /// Asks the device to write any cached data for the whole medium to
+    /// permanent storage, returning once it has done so.
+    pub fn synchronize_cache(&mut self) -> Result<(), ScsiError> {
+        let prev_tag = self.take_prev_tag();
+        let command = SynchronizeCache10Command::new();
+        let (_, csw) = transfer_out_command(&mut self.comm_channel, &command, &[][..])?;
+        self.finish_transfer(prev_tag, Some(csw), 0);
+        Ok(())
+    }
+
+    /// Tells the device that the `count` logical blocks starting at block
+    /// `lba` no longer hold data that is needed, using a single `UnmapCommand`.
+    ///
+    /// Devices that do not support thin provisioning reject the command, which
+    /// is reported as a `FlagError`.
+    ///
+    /// # Errors
+    /// Returns a `BlockAddressOutOfRangeError` if the blocks are past the end
+    /// of the device, or a `ReadOnlyDeviceError` if the device is read-only.
+    pub fn unmap(&mut self, lba: u64, count: u32) -> Result<(), ScsiError> {
+        let prev_tag = self.take_prev_tag();
+        if self.is_read_only() {
+            return Err(ScsiError::from_cause(ErrorCause::ReadOnlyDeviceError));
+        }
+        self.info.check_range(lba, count)?;
+        if count == 0 {
+            return Ok(());
+        }
+        let (command, buffer) = UnmapCommand::single_range(lba, count)?;
+        let (_, csw) = transfer_out_command(&mut self.comm_channel, &command, &buffer[..])?;
+        self.finish_transfer(prev_tag, Some(csw), csw.data_residue as usize);
+        Ok(())
+    }
+
+    /// Tells the device that the `count` logical blocks starting at block
+    /// `lba` no longer hold data that is needed, using as few `UnmapCommand`s
+    /// as the device allows.
+    ///
+    /// Once `read_block_limits` has been called, only the whole units of the
+    /// device's optimal unmap granularity within the range are discarded, and
+    /// every command stays within the device's maximum unmap LBA and block
+    /// descriptor counts. Returns the number of blocks discarded, which can
+    /// be less than `count` because of the granularity.
+    ///
+    /// # Errors
+    /// Returns a `BlockAddressOutOfRangeError` if the blocks are past the end
+    /// of the device, a `ReadOnlyDeviceError` if the device is read-only, or
+    /// an `UnsupportedOperationError` if its Block Limits page says it does
+    /// not support UNMAP.
+    pub fn discard(&mut self, lba: u64, count: u64) -> Result<u64, ScsiError> {
+        let prev_tag = self.take_prev_tag();
+        let mut batches = DiscardBatches::new(&self.info, lba, count)?;
+        let blocks = batches.blocks();
+        let mut last_csw = None;
+        while let Some((command, buffer)) = batches.next_batch()? {
+            let length = command.parameter_list_length();
+            let (_, csw) =
+                transfer_out_command(&mut self.comm_channel, &command, &buffer[..length])?;
+            last_csw = Some(csw);
+        }
+        self.finish_transfer(prev_tag, last_csw, 0);
+        Ok(blocks)
+    }
+
+    /// Writes the first block of `block` to each of the `count` blocks
+    /// starting at `lba`, using `WriteSame16Command`s no longer than the
+    /// device's maximum write same length.
+    ///
+    /// If `unmap` is set, the device may unmap the blocks instead, as long as
+    /// they read back as `block` afterwards.
+    ///
+    /// # Errors
+    /// Returns a `BlockAddressOutOfRangeError` if the blocks are past the end
+    /// of the device, a `ReadOnlyDeviceError` if the device is read-only, or a
+    /// `BufferTooSmallError` if `block` is shorter than a block.
+    pub fn write_same(
+        &mut self,
+        lba: u64,
+        count: u64,
+        block: &[u8],
+        unmap: bool,
+    ) -> Result<(), ScsiError> {
+        let prev_tag = self.take_prev_tag();
+        if self.is_read_only() {
+            return Err(ScsiError::from_cause(ErrorCause::ReadOnlyDeviceError));
+        }
+        self.info.check_extent(lba, count)?;
+        let block = &block[..self.info.check_buffer(1, block.len())?];
+        let max_blocks = u64::from(self.info.max_write_same_blocks());
+        let mut last_csw = None;
+        let mut done = 0;
+        while done < count {
+            let blocks = (count - done).min(max_blocks);
+            let mut command = WriteSame16Command::new(lba + done, blocks as u32, self.block_size());
+            command.unmap = unmap;
+            let (_, csw) = transfer_out_command(&mut self.comm_channel, &command, block)?;
+            last_csw = Some(csw);
+            done += blocks;
+        }
+        self.finish_transfer(prev_tag, last_csw, 0);
+        Ok(())
+    }
+
+    /// Asks the device to check that the `count` logical blocks starting at
+    /// block `lba` can be read back from its medium, without transferring
+    /// them.
+    ///
+    /// If `data` is given, the blocks are also compared against it. Ranges
+    /// longer than `max_transfer_blocks` blocks are split into several
+    /// commands, using `Verify16Command`s for blocks past the reach of
+    /// `Verify10Command`s.
+    ///
+    /// A block that cannot be read, or that does not match `data`, fails the
+    /// command with a `FlagError`; `request_sense` then describes the failure.
+    /// For comparisons, the sense data's `information` is the offset of the
+    /// first differing byte within the failed command's share of `data`. When a
+    /// command fails, `prev_csw` keeps the status of the last one that passed.
+    ///
+    /// # Errors
+    /// Returns a `BlockAddressOutOfRangeError` if the blocks are past the end
+    /// of the device, or a `BufferTooSmallError` if `data` holds fewer than
+    /// `count` blocks.
+    pub fn verify(&mut self, lba: u64, count: u32, data: Option<&[u8]>) -> Result<(), ScsiError> {
+        let prev = self.prev_csw;
+        let prev_tag = self.take_prev_tag();
+        self.info.check_range(lba, count)?;
+        if let Some(data) = data {
+            self.info.check_buffer(count, data.len())?;
+        }
+        let block_size = self.info.block_size as usize;
+        let max_blocks = self.max_transfer_blocks();
+        let mut done = 0;
+        let mut last_csw = None;
+        while done < count {
+            let blocks = (count - done).min(max_blocks);
+            let (byte_check, data) = match data {
+                Some(data) => {
+                    let start = done as usize * block_size;
+                    let end = start + blocks as usize * block_size;
+                    (VerifyByteCheck::Compare, &data[start..end])
+                }
+                None => (VerifyByteCheck::None, &[][..]),
+            };
+            let mut command =
+                Verify16Command::new(lba + u64::from(done), blocks, self.block_size());
+            command.byte_check = byte_check;
+            let result = match u32::try_from(command.block_address) {
+                Ok(block_address) => {
+                    let command = Verify10Command {
+                        block_address,
+                        block_size: command.block_size,
+                        transfer_blocks: blocks as u16,
+                        byte_check,
+                    };
+                    transfer_out_command(&mut self.comm_channel, &command, data)
+                }
+                Err(_) => transfer_out_command(&mut self.comm_channel, &command, data),
+            };
+            match result {
+                Ok((_, csw)) => last_csw = Some(csw),
+                Err(error) => {
+                    abort_transfer(&mut self.prev_csw, prev, last_csw);
+                    return Err(error);
+                }
+            }
+            done += blocks;
+        }
+        self.finish_transfer(prev_tag, last_csw, 0);
+        Ok(())
+    }
+
+    /// Verifies every block on the device, `chunk_blocks` blocks at a time,
+    /// calling `report` after every chunk and for every bad block found.
+    ///
+    /// A `chunk_blocks` of 0 verifies as many blocks per chunk as a single
+    /// command may cover. When the device fails a chunk, its sense data is
+    /// used to find the bad block, and the scrub carries on from the block
+    /// after it; devices that do not say which block was bad have the chunk
+    /// checked again one block at a time. Returns the number of bad blocks.
+    ///
+    /// # Errors
+    /// Returns a `FlagError` if the device fails a chunk for a reason other
+    /// than a medium or hardware error, such as not supporting VERIFY.
+    pub fn scrub<F: FnMut(ScrubEvent)>(
+        &mut self,
+        chunk_blocks: u32,
+        mut report: F,
+    ) -> Result<u64, ScsiError> {
+        let mut scrub = Scrub::new(&self.info, chunk_blocks);
+        while let Some((lba, blocks)) = scrub.next_chunk() {
+            match self.verify(lba, blocks, None) {
+                Ok(()) => report(scrub.passed(blocks)),
+                Err(
+                    error @ ScsiError {
+                        cause: ErrorCause::FlagError { .. },
+                    },
+                ) => {
+                    let sense = self.request_sense()?;
+                    if let Some(event) = scrub.failed(blocks, sense, error)? {
+                        report(event);
+                    }
+                }
+                Err(error) => return Err(error),
+            }
+        }
+        Ok(scrub.bad_blocks())
+    }
+
+    /// Asks the device to low-level format its medium, erasing every block.
+    ///
+    /// Without `parameters` the device formats with its default settings. If
+    /// the parameters' `immediate` flag is set the device returns before it
+    /// has finished, and `format_progress` can be polled until it is done;
+    /// otherwise this only returns once the format is complete. Only the
+    /// parameter list header is sent, so its `defect_list_length` should be 0.
+    ///
+    /// # Errors
+    /// Returns a `ReadOnlyDeviceError` if the device is read-only, or a
+    /// `BufferTooSmallError` if `parameters` has a nonzero
+    /// `defect_list_length`.
+    pub fn format_unit(&mut self, parameters: Option<&FormatParameters>) -> Result<(), ScsiError> {
+        let prev_tag = self.take_prev_tag();
+        if self.is_read_only() {
+            return Err(ScsiError::from_cause(ErrorCause::ReadOnlyDeviceError));
+        }
+        let mut header = [0; FormatUnitCommand::SHORT_HEADER_SIZE];
+        let (command, header) = match parameters {
+            Some(parameters) => {
+                let pushed = parameters.push_to_buffer(&mut header[..])?;
+                let command = FormatUnitCommand::with_parameters(parameters);
+                if command.parameter_list_length as usize != pushed {
+                    return Err(ScsiError::from_cause(ErrorCause::BufferTooSmallError {
+                        expected: command.parameter_list_length as usize,
+                        actual: pushed,
+                    }));
+                }
+                (command, &header[..pushed])
+            }
+            None => (FormatUnitCommand::new(), &header[..0]),
+        };
+        let (_, csw) = transfer_out_command(&mut self.comm_channel, &command, header)?;
+        self.finish_transfer(prev_tag, Some(csw), 0);
+        Ok(())
+    }
+
+    /// Sends a `SanitizeCommand`, along with `parameters` if it is an
+    /// overwrite, making all of the data on the device unrecoverable.
+    ///
+    /// The command's parameter list length is set to match `parameters`. If
+    /// its `immediate` flag is set this returns once the device has accepted
+    /// the command, and `sanitize_progress` can be used to wait for it to
+    /// finish.
+    ///
+    /// # Errors
+    /// Returns a `ReadOnlyDeviceError` if the device is write protected, or a
+    /// `TransferTooLargeError` if the overwrite pattern is longer than 512
+    /// bytes.
+    pub fn sanitize(
+        &mut self,
+        mut command: SanitizeCommand,
+        parameters: Option<&OverwriteParameters<'_>>,
+    ) -> Result<(), ScsiError> {
+        let prev_tag = self.take_prev_tag();
+        if self.is_read_only() {
+            return Err(ScsiError::from_cause(ErrorCause::ReadOnlyDeviceError));
+        }
+        let mut buffer = [0; SanitizeCommand::MAX_PARAMETERS_SIZE];
+        let list = command.push_parameter_list(parameters, &mut buffer)?;
+        let (_, csw) = transfer_out_command(&mut self.comm_channel, &command, list)?;
+        self.finish_transfer(prev_tag, Some(csw), 0);
+        Ok(())
+    }
+
+    /// Atomically replaces the blocks starting at `lba` with `new`, but only if
+    /// they currently hold `expected`; both are a whole number of blocks long.
+    ///
+    /// Nothing is written if the comparison fails, which is reported as a
+    /// `MiscompareError` with the offset of the first differing byte in
+    /// `expected`, read from the device's sense data.
+    ///
+    /// # Errors
+    /// Besides `MiscompareError`, returns a `ReadOnlyDeviceError` if the
+    /// device is read-only, a `BufferTooSmallError` if `new` is not as long as
+    /// `expected`, a `TransferTooLargeError` if they cover more blocks than the
+    /// device's maximum compare and write length, or a
+    /// `BlockAddressOutOfRangeError` if the blocks are past the end of the
+    /// device.
+    pub fn compare_and_write(
+        &mut self,
+        lba: u64,
+        expected: &[u8],
+        new: &[u8],
+    ) -> Result<(), ScsiError> {
+        let prev_tag = self.take_prev_tag();
+        let command = self.info.compare_and_write_command(lba, expected, new)?;
+        match transfer_out_parts(&mut self.comm_channel, &command, &[expected, new]) {
+            Ok((_, csw)) => {
+                self.finish_transfer(prev_tag, Some(csw), 0);
+                Ok(())
+            }
+            Err(
+                error @ ScsiError {
+                    cause: ErrorCause::FlagError { .. },
+                },
+            ) => {
+                let sense = self.request_sense()?;
+                Err(CompareAndWriteCommand::miscompare(error, &sense))
+            }
+            Err(error) => Err(error),
+        }
+    }
+
+    /// Reads every reservation key registered with the device.
+    pub fn read_reservation_keys(&mut self) -> Result<ReservationKeysResponse, ScsiError> {
+        let mut buffer = [0; ReservationKeysResponse::MAX_SIZE];
+        let length =
+            self.persistent_reserve_in(PersistentReserveInServiceAction::ReadKeys, &mut buffer)?;
+        ReservationKeysResponse::pull_from_buffer(&buffer[..length])
+    }
+
+    /// Reads the device's current persistent reservation.
+    pub fn read_reservation(&mut self) -> Result<ReservationResponse, ScsiError> {
+        let mut buffer = [0; ReservationKeysResponse::MAX_SIZE];
+        let action = PersistentReserveInServiceAction::ReadReservation;
+        let length = self.persistent_reserve_in(action, &mut buffer)?;
+        ReservationResponse::pull_from_buffer(&buffer[..length])
+    }
+
+    /// Asks the device which persistent reservation features it supports.
+    pub fn report_reservation_capabilities(
+        &mut self,
+    ) -> Result<ReservationCapabilitiesResponse, ScsiError> {
+        let prev_tag = self.take_prev_tag();
+        let mut buffer = [0; ReservationCapabilitiesResponse::SIZE];
+        let mut command =
+            PersistentReserveInCommand::new(PersistentReserveInServiceAction::ReportCapabilities);
+        command.allocation_length = ReservationCapabilitiesResponse::SIZE as u16;
+        let (_, csw) = transfer_in_command(&mut self.comm_channel, &command, &mut buffer[..])?;
+        self.finish_transfer(prev_tag, Some(csw), csw.data_residue as usize);
+        ReservationCapabilitiesResponse::pull_from_buffer(&buffer[..])
+    }
+
+    /// Reads the header of a READ KEYS or READ RESERVATION response and then
+    /// the whole response into `buffer`, returning its length.
+    fn persistent_reserve_in(
+        &mut self,
+        service_action: PersistentReserveInServiceAction,
+        buffer: &mut [u8; ReservationKeysResponse::MAX_SIZE],
+    ) -> Result<usize, ScsiError> {
+        let mut length = ReservationKeysResponse::HEADER_SIZE;
+        loop {
+            let prev_tag = self.take_prev_tag();
+            let mut command = PersistentReserveInCommand::new(service_action);
+            command.allocation_length = length as u16;
+            let (_, csw) =
+                transfer_in_command(&mut self.comm_channel, &command, &mut buffer[..length])?;
+            self.finish_transfer(prev_tag, Some(csw), csw.data_residue as usize);
+            let full_length = ReservationKeysResponse::response_length(&buffer[..]);
+            if full_length <= length {
+                return Ok(length);
+            }
+            length = full_length;
+        }
+    }
+
+    /// Sends a `PersistentReserveOutCommand` along with `parameters`, changing
+    /// the device's persistent reservation state.
+    ///
+    /// The command's parameter list length is set to match `parameters`.
+    /// Commands the device refuses, including those that conflict with
+    /// another initiator's reservation, fail with a `FlagError`. Transports
+    /// that carry the full SCSI status, such as iSCSI and SG_IO, report
+    /// conflicts as a `ReservationConflictError` instead.
+    pub fn persistent_reserve_out(
+        &mut self,
+        mut command: PersistentReserveOutCommand,
+        parameters: &PersistentReserveOutParameters,
+    ) -> Result<(), ScsiError> {
+        let prev_tag = self.take_prev_tag();
+        let mut list = [0; PersistentReserveOutParameters::SIZE];
+        let pushed = parameters.push_to_buffer(&mut list[..])?;
+        command.parameter_list_length = pushed as u32;
+        let (_, csw) = transfer_out_command(&mut self.comm_channel, &command, &list[..pushed])?;
+        self.finish_transfer(prev_tag, Some(csw), 0);
+        Ok(())
+    }
+
+    /// Registers `new_key` as this initiator's reservation key in place of
+    /// `key`, which is 0 if it has not registered one yet; a `new_key` of 0
+    /// unregisters it.
+    pub fn register(&mut self, key: u64, new_key: u64) -> Result<(), ScsiError> {
+        let command =
+            PersistentReserveOutCommand::new(PersistentReserveOutServiceAction::Register, None);
+        self.persistent_reserve_out(command, &PersistentReserveOutParameters::new(key, new_key))
+    }
+
+    /// Takes out a persistent reservation of type `reservation_type` for this
+    /// initiator, registered with `key`.
+    pub fn reserve(
+        &mut self,
+        key: u64,
+        reservation_type: ReservationType,
+    ) -> Result<(), ScsiError> {
+        let command = PersistentReserveOutCommand::new(
+            PersistentReserveOutServiceAction::Reserve,
+            Some(reservation_type),
+        );
+        self.persistent_reserve_out(command, &PersistentReserveOutParameters::new(key, 0))
+    }
+
+    /// Gives up the persistent reservation of type `reservation_type` this
+    /// initiator, registered with `key`, holds.
+    pub fn release(
+        &mut self,
+        key: u64,
+        reservation_type: ReservationType,
+    ) -> Result<(), ScsiError> {
+        let command = PersistentReserveOutCommand::new(
+            PersistentReserveOutServiceAction::Release,
+            Some(reservation_type),
+        );
+        self.persistent_reserve_out(command, &PersistentReserveOutParameters::new(key, 0))
+    }
+
+    /// Removes every registration and the reservation from the device, on
+    /// behalf of this initiator registered with `key`.
+    pub fn clear_reservations(&mut self, key: u64) -> Result<(), ScsiError> {
+        let command =
+            PersistentReserveOutCommand::new(PersistentReserveOutServiceAction::Clear, None);
+        self.persistent_reserve_out(command, &PersistentReserveOutParameters::new(key, 0))
+    }
+
+    /// Removes the registrations of `preempted_key`, on behalf of this
+    /// initiator registered with `key`, taking over their reservation with
+    /// type `reservation_type` if they held it.
+    ///
+    /// If `abort` is set, the device also aborts the commands the preempted
+    /// initiators have in flight.
+    pub fn preempt(
+        &mut self,
+        key: u64,
+        preempted_key: u64,
+        reservation_type: Option<ReservationType>,
+        abort: bool,
+    ) -> Result<(), ScsiError> {
+        let service_action = if abort {
+            PersistentReserveOutServiceAction::PreemptAndAbort
+        } else {
+            PersistentReserveOutServiceAction::Preempt
+        };
+        let command = PersistentReserveOutCommand::new(service_action, reservation_type);
+        let parameters = PersistentReserveOutParameters::new(key, preempted_key);
+        self.persistent_reserve_out(command, &parameters)
+    }
+
+    /// Reserves the whole device for this initiator with a `Reserve6Command`,
+    /// for devices and hosts that predate persistent reservations.
+    ///
+    /// Other initiators cannot access the medium until the reservation is
+    /// dropped with `release_unit`, or the device is reset.
+    pub fn reserve_unit(&mut self) -> Result<(), ScsiError> {
+        let prev_tag = self.take_prev_tag();
+        let command = Reserve6Command::new();
+        let (_, csw) = transfer_out_command(&mut self.comm_channel, &command, &[][..])?;
+        self.finish_transfer(prev_tag, Some(csw), 0);
+        Ok(())
+    }
+
+    /// Drops a reservation taken with `reserve_unit`, using a
+    /// `Release6Command`.
+    pub fn release_unit(&mut self) -> Result<(), ScsiError> {
+        let prev_tag = self.take_prev_tag();
+        let command = Release6Command::new();
+        let (_, csw) = transfer_out_command(&mut self.comm_channel, &command, &[][..])?;
+        self.finish_transfer(prev_tag, Some(csw), 0);
+        Ok(())
+    }
+
+    /// Sends a `WriteBufferCommand` in `mode`, writing `data` to the device's
+    /// buffer `buffer_id` at `offset`.
+    ///
+    /// # Errors
+    /// Returns a `TransferTooLargeError` if `offset` or the length of `data`
+    /// does not fit in the command.
+    pub fn write_buffer(
+        &mut self,
+        mode: WriteBufferMode,
+        buffer_id: u8,
+        offset: u32,
+        data: &[u8],
+    ) -> Result<(), ScsiError> {
+        let prev_tag = self.take_prev_tag();
+        let command = WriteBufferCommand::with_length(mode, buffer_id, offset, data.len())?;
+        let (_, csw) = transfer_out_command(&mut self.comm_channel, &command, data)?;
+        self.finish_transfer(prev_tag, Some(csw), 0);
+        Ok(())
+    }
+
+    /// Downloads the firmware `image` to the device's buffer `buffer_id`
+    /// using WRITE BUFFER commands in `mode`.
+    ///
+    /// Modes that take offsets send the image in segments of `segment_size`
+    /// bytes, which should be a multiple of the offset alignment reported by
+    /// `read_buffer_descriptor`; a `segment_size` of 0, or a mode without
+    /// offsets, sends the whole image in one command. With
+    /// `WriteBufferMode::DownloadMicrocodeWithOffsetsSaveAndDefer` the new
+    /// firmware only runs after `activate_deferred_microcode`.
+    ///
+    /// # Errors
+    /// Returns an `UnsupportedOperationError` if `mode` does not download
+    /// microcode, or a `TransferTooLargeError` if the image or its segments
+    /// are too large for the commands to carry. Segments the device rejects
+    /// stop the download.
+    pub fn download_microcode(
+        &mut self,
+        mode: WriteBufferMode,
+        buffer_id: u8,
+        image: &[u8],
+        segment_size: usize,
+    ) -> Result<(), ScsiError> {
+        for (command, segment) in MicrocodeSegments::new(mode, buffer_id, image, segment_size)? {
+            let prev_tag = self.take_prev_tag();
+            let (_, csw) = transfer_out_command(&mut self.comm_channel, &command, segment)?;
+            self.finish_transfer(prev_tag, Some(csw), 0);
+        }
+        Ok(())
+    }
+
+    /// Activates firmware downloaded with
+    /// `WriteBufferMode::DownloadMicrocodeWithOffsetsSaveAndDefer`.
+    pub fn activate_deferred_microcode(&mut self) -> Result<(), ScsiError> {
+        self.write_buffer(WriteBufferMode::ActivateDeferredMicrocode, 0, 0, &[])
+    }
+
+    /// Fills `dest` from the device's buffer `buffer_id`, starting at
+    /// `offset`, returning the number of bytes read.
+    ///
+    /// `dest` should not reach past the end of the buffer, whose size can be
+    /// read with `read_buffer_descriptor`.
+    pub fn read_buffer(
+        &mut self,
+        buffer_id: u8,
+        offset: u32,
+        dest: &mut [u8],
+    ) -> Result<usize, ScsiError> {
+        self.read_buffer_data(ReadBufferMode::Data, buffer_id, offset, dest)
+    }
+
+    /// Asks the device for the size of its buffer `buffer_id` and the offsets
+    /// it accepts.
+    pub fn read_buffer_descriptor(&mut self, buffer_id: u8) -> Result<BufferDescriptor, ScsiError> {
+        let mut buffer = [0; BufferDescriptor::SIZE];
+        self.read_buffer_data(ReadBufferMode::Descriptor, buffer_id, 0, &mut buffer)?;
+        BufferDescriptor::pull_from_buffer(&buffer[..])
+    }
+
+    /// Fills `dest` from the device's echo buffer, which holds the data last
+    /// written to it with `WriteBufferMode::EchoBuffer`, returning the number
+    /// of bytes read.
+    pub fn read_echo_buffer(&mut self, dest: &mut [u8]) -> Result<usize, ScsiError> {
+        self.read_buffer_data(ReadBufferMode::EchoBuffer, 0, 0, dest)
+    }
+
+    /// Asks the device for the size of its echo buffer.
+    pub fn read_echo_buffer_descriptor(&mut self) -> Result<EchoBufferDescriptor, ScsiError> {
+        let mut buffer = [0; EchoBufferDescriptor::SIZE];
+        self.read_buffer_data(ReadBufferMode::EchoBufferDescriptor, 0, 0, &mut buffer)?;
+        EchoBufferDescriptor::pull_from_buffer(&buffer[..])
+    }
+
+    /// Sends a `ReadBufferCommand` in `mode`, filling `dest` with the
+    /// response.
+    fn read_buffer_data(
+        &mut self,
+        mode: ReadBufferMode,
+        buffer_id: u8,
+        offset: u32,
+        dest: &mut [u8],
+    ) -> Result<usize, ScsiError> {
+        let prev_tag = self.take_prev_tag();
+        let command = ReadBufferCommand::with_length(mode, buffer_id, offset, dest.len())?;
+        let (read, csw) = transfer_in_command(&mut self.comm_channel, &command, dest)?;
+        self.finish_transfer(prev_tag, Some(csw), csw.data_residue as usize);
+        Ok(read)
+    }
+
+    /// Reads log page `page_code` into `dest`, returning its length.
+    ///
+    /// This works for any page, including vendor-specific ones, whose
+    /// parameters can be walked with `LogParameters`. Pages longer than
+    /// `dest` are cut short.
+    pub fn log_sense(&mut self, page_code: u8, dest: &mut [u8]) -> Result<usize, ScsiError> {
+        let mut length = LogPage::HEADER_SIZE.min(dest.len());
+        loop {
+            let prev_tag = self.take_prev_tag();
+            let command = LogSenseCommand::new(page_code, length as u16);
+            let (read, csw) =
+                transfer_in_command(&mut self.comm_channel, &command, &mut dest[..length])?;
+            self.finish_transfer(prev_tag, Some(csw), csw.data_residue as usize);
+            let full_length = LogPage::length(dest);
+            if read < length || full_length <= length {
+                return Ok(read.min(full_length));
+            }
+            length = full_length;
+        }
+    }
+
+    /// Reads and parses log page `page_code`.
+    ///
+    /// # Errors
+    /// Returns a `ParseError` if this crate has no parser for the page.
+    pub fn read_log_page(&mut self, page_code: u8) -> Result<LogPage, ScsiError> {
+        self.read_parsed_log_page(page_code)
+    }
+
+    /// Reads the list of log pages the device has.
+    pub fn supported_log_pages(&mut self) -> Result<SupportedLogPages, ScsiError> {
+        self.read_parsed_log_page(SupportedLogPages::PAGE_CODE)
+    }
+
+    /// Reads the device's write, read or verify error counters.
+    pub fn error_counters(
+        &mut self,
+        kind: ErrorCounterKind,
+    ) -> Result<ErrorCounterPage, ScsiError> {
+        self.read_parsed_log_page(kind.page_code())
+    }
+
+    /// Reads the device's temperature.
+    pub fn temperature(&mut self) -> Result<TemperaturePage, ScsiError> {
+        self.read_parsed_log_page(TemperaturePage::PAGE_CODE)
+    }
+
+    /// Reads how many start-stop and load-unload cycles the device has been
+    /// through.
+    pub fn start_stop_cycles(&mut self) -> Result<StartStopCyclePage, ScsiError> {
+        self.read_parsed_log_page(StartStopCyclePage::PAGE_CODE)
+    }
+
+    /// Reads the outcomes of the device's most recent self-tests.
+    pub fn self_test_results(&mut self) -> Result<SelfTestResultsPage, ScsiError> {
+        self.read_parsed_log_page(SelfTestResultsPage::PAGE_CODE)
+    }
+
+    /// Reads how worn out the device's flash is.
+    pub fn solid_state_media(&mut self) -> Result<SolidStateMediaPage, ScsiError> {
+        self.read_parsed_log_page(SolidStateMediaPage::PAGE_CODE)
+    }
+
+    /// Reads whether the device predicts that it is going to fail.
+    pub fn informational_exceptions(&mut self) -> Result<InformationalExceptionsPage, ScsiError> {
+        self.read_parsed_log_page(InformationalExceptionsPage::PAGE_CODE)
+    }
+
+    /// Sends a `LogSelectCommand` along with `parameters`, which hold log
+    /// pages in the format `log_sense` returns them.
+    ///
+    /// The command's parameter list length is set to match `parameters`.
+    ///
+    /// # Errors
+    /// Returns a `TransferTooLargeError` if `parameters` is longer than the
+    /// command can carry.
+    pub fn log_select(
+        &mut self,
+        command: LogSelectCommand,
+        parameters: &[u8],
+    ) -> Result<(), ScsiError> {
+        let command = command.with_parameter_list(parameters)?;
+        let prev_tag = self.take_prev_tag();
+        let (_, csw) = transfer_out_command(&mut self.comm_channel, &command, parameters)?;
+        self.finish_transfer(prev_tag, Some(csw), 0);
+        Ok(())
+    }
+
+    /// Resets the device's cumulative log parameters, such as its error
+    /// counters.
+    pub fn reset_log_parameters(&mut self) -> Result<(), ScsiError> {
+        let command = LogSelectCommand::reset(LogPageControl::CumulativeValues);
+        self.log_select(command, &[])
+    }
+
+    /// Reads log page `page_code` and parses it as a `P`.
+    fn read_parsed_log_page<P: BufferPullable>(&mut self, page_code: u8) -> Result<P, ScsiError> {
+        let mut buffer = [0; LogPage::MAX_SIZE];
+        let length = self.log_sense(page_code, &mut buffer)?;
+        P::pull_from_buffer(&buffer[..length])
+    }
+
+    /// Polls a sanitize started with the `immediate` flag, returning how far
+    /// along it is as a fraction out of 65536, or `None` once the device is
+    /// no longer sanitizing.
+    ///
+    /// Like `format_progress`, this clears the device's sense data.
+    pub fn sanitize_progress(&mut self) -> Result<Option<u16>, ScsiError> {
+        let sense = self.request_sense()?;
+        if !sense.is_sanitize_in_progress() {
+            return Ok(None);
+        }
+        Ok(Some(sense.progress().unwrap_or(0)))
+    }
+
+    /// Polls a format started with the `immediate` flag, returning how far
+    /// along it is as a fraction out of 65536, or `None` once the device is
+    /// no longer formatting.
+    ///
+    /// The progress is read from the device's sense data, which this clears;
+    /// devices that do not report progress are treated as just starting.
+    pub fn format_progress(&mut self) -> Result<Option<u16>, ScsiError> {
+        let sense = self.request_sense()?;
+        if !sense.is_format_in_progress() {
+            return Ok(None);
+        }
+        Ok(Some(sense.progress().unwrap_or(0)))
+    }
+
+    /// Asks the device for the sense data describing why the previous command
+    /// failed, clearing it.
+    pub fn request_sense(&mut self) -> Result<RequestSenseResponse, ScsiError> {
+        let prev_tag = self.take_prev_tag();
+        let mut buffer = [0; RequestSenseResponse::SIZE];
+        let command = RequestSenseCommand::new(RequestSenseResponse::SIZE as u8);
+        let (_, csw) = transfer_in_command(&mut self.comm_channel, &command, &mut buffer[..])?;
+        self.finish_transfer(prev_tag, Some(csw), csw.data_residue as usize);
+        RequestSenseResponse::pull_from_buffer(&buffer[..])
+    }
+
+    /// Asks the device for its Block Limits vital product data page, limiting
+    /// future reads and writes to its maximum transfer length and discards to
+    /// its unmap limits.
+    ///
+    /// Many USB mass storage devices do not support vital product data pages,
+    /// so this is not done when the device is constructed.
+    pub fn read_block_limits(&mut self) -> Result<BlockLimitsPage, ScsiError> {
+        let prev_tag = self.take_prev_tag();
+        let mut buffer = [0; BlockLimitsPage::SIZE];
+        let inquiry =
+            VpdInquiryCommand::new(BlockLimitsPage::PAGE_CODE, BlockLimitsPage::SIZE as u8);
+        let (_, csw) = transfer_in_command(&mut self.comm_channel, &inquiry, &mut buffer[..])?;
+        self.finish_transfer(prev_tag, Some(csw), csw.data_residue as usize);
+        let page = BlockLimitsPage::pull_from_buffer(&buffer[..])?;
+        self.info.block_limits = Some(page);
+        Ok(page)
+    }
+
+    /// The largest number of blocks sent in a single read or write command.
+    ///
+    /// This is the smallest of the limit of the commands themselves, the
+    /// device's maximum transfer length if `read_block_limits` was called, and
+    /// the cap set with `set_max_transfer_blocks`.
+    pub fn max_transfer_blocks(&self) -> u32 {
+        self.info.max_transfer_blocks()
+    }
+
+    /// Caps the number of blocks sent in a single read or write command; a
+    /// `max_blocks` of 0 removes the cap.
+    pub fn set_max_transfer_blocks(&mut self, max_blocks: u32) {
+        self.info.user_max_transfer_blocks = max_blocks;
+    }
+
+    /// Clears `prev_csw` before a new operation, returning the tag it held.
+    fn take_prev_tag(&mut self) -> u32 {
+        take_prev_tag(&mut self.prev_csw)
+    }
+
+    /// Records the status of a finished operation in `prev_csw`.
+    fn finish_transfer(
+        &mut self,
+        prev_tag: u32,
+        last_csw: Option<CommandStatusWrapper>,
+        residue: usize,
+    ) {
+        finish_transfer(&mut self.prev_csw, prev_tag, last_csw, residue);
+    }
+
+    /// Converts a byte offset and length into a block address and count.
+    fn offset_to_blocks(&self, offset: u32, length: usize) -> Result<(u64, u32), ScsiError> {
+        self.info.offset_to_blocks(offset, length)
+    }
+
+    /// The size of the read/write blocks for this device.
+    pub fn block_size(&self) -> u32 {
+        self.info.block_size
+    }
+
+    /// The number of logical blocks on the device, as reported by the
+    /// `ReadCapacityCommand` sent during initialization, or by the
+    /// `ReadCapacity16Command` that follows it on devices too large for the
+    /// former to describe.
+    pub fn num_blocks(&self) -> u64 {
+        self.info.num_blocks
+    }
+
+    /// The total capacity of the device in bytes.
+    pub fn capacity_bytes(&self) -> u64 {
+        self.info.capacity_bytes()
+    }
+
+    /// The peripheral device type the device reported when it was connected.
+    pub fn device_type(&self) -> PeripheralDeviceType {
+        self.info.device_type
+    }
+
+    /// Whether or not the device can only be read from; currently this is the
+    /// case for MMC (CD/DVD) devices.
+    pub fn is_read_only(&self) -> bool {
+        self.info.is_read_only()
+    }
+}
+
+fn read_csw<C: CommunicationChannel>(
+    comm_channel: &mut C,
+) -> Result<CommandStatusWrapper, ScsiError> {
+    let mut scratch_buffer = [0; CommandStatusWrapper::SIZE as usize];
+    let read_count = comm_channel.in_transfer(&mut scratch_buffer)?;
+    parse_csw(&scratch_buffer, read_count)
+}
+
+fn push_command<C: CommunicationChannel, Cmd: Command>(
+    comm_channel: &mut C,
+    command: &Cmd,
+) -> Result<usize, ScsiError> {
+    let mut scratch_buffer = [0; 31];
+    // Push the command's bytes to the buffer
+    let _serial_bytes = command.push_to_buffer(&mut scratch_buffer)?;
+    let pushed_bytes = comm_channel.out_transfer(scratch_buffer)?;
+    check_pushed(pushed_bytes)
+}
+
+fn transfer_out_command<Usb: CommunicationChannel, C: Command, OutBuff: AsRef<[u8]>>(
+    comm_channel: &mut Usb,
+    command: &C,
+    out_buffer: OutBuff,
+) -> Result<(usize, CommandStatusWrapper), ScsiError> {
+    transfer_out_parts(comm_channel, command, &[out_buffer.as_ref()])
+}
+
+/// Like `transfer_out_command`, but sends the data phase from each of `parts`
+/// in turn.
+fn transfer_out_parts<Usb: CommunicationChannel, C: Command>(
+    comm_channel: &mut Usb,
+    command: &C,
+    parts: &[&[u8]],
+) -> Result<(usize, CommandStatusWrapper), ScsiError> {
+    let _command_bytes = push_command(comm_channel, command)?;
+
+    let total = parts.iter().map(|part| part.len()).sum();
+    let length = data_length(command, total, Direction::IN)?;
+    let mut written = 0;
+    for part in parts {
+        let part = &part[..part.len().min(length - written)];
+        let mut sent = 0;
+        while sent < part.len() {
+            let cur = comm_channel.out_transfer(&part[sent..])?;
+            if cur == 0 {
+                return Err(ScsiError::from_cause(ErrorCause::UsbTransferError {
+                    direction: UsbTransferDirection::Out,
+                }));
+            }
+            sent += cur;
+        }
+        written += sent;
+    }
+    let csw = read_csw(comm_channel)?;
+    Ok((
+        written,
+        check_status(command, csw, comm_channel.scsi_status())?,
+    ))
+}
+
+fn transfer_in_command<Usb: CommunicationChannel, C: Command, InBuff: AsMut<[u8]>>(
+    comm_channel: &mut Usb,
+    command: &C,
+    mut in_buffer: InBuff,
+) -> Result<(usize, CommandStatusWrapper), ScsiError> {
+    let _command_bytes = push_command(comm_channel, command)?;
+
+    let in_buffer = in_buffer.as_mut();
+    let length = data_length(command, in_buffer.len(), Direction::OUT)?;
+    let in_buffer = &mut in_buffer[..length];
+    let mut read = 0;
+    while read < in_buffer.len() {
+        let cur = comm_channel.in_transfer(&mut in_buffer[read..])?;
+        if cur == 0 {
+            return Err(ScsiError::from_cause(ErrorCause::UsbTransferError {
+                direction: UsbTransferDirection::In,
+            }));
+        }
+        read += cur;
+    }
+    let csw = read_csw(comm_channel)?;
+    Ok((
+        read,
+        check_status(command, csw, comm_channel.scsi_status())?,
+    ))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{DeviceTypePolicy, ScrubEvent, ScsiBlockDevice};
+    use crate::error::ErrorCause;
+    use crate::scsi::cdrom::CdromResponder;
+    use crate::scsi::commands::{
+        ErrorCounterKind, ErrorCounterPage, FormatParameters, InformationalExceptionsPage, LogPage,
+        LogParameters, LogSelectCommand, OverwriteParameters, PeripheralDeviceType,
+        RequestSenseResponse, SanitizeCommand, SanitizeServiceAction, SelfTestResult,
+        SelfTestResultsPage, SolidStateMediaPage, TemperaturePage, WriteBufferMode,
+    };
+    use crate::scsi::responder::tests::{LoopbackChannel, MemoryResponder};
+    use crate::traits::BufferPushable;
+    use std::vec::Vec;
+
+    fn image() -> Vec<u8> {
+        (0..4 * CdromResponder::<&[u8]>::BLOCK_SIZE)
+            .map(|idx| (idx / CdromResponder::<&[u8]>::BLOCK_SIZE) as u8)
+            .collect()
+    }
+
+    #[test]
+    fn test_rejects_cdrom_by_default() {
+        let image = image();
+        let channel = LoopbackChannel::new(CdromResponder::new(Some(&image[..])));
+        let mut scratch = [0; 64];
+        let err = ScsiBlockDevice::new(channel, &mut scratch).err().unwrap();
+        assert_eq!(err.cause, ErrorCause::InvalidDeviceError);
+    }
+
+    #[test]
+    fn test_cdrom_block_device() {
+        let image = image();
+        let mut responder = CdromResponder::new(None);
+        // Leaves a unit attention pending, which initialization has to clear.
+        responder.insert(&image[..]);
+        let channel = LoopbackChannel::new(responder);
+        let mut scratch = [0; 64];
+        let mut device =
+            ScsiBlockDevice::with_policy(channel, &mut scratch, DeviceTypePolicy::BlockDevices)
+                .unwrap();
+        assert_eq!(device.device_type(), PeripheralDeviceType::CdDvd);
+        assert_eq!(device.block_size(), 2048);
+        assert!(device.is_read_only());
+
+        let mut buffer = [0; 2 * 2048];
+        assert_eq!(device.read(2048, &mut buffer[..]).unwrap(), buffer.len());
+        assert!(buffer[..2048].iter().all(|&b| b == 1));
+        assert!(buffer[2048..].iter().all(|&b| b == 2));
+
+        let err = device.write(0, &mut buffer[..]).err().unwrap();
+        assert_eq!(err.cause, ErrorCause::ReadOnlyDeviceError);
+    }
+
+    #[test]
+    fn test_explicit_device_types() {
+        let image = image();
+        let allowed = [PeripheralDeviceType::CdDvd];
+        let channel = LoopbackChannel::new(CdromResponder::new(Some(&image[..])));
+        let mut scratch = [0; 64];
+        let device =
+            ScsiBlockDevice::with_policy(channel, &mut scratch, DeviceTypePolicy::Only(&allowed))
+                .unwrap();
+        assert_eq!(device.device_type(), PeripheralDeviceType::CdDvd);
+
+        let rejected = [PeripheralDeviceType::DirectAccess];
+        let channel = LoopbackChannel::new(CdromResponder::new(Some(&image[..])));
+        let err =
+            ScsiBlockDevice::with_policy(channel, &mut scratch, DeviceTypePolicy::Only(&rejected))
+                .err()
+                .unwrap();
+        assert_eq!(err.cause, ErrorCause::InvalidDeviceError);
+    }
+
+    #[test]
+    fn test_block_addressing() {
+        let channel = LoopbackChannel::new(MemoryResponder::default());
+        let mut scratch = [0; 64];
+        let mut device = ScsiBlockDevice::new(channel, &mut scratch).unwrap();
+        assert_eq!(device.num_blocks(), 1024);
+        assert_eq!(device.capacity_bytes(), 256 * 1024);
+
+        let data: Vec<u8> = (0..3 * 256).map(|idx| (idx / 256) as u8 + 1).collect();
+        assert_eq!(device.write_blocks(1021, 3, &data).unwrap(), data.len());
+        let mut readback = [0; 4 * 256];
+        assert_eq!(
+            device.read_blocks(1020, 4, &mut readback[..]).unwrap(),
+            4 * 256
+        );
+        assert!(readback[..256].iter().all(|&b| b == 0));
+        assert_eq!(&readback[256..], &data[..]);
+
+        let err = device
+            .read_blocks(1022, 3, &mut readback[..])
+            .err()
+            .unwrap();
+        assert_eq!(
+            err.cause,
+            ErrorCause::BlockAddressOutOfRangeError {
+                block_address: 1022,
+                num_blocks: 1024,
+            }
+        );
+        let err = device.write_blocks(0, 2, &data[..256]).err().unwrap();
+        assert_eq!(
+            err.cause,
+            ErrorCause::BufferTooSmallError {
+                expected: 512,
+                actual: 256,
+            }
+        );
+    }
+
+    #[test]
+    fn test_split_transfers() {
+        let channel = LoopbackChannel::new(MemoryResponder::default());
+        let mut scratch = [0; 64];
+        let mut device = ScsiBlockDevice::new(channel, &mut scratch).unwrap();
+        assert_eq!(device.max_transfer_blocks(), 0xffff);
+        assert_eq!(device.read_block_limits().unwrap().max_transfer_length, 16);
+        assert_eq!(device.max_transfer_blocks(), 16);
+        device.set_max_transfer_blocks(100);
+        assert_eq!(device.max_transfer_blocks(), 16);
+        device.set_max_transfer_blocks(10);
+        assert_eq!(device.max_transfer_blocks(), 10);
+
+        let data: Vec<u8> = (0..25 * 256).map(|idx| (idx / 256) as u8).collect();
+        let before = device.comm_channel.commands;
+        assert_eq!(device.write(256, data.clone()).unwrap(), data.len());
+        assert_eq!(device.comm_channel.commands - before, 3);
+        assert_eq!(device.prev_csw.unwrap().data_residue, 0);
+
+        device.set_max_transfer_blocks(0);
+        let mut readback = vec![0; data.len()];
+        let before = device.comm_channel.commands;
+        assert_eq!(device.read(256, &mut readback[..]).unwrap(), data.len());
+        assert_eq!(device.comm_channel.commands - before, 2);
+        assert_eq!(readback, data);
+    }
+
+    #[test]
+    fn test_discard_and_write_same() {
+        let channel = LoopbackChannel::new(MemoryResponder::default());
+        let mut scratch = [0; 64];
+        let mut device = ScsiBlockDevice::new(channel, &mut scratch).unwrap();
+        device.comm_channel.responder.buffer.fill(0xff);
+        let page = device.read_block_limits().unwrap();
+        assert_eq!(page.optimal_unmap_granularity, 4);
+        assert_eq!(page.unmap_granularity_alignment, Some(2));
+
+        // Units of 4 blocks start at block 2, so the first and last 2 blocks
+        // are left alone, and each command covers at most 4 * 8 blocks.
+        let before = device.comm_channel.commands;
+        assert_eq!(device.discard(0, 1024).unwrap(), 1020);
+        assert_eq!(device.comm_channel.commands - before, 32);
+        let buffer = &device.comm_channel.responder.buffer;
+        assert!(buffer[..2 * 256].iter().all(|&byte| byte == 0xff));
+        assert!(buffer[2 * 256..1022 * 256].iter().all(|&byte| byte == 0));
+        assert!(buffer[1022 * 256..].iter().all(|&byte| byte == 0xff));
+
+        assert_eq!(device.discard(1, 20).unwrap(), 16);
+        assert_eq!(device.discard(3, 2).unwrap(), 0);
+        assert_eq!(device.discard(0, 1).unwrap(), 0);
+        assert_eq!(device.discard(0, 0).unwrap(), 0);
+        let err = device.discard(1000, 100).err().unwrap();
+        assert_eq!(
+            err.cause,
+            ErrorCause::BlockAddressOutOfRangeError {
+                block_address: 1000,
+                num_blocks: 1024,
+            }
+        );
+
+        let mut block = [0xab; 256];
+        block[0] = 0;
+        device.write_same(100, 5, &block, false).unwrap();
+        let mut readback = vec![0; 7 * 256];
+        device.read(99 * 256, &mut readback[..]).unwrap();
+        assert!(readback[..256].iter().all(|&byte| byte == 0));
+        for lba in 1..6 {
+            assert_eq!(&readback[lba * 256..(lba + 1) * 256], &block[..]);
+        }
+        assert!(readback[6 * 256..].iter().all(|&byte| byte == 0));
+        let err = device
+            .write_same(100, 5, &block[..10], false)
+            .err()
+            .unwrap();
+        assert_eq!(
+            err.cause,
+            ErrorCause::BufferTooSmallError {
+                expected: 256,
+                actual: 10,
+            }
+        );
+    }
+
+    #[test]
+    fn test_verify_error_keeps_tags() {
+        let channel = LoopbackChannel::new(MemoryResponder::default());
+        let mut scratch = [0; 64];
+        let mut device = ScsiBlockDevice::new(channel, &mut scratch).unwrap();
+        device.set_max_transfer_blocks(2);
+        device.verify(0, 4, None).unwrap();
+        let tag = device.prev_csw.unwrap().tag;
+
+        // Nothing passed before the failure: the previous status is kept.
+        device.comm_channel.responder.bad_blocks = vec![1];
+        device.verify(0, 4, None).unwrap_err();
+        assert_eq!(device.prev_csw.unwrap().tag, tag);
+        assert_eq!(
+            device.request_sense().unwrap().sense_key,
+            RequestSenseResponse::MEDIUM_ERROR
+        );
+        assert_eq!(device.prev_csw.unwrap().tag, tag + 1);
+
+        // The first chunk passed, so its status is recorded.
+        device.comm_channel.responder.bad_blocks = vec![3];
+        device.verify(0, 4, None).unwrap_err();
+        let csw = device.prev_csw.unwrap();
+        assert_eq!((csw.tag, csw.status), (tag + 2, 0));
+        device.request_sense().unwrap();
+        assert_eq!(device.prev_csw.unwrap().tag, tag + 3);
+
+        // The scrub's REQUEST SENSE follows the failed verify.
+        assert_eq!(device.scrub(4, |_| {}).unwrap(), 1);
+        assert!(device.prev_csw.unwrap().tag > tag + 3);
+    }
+
+    #[test]
+    fn test_large_device() {
+        // The last 1024 blocks of a device with 2^32 + 4 blocks, which READ
+        // CAPACITY(10) cannot describe.
+        let mut responder = MemoryResponder::default();
+        responder.base_lba = 0xffff_fc04;
+        let channel = LoopbackChannel::new(responder);
+        let mut scratch = [0; 64];
+        let mut device = ScsiBlockDevice::new(channel, &mut scratch).unwrap();
+        assert_eq!(device.num_blocks(), 0x1_0000_0004);
+        device.set_max_transfer_blocks(2);
+
+        // Blocks past 2^32 can only be reached with the 16 byte commands.
+        let data: Vec<u8> = (0..6 * 256).map(|idx| (idx / 256) as u8 + 1).collect();
+        let lba = 0xffff_fffe;
+        assert_eq!(device.write_blocks(lba, 6, &data).unwrap(), data.len());
+        let mut readback = vec![0; data.len()];
+        assert_eq!(
+            device.read_blocks(lba, 6, &mut readback[..]).unwrap(),
+            data.len()
+        );
+        assert_eq!(readback, data);
+        let stored = &device.comm_channel.responder.buffer[1018 * 256..];
+        assert_eq!(stored, &data[..]);
+        device.verify(lba, 6, Some(&data[..])).unwrap();
+
+        let err = device
+            .read_blocks(0x1_0000_0003, 2, &mut readback[..])
+            .err()
+            .unwrap();
+        assert_eq!(
+            err.cause,
+            ErrorCause::BlockAddressOutOfRangeError {
+                block_address: 0x1_0000_0003,
+                num_blocks: 0x1_0000_0004,
+            }
+        );
+    }
+
+    #[test]
+    fn test_verify_and_scrub() {
+        let channel = LoopbackChannel::new(MemoryResponder::default());
+        let mut scratch = [0; 64];
+        let mut device = ScsiBlockDevice::new(channel, &mut scratch).unwrap();
+        let mut data: Vec<u8> = (0..4 * 256).map(|idx| idx as u8).collect();
+        device.write_blocks(10, 4, &data[..]).unwrap();
+        device.verify(10, 4, None).unwrap();
+        device.verify(10, 4, Some(&data[..])).unwrap();
+
+        data[2 * 256 + 5] ^= 0xff;
+        let err = device.verify(10, 4, Some(&data[..])).err().unwrap();
+        assert_eq!(err.cause, ErrorCause::FlagError { flags: 1 });
+        let sense = device.request_sense().unwrap();
+        assert_eq!(sense.sense_key, RequestSenseResponse::MISCOMPARE);
+        assert_eq!(sense.information, 2 * 256 + 5);
+        assert_eq!(
+            device.request_sense().unwrap().sense_key,
+            RequestSenseResponse::NO_SENSE
+        );
+        let err = device.verify(1020, 8, None).err().unwrap();
+        assert_eq!(
+            err.cause,
+            ErrorCause::BlockAddressOutOfRangeError {
+                block_address: 1020,
+                num_blocks: 1024,
+            }
+        );
+
+        device.comm_channel.responder.bad_blocks = vec![5, 700, 701];
+        device.read_block_limits().unwrap();
+        for vague_errors in [false, true] {
+            device.comm_channel.responder.vague_errors = vague_errors;
+            let mut bad = Vec::new();
+            let mut checked = 0;
+            let found = device
+                .scrub(0, |event| match event {
+                    ScrubEvent::Progress {
+                        checked: now,
+                        total,
+                    } => {
+                        assert!(now > checked);
+                        assert_eq!(total, 1024);
+                        checked = now;
+                    }
+                    ScrubEvent::BadBlock { lba, sense } => {
+                        assert_eq!(sense.sense_key, RequestSenseResponse::MEDIUM_ERROR);
+                        bad.push(lba);
+                    }
+                })
+                .unwrap();
+            assert_eq!(found, 3);
+            assert_eq!(bad, vec![5, 700, 701]);
+            assert_eq!(checked, 1024);
+        }
+    }
+
+    #[test]
+    fn test_format_unit() {
+        let channel = LoopbackChannel::new(MemoryResponder::default());
+        let mut scratch = [0; 64];
+        let mut device = ScsiBlockDevice::new(channel, &mut scratch).unwrap();
+        let data = vec![0x5a; 4 * 256];
+        let mut readback = vec![0xff; data.len()];
+
+        device.write_blocks(8, 4, &data[..]).unwrap();
+        device.format_unit(None).unwrap();
+        assert_eq!(device.format_progress().unwrap(), None);
+        device.read_blocks(8, 4, &mut readback[..]).unwrap();
+        assert!(readback.iter().all(|&byte| byte == 0));
+
+        let mut parameters = FormatParameters {
+            immediate: true,
+            ..Default::default()
+        };
+        device.write_blocks(8, 4, &data[..]).unwrap();
+        device.format_unit(Some(&parameters)).unwrap();
+        assert_eq!(device.format_progress().unwrap(), Some(0));
+        assert_eq!(device.format_progress().unwrap(), Some(0x8000));
+        assert_eq!(device.format_progress().unwrap(), None);
+        device.read_blocks(8, 4, &mut readback[..]).unwrap();
+        assert!(readback.iter().all(|&byte| byte == 0));
+
+        parameters.defect_list_length = 4;
+        let err = device.format_unit(Some(&parameters)).err().unwrap();
+        assert_eq!(
+            err.cause,
+            ErrorCause::BufferTooSmallError {
+                expected: 8,
+                actual: 4,
+            }
+        );
+    }
+
+    #[test]
+    fn test_sanitize() {
+        let channel = LoopbackChannel::new(MemoryResponder::default());
+        let mut scratch = [0; 64];
+        let mut device = ScsiBlockDevice::new(channel, &mut scratch).unwrap();
+        let mut readback = vec![0; 2 * 256];
+
+        let parameters = OverwriteParameters::new(&[0x12, 0x34]);
+        device
+            .sanitize(SanitizeCommand::overwrite(&parameters), Some(&parameters))
+            .unwrap();
+        assert_eq!(device.sanitize_progress().unwrap(), None);
+        device.read_blocks(0, 2, &mut readback[..]).unwrap();
+        assert!(readback.chunks(2).all(|pair| pair == [0x12, 0x34]));
+
+        // The parameter list length follows the parameters that are sent.
+        let mut command = SanitizeCommand::overwrite(&parameters);
+        command.service_action = SanitizeServiceAction::BlockErase;
+        command.immediate = true;
+        device.sanitize(command, None).unwrap();
+        assert_eq!(device.sanitize_progress().unwrap(), Some(0));
+        assert_eq!(device.sanitize_progress().unwrap(), Some(0x8000));
+        assert_eq!(device.sanitize_progress().unwrap(), None);
+        device.read_blocks(0, 2, &mut readback[..]).unwrap();
+        assert!(readback.iter().all(|&byte| byte == 0));
+
+        let long = OverwriteParameters::new(&[0; 513]);
+        let err = device.sanitize(SanitizeCommand::overwrite(&long), Some(&long));
+        assert_eq!(
+            err.err().unwrap().cause,
+            ErrorCause::TransferTooLargeError {
+                actual: 513,
+                max: 512,
+            }
+        );
+    }
+
+    #[test]
+    fn test_compare_and_write() {
+        let channel = LoopbackChannel::new(MemoryResponder::default());
+        let mut scratch = [0; 64];
+        let mut device = ScsiBlockDevice::new(channel, &mut scratch).unwrap();
+        let unlocked = [0; 256];
+        let mut locked = [0; 256];
+        locked[16..20].copy_from_slice(b"host");
+
+        device.compare_and_write(9, &unlocked, &locked).unwrap();
+        assert_eq!(
+            &device.comm_channel.responder.buffer[9 * 256..10 * 256],
+            &locked
+        );
+
+        // A second attempt to take the lock sees it is already held.
+        let err = device
+            .compare_and_write(9, &unlocked, &locked)
+            .err()
+            .unwrap();
+        assert_eq!(err.cause, ErrorCause::MiscompareError { offset: Some(16) });
+        assert_eq!(
+            &device.comm_channel.responder.buffer[9 * 256..10 * 256],
+            &locked
+        );
+
+        device.compare_and_write(9, &locked, &unlocked).unwrap();
+        assert!(device
+            .comm_channel
+            .responder
+            .buffer
+            .iter()
+            .all(|&byte| byte == 0));
+
+        let err = device
+            .compare_and_write(9, &unlocked, &locked[..128])
+            .err()
+            .unwrap();
+        assert_eq!(
+            err.cause,
+            ErrorCause::BufferTooSmallError {
+                expected: 256,
+                actual: 128,
+            }
+        );
+        let err = device
+            .compare_and_write(1024, &unlocked, &locked)
+            .err()
+            .unwrap();
+        assert_eq!(
+            err.cause,
+            ErrorCause::BlockAddressOutOfRangeError {
+                block_address: 1024,
+                num_blocks: 1024,
+            }
+        );
+    }
+
+    #[test]
+    fn test_download_microcode() {
+        let channel = LoopbackChannel::new(MemoryResponder::default());
+        let mut scratch = [0; 64];
+        let mut device = ScsiBlockDevice::new(channel, &mut scratch).unwrap();
+        let image: Vec<u8> = (0..1000).map(|idx| (idx % 251) as u8).collect();
+
+        let descriptor = device.read_buffer_descriptor(0).unwrap();
+        assert_eq!(descriptor.offset_alignment(), Some(256));
+        assert_eq!(descriptor.capacity, 0x1_0000);
+
+        let mode = WriteBufferMode::DownloadMicrocodeWithOffsetsSaveAndDefer;
+        device.download_microcode(mode, 0, &image, 512).unwrap();
+        let responder = &device.comm_channel.responder;
+        assert_eq!(
+            responder.firmware_chunks,
+            [(0, 0, 256), (0, 256, 256), (512, 512, 256), (512, 768, 232)]
+        );
+        assert!(responder.active_firmware.is_empty());
+        device.activate_deferred_microcode().unwrap();
+        assert_eq!(device.comm_channel.responder.active_firmware, image);
+
+        let mut readback = [0; 488];
+        assert_eq!(device.read_buffer(0, 512, &mut readback[..]).unwrap(), 488);
+        assert_eq!(&readback[..], &image[512..]);
+
+        // Segments that break the device's offset boundary are rejected.
+        let mode = WriteBufferMode::DownloadMicrocodeWithOffsetsSaveAndActivate;
+        let err = device
+            .download_microcode(mode, 0, &image, 300)
+            .err()
+            .unwrap();
+        assert_eq!(err.cause, ErrorCause::FlagError { flags: 1 });
+        let sense = device.request_sense().unwrap();
+        assert_eq!(sense.sense_key, RequestSenseResponse::ILLEGAL_REQUEST);
+
+        let err = device
+            .download_microcode(WriteBufferMode::Data, 0, &image, 512)
+            .err()
+            .unwrap();
+        assert_eq!(err.cause, ErrorCause::UnsupportedOperationError);
+        let err = device
+            .download_microcode(mode, 0, &image, 0x100_0000)
+            .err()
+            .unwrap();
+        assert_eq!(
+            err.cause,
+            ErrorCause::TransferTooLargeError {
+                actual: 0x100_0000,
+                max: 0xff_ffff,
+            }
+        );
+    }
+
+    #[test]
+    fn test_echo_buffer() {
+        let channel = LoopbackChannel::new(MemoryResponder::default());
+        let mut scratch = [0; 64];
+        let mut device = ScsiBlockDevice::new(channel, &mut scratch).unwrap();
+        let descriptor = device.read_echo_buffer_descriptor().unwrap();
+        assert_eq!(descriptor.capacity, 256);
+
+        let pattern = [0xa5; 100];
+        device
+            .write_buffer(WriteBufferMode::EchoBuffer, 0, 0, &pattern)
+            .unwrap();
+        let mut echoed = [0; 100];
+        assert_eq!(device.read_echo_buffer(&mut echoed[..]).unwrap(), 100);
+        assert_eq!(echoed, pattern);
+    }
+
+    #[test]
+    fn test_log_pages() {
+        let mut responder = MemoryResponder::default();
+        let mut self_tests = SelfTestResultsPage::default();
+        for power_on_hours in [300, 200, 100] {
+            self_tests
+                .push_result(SelfTestResult {
+                    self_test_code: 1,
+                    power_on_hours,
+                    ..Default::default()
+                })
+                .unwrap();
+        }
+        responder.log_pages = vec![
+            LogPage::ErrorCounter(ErrorCounterPage {
+                kind: ErrorCounterKind::Read,
+                total_corrected: Some(17),
+                total_uncorrected: Some(2),
+                ..Default::default()
+            }),
+            LogPage::Temperature(TemperaturePage {
+                temperature: Some(41),
+                reference_temperature: Some(65),
+            }),
+            LogPage::SelfTestResults(self_tests),
+            LogPage::SolidStateMedia(SolidStateMediaPage {
+                percentage_used: Some(12),
+            }),
+            LogPage::InformationalExceptions(InformationalExceptionsPage::default()),
+        ];
+        let channel = LoopbackChannel::new(responder);
+        let mut scratch = [0; 64];
+        let mut device = ScsiBlockDevice::new(channel, &mut scratch).unwrap();
+
+        let supported = device.supported_log_pages().unwrap();
+        assert_eq!(
+            supported.page_codes(),
+            &[0x00, 0x03, 0x0d, 0x10, 0x11, 0x2f]
+        );
+        assert_eq!(device.temperature().unwrap().temperature, Some(41));
+        assert_eq!(
+            device.solid_state_media().unwrap().percentage_used,
+            Some(12)
+        );
+        assert!(device.informational_exceptions().unwrap().is_healthy());
+
+        // Pages are read header first, then in full.
+        let results = device.self_test_results().unwrap();
+        assert_eq!(results, self_tests);
+        assert_eq!(results.latest().unwrap().power_on_hours, 300);
+
+        let counters = device.error_counters(ErrorCounterKind::Read).unwrap();
+        assert_eq!(counters.total_uncorrected, Some(2));
+        device.reset_log_parameters().unwrap();
+        let counters = device.error_counters(ErrorCounterKind::Read).unwrap();
+        assert_eq!(counters.total_uncorrected, None);
+
+        // Raw pages can be walked parameter by parameter.
+        let mut raw = [0; 64];
+        let length = device
+            .log_sense(TemperaturePage::PAGE_CODE, &mut raw)
+            .unwrap();
+        assert_eq!(length, TemperaturePage::SIZE);
+        let parameters = LogParameters::new(&raw[..length]).unwrap();
+        assert_eq!(parameters.map(|p| p.value[1]).collect::<Vec<_>>(), [41, 65]);
+
+        let cooler = TemperaturePage {
+            temperature: Some(30),
+            reference_temperature: Some(65),
+        };
+        let mut list = [0; TemperaturePage::SIZE];
+        cooler.push_to_buffer(&mut list[..]).unwrap();
+        let command = LogSelectCommand::new(TemperaturePage::PAGE_CODE, 0);
+        device.log_select(command, &list).unwrap();
+        assert_eq!(
+            device.read_log_page(TemperaturePage::PAGE_CODE).unwrap(),
+            LogPage::Temperature(cooler)
+        );
+
+        assert!(device.start_stop_cycles().is_err());
+        let sense = device.request_sense().unwrap();
+        assert_eq!(sense.sense_key, RequestSenseResponse::ILLEGAL_REQUEST);
+    }
+}
@@ -0,0 +1,177 @@
+use embedded_io::{Read, Write};
+
+use crate::error::{ErrorCause, ScsiError, UsbTransferDirection};
+use crate::traits::CommunicationChannel;
+
+/// A `CommunicationChannel` over an `embedded_io` byte stream, such as a UART
+/// or a TCP socket from an embedded network stack.
+///
+/// Byte streams have no packet boundaries, so `in_transfer` keeps reading
+/// until the buffer is full or the stream ends; both ends of the stream need
+/// to agree on the length of every transfer.
+pub struct EmbeddedIoChannel<T> {
+    inner: T,
+}
+
+impl<T> EmbeddedIoChannel<T> {
+    /// Wraps `inner`.
+    pub fn new(inner: T) -> Self {
+        EmbeddedIoChannel { inner }
+    }
+
+    /// Returns a reference to the underlying stream.
+    pub fn get_ref(&self) -> &T {
+        &self.inner
+    }
+
+    /// Returns a mutable reference to the underlying stream.
+    pub fn get_mut(&mut self) -> &mut T {
+        &mut self.inner
+    }
+
+    /// Unwraps the underlying stream.
+    pub fn into_inner(self) -> T {
+        self.inner
+    }
+}
+
+impl<T: Read + Write> CommunicationChannel for EmbeddedIoChannel<T> {
+    fn out_transfer<B: AsRef<[u8]>>(&mut self, bytes: B) -> Result<usize, ScsiError> {
+        let bytes = bytes.as_ref();
+        let error = ScsiError::from_cause(ErrorCause::UsbTransferError {
+            direction: UsbTransferDirection::Out,
+        });
+        let mut written = 0;
+        while written < bytes.len() {
+            // Unlike `write_all`, treat a writer that stops accepting data as
+            // an error rather than panicking.
+            match self.inner.write(&bytes[written..]) {
+                Ok(0) | Err(_) => return Err(error),
+                Ok(count) => written += count,
+            }
+        }
+        self.inner.flush().map_err(|_| error)?;
+        Ok(written)
+    }
+
+    fn in_transfer<B: AsMut<[u8]>>(&mut self, mut buffer: B) -> Result<usize, ScsiError> {
+        let buffer = buffer.as_mut();
+        let mut read = 0;
+        while read < buffer.len() {
+            match self.inner.read(&mut buffer[read..]) {
+                Ok(0) => break,
+                Ok(count) => read += count,
+                Err(_) => {
+                    return Err(ScsiError::from_cause(ErrorCause::UsbTransferError {
+                        direction: UsbTransferDirection::In,
+                    }))
+                }
+            }
+        }
+        Ok(read)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::EmbeddedIoChannel;
+    use crate::error::{ErrorCause, UsbTransferDirection};
+    use crate::traits::CommunicationChannel;
+    use embedded_io::{ErrorKind, ErrorType, Read, Write};
+    use std::vec::Vec;
+
+    /// A stream moving at most 5 bytes per call.
+    #[derive(Default)]
+    struct TricklingStream {
+        incoming: Vec<u8>,
+        outgoing: Vec<u8>,
+        closed: bool,
+        broken: bool,
+    }
+
+    impl ErrorType for TricklingStream {
+        type Error = ErrorKind;
+    }
+
+    impl Read for TricklingStream {
+        fn read(&mut self, buf: &mut [u8]) -> Result<usize, ErrorKind> {
+            if self.broken && self.incoming.is_empty() {
+                return Err(ErrorKind::BrokenPipe);
+            }
+            let count = buf.len().min(self.incoming.len()).min(5);
+            buf[..count].copy_from_slice(&self.incoming[..count]);
+            self.incoming.drain(..count);
+            Ok(count)
+        }
+    }
+
+    impl Write for TricklingStream {
+        fn write(&mut self, buf: &[u8]) -> Result<usize, ErrorKind> {
+            if self.broken {
+                return Err(ErrorKind::BrokenPipe);
+            }
+            let count = if self.closed { 0 } else { buf.len().min(5) };
+            self.outgoing.extend_from_slice(&buf[..count]);
+            Ok(count)
+        }
+
+        fn flush(&mut self) -> Result<(), ErrorKind> {
+            Ok(())
+        }
+    }
+
+    #[test]
+    fn test_embedded_io_channel() {
+        let mut channel = EmbeddedIoChannel::new(TricklingStream::default());
+        assert_eq!(channel.out_transfer([1; 31]).unwrap(), 31);
+        assert_eq!(channel.get_ref().outgoing, vec![1; 31]);
+
+        channel.get_mut().incoming = (0..20).collect();
+        let mut buffer = [0; 13];
+        assert_eq!(channel.in_transfer(&mut buffer).unwrap(), 13);
+        assert_eq!(&buffer[..], &(0..13).collect::<Vec<u8>>()[..]);
+        assert_eq!(channel.in_transfer(&mut buffer).unwrap(), 7);
+
+        // Writers that stop accepting data fail the transfer.
+        channel.get_mut().closed = true;
+        let err = channel.out_transfer([1; 13]).err().unwrap();
+        assert_eq!(
+            err.cause,
+            ErrorCause::UsbTransferError {
+                direction: UsbTransferDirection::Out,
+            }
+        );
+    }
+
+    #[test]
+    fn test_embedded_io_errors() {
+        let mut channel = EmbeddedIoChannel::new(TricklingStream::default());
+
+        // A stream that ends early cuts the transfer short, leaving the
+        // caller to notice.
+        channel.get_mut().incoming = vec![7; 9];
+        let mut buffer = [0; 13];
+        assert_eq!(channel.in_transfer(&mut buffer).unwrap(), 9);
+        assert_eq!(channel.in_transfer(&mut buffer).unwrap(), 0);
+
+        // A connection that breaks partway through fails the transfer, even
+        // though some of the data had arrived.
+        channel.get_mut().incoming = vec![7; 9];
+        channel.get_mut().broken = true;
+        let err = channel.in_transfer(&mut buffer).err().unwrap();
+        assert_eq!(
+            err.cause,
+            ErrorCause::UsbTransferError {
+                direction: UsbTransferDirection::In,
+            }
+        );
+        let err = channel.out_transfer([1; 31]).err().unwrap();
+        assert_eq!(
+            err.cause,
+            ErrorCause::UsbTransferError {
+                direction: UsbTransferDirection::Out,
+            }
+        );
+        assert!(channel.get_ref().outgoing.is_empty());
+    }
+}
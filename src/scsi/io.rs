@@ -0,0 +1,197 @@
+use std::io::{self, Read, Seek, SeekFrom, Write};
+use std::vec::Vec;
+
+use crate::scsi::ScsiBlockDevice;
+use crate::traits::CommunicationChannel;
+
+/// Exposes an `ScsiBlockDevice` as a byte stream implementing `std::io::Read`,
+/// `Write` and `Seek`, so that it can be handed to crates like `fatfs`.
+///
+/// Accesses that are not aligned to the device's block size are handled by
+/// reading the surrounding block and, for writes, writing the modified block
+/// back.
+pub struct ScsiBlockIo<CommType: CommunicationChannel> {
+    device: ScsiBlockDevice<CommType>,
+    position: u64,
+    size: u64,
+    block: Vec<u8>,
+}
+
+impl<CommType: CommunicationChannel> ScsiBlockIo<CommType> {
+    /// Wraps `device`, starting at the beginning of the device.
+    pub fn new(device: ScsiBlockDevice<CommType>) -> Self {
+        ScsiBlockIo {
+            block: vec![0; device.block_size() as usize],
+            size: device.capacity_bytes(),
+            device,
+            position: 0,
+        }
+    }
+
+    /// The number of bytes that can be accessed through the stream.
+    pub fn size(&self) -> u64 {
+        self.size
+    }
+
+    /// Returns a reference to the underlying `ScsiBlockDevice`.
+    pub fn get_ref(&self) -> &ScsiBlockDevice<CommType> {
+        &self.device
+    }
+
+    /// Returns a mutable reference to the underlying `ScsiBlockDevice`.
+    pub fn get_mut(&mut self) -> &mut ScsiBlockDevice<CommType> {
+        &mut self.device
+    }
+
+    /// Unwraps the underlying `ScsiBlockDevice`.
+    pub fn into_inner(self) -> ScsiBlockDevice<CommType> {
+        self.device
+    }
+
+    /// The number of blocks, starting at the current position, that can be
+    /// transferred directly without going through the block buffer, or 0 if
+    /// the access is unaligned.
+    fn direct_blocks(&self, requested: u64) -> u32 {
+        let block_size = self.block.len() as u64;
+        if self.position % block_size != 0 {
+            return 0;
+        }
+        (requested / block_size).min(u64::from(u32::MAX)) as u32
+    }
+}
+
+impl<CommType: CommunicationChannel> Read for ScsiBlockIo<CommType> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        if buf.is_empty() || self.position >= self.size {
+            return Ok(0);
+        }
+        let requested = (self.size - self.position).min(buf.len() as u64);
+        let block_size = self.block.len() as u64;
+        let lba = self.position / block_size;
+        let direct = self.direct_blocks(requested);
+        let read = if direct > 0 {
+            self.device.read_blocks(lba, direct, &mut buf[..])?
+        } else {
+            let within = (self.position % block_size) as usize;
+            self.device.read_blocks(lba, 1, &mut self.block[..])?;
+            let len = (self.block.len() - within).min(requested as usize);
+            buf[..len].copy_from_slice(&self.block[within..within + len]);
+            len
+        };
+        self.position += read as u64;
+        Ok(read)
+    }
+}
+
+impl<CommType: CommunicationChannel> Write for ScsiBlockIo<CommType> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        if buf.is_empty() || self.position >= self.size {
+            return Ok(0);
+        }
+        let requested = (self.size - self.position).min(buf.len() as u64);
+        let block_size = self.block.len() as u64;
+        let lba = self.position / block_size;
+        let direct = self.direct_blocks(requested);
+        let written = if direct > 0 {
+            self.device.write_blocks(lba, direct, buf)?
+        } else {
+            let within = (self.position % block_size) as usize;
+            self.device.read_blocks(lba, 1, &mut self.block[..])?;
+            let len = (self.block.len() - within).min(requested as usize);
+            self.block[within..within + len].copy_from_slice(&buf[..len]);
+            self.device.write_blocks(lba, 1, &self.block[..])?;
+            len
+        };
+        self.position += written as u64;
+        Ok(written)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+impl<CommType: CommunicationChannel> Seek for ScsiBlockIo<CommType> {
+    fn seek(&mut self, pos: SeekFrom) -> io::Result<u64> {
+        let (base, delta) = match pos {
+            SeekFrom::Start(offset) => {
+                self.position = offset;
+                return Ok(offset);
+            }
+            SeekFrom::End(delta) => (self.size, delta),
+            SeekFrom::Current(delta) => (self.position, delta),
+        };
+        let position = if delta >= 0 {
+            base.checked_add(delta as u64)
+        } else {
+            base.checked_sub(delta.unsigned_abs())
+        };
+        match position {
+            Some(position) => {
+                self.position = position;
+                Ok(position)
+            }
+            None => Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                "invalid seek to a negative or overflowing position",
+            )),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::ScsiBlockIo;
+    use crate::scsi::responder::tests::{LoopbackChannel, MemoryResponder};
+    use crate::scsi::ScsiBlockDevice;
+    use fatfs::{FileSystem, FormatVolumeOptions, FsOptions};
+    use std::io::{Read, Seek, SeekFrom, Write};
+    use std::vec::Vec;
+
+    fn stream() -> ScsiBlockIo<LoopbackChannel<MemoryResponder>> {
+        let channel = LoopbackChannel::new(MemoryResponder::default());
+        let mut scratch = [0; 64];
+        let device = ScsiBlockDevice::new(channel, &mut scratch).unwrap();
+        ScsiBlockIo::new(device)
+    }
+
+    #[test]
+    fn test_unaligned_access() {
+        let mut stream = stream();
+        assert_eq!(stream.size(), 256 * 1024);
+
+        let data: Vec<u8> = (0..600).map(|idx| idx as u8).collect();
+        stream.seek(SeekFrom::Start(100)).unwrap();
+        stream.write_all(&data).unwrap();
+        assert_eq!(stream.stream_position().unwrap(), 700);
+
+        let mut readback = [0; 700];
+        stream.seek(SeekFrom::Start(0)).unwrap();
+        stream.read_exact(&mut readback).unwrap();
+        assert!(readback[..100].iter().all(|&b| b == 0));
+        assert_eq!(&readback[100..], &data[..]);
+
+        stream.seek(SeekFrom::End(-1)).unwrap();
+        assert_eq!(stream.read(&mut readback).unwrap(), 1);
+        assert_eq!(stream.read(&mut readback).unwrap(), 0);
+        assert!(stream.seek(SeekFrom::Current(-(256 * 1024 + 1))).is_err());
+    }
+
+    #[test]
+    fn test_fatfs() {
+        let mut stream = stream();
+        fatfs::format_volume(&mut stream, FormatVolumeOptions::new()).unwrap();
+        stream.seek(SeekFrom::Start(0)).unwrap();
+        {
+            let fs = FileSystem::new(&mut stream, FsOptions::new()).unwrap();
+            let mut file = fs.root_dir().create_file("hello.txt").unwrap();
+            file.write_all(b"Hello, SCSI!").unwrap();
+        }
+        stream.seek(SeekFrom::Start(0)).unwrap();
+        let fs = FileSystem::new(&mut stream, FsOptions::new()).unwrap();
+        let mut contents = Vec::new();
+        let mut file = fs.root_dir().open_file("hello.txt").unwrap();
+        file.read_to_end(&mut contents).unwrap();
+        assert_eq!(&contents[..], b"Hello, SCSI!");
+    }
+}
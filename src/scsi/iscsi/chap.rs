@@ -0,0 +1,170 @@
+use std::string::String;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::time::{SystemTime, UNIX_EPOCH};
+use std::vec::Vec;
+
+use crate::error::{ErrorCause, ScsiError};
+
+/// The CHAP algorithm identifier for MD5, the only one iSCSI requires.
+pub(crate) const CHAP_MD5: &str = "5";
+
+/// Computes the response to a CHAP challenge, `MD5(id || secret || challenge)`.
+pub(crate) fn chap_response(id: u8, secret: &[u8], challenge: &[u8]) -> [u8; 16] {
+    let mut message = Vec::with_capacity(1 + secret.len() + challenge.len());
+    message.push(id);
+    message.extend_from_slice(secret);
+    message.extend_from_slice(challenge);
+    md5(&message)
+}
+
+/// Makes up a fresh 16 byte CHAP challenge.
+///
+/// The challenge only needs to be unpredictable enough that old responses
+/// cannot be replayed, so it is derived from the clock and a counter rather
+/// than a proper random number generator.
+pub(crate) fn generate_challenge() -> [u8; 16] {
+    static COUNTER: AtomicUsize = AtomicUsize::new(0);
+    let time = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|time| time.as_nanos())
+        .unwrap_or(0);
+    let count = COUNTER.fetch_add(1, Ordering::Relaxed) as u64;
+    let mut seed = Vec::with_capacity(28);
+    seed.extend_from_slice(&time.to_le_bytes());
+    seed.extend_from_slice(&count.to_le_bytes());
+    seed.extend_from_slice(&::std::process::id().to_le_bytes());
+    md5(&seed)
+}
+
+/// Encodes `bytes` as a `0x`-prefixed hex string, the format iSCSI uses for
+/// binary key values.
+pub(crate) fn encode_hex(bytes: &[u8]) -> String {
+    let mut encoded = String::with_capacity(2 + 2 * bytes.len());
+    encoded.push_str("0x");
+    for byte in bytes {
+        encoded.push_str(&format!("{:02x}", byte));
+    }
+    encoded
+}
+
+/// Decodes a `0x`-prefixed hex string.
+///
+/// # Errors
+/// Returns a `ParseError` if `value` is not valid hex. Base64 values, which
+/// iSCSI also allows, are not supported.
+pub(crate) fn decode_hex(value: &str) -> Result<Vec<u8>, ScsiError> {
+    let digits = match value.get(..2) {
+        Some("0x") | Some("0X") => &value[2..],
+        _ => return Err(ScsiError::from_cause(ErrorCause::ParseError)),
+    };
+    if digits.is_empty() || digits.len() % 2 != 0 {
+        return Err(ScsiError::from_cause(ErrorCause::ParseError));
+    }
+    (0..digits.len())
+        .step_by(2)
+        .map(|idx| {
+            u8::from_str_radix(&digits[idx..idx + 2], 16)
+                .map_err(|_| ScsiError::from_cause(ErrorCause::ParseError))
+        })
+        .collect()
+}
+
+/// The per-round shift amounts of MD5.
+const MD5_SHIFTS: [u32; 16] = [7, 12, 17, 22, 5, 9, 14, 20, 4, 11, 16, 23, 6, 10, 15, 21];
+
+/// The per-step constants of MD5, `floor(abs(sin(i + 1)) * 2^32)`.
+#[rustfmt::skip]
+const MD5_CONSTANTS: [u32; 64] = [
+    0xd76a_a478, 0xe8c7_b756, 0x2420_70db, 0xc1bd_ceee,
+    0xf57c_0faf, 0x4787_c62a, 0xa830_4613, 0xfd46_9501,
+    0x6980_98d8, 0x8b44_f7af, 0xffff_5bb1, 0x895c_d7be,
+    0x6b90_1122, 0xfd98_7193, 0xa679_438e, 0x49b4_0821,
+    0xf61e_2562, 0xc040_b340, 0x265e_5a51, 0xe9b6_c7aa,
+    0xd62f_105d, 0x0244_1453, 0xd8a1_e681, 0xe7d3_fbc8,
+    0x21e1_cde6, 0xc337_07d6, 0xf4d5_0d87, 0x455a_14ed,
+    0xa9e3_e905, 0xfcef_a3f8, 0x676f_02d9, 0x8d2a_4c8a,
+    0xfffa_3942, 0x8771_f681, 0x6d9d_6122, 0xfde5_380c,
+    0xa4be_ea44, 0x4bde_cfa9, 0xf6bb_4b60, 0xbebf_bc70,
+    0x289b_7ec6, 0xeaa1_27fa, 0xd4ef_3085, 0x0488_1d05,
+    0xd9d4_d039, 0xe6db_99e5, 0x1fa2_7cf8, 0xc4ac_5665,
+    0xf429_2244, 0x432a_ff97, 0xab94_23a7, 0xfc93_a039,
+    0x655b_59c3, 0x8f0c_cc92, 0xffef_f47d, 0x8584_5dd1,
+    0x6fa8_7e4f, 0xfe2c_e6e0, 0xa301_4314, 0x4e08_11a1,
+    0xf753_7e82, 0xbd3a_f235, 0x2ad7_d2bb, 0xeb86_d391,
+];
+
+/// Computes the MD5 digest of `message`, as required by CHAP.
+fn md5(message: &[u8]) -> [u8; 16] {
+    let mut padded = message.to_vec();
+    padded.push(0x80);
+    while padded.len() % 64 != 56 {
+        padded.push(0);
+    }
+    padded.extend_from_slice(&((message.len() as u64).wrapping_mul(8)).to_le_bytes());
+
+    let mut state: [u32; 4] = [0x6745_2301, 0xefcd_ab89, 0x98ba_dcfe, 0x1032_5476];
+    for chunk in padded.chunks(64) {
+        let mut words = [0u32; 16];
+        for (word, bytes) in words.iter_mut().zip(chunk.chunks(4)) {
+            *word = u32::from_le_bytes([bytes[0], bytes[1], bytes[2], bytes[3]]);
+        }
+        let [mut a, mut b, mut c, mut d] = state;
+        for step in 0..64 {
+            let (f, idx) = match step / 16 {
+                0 => ((b & c) | (!b & d), step),
+                1 => ((d & b) | (!d & c), (5 * step + 1) % 16),
+                2 => (b ^ c ^ d, (3 * step + 5) % 16),
+                _ => (c ^ (b | !d), (7 * step) % 16),
+            };
+            let rotated = a
+                .wrapping_add(f)
+                .wrapping_add(MD5_CONSTANTS[step])
+                .wrapping_add(words[idx])
+                .rotate_left(MD5_SHIFTS[(step / 16) * 4 + step % 4]);
+            a = d;
+            d = c;
+            c = b;
+            b = b.wrapping_add(rotated);
+        }
+        state[0] = state[0].wrapping_add(a);
+        state[1] = state[1].wrapping_add(b);
+        state[2] = state[2].wrapping_add(c);
+        state[3] = state[3].wrapping_add(d);
+    }
+
+    let mut digest = [0; 16];
+    for (bytes, word) in digest.chunks_mut(4).zip(state.iter()) {
+        bytes.copy_from_slice(&word.to_le_bytes());
+    }
+    digest
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{chap_response, decode_hex, encode_hex, generate_challenge, md5};
+
+    #[test]
+    fn test_md5() {
+        assert_eq!(encode_hex(&md5(b"")), "0xd41d8cd98f00b204e9800998ecf8427e");
+        assert_eq!(
+            encode_hex(&md5(b"The quick brown fox jumps over the lazy dog")),
+            "0x9e107d9d372bb6826bd81d3542a419d6"
+        );
+        let long = [b'a'; 1000];
+        assert_eq!(
+            encode_hex(&md5(&long)),
+            "0xcabe45dcc9ae5b66ba86600cca6b8ba8"
+        );
+    }
+
+    #[test]
+    fn test_chap_response() {
+        let challenge = decode_hex("0x0123456789abcdef").unwrap();
+        assert_eq!(challenge.len(), 8);
+        let response = chap_response(1, b"secret", &challenge);
+        assert_eq!(encode_hex(&response), "0x1541dc88f46c12336d84ffdaf8cc7dbe");
+        assert!(decode_hex("0x123").is_err());
+        assert!(decode_hex("0bAAAA").is_err());
+        assert_ne!(generate_challenge(), generate_challenge());
+    }
+}
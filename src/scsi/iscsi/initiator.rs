@@ -0,0 +1,812 @@
+use std::io::{Read, Write};
+use std::mem;
+use std::string::String;
+use std::vec::Vec;
+
+use byteorder::{ByteOrder, BE};
+
+use crate::error::{ErrorCause, ScsiError};
+use crate::scsi::commands::{RequestSenseResponse, ScsiStatus};
+use crate::scsi::iscsi::chap::{chap_response, decode_hex, encode_hex, CHAP_MD5};
+use crate::scsi::iscsi::pdu::{
+    Pdu, FULL_FEATURE_PHASE, OPERATIONAL_NEGOTIATION, SECURITY_NEGOTIATION,
+};
+use crate::scsi::passthrough::BulkOnlyEmulation;
+use crate::scsi::{CommandData, CommandExecutor, CommandOutcome};
+use crate::traits::{BufferPullable, CommunicationChannel};
+
+/// The largest data segment we accept from the target.
+const MAX_RECV_DATA_SEGMENT_LENGTH: usize = 64 * 1024;
+
+/// The data segment length assumed for the target if it does not declare one.
+const DEFAULT_DATA_SEGMENT_LENGTH: usize = 8192;
+
+/// The number of login requests sent per stage before giving up on a target
+/// that never lets us move on.
+const MAX_LOGIN_ROUNDS: usize = 8;
+
+/// The initiator session identifier sent at login: a random-format ISID with
+/// a fixed qualifier, since only a single session per initiator is supported.
+const ISID: [u8; 6] = [0x80, 0x00, 0x00, 0x00, 0x53, 0x52];
+
+/// The username and secret used for CHAP authentication.
+#[derive(Clone, Eq, PartialEq, Debug)]
+pub struct ChapCredentials {
+    /// The name sent by the initiator as `CHAP_N`.
+    pub username: String,
+
+    /// The shared secret.
+    pub secret: Vec<u8>,
+}
+
+/// The parameters used to log in to an iSCSI target.
+#[derive(Clone, Eq, PartialEq, Debug)]
+pub struct LoginOptions {
+    /// Our own iSCSI qualified name, eg `iqn.2019-01.com.example:host`.
+    pub initiator_name: String,
+
+    /// The name of the target to log in to.
+    pub target_name: String,
+
+    /// The credentials to authenticate with, or `None` if the target does not
+    /// require authentication.
+    ///
+    /// Only one-way CHAP is supported; the target is not asked to
+    /// authenticate itself.
+    pub chap: Option<ChapCredentials>,
+}
+
+impl LoginOptions {
+    /// Constructs options for logging in without authentication.
+    pub fn new(initiator_name: &str, target_name: &str) -> LoginOptions {
+        LoginOptions {
+            initiator_name: initiator_name.to_string(),
+            target_name: target_name.to_string(),
+            chap: None,
+        }
+    }
+
+    /// Authenticates with CHAP using the given credentials.
+    pub fn with_chap(mut self, username: &str, secret: &[u8]) -> LoginOptions {
+        self.chap = Some(ChapCredentials {
+            username: username.to_string(),
+            secret: secret.to_vec(),
+        });
+        self
+    }
+}
+
+/// The initiator side of an iSCSI session, running SCSI commands on a
+/// network target over any byte stream, usually a `std::net::TcpStream`.
+///
+/// Commands can be run directly through `CommandExecutor`, or the session can
+/// be handed to `ScsiBlockDevice` as its `CommunicationChannel`, in which case
+/// the Bulk-Only CBWs it writes are translated into iSCSI commands.
+///
+/// Only a single command is in flight at a time, and header and data digests
+/// are not supported.
+pub struct IscsiInitiator<S: Read + Write> {
+    stream: S,
+    lun: u64,
+    cmd_sn: u32,
+    exp_stat_sn: u32,
+    next_tag: u32,
+    tsih: u16,
+    max_send_data_segment: usize,
+    emulation: BulkOnlyEmulation,
+}
+
+impl<S: Read + Write> IscsiInitiator<S> {
+    /// Logs in to the target at the other end of `stream`.
+    ///
+    /// # Errors
+    /// Returns a `LoginError` if the target rejects the login, for example
+    /// because the CHAP credentials are wrong.
+    pub fn login(stream: S, options: &LoginOptions) -> Result<IscsiInitiator<S>, ScsiError> {
+        let mut session = IscsiInitiator {
+            stream,
+            lun: 0,
+            cmd_sn: 1,
+            exp_stat_sn: 0,
+            next_tag: 0,
+            tsih: 0,
+            max_send_data_segment: DEFAULT_DATA_SEGMENT_LENGTH,
+            emulation: BulkOnlyEmulation::default(),
+        };
+        let tag = session.allocate_tag();
+        session.security_negotiation(tag, options)?;
+        session.operational_negotiation(tag)?;
+        Ok(session)
+    }
+
+    /// Addresses all future commands to the given logical unit, in SAM format.
+    pub fn set_lun(&mut self, lun: u64) {
+        self.lun = lun;
+    }
+
+    /// The session handle the target assigned at login.
+    pub fn tsih(&self) -> u16 {
+        self.tsih
+    }
+
+    /// Returns a reference to the underlying stream.
+    pub fn get_ref(&self) -> &S {
+        &self.stream
+    }
+
+    /// Returns a mutable reference to the underlying stream.
+    pub fn get_mut(&mut self) -> &mut S {
+        &mut self.stream
+    }
+
+    /// Pings the target with a NOP-Out and waits for its reply, eg to keep
+    /// an idle connection alive.
+    pub fn nop(&mut self) -> Result<(), ScsiError> {
+        let tag = self.allocate_tag();
+        let mut request = Pdu::new(Pdu::NOP_OUT);
+        request.set_immediate(true);
+        request.set_flags(Pdu::FLAG_FINAL);
+        request.set_initiator_task_tag(tag);
+        request.set_field(Pdu::TARGET_TRANSFER_TAG, Pdu::RESERVED_TAG);
+        self.send_immediate(&mut request)?;
+        let response = self.receive()?;
+        if response.opcode() != Pdu::NOP_IN || response.initiator_task_tag() != tag {
+            return Err(ScsiError::from_cause(ErrorCause::ParseError));
+        }
+        Ok(())
+    }
+
+    /// Closes the session, returning the underlying stream.
+    pub fn logout(mut self) -> Result<S, ScsiError> {
+        let tag = self.allocate_tag();
+        let mut request = Pdu::new(Pdu::LOGOUT_REQUEST);
+        request.set_immediate(true);
+        // Reason code 0 closes the whole session.
+        request.set_flags(Pdu::FLAG_FINAL);
+        request.set_initiator_task_tag(tag);
+        self.send_immediate(&mut request)?;
+        let response = self.receive()?;
+        if response.opcode() != Pdu::LOGOUT_RESPONSE || response.initiator_task_tag() != tag {
+            return Err(ScsiError::from_cause(ErrorCause::ParseError));
+        }
+        if response.header[2] != 0 {
+            return Err(ScsiError::from_cause(ErrorCause::FlagError {
+                flags: u32::from(response.header[2]),
+            }));
+        }
+        Ok(self.stream)
+    }
+
+    fn security_negotiation(&mut self, tag: u32, options: &LoginOptions) -> Result<(), ScsiError> {
+        let mut keys = vec![
+            ("InitiatorName", options.initiator_name.clone()),
+            ("TargetName", options.target_name.clone()),
+            ("SessionType", "Normal".to_string()),
+        ];
+        let chap = match options.chap {
+            Some(ref chap) => chap,
+            None => {
+                keys.push(("AuthMethod", "None".to_string()));
+                return self.finish_stage(tag, SECURITY_NEGOTIATION, keys);
+            }
+        };
+        keys.push(("AuthMethod", "CHAP,None".to_string()));
+        let response = self.login_request(tag, SECURITY_NEGOTIATION, false, &keys)?;
+        if response.flags() & Pdu::FLAG_TRANSIT != 0 {
+            return Ok(());
+        }
+        if response.key("AuthMethod") != Some("CHAP") {
+            // The target turned authentication down.
+            return self.finish_stage(tag, SECURITY_NEGOTIATION, Vec::new());
+        }
+        let keys = vec![("CHAP_A", CHAP_MD5.to_string())];
+        let response = self.login_request(tag, SECURITY_NEGOTIATION, false, &keys)?;
+        if response.key("CHAP_A") != Some(CHAP_MD5) {
+            return Err(ScsiError::from_cause(ErrorCause::UnsupportedOperationError));
+        }
+        let id = response
+            .key("CHAP_I")
+            .and_then(|id| id.parse::<u8>().ok())
+            .ok_or_else(|| ScsiError::from_cause(ErrorCause::ParseError))?;
+        let challenge = decode_hex(
+            response
+                .key("CHAP_C")
+                .ok_or_else(|| ScsiError::from_cause(ErrorCause::ParseError))?,
+        )?;
+        let keys = vec![
+            ("CHAP_N", chap.username.clone()),
+            (
+                "CHAP_R",
+                encode_hex(&chap_response(id, &chap.secret, &challenge)),
+            ),
+        ];
+        self.finish_stage(tag, SECURITY_NEGOTIATION, keys)
+    }
+
+    fn operational_negotiation(&mut self, tag: u32) -> Result<(), ScsiError> {
+        let keys = vec![
+            ("HeaderDigest", "None".to_string()),
+            ("DataDigest", "None".to_string()),
+            ("ErrorRecoveryLevel", "0".to_string()),
+            ("InitialR2T", "Yes".to_string()),
+            ("ImmediateData", "No".to_string()),
+            ("MaxConnections", "1".to_string()),
+            (
+                "MaxRecvDataSegmentLength",
+                MAX_RECV_DATA_SEGMENT_LENGTH.to_string(),
+            ),
+        ];
+        self.finish_stage(tag, OPERATIONAL_NEGOTIATION, keys)
+    }
+
+    /// Sends `keys` asking to leave the current login stage, repeating the
+    /// request until the target agrees.
+    fn finish_stage(
+        &mut self,
+        tag: u32,
+        stage: u8,
+        mut keys: Vec<(&str, String)>,
+    ) -> Result<(), ScsiError> {
+        for _ in 0..MAX_LOGIN_ROUNDS {
+            let response = self.login_request(tag, stage, true, &keys)?;
+            if let Some(length) = response.key("MaxRecvDataSegmentLength") {
+                self.max_send_data_segment = length
+                    .parse()
+                    .map_err(|_| ScsiError::from_cause(ErrorCause::ParseError))?;
+            }
+            if response.flags() & Pdu::FLAG_TRANSIT != 0 {
+                if response.flags() & 0x3 == FULL_FEATURE_PHASE {
+                    self.tsih = BE::read_u16(&response.header[14..]);
+                    self.cmd_sn = response.field(Pdu::EXP_CMD_SN);
+                }
+                return Ok(());
+            }
+            keys.clear();
+        }
+        Err(ScsiError::from_cause(ErrorCause::ParseError))
+    }
+
+    /// Sends a single login request and returns the target's response.
+    fn login_request(
+        &mut self,
+        tag: u32,
+        stage: u8,
+        transit: bool,
+        keys: &[(&str, String)],
+    ) -> Result<Pdu, ScsiError> {
+        let next_stage = match stage {
+            SECURITY_NEGOTIATION => OPERATIONAL_NEGOTIATION,
+            _ => FULL_FEATURE_PHASE,
+        };
+        let mut request = Pdu::new(Pdu::LOGIN_REQUEST);
+        request.set_immediate(true);
+        let transit = if transit { Pdu::FLAG_TRANSIT } else { 0 };
+        request.set_flags(transit | (stage << 2) | next_stage);
+        request.header[8..14].copy_from_slice(&ISID);
+        request.set_initiator_task_tag(tag);
+        for (key, value) in keys {
+            request.push_key(key, value);
+        }
+        self.send_immediate(&mut request)?;
+        let response = self.receive()?;
+        if response.opcode() != Pdu::LOGIN_RESPONSE {
+            return Err(ScsiError::from_cause(ErrorCause::ParseError));
+        }
+        let (status_class, status_detail) = (response.header[36], response.header[37]);
+        if status_class != 0 {
+            return Err(ScsiError::from_cause(ErrorCause::LoginError {
+                status_class,
+                status_detail,
+            }));
+        }
+        Ok(response)
+    }
+
+    /// Answers each R2T by sending the requested part of `buffer` in as many
+    /// Data-Out PDUs as the target's segment length requires.
+    fn send_data(&mut self, r2t: &Pdu, buffer: &[u8]) -> Result<(), ScsiError> {
+        let offset = r2t.field(Pdu::BUFFER_OFFSET) as usize;
+        let end = offset + r2t.field(Pdu::DESIRED_DATA_LENGTH) as usize;
+        if end > buffer.len() {
+            return Err(ScsiError::from_cause(ErrorCause::BufferTooSmallError {
+                expected: end,
+                actual: buffer.len(),
+            }));
+        }
+        let segment = self.max_send_data_segment.max(512);
+        let mut position = offset;
+        let mut data_sn = 0;
+        while position < end {
+            let length = segment.min(end - position);
+            let mut pdu = Pdu::new(Pdu::DATA_OUT);
+            if position + length == end {
+                pdu.set_flags(Pdu::FLAG_FINAL);
+            }
+            pdu.set_lun(self.lun);
+            pdu.set_initiator_task_tag(r2t.initiator_task_tag());
+            pdu.set_field(
+                Pdu::TARGET_TRANSFER_TAG,
+                r2t.field(Pdu::TARGET_TRANSFER_TAG),
+            );
+            pdu.set_field(Pdu::EXP_STAT_SN, self.exp_stat_sn);
+            pdu.set_field(Pdu::DATA_SN, data_sn);
+            pdu.set_field(Pdu::BUFFER_OFFSET, position as u32);
+            pdu.data
+                .extend_from_slice(&buffer[position..position + length]);
+            pdu.write_to(&mut self.stream)?;
+            position += length;
+            data_sn += 1;
+        }
+        Ok(())
+    }
+
+    /// Sends a non-immediate command, which takes up the next CmdSN.
+    fn send_command(&mut self, request: &mut Pdu) -> Result<(), ScsiError> {
+        self.send_immediate(request)?;
+        self.cmd_sn = self.cmd_sn.wrapping_add(1);
+        Ok(())
+    }
+
+    /// Sends an immediate request, which reuses the current CmdSN.
+    fn send_immediate(&mut self, request: &mut Pdu) -> Result<(), ScsiError> {
+        request.set_field(Pdu::CMD_SN, self.cmd_sn);
+        request.set_field(Pdu::EXP_STAT_SN, self.exp_stat_sn);
+        request.write_to(&mut self.stream)
+    }
+
+    /// Reads the next PDU meant for us, answering any pings from the target
+    /// along the way.
+    fn receive(&mut self) -> Result<Pdu, ScsiError> {
+        loop {
+            let pdu = Pdu::read_from(&mut self.stream)?;
+            let carries_status = match pdu.opcode() {
+                Pdu::DATA_IN => pdu.flags() & Pdu::FLAG_STATUS != 0,
+                Pdu::NOP_IN => pdu.initiator_task_tag() != Pdu::RESERVED_TAG,
+                Pdu::SCSI_RESPONSE
+                | Pdu::TASK_MANAGEMENT_RESPONSE
+                | Pdu::LOGIN_RESPONSE
+                | Pdu::TEXT_RESPONSE
+                | Pdu::LOGOUT_RESPONSE => true,
+                _ => false,
+            };
+            if carries_status {
+                self.exp_stat_sn = pdu.field(Pdu::STAT_SN).wrapping_add(1);
+            }
+            match pdu.opcode() {
+                Pdu::NOP_IN if pdu.initiator_task_tag() == Pdu::RESERVED_TAG => {
+                    let transfer_tag = pdu.field(Pdu::TARGET_TRANSFER_TAG);
+                    if transfer_tag != Pdu::RESERVED_TAG {
+                        let mut reply = Pdu::new(Pdu::NOP_OUT);
+                        reply.set_immediate(true);
+                        reply.set_flags(Pdu::FLAG_FINAL);
+                        reply.set_lun(pdu.lun());
+                        reply.set_initiator_task_tag(Pdu::RESERVED_TAG);
+                        reply.set_field(Pdu::TARGET_TRANSFER_TAG, transfer_tag);
+                        reply.data = pdu.data;
+                        self.send_immediate(&mut reply)?;
+                    }
+                }
+                Pdu::ASYNC_MESSAGE => {}
+                Pdu::REJECT => {
+                    return Err(ScsiError::from_cause(ErrorCause::UnsupportedOperationError))
+                }
+                _ => return Ok(pdu),
+            }
+        }
+    }
+
+    fn allocate_tag(&mut self) -> u32 {
+        let tag = self.next_tag;
+        self.next_tag = self.next_tag.wrapping_add(1);
+        if self.next_tag == Pdu::RESERVED_TAG {
+            self.next_tag = 0;
+        }
+        tag
+    }
+}
+
+impl<S: Read + Write> CommandExecutor for IscsiInitiator<S> {
+    fn execute(&mut self, cdb: &[u8], mut data: CommandData) -> Result<CommandOutcome, ScsiError> {
+        let tag = self.allocate_tag();
+        let (flags, expected) = match data {
+            CommandData::None => (Pdu::FLAG_FINAL, 0),
+            CommandData::In(ref buffer) => (Pdu::FLAG_FINAL | Pdu::FLAG_READ, buffer.len()),
+            CommandData::Out(buffer) => (Pdu::FLAG_FINAL | Pdu::FLAG_WRITE, buffer.len()),
+        };
+        let mut request = Pdu::new(Pdu::SCSI_COMMAND);
+        // The low bits select the SIMPLE task attribute.
+        request.set_flags(flags | 0x01);
+        request.set_lun(self.lun);
+        request.set_initiator_task_tag(tag);
+        request.set_field(Pdu::EXPECTED_DATA_LENGTH, expected as u32);
+        let cdb_length = cdb.len().min(16);
+        request.header[32..32 + cdb_length].copy_from_slice(&cdb[..cdb_length]);
+        self.send_command(&mut request)?;
+
+        loop {
+            let response = self.receive()?;
+            if response.initiator_task_tag() != tag {
+                return Err(ScsiError::from_cause(ErrorCause::ParseError));
+            }
+            match (response.opcode(), &mut data) {
+                (Pdu::DATA_IN, CommandData::In(buffer)) => {
+                    let offset = response.field(Pdu::BUFFER_OFFSET) as usize;
+                    let end = offset + response.data.len();
+                    if end > buffer.len() {
+                        return Err(ScsiError::from_cause(ErrorCause::BufferTooSmallError {
+                            expected: end,
+                            actual: buffer.len(),
+                        }));
+                    }
+                    buffer[offset..end].copy_from_slice(&response.data);
+                    if response.flags() & Pdu::FLAG_STATUS != 0 {
+                        return Ok(CommandOutcome {
+                            status: ScsiStatus::from_code(response.header[3])?,
+                            sense: None,
+                            residual: residual(&response),
+                        });
+                    }
+                }
+                (Pdu::READY_TO_TRANSFER, CommandData::Out(buffer)) => {
+                    self.send_data(&response, buffer)?;
+                }
+                (Pdu::SCSI_RESPONSE, _) => {
+                    // A nonzero response means the target could not run the
+                    // command at all.
+                    if response.header[2] != 0 {
+                        return Err(ScsiError::from_cause(ErrorCause::FlagError {
+                            flags: u32::from(response.header[2]),
+                        }));
+                    }
+                    let sense = if response.data.len() > 2 {
+                        let length = usize::from(BE::read_u16(&response.data)) + 2;
+                        let end = length.min(response.data.len());
+                        RequestSenseResponse::pull_from_buffer(&response.data[2..end]).ok()
+                    } else {
+                        None
+                    };
+                    return Ok(CommandOutcome {
+                        status: ScsiStatus::from_code(response.header[3])?,
+                        sense,
+                        residual: residual(&response),
+                    });
+                }
+                _ => return Err(ScsiError::from_cause(ErrorCause::ParseError)),
+            }
+        }
+    }
+}
+
+impl<S: Read + Write> CommunicationChannel for IscsiInitiator<S> {
+    fn out_transfer<B: AsRef<[u8]>>(&mut self, bytes: B) -> Result<usize, ScsiError> {
+        let mut emulation = mem::take(&mut self.emulation);
+        let result = emulation.out_transfer(self, bytes.as_ref());
+        self.emulation = emulation;
+        result
+    }
+
+    fn in_transfer<B: AsMut<[u8]>>(&mut self, mut buffer: B) -> Result<usize, ScsiError> {
+        Ok(self.emulation.in_transfer(buffer.as_mut()))
+    }
+
+    fn scsi_status(&self) -> Option<ScsiStatus> {
+        self.emulation.scsi_status()
+    }
+}
+
+/// The residual count of a response, counting only data the target did not
+/// send.
+fn residual(response: &Pdu) -> u32 {
+    if response.flags() & Pdu::FLAG_UNDERFLOW != 0 {
+        response.field(Pdu::RESIDUAL_COUNT)
+    } else {
+        0
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{IscsiInitiator, LoginOptions};
+    use crate::error::ErrorCause;
+    use crate::error::ScsiError;
+    use crate::scsi::commands::{
+        CommandBlockWrapper, CommandStatusWrapper, Direction, RequestSenseCommand,
+        RequestSenseResponse, ScsiStatus, TestUnitReady,
+    };
+    use crate::scsi::iscsi::chap::{chap_response, decode_hex};
+    use crate::scsi::iscsi::Pdu;
+    use crate::scsi::responder::tests::MemoryResponder;
+    use crate::scsi::ScsiResponder;
+    use crate::scsi::{
+        dispatch_command, CdromResponder, CommandData, CommandExecutor, DataPhase, ScsiBlockDevice,
+        ScsiCommand,
+    };
+    use crate::traits::{BufferPullable, BufferPushable, CommunicationChannel};
+    use std::net::{TcpListener, TcpStream};
+    use std::thread::{self, JoinHandle};
+    use std::vec::Vec;
+
+    const CHALLENGE: &str = "0x0123456789abcdef";
+
+    /// Collects the data a responder sends, and feeds it the data the
+    /// initiator wrote.
+    struct BufferedData {
+        sent: Vec<u8>,
+        received: Vec<u8>,
+        position: usize,
+    }
+
+    impl DataPhase for BufferedData {
+        fn send(&mut self, data: &[u8]) -> Result<usize, ScsiError> {
+            self.sent.extend_from_slice(data);
+            Ok(data.len())
+        }
+
+        fn receive(&mut self, buffer: &mut [u8]) -> Result<usize, ScsiError> {
+            let length = buffer.len().min(self.received.len() - self.position);
+            buffer[..length].copy_from_slice(&self.received[self.position..][..length]);
+            self.position += length;
+            Ok(length)
+        }
+    }
+
+    /// Runs a minimal target for a single session on the next connection to
+    /// `listener`, returning whether the initiator logged in.
+    fn spawn_target<R: ScsiResponder + Send + 'static>(
+        listener: TcpListener,
+        mut responder: R,
+        secret: Option<&'static [u8]>,
+    ) -> JoinHandle<bool> {
+        thread::spawn(move || {
+            let (mut stream, _) = listener.accept().unwrap();
+            if !target_login(&mut stream, secret) {
+                return false;
+            }
+            let mut stat_sn = 1;
+            let mut pinged = false;
+            loop {
+                let request = Pdu::read_from(&mut stream).unwrap();
+                let tag = request.initiator_task_tag();
+                match request.opcode() {
+                    Pdu::SCSI_COMMAND => {
+                        if !pinged {
+                            // Make sure pings from the target are answered
+                            // in the middle of a command.
+                            let mut ping = Pdu::new(Pdu::NOP_IN);
+                            ping.set_flags(Pdu::FLAG_FINAL);
+                            ping.set_initiator_task_tag(Pdu::RESERVED_TAG);
+                            ping.set_field(Pdu::TARGET_TRANSFER_TAG, 0x77);
+                            ping.write_to(&mut stream).unwrap();
+                            let reply = Pdu::read_from(&mut stream).unwrap();
+                            assert_eq!(reply.opcode(), Pdu::NOP_OUT);
+                            assert_eq!(reply.field(Pdu::TARGET_TRANSFER_TAG), 0x77);
+                            pinged = true;
+                        }
+                        let expected = request.field(Pdu::EXPECTED_DATA_LENGTH) as usize;
+                        let mut data = BufferedData {
+                            sent: Vec::new(),
+                            received: Vec::new(),
+                            position: 0,
+                        };
+                        if request.flags() & Pdu::FLAG_WRITE != 0 {
+                            let mut r2t = Pdu::new(Pdu::READY_TO_TRANSFER);
+                            r2t.set_flags(Pdu::FLAG_FINAL);
+                            r2t.set_initiator_task_tag(tag);
+                            r2t.set_field(Pdu::TARGET_TRANSFER_TAG, 0x1000);
+                            r2t.set_field(Pdu::DESIRED_DATA_LENGTH, expected as u32);
+                            r2t.write_to(&mut stream).unwrap();
+                            loop {
+                                let data_out = Pdu::read_from(&mut stream).unwrap();
+                                assert_eq!(data_out.opcode(), Pdu::DATA_OUT);
+                                assert!(data_out.data.len() <= 512);
+                                assert_eq!(
+                                    data_out.field(Pdu::BUFFER_OFFSET) as usize,
+                                    data.received.len()
+                                );
+                                data.received.extend_from_slice(&data_out.data);
+                                if data_out.flags() & Pdu::FLAG_FINAL != 0 {
+                                    break;
+                                }
+                            }
+                        }
+                        let command = ScsiCommand::from_cdb(&request.header[32..48], 256).unwrap();
+                        let (csw, _) =
+                            dispatch_command(&mut responder, command, expected, &mut data).unwrap();
+                        let mut response = Pdu::new(Pdu::SCSI_RESPONSE);
+                        if csw.status != CommandStatusWrapper::COMMAND_PASSED {
+                            let (sense, _) =
+                                responder.sense_data(RequestSenseCommand::new(18)).unwrap();
+                            let mut buffer = [0; 2 + RequestSenseResponse::SIZE];
+                            let length = sense.push_to_buffer(&mut buffer[2..]).unwrap();
+                            buffer[1] = length as u8;
+                            response.header[3] = 2;
+                            response.data.extend_from_slice(&buffer[..2 + length]);
+                        } else if !data.sent.is_empty() {
+                            // Split the data in two to check the offsets.
+                            let half = data.sent.len() / 2;
+                            let mut first = Pdu::new(Pdu::DATA_IN);
+                            first.set_initiator_task_tag(tag);
+                            first.data.extend_from_slice(&data.sent[..half]);
+                            first.write_to(&mut stream).unwrap();
+                            response = Pdu::new(Pdu::DATA_IN);
+                            response.set_field(Pdu::BUFFER_OFFSET, half as u32);
+                            response.data.extend_from_slice(&data.sent[half..]);
+                        }
+                        let flags = if response.opcode() == Pdu::DATA_IN {
+                            Pdu::FLAG_FINAL | Pdu::FLAG_STATUS
+                        } else {
+                            Pdu::FLAG_FINAL
+                        };
+                        response.set_flags(flags);
+                        response.set_initiator_task_tag(tag);
+                        response.set_field(Pdu::STAT_SN, stat_sn);
+                        response.write_to(&mut stream).unwrap();
+                        stat_sn += 1;
+                    }
+                    Pdu::NOP_OUT => {
+                        let mut response = Pdu::new(Pdu::NOP_IN);
+                        response.set_flags(Pdu::FLAG_FINAL);
+                        response.set_initiator_task_tag(tag);
+                        response.set_field(Pdu::TARGET_TRANSFER_TAG, Pdu::RESERVED_TAG);
+                        response.set_field(Pdu::STAT_SN, stat_sn);
+                        response.write_to(&mut stream).unwrap();
+                        stat_sn += 1;
+                    }
+                    Pdu::LOGOUT_REQUEST => {
+                        let mut response = Pdu::new(Pdu::LOGOUT_RESPONSE);
+                        response.set_flags(Pdu::FLAG_FINAL);
+                        response.set_initiator_task_tag(tag);
+                        response.set_field(Pdu::STAT_SN, stat_sn);
+                        response.write_to(&mut stream).unwrap();
+                        return true;
+                    }
+                    other => panic!("Unexpected opcode {:#x}", other),
+                }
+            }
+        })
+    }
+
+    /// Answers login requests until the initiator reaches the full feature
+    /// phase, checking its CHAP response if `secret` is set.
+    fn target_login(stream: &mut TcpStream, secret: Option<&[u8]>) -> bool {
+        let mut authenticated = secret.is_none();
+        loop {
+            let request = Pdu::read_from(stream).unwrap();
+            assert_eq!(request.opcode(), Pdu::LOGIN_REQUEST);
+            assert!(request.is_immediate());
+            let mut response = Pdu::new(Pdu::LOGIN_RESPONSE);
+            response.set_initiator_task_tag(request.initiator_task_tag());
+            let stage = (request.flags() >> 2) & 0x3;
+            let mut transit = request.flags() & Pdu::FLAG_TRANSIT;
+            if let Some(secret) = secret {
+                if request.key("AuthMethod").is_some() {
+                    assert_eq!(request.key("AuthMethod"), Some("CHAP,None"));
+                    response.push_key("AuthMethod", "CHAP");
+                } else if request.key("CHAP_A").is_some() {
+                    response.push_key("CHAP_A", "5");
+                    response.push_key("CHAP_I", "7");
+                    response.push_key("CHAP_C", CHALLENGE);
+                } else if let Some(answer) = request.key("CHAP_R") {
+                    assert_eq!(request.key("CHAP_N"), Some("user"));
+                    let challenge = decode_hex(CHALLENGE).unwrap();
+                    if decode_hex(answer).unwrap()[..] != chap_response(7, secret, &challenge) {
+                        // Authentication failure.
+                        response.header[36] = 2;
+                        response.header[37] = 1;
+                        response.write_to(stream).unwrap();
+                        return false;
+                    }
+                    authenticated = true;
+                }
+            }
+            if stage == 0 && !authenticated {
+                transit = 0;
+            }
+            if stage == 1 {
+                response.push_key("MaxRecvDataSegmentLength", "512");
+                response.header[14..16].copy_from_slice(&[0x00, 0x05]);
+            }
+            response.set_flags(transit | (request.flags() & 0x0f));
+            response.set_field(Pdu::EXP_CMD_SN, 1);
+            response.write_to(stream).unwrap();
+            if transit != 0 && request.flags() & 0x3 == 3 {
+                return true;
+            }
+        }
+    }
+
+    fn connect<R: ScsiResponder + Send + 'static>(
+        responder: R,
+        secret: Option<&'static [u8]>,
+    ) -> (TcpStream, JoinHandle<bool>) {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let address = listener.local_addr().unwrap();
+        let target = spawn_target(listener, responder, secret);
+        (TcpStream::connect(address).unwrap(), target)
+    }
+
+    fn options() -> LoginOptions {
+        LoginOptions::new(
+            "iqn.2019-01.rs.scsi:initiator",
+            "iqn.2019-01.rs.scsi:target",
+        )
+    }
+
+    #[test]
+    fn test_iscsi_block_device() {
+        let (stream, target) = connect(MemoryResponder::default(), Some(b"secret"));
+        let options = options().with_chap("user", b"secret");
+        let initiator = IscsiInitiator::login(stream, &options).unwrap();
+        assert_eq!(initiator.tsih(), 5);
+
+        let mut scratch = [0; 512];
+        let mut device = ScsiBlockDevice::new(initiator, &mut scratch[..]).unwrap();
+        assert_eq!(device.block_size(), 256);
+        assert_eq!(device.num_blocks(), 1024);
+
+        let written: Vec<u8> = (0..256 * 6).map(|idx| (idx % 251) as u8).collect();
+        assert_eq!(device.write_blocks(10, 6, &written).unwrap(), written.len());
+        let mut read = vec![0; written.len()];
+        assert_eq!(device.read_blocks(10, 6, &mut read).unwrap(), read.len());
+        assert_eq!(read, written);
+
+        let mut initiator = device.comm_channel;
+        initiator.nop().unwrap();
+        initiator.logout().unwrap();
+        assert!(target.join().unwrap());
+    }
+
+    #[test]
+    fn test_iscsi_login_failure() {
+        let (stream, target) = connect(MemoryResponder::default(), Some(b"secret"));
+        let options = options().with_chap("user", b"wrong");
+        let error = IscsiInitiator::login(stream, &options).err().unwrap();
+        assert_eq!(
+            error.cause,
+            ErrorCause::LoginError {
+                status_class: 2,
+                status_detail: 1,
+            }
+        );
+        assert!(!target.join().unwrap());
+    }
+
+    #[test]
+    fn test_iscsi_check_condition() {
+        let (stream, target) = connect(CdromResponder::<&[u8]>::new(None), None);
+        let mut initiator = IscsiInitiator::login(stream, &options()).unwrap();
+        let status = initiator
+            .execute_command(&TestUnitReady::new(), CommandData::None)
+            .unwrap();
+        assert_eq!(status.status, ScsiStatus::CheckCondition);
+        let sense = status.sense.unwrap();
+        assert_eq!(sense.sense_key, RequestSenseResponse::NOT_READY);
+        assert_eq!(sense.additional_sense_code, 0x3a);
+
+        // Through the Bulk-Only emulation, the sense data is kept for the
+        // REQUEST SENSE that follows the failed CSW.
+        let mut buffer = [0; 31];
+        let mut wrapper = CommandBlockWrapper::new(0, Direction::NONE, 0, 6);
+        wrapper.tag = 9;
+        wrapper.push_to_buffer(&mut buffer[..]).unwrap();
+        initiator.out_transfer(&buffer[..]).unwrap();
+        let mut csw = [0; CommandStatusWrapper::SIZE as usize];
+        assert_eq!(initiator.in_transfer(&mut csw[..]).unwrap(), csw.len());
+        let csw = CommandStatusWrapper::pull_from_buffer(&csw[..]).unwrap();
+        assert_eq!(csw.tag, 9);
+        assert_eq!(csw.status, CommandStatusWrapper::COMMAND_FAILED);
+
+        let mut buffer = [0; 31];
+        RequestSenseCommand::new(18)
+            .push_to_buffer(&mut buffer[..])
+            .unwrap();
+        initiator.out_transfer(&buffer[..]).unwrap();
+        let mut data = [0; 18];
+        assert_eq!(initiator.in_transfer(&mut data[..]).unwrap(), 18);
+        let local = RequestSenseResponse::pull_from_buffer(&data[..]).unwrap();
+        assert_eq!(local.sense_key, RequestSenseResponse::NOT_READY);
+
+        initiator.logout().unwrap();
+        assert!(target.join().unwrap());
+    }
+}
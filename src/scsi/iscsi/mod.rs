@@ -0,0 +1,18 @@
+//! Support for running SCSI commands over a network with iSCSI.
+//!
+//! iSCSI carries the same command blocks as the USB transports inside
+//! protocol data units sent over a TCP connection. An `IscsiInitiator` logs
+//! in to a target, optionally authenticating with CHAP, and can then be used
+//! on its own or as the `CommunicationChannel` of a `ScsiBlockDevice`, and an
+//! `IscsiTarget` exports any `ScsiResponder` to initiators on the network.
+
+mod chap;
+
+mod pdu;
+pub use self::pdu::*;
+
+mod initiator;
+pub use self::initiator::*;
+
+mod target;
+pub use self::target::*;
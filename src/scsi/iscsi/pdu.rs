@@ -0,0 +1,306 @@
+use std::io::{Read, Write};
+use std::string::String;
+use std::vec::Vec;
+
+use byteorder::{ByteOrder, BE};
+
+use crate::error::{ErrorCause, ScsiError};
+
+/// The largest data segment accepted from the other side of the connection,
+/// which is the largest the 24 bit length field can describe.
+const MAX_DATA_SEGMENT_LENGTH: usize = 0xff_ffff;
+
+/// The login stage in which the initiator and target authenticate each other.
+pub(crate) const SECURITY_NEGOTIATION: u8 = 0;
+
+/// The login stage in which session parameters are negotiated.
+pub(crate) const OPERATIONAL_NEGOTIATION: u8 = 1;
+
+/// The stage a session is in once login completes.
+pub(crate) const FULL_FEATURE_PHASE: u8 = 3;
+
+/// A single iSCSI protocol data unit: a 48 byte Basic Header Segment followed
+/// by an optional data segment.
+///
+/// Most fields sit at the same offset in every PDU that has them, so rather
+/// than a struct per PDU type, fields are read and written through the
+/// accessors and offset constants on `Pdu`.
+#[derive(Clone, Eq, PartialEq, Debug)]
+pub struct Pdu {
+    /// The Basic Header Segment.
+    pub header: [u8; Pdu::HEADER_SIZE],
+
+    /// The data segment, without padding.
+    pub data: Vec<u8>,
+}
+
+impl Pdu {
+    /// The size of the Basic Header Segment.
+    pub const HEADER_SIZE: usize = 48;
+
+    /// Opcode of a ping from the initiator, or the reply to a target's ping.
+    pub const NOP_OUT: u8 = 0x00;
+    /// Opcode of a SCSI command.
+    pub const SCSI_COMMAND: u8 = 0x01;
+    /// Opcode of a task management request.
+    pub const TASK_MANAGEMENT_REQUEST: u8 = 0x02;
+    /// Opcode of a login request.
+    pub const LOGIN_REQUEST: u8 = 0x03;
+    /// Opcode of a text request.
+    pub const TEXT_REQUEST: u8 = 0x04;
+    /// Opcode of a PDU carrying data from the initiator.
+    pub const DATA_OUT: u8 = 0x05;
+    /// Opcode of a logout request.
+    pub const LOGOUT_REQUEST: u8 = 0x06;
+    /// Opcode of a ping from the target, or the reply to an initiator's ping.
+    pub const NOP_IN: u8 = 0x20;
+    /// Opcode of the status of a SCSI command.
+    pub const SCSI_RESPONSE: u8 = 0x21;
+    /// Opcode of a task management response.
+    pub const TASK_MANAGEMENT_RESPONSE: u8 = 0x22;
+    /// Opcode of a login response.
+    pub const LOGIN_RESPONSE: u8 = 0x23;
+    /// Opcode of a text response.
+    pub const TEXT_RESPONSE: u8 = 0x24;
+    /// Opcode of a PDU carrying data from the target.
+    pub const DATA_IN: u8 = 0x25;
+    /// Opcode of a logout response.
+    pub const LOGOUT_RESPONSE: u8 = 0x26;
+    /// Opcode of a target's request for data (R2T).
+    pub const READY_TO_TRANSFER: u8 = 0x31;
+    /// Opcode of an asynchronous message from the target.
+    pub const ASYNC_MESSAGE: u8 = 0x32;
+    /// Opcode of a target's rejection of a PDU.
+    pub const REJECT: u8 = 0x3f;
+
+    /// Marks the last PDU of a sequence.
+    pub const FLAG_FINAL: u8 = 0x80;
+    /// In a login PDU, marks a request or permission to move to the next stage.
+    pub const FLAG_TRANSIT: u8 = 0x80;
+    /// In a SCSI command, marks that data will be read from the target.
+    pub const FLAG_READ: u8 = 0x40;
+    /// In a SCSI command, marks that data will be written to the target.
+    pub const FLAG_WRITE: u8 = 0x20;
+    /// In a SCSI response or Data-In PDU, marks that less data was transferred
+    /// than expected.
+    pub const FLAG_UNDERFLOW: u8 = 0x02;
+    /// In a SCSI response or Data-In PDU, marks that more data was available
+    /// than expected.
+    pub const FLAG_OVERFLOW: u8 = 0x04;
+    /// In a Data-In PDU, marks that the PDU also carries the command's status.
+    pub const FLAG_STATUS: u8 = 0x01;
+
+    /// The task tag used by PDUs that do not belong to any task.
+    pub const RESERVED_TAG: u32 = 0xffff_ffff;
+
+    /// Offset of the target transfer tag.
+    pub const TARGET_TRANSFER_TAG: usize = 20;
+    /// Offset of a SCSI command's expected data transfer length.
+    pub const EXPECTED_DATA_LENGTH: usize = 20;
+    /// Offset of the command sequence number, in PDUs from the initiator.
+    pub const CMD_SN: usize = 24;
+    /// Offset of the status sequence number, in PDUs from the target.
+    pub const STAT_SN: usize = 24;
+    /// Offset of the next expected status sequence number, in PDUs from the
+    /// initiator.
+    pub const EXP_STAT_SN: usize = 28;
+    /// Offset of the next expected command sequence number, in PDUs from the
+    /// target.
+    pub const EXP_CMD_SN: usize = 28;
+    /// Offset of the largest command sequence number the target will accept.
+    pub const MAX_CMD_SN: usize = 32;
+    /// Offset of the data sequence number of a Data-In or Data-Out PDU, or
+    /// the R2T sequence number of an R2T.
+    pub const DATA_SN: usize = 36;
+    /// Offset of the position of a data PDU's data within the command's data.
+    pub const BUFFER_OFFSET: usize = 40;
+    /// Offset of the residual count of a SCSI response or Data-In PDU.
+    pub const RESIDUAL_COUNT: usize = 44;
+    /// Offset of the amount of data requested by an R2T.
+    pub const DESIRED_DATA_LENGTH: usize = 44;
+
+    /// Constructs an empty PDU with the given opcode.
+    pub fn new(opcode: u8) -> Pdu {
+        let mut header = [0; Pdu::HEADER_SIZE];
+        header[0] = opcode & 0x3f;
+        Pdu {
+            header,
+            data: Vec::new(),
+        }
+    }
+
+    /// The PDU's opcode; see the `Pdu::*` opcode constants.
+    pub fn opcode(&self) -> u8 {
+        self.header[0] & 0x3f
+    }
+
+    /// Whether the PDU is an immediate request, which is not ordered with
+    /// other commands.
+    pub fn is_immediate(&self) -> bool {
+        self.header[0] & 0x40 != 0
+    }
+
+    /// Marks the PDU as an immediate request.
+    pub fn set_immediate(&mut self, immediate: bool) {
+        self.header[0] = (self.header[0] & 0x3f) | if immediate { 0x40 } else { 0 };
+    }
+
+    /// The opcode-specific flags byte.
+    pub fn flags(&self) -> u8 {
+        self.header[1]
+    }
+
+    /// Sets the opcode-specific flags byte.
+    pub fn set_flags(&mut self, flags: u8) {
+        self.header[1] = flags;
+    }
+
+    /// The logical unit number, in SAM format.
+    pub fn lun(&self) -> u64 {
+        BE::read_u64(&self.header[8..])
+    }
+
+    /// Sets the logical unit number.
+    pub fn set_lun(&mut self, lun: u64) {
+        BE::write_u64(&mut self.header[8..], lun);
+    }
+
+    /// The initiator task tag, which ties responses to their requests.
+    pub fn initiator_task_tag(&self) -> u32 {
+        self.field(16)
+    }
+
+    /// Sets the initiator task tag.
+    pub fn set_initiator_task_tag(&mut self, tag: u32) {
+        self.set_field(16, tag);
+    }
+
+    /// Reads the big-endian `u32` at `offset` in the header.
+    pub fn field(&self, offset: usize) -> u32 {
+        BE::read_u32(&self.header[offset..])
+    }
+
+    /// Writes a big-endian `u32` at `offset` in the header.
+    pub fn set_field(&mut self, offset: usize, value: u32) {
+        BE::write_u32(&mut self.header[offset..], value);
+    }
+
+    /// Parses the data segment as a list of `key=value` pairs, as used by
+    /// login and text PDUs.
+    pub fn keys(&self) -> Vec<(String, String)> {
+        self.data
+            .split(|&b| b == 0)
+            .filter(|pair| !pair.is_empty())
+            .filter_map(|pair| {
+                let pair = String::from_utf8_lossy(pair);
+                let mut split = pair.splitn(2, '=');
+                let key = split.next()?.to_string();
+                let value = split.next()?.to_string();
+                Some((key, value))
+            })
+            .collect()
+    }
+
+    /// Finds the value of `key` in the data segment of a login or text PDU.
+    pub fn key(&self, key: &str) -> Option<&str> {
+        self.data
+            .split(|&b| b == 0)
+            .filter_map(|pair| ::std::str::from_utf8(pair).ok())
+            .filter_map(|pair| {
+                let mut split = pair.splitn(2, '=');
+                match (split.next(), split.next()) {
+                    (Some(name), Some(value)) if name == key => Some(value),
+                    _ => None,
+                }
+            })
+            .next()
+    }
+
+    /// Appends a `key=value` pair to the data segment.
+    pub fn push_key(&mut self, key: &str, value: &str) {
+        self.data.extend_from_slice(key.as_bytes());
+        self.data.push(b'=');
+        self.data.extend_from_slice(value.as_bytes());
+        self.data.push(0);
+    }
+
+    /// Reads the next PDU from `reader`, skipping any additional header
+    /// segments.
+    pub fn read_from<R: Read>(reader: &mut R) -> Result<Pdu, ScsiError> {
+        let mut header = [0; Pdu::HEADER_SIZE];
+        reader.read_exact(&mut header)?;
+        let ahs_length = usize::from(header[4]) * 4;
+        let data_length = (BE::read_u32(&header[4..]) & 0xff_ffff) as usize;
+        let mut ahs = vec![0; ahs_length];
+        reader.read_exact(&mut ahs)?;
+        let mut data = vec![0; padded_length(data_length)];
+        reader.read_exact(&mut data)?;
+        data.truncate(data_length);
+        Ok(Pdu { header, data })
+    }
+
+    /// Writes the PDU to `writer`, filling in the data segment length and
+    /// padding the data segment to a multiple of 4 bytes.
+    ///
+    /// # Errors
+    /// Returns a `BufferTooSmallError` if the data segment is too long to be
+    /// described by the header.
+    pub fn write_to<W: Write>(&self, writer: &mut W) -> Result<(), ScsiError> {
+        if self.data.len() > MAX_DATA_SEGMENT_LENGTH {
+            return Err(ScsiError::from_cause(ErrorCause::BufferTooSmallError {
+                expected: self.data.len(),
+                actual: MAX_DATA_SEGMENT_LENGTH,
+            }));
+        }
+        let mut buffer = Vec::with_capacity(Pdu::HEADER_SIZE + padded_length(self.data.len()));
+        buffer.extend_from_slice(&self.header);
+        // No additional header segments are ever sent.
+        BE::write_u32(&mut buffer[4..], self.data.len() as u32);
+        buffer.extend_from_slice(&self.data);
+        buffer.resize(Pdu::HEADER_SIZE + padded_length(self.data.len()), 0);
+        writer.write_all(&buffer)?;
+        writer.flush()?;
+        Ok(())
+    }
+}
+
+/// Rounds a data segment length up to the next multiple of 4.
+fn padded_length(length: usize) -> usize {
+    length.div_ceil(4) * 4
+}
+
+#[cfg(test)]
+mod tests {
+    use super::Pdu;
+    use std::io::Cursor;
+    use std::vec::Vec;
+
+    #[test]
+    fn test_pdu_roundtrip() {
+        let mut pdu = Pdu::new(Pdu::LOGIN_REQUEST);
+        pdu.set_immediate(true);
+        pdu.set_flags(Pdu::FLAG_TRANSIT | 0x01);
+        pdu.set_initiator_task_tag(0x1234_5678);
+        pdu.set_field(Pdu::CMD_SN, 7);
+        pdu.push_key("InitiatorName", "iqn.2019-01.rs.scsi:test");
+        pdu.push_key("AuthMethod", "None");
+
+        let mut wire = Vec::new();
+        pdu.write_to(&mut wire).unwrap();
+        assert_eq!(wire.len() % 4, 0);
+        assert_eq!(wire[0], 0x43);
+        assert_eq!(&wire[5..8], &[0, 0, pdu.data.len() as u8]);
+
+        let pulled = Pdu::read_from(&mut Cursor::new(&wire)).unwrap();
+        assert_eq!(pulled.data, pdu.data);
+        assert_eq!(pulled.opcode(), Pdu::LOGIN_REQUEST);
+        assert!(pulled.is_immediate());
+        assert_eq!(pulled.initiator_task_tag(), 0x1234_5678);
+        assert_eq!(pulled.field(Pdu::CMD_SN), 7);
+        let keys = pulled.keys();
+        assert_eq!(keys.len(), 2);
+        assert_eq!(keys[1], ("AuthMethod".to_string(), "None".to_string()));
+        assert_eq!(pulled.key("AuthMethod"), Some("None"));
+        assert_eq!(pulled.key("TargetName"), None);
+    }
+}
@@ -0,0 +1,935 @@
+use std::collections::VecDeque;
+use std::io::{Read, Write};
+use std::string::String;
+use std::vec::Vec;
+
+use byteorder::{ByteOrder, BE};
+
+use crate::error::{ErrorCause, ScsiError, UsbTransferDirection};
+use crate::scsi::commands::{Direction, RequestSenseResponse, ScsiStatus};
+use crate::scsi::iscsi::chap::{
+    chap_response, decode_hex, encode_hex, generate_challenge, CHAP_MD5,
+};
+use crate::scsi::iscsi::pdu::{
+    Pdu, FULL_FEATURE_PHASE, OPERATIONAL_NEGOTIATION, SECURITY_NEGOTIATION,
+};
+use crate::scsi::iscsi::ChapCredentials;
+use crate::scsi::responder::{
+    command_status, dispatch_command, reject_command, DataPhase, ScsiCommand,
+};
+use crate::scsi::{InitiatorId, ScsiResponder};
+use crate::traits::BufferPushable;
+
+/// The largest data segment we accept from the initiator.
+const MAX_RECV_DATA_SEGMENT_LENGTH: usize = 64 * 1024;
+
+/// The data segment length assumed for the initiator if it does not declare
+/// one.
+const DEFAULT_DATA_SEGMENT_LENGTH: usize = 8192;
+
+/// The most data we move in a single Data-In sequence or R2T.
+const MAX_BURST_LENGTH: usize = 256 * 1024;
+
+/// The number of login requests answered before giving up on an initiator
+/// that never finishes logging in.
+const MAX_LOGIN_ROUNDS: usize = 16;
+
+/// The session handle given to the one session we serve.
+const TSIH: u16 = 1;
+
+/// The CHAP identifier sent with our challenge.
+const CHAP_ID: u8 = 1;
+
+/// Login status class for requests the initiator got wrong.
+const INITIATOR_ERROR: u8 = 0x02;
+/// Login status detail for a failed authentication.
+const AUTHENTICATION_FAILURE: u8 = 0x01;
+/// Login status detail for a request naming a target we do not serve.
+const TARGET_NOT_FOUND: u8 = 0x03;
+/// Login status detail for a request missing a required key.
+const MISSING_PARAMETER: u8 = 0x07;
+/// Login status detail for a non-login PDU sent during login.
+const INVALID_DURING_LOGIN: u8 = 0x0b;
+
+/// Reject reason for a PDU that breaks the protocol.
+const REJECT_PROTOCOL_ERROR: u8 = 0x04;
+/// Reject reason for a request we do not support.
+const REJECT_COMMAND_NOT_SUPPORTED: u8 = 0x05;
+
+/// Task management response for a completed function.
+const FUNCTION_COMPLETE: u8 = 0;
+/// Task management response for a function we do not support.
+const FUNCTION_NOT_SUPPORTED: u8 = 5;
+
+/// The parameters a target uses to accept logins.
+#[derive(Clone, Eq, PartialEq, Debug)]
+pub struct TargetOptions {
+    /// The iSCSI qualified name initiators must ask for, eg
+    /// `iqn.2019-01.com.example:disk`.
+    pub target_name: String,
+
+    /// The credentials initiators must authenticate with, or `None` to let
+    /// any initiator log in.
+    pub chap: Option<ChapCredentials>,
+}
+
+impl TargetOptions {
+    /// Constructs options for a target that does not require authentication.
+    pub fn new(target_name: &str) -> TargetOptions {
+        TargetOptions {
+            target_name: target_name.to_string(),
+            chap: None,
+        }
+    }
+
+    /// Requires initiators to authenticate with CHAP using the given
+    /// credentials.
+    pub fn with_chap(mut self, username: &str, secret: &[u8]) -> TargetOptions {
+        self.chap = Some(ChapCredentials {
+            username: username.to_string(),
+            secret: secret.to_vec(),
+        });
+        self
+    }
+}
+
+/// The target side of a single iSCSI connection, which runs the commands it
+/// receives against a `ScsiResponder`.
+///
+/// This lets the same responder used as USB firmware be exported over the
+/// network, eg for an initiator such as Linux's `iscsiadm` to mount. Commands
+/// are run one at a time in the order they arrive; data is requested with
+/// R2Ts and returned in Data-In PDUs, and a failed CSW is turned into a
+/// CHECK CONDITION carrying the sense data from the responder's
+/// `sense_data` hook. Discovery sessions can ask for the target's name
+/// with `SendTargets`.
+///
+/// Only logical unit 0 is served, and header and data digests are not
+/// supported.
+pub struct IscsiTarget<S: Read + Write> {
+    stream: S,
+    options: TargetOptions,
+    initiator_name: Option<String>,
+    discovery: bool,
+    logged_in: bool,
+    stat_sn: u32,
+    exp_cmd_sn: u32,
+    next_transfer_tag: u32,
+    max_send_data_segment: usize,
+    max_burst_length: usize,
+    block_size: Option<u32>,
+}
+
+impl<S: Read + Write> IscsiTarget<S> {
+    /// Starts serving a newly accepted connection.
+    pub fn new(stream: S, options: TargetOptions) -> IscsiTarget<S> {
+        IscsiTarget {
+            stream,
+            options,
+            initiator_name: None,
+            discovery: false,
+            logged_in: false,
+            stat_sn: 0,
+            exp_cmd_sn: 0,
+            next_transfer_tag: 0,
+            max_send_data_segment: DEFAULT_DATA_SEGMENT_LENGTH,
+            max_burst_length: MAX_BURST_LENGTH,
+            block_size: None,
+        }
+    }
+
+    /// The name the initiator gave at login, if it has logged in.
+    pub fn initiator_name(&self) -> Option<&str> {
+        self.initiator_name.as_deref()
+    }
+
+    /// Whether the initiator logged in to a discovery session, which can only
+    /// be used to look up the target's name.
+    pub fn is_discovery(&self) -> bool {
+        self.discovery
+    }
+
+    /// Returns a reference to the underlying stream.
+    pub fn get_ref(&self) -> &S {
+        &self.stream
+    }
+
+    /// Returns a mutable reference to the underlying stream.
+    pub fn get_mut(&mut self) -> &mut S {
+        &mut self.stream
+    }
+
+    /// Logs the initiator in, then runs its requests against `responder`
+    /// until it logs out.
+    pub fn serve<R: ScsiResponder>(&mut self, responder: &mut R) -> Result<(), ScsiError> {
+        if !self.logged_in {
+            self.login()?;
+        }
+        while self.process_pdu(responder)? {}
+        Ok(())
+    }
+
+    /// Answers login requests until the initiator reaches the full feature
+    /// phase.
+    ///
+    /// # Errors
+    /// Returns a `LoginError` with the status sent to the initiator if its
+    /// login is refused, for example because of a bad CHAP response.
+    pub fn login(&mut self) -> Result<(), ScsiError> {
+        let mut authenticated = self.options.chap.is_none();
+        let mut challenge = None;
+        for round in 0..MAX_LOGIN_ROUNDS {
+            let request = Pdu::read_from(&mut self.stream)?;
+            if request.opcode() != Pdu::LOGIN_REQUEST {
+                return self.refuse_login(&request, INVALID_DURING_LOGIN);
+            }
+            if round == 0 {
+                self.stat_sn = request.field(Pdu::EXP_STAT_SN);
+            }
+            self.exp_cmd_sn = request.field(Pdu::CMD_SN);
+            let stage = (request.flags() >> 2) & 0x3;
+            let next_stage = request.flags() & 0x3;
+            let mut transit = request.flags() & Pdu::FLAG_TRANSIT != 0;
+
+            let mut response = Pdu::new(Pdu::LOGIN_RESPONSE);
+            for (key, value) in request.keys() {
+                match key.as_str() {
+                    "InitiatorName" => self.initiator_name = Some(value),
+                    "SessionType" => self.discovery = value == "Discovery",
+                    "TargetName" if value != self.options.target_name => {
+                        return self.refuse_login(&request, TARGET_NOT_FOUND);
+                    }
+                    "TargetName" => {}
+                    "AuthMethod" => {
+                        let wanted = if self.options.chap.is_some() {
+                            "CHAP"
+                        } else {
+                            "None"
+                        };
+                        if !value.split(',').any(|method| method == wanted) {
+                            return self.refuse_login(&request, AUTHENTICATION_FAILURE);
+                        }
+                        response.push_key("AuthMethod", wanted);
+                    }
+                    "CHAP_A" if self.options.chap.is_some() => {
+                        if !value.split(',').any(|algorithm| algorithm == CHAP_MD5) {
+                            return self.refuse_login(&request, AUTHENTICATION_FAILURE);
+                        }
+                        let generated = generate_challenge();
+                        response.push_key("CHAP_A", CHAP_MD5);
+                        response.push_key("CHAP_I", &CHAP_ID.to_string());
+                        response.push_key("CHAP_C", &encode_hex(&generated));
+                        challenge = Some(generated);
+                    }
+                    "CHAP_N" | "CHAP_R" => {}
+                    _ => {
+                        if let Some(answer) = self.negotiate(&key, &value) {
+                            response.push_key(&key, &answer);
+                        }
+                    }
+                }
+            }
+            if round == 0 {
+                if self.initiator_name.is_none() {
+                    return self.refuse_login(&request, MISSING_PARAMETER);
+                }
+                if !self.discovery && request.key("TargetName").is_none() {
+                    return self.refuse_login(&request, MISSING_PARAMETER);
+                }
+            }
+            if let Some(answer) = request.key("CHAP_R") {
+                if !self.check_chap(request.key("CHAP_N"), answer, challenge.take()) {
+                    return self.refuse_login(&request, AUTHENTICATION_FAILURE);
+                }
+                authenticated = true;
+            }
+            if !authenticated {
+                if stage != SECURITY_NEGOTIATION {
+                    return self.refuse_login(&request, AUTHENTICATION_FAILURE);
+                }
+                transit = false;
+            }
+            let valid_transit = matches!(
+                (stage, next_stage),
+                (SECURITY_NEGOTIATION, OPERATIONAL_NEGOTIATION)
+                    | (SECURITY_NEGOTIATION, FULL_FEATURE_PHASE)
+                    | (OPERATIONAL_NEGOTIATION, FULL_FEATURE_PHASE)
+            );
+            if transit && !valid_transit {
+                return self.refuse_login(&request, INVALID_DURING_LOGIN);
+            }
+
+            let flags = if transit {
+                Pdu::FLAG_TRANSIT | (stage << 2) | next_stage
+            } else {
+                stage << 2
+            };
+            response.set_flags(flags);
+            response.header[8..14].copy_from_slice(&request.header[8..14]);
+            if transit && next_stage == FULL_FEATURE_PHASE {
+                BE::write_u16(&mut response.header[14..], TSIH);
+            }
+            response.set_initiator_task_tag(request.initiator_task_tag());
+            self.send_status(&mut response)?;
+            if transit && next_stage == FULL_FEATURE_PHASE {
+                self.logged_in = true;
+                return Ok(());
+            }
+        }
+        Err(ScsiError::from_cause(ErrorCause::LoginError {
+            status_class: INITIATOR_ERROR,
+            status_detail: 0,
+        }))
+    }
+
+    /// Reads and answers a single request from the initiator, running it
+    /// against `responder` if it is a SCSI command.
+    ///
+    /// Returns `false` once the initiator has logged out.
+    pub fn process_pdu<R: ScsiResponder>(&mut self, responder: &mut R) -> Result<bool, ScsiError> {
+        let request = Pdu::read_from(&mut self.stream)?;
+        if request.opcode() != Pdu::DATA_OUT && !request.is_immediate() {
+            self.exp_cmd_sn = request.field(Pdu::CMD_SN).wrapping_add(1);
+        }
+        match request.opcode() {
+            Pdu::SCSI_COMMAND if !self.discovery => self.scsi_command(responder, &request)?,
+            Pdu::NOP_OUT => self.nop(&request)?,
+            Pdu::TEXT_REQUEST => self.text(&request)?,
+            Pdu::TASK_MANAGEMENT_REQUEST => {
+                // Commands are finished before the next request is read, so
+                // there is never anything left to abort.
+                let response_code = match request.flags() & 0x7f {
+                    1 | 2 | 4 | 5 | 6 => FUNCTION_COMPLETE,
+                    _ => FUNCTION_NOT_SUPPORTED,
+                };
+                let mut response = Pdu::new(Pdu::TASK_MANAGEMENT_RESPONSE);
+                response.set_flags(Pdu::FLAG_FINAL);
+                response.header[2] = response_code;
+                response.set_initiator_task_tag(request.initiator_task_tag());
+                self.send_status(&mut response)?;
+            }
+            Pdu::LOGOUT_REQUEST => {
+                let mut response = Pdu::new(Pdu::LOGOUT_RESPONSE);
+                response.set_flags(Pdu::FLAG_FINAL);
+                response.set_initiator_task_tag(request.initiator_task_tag());
+                self.send_status(&mut response)?;
+                return Ok(false);
+            }
+            Pdu::DATA_OUT => self.reject(&request, REJECT_PROTOCOL_ERROR)?,
+            _ => self.reject(&request, REJECT_COMMAND_NOT_SUPPORTED)?,
+        }
+        Ok(true)
+    }
+
+    fn scsi_command<R: ScsiResponder>(
+        &mut self,
+        responder: &mut R,
+        request: &Pdu,
+    ) -> Result<(), ScsiError> {
+        let tag = request.initiator_task_tag();
+        let block_size = match self.block_size {
+            Some(block_size) => block_size,
+            None => {
+                let block_size = responder.memory_buffer().as_ref().len() as u32;
+                self.block_size = Some(block_size);
+                block_size
+            }
+        };
+        let command = if request.lun() != 0 {
+            // Logical unit not supported.
+            Err(RequestSenseResponse::new(
+                RequestSenseResponse::ILLEGAL_REQUEST,
+                0x25,
+                0,
+            ))
+        } else {
+            ScsiCommand::from_cdb(&request.header[32..48], block_size)
+                .map_err(|err| reject_command(responder, &err))
+        };
+        let command = match command {
+            Ok(command) => command,
+            Err(sense) => {
+                let response = Pdu::new(Pdu::SCSI_RESPONSE);
+                return self.send_response(response, tag, ScsiStatus::CheckCondition, Some(sense));
+            }
+        };
+
+        let wrapper = command.wrapper();
+        let requested = request.field(Pdu::EXPECTED_DATA_LENGTH) as usize;
+        let expected = match wrapper.direction {
+            Direction::IN if request.flags() & Pdu::FLAG_READ != 0 => requested,
+            Direction::OUT if request.flags() & Pdu::FLAG_WRITE != 0 => requested,
+            _ => 0,
+        };
+        let initiator = self.initiator_name.as_deref().unwrap_or_default();
+        responder.set_initiator(InitiatorId::from_name(initiator.as_bytes()));
+        let mut data = IscsiDataPhase {
+            target: self,
+            lun: request.lun(),
+            tag,
+            expected,
+            pending: Vec::new(),
+            offset: 0,
+            data_sn: 0,
+            burst: 0,
+            received: VecDeque::new(),
+            requested: 0,
+            outstanding: 0,
+            r2t_sn: 0,
+        };
+        let result = dispatch_command(responder, command, expected, &mut data);
+        let (status, sense, transferred) = command_status(responder, result)?;
+
+        let implied = wrapper.data_transfer_length as usize;
+        let (residual_flag, residual) =
+            if wrapper.direction != Direction::NONE && implied > expected {
+                (Pdu::FLAG_OVERFLOW, implied - expected)
+            } else if transferred < expected {
+                (Pdu::FLAG_UNDERFLOW, expected - transferred)
+            } else {
+                (0, 0)
+            };
+        if status == ScsiStatus::Good && !data.pending.is_empty() {
+            // The status rides along with the last Data-In PDU.
+            return data.send_data_in(Some((residual_flag, residual as u32)));
+        }
+        if !data.pending.is_empty() {
+            data.send_data_in(None)?;
+        }
+        let exp_data_sn = data.data_sn;
+        let mut response = Pdu::new(Pdu::SCSI_RESPONSE);
+        response.set_flags(residual_flag);
+        response.set_field(Pdu::DATA_SN, exp_data_sn);
+        response.set_field(Pdu::RESIDUAL_COUNT, residual as u32);
+        self.send_response(response, tag, status, sense)
+    }
+
+    /// Sends a SCSI Response PDU, with `sense` in its data segment.
+    fn send_response(
+        &mut self,
+        mut response: Pdu,
+        tag: u32,
+        status: ScsiStatus,
+        sense: Option<RequestSenseResponse>,
+    ) -> Result<(), ScsiError> {
+        let flags = response.flags();
+        response.set_flags(Pdu::FLAG_FINAL | flags);
+        response.header[3] = status.code();
+        response.set_initiator_task_tag(tag);
+        if let Some(sense) = sense {
+            let mut buffer = [0; 2 + RequestSenseResponse::SIZE];
+            let length = sense.push_to_buffer(&mut buffer[2..])?;
+            BE::write_u16(&mut buffer[..], length as u16);
+            response.data.extend_from_slice(&buffer[..2 + length]);
+        }
+        self.send_status(&mut response)
+    }
+
+    fn nop(&mut self, request: &Pdu) -> Result<(), ScsiError> {
+        // A NOP-Out without a task tag is the answer to one of our own pings,
+        // which we never send.
+        if request.initiator_task_tag() == Pdu::RESERVED_TAG {
+            return Ok(());
+        }
+        let mut response = Pdu::new(Pdu::NOP_IN);
+        response.set_flags(Pdu::FLAG_FINAL);
+        response.set_lun(request.lun());
+        response.set_initiator_task_tag(request.initiator_task_tag());
+        response.set_field(Pdu::TARGET_TRANSFER_TAG, Pdu::RESERVED_TAG);
+        response.data = request.data.clone();
+        self.send_status(&mut response)
+    }
+
+    fn text(&mut self, request: &Pdu) -> Result<(), ScsiError> {
+        let mut response = Pdu::new(Pdu::TEXT_RESPONSE);
+        for (key, value) in request.keys() {
+            if key == "SendTargets" {
+                let target_name = self.options.target_name.clone();
+                if value == "All" || value.is_empty() || value == target_name {
+                    response.push_key("TargetName", &target_name);
+                }
+            } else if let Some(answer) = self.negotiate(&key, &value) {
+                response.push_key(&key, &answer);
+            }
+        }
+        response.set_flags(Pdu::FLAG_FINAL);
+        response.set_initiator_task_tag(request.initiator_task_tag());
+        response.set_field(Pdu::TARGET_TRANSFER_TAG, Pdu::RESERVED_TAG);
+        self.send_status(&mut response)
+    }
+
+    /// Picks our value for a negotiated session parameter, returning `None`
+    /// for keys that need no answer.
+    fn negotiate(&mut self, key: &str, value: &str) -> Option<String> {
+        let number = value.parse::<usize>().ok();
+        let answer = match key {
+            "HeaderDigest" | "DataDigest" => {
+                if value.split(',').any(|digest| digest == "None") {
+                    "None".to_string()
+                } else {
+                    "Reject".to_string()
+                }
+            }
+            "MaxRecvDataSegmentLength" => {
+                self.max_send_data_segment = number?.max(512);
+                MAX_RECV_DATA_SEGMENT_LENGTH.to_string()
+            }
+            "MaxBurstLength" => {
+                self.max_burst_length = number?.clamp(512, MAX_BURST_LENGTH);
+                self.max_burst_length.to_string()
+            }
+            "FirstBurstLength" => number?.min(MAX_BURST_LENGTH).to_string(),
+            "InitialR2T" | "DataPDUInOrder" | "DataSequenceInOrder" => "Yes".to_string(),
+            "ImmediateData" | "IFMarker" | "OFMarker" => "No".to_string(),
+            "MaxConnections" | "MaxOutstandingR2T" => "1".to_string(),
+            "ErrorRecoveryLevel" => "0".to_string(),
+            "DefaultTime2Wait" | "DefaultTime2Retain" => number?.to_string(),
+            "InitiatorAlias" => return None,
+            _ => "NotUnderstood".to_string(),
+        };
+        Some(answer)
+    }
+
+    fn check_chap(
+        &self,
+        username: Option<&str>,
+        answer: &str,
+        challenge: Option<[u8; 16]>,
+    ) -> bool {
+        let (chap, challenge) = match (self.options.chap.as_ref(), challenge) {
+            (Some(chap), Some(challenge)) => (chap, challenge),
+            _ => return false,
+        };
+        let expected = chap_response(CHAP_ID, &chap.secret, &challenge);
+        username == Some(chap.username.as_str())
+            && decode_hex(answer).is_ok_and(|answer| answer[..] == expected)
+    }
+
+    /// Refuses a login request, returning the matching `LoginError`.
+    fn refuse_login(&mut self, request: &Pdu, status_detail: u8) -> Result<(), ScsiError> {
+        let mut response = Pdu::new(Pdu::LOGIN_RESPONSE);
+        response.header[8..14].copy_from_slice(&request.header[8..14]);
+        response.set_initiator_task_tag(request.initiator_task_tag());
+        response.header[36] = INITIATOR_ERROR;
+        response.header[37] = status_detail;
+        self.send_status(&mut response)?;
+        Err(ScsiError::from_cause(ErrorCause::LoginError {
+            status_class: INITIATOR_ERROR,
+            status_detail,
+        }))
+    }
+
+    /// Sends a Reject PDU carrying the header of the rejected request.
+    fn reject(&mut self, request: &Pdu, reason: u8) -> Result<(), ScsiError> {
+        let mut response = Pdu::new(Pdu::REJECT);
+        response.set_flags(Pdu::FLAG_FINAL);
+        response.header[2] = reason;
+        response.set_initiator_task_tag(Pdu::RESERVED_TAG);
+        response.data.extend_from_slice(&request.header);
+        self.send_status(&mut response)
+    }
+
+    /// Sends a PDU that takes up the next StatSN.
+    fn send_status(&mut self, response: &mut Pdu) -> Result<(), ScsiError> {
+        response.set_field(Pdu::STAT_SN, self.stat_sn);
+        self.stat_sn = self.stat_sn.wrapping_add(1);
+        self.send(response, false)
+    }
+
+    /// Sends a PDU, filling in the command window.
+    ///
+    /// Only one command is accepted at a time, so the window is closed while
+    /// a command is `busy`.
+    fn send(&mut self, response: &mut Pdu, busy: bool) -> Result<(), ScsiError> {
+        let max_cmd_sn = if busy {
+            self.exp_cmd_sn.wrapping_sub(1)
+        } else {
+            self.exp_cmd_sn
+        };
+        response.set_field(Pdu::EXP_CMD_SN, self.exp_cmd_sn);
+        response.set_field(Pdu::MAX_CMD_SN, max_cmd_sn);
+        response.write_to(&mut self.stream)
+    }
+}
+
+/// The data phase of a single SCSI command, carried in Data-In PDUs and
+/// R2T-solicited Data-Out PDUs.
+struct IscsiDataPhase<'a, S: Read + Write + 'a> {
+    target: &'a mut IscsiTarget<S>,
+    lun: u64,
+    tag: u32,
+    expected: usize,
+
+    /// Data from the responder that has not been sent yet. The last segment
+    /// is always held back, so that it can carry the command's status.
+    pending: Vec<u8>,
+    offset: usize,
+    data_sn: u32,
+    burst: usize,
+
+    /// Data from the initiator that the responder has not read yet.
+    received: VecDeque<u8>,
+    requested: usize,
+    outstanding: usize,
+    r2t_sn: u32,
+}
+
+impl<'a, S: Read + Write> IscsiDataPhase<'a, S> {
+    /// Sends the next segment of pending data, along with the command's
+    /// residual and GOOD status if `status` is set.
+    fn send_data_in(&mut self, status: Option<(u8, u32)>) -> Result<(), ScsiError> {
+        let segment = self.target.max_send_data_segment;
+        let length = self.pending.len().min(segment);
+        let mut pdu = Pdu::new(Pdu::DATA_IN);
+        pdu.data.extend(self.pending.drain(..length));
+        self.burst += length;
+        let mut flags = 0;
+        if self.pending.is_empty() || self.burst + segment > self.target.max_burst_length {
+            flags |= Pdu::FLAG_FINAL;
+            self.burst = 0;
+        }
+        pdu.set_lun(self.lun);
+        pdu.set_initiator_task_tag(self.tag);
+        pdu.set_field(Pdu::TARGET_TRANSFER_TAG, Pdu::RESERVED_TAG);
+        pdu.set_field(Pdu::DATA_SN, self.data_sn);
+        pdu.set_field(Pdu::BUFFER_OFFSET, self.offset as u32);
+        self.offset += length;
+        self.data_sn += 1;
+        match status {
+            Some((residual_flag, residual)) if self.pending.is_empty() => {
+                pdu.set_flags(flags | Pdu::FLAG_STATUS | residual_flag);
+                pdu.header[3] = ScsiStatus::Good.code();
+                pdu.set_field(Pdu::RESIDUAL_COUNT, residual);
+                self.target.send_status(&mut pdu)
+            }
+            _ => {
+                pdu.set_flags(flags);
+                pdu.set_field(Pdu::STAT_SN, self.target.stat_sn);
+                self.target.send(&mut pdu, true)
+            }
+        }
+    }
+
+    /// Asks the initiator for the next burst of data.
+    fn send_r2t(&mut self) -> Result<(), ScsiError> {
+        let length = (self.expected - self.requested).min(self.target.max_burst_length);
+        let mut r2t = Pdu::new(Pdu::READY_TO_TRANSFER);
+        r2t.set_flags(Pdu::FLAG_FINAL);
+        r2t.set_lun(self.lun);
+        r2t.set_initiator_task_tag(self.tag);
+        r2t.set_field(Pdu::TARGET_TRANSFER_TAG, self.target.next_transfer_tag);
+        r2t.set_field(Pdu::STAT_SN, self.target.stat_sn);
+        r2t.set_field(Pdu::DATA_SN, self.r2t_sn);
+        r2t.set_field(Pdu::BUFFER_OFFSET, self.requested as u32);
+        r2t.set_field(Pdu::DESIRED_DATA_LENGTH, length as u32);
+        self.target.next_transfer_tag = self.target.next_transfer_tag.wrapping_add(1) & 0x7fff_ffff;
+        self.r2t_sn += 1;
+        self.requested += length;
+        self.outstanding = length;
+        self.target.send(&mut r2t, true)
+    }
+}
+
+impl<'a, S: Read + Write> DataPhase for IscsiDataPhase<'a, S> {
+    fn send(&mut self, data: &[u8]) -> Result<usize, ScsiError> {
+        self.pending.extend_from_slice(data);
+        while self.pending.len() > self.target.max_send_data_segment {
+            self.send_data_in(None)?;
+        }
+        Ok(data.len())
+    }
+
+    fn receive(&mut self, buffer: &mut [u8]) -> Result<usize, ScsiError> {
+        while self.received.is_empty() {
+            if self.outstanding == 0 {
+                if self.requested >= self.expected {
+                    return Err(ScsiError::from_cause(ErrorCause::UsbTransferError {
+                        direction: UsbTransferDirection::In,
+                    }));
+                }
+                self.send_r2t()?;
+            }
+            let pdu = Pdu::read_from(&mut self.target.stream)?;
+            match pdu.opcode() {
+                Pdu::DATA_OUT if pdu.initiator_task_tag() == self.tag => {
+                    let offset = pdu.field(Pdu::BUFFER_OFFSET) as usize;
+                    let received = self.requested - self.outstanding;
+                    if offset != received || pdu.data.len() > self.outstanding {
+                        return Err(ScsiError::from_cause(ErrorCause::ParseError));
+                    }
+                    self.outstanding -= pdu.data.len();
+                    self.received.extend(&pdu.data);
+                }
+                Pdu::NOP_OUT if pdu.is_immediate() => self.target.nop(&pdu)?,
+                _ => return Err(ScsiError::from_cause(ErrorCause::ParseError)),
+            }
+        }
+        let length = buffer.len().min(self.received.len());
+        for (dst, src) in buffer.iter_mut().zip(self.received.drain(..length)) {
+            *dst = src;
+        }
+        Ok(length)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{IscsiTarget, TargetOptions};
+    use crate::error::ErrorCause;
+    use crate::scsi::commands::{
+        command_block, Read10Command, RequestSenseResponse, Reserve6Command, ScsiStatus,
+        Write10Command,
+    };
+    use crate::scsi::iscsi::{IscsiInitiator, LoginOptions, Pdu};
+    use crate::scsi::responder::tests::MemoryResponder;
+    use crate::scsi::{CommandData, CommandExecutor, RamDiskResponder, ScsiBlockDevice};
+    use crate::traits::BufferPullable;
+    use byteorder::{ByteOrder, BE};
+    use std::net::{TcpListener, TcpStream};
+    use std::string::String;
+    use std::thread::{self, JoinHandle};
+    use std::vec::Vec;
+
+    const TARGET_NAME: &str = "iqn.2019-01.rs.scsi:target";
+
+    fn spawn_target(options: TargetOptions) -> (TcpStream, JoinHandle<Result<String, ErrorCause>>) {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let address = listener.local_addr().unwrap();
+        let target = thread::spawn(move || {
+            let (stream, _) = listener.accept().unwrap();
+            let mut target = IscsiTarget::new(stream, options);
+            let mut responder = MemoryResponder::default();
+            target.serve(&mut responder).map_err(|err| err.cause)?;
+            Ok(target.initiator_name().unwrap().to_string())
+        });
+        (TcpStream::connect(address).unwrap(), target)
+    }
+
+    fn exchange(stream: &mut TcpStream, request: &Pdu) -> Pdu {
+        request.write_to(stream).unwrap();
+        Pdu::read_from(stream).unwrap()
+    }
+
+    fn scsi_command(cdb: &[u8], flags: u8, length: u32, tag: u32, cmd_sn: u32) -> Pdu {
+        let mut request = Pdu::new(Pdu::SCSI_COMMAND);
+        request.set_flags(Pdu::FLAG_FINAL | flags | 0x01);
+        request.set_initiator_task_tag(tag);
+        request.set_field(Pdu::EXPECTED_DATA_LENGTH, length);
+        request.set_field(Pdu::CMD_SN, cmd_sn);
+        request.header[32..32 + cdb.len()].copy_from_slice(cdb);
+        request
+    }
+
+    #[test]
+    fn test_iscsi_target_session() {
+        let options = TargetOptions::new(TARGET_NAME).with_chap("user", b"secret");
+        let (stream, target) = spawn_target(options);
+        let options = LoginOptions::new("iqn.2019-01.rs.scsi:initiator", TARGET_NAME)
+            .with_chap("user", b"secret");
+        let initiator = IscsiInitiator::login(stream, &options).unwrap();
+        assert_eq!(initiator.tsih(), 1);
+
+        let mut scratch = [0; 512];
+        let mut device = ScsiBlockDevice::new(initiator, &mut scratch[..]).unwrap();
+        assert_eq!(device.block_size(), 256);
+        assert_eq!(device.num_blocks(), 1024);
+        let written: Vec<u8> = (0..256 * 40).map(|idx| (idx % 253) as u8).collect();
+        assert_eq!(
+            device.write_blocks(100, 40, &written).unwrap(),
+            written.len()
+        );
+        let mut read = vec![0; written.len()];
+        assert_eq!(device.read_blocks(100, 40, &mut read).unwrap(), read.len());
+        assert_eq!(read, written);
+
+        let mut initiator = device.comm_channel;
+        initiator.nop().unwrap();
+        initiator.logout().unwrap();
+        assert_eq!(
+            target.join().unwrap(),
+            Ok("iqn.2019-01.rs.scsi:initiator".to_string())
+        );
+    }
+
+    #[test]
+    fn test_iscsi_target_reservation_conflict() {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let address = listener.local_addr().unwrap();
+        let target = thread::spawn(move || {
+            // Both sessions share one disk, one after the other.
+            let mut responder = RamDiskResponder::new(vec![0; 512 * 4]);
+            for _ in 0..2 {
+                let (stream, _) = listener.accept().unwrap();
+                let mut target = IscsiTarget::new(stream, TargetOptions::new(TARGET_NAME));
+                target.serve(&mut responder).unwrap();
+            }
+        });
+        let login = |name: &str| {
+            let stream = TcpStream::connect(address).unwrap();
+            IscsiInitiator::login(stream, &LoginOptions::new(name, TARGET_NAME)).unwrap()
+        };
+
+        let mut first = login("iqn.2019-01.rs.scsi:first");
+        let outcome = first
+            .execute_command(&Reserve6Command::new(), CommandData::None)
+            .unwrap();
+        assert_eq!(outcome.status, ScsiStatus::Good);
+        first.logout().unwrap();
+
+        let mut second = login("iqn.2019-01.rs.scsi:second");
+        let outcome = second
+            .execute_command(&Reserve6Command::new(), CommandData::None)
+            .unwrap();
+        assert_eq!(outcome.status, ScsiStatus::ReservationConflict);
+        assert_eq!(outcome.sense, None);
+
+        // Unlike over Bulk-Only, the block device sees the conflict itself.
+        let mut scratch = [0; 512];
+        let mut device = ScsiBlockDevice::new(second, &mut scratch[..]).unwrap();
+        let err = device.write_blocks(0, 1, &[0xaa; 512][..]).unwrap_err();
+        assert_eq!(err.cause, ErrorCause::ReservationConflictError);
+        let err = device.reserve_unit().unwrap_err();
+        assert_eq!(err.cause, ErrorCause::ReservationConflictError);
+        device.release_unit().unwrap();
+        device.comm_channel.logout().unwrap();
+        target.join().unwrap();
+    }
+
+    #[test]
+    fn test_iscsi_target_chap_failure() {
+        let options = TargetOptions::new(TARGET_NAME).with_chap("user", b"secret");
+        let (stream, target) = spawn_target(options);
+        let options = LoginOptions::new("iqn.2019-01.rs.scsi:initiator", TARGET_NAME)
+            .with_chap("user", b"guess");
+        let error = IscsiInitiator::login(stream, &options).err().unwrap();
+        let expected = ErrorCause::LoginError {
+            status_class: 2,
+            status_detail: 1,
+        };
+        assert_eq!(error.cause, expected);
+        assert_eq!(target.join().unwrap(), Err(expected));
+    }
+
+    #[test]
+    fn test_iscsi_target_pdus() {
+        let (mut stream, target) = spawn_target(TargetOptions::new(TARGET_NAME));
+
+        // Skip straight to the operational stage, asking for small segments.
+        let mut login = Pdu::new(Pdu::LOGIN_REQUEST);
+        login.set_immediate(true);
+        login.set_flags(Pdu::FLAG_TRANSIT | (1 << 2) | 3);
+        login.header[8] = 0x80;
+        login.set_field(Pdu::CMD_SN, 1);
+        login.push_key("InitiatorName", "iqn.2019-01.rs.scsi:raw");
+        login.push_key("TargetName", TARGET_NAME);
+        login.push_key("HeaderDigest", "CRC32C,None");
+        login.push_key("MaxRecvDataSegmentLength", "512");
+        login.push_key("X-rs.scsi.Unknown", "Yes");
+        let response = exchange(&mut stream, &login);
+        assert_eq!(response.opcode(), Pdu::LOGIN_RESPONSE);
+        assert_eq!(&response.header[36..38], &[0, 0]);
+        assert_eq!(response.flags(), Pdu::FLAG_TRANSIT | (1 << 2) | 3);
+        assert_eq!(response.header[8], 0x80);
+        assert_eq!(BE::read_u16(&response.header[14..]), 1);
+        assert_eq!(response.key("HeaderDigest"), Some("None"));
+        assert_eq!(response.key("X-rs.scsi.Unknown"), Some("NotUnderstood"));
+        assert_eq!(response.field(Pdu::EXP_CMD_SN), 1);
+
+        // Writes are solicited with an R2T.
+        let write = Write10Command::new(4 * 256, 512, 256).unwrap();
+        let (cdb, length) = command_block(&write).unwrap();
+        let request = scsi_command(&cdb[..length], Pdu::FLAG_WRITE, 512, 1, 1);
+        let r2t = exchange(&mut stream, &request);
+        assert_eq!(r2t.opcode(), Pdu::READY_TO_TRANSFER);
+        assert_eq!(r2t.initiator_task_tag(), 1);
+        assert_eq!(r2t.field(Pdu::BUFFER_OFFSET), 0);
+        assert_eq!(r2t.field(Pdu::DESIRED_DATA_LENGTH), 512);
+        let written: Vec<u8> = (0..512).map(|idx| (idx % 249) as u8).collect();
+        for (idx, chunk) in written.chunks(256).enumerate() {
+            let mut data_out = Pdu::new(Pdu::DATA_OUT);
+            data_out.set_flags(if idx == 1 { Pdu::FLAG_FINAL } else { 0 });
+            data_out.set_initiator_task_tag(1);
+            data_out.set_field(
+                Pdu::TARGET_TRANSFER_TAG,
+                r2t.field(Pdu::TARGET_TRANSFER_TAG),
+            );
+            data_out.set_field(Pdu::DATA_SN, idx as u32);
+            data_out.set_field(Pdu::BUFFER_OFFSET, 256 * idx as u32);
+            data_out.data.extend_from_slice(chunk);
+            data_out.write_to(&mut stream).unwrap();
+        }
+        let response = Pdu::read_from(&mut stream).unwrap();
+        assert_eq!(response.opcode(), Pdu::SCSI_RESPONSE);
+        assert_eq!(response.flags(), Pdu::FLAG_FINAL);
+        assert_eq!(response.header[3], 0);
+        assert_eq!(response.field(Pdu::STAT_SN), 1);
+        assert_eq!(response.field(Pdu::MAX_CMD_SN), 2);
+
+        // Reads are split to fit the initiator's segment length, and the
+        // status comes with the last Data-In PDU. Asking for less than the
+        // command transfers reports an overflow.
+        let read = Read10Command::new(0, 256 * 8, 256).unwrap();
+        let (cdb, length) = command_block(&read).unwrap();
+        let request = scsi_command(&cdb[..length], Pdu::FLAG_READ, 1536, 2, 2);
+        request.write_to(&mut stream).unwrap();
+        let mut data = Vec::new();
+        for idx in 0..3 {
+            let data_in = Pdu::read_from(&mut stream).unwrap();
+            assert_eq!(data_in.opcode(), Pdu::DATA_IN);
+            assert_eq!(data_in.initiator_task_tag(), 2);
+            assert_eq!(data_in.field(Pdu::DATA_SN), idx);
+            assert_eq!(data_in.field(Pdu::BUFFER_OFFSET) as usize, data.len());
+            assert_eq!(data_in.data.len(), 512);
+            data.extend_from_slice(&data_in.data);
+            if idx == 2 {
+                let flags = Pdu::FLAG_FINAL | Pdu::FLAG_STATUS | Pdu::FLAG_OVERFLOW;
+                assert_eq!(data_in.flags(), flags);
+                assert_eq!(data_in.header[3], 0);
+                assert_eq!(data_in.field(Pdu::RESIDUAL_COUNT), 512);
+                assert_eq!(data_in.field(Pdu::STAT_SN), 2);
+            } else {
+                assert_eq!(data_in.flags() & Pdu::FLAG_STATUS, 0);
+            }
+        }
+        assert_eq!(&data[1024..1536], &written[..]);
+
+        // Unknown opcodes fail with sense data in the response.
+        let request = scsi_command(&[0xff, 0, 0, 0, 0, 0], Pdu::FLAG_READ, 64, 3, 3);
+        let response = exchange(&mut stream, &request);
+        assert_eq!(response.opcode(), Pdu::SCSI_RESPONSE);
+        assert_eq!(response.header[3], 2);
+        let sense_length = BE::read_u16(&response.data) as usize;
+        assert_eq!(sense_length, RequestSenseResponse::SIZE);
+        let sense = RequestSenseResponse::pull_from_buffer(&response.data[2..]).unwrap();
+        assert_eq!(sense.sense_key, RequestSenseResponse::ILLEGAL_REQUEST);
+        assert_eq!(sense.additional_sense_code, 0x20);
+
+        let mut text = Pdu::new(Pdu::TEXT_REQUEST);
+        text.set_flags(Pdu::FLAG_FINAL);
+        text.set_initiator_task_tag(4);
+        text.set_field(Pdu::CMD_SN, 4);
+        text.push_key("SendTargets", "All");
+        let response = exchange(&mut stream, &text);
+        assert_eq!(response.opcode(), Pdu::TEXT_RESPONSE);
+        assert_eq!(response.key("TargetName"), Some(TARGET_NAME));
+
+        // SNACKs are not supported.
+        let response = exchange(&mut stream, &Pdu::new(0x10));
+        assert_eq!(response.opcode(), Pdu::REJECT);
+        assert_eq!(response.header[2], 0x05);
+        assert_eq!(response.data[0], 0x10);
+
+        let mut logout = Pdu::new(Pdu::LOGOUT_REQUEST);
+        logout.set_immediate(true);
+        logout.set_flags(Pdu::FLAG_FINAL);
+        logout.set_initiator_task_tag(5);
+        logout.set_field(Pdu::CMD_SN, 5);
+        let response = exchange(&mut stream, &logout);
+        assert_eq!(response.opcode(), Pdu::LOGOUT_RESPONSE);
+        assert_eq!(response.header[2], 0);
+        assert_eq!(
+            target.join().unwrap(),
+            Ok("iqn.2019-01.rs.scsi:raw".to_string())
+        );
+    }
+}
@@ -5,8 +5,73 @@
 /// Contains implementations of the different SCSI commands and responses.
 pub mod commands;
 
+mod cdrom;
+pub use self::cdrom::*;
+
+mod ramdisk;
+pub use self::ramdisk::*;
+
+mod reservations;
+pub use self::reservations::*;
+
 mod device;
 pub use self::device::*;
 
+mod asyncdevice;
+pub use self::asyncdevice::*;
+
+#[cfg(feature = "std")]
+mod io;
+#[cfg(feature = "std")]
+pub use self::io::*;
+
+#[cfg(feature = "std")]
+mod passthrough;
+#[cfg(feature = "std")]
+pub use self::passthrough::*;
+
+#[cfg(all(feature = "std", target_os = "linux"))]
+mod sg;
+#[cfg(all(feature = "std", target_os = "linux"))]
+pub use self::sg::*;
+
+#[cfg(feature = "rusb")]
+mod usb;
+#[cfg(feature = "rusb")]
+pub use self::usb::*;
+
+#[cfg(feature = "embedded-sdmmc")]
+mod sdmmc;
+#[cfg(feature = "embedded-sdmmc")]
+pub use self::sdmmc::*;
+
+mod bulkonly;
+pub use self::bulkonly::*;
+
+#[cfg(feature = "usb-device")]
+mod usbdevice;
+#[cfg(feature = "usb-device")]
+pub use self::usbdevice::*;
+
+#[cfg(feature = "embedded-io")]
+mod embeddedio;
+#[cfg(feature = "embedded-io")]
+pub use self::embeddedio::*;
+
 mod responder;
 pub use self::responder::*;
+
+/// Contains the USB Attached SCSI transport.
+pub mod uas;
+
+/// Contains the iSCSI transport.
+#[cfg(feature = "std")]
+pub mod iscsi;
+
+/// Contains the NBD server.
+#[cfg(feature = "std")]
+pub mod nbd;
+
+/// Contains the USB/IP server.
+#[cfg(feature = "std")]
+pub mod usbip;
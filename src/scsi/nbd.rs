@@ -0,0 +1,801 @@
+//! Support for exporting an `ScsiBlockDevice` over the Network Block Device
+//! protocol.
+//!
+//! An `NbdServer` answers a single client connection using the fixed
+//! newstyle handshake, then turns the client's reads, writes, flushes and
+//! trims into READ(10), WRITE(10), SYNCHRONIZE CACHE(10) and UNMAP commands
+//! on the device. This makes any device reachable through this crate usable
+//! as an ordinary Linux block device with `nbd-client`, or as a disk image
+//! for `qemu` with `nbd://127.0.0.1/<export name>`.
+
+use std::io::{Read, Write};
+use std::string::String;
+use std::vec::Vec;
+
+use byteorder::{ByteOrder, BE};
+
+use crate::error::{ErrorCause, ScsiError};
+use crate::scsi::ScsiBlockDevice;
+use crate::traits::CommunicationChannel;
+
+/// The magic numbers opening the handshake.
+const NBD_MAGIC: u64 = 0x4e42_444d_4147_4943;
+const OPTION_MAGIC: u64 = 0x4948_4156_454f_5054;
+
+/// The magic number opening every option reply.
+const OPTION_REPLY_MAGIC: u64 = 0x0003_e889_0455_65a9;
+
+/// The magic numbers of transmission requests and simple replies.
+const REQUEST_MAGIC: u32 = 0x2560_9513;
+const SIMPLE_REPLY_MAGIC: u32 = 0x6744_6698;
+
+/// Handshake flags, sent by the server.
+const FLAG_FIXED_NEWSTYLE: u16 = 1 << 0;
+const FLAG_NO_ZEROES: u16 = 1 << 1;
+
+/// Client flags, acknowledging the handshake flags.
+const CLIENT_FIXED_NEWSTYLE: u32 = 1 << 0;
+const CLIENT_NO_ZEROES: u32 = 1 << 1;
+
+/// Options a client can send during the handshake.
+const OPT_EXPORT_NAME: u32 = 1;
+const OPT_ABORT: u32 = 2;
+const OPT_LIST: u32 = 3;
+const OPT_INFO: u32 = 6;
+const OPT_GO: u32 = 7;
+
+/// Option reply types.
+const REP_ACK: u32 = 1;
+const REP_SERVER: u32 = 2;
+const REP_INFO: u32 = 3;
+const REP_ERR_UNSUP: u32 = (1 << 31) + 1;
+const REP_ERR_INVALID: u32 = (1 << 31) + 3;
+const REP_ERR_UNKNOWN: u32 = (1 << 31) + 6;
+
+/// Information types sent in `REP_INFO` replies.
+const INFO_EXPORT: u16 = 0;
+const INFO_BLOCK_SIZE: u16 = 3;
+
+/// Transmission flags, describing the export.
+const TRANSMISSION_HAS_FLAGS: u16 = 1 << 0;
+const TRANSMISSION_READ_ONLY: u16 = 1 << 1;
+const TRANSMISSION_SEND_FLUSH: u16 = 1 << 2;
+const TRANSMISSION_SEND_FUA: u16 = 1 << 3;
+const TRANSMISSION_SEND_TRIM: u16 = 1 << 5;
+
+/// Transmission request types.
+const CMD_READ: u16 = 0;
+const CMD_WRITE: u16 = 1;
+const CMD_DISC: u16 = 2;
+const CMD_FLUSH: u16 = 3;
+const CMD_TRIM: u16 = 4;
+
+/// Asks for a write to reach permanent storage before it is acknowledged.
+const CMD_FLAG_FUA: u16 = 1 << 0;
+
+/// The errors reported in transmission replies.
+const EPERM: u32 = 1;
+const EIO: u32 = 5;
+const EINVAL: u32 = 22;
+const ENOSPC: u32 = 28;
+
+/// The size of a transmission request.
+const REQUEST_SIZE: usize = 28;
+
+/// The longest option a client may send, which comfortably holds the 4096
+/// byte export names the protocol allows.
+const MAX_OPTION_LENGTH: usize = 8 * 1024;
+
+/// The largest read or write a client may request.
+const MAX_REQUEST_LENGTH: usize = 32 * 1024 * 1024;
+
+/// The parameters a server uses to describe its export.
+#[derive(Clone, Eq, PartialEq, Debug)]
+pub struct NbdOptions {
+    /// The name clients ask for the export by. Clients asking for the empty
+    /// name are given the export as well, since that is the default export.
+    pub export_name: String,
+
+    /// Whether to advertise support for trimming, which is sent to the device
+    /// as UNMAP commands.
+    pub trim: bool,
+}
+
+impl NbdOptions {
+    /// Constructs options for an export with the given name, without
+    /// support for trimming.
+    pub fn new(export_name: &str) -> NbdOptions {
+        NbdOptions {
+            export_name: export_name.to_string(),
+            trim: false,
+        }
+    }
+
+    /// Advertises support for trimming, for devices that implement UNMAP.
+    pub fn with_trim(mut self) -> NbdOptions {
+        self.trim = true;
+        self
+    }
+}
+
+/// The server side of a single NBD connection, exporting an
+/// `ScsiBlockDevice`.
+///
+/// Requests are answered one at a time, in the order they are received.
+/// Reads and writes that are not aligned to the device's block size are
+/// handled by reading the surrounding blocks, so clients do not need to
+/// negotiate block sizes. Writes with the FUA flag, and flushes, are followed
+/// by a SYNCHRONIZE CACHE command, and trims discard the whole blocks that lie
+/// within the trimmed range, in batches the device's unmap limits allow.
+pub struct NbdServer<S: Read + Write> {
+    stream: S,
+    options: NbdOptions,
+    no_zeroes: bool,
+    transmitting: bool,
+}
+
+impl<S: Read + Write> NbdServer<S> {
+    /// Starts serving a newly accepted connection.
+    pub fn new(stream: S, options: NbdOptions) -> NbdServer<S> {
+        NbdServer {
+            stream,
+            options,
+            no_zeroes: false,
+            transmitting: false,
+        }
+    }
+
+    /// Whether the handshake has finished and the client is sending requests.
+    pub fn is_transmitting(&self) -> bool {
+        self.transmitting
+    }
+
+    /// Returns a reference to the underlying stream.
+    pub fn get_ref(&self) -> &S {
+        &self.stream
+    }
+
+    /// Returns a mutable reference to the underlying stream.
+    pub fn get_mut(&mut self) -> &mut S {
+        &mut self.stream
+    }
+
+    /// Runs the handshake, then answers the client's requests against
+    /// `device` until it disconnects.
+    pub fn serve<C: CommunicationChannel>(
+        &mut self,
+        device: &mut ScsiBlockDevice<C>,
+    ) -> Result<(), ScsiError> {
+        if !self.transmitting && !self.handshake(device)? {
+            return Ok(());
+        }
+        while self.process_request(device)? {}
+        Ok(())
+    }
+
+    /// Greets the client and answers its options until it picks the export.
+    ///
+    /// Returns whether the client moved on to sending requests; if not, the
+    /// connection is finished with.
+    pub fn handshake<C: CommunicationChannel>(
+        &mut self,
+        device: &ScsiBlockDevice<C>,
+    ) -> Result<bool, ScsiError> {
+        let mut greeting = [0; 18];
+        BE::write_u64(&mut greeting, NBD_MAGIC);
+        BE::write_u64(&mut greeting[8..], OPTION_MAGIC);
+        BE::write_u16(&mut greeting[16..], FLAG_FIXED_NEWSTYLE | FLAG_NO_ZEROES);
+        self.stream.write_all(&greeting)?;
+        self.stream.flush()?;
+
+        let mut client_flags = [0; 4];
+        self.stream.read_exact(&mut client_flags)?;
+        let client_flags = BE::read_u32(&client_flags);
+        if client_flags & !(CLIENT_FIXED_NEWSTYLE | CLIENT_NO_ZEROES) != 0 {
+            // The client wants something we did not offer.
+            return Ok(false);
+        }
+        self.no_zeroes = client_flags & CLIENT_NO_ZEROES != 0;
+
+        loop {
+            let mut header = [0; 16];
+            self.stream.read_exact(&mut header)?;
+            if BE::read_u64(&header) != OPTION_MAGIC {
+                return Err(ScsiError::from_cause(ErrorCause::ParseError));
+            }
+            let option = BE::read_u32(&header[8..]);
+            let length = BE::read_u32(&header[12..]) as usize;
+            if length > MAX_OPTION_LENGTH {
+                return Err(ScsiError::from_cause(ErrorCause::TransferTooLargeError {
+                    actual: length,
+                    max: MAX_OPTION_LENGTH,
+                }));
+            }
+            let mut data = vec![0; length];
+            self.stream.read_exact(&mut data)?;
+            match option {
+                OPT_EXPORT_NAME => {
+                    if !self.is_export(&data) {
+                        return Ok(false);
+                    }
+                    let mut reply = vec![0; 10];
+                    BE::write_u64(&mut reply, device.capacity_bytes());
+                    BE::write_u16(&mut reply[8..], self.transmission_flags(device));
+                    if !self.no_zeroes {
+                        reply.resize(10 + 124, 0);
+                    }
+                    self.stream.write_all(&reply)?;
+                    self.stream.flush()?;
+                    self.transmitting = true;
+                    return Ok(true);
+                }
+                OPT_ABORT => {
+                    self.option_reply(option, REP_ACK, &[])?;
+                    return Ok(false);
+                }
+                OPT_LIST if !data.is_empty() => {
+                    self.option_reply(option, REP_ERR_INVALID, &[])?;
+                }
+                OPT_LIST => {
+                    let name = self.options.export_name.as_bytes();
+                    let mut server = vec![0; 4];
+                    BE::write_u32(&mut server, name.len() as u32);
+                    server.extend_from_slice(name);
+                    self.option_reply(option, REP_SERVER, &server)?;
+                    self.option_reply(option, REP_ACK, &[])?;
+                }
+                OPT_INFO | OPT_GO => {
+                    if self.answer_info(device, option, &data)? && option == OPT_GO {
+                        self.transmitting = true;
+                        return Ok(true);
+                    }
+                }
+                _ => self.option_reply(option, REP_ERR_UNSUP, &[])?,
+            }
+        }
+    }
+
+    /// Reads and answers a single request from the client.
+    ///
+    /// Returns `false` once the client has disconnected.
+    pub fn process_request<C: CommunicationChannel>(
+        &mut self,
+        device: &mut ScsiBlockDevice<C>,
+    ) -> Result<bool, ScsiError> {
+        let mut request = [0; REQUEST_SIZE];
+        let read = self.stream.read(&mut request)?;
+        if read == 0 {
+            return Ok(false);
+        }
+        self.stream.read_exact(&mut request[read..])?;
+        if BE::read_u32(&request) != REQUEST_MAGIC {
+            return Err(ScsiError::from_cause(ErrorCause::ParseError));
+        }
+        let flags = BE::read_u16(&request[4..]);
+        let kind = BE::read_u16(&request[6..]);
+        let handle = BE::read_u64(&request[8..]);
+        let offset = BE::read_u64(&request[16..]);
+        let length = BE::read_u32(&request[24..]) as usize;
+        let in_range = offset
+            .checked_add(length as u64)
+            .is_some_and(|end| end <= device.capacity_bytes());
+
+        match kind {
+            CMD_READ => {
+                if !in_range || length > MAX_REQUEST_LENGTH {
+                    return self.reply(handle, EINVAL, &[]).map(|_| true);
+                }
+                match read_bytes(device, offset, length) {
+                    Ok(data) => self.reply(handle, 0, &data)?,
+                    Err(err) => self.reply(handle, error_code(&err), &[])?,
+                }
+            }
+            CMD_WRITE => {
+                if length > MAX_REQUEST_LENGTH {
+                    // Skipping the payload would mean reading it all anyway.
+                    return Err(ScsiError::from_cause(ErrorCause::TransferTooLargeError {
+                        actual: length,
+                        max: MAX_REQUEST_LENGTH,
+                    }));
+                }
+                let mut data = vec![0; length];
+                self.stream.read_exact(&mut data)?;
+                let error = if device.is_read_only() {
+                    EPERM
+                } else if !in_range {
+                    ENOSPC
+                } else {
+                    let result = write_bytes(device, offset, &data).and_then(|_| {
+                        if flags & CMD_FLAG_FUA != 0 {
+                            device.synchronize_cache()
+                        } else {
+                            Ok(())
+                        }
+                    });
+                    result.err().map_or(0, |err| error_code(&err))
+                };
+                self.reply(handle, error, &[])?;
+            }
+            CMD_DISC => return Ok(false),
+            CMD_FLUSH => {
+                let error = device
+                    .synchronize_cache()
+                    .err()
+                    .map_or(0, |err| error_code(&err));
+                self.reply(handle, error, &[])?;
+            }
+            CMD_TRIM => {
+                let error = if !self.trim_enabled(device) || !in_range {
+                    EINVAL
+                } else {
+                    trim_bytes(device, offset, length)
+                        .err()
+                        .map_or(0, |err| error_code(&err))
+                };
+                self.reply(handle, error, &[])?;
+            }
+            _ => self.reply(handle, EINVAL, &[])?,
+        }
+        Ok(true)
+    }
+
+    /// Answers an `OPT_INFO` or `OPT_GO` option, returning whether it named
+    /// the export.
+    fn answer_info<C: CommunicationChannel>(
+        &mut self,
+        device: &ScsiBlockDevice<C>,
+        option: u32,
+        data: &[u8],
+    ) -> Result<bool, ScsiError> {
+        let name_length = if data.len() >= 4 {
+            BE::read_u32(data) as usize
+        } else {
+            usize::MAX
+        };
+        let requests_start = name_length.saturating_add(4);
+        let valid = data.len() >= requests_start.saturating_add(2)
+            && data.len()
+                == requests_start + 2 + 2 * usize::from(BE::read_u16(&data[requests_start..]));
+        if !valid {
+            self.option_reply(option, REP_ERR_INVALID, &[])?;
+            return Ok(false);
+        }
+        if !self.is_export(&data[4..requests_start]) {
+            self.option_reply(option, REP_ERR_UNKNOWN, &[])?;
+            return Ok(false);
+        }
+
+        let mut export = [0; 12];
+        BE::write_u16(&mut export, INFO_EXPORT);
+        BE::write_u64(&mut export[2..], device.capacity_bytes());
+        BE::write_u16(&mut export[10..], self.transmission_flags(device));
+        self.option_reply(option, REP_INFO, &export)?;
+        let wants_block_size = data[requests_start + 2..]
+            .chunks(2)
+            .any(|request| BE::read_u16(request) == INFO_BLOCK_SIZE);
+        if wants_block_size {
+            let mut block_size = [0; 14];
+            BE::write_u16(&mut block_size, INFO_BLOCK_SIZE);
+            BE::write_u32(&mut block_size[2..], 1);
+            BE::write_u32(&mut block_size[6..], device.block_size());
+            BE::write_u32(&mut block_size[10..], MAX_REQUEST_LENGTH as u32);
+            self.option_reply(option, REP_INFO, &block_size)?;
+        }
+        self.option_reply(option, REP_ACK, &[])?;
+        Ok(true)
+    }
+
+    /// Whether `name` refers to the export.
+    fn is_export(&self, name: &[u8]) -> bool {
+        name.is_empty() || name == self.options.export_name.as_bytes()
+    }
+
+    /// Whether trim requests are accepted.
+    fn trim_enabled<C: CommunicationChannel>(&self, device: &ScsiBlockDevice<C>) -> bool {
+        self.options.trim && !device.is_read_only()
+    }
+
+    /// The transmission flags describing the export of `device`.
+    fn transmission_flags<C: CommunicationChannel>(&self, device: &ScsiBlockDevice<C>) -> u16 {
+        let mut flags = TRANSMISSION_HAS_FLAGS | TRANSMISSION_SEND_FLUSH;
+        if device.is_read_only() {
+            flags |= TRANSMISSION_READ_ONLY;
+        } else {
+            flags |= TRANSMISSION_SEND_FUA;
+        }
+        if self.trim_enabled(device) {
+            flags |= TRANSMISSION_SEND_TRIM;
+        }
+        flags
+    }
+
+    /// Sends a reply to an option.
+    fn option_reply(&mut self, option: u32, kind: u32, data: &[u8]) -> Result<(), ScsiError> {
+        let mut reply = vec![0; 20];
+        BE::write_u64(&mut reply, OPTION_REPLY_MAGIC);
+        BE::write_u32(&mut reply[8..], option);
+        BE::write_u32(&mut reply[12..], kind);
+        BE::write_u32(&mut reply[16..], data.len() as u32);
+        reply.extend_from_slice(data);
+        self.stream.write_all(&reply)?;
+        self.stream.flush()?;
+        Ok(())
+    }
+
+    /// Sends a simple reply to a request, followed by the data for reads.
+    fn reply(&mut self, handle: u64, error: u32, data: &[u8]) -> Result<(), ScsiError> {
+        // The header and data go out in a single write, so that they are not
+        // held apart by Nagle's algorithm.
+        let mut reply = vec![0; 16 + data.len()];
+        BE::write_u32(&mut reply, SIMPLE_REPLY_MAGIC);
+        BE::write_u32(&mut reply[4..], error);
+        BE::write_u64(&mut reply[8..], handle);
+        reply[16..].copy_from_slice(data);
+        self.stream.write_all(&reply)?;
+        self.stream.flush()?;
+        Ok(())
+    }
+}
+
+/// The range of blocks covering `length` bytes starting at byte `offset`.
+fn covering_blocks(block_size: u64, offset: u64, length: usize) -> (u64, u32) {
+    let first = offset / block_size;
+    let end = (offset + length as u64).div_ceil(block_size);
+    (first, (end - first) as u32)
+}
+
+/// Reads `length` bytes starting at byte `offset`.
+fn read_bytes<C: CommunicationChannel>(
+    device: &mut ScsiBlockDevice<C>,
+    offset: u64,
+    length: usize,
+) -> Result<Vec<u8>, ScsiError> {
+    let block_size = u64::from(device.block_size());
+    let (lba, count) = covering_blocks(block_size, offset, length);
+    let mut blocks = vec![0; count as usize * block_size as usize];
+    if count > 0 {
+        device.read_blocks(lba, count, &mut blocks[..])?;
+    }
+    let within = (offset % block_size) as usize;
+    blocks.truncate(within + length);
+    blocks.drain(..within);
+    Ok(blocks)
+}
+
+/// Writes `data` starting at byte `offset`, reading back the blocks at
+/// either end if they are only partly overwritten.
+fn write_bytes<C: CommunicationChannel>(
+    device: &mut ScsiBlockDevice<C>,
+    offset: u64,
+    data: &[u8],
+) -> Result<(), ScsiError> {
+    let block_size = u64::from(device.block_size());
+    let (lba, count) = covering_blocks(block_size, offset, data.len());
+    if count == 0 {
+        return Ok(());
+    }
+    let within = (offset % block_size) as usize;
+    let end = within + data.len();
+    if within == 0 && end % (block_size as usize) == 0 {
+        device.write_blocks(lba, count, data)?;
+        return Ok(());
+    }
+    let size = block_size as usize;
+    let mut blocks = vec![0; count as usize * size];
+    if within != 0 {
+        device.read_blocks(lba, 1, &mut blocks[..size])?;
+    }
+    let last = blocks.len() - size;
+    if end % size != 0 && (count > 1 || within == 0) {
+        device.read_blocks(lba + u64::from(count) - 1, 1, &mut blocks[last..])?;
+    }
+    blocks[within..end].copy_from_slice(data);
+    device.write_blocks(lba, count, &blocks[..])?;
+    Ok(())
+}
+
+/// Discards the whole blocks within `length` bytes starting at byte `offset`.
+fn trim_bytes<C: CommunicationChannel>(
+    device: &mut ScsiBlockDevice<C>,
+    offset: u64,
+    length: usize,
+) -> Result<(), ScsiError> {
+    let block_size = u64::from(device.block_size());
+    let first = offset.div_ceil(block_size);
+    let end = (offset + length as u64) / block_size;
+    if end <= first {
+        return Ok(());
+    }
+    device.discard(first, end - first)?;
+    Ok(())
+}
+
+/// The error reported to the client when a request fails with `err`.
+fn error_code(err: &ScsiError) -> u32 {
+    match err.cause {
+        ErrorCause::ReadOnlyDeviceError => EPERM,
+        ErrorCause::BlockAddressOutOfRangeError { .. } => EINVAL,
+        _ => EIO,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{NbdOptions, NbdServer};
+    use crate::error::ErrorCause;
+    use crate::scsi::responder::tests::{LoopbackChannel, MemoryResponder};
+    use crate::scsi::ScsiBlockDevice;
+    use byteorder::{ByteOrder, BE};
+    use std::io::{Read, Write};
+    use std::net::{TcpListener, TcpStream};
+    use std::thread::{self, JoinHandle};
+    use std::vec::Vec;
+
+    /// The flags of the test export: HAS_FLAGS, SEND_FLUSH and SEND_FUA.
+    const EXPORT_FLAGS: u16 = 0x0d;
+
+    const EXPORT_SIZE: u64 = 256 * 1024;
+
+    fn spawn_server(connections: usize) -> (String, JoinHandle<Vec<Result<bool, ErrorCause>>>) {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let address = listener.local_addr().unwrap().to_string();
+        let server = thread::spawn(move || {
+            let channel = LoopbackChannel::new(MemoryResponder::default());
+            let mut scratch = [0; 512];
+            let mut device = ScsiBlockDevice::new(channel, &mut scratch[..]).unwrap();
+            (0..connections)
+                .map(|_| {
+                    let (stream, _) = listener.accept().unwrap();
+                    let mut server = NbdServer::new(stream, NbdOptions::new("disk"));
+                    server.serve(&mut device).map_err(|err| err.cause)?;
+                    Ok(server.is_transmitting())
+                })
+                .collect()
+        });
+        (address, server)
+    }
+
+    /// Connects and reads the greeting, answering it with `flags`.
+    fn connect(address: &str, flags: u32) -> TcpStream {
+        let mut stream = TcpStream::connect(address).unwrap();
+        let mut greeting = [0; 18];
+        stream.read_exact(&mut greeting).unwrap();
+        assert_eq!(&greeting[..8], b"NBDMAGIC");
+        assert_eq!(&greeting[8..16], b"IHAVEOPT");
+        assert_eq!(BE::read_u16(&greeting[16..]), 3);
+        let mut client_flags = [0; 4];
+        BE::write_u32(&mut client_flags, flags);
+        stream.write_all(&client_flags).unwrap();
+        stream
+    }
+
+    fn send_option(stream: &mut TcpStream, option: u32, data: &[u8]) {
+        let mut request = b"IHAVEOPT".to_vec();
+        request.extend_from_slice(&[0; 8]);
+        BE::write_u32(&mut request[8..], option);
+        BE::write_u32(&mut request[12..], data.len() as u32);
+        request.extend_from_slice(data);
+        stream.write_all(&request).unwrap();
+    }
+
+    fn read_option_reply(stream: &mut TcpStream, option: u32) -> (u32, Vec<u8>) {
+        let mut header = [0; 20];
+        stream.read_exact(&mut header).unwrap();
+        assert_eq!(BE::read_u64(&header), 0x0003_e889_0455_65a9);
+        assert_eq!(BE::read_u32(&header[8..]), option);
+        let mut data = vec![0; BE::read_u32(&header[16..]) as usize];
+        stream.read_exact(&mut data).unwrap();
+        (BE::read_u32(&header[12..]), data)
+    }
+
+    fn info_request(name: &str, requests: &[u16]) -> Vec<u8> {
+        let mut data = vec![0; 4];
+        BE::write_u32(&mut data, name.len() as u32);
+        data.extend_from_slice(name.as_bytes());
+        data.extend_from_slice(&[0; 2]);
+        BE::write_u16(&mut data[4 + name.len()..], requests.len() as u16);
+        for request in requests {
+            data.extend_from_slice(&[0; 2]);
+            let at = data.len() - 2;
+            BE::write_u16(&mut data[at..], *request);
+        }
+        data
+    }
+
+    /// Sends a request, returning the error and any data in the reply.
+    fn request(
+        stream: &mut TcpStream,
+        kind: u16,
+        flags: u16,
+        offset: u64,
+        length: u32,
+        data: &[u8],
+    ) -> (u32, Vec<u8>) {
+        let mut request = vec![0; 28];
+        BE::write_u32(&mut request, 0x2560_9513);
+        BE::write_u16(&mut request[4..], flags);
+        BE::write_u16(&mut request[6..], kind);
+        BE::write_u64(&mut request[8..], 0x1234_5678 + u64::from(kind));
+        BE::write_u64(&mut request[16..], offset);
+        BE::write_u32(&mut request[24..], length);
+        request.extend_from_slice(data);
+        stream.write_all(&request).unwrap();
+
+        let mut reply = [0; 16];
+        stream.read_exact(&mut reply).unwrap();
+        assert_eq!(BE::read_u32(&reply), 0x6744_6698);
+        assert_eq!(BE::read_u64(&reply[8..]), 0x1234_5678 + u64::from(kind));
+        let error = BE::read_u32(&reply[4..]);
+        let mut data = Vec::new();
+        if kind == 0 && error == 0 {
+            data.resize(length as usize, 0);
+            stream.read_exact(&mut data).unwrap();
+        }
+        (error, data)
+    }
+
+    #[test]
+    fn test_nbd_options() {
+        let (address, server) = spawn_server(2);
+        let mut stream = connect(&address, 3);
+
+        send_option(&mut stream, 3, &[]);
+        let (kind, data) = read_option_reply(&mut stream, 3);
+        assert_eq!(kind, 2);
+        assert_eq!(BE::read_u32(&data), 4);
+        assert_eq!(&data[4..], b"disk");
+        assert_eq!(read_option_reply(&mut stream, 3), (1, Vec::new()));
+
+        send_option(&mut stream, 6, &info_request("other", &[]));
+        assert_eq!(read_option_reply(&mut stream, 6).0, (1 << 31) + 6);
+        send_option(&mut stream, 6, &[0, 0, 0, 9]);
+        assert_eq!(read_option_reply(&mut stream, 6).0, (1 << 31) + 3);
+        send_option(&mut stream, 99, &[1, 2, 3]);
+        assert_eq!(read_option_reply(&mut stream, 99).0, (1 << 31) + 1);
+
+        send_option(&mut stream, 6, &info_request("", &[]));
+        let (kind, export) = read_option_reply(&mut stream, 6);
+        assert_eq!((kind, export.len()), (3, 12));
+        assert_eq!(BE::read_u64(&export[2..]), EXPORT_SIZE);
+        assert_eq!(read_option_reply(&mut stream, 6).0, 1);
+
+        send_option(&mut stream, 2, &[]);
+        assert_eq!(read_option_reply(&mut stream, 2).0, 1);
+
+        // Asking for an export we do not have by name just drops the
+        // connection.
+        let mut stream = connect(&address, 1);
+        send_option(&mut stream, 1, b"other");
+        assert_eq!(stream.read(&mut [0; 8]).unwrap(), 0);
+        assert_eq!(server.join().unwrap(), vec![Ok(false), Ok(false)]);
+    }
+
+    #[test]
+    fn test_nbd_session() {
+        let (address, server) = spawn_server(2);
+        let mut stream = connect(&address, 3);
+        send_option(&mut stream, 7, &info_request("disk", &[3]));
+        let (kind, export) = read_option_reply(&mut stream, 7);
+        assert_eq!(kind, 3);
+        assert_eq!(BE::read_u16(&export), 0);
+        assert_eq!(BE::read_u64(&export[2..]), EXPORT_SIZE);
+        assert_eq!(BE::read_u16(&export[10..]), EXPORT_FLAGS);
+        let (kind, block_size) = read_option_reply(&mut stream, 7);
+        assert_eq!(kind, 3);
+        assert_eq!(BE::read_u16(&block_size), 3);
+        assert_eq!(BE::read_u32(&block_size[2..]), 1);
+        assert_eq!(BE::read_u32(&block_size[6..]), 256);
+        assert_eq!(read_option_reply(&mut stream, 7).0, 1);
+
+        // An unaligned write spanning several blocks leaves its neighbours
+        // alone.
+        let written: Vec<u8> = (0..1000).map(|idx| (idx % 251) as u8 + 1).collect();
+        assert_eq!(request(&mut stream, 1, 0, 300, 1000, &written).0, 0);
+        let (error, read) = request(&mut stream, 0, 0, 0, 2048, &[]);
+        assert_eq!(error, 0);
+        assert!(read[..300].iter().all(|&byte| byte == 0));
+        assert_eq!(&read[300..1300], &written[..]);
+        assert!(read[1300..].iter().all(|&byte| byte == 0));
+
+        // Writes within a single block, and aligned writes with FUA.
+        assert_eq!(request(&mut stream, 1, 0, 2050, 4, &[9; 4]).0, 0);
+        assert_eq!(request(&mut stream, 1, 1, 4096, 512, &[7; 512]).0, 0);
+        assert_eq!(request(&mut stream, 3, 0, 0, 0, &[]).0, 0);
+        let (_, read) = request(&mut stream, 0, 0, 2048, 8, &[]);
+        assert_eq!(read, vec![0, 0, 9, 9, 9, 9, 0, 0]);
+        let (_, read) = request(&mut stream, 0, 0, 4095, 514, &[]);
+        assert_eq!((read[0], read[1], read[512], read[513]), (0, 7, 7, 0));
+
+        // Requests past the end of the export, and trims, which were not
+        // advertised.
+        assert_eq!(request(&mut stream, 0, 0, EXPORT_SIZE - 10, 20, &[]).0, 22);
+        assert_eq!(request(&mut stream, 1, 0, EXPORT_SIZE, 4, &[1; 4]).0, 28);
+        assert_eq!(request(&mut stream, 4, 0, 0, 4096, &[]).0, 22);
+        assert_eq!(request(&mut stream, 9, 0, 0, 0, &[]).0, 22);
+
+        let mut disconnect = [0; 28];
+        BE::write_u32(&mut disconnect, 0x2560_9513);
+        BE::write_u16(&mut disconnect[6..], 2);
+        stream.write_all(&disconnect).unwrap();
+        assert_eq!(stream.read(&mut [0; 16]).unwrap(), 0);
+
+        // The old style of picking the export, which gets the export's
+        // details padded out with zeroes unless the client said otherwise.
+        let mut stream = connect(&address, 1);
+        send_option(&mut stream, 1, b"disk");
+        let mut export = [0; 10 + 124];
+        stream.read_exact(&mut export).unwrap();
+        assert_eq!(BE::read_u64(&export), EXPORT_SIZE);
+        assert_eq!(BE::read_u16(&export[8..]), EXPORT_FLAGS);
+        assert!(export[10..].iter().all(|&byte| byte == 0));
+        let (_, read) = request(&mut stream, 0, 0, 2048, 4, &[]);
+        assert_eq!(read, vec![0, 0, 9, 9]);
+        drop(stream);
+        assert_eq!(server.join().unwrap(), vec![Ok(true), Ok(true)]);
+    }
+
+    #[test]
+    fn test_nbd_protocol_errors() {
+        let (address, server) = spawn_server(6);
+        let go = |stream: &mut TcpStream| {
+            send_option(stream, 7, &info_request("disk", &[]));
+            assert_eq!(read_option_reply(stream, 7).0, 3);
+            assert_eq!(read_option_reply(stream, 7).0, 1);
+        };
+
+        // Client flags the server never offered end the handshake quietly.
+        let mut stream = connect(&address, 4);
+        assert_eq!(stream.read(&mut [0; 8]).unwrap(), 0);
+
+        // Options without the option magic, or too long to be reasonable.
+        let mut stream = connect(&address, 3);
+        stream.write_all(&[0; 16]).unwrap();
+        drop(stream);
+        let mut stream = connect(&address, 3);
+        let mut request = b"IHAVEOPT".to_vec();
+        request.extend_from_slice(&[0, 0, 0, 3, 0, 1, 0, 0]);
+        stream.write_all(&request).unwrap();
+        drop(stream);
+
+        // Requests without the request magic, writes too large to buffer, and
+        // a client that goes away partway through a write's data.
+        let mut stream = connect(&address, 3);
+        go(&mut stream);
+        stream.write_all(&[0; 28]).unwrap();
+        drop(stream);
+        let mut stream = connect(&address, 3);
+        go(&mut stream);
+        let mut write = [0; 28];
+        BE::write_u32(&mut write, 0x2560_9513);
+        BE::write_u16(&mut write[6..], 1);
+        BE::write_u32(&mut write[24..], 64 * 1024 * 1024);
+        stream.write_all(&write).unwrap();
+        drop(stream);
+        let mut stream = connect(&address, 3);
+        go(&mut stream);
+        BE::write_u32(&mut write[24..], 512);
+        stream.write_all(&write).unwrap();
+        stream.write_all(&[1; 100]).unwrap();
+        drop(stream);
+
+        assert_eq!(
+            server.join().unwrap(),
+            vec![
+                Ok(false),
+                Err(ErrorCause::ParseError),
+                Err(ErrorCause::TransferTooLargeError {
+                    actual: 0x0001_0000,
+                    max: 8 * 1024,
+                }),
+                Err(ErrorCause::ParseError),
+                Err(ErrorCause::TransferTooLargeError {
+                    actual: 64 * 1024 * 1024,
+                    max: 32 * 1024 * 1024,
+                }),
+                Err(ErrorCause::IoError {
+                    kind: std::io::ErrorKind::UnexpectedEof
+                }),
+            ]
+        );
+    }
+}
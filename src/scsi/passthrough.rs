@@ -0,0 +1,455 @@
+use std::collections::VecDeque;
+use std::vec::Vec;
+
+use crate::error::{ErrorCause, ScsiError, UsbTransferDirection};
+use crate::scsi::commands::{
+    command_block, Command, CommandBlockWrapper, CommandStatusWrapper, Direction,
+    RequestSenseResponse, ScsiStatus,
+};
+use crate::traits::{BufferPullable, BufferPushable};
+
+/// The opcode of REQUEST SENSE, which `BulkOnlyEmulation` answers itself.
+const REQUEST_SENSE_OPCODE: u8 = 0x03;
+
+/// The data buffer belonging to a command run through a `CommandExecutor`.
+#[derive(Debug)]
+pub enum CommandData<'a> {
+    /// The command transfers no data.
+    None,
+
+    /// The command reads data from the device into the buffer.
+    In(&'a mut [u8]),
+
+    /// The command writes the buffer's contents to the device.
+    Out(&'a [u8]),
+}
+
+/// The outcome of a command run through a `CommandExecutor`.
+#[derive(Clone, Copy, Eq, PartialEq, Debug)]
+pub struct CommandOutcome {
+    /// The SCSI status of the command.
+    pub status: ScsiStatus,
+
+    /// The sense data returned with a failed status, if any.
+    pub sense: Option<RequestSenseResponse>,
+
+    /// The number of expected bytes that were not transferred.
+    pub residual: u32,
+}
+
+/// A transport that runs bare command blocks rather than Bulk-Only wrappers,
+/// such as iSCSI or Linux's `SG_IO`.
+///
+/// These transports return sense data along with a failed status instead of
+/// leaving it for a separate REQUEST SENSE command.
+pub trait CommandExecutor {
+    /// Runs a raw command block, transferring data to or from `data`.
+    ///
+    /// The length of `data` is used as the command's expected transfer length.
+    fn execute(&mut self, cdb: &[u8], data: CommandData) -> Result<CommandOutcome, ScsiError>;
+
+    /// Runs one of this crate's command structs.
+    fn execute_command<C: Command>(
+        &mut self,
+        command: &C,
+        data: CommandData,
+    ) -> Result<CommandOutcome, ScsiError>
+    where
+        Self: Sized,
+    {
+        let (cdb, length) = command_block(command)?;
+        self.execute(&cdb[..length], data)
+    }
+}
+
+/// A Bulk-Only write whose data is still arriving through `out_transfer`.
+struct PendingWrite {
+    wrapper: CommandBlockWrapper,
+    cdb: [u8; 16],
+    data: Vec<u8>,
+}
+
+/// Lets a `CommandExecutor` act as the `CommunicationChannel` of a
+/// `ScsiBlockDevice`.
+///
+/// The CBWs written to the channel are run as bare commands, and the data and
+/// CSWs read back are generated from their outcomes. Since the executor hands
+/// back sense data along with a failed status, a REQUEST SENSE sent right
+/// after the failure is answered from that data without reaching the device.
+///
+/// CSWs only ever report `COMMAND_PASSED` or `COMMAND_FAILED`. Statuses that
+/// Bulk-Only has no way to report, such as RESERVATION CONFLICT, are kept
+/// for `scsi_status` instead, for the channel to pass on through
+/// `CommunicationChannel::scsi_status`.
+#[derive(Default)]
+pub(crate) struct BulkOnlyEmulation {
+    pending_write: Option<PendingWrite>,
+    output: VecDeque<u8>,
+    sense: Option<RequestSenseResponse>,
+    status: Option<ScsiStatus>,
+}
+
+impl BulkOnlyEmulation {
+    /// Takes a CBW, or the data of the write it started, running the command
+    /// on `executor` once everything it needs has arrived.
+    pub(crate) fn out_transfer<E: CommandExecutor>(
+        &mut self,
+        executor: &mut E,
+        bytes: &[u8],
+    ) -> Result<usize, ScsiError> {
+        if let Some(mut pending) = self.pending_write.take() {
+            pending.data.extend_from_slice(bytes);
+            if pending.data.len() < pending.wrapper.data_transfer_length as usize {
+                self.pending_write = Some(pending);
+            } else {
+                let cdb_length = usize::from(pending.wrapper.cb_length).min(16);
+                let cdb = &pending.cdb[..cdb_length];
+                self.run(executor, pending.wrapper, cdb, &pending.data)?;
+            }
+            return Ok(bytes.len());
+        }
+        if bytes.len() != 31 {
+            return Err(ScsiError::from_cause(ErrorCause::UsbTransferError {
+                direction: UsbTransferDirection::Out,
+            }));
+        }
+        let wrapper = CommandBlockWrapper::pull_from_buffer(bytes)?;
+        let cdb_length = usize::from(wrapper.cb_length).min(16);
+        let mut cdb = [0; 16];
+        cdb[..cdb_length].copy_from_slice(&bytes[15..15 + cdb_length]);
+        if wrapper.direction == Direction::OUT && wrapper.data_transfer_length > 0 {
+            self.pending_write = Some(PendingWrite {
+                wrapper,
+                cdb,
+                data: Vec::new(),
+            });
+        } else {
+            self.run(executor, wrapper, &cdb[..cdb_length], &[])?;
+        }
+        Ok(bytes.len())
+    }
+
+    /// The full status of the last command run.
+    pub(crate) fn scsi_status(&self) -> Option<ScsiStatus> {
+        self.status
+    }
+
+    /// Hands back the data and CSWs of the commands run so far.
+    pub(crate) fn in_transfer(&mut self, buffer: &mut [u8]) -> usize {
+        let length = buffer.len().min(self.output.len());
+        for (dst, src) in buffer.iter_mut().zip(self.output.drain(..length)) {
+            *dst = src;
+        }
+        length
+    }
+
+    fn run<E: CommandExecutor>(
+        &mut self,
+        executor: &mut E,
+        wrapper: CommandBlockWrapper,
+        cdb: &[u8],
+        data_out: &[u8],
+    ) -> Result<(), ScsiError> {
+        let expected = wrapper.data_transfer_length as usize;
+        let is_read = wrapper.direction == Direction::IN && expected > 0;
+        let outcome = match self.sense.take() {
+            Some(sense) if is_read && cdb[0] == REQUEST_SENSE_OPCODE => {
+                let mut data = [0; RequestSenseResponse::SIZE];
+                let length = sense.push_to_buffer(&mut data[..])?.min(expected);
+                self.output.extend(&data[..length]);
+                self.output.extend((length..expected).map(|_| 0));
+                CommandOutcome {
+                    status: ScsiStatus::Good,
+                    sense: None,
+                    residual: (expected - length) as u32,
+                }
+            }
+            _ if is_read => {
+                let mut data = vec![0; expected];
+                let outcome = executor.execute(cdb, CommandData::In(&mut data[..]))?;
+                // The data phase is always padded out to the length the host
+                // asked for, so it never mistakes the CSW for data.
+                self.output.extend(&data);
+                outcome
+            }
+            _ if expected > 0 => executor.execute(cdb, CommandData::Out(data_out))?,
+            _ => executor.execute(cdb, CommandData::None)?,
+        };
+        self.sense = outcome.sense;
+        self.status = Some(outcome.status);
+        let csw = CommandStatusWrapper {
+            tag: wrapper.tag,
+            data_residue: outcome.residual,
+            status: outcome.status.bulk_only_status(),
+        };
+        let mut buffer = [0; CommandStatusWrapper::SIZE as usize];
+        csw.push_to_buffer(&mut buffer[..])?;
+        self.output.extend(&buffer);
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{BulkOnlyEmulation, CommandData, CommandExecutor, CommandOutcome};
+    use crate::error::{ErrorCause, ScsiError};
+    use crate::scsi::commands::{
+        Command, CommandStatusWrapper, Read10Command, ReadCapacityResponse, RequestSenseCommand,
+        RequestSenseResponse, Reserve6Command, ScsiStatus, TestUnitReady, Write10Command,
+    };
+    use crate::scsi::ScsiBlockDevice;
+    use crate::traits::{BufferPullable, BufferPushable, CommunicationChannel};
+    use std::vec::Vec;
+
+    /// Records the commands it runs, failing any TEST UNIT READY and
+    /// reporting a reservation conflict for any RESERVE(6).
+    #[derive(Default)]
+    struct TestExecutor {
+        cdbs: Vec<Vec<u8>>,
+        written: Vec<u8>,
+    }
+
+    impl CommandExecutor for TestExecutor {
+        fn execute(&mut self, cdb: &[u8], data: CommandData) -> Result<CommandOutcome, ScsiError> {
+            self.cdbs.push(cdb.to_vec());
+            if let CommandData::Out(data) = data {
+                self.written.extend_from_slice(data);
+            }
+            let sense = RequestSenseResponse::new(RequestSenseResponse::NOT_READY, 0x04, 0x01);
+            Ok(match cdb[0] {
+                0x00 => CommandOutcome {
+                    status: ScsiStatus::CheckCondition,
+                    sense: Some(sense),
+                    residual: 0,
+                },
+                0x16 => CommandOutcome {
+                    status: ScsiStatus::ReservationConflict,
+                    sense: None,
+                    residual: 0,
+                },
+                _ => CommandOutcome {
+                    status: ScsiStatus::Good,
+                    sense: None,
+                    residual: 0,
+                },
+            })
+        }
+    }
+
+    /// Reads back 100 bytes of whatever is asked for, until it is unplugged.
+    struct UnpluggableExecutor {
+        connected: bool,
+    }
+
+    impl CommandExecutor for UnpluggableExecutor {
+        fn execute(&mut self, _: &[u8], data: CommandData) -> Result<CommandOutcome, ScsiError> {
+            if !self.connected {
+                return Err(ScsiError::from_cause(ErrorCause::IoError {
+                    kind: std::io::ErrorKind::NotFound,
+                }));
+            }
+            let residual = match data {
+                CommandData::In(buffer) => {
+                    let length = buffer.len().min(100);
+                    buffer[..length].fill(0xaa);
+                    buffer.len() - length
+                }
+                _ => 0,
+            };
+            Ok(CommandOutcome {
+                status: ScsiStatus::Good,
+                sense: None,
+                residual: residual as u32,
+            })
+        }
+    }
+
+    /// A disk of 16 blocks reserved by another initiator, which fails every
+    /// read, write and RESERVE(6) with a reservation conflict.
+    struct ReservedExecutor;
+
+    impl CommandExecutor for ReservedExecutor {
+        fn execute(&mut self, cdb: &[u8], data: CommandData) -> Result<CommandOutcome, ScsiError> {
+            let status = match (cdb[0], data) {
+                (0x25, CommandData::In(buffer)) => {
+                    let capacity = ReadCapacityResponse {
+                        logical_block_address: 15,
+                        block_length: 512,
+                    };
+                    capacity.push_to_buffer(buffer)?;
+                    ScsiStatus::Good
+                }
+                (0x16 | 0x28 | 0x2a, _) => ScsiStatus::ReservationConflict,
+                _ => ScsiStatus::Good,
+            };
+            Ok(CommandOutcome {
+                status,
+                sense: None,
+                residual: 0,
+            })
+        }
+    }
+
+    /// Runs a block device's commands on an executor.
+    struct ExecutorChannel<E: CommandExecutor> {
+        executor: E,
+        emulation: BulkOnlyEmulation,
+    }
+
+    impl<E: CommandExecutor> CommunicationChannel for ExecutorChannel<E> {
+        fn out_transfer<B: AsRef<[u8]>>(&mut self, bytes: B) -> Result<usize, ScsiError> {
+            self.emulation
+                .out_transfer(&mut self.executor, bytes.as_ref())
+        }
+
+        fn in_transfer<B: AsMut<[u8]>>(&mut self, mut buffer: B) -> Result<usize, ScsiError> {
+            Ok(self.emulation.in_transfer(buffer.as_mut()))
+        }
+
+        fn scsi_status(&self) -> Option<ScsiStatus> {
+            self.emulation.scsi_status()
+        }
+    }
+
+    fn read_csw(emulation: &mut BulkOnlyEmulation) -> CommandStatusWrapper {
+        let mut buffer = [0; CommandStatusWrapper::SIZE as usize];
+        assert_eq!(emulation.in_transfer(&mut buffer[..]), buffer.len());
+        CommandStatusWrapper::pull_from_buffer(&buffer[..]).unwrap()
+    }
+
+    #[test]
+    fn test_bulk_only_emulation() {
+        let mut executor = TestExecutor::default();
+        let mut emulation = BulkOnlyEmulation::default();
+        let mut buffer = [0; 31];
+
+        // Writes run once all of their data has arrived.
+        Write10Command::new(0, 1024, 512)
+            .unwrap()
+            .push_to_buffer(&mut buffer[..])
+            .unwrap();
+        emulation.out_transfer(&mut executor, &buffer).unwrap();
+        emulation.out_transfer(&mut executor, &[0xaa; 512]).unwrap();
+        assert!(executor.cdbs.is_empty());
+        emulation.out_transfer(&mut executor, &[0xbb; 512]).unwrap();
+        assert_eq!(executor.cdbs.len(), 1);
+        assert_eq!(executor.cdbs[0][0], 0x2a);
+        assert_eq!(executor.written.len(), 1024);
+        assert_eq!(read_csw(&mut emulation).status, 0);
+
+        // The sense data of a failure is kept for the next REQUEST SENSE.
+        TestUnitReady::new()
+            .push_to_buffer(&mut buffer[..])
+            .unwrap();
+        emulation.out_transfer(&mut executor, &buffer).unwrap();
+        assert_eq!(
+            read_csw(&mut emulation).status,
+            CommandStatusWrapper::COMMAND_FAILED
+        );
+        RequestSenseCommand::new(18)
+            .push_to_buffer(&mut buffer[..])
+            .unwrap();
+        emulation.out_transfer(&mut executor, &buffer).unwrap();
+        let mut data = [0; 18];
+        assert_eq!(emulation.in_transfer(&mut data[..]), 18);
+        let sense = RequestSenseResponse::pull_from_buffer(&data[..]).unwrap();
+        assert_eq!(sense.additional_sense_code, 0x04);
+        assert_eq!(read_csw(&mut emulation).status, 0);
+        assert_eq!(executor.cdbs.len(), 2);
+
+        // Statuses without a Bulk-Only equivalent fail the CSW, and are
+        // passed on beside it.
+        assert_eq!(emulation.scsi_status(), Some(ScsiStatus::Good));
+        Reserve6Command::new()
+            .push_to_buffer(&mut buffer[..])
+            .unwrap();
+        emulation.out_transfer(&mut executor, &buffer).unwrap();
+        let csw = read_csw(&mut emulation);
+        assert_eq!(csw.status, CommandStatusWrapper::COMMAND_FAILED);
+        assert_eq!(
+            emulation.scsi_status(),
+            Some(ScsiStatus::ReservationConflict)
+        );
+        assert_eq!(emulation.in_transfer(&mut data[..]), 0);
+
+        assert!(emulation.out_transfer(&mut executor, &[0; 13]).is_err());
+    }
+
+    #[test]
+    fn test_bulk_only_emulation_errors() {
+        let mut executor = UnpluggableExecutor { connected: true };
+        let mut emulation = BulkOnlyEmulation::default();
+        let mut buffer = [0; 31];
+
+        // A short read is padded out, with the shortfall in the residue, and
+        // can be taken in pieces.
+        let read = Read10Command::new(0, 512, 512).unwrap();
+        read.push_to_buffer(&mut buffer[..]).unwrap();
+        emulation.out_transfer(&mut executor, &buffer).unwrap();
+        let mut data = [0; 512];
+        assert_eq!(emulation.in_transfer(&mut data[..64]), 64);
+        assert_eq!(emulation.in_transfer(&mut data[64..]), 448);
+        assert!(data[..100].iter().all(|&byte| byte == 0xaa));
+        assert!(data[100..].iter().all(|&byte| byte == 0));
+        let csw = read_csw(&mut emulation);
+        assert_eq!((csw.tag, csw.data_residue), (read.wrapper().tag, 412));
+
+        // Malformed CBWs are rejected without running anything.
+        let err = emulation.out_transfer(&mut executor, &buffer[..30]);
+        assert!(matches!(
+            err.unwrap_err().cause,
+            ErrorCause::UsbTransferError { .. }
+        ));
+        buffer[0] = 0;
+        let err = emulation.out_transfer(&mut executor, &buffer).unwrap_err();
+        assert!(matches!(err.cause, ErrorCause::FlagError { .. }));
+        assert_eq!(emulation.in_transfer(&mut data[..]), 0);
+
+        // A device that goes away mid-transfer fails the command, leaving
+        // nothing queued and no write half received.
+        let write = Write10Command::new(0, 512, 512).unwrap();
+        write.push_to_buffer(&mut buffer[..]).unwrap();
+        emulation.out_transfer(&mut executor, &buffer).unwrap();
+        emulation.out_transfer(&mut executor, &data[..256]).unwrap();
+        executor.connected = false;
+        let err = emulation
+            .out_transfer(&mut executor, &data[256..])
+            .unwrap_err();
+        assert_eq!(
+            err.cause,
+            ErrorCause::IoError {
+                kind: std::io::ErrorKind::NotFound
+            }
+        );
+        assert_eq!(emulation.in_transfer(&mut data[..]), 0);
+
+        executor.connected = true;
+        let unit_ready = TestUnitReady::new();
+        unit_ready.push_to_buffer(&mut buffer[..]).unwrap();
+        emulation.out_transfer(&mut executor, &buffer).unwrap();
+        assert_eq!(read_csw(&mut emulation).tag, unit_ready.wrapper().tag);
+    }
+
+    #[test]
+    fn test_block_device_reservation_conflict() {
+        let channel = ExecutorChannel {
+            executor: ReservedExecutor,
+            emulation: BulkOnlyEmulation::default(),
+        };
+        let mut scratch = [0; 64];
+        let mut device = ScsiBlockDevice::new(channel, &mut scratch[..]).unwrap();
+        assert_eq!(device.num_blocks(), 16);
+
+        let mut buffer = [0; 512];
+        let err = device.read_blocks(0, 1, &mut buffer[..]).unwrap_err();
+        assert_eq!(err.cause, ErrorCause::ReservationConflictError);
+        let err = device.write_blocks(0, 1, &buffer[..]).unwrap_err();
+        assert_eq!(err.cause, ErrorCause::ReservationConflictError);
+        let err = device.reserve_unit().unwrap_err();
+        assert_eq!(err.cause, ErrorCause::ReservationConflictError);
+
+        // The channel stays in step with the device after each conflict.
+        device.release_unit().unwrap();
+        device.synchronize_cache().unwrap();
+    }
+}
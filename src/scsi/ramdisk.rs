@@ -0,0 +1,702 @@
+use crate::scsi::commands::{
+    BlockLimitsPage, CommandStatusWrapper, CompareAndWriteCommand, FormatParameters,
+    FormatUnitCommand, InquiryCommand, InquiryIdentification, InquiryResponse, OverwriteParameters,
+    PersistentReserveInCommand, PersistentReserveInResponse, PersistentReserveOutCommand,
+    PersistentReserveOutParameters, Read10Command, ReadCapacityCommand, ReadCapacityResponse,
+    Release6Command, RequestSenseCommand, RequestSenseResponse, Reserve6Command, SanitizeCommand,
+    SanitizeServiceAction, TestUnitReady, UnmapBlockDescriptor, UnmapBlockDescriptors,
+    UnmapCommand, Verify16Command, VerifyByteCheck, VpdInquiryCommand, Write10Command,
+    WriteSame16Command,
+};
+use crate::scsi::{InitiatorId, MediumAccess, PersistentReservations, ScsiResponder};
+use crate::ScsiError;
+
+const RAM_DISK_BLOCK_SIZE: usize = 512;
+
+/// The number of initiators that can register with a `RamDiskResponder` at
+/// once.
+const RAM_DISK_REGISTRATIONS: usize = 8;
+
+/// The block buffer used by a `RamDiskResponder`, sized to a single 512 byte
+/// block.
+pub struct RamDiskBlock(pub [u8; RAM_DISK_BLOCK_SIZE]);
+
+impl AsRef<[u8]> for RamDiskBlock {
+    fn as_ref(&self) -> &[u8] {
+        &self.0
+    }
+}
+
+impl AsMut<[u8]> for RamDiskBlock {
+    fn as_mut(&mut self) -> &mut [u8] {
+        &mut self.0
+    }
+}
+
+/// A `ScsiResponder` that serves a region of memory, such as a static array
+/// or a `Vec<u8>`, as a direct-access block device with 512 byte blocks.
+///
+/// Besides reads and writes, the responder implements the commands that
+/// erase or check its contents: UNMAP, WRITE SAME, VERIFY, COMPARE AND WRITE,
+/// FORMAT UNIT and SANITIZE. Compare and writes are limited to a single
+/// block, as reported in its Block Limits page. Every command finishes before
+/// its status is reported, so formats and sanitizes never report progress,
+/// even when the host asks for them to run in the background. Any bytes past
+/// the last whole block are left untouched.
+///
+/// Persistent reservations are kept in a `PersistentReservations` with room
+/// for 8 registered initiators, so a disk shared over a transport such as
+/// iSCSI fences off the initiators that do not hold its reservation. The
+/// whole-unit reservations of RESERVE(6) and RELEASE(6) are kept there too.
+pub struct RamDiskResponder<S: AsRef<[u8]> + AsMut<[u8]>> {
+    storage: S,
+    sense: RequestSenseResponse,
+    read_status: Option<CommandStatusWrapper>,
+    read_cursor: u32,
+    read_remaining: u16,
+    write_status: Option<CommandStatusWrapper>,
+    write_cursor: u32,
+    write_remaining: u16,
+    reservations: PersistentReservations<RAM_DISK_REGISTRATIONS>,
+    initiator: InitiatorId,
+}
+
+impl<S: AsRef<[u8]> + AsMut<[u8]>> RamDiskResponder<S> {
+    /// The size of a single block in bytes.
+    pub const BLOCK_SIZE: u32 = RAM_DISK_BLOCK_SIZE as u32;
+
+    /// Constructs a new `RamDiskResponder` serving `storage`.
+    pub fn new(storage: S) -> RamDiskResponder<S> {
+        RamDiskResponder {
+            storage,
+            sense: RequestSenseResponse::default(),
+            read_status: None,
+            read_cursor: 0,
+            read_remaining: 0,
+            write_status: None,
+            write_cursor: 0,
+            write_remaining: 0,
+            reservations: PersistentReservations::new(),
+            initiator: InitiatorId::default(),
+        }
+    }
+
+    /// A reference to the memory being served.
+    pub fn storage(&self) -> &S {
+        &self.storage
+    }
+
+    /// Consumes the responder, returning the memory it was serving.
+    pub fn into_inner(self) -> S {
+        self.storage
+    }
+
+    /// The number of whole blocks in the served memory.
+    pub fn block_count(&self) -> u64 {
+        (self.storage.as_ref().len() / RAM_DISK_BLOCK_SIZE) as u64
+    }
+
+    fn fail(&mut self, sense_key: u8, asc: u8, ascq: u8) -> CommandStatusWrapper {
+        self.sense = RequestSenseResponse::new(sense_key, asc, ascq);
+        CommandStatusWrapper {
+            status: CommandStatusWrapper::COMMAND_FAILED,
+            ..Default::default()
+        }
+    }
+
+    fn pass(&mut self) -> CommandStatusWrapper {
+        self.sense = RequestSenseResponse::default();
+        CommandStatusWrapper::default()
+    }
+
+    /// Checks that the `count` blocks starting at `lba` are on the disk,
+    /// returning the failing CSW if not.
+    fn check_range(&mut self, lba: u64, count: u64) -> Option<CommandStatusWrapper> {
+        match lba.checked_add(count) {
+            Some(end) if end <= self.block_count() => None,
+            // Logical block address out of range.
+            _ => Some(self.fail(RequestSenseResponse::ILLEGAL_REQUEST, 0x21, 0x00)),
+        }
+    }
+
+    /// The bytes backing the `count` blocks starting at `lba`, which must
+    /// already have passed `check_range`.
+    fn blocks_mut(&mut self, lba: u64, count: u64) -> &mut [u8] {
+        let start = lba as usize * RAM_DISK_BLOCK_SIZE;
+        let end = start + count as usize * RAM_DISK_BLOCK_SIZE;
+        &mut self.storage.as_mut()[start..end]
+    }
+
+    /// Fails a command whose comparison found a difference at `offset`.
+    fn miscompare(&mut self, offset: usize) -> CommandStatusWrapper {
+        let csw = self.fail(RequestSenseResponse::MISCOMPARE, 0x1d, 0x00);
+        self.sense.valid = true;
+        self.sense.information = offset as u32;
+        csw
+    }
+
+    fn disk_mut(&mut self) -> &mut [u8] {
+        let blocks = self.block_count();
+        self.blocks_mut(0, blocks)
+    }
+
+    fn overwrite(&mut self, parameters: &OverwriteParameters<'_>) -> CommandStatusWrapper {
+        if parameters.overwrite_count == 0 {
+            // Invalid field in parameter list.
+            return self.fail(RequestSenseResponse::ILLEGAL_REQUEST, 0x26, 0x00);
+        }
+        for pass in 0..parameters.overwrite_count {
+            let mask = if parameters.invert && pass % 2 == 1 {
+                0xff
+            } else {
+                0
+            };
+            for block in self.disk_mut().chunks_mut(RAM_DISK_BLOCK_SIZE) {
+                for (byte, pattern) in block.iter_mut().zip(parameters.pattern.iter().cycle()) {
+                    *byte = pattern ^ mask;
+                }
+            }
+        }
+        self.pass()
+    }
+}
+
+impl<S: AsRef<[u8]> + AsMut<[u8]>> ScsiResponder for RamDiskResponder<S> {
+    type BlockType = RamDiskBlock;
+
+    fn read_capacity(
+        &mut self,
+        _command: ReadCapacityCommand,
+    ) -> Result<(ReadCapacityResponse, CommandStatusWrapper), ScsiError> {
+        let last = self.block_count().saturating_sub(1);
+        let response = ReadCapacityResponse {
+            logical_block_address: last.min(u64::from(u32::MAX)) as u32,
+            block_length: Self::BLOCK_SIZE,
+        };
+        Ok((response, self.pass()))
+    }
+
+    fn inquiry(
+        &mut self,
+        _command: InquiryCommand,
+    ) -> Result<(InquiryResponse, CommandStatusWrapper), ScsiError> {
+        let response = InquiryResponse {
+            device_qualifier: 0,
+            device_type: 0x00,
+            removable_flags: 0,
+            spc_version: 0x05,
+            response_format: 0x02,
+        };
+        Ok((response, CommandStatusWrapper::default()))
+    }
+
+    fn identification(&mut self) -> InquiryIdentification {
+        InquiryIdentification {
+            vendor_identification: *b"SCSI-RS ",
+            product_identification: *b"Virtual RAM Disk",
+            product_revision_level: *b"0.2 ",
+        }
+    }
+
+    fn request_sense(
+        &mut self,
+        _command: RequestSenseCommand,
+    ) -> Result<CommandStatusWrapper, ScsiError> {
+        Ok(CommandStatusWrapper::default())
+    }
+
+    fn sense_data(
+        &mut self,
+        _command: RequestSenseCommand,
+    ) -> Result<(RequestSenseResponse, CommandStatusWrapper), ScsiError> {
+        let sense = core::mem::take(&mut self.sense);
+        Ok((sense, CommandStatusWrapper::default()))
+    }
+
+    fn command_rejected(&mut self, sense: RequestSenseResponse) {
+        self.sense = sense;
+    }
+
+    fn test_unit_ready(
+        &mut self,
+        _command: TestUnitReady,
+    ) -> Result<CommandStatusWrapper, ScsiError> {
+        Ok(self.pass())
+    }
+
+    fn read10_start(&mut self, command: Read10Command) -> Result<(), ScsiError> {
+        self.read_remaining = 0;
+        self.read_status = self.check_range(
+            u64::from(command.block_address),
+            u64::from(command.transfer_blocks),
+        );
+        if self.read_status.is_none() {
+            self.read_cursor = command.block_address;
+            self.read_remaining = command.transfer_blocks;
+        }
+        Ok(())
+    }
+
+    fn read_block(&mut self, buffer: &mut [u8]) -> Result<Option<CommandStatusWrapper>, ScsiError> {
+        if let Some(csw) = self.read_status.take() {
+            return Ok(Some(csw));
+        }
+        if self.read_remaining == 0 {
+            return Ok(Some(self.pass()));
+        }
+        let block = self.blocks_mut(u64::from(self.read_cursor), 1);
+        buffer.copy_from_slice(block);
+        self.read_cursor += 1;
+        self.read_remaining -= 1;
+        Ok(None)
+    }
+
+    fn write10_start(&mut self, command: Write10Command) -> Result<(), ScsiError> {
+        self.write_remaining = 0;
+        self.write_status = self.check_range(
+            u64::from(command.block_address),
+            u64::from(command.transfer_blocks),
+        );
+        if self.write_status.is_none() {
+            self.write_cursor = command.block_address;
+            self.write_remaining = command.transfer_blocks;
+        }
+        Ok(())
+    }
+
+    fn write_block(&mut self, buffer: &[u8]) -> Result<Option<CommandStatusWrapper>, ScsiError> {
+        if let Some(csw) = self.write_status.take() {
+            return Ok(Some(csw));
+        }
+        if self.write_remaining == 0 {
+            // The host sent more data than the command asked for.
+            return Ok(Some(self.fail(
+                RequestSenseResponse::ILLEGAL_REQUEST,
+                0x24,
+                0x00,
+            )));
+        }
+        let block = self.blocks_mut(u64::from(self.write_cursor), 1);
+        block[..buffer.len()].copy_from_slice(buffer);
+        self.write_cursor += 1;
+        self.write_remaining -= 1;
+        Ok(None)
+    }
+
+    fn write_end(&mut self) -> Result<CommandStatusWrapper, ScsiError> {
+        Ok(self.write_status.take().unwrap_or_else(|| self.pass()))
+    }
+
+    fn memory_buffer(&mut self) -> Self::BlockType {
+        RamDiskBlock([0; RAM_DISK_BLOCK_SIZE])
+    }
+
+    fn block_limits(
+        &mut self,
+        _command: VpdInquiryCommand,
+    ) -> Result<(BlockLimitsPage, CommandStatusWrapper), ScsiError> {
+        let descriptors =
+            (RAM_DISK_BLOCK_SIZE - UnmapCommand::HEADER_SIZE) / UnmapBlockDescriptor::SIZE;
+        let page = BlockLimitsPage {
+            max_compare_and_write_length: 1,
+            max_unmap_lba_count: u32::MAX,
+            max_unmap_block_descriptor_count: descriptors as u32,
+            ..Default::default()
+        };
+        Ok((page, self.pass()))
+    }
+
+    fn unmap(
+        &mut self,
+        _command: UnmapCommand,
+        descriptors: UnmapBlockDescriptors<'_>,
+    ) -> Result<CommandStatusWrapper, ScsiError> {
+        for descriptor in descriptors.clone() {
+            let count = u64::from(descriptor.block_count);
+            if let Some(csw) = self.check_range(descriptor.block_address, count) {
+                return Ok(csw);
+            }
+        }
+        for descriptor in descriptors {
+            let count = u64::from(descriptor.block_count);
+            self.blocks_mut(descriptor.block_address, count).fill(0);
+        }
+        Ok(self.pass())
+    }
+
+    fn write_same(
+        &mut self,
+        command: WriteSame16Command,
+        block: &[u8],
+    ) -> Result<CommandStatusWrapper, ScsiError> {
+        let count = u64::from(command.transfer_blocks);
+        if let Some(csw) = self.check_range(command.block_address, count) {
+            return Ok(csw);
+        }
+        if command.no_data_out || block.is_empty() {
+            self.blocks_mut(command.block_address, count).fill(0);
+            return Ok(self.pass());
+        }
+        if block.len() != RAM_DISK_BLOCK_SIZE {
+            // Invalid field in CDB; the host assumed a different block size.
+            return Ok(self.fail(RequestSenseResponse::ILLEGAL_REQUEST, 0x24, 0x00));
+        }
+        for target in self
+            .blocks_mut(command.block_address, count)
+            .chunks_mut(RAM_DISK_BLOCK_SIZE)
+        {
+            target.copy_from_slice(block);
+        }
+        Ok(self.pass())
+    }
+
+    fn compare_and_write(
+        &mut self,
+        command: CompareAndWriteCommand,
+        expected: &[u8],
+        new: &[u8],
+    ) -> Result<CommandStatusWrapper, ScsiError> {
+        let count = u64::from(command.transfer_blocks);
+        if let Some(csw) = self.check_range(command.block_address, count) {
+            return Ok(csw);
+        }
+        let length = count as usize * RAM_DISK_BLOCK_SIZE;
+        if expected.len() != length || new.len() != length {
+            // Invalid field in CDB; the host assumed a different block size.
+            return Ok(self.fail(RequestSenseResponse::ILLEGAL_REQUEST, 0x24, 0x00));
+        }
+        let stored = self.blocks_mut(command.block_address, count);
+        if let Some(offset) = stored.iter().zip(expected).position(|(a, b)| a != b) {
+            return Ok(self.miscompare(offset));
+        }
+        stored.copy_from_slice(new);
+        Ok(self.pass())
+    }
+
+    fn verify(
+        &mut self,
+        command: Verify16Command,
+        block_address: u64,
+        data: &[u8],
+    ) -> Result<Option<CommandStatusWrapper>, ScsiError> {
+        let count = u64::from(command.transfer_blocks);
+        if let Some(csw) = self.check_range(command.block_address, count) {
+            return Ok(Some(csw));
+        }
+        if data.is_empty() {
+            // Memory can always be read back, so only comparisons can fail.
+            return Ok(Some(self.pass()));
+        }
+        let stored = self.blocks_mut(block_address, 1);
+        let Some(offset) = stored.iter().zip(data).position(|(a, b)| a != b) else {
+            return Ok(None);
+        };
+        let offset = match command.byte_check {
+            VerifyByteCheck::Compare => {
+                (block_address - command.block_address) as usize * RAM_DISK_BLOCK_SIZE + offset
+            }
+            _ => offset,
+        };
+        Ok(Some(self.miscompare(offset)))
+    }
+
+    fn format_unit(
+        &mut self,
+        _command: FormatUnitCommand,
+        _parameters: Option<FormatParameters>,
+    ) -> Result<CommandStatusWrapper, ScsiError> {
+        self.disk_mut().fill(0);
+        Ok(self.pass())
+    }
+
+    fn sanitize(
+        &mut self,
+        command: SanitizeCommand,
+        parameters: Option<OverwriteParameters<'_>>,
+    ) -> Result<CommandStatusWrapper, ScsiError> {
+        Ok(match command.service_action {
+            SanitizeServiceAction::Overwrite => match parameters {
+                Some(parameters) => self.overwrite(&parameters),
+                // Parameter list length error.
+                None => self.fail(RequestSenseResponse::ILLEGAL_REQUEST, 0x1a, 0x00),
+            },
+            // Memory has no keys to change, so a cryptographic erase zeroes
+            // it just like a block erase.
+            SanitizeServiceAction::BlockErase | SanitizeServiceAction::CryptographicErase => {
+                self.disk_mut().fill(0);
+                self.pass()
+            }
+            // A sanitize of memory cannot fail, so there is never a failure
+            // mode to leave.
+            SanitizeServiceAction::ExitFailureMode => self.pass(),
+        })
+    }
+
+    fn persistent_reserve_in(
+        &mut self,
+        command: PersistentReserveInCommand,
+    ) -> Result<(PersistentReserveInResponse, CommandStatusWrapper), ScsiError> {
+        let response = self.reservations.persistent_reserve_in(command)?;
+        Ok((response, self.pass()))
+    }
+
+    fn persistent_reserve_out(
+        &mut self,
+        command: PersistentReserveOutCommand,
+        parameters: PersistentReserveOutParameters,
+    ) -> Result<CommandStatusWrapper, ScsiError> {
+        let sense =
+            self.reservations
+                .persistent_reserve_out(self.initiator, command, parameters)?;
+        Ok(match sense {
+            Some(sense) => self.fail(
+                sense.sense_key,
+                sense.additional_sense_code,
+                sense.additional_sense_code_qualifier,
+            ),
+            None => self.pass(),
+        })
+    }
+
+    fn reserve6(&mut self, _command: Reserve6Command) -> Result<CommandStatusWrapper, ScsiError> {
+        self.reservations.reserve_unit(self.initiator)?;
+        Ok(self.pass())
+    }
+
+    fn release6(&mut self, _command: Release6Command) -> Result<CommandStatusWrapper, ScsiError> {
+        self.reservations.release_unit(self.initiator);
+        Ok(self.pass())
+    }
+
+    fn set_initiator(&mut self, initiator: InitiatorId) {
+        self.initiator = initiator;
+    }
+
+    fn check_access(&mut self, access: MediumAccess) -> Result<(), ScsiError> {
+        self.reservations.check_access(self.initiator, access)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::RamDiskResponder;
+    use crate::scsi::commands::{
+        CommandStatusWrapper, OverwriteParameters, Read10Command, Read16Command,
+        ReadCapacity16Command, RequestSenseCommand, RequestSenseResponse, Reservation,
+        ReservationType, SanitizeCommand, SanitizeServiceAction,
+    };
+    use crate::scsi::responder::tests::LoopbackChannel;
+    use crate::scsi::{InitiatorId, ScsiBlockDevice, ScsiResponder};
+    use crate::{ErrorCause, ScsiError};
+    use std::vec;
+    use std::vec::Vec;
+
+    fn device(blocks: usize) -> ScsiBlockDevice<LoopbackChannel<RamDiskResponder<Vec<u8>>>> {
+        let responder = RamDiskResponder::new(vec![0; 512 * blocks]);
+        let mut scratch = [0; 64];
+        ScsiBlockDevice::new(LoopbackChannel::new(responder), &mut scratch).unwrap()
+    }
+
+    #[test]
+    fn test_ramdisk_io() {
+        let mut dev = device(16);
+        assert_eq!(dev.block_size(), 512);
+
+        let data: Vec<u8> = (0..1024).map(|idx| (idx % 251) as u8).collect();
+        dev.write_blocks(3, 2, &data[..]).unwrap();
+        let mut read = vec![0; 1024];
+        dev.read_blocks(3, 2, &mut read[..]).unwrap();
+        assert_eq!(read, data);
+
+        dev.verify(3, 2, Some(&data)).unwrap();
+        let mut different = data.clone();
+        different[700] ^= 1;
+        assert!(dev.verify(3, 2, Some(&different)).is_err());
+        let sense = dev.request_sense().unwrap();
+        assert_eq!(sense.sense_key, RequestSenseResponse::MISCOMPARE);
+        assert_eq!(sense.information, 700);
+
+        dev.discard(3, 1).unwrap();
+        dev.read_blocks(3, 2, &mut read[..]).unwrap();
+        assert_eq!(&read[..512], &[0; 512][..]);
+        assert_eq!(&read[512..], &data[512..]);
+
+        let limits = dev.read_block_limits().unwrap();
+        assert_eq!(limits.max_compare_and_write_length, 1);
+        let lock: Vec<u8> = (0..512).map(|idx| (idx % 7) as u8).collect();
+        dev.compare_and_write(8, &[0; 512], &lock).unwrap();
+        let err = dev.compare_and_write(8, &[0; 512], &lock).unwrap_err();
+        assert_eq!(err.cause, ErrorCause::MiscompareError { offset: Some(1) });
+        let err = dev
+            .compare_and_write(8, &[0; 1024], &[0; 1024])
+            .unwrap_err();
+        assert_eq!(
+            err.cause,
+            ErrorCause::TransferTooLargeError { actual: 2, max: 1 }
+        );
+        dev.read_blocks(8, 1, &mut read[..512]).unwrap();
+        assert_eq!(&read[..512], &lock[..]);
+
+        // Block 16 is past the end of the disk.
+        let mut responder = RamDiskResponder::new(vec![0; 512 * 16]);
+        responder
+            .read10_start(Read10Command::new(15 * 512, 1024, 512).unwrap())
+            .unwrap();
+        let mut block = responder.memory_buffer();
+        let csw = responder.read_block(block.as_mut()).unwrap().unwrap();
+        assert_eq!(csw.status, CommandStatusWrapper::COMMAND_FAILED);
+        let (sense, _) = responder.sense_data(RequestSenseCommand::new(18)).unwrap();
+        assert_eq!(sense.additional_sense_code, 0x21);
+
+        // The 16 byte commands fall back on the 10 byte ones while they fit.
+        let (capacity, _) = responder
+            .read_capacity16(ReadCapacity16Command::new())
+            .unwrap();
+        assert_eq!(
+            (capacity.logical_block_address, capacity.block_length),
+            (15, 512)
+        );
+        responder
+            .read16_start(Read16Command::new(2, 1, 512))
+            .unwrap();
+        assert_eq!(responder.read_block(block.as_mut()).unwrap(), None);
+        let err = responder
+            .read16_start(Read16Command::new(0x1_0000_0000, 1, 512))
+            .unwrap_err();
+        assert_eq!(err.cause, ErrorCause::UnsupportedOperationError);
+    }
+
+    #[test]
+    fn test_ramdisk_sanitize() {
+        let mut dev = device(4);
+        let parameters = OverwriteParameters {
+            invert: true,
+            overwrite_count: 2,
+            pattern: &[0x0f, 0xa5, 0x3c],
+        };
+        let mut command = SanitizeCommand::overwrite(&parameters);
+        command.immediate = true;
+        dev.sanitize(command, Some(&parameters)).unwrap();
+        assert_eq!(dev.sanitize_progress().unwrap(), None);
+        let storage = dev.comm_channel.responder.storage();
+        assert_eq!(&storage[..4], &[0xf0, 0x5a, 0xc3, 0xf0]);
+        // The pattern restarts at the start of every block.
+        assert_eq!(&storage[511..514], &[0x5a, 0xf0, 0x5a]);
+
+        // Overwrites need at least one pass.
+        let none = OverwriteParameters {
+            overwrite_count: 0,
+            ..OverwriteParameters::new(&[0xee])
+        };
+        dev.sanitize(SanitizeCommand::overwrite(&none), Some(&none))
+            .unwrap_err();
+        let sense = dev.request_sense().unwrap();
+        assert_eq!(sense.additional_sense_code, 0x26);
+
+        for action in [
+            SanitizeServiceAction::BlockErase,
+            SanitizeServiceAction::CryptographicErase,
+        ] {
+            dev.write_blocks(1, 1, &[0xff; 512][..]).unwrap();
+            dev.sanitize(SanitizeCommand::new(action), None).unwrap();
+            let storage = dev.comm_channel.responder.storage();
+            assert!(storage.iter().all(|&byte| byte == 0));
+        }
+        let exit = SanitizeCommand::new(SanitizeServiceAction::ExitFailureMode);
+        dev.sanitize(exit, None).unwrap();
+
+        // Longer patterns are rejected before anything is sent to the device.
+        let huge = OverwriteParameters::new(&[0xee; 1024]);
+        let err = dev.sanitize(SanitizeCommand::overwrite(&huge), Some(&huge));
+        assert_eq!(
+            err.unwrap_err(),
+            ScsiError::from_cause(ErrorCause::TransferTooLargeError {
+                actual: 1024,
+                max: 512
+            })
+        );
+        assert_eq!(dev.comm_channel.responder.block_count(), 4);
+    }
+
+    #[test]
+    fn test_ramdisk_reservations() {
+        let mut dev = device(4);
+        let other = InitiatorId::from_name(b"iqn.2019-01.rs.scsi:other");
+        let capabilities = dev.report_reservation_capabilities().unwrap();
+        assert!(capabilities.supports(ReservationType::ExclusiveAccess));
+
+        dev.register(0, 0xa).unwrap();
+        dev.reserve(0xa, ReservationType::ExclusiveAccess).unwrap();
+        dev.write_blocks(0, 1, &[0x11; 512][..]).unwrap();
+        let reservation = dev.read_reservation().unwrap();
+        assert_eq!(reservation.generation, 1);
+        assert_eq!(
+            reservation.reservation,
+            Some(Reservation {
+                key: 0xa,
+                reservation_type: ReservationType::ExclusiveAccess,
+            })
+        );
+
+        // Bulk-Only can only report the conflict as a failure.
+        dev.comm_channel.responder.set_initiator(other);
+        let err = dev.write_blocks(0, 1, &[0x22; 512][..]).unwrap_err();
+        assert_eq!(err.cause, ErrorCause::FlagError { flags: 1 });
+        assert!(dev.verify(0, 1, None).is_err());
+        assert!(dev.reserve(0xb, ReservationType::WriteExclusive).is_err());
+        dev.register(0, 0xb).unwrap();
+        assert_eq!(dev.read_reservation_keys().unwrap().keys(), &[0xa, 0xb]);
+        dev.preempt(0xb, 0xa, Some(ReservationType::WriteExclusive), true)
+            .unwrap();
+        let mut read = vec![0; 512];
+        dev.read_blocks(0, 1, &mut read[..]).unwrap();
+        assert_eq!(read, vec![0x11; 512]);
+
+        // The preempted initiator lost its registration along with its
+        // reservation, and can only read now.
+        dev.comm_channel
+            .responder
+            .set_initiator(InitiatorId::default());
+        assert_eq!(dev.read_reservation_keys().unwrap().keys(), &[0xb]);
+        dev.read_blocks(0, 1, &mut read[..]).unwrap();
+        assert!(dev.write_blocks(0, 1, &[0x22; 512][..]).is_err());
+        assert!(dev.release(0xa, ReservationType::WriteExclusive).is_err());
+
+        dev.comm_channel.responder.set_initiator(other);
+        dev.release(0xb, ReservationType::WriteExclusive).unwrap();
+        dev.clear_reservations(0xb).unwrap();
+        let reservation = dev.read_reservation().unwrap();
+        assert_eq!(reservation.reservation, None);
+        assert_eq!(reservation.generation, 4);
+        assert!(dev.read_reservation_keys().unwrap().keys().is_empty());
+    }
+
+    #[test]
+    fn test_ramdisk_unit_reservation() {
+        let mut dev = device(4);
+        let other = InitiatorId::from_name(b"iqn.2019-01.rs.scsi:other");
+        dev.reserve_unit().unwrap();
+        dev.write_blocks(0, 1, &[0x11; 512][..]).unwrap();
+
+        dev.comm_channel.responder.set_initiator(other);
+        let mut read = vec![0; 512];
+        let err = dev.write_blocks(0, 1, &[0x22; 512][..]).unwrap_err();
+        assert_eq!(err.cause, ErrorCause::FlagError { flags: 1 });
+        assert!(dev.reserve_unit().is_err());
+        assert!(dev.register(0, 0xb).is_err());
+        // Releasing someone else's reservation does nothing.
+        dev.release_unit().unwrap();
+        assert!(dev.write_blocks(0, 1, &[0x22; 512][..]).is_err());
+
+        dev.comm_channel
+            .responder
+            .set_initiator(InitiatorId::default());
+        dev.release_unit().unwrap();
+        dev.comm_channel.responder.set_initiator(other);
+        dev.read_blocks(0, 1, &mut read[..]).unwrap();
+        assert_eq!(read, vec![0x11; 512]);
+        // Registered initiators keep the unit from being reserved.
+        dev.register(0, 0xb).unwrap();
+        assert!(dev.reserve_unit().is_err());
+    }
+}
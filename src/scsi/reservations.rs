@@ -0,0 +1,619 @@
+use crate::scsi::commands::{
+    PersistentReserveInCommand, PersistentReserveInResponse, PersistentReserveInServiceAction,
+    PersistentReserveOutCommand, PersistentReserveOutParameters, PersistentReserveOutServiceAction,
+    RequestSenseResponse, Reservation, ReservationCapabilitiesResponse, ReservationKeysResponse,
+    ReservationResponse, ReservationType,
+};
+use crate::{ErrorCause, ScsiError};
+
+/// Identifies the initiator that sent a command, so that a responder shared
+/// by several of them can tell their reservations apart.
+///
+/// Transports that know who they are talking to pass it to the responder
+/// through `ScsiResponder::set_initiator` before every command.
+#[derive(Clone, Copy, Eq, PartialEq, Hash, Debug, Default)]
+pub struct InitiatorId(pub u64);
+
+impl InitiatorId {
+    /// Derives an identifier from an initiator's name, such as its iSCSI
+    /// name, by hashing it.
+    pub fn from_name(name: &[u8]) -> InitiatorId {
+        // 64 bit FNV-1a.
+        let mut hash: u64 = 0xcbf2_9ce4_8422_2325;
+        for byte in name {
+            hash ^= u64::from(*byte);
+            hash = hash.wrapping_mul(0x0100_0000_01b3);
+        }
+        InitiatorId(hash)
+    }
+}
+
+/// How a command accesses the medium, which decides whether a reservation
+/// held by another initiator lets it run.
+#[derive(Clone, Copy, Eq, PartialEq, Debug)]
+pub enum MediumAccess {
+    /// The command does not touch the medium, such as INQUIRY.
+    None,
+
+    /// The command reads from the medium.
+    Read,
+
+    /// The command changes the medium.
+    Write,
+}
+
+/// A single initiator's registration.
+#[derive(Clone, Copy, Eq, PartialEq, Debug)]
+struct Registration {
+    initiator: InitiatorId,
+    key: u64,
+}
+
+/// The persistent reservation state of a logical unit, with room for `N`
+/// registered initiators.
+///
+/// A `ScsiResponder` shared by several initiators can embed this to answer
+/// PERSISTENT RESERVE IN and OUT, and to fence off the initiators that do not
+/// hold the reservation by calling `check_access` from its own
+/// `ScsiResponder::check_access`. Commands that conflict with the
+/// reservation fail with a `ReservationConflictError`, which transports
+/// report as RESERVATION CONFLICT status.
+///
+/// The engine also keeps the older whole-unit reservation taken with RESERVE(6)
+/// and dropped with RELEASE(6), through `reserve_unit` and `release_unit`.
+/// While it is held, other initiators can neither access the medium nor
+/// change the persistent reservation state, and it cannot be taken while any
+/// initiator is registered.
+///
+/// The state only lives in memory, so it never persists through a loss of
+/// power.
+#[derive(Clone, Debug)]
+pub struct PersistentReservations<const N: usize> {
+    registrations: [Option<Registration>; N],
+    holder: Option<(InitiatorId, ReservationType)>,
+    unit_holder: Option<InitiatorId>,
+    generation: u32,
+}
+
+impl<const N: usize> Default for PersistentReservations<N> {
+    fn default() -> Self {
+        PersistentReservations::new()
+    }
+}
+
+impl<const N: usize> PersistentReservations<N> {
+    /// Constructs an engine without any registrations or reservation.
+    pub const fn new() -> PersistentReservations<N> {
+        PersistentReservations {
+            registrations: [None; N],
+            holder: None,
+            unit_holder: None,
+            generation: 0,
+        }
+    }
+
+    /// The counter that is incremented every time the registrations change.
+    pub fn generation(&self) -> u32 {
+        self.generation
+    }
+
+    /// The key `initiator` has registered, if it has registered one.
+    pub fn registered_key(&self, initiator: InitiatorId) -> Option<u64> {
+        self.registrations
+            .iter()
+            .flatten()
+            .find(|registration| registration.initiator == initiator)
+            .map(|registration| registration.key)
+    }
+
+    /// The current reservation, as reported to READ RESERVATION.
+    pub fn reservation(&self) -> Option<Reservation> {
+        self.holder.map(|(holder, reservation_type)| Reservation {
+            key: if reservation_type.is_all_registrants() {
+                0
+            } else {
+                self.registered_key(holder).unwrap_or(0)
+            },
+            reservation_type,
+        })
+    }
+
+    /// Whether `initiator` holds the current reservation.
+    pub fn holds(&self, initiator: InitiatorId) -> bool {
+        match self.holder {
+            Some((_, reservation_type)) if reservation_type.is_all_registrants() => {
+                self.registered_key(initiator).is_some()
+            }
+            Some((holder, _)) => holder == initiator,
+            None => false,
+        }
+    }
+
+    /// The initiator holding the whole-unit reservation taken with
+    /// `reserve_unit`, if any.
+    pub fn unit_reservation(&self) -> Option<InitiatorId> {
+        self.unit_holder
+    }
+
+    /// Whether the current reservation lets `initiator` access the medium in
+    /// the way `access` describes.
+    pub fn allows(&self, initiator: InitiatorId, access: MediumAccess) -> bool {
+        if self.unit_holder.is_some_and(|holder| holder != initiator) {
+            return access == MediumAccess::None;
+        }
+        let reservation_type = match self.holder {
+            Some((_, reservation_type)) => reservation_type,
+            None => return true,
+        };
+        if self.holds(initiator)
+            || (reservation_type.admits_registrants() && self.registered_key(initiator).is_some())
+        {
+            return true;
+        }
+        match access {
+            MediumAccess::None => true,
+            MediumAccess::Read => !reservation_type.is_exclusive_access(),
+            MediumAccess::Write => false,
+        }
+    }
+
+    /// Checks that the current reservation lets `initiator` access the medium
+    /// in the way `access` describes.
+    ///
+    /// # Errors
+    /// Returns a `ReservationConflictError` if it does not.
+    pub fn check_access(
+        &self,
+        initiator: InitiatorId,
+        access: MediumAccess,
+    ) -> Result<(), ScsiError> {
+        if self.allows(initiator, access) {
+            Ok(())
+        } else {
+            Err(ScsiError::from_cause(ErrorCause::ReservationConflictError))
+        }
+    }
+
+    /// Runs a RESERVE(6) command sent by `initiator`, reserving the whole
+    /// logical unit for it.
+    ///
+    /// # Errors
+    /// Returns a `ReservationConflictError` if another initiator holds the
+    /// unit reservation, or if any initiator has registered a persistent
+    /// reservation key.
+    pub fn reserve_unit(&mut self, initiator: InitiatorId) -> Result<(), ScsiError> {
+        let registered = self.registrations.iter().any(Option::is_some);
+        if registered || self.unit_holder.is_some_and(|holder| holder != initiator) {
+            return Err(conflict());
+        }
+        self.unit_holder = Some(initiator);
+        Ok(())
+    }
+
+    /// Runs a RELEASE(6) command sent by `initiator`, dropping the unit
+    /// reservation if `initiator` holds it.
+    ///
+    /// Releasing a unit reserved by another initiator, or not reserved at all,
+    /// does nothing, and persistent reservations are never released.
+    pub fn release_unit(&mut self, initiator: InitiatorId) {
+        if self.unit_holder == Some(initiator) {
+            self.unit_holder = None;
+        }
+    }
+
+    /// The capabilities this engine reports to REPORT CAPABILITIES.
+    pub fn capabilities(&self) -> ReservationCapabilitiesResponse {
+        let mask = [
+            ReservationType::WriteExclusive,
+            ReservationType::ExclusiveAccess,
+            ReservationType::WriteExclusiveRegistrantsOnly,
+            ReservationType::ExclusiveAccessRegistrantsOnly,
+            ReservationType::WriteExclusiveAllRegistrants,
+            ReservationType::ExclusiveAccessAllRegistrants,
+        ]
+        .iter()
+        .fold(0, |mask, reservation_type| {
+            mask | reservation_type.mask_bit()
+        });
+        ReservationCapabilitiesResponse {
+            type_mask: Some(mask),
+            ..Default::default()
+        }
+    }
+
+    /// Answers a PERSISTENT RESERVE IN command.
+    ///
+    /// # Errors
+    /// Returns a `BufferTooSmallError` if more keys are registered than a
+    /// `ReservationKeysResponse` can carry.
+    pub fn persistent_reserve_in(
+        &self,
+        command: PersistentReserveInCommand,
+    ) -> Result<PersistentReserveInResponse, ScsiError> {
+        Ok(match command.service_action {
+            PersistentReserveInServiceAction::ReadKeys => {
+                let mut response = ReservationKeysResponse {
+                    generation: self.generation,
+                    ..Default::default()
+                };
+                for registration in self.registrations.iter().flatten() {
+                    response.push_key(registration.key)?;
+                }
+                PersistentReserveInResponse::Keys(response)
+            }
+            PersistentReserveInServiceAction::ReadReservation => {
+                PersistentReserveInResponse::Reservation(ReservationResponse {
+                    generation: self.generation,
+                    reservation: self.reservation(),
+                })
+            }
+            PersistentReserveInServiceAction::ReportCapabilities => {
+                PersistentReserveInResponse::Capabilities(self.capabilities())
+            }
+        })
+    }
+
+    /// Runs a PERSISTENT RESERVE OUT command sent by `initiator`.
+    ///
+    /// Returns `None` if the command succeeded, or the sense data to fail it
+    /// with if it was malformed, such as a RESERVE without a type or a
+    /// REGISTER with no room left for another registration.
+    ///
+    /// # Errors
+    /// Returns a `ReservationConflictError` if the parameters' reservation key
+    /// does not match the one `initiator` registered, or if the command
+    /// conflicts with a reservation held by another initiator, including a
+    /// unit reservation.
+    pub fn persistent_reserve_out(
+        &mut self,
+        initiator: InitiatorId,
+        command: PersistentReserveOutCommand,
+        parameters: PersistentReserveOutParameters,
+    ) -> Result<Option<RequestSenseResponse>, ScsiError> {
+        if self.unit_holder.is_some_and(|holder| holder != initiator) {
+            return Err(conflict());
+        }
+        let registered = self.registered_key(initiator);
+        match command.service_action {
+            PersistentReserveOutServiceAction::Register => {
+                if registered.unwrap_or(0) != parameters.reservation_key {
+                    return Err(conflict());
+                }
+                Ok(self.register(initiator, parameters.service_action_key))
+            }
+            PersistentReserveOutServiceAction::RegisterAndIgnoreExistingKey => {
+                Ok(self.register(initiator, parameters.service_action_key))
+            }
+            _ if registered != Some(parameters.reservation_key) => Err(conflict()),
+            PersistentReserveOutServiceAction::Reserve => {
+                let reservation_type = match command.reservation_type {
+                    Some(reservation_type) => reservation_type,
+                    None => return Ok(Some(invalid_field_in_cdb())),
+                };
+                match self.holder {
+                    None => {
+                        self.holder = Some((initiator, reservation_type));
+                        Ok(None)
+                    }
+                    Some((_, held_type))
+                        if held_type == reservation_type && self.holds(initiator) =>
+                    {
+                        Ok(None)
+                    }
+                    Some(_) => Err(conflict()),
+                }
+            }
+            PersistentReserveOutServiceAction::Release => match self.holder {
+                Some((_, held_type)) if self.holds(initiator) => {
+                    if command.reservation_type != Some(held_type) {
+                        // Invalid release of persistent reservation.
+                        return Ok(Some(RequestSenseResponse::new(
+                            RequestSenseResponse::ILLEGAL_REQUEST,
+                            0x26,
+                            0x04,
+                        )));
+                    }
+                    self.holder = None;
+                    Ok(None)
+                }
+                _ => Ok(None),
+            },
+            PersistentReserveOutServiceAction::Clear => {
+                self.registrations = [None; N];
+                self.holder = None;
+                self.generation = self.generation.wrapping_add(1);
+                Ok(None)
+            }
+            PersistentReserveOutServiceAction::Preempt
+            | PersistentReserveOutServiceAction::PreemptAndAbort => {
+                // Commands are run one at a time, so there is never anything
+                // left for a preempt and abort to abort.
+                self.preempt(
+                    initiator,
+                    command.reservation_type,
+                    parameters.service_action_key,
+                )
+            }
+        }
+    }
+
+    /// Registers `key` for `initiator`, replacing its current key, or
+    /// unregisters it if `key` is 0.
+    fn register(&mut self, initiator: InitiatorId, key: u64) -> Option<RequestSenseResponse> {
+        let index = self
+            .registrations
+            .iter()
+            .position(|slot| slot.is_some_and(|r| r.initiator == initiator));
+        match (index, key) {
+            (None, 0) => return None,
+            (Some(index), 0) => {
+                self.registrations[index] = None;
+                self.drop_orphaned_reservation();
+            }
+            (Some(index), key) => self.registrations[index] = Some(Registration { initiator, key }),
+            (None, key) => match self.registrations.iter().position(Option::is_none) {
+                Some(index) => self.registrations[index] = Some(Registration { initiator, key }),
+                None => {
+                    // Insufficient registration resources.
+                    return Some(RequestSenseResponse::new(
+                        RequestSenseResponse::ILLEGAL_REQUEST,
+                        0x55,
+                        0x04,
+                    ));
+                }
+            },
+        }
+        self.generation = self.generation.wrapping_add(1);
+        None
+    }
+
+    /// Removes the registrations holding `key`, other than `initiator`'s own,
+    /// and takes over the reservation if they held it.
+    fn preempt(
+        &mut self,
+        initiator: InitiatorId,
+        reservation_type: Option<ReservationType>,
+        key: u64,
+    ) -> Result<Option<RequestSenseResponse>, ScsiError> {
+        let takes_over = match self.reservation() {
+            Some(reservation) => reservation.key == key,
+            None => false,
+        };
+        if key == 0 && !takes_over {
+            // Invalid field in parameter list.
+            return Ok(Some(RequestSenseResponse::new(
+                RequestSenseResponse::ILLEGAL_REQUEST,
+                0x26,
+                0,
+            )));
+        }
+        if takes_over && reservation_type.is_none() {
+            return Ok(Some(invalid_field_in_cdb()));
+        }
+        let mut removed = false;
+        for slot in self.registrations.iter_mut() {
+            if let Some(registration) = slot {
+                // Preempting an all registrants reservation removes everyone.
+                let matches = registration.key == key || (takes_over && key == 0);
+                if matches && registration.initiator != initiator {
+                    *slot = None;
+                    removed = true;
+                }
+            }
+        }
+        if takes_over {
+            self.holder = reservation_type.map(|reservation_type| (initiator, reservation_type));
+        } else if !removed {
+            return Err(conflict());
+        } else {
+            self.drop_orphaned_reservation();
+        }
+        self.generation = self.generation.wrapping_add(1);
+        Ok(None)
+    }
+
+    /// Drops the reservation once nobody registered holds it any more.
+    fn drop_orphaned_reservation(&mut self) {
+        if let Some((holder, reservation_type)) = self.holder {
+            let orphaned = if reservation_type.is_all_registrants() {
+                self.registrations.iter().all(Option::is_none)
+            } else {
+                self.registered_key(holder).is_none()
+            };
+            if orphaned {
+                self.holder = None;
+            }
+        }
+    }
+}
+
+fn conflict() -> ScsiError {
+    ScsiError::from_cause(ErrorCause::ReservationConflictError)
+}
+
+fn invalid_field_in_cdb() -> RequestSenseResponse {
+    RequestSenseResponse::new(RequestSenseResponse::ILLEGAL_REQUEST, 0x24, 0)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{InitiatorId, MediumAccess, PersistentReservations};
+    use crate::scsi::commands::{
+        PersistentReserveInCommand, PersistentReserveInResponse, PersistentReserveInServiceAction,
+        PersistentReserveOutCommand, PersistentReserveOutParameters,
+        PersistentReserveOutServiceAction, RequestSenseResponse, Reservation, ReservationType,
+    };
+    use crate::{ErrorCause, ScsiError};
+
+    const A: InitiatorId = InitiatorId(1);
+    const B: InitiatorId = InitiatorId(2);
+    const C: InitiatorId = InitiatorId(3);
+
+    fn out(
+        engine: &mut PersistentReservations<2>,
+        initiator: InitiatorId,
+        action: PersistentReserveOutServiceAction,
+        reservation_type: Option<ReservationType>,
+        keys: (u64, u64),
+    ) -> Result<Option<RequestSenseResponse>, ScsiError> {
+        engine.persistent_reserve_out(
+            initiator,
+            PersistentReserveOutCommand::new(action, reservation_type),
+            PersistentReserveOutParameters::new(keys.0, keys.1),
+        )
+    }
+
+    #[test]
+    fn test_reservations_register_and_reserve() {
+        use PersistentReserveOutServiceAction::*;
+        let mut engine = PersistentReservations::<2>::new();
+        let we = Some(ReservationType::WriteExclusive);
+        assert_eq!(out(&mut engine, A, Register, None, (0, 0xa)), Ok(None));
+        assert_eq!(out(&mut engine, B, Register, None, (0, 0xb)), Ok(None));
+        assert_eq!(engine.generation(), 2);
+        // The table is full.
+        let sense = out(&mut engine, C, Register, None, (0, 0xc))
+            .unwrap()
+            .unwrap();
+        assert_eq!(sense.additional_sense_code, 0x55);
+        // The wrong current key conflicts, unless it is ignored.
+        let err = out(&mut engine, A, Register, None, (0xb, 0xaa)).unwrap_err();
+        assert_eq!(err.cause, ErrorCause::ReservationConflictError);
+        assert_eq!(
+            out(&mut engine, A, RegisterAndIgnoreExistingKey, None, (0, 0xa)),
+            Ok(None)
+        );
+
+        assert_eq!(out(&mut engine, A, Reserve, we, (0xa, 0)), Ok(None));
+        assert_eq!(out(&mut engine, A, Reserve, we, (0xa, 0)), Ok(None));
+        assert!(out(&mut engine, B, Reserve, we, (0xb, 0)).is_err());
+        assert_eq!(
+            engine.reservation(),
+            Some(Reservation {
+                key: 0xa,
+                reservation_type: ReservationType::WriteExclusive,
+            })
+        );
+        assert!(engine.allows(A, MediumAccess::Write));
+        assert!(engine.allows(B, MediumAccess::Read));
+        assert!(!engine.allows(B, MediumAccess::Write));
+        assert!(engine.allows(C, MediumAccess::None));
+        assert_eq!(
+            engine
+                .check_access(C, MediumAccess::Write)
+                .unwrap_err()
+                .cause,
+            ErrorCause::ReservationConflictError
+        );
+
+        // Releasing with the wrong type fails; others releasing does nothing.
+        let ea = Some(ReservationType::ExclusiveAccess);
+        let sense = out(&mut engine, A, Release, ea, (0xa, 0)).unwrap().unwrap();
+        assert_eq!(sense.additional_sense_code, 0x26);
+        assert_eq!(out(&mut engine, B, Release, we, (0xb, 0)), Ok(None));
+        assert!(engine.reservation().is_some());
+        assert_eq!(out(&mut engine, A, Release, we, (0xa, 0)), Ok(None));
+        assert!(engine.reservation().is_none());
+        assert!(engine.allows(B, MediumAccess::Write));
+
+        // Unregistering the holder drops its reservation.
+        assert_eq!(out(&mut engine, B, Reserve, ea, (0xb, 0)), Ok(None));
+        assert!(!engine.allows(A, MediumAccess::Read));
+        assert_eq!(out(&mut engine, B, Register, None, (0xb, 0)), Ok(None));
+        assert!(engine.reservation().is_none());
+        assert_eq!(engine.registered_key(B), None);
+    }
+
+    #[test]
+    fn test_reservations_unit_reservation() {
+        use PersistentReserveOutServiceAction::*;
+        let mut engine = PersistentReservations::<2>::new();
+        assert_eq!(engine.reserve_unit(A), Ok(()));
+        assert_eq!(engine.reserve_unit(A), Ok(()));
+        assert_eq!(engine.unit_reservation(), Some(A));
+        let err = engine.reserve_unit(B).unwrap_err();
+        assert_eq!(err.cause, ErrorCause::ReservationConflictError);
+        assert!(engine.allows(A, MediumAccess::Write));
+        assert!(engine.allows(B, MediumAccess::None));
+        assert!(!engine.allows(B, MediumAccess::Read));
+        assert!(out(&mut engine, B, Register, None, (0, 0xb)).is_err());
+
+        // Only the holder's release counts.
+        engine.release_unit(B);
+        assert_eq!(engine.unit_reservation(), Some(A));
+        engine.release_unit(A);
+        assert_eq!(engine.unit_reservation(), None);
+        assert!(engine.allows(B, MediumAccess::Write));
+
+        // Registrations keep the unit from being reserved.
+        out(&mut engine, B, Register, None, (0, 0xb)).unwrap();
+        assert!(engine.reserve_unit(A).is_err());
+        assert!(engine.reserve_unit(B).is_err());
+    }
+
+    #[test]
+    fn test_reservations_preempt_and_clear() {
+        use PersistentReserveOutServiceAction::*;
+        let mut engine = PersistentReservations::<2>::new();
+        let ea = Some(ReservationType::ExclusiveAccess);
+        let ea_ro = Some(ReservationType::ExclusiveAccessRegistrantsOnly);
+        out(&mut engine, A, Register, None, (0, 0xa)).unwrap();
+        out(&mut engine, B, Register, None, (0, 0xb)).unwrap();
+        out(&mut engine, A, Reserve, ea_ro, (0xa, 0)).unwrap();
+        // Every registrant gets access, but nobody else does.
+        assert!(engine.allows(B, MediumAccess::Write));
+        assert!(!engine.allows(C, MediumAccess::Read));
+
+        // Preempting a key that nobody holds conflicts.
+        assert!(out(&mut engine, B, Preempt, ea, (0xb, 0xc)).is_err());
+        let generation = engine.generation();
+        assert_eq!(
+            out(&mut engine, B, PreemptAndAbort, ea, (0xb, 0xa)),
+            Ok(None)
+        );
+        assert_eq!(engine.generation(), generation + 1);
+        assert_eq!(engine.registered_key(A), None);
+        assert_eq!(engine.reservation().unwrap().key, 0xb);
+        assert!(!engine.allows(A, MediumAccess::Read));
+
+        let keys = engine
+            .persistent_reserve_in(PersistentReserveInCommand::new(
+                PersistentReserveInServiceAction::ReadKeys,
+            ))
+            .unwrap();
+        match keys {
+            PersistentReserveInResponse::Keys(keys) => assert_eq!(keys.keys(), &[0xb]),
+            _ => panic!("expected keys"),
+        }
+
+        // Unregistered initiators cannot clear.
+        assert!(out(&mut engine, A, Clear, None, (0xa, 0)).is_err());
+        assert_eq!(out(&mut engine, B, Clear, None, (0xb, 0)), Ok(None));
+        assert!(engine.reservation().is_none());
+        assert_eq!(engine.registered_key(B), None);
+    }
+
+    #[test]
+    fn test_reservations_all_registrants() {
+        use PersistentReserveOutServiceAction::*;
+        let mut engine = PersistentReservations::<2>::new();
+        let we_ar = Some(ReservationType::WriteExclusiveAllRegistrants);
+        out(&mut engine, A, Register, None, (0, 0xa)).unwrap();
+        out(&mut engine, A, Reserve, we_ar, (0xa, 0)).unwrap();
+        out(&mut engine, B, Register, None, (0, 0xb)).unwrap();
+        assert!(engine.holds(B));
+        assert_eq!(engine.reservation().unwrap().key, 0);
+        // The reservation outlives the registrant that took it out.
+        out(&mut engine, A, Register, None, (0xa, 0)).unwrap();
+        assert!(engine.reservation().is_some());
+        assert!(!engine.allows(A, MediumAccess::Write));
+        assert!(engine.allows(A, MediumAccess::Read));
+        out(&mut engine, B, Register, None, (0xb, 0)).unwrap();
+        assert!(engine.reservation().is_none());
+
+        assert_ne!(
+            InitiatorId::from_name(b"iqn.a"),
+            InitiatorId::from_name(b"iqn.b")
+        );
+    }
+}
@@ -1,12 +1,32 @@
 use crate::scsi::commands::{
-    Command, CommandBlockWrapper, CommandStatusWrapper, InquiryCommand, InquiryResponse,
-    Read10Command, ReadCapacityCommand, ReadCapacityResponse, RequestSenseCommand, TestUnitReady,
-    Write10Command,
+    BlockLimitsPage, BufferDescriptor, Command, CommandBlockWrapper, CommandStatusWrapper,
+    CompareAndWriteCommand, Direction, DiscInformationResponse, EchoBufferDescriptor,
+    EventStatusResponse, FormatParameters, FormatUnitCommand, GetConfigurationCommand,
+    GetConfigurationResponse, GetEventStatusNotificationCommand, InquiryCommand,
+    InquiryIdentification, InquiryResponse, LogPage, LogSelectCommand, LogSenseCommand,
+    OverwriteParameters, PersistentReserveInCommand, PersistentReserveInResponse,
+    PersistentReserveOutCommand, PersistentReserveOutParameters, PreventAllowMediumRemovalCommand,
+    Read10Command, Read16Command, ReadBufferCommand, ReadBufferMode, ReadCapacity16Command,
+    ReadCapacity16Response, ReadCapacityCommand, ReadCapacityResponse, ReadDiscInformationCommand,
+    ReadTocCommand, Release6Command, RequestSenseCommand, RequestSenseResponse, Reserve6Command,
+    SanitizeCommand, ScsiStatus, StandardInquiryData, StartStopUnitCommand,
+    SynchronizeCache10Command, TestUnitReady, TocResponse, UnmapBlockDescriptors, UnmapCommand,
+    Verify10Command, Verify16Command, VerifyByteCheck, VpdInquiryCommand, Write10Command,
+    Write16Command, WriteBufferCommand, WriteSame10Command, WriteSame16Command,
 };
+use crate::scsi::{InitiatorId, MediumAccess};
 use crate::{
-    BufferPullable, BufferPushable, CommunicationChannel, ErrorCause, ScsiError,
-    UsbTransferDirection,
+    AsyncCommunicationChannel, BufferPullable, BufferPushable, CommunicationChannel, ErrorCause,
+    ScsiError, UsbTransferDirection,
 };
+use byteorder::{ByteOrder, BE};
+use core::future::Future;
+use core::pin::pin;
+use core::task::{Context, Poll, Waker};
+
+/// The size of the scratch buffer used to serialize non-block command responses
+/// before they are sent to the host, which fits the largest `LogPage`.
+pub(crate) const RESPONSE_BUFFER_SIZE: usize = 512;
 
 /// A trait to describe a device to respond to SCSI command, such as a flash drive.
 ///
@@ -17,6 +37,12 @@ use crate::{
 /// All SCSI sessions end with the device sending a `CommandStatusWrapper` back to the host; therefore,
 /// nearly all functions here expect the device to construct one to be returned. Usually this will be done
 /// via `Ok(CommandStatsWrapper::default())`, but error situations can be set as necessary as well.
+///
+/// The same hooks can also be served over USB Attached SCSI with a `uas::UasTarget`.
+///
+/// The multimedia (MMC) commands used by optical drives have default implementations
+/// that return an `UnsupportedOperationError`, so only devices that need them have
+/// to implement them.
 pub trait ScsiResponder {
     /// The type to use as a memory buffer for per-block operations, mainly read
     /// or write transfers. Usually this would be of the form `[u8 ; N]`, where
@@ -32,17 +58,35 @@ pub trait ScsiResponder {
         command: ReadCapacityCommand,
     ) -> Result<(ReadCapacityResponse, CommandStatusWrapper), ScsiError>;
 
+    /// Called in response to a `ReadCapacity16Command` from the host, which
+    /// hosts send when `read_capacity` reports a final block address of
+    /// 0xFFFFFFFF.
+    ///
+    /// Defaults to widening the response of `read_capacity`, which is enough
+    /// for any device whose blocks all fit in 32 bit addresses.
+    fn read_capacity16(
+        &mut self,
+        _command: ReadCapacity16Command,
+    ) -> Result<(ReadCapacity16Response, CommandStatusWrapper), ScsiError> {
+        let (response, csw) = self.read_capacity(ReadCapacityCommand::new())?;
+        Ok((response.into(), csw))
+    }
+
     /// Called in response to a `InquiryCommand` from the host.
     ///
     /// Currently, the library does not yet include support for `allocation_length`s
     /// not equal to 36; in the future more research will be done into which fields
     /// are added and removed at different values.
+    ///
+    /// Requests for vital product data pages other than the Block Limits page
+    /// (see `block_limits`) are rejected by the dispatcher and never reach
+    /// this.
     fn inquiry(
         &mut self,
         command: InquiryCommand,
     ) -> Result<(InquiryResponse, CommandStatusWrapper), ScsiError>;
 
-    /// Called in response to a `InquiryCommand` from the host.
+    /// Called in response to a `RequestSenseCommand` from the host.
     ///
     /// Currently it is not known what the command's `allocation_length` field does,
     /// but it is still passed in to the command regardless.
@@ -51,6 +95,27 @@ pub trait ScsiResponder {
         command: RequestSenseCommand,
     ) -> Result<CommandStatusWrapper, ScsiError>;
 
+    /// Called in response to a `RequestSenseCommand` from the host to get the
+    /// sense data sent back in the data phase.
+    ///
+    /// The returned sense data should describe why the previous command failed,
+    /// if it did; it is truncated to the command's `allocation_length` before
+    /// being sent. The default reports no sense alongside `request_sense`'s status.
+    fn sense_data(
+        &mut self,
+        command: RequestSenseCommand,
+    ) -> Result<(RequestSenseResponse, CommandStatusWrapper), ScsiError> {
+        let csw = self.request_sense(command)?;
+        Ok((RequestSenseResponse::default(), csw))
+    }
+
+    /// Called when the host sends a command that cannot be run, such as one
+    /// with an unknown operation code, just before it is failed.
+    ///
+    /// `sense` explains the failure; a responder that keeps sense data should
+    /// return it from the next `sense_data` call. By default it is dropped.
+    fn command_rejected(&mut self, _sense: RequestSenseResponse) {}
+
     /// Called in response to a `TestUnitReady` from the host.
     ///
     /// All of the response information is encoded in the `CommandStatusWrapper`;
@@ -68,12 +133,23 @@ pub trait ScsiResponder {
     /// up indices, etc.
     fn read10_start(&mut self, command: Read10Command) -> Result<(), ScsiError>;
 
-    /// Called multiple times after a `read10_start` command to pull the relevant data out of the responder.
+    /// Called when the host sends a `Read16Command`, which is followed by the
+    /// same `read_block` calls as a `Read10Command`.
+    ///
+    /// Defaults to passing the command on to `read10_start` when it fits in
+    /// the 10 byte form, and failing it otherwise.
+    fn read16_start(&mut self, command: Read16Command) -> Result<(), ScsiError> {
+        self.read10_start(Read10Command::try_from(command)?)
+    }
+
+    /// Called multiple times after a `read10_start` or `read16_start` command to pull the relevant data out of the responder.
     ///
     /// `buffer` will be guranteed to be equal to the block length of the device as specified by the responder's
     /// `memory_buffer` method; in nearly all cases, it will be the same buffer. The method will keep being called until
     /// it returns `Some(_)`, even if this leads to a different number of blocks being read than expected by the original
     /// `Read10Command`; it is up to the responder to gurantee that the number of blocks read is correct.
+    ///
+    /// The contents of `buffer` are only sent to the host when `None` is returned.
     fn read_block(&mut self, buffer: &mut [u8]) -> Result<Option<CommandStatusWrapper>, ScsiError>;
 
     /// Called when the host sends the `Write10` command itself over the
@@ -84,143 +160,1605 @@ pub trait ScsiResponder {
     /// up indices, etc.
     fn write10_start(&mut self, command: Write10Command) -> Result<(), ScsiError>;
 
-    /// Called multiple times after a `write10_start` command to pull the relevant data out of the responder.
+    /// Called when the host sends a `Write16Command`, which is followed by the
+    /// same `write_block` calls as a `Write10Command`.
+    ///
+    /// Defaults to passing the command on to `write10_start` when it fits in
+    /// the 10 byte form, and failing it otherwise.
+    fn write16_start(&mut self, command: Write16Command) -> Result<(), ScsiError> {
+        self.write10_start(Write10Command::try_from(command)?)
+    }
+
+    /// Called multiple times after a `write10_start` or `write16_start` command to pull the relevant data out of the responder.
     ///
     /// `buffer` will be guranteed to be equal to the block length of the device as specified by the responder's
     /// `memory_buffer` method; in nearly all cases, it will be the same buffer. The method will keep being called until
     /// it returns `Some(_)`, even if this leads to a different number of blocks being written to than expected by the original
     /// `Write10Command`; it is up to the responder to gurantee that the number of blocks read is correct.
+    ///
+    /// The block passed to the call that returns `Some(_)` counts as written; any data the host sends after it is
+    /// received and discarded.
     fn write_block(&mut self, buffer: &[u8]) -> Result<Option<CommandStatusWrapper>, ScsiError>;
 
+    /// Called when the host has sent all of a write command's data without
+    /// `write_block` returning `Some(_)`, to get the command's status.
+    fn write_end(&mut self) -> Result<CommandStatusWrapper, ScsiError> {
+        Ok(CommandStatusWrapper::default())
+    }
+
     /// Generates a new, owned instance of the responder's block buffer.
     ///
     /// Usually, this can be implemented as just `[0 ; N]`, where `N` is the same
     /// as the one picked for `Self::BlockType`.
     fn memory_buffer(&mut self) -> Self::BlockType;
 
+    /// Called in response to a `VpdInquiryCommand` for the Block Limits vital
+    /// product data page, which tells the host how much data it may transfer
+    /// per command.
+    fn block_limits(
+        &mut self,
+        _command: VpdInquiryCommand,
+    ) -> Result<(BlockLimitsPage, CommandStatusWrapper), ScsiError> {
+        Err(ScsiError::from_cause(ErrorCause::UnsupportedOperationError))
+    }
+
+    /// Called after a standard `inquiry` to get the vendor, product and
+    /// revision strings sent along with its `InquiryResponse`.
+    ///
+    /// By default the strings are left blank.
+    fn identification(&mut self) -> InquiryIdentification {
+        InquiryIdentification::default()
+    }
+
+    /// Called in response to a `SynchronizeCache10Command` from the host.
+    ///
+    /// Responders that write blocks straight through to their medium have
+    /// nothing to flush, so by default this reports success.
+    fn synchronize_cache(
+        &mut self,
+        _command: SynchronizeCache10Command,
+    ) -> Result<CommandStatusWrapper, ScsiError> {
+        Ok(CommandStatusWrapper::default())
+    }
+
+    /// Called in response to an `UnmapCommand` from the host, with the block
+    /// descriptors from its parameter list.
+    ///
+    /// Responders backed by thin-provisioned storage, such as RAM or sparse
+    /// files, can free the blocks in every range; unmapped blocks should read
+    /// back as zeroes. Parameter lists longer than the responder's block
+    /// buffer are rejected before this is called.
+    fn unmap(
+        &mut self,
+        _command: UnmapCommand,
+        _descriptors: UnmapBlockDescriptors<'_>,
+    ) -> Result<CommandStatusWrapper, ScsiError> {
+        Err(ScsiError::from_cause(ErrorCause::UnsupportedOperationError))
+    }
+
+    /// Called in response to a `WriteSame10Command` or `WriteSame16Command`
+    /// from the host, the former converted into the latter.
+    ///
+    /// `block` should be written to every block in the range. It is empty if
+    /// the command's `no_data_out` flag is set, in which case the blocks
+    /// should be zeroed instead. If the command's `unmap` flag is set, the
+    /// responder may free the blocks as long as they read back as `block`.
+    fn write_same(
+        &mut self,
+        _command: WriteSame16Command,
+        _block: &[u8],
+    ) -> Result<CommandStatusWrapper, ScsiError> {
+        Err(ScsiError::from_cause(ErrorCause::UnsupportedOperationError))
+    }
+
+    /// Called in response to a `CompareAndWriteCommand` from the host, with the
+    /// blocks to compare against and the blocks to write in their place.
+    ///
+    /// The responder should compare the blocks starting at the command's
+    /// `block_address` against `expected` and, only if every byte matches,
+    /// overwrite them with `new`, without anything else reading or changing
+    /// the blocks in between. A failed comparison should be described by the
+    /// following `sense_data`, using the `MISCOMPARE` sense key with the
+    /// offset of the first differing byte in `expected` as the `information`.
+    /// Commands whose halves are longer than the responder's block buffer are
+    /// rejected before this is called.
+    fn compare_and_write(
+        &mut self,
+        _command: CompareAndWriteCommand,
+        _expected: &[u8],
+        _new: &[u8],
+    ) -> Result<CommandStatusWrapper, ScsiError> {
+        Err(ScsiError::from_cause(ErrorCause::UnsupportedOperationError))
+    }
+
+    /// Called in response to a `Verify10Command` or `Verify16Command` from the
+    /// host, the former converted into the latter.
+    ///
+    /// When the command's `byte_check` is `VerifyByteCheck::None` this is
+    /// only called once, with an empty `data`, and the responder should check
+    /// that every block in the range can still be read. Otherwise it is
+    /// called for each block the host sends, with `block_address` set to the
+    /// block `data` should be compared against; with
+    /// `VerifyByteCheck::CompareSingleBlock` the same data is passed for every
+    /// block in the range. Returning `Some(_)` ends the comparison early, and
+    /// the rest of the host's data is received and discarded. Once all of the
+    /// data has been compared it is called one final time with an empty
+    /// `data`, and returning `None` at that point is treated as success.
+    ///
+    /// Failures should be described by the following `sense_data`, using
+    /// the `MEDIUM_ERROR` sense key with the unreadable block's address as the
+    /// `information`, or `MISCOMPARE` with the offset of the first differing
+    /// byte.
+    fn verify(
+        &mut self,
+        _command: Verify16Command,
+        _block_address: u64,
+        _data: &[u8],
+    ) -> Result<Option<CommandStatusWrapper>, ScsiError> {
+        Err(ScsiError::from_cause(ErrorCause::UnsupportedOperationError))
+    }
+
+    /// Called in response to a `FormatUnitCommand` from the host, along with
+    /// the header of its parameter list if one was sent.
+    ///
+    /// The responder should erase its backing store, for example by zeroing
+    /// or freeing every block. If the parameters' `immediate` flag is set, it
+    /// may report success straight away and keep formatting in the
+    /// background; until it is done, `sense_data` should report
+    /// `RequestSenseResponse::format_in_progress`, and commands that access
+    /// the medium should fail. Parameter lists longer than the responder's
+    /// block buffer are rejected before this is called.
+    fn format_unit(
+        &mut self,
+        _command: FormatUnitCommand,
+        _parameters: Option<FormatParameters>,
+    ) -> Result<CommandStatusWrapper, ScsiError> {
+        Err(ScsiError::from_cause(ErrorCause::UnsupportedOperationError))
+    }
+
+    /// Called in response to a `SanitizeCommand` from the host, along with its
+    /// parameter list if it is an overwrite.
+    ///
+    /// The responder should make every block of its backing store
+    /// unrecoverable, either by writing the parameters' pattern over it or by
+    /// erasing it. If the command's `immediate` flag is set, it may report
+    /// success straight away and keep going in the background; until it is
+    /// done, `sense_data` should report
+    /// `RequestSenseResponse::sanitize_in_progress`, and commands that access
+    /// the medium should fail. Parameter lists longer than the responder's
+    /// block buffer are rejected before this is called.
+    fn sanitize(
+        &mut self,
+        _command: SanitizeCommand,
+        _parameters: Option<OverwriteParameters<'_>>,
+    ) -> Result<CommandStatusWrapper, ScsiError> {
+        Err(ScsiError::from_cause(ErrorCause::UnsupportedOperationError))
+    }
+
+    /// Called in response to a `PersistentReserveInCommand` from the host.
+    ///
+    /// Responders shared by several initiators can answer this from an
+    /// embedded `PersistentReservations`.
+    fn persistent_reserve_in(
+        &mut self,
+        _command: PersistentReserveInCommand,
+    ) -> Result<(PersistentReserveInResponse, CommandStatusWrapper), ScsiError> {
+        Err(ScsiError::from_cause(ErrorCause::UnsupportedOperationError))
+    }
+
+    /// Called in response to a `PersistentReserveOutCommand` from the host,
+    /// along with its parameter list.
+    ///
+    /// Commands that conflict with the reservation state, for example
+    /// because the parameters' `reservation_key` is not the one the
+    /// initiator registered, should return a `ReservationConflictError`.
+    /// Parameter lists that are cut short or longer than the responder's
+    /// block buffer are rejected before this is called.
+    fn persistent_reserve_out(
+        &mut self,
+        _command: PersistentReserveOutCommand,
+        _parameters: PersistentReserveOutParameters,
+    ) -> Result<CommandStatusWrapper, ScsiError> {
+        Err(ScsiError::from_cause(ErrorCause::UnsupportedOperationError))
+    }
+
+    /// Called in response to a `LogSenseCommand` from the host, which asks for
+    /// one of the responder's log pages, such as its temperature or how worn
+    /// out its flash is.
+    ///
+    /// Pages the responder does not have should fail the command, with
+    /// `sense_data` reporting an `ILLEGAL_REQUEST` with an additional sense
+    /// code of `0x24`; the page returned along with a failed CSW is not sent.
+    /// The response is cut short to the command's `allocation_length`.
+    fn log_sense(
+        &mut self,
+        _command: LogSenseCommand,
+    ) -> Result<(LogPage, CommandStatusWrapper), ScsiError> {
+        Err(ScsiError::from_cause(ErrorCause::UnsupportedOperationError))
+    }
+
+    /// Called in response to a `LogSelectCommand` from the host, along with
+    /// its parameter list, which is empty if the command's
+    /// `parameter_code_reset` flag is set.
+    ///
+    /// The parameters can be walked with `LogParameters`. Parameter lists
+    /// longer than the responder's block buffer are rejected before this is
+    /// called.
+    fn log_select(
+        &mut self,
+        _command: LogSelectCommand,
+        _parameters: &[u8],
+    ) -> Result<CommandStatusWrapper, ScsiError> {
+        Err(ScsiError::from_cause(ErrorCause::UnsupportedOperationError))
+    }
+
+    /// Called by transports that can tell initiators apart, such as iSCSI,
+    /// with the initiator that sent the next command.
+    ///
+    /// Transports serving a single initiator never call this.
+    fn set_initiator(&mut self, _initiator: InitiatorId) {}
+
+    /// Called before every command with the way it accesses the medium.
+    ///
+    /// Returning a `ReservationConflictError` keeps the command from running
+    /// and reports RESERVATION CONFLICT status to the host, which lets
+    /// responders fence off initiators that do not hold their reservation.
+    /// By default every command is allowed.
+    fn check_access(&mut self, _access: MediumAccess) -> Result<(), ScsiError> {
+        Ok(())
+    }
+
+    /// Called in response to a `Reserve6Command` from the host, which
+    /// reserves the whole logical unit for the initiator that sent it.
+    ///
+    /// Responders should return a `ReservationConflictError` if another
+    /// initiator holds the reservation; an embedded `PersistentReservations`
+    /// handles this.
+    fn reserve6(&mut self, _command: Reserve6Command) -> Result<CommandStatusWrapper, ScsiError> {
+        Err(ScsiError::from_cause(ErrorCause::UnsupportedOperationError))
+    }
+
+    /// Called in response to a `Release6Command` from the host.
+    fn release6(&mut self, _command: Release6Command) -> Result<CommandStatusWrapper, ScsiError> {
+        Err(ScsiError::from_cause(ErrorCause::UnsupportedOperationError))
+    }
+
+    /// Called in response to a `WriteBufferCommand` from the host, such as
+    /// one segment of a firmware download.
+    ///
+    /// The data the host sends is passed on in chunks the size of the
+    /// responder's block buffer, with `offset` set to where `data` goes
+    /// within the buffer: the command's `buffer_offset` plus the bytes that
+    /// came before it. Returning `Some(_)` ends the command early, and the
+    /// rest of the host's data is received and discarded. Once all of the
+    /// data has been passed on it is called one final time with an empty
+    /// `data`, and returning `None` at that point is treated as success;
+    /// commands without data, such as
+    /// `WriteBufferMode::ActivateDeferredMicrocode`, only get this final call.
+    ///
+    /// A microcode image should only be saved, or activated, once the final
+    /// call for the command carrying its last segment has been made.
+    fn write_buffer(
+        &mut self,
+        _command: WriteBufferCommand,
+        _offset: u32,
+        _data: &[u8],
+    ) -> Result<Option<CommandStatusWrapper>, ScsiError> {
+        Err(ScsiError::from_cause(ErrorCause::UnsupportedOperationError))
+    }
+
+    /// Called in response to a `ReadBufferCommand` from the host in
+    /// `ReadBufferMode::Data` or `ReadBufferMode::EchoBuffer`.
+    ///
+    /// It is called for each chunk of the command's `allocation_length`, with
+    /// `buffer` sized to the chunk and `offset` set to where the chunk starts
+    /// within the device's buffer. The contents of `buffer` are only sent to
+    /// the host when `None` is returned; returning `Some(_)` ends the command
+    /// without sending the chunk. Reads past the end of the device's buffer
+    /// should fail.
+    fn read_buffer(
+        &mut self,
+        _command: ReadBufferCommand,
+        _offset: u32,
+        _buffer: &mut [u8],
+    ) -> Result<Option<CommandStatusWrapper>, ScsiError> {
+        Err(ScsiError::from_cause(ErrorCause::UnsupportedOperationError))
+    }
+
+    /// Called in response to a `ReadBufferCommand` from the host in
+    /// `ReadBufferMode::Descriptor`, which asks for the size of the
+    /// command's buffer and the offsets it accepts.
+    fn read_buffer_descriptor(
+        &mut self,
+        _command: ReadBufferCommand,
+    ) -> Result<(BufferDescriptor, CommandStatusWrapper), ScsiError> {
+        Err(ScsiError::from_cause(ErrorCause::UnsupportedOperationError))
+    }
+
+    /// Called in response to a `ReadBufferCommand` from the host in
+    /// `ReadBufferMode::EchoBufferDescriptor`.
+    fn read_echo_buffer_descriptor(
+        &mut self,
+        _command: ReadBufferCommand,
+    ) -> Result<(EchoBufferDescriptor, CommandStatusWrapper), ScsiError> {
+        Err(ScsiError::from_cause(ErrorCause::UnsupportedOperationError))
+    }
+
+    /// Called in response to a `StartStopUnitCommand` from the host, which
+    /// includes requests to load or eject removable media.
+    fn start_stop_unit(
+        &mut self,
+        _command: StartStopUnitCommand,
+    ) -> Result<CommandStatusWrapper, ScsiError> {
+        Err(ScsiError::from_cause(ErrorCause::UnsupportedOperationError))
+    }
+
+    /// Called in response to a `PreventAllowMediumRemovalCommand` from the host.
+    fn prevent_allow_medium_removal(
+        &mut self,
+        _command: PreventAllowMediumRemovalCommand,
+    ) -> Result<CommandStatusWrapper, ScsiError> {
+        Err(ScsiError::from_cause(ErrorCause::UnsupportedOperationError))
+    }
+
+    /// Called in response to a `ReadTocCommand` from the host.
+    fn read_toc(
+        &mut self,
+        _command: ReadTocCommand,
+    ) -> Result<(TocResponse, CommandStatusWrapper), ScsiError> {
+        Err(ScsiError::from_cause(ErrorCause::UnsupportedOperationError))
+    }
+
+    /// Called in response to a `GetConfigurationCommand` from the host.
+    ///
+    /// The responder is responsible for filtering the returned features based
+    /// on the command's `request_type` and `starting_feature`.
+    fn get_configuration(
+        &mut self,
+        _command: GetConfigurationCommand,
+    ) -> Result<(GetConfigurationResponse, CommandStatusWrapper), ScsiError> {
+        Err(ScsiError::from_cause(ErrorCause::UnsupportedOperationError))
+    }
+
+    /// Called in response to a `GetEventStatusNotificationCommand` from the host.
+    fn get_event_status_notification(
+        &mut self,
+        _command: GetEventStatusNotificationCommand,
+    ) -> Result<(EventStatusResponse, CommandStatusWrapper), ScsiError> {
+        Err(ScsiError::from_cause(ErrorCause::UnsupportedOperationError))
+    }
+
+    /// Called in response to a `ReadDiscInformationCommand` from the host.
+    fn read_disc_information(
+        &mut self,
+        _command: ReadDiscInformationCommand,
+    ) -> Result<(DiscInformationResponse, CommandStatusWrapper), ScsiError> {
+        Err(ScsiError::from_cause(ErrorCause::UnsupportedOperationError))
+    }
+
     /// Processes a single command from a host, from reading the CBW to outputting
     /// the CSW.
     ///
     /// First, the CBW and command header is read from `channel` via `in_transfer`;
     /// the correct method is then called on `self` based on which opcode was read.
-    /// Next, if necessary for that particular command (currently, only `Write10`),
+    /// Next, if necessary for that particular command (currently, `Write10` and `Write16`),
     /// a new block buffer will be allocated via `self.memory_buffer()` and any needed input blocks
     /// will be pulled from `channel` and routed to the relevant method on `self`.
-    /// Next, if necessary for that particular command (currently, only `Read10`),
+    /// Next, if necessary for that particular command (currently, `Read10` and `Read16`),
     /// a new block buffer will be allocated via `self.memory_buffer()` and any needed ouput blocks
     /// will be pulled from the relevant method on `self` and pushed to `channel`.
-    /// Next, if the command has an extra specialized response struct, it will be sent via `channel.out_transfer`
-    /// using a 31-length buffer.
-    /// Finally, the CSW struct's tag is set to match the input CBW's and it is sent across the channel using a 31-length buffer.
+    /// Next, if the command has an extra specialized response struct and the returned CSW
+    /// reports success, it will be sent via `channel.out_transfer`, truncated to the length
+    /// the host asked for in the CBW.
+    /// Finally, the CSW struct's tag and data residue are set to match the input CBW's and
+    /// the data actually transferred, and it is sent across the channel.
     fn process_command<C: CommunicationChannel>(
         &mut self,
         channel: &mut C,
     ) -> Result<(), ScsiError> {
         let mut command_buffer = [0; 31];
         let read = channel.in_transfer(&mut command_buffer)?;
-        if read != 31 {
+        let (cbw, command) = parse_cbw(&command_buffer, read)?;
+        let mut data = BulkOnlyData(channel);
+        let (csw, transferred) = run_blocking(dispatch_bulk_only(
+            self,
+            &cbw,
+            command,
+            &mut BlockingData(&mut data),
+        ))?;
+        let csw_pushed = push_csw(csw, &cbw, transferred, &mut command_buffer)?;
+        let csw_sent = channel.out_transfer(&command_buffer[..csw_pushed])?;
+        check_csw_sent(csw_sent, csw_pushed)
+    }
+
+    /// The asynchronous counterpart of `process_command`, for responders whose
+    /// channel is driven by an executor rather than blocking.
+    ///
+    /// The responder's own methods are still called synchronously; only the
+    /// transfers on `channel` are awaited.
+    #[allow(async_fn_in_trait)]
+    async fn process_command_async<C: AsyncCommunicationChannel>(
+        &mut self,
+        channel: &mut C,
+    ) -> Result<(), ScsiError> {
+        let mut command_buffer = [0; 31];
+        let read = channel.in_transfer(&mut command_buffer).await?;
+        let (cbw, command) = parse_cbw(&command_buffer, read)?;
+        let (csw, transferred) =
+            dispatch_bulk_only(self, &cbw, command, &mut AsyncBulkOnlyData(channel)).await?;
+        let csw_pushed = push_csw(csw, &cbw, transferred, &mut command_buffer)?;
+        let csw_sent = channel.out_transfer(&command_buffer[..csw_pushed]).await?;
+        check_csw_sent(csw_sent, csw_pushed)
+    }
+}
+
+/// Parses the CBW opening a Bulk-Only command, along with the command in it.
+///
+/// Only a bad CBW is an error here; a command that cannot be parsed is
+/// returned as is, to be failed with a CSW of its own.
+fn parse_cbw(
+    buffer: &[u8; 31],
+    read: usize,
+) -> Result<(CommandBlockWrapper, Result<ScsiCommand, ScsiError>), ScsiError> {
+    if read != 31 {
+        return Err(ScsiError::from_cause(ErrorCause::UsbTransferError {
+            direction: UsbTransferDirection::In,
+        }));
+    }
+    let cbw = CommandBlockWrapper::pull_from_buffer(buffer)?;
+    let command = ScsiCommand::pull_from_buffer(buffer);
+    Ok((cbw, command))
+}
+
+/// Serializes the CSW answering `cbw` to `buffer`, with its tag and data
+/// residue set to match the CBW and the data actually transferred.
+fn push_csw(
+    mut csw: CommandStatusWrapper,
+    cbw: &CommandBlockWrapper,
+    transferred: usize,
+    buffer: &mut [u8],
+) -> Result<usize, ScsiError> {
+    let expected = cbw.data_transfer_length as usize;
+    csw.tag = cbw.tag;
+    csw.data_residue = (expected - transferred.min(expected)) as u32;
+    let csw_pushed = csw.push_to_buffer(buffer)?;
+    if csw_pushed != CommandStatusWrapper::SIZE as usize {
+        return Err(ScsiError::from_cause(ErrorCause::BufferTooSmallError {
+            expected: CommandStatusWrapper::SIZE as usize,
+            actual: csw_pushed,
+        }));
+    }
+    Ok(csw_pushed)
+}
+
+/// Runs a command received over Bulk-Only, which can only report whether a
+/// command passed or failed, so commands that end in a status of their own,
+/// such as RESERVATION CONFLICT, just fail.
+///
+/// Commands that could not be parsed, or that the responder does not
+/// implement, fail with ILLEGAL REQUEST sense data, which is passed to the
+/// responder's `command_rejected` hook.
+async fn dispatch_bulk_only<R: ScsiResponder + ?Sized, D: AsyncDataPhase>(
+    responder: &mut R,
+    cbw: &CommandBlockWrapper,
+    command: Result<ScsiCommand, ScsiError>,
+    data: &mut D,
+) -> Result<(CommandStatusWrapper, usize), ScsiError> {
+    let expected = cbw.data_transfer_length as usize;
+    let mut data = CountingData { data, received: 0 };
+    let status = match command {
+        Ok(command) => {
+            match dispatch_command_async(responder, command, expected, &mut data).await {
+                Err(err) if err.cause == ErrorCause::UnsupportedOperationError => {
+                    reject_command(responder, &err);
+                    ScsiStatus::CheckCondition
+                }
+                Err(err) => match ScsiStatus::from_error(&err) {
+                    Some(status) => status,
+                    None => return Err(err),
+                },
+                result => return result,
+            }
+        }
+        Err(err) => {
+            reject_command(responder, &err);
+            ScsiStatus::CheckCondition
+        }
+    };
+    // The host sends a write's data whether or not it is wanted.
+    if cbw.direction == Direction::OUT {
+        let remaining = expected - data.received.min(expected);
+        receive_parameters(&mut data, remaining, &mut []).await?;
+    }
+    let csw = CommandStatusWrapper {
+        status: status.bulk_only_status(),
+        ..Default::default()
+    };
+    Ok((csw, 0))
+}
+
+/// Passes the ILLEGAL REQUEST sense data for a command refused with `err` to
+/// the responder's `command_rejected` hook, returning it for transports that
+/// send it along with the status.
+pub(crate) fn reject_command<R: ScsiResponder + ?Sized>(
+    responder: &mut R,
+    err: &ScsiError,
+) -> RequestSenseResponse {
+    // Invalid command operation code, or invalid field in CDB.
+    let asc = match err.cause {
+        ErrorCause::UnsupportedOperationError => 0x20,
+        _ => 0x24,
+    };
+    let sense = RequestSenseResponse::new(RequestSenseResponse::ILLEGAL_REQUEST, asc, 0);
+    responder.command_rejected(sense);
+    sense
+}
+
+/// Turns the result of `dispatch_command` into the status, sense data and
+/// number of bytes transferred reported by transports that carry a full SCSI
+/// status, such as UAS and iSCSI.
+///
+/// The sense data of a failed command comes from the responder's
+/// `sense_data` hook, while commands the responder does not implement fail
+/// with ILLEGAL REQUEST sense data, which is passed to its `command_rejected`
+/// hook. Errors that stand for a status of their own, such as
+/// a `ReservationConflictError`, are reported with that status, while other
+/// errors are passed through.
+pub(crate) fn command_status<R: ScsiResponder + ?Sized>(
+    responder: &mut R,
+    result: Result<(CommandStatusWrapper, usize), ScsiError>,
+) -> Result<(ScsiStatus, Option<RequestSenseResponse>, usize), ScsiError> {
+    match result {
+        Ok((csw, transferred)) if csw.status == CommandStatusWrapper::COMMAND_PASSED => {
+            Ok((ScsiStatus::Good, None, transferred))
+        }
+        Ok((_, transferred)) => {
+            let request = RequestSenseCommand::new(RequestSenseResponse::SIZE as u8);
+            let sense = responder.sense_data(request).ok().map(|(sense, _)| sense);
+            Ok((ScsiStatus::CheckCondition, sense, transferred))
+        }
+        Err(ref err) if err.cause == ErrorCause::UnsupportedOperationError => {
+            let sense = reject_command(responder, err);
+            Ok((ScsiStatus::CheckCondition, Some(sense), 0))
+        }
+        Err(err) => match ScsiStatus::from_error(&err) {
+            Some(status) => Ok((status, None, 0)),
+            None => Err(err),
+        },
+    }
+}
+
+/// Checks that the whole CSW was sent.
+fn check_csw_sent(csw_sent: usize, csw_pushed: usize) -> Result<(), ScsiError> {
+    if csw_sent != csw_pushed {
+        return Err(ScsiError::from_cause(ErrorCause::UsbTransferError {
+            direction: UsbTransferDirection::Out,
+        }));
+    }
+    Ok(())
+}
+
+/// Runs a parsed command against `responder`, moving any data through `data`.
+///
+/// Returns the command's CSW along with the number of bytes actually
+/// transferred; the caller is responsible for reporting the status in whatever
+/// form its transport uses.
+pub(crate) fn dispatch_command<R: ScsiResponder + ?Sized, D: DataPhase>(
+    responder: &mut R,
+    command: ScsiCommand,
+    expected: usize,
+    data: &mut D,
+) -> Result<(CommandStatusWrapper, usize), ScsiError> {
+    run_blocking(dispatch_command_async(
+        responder,
+        command,
+        expected,
+        &mut BlockingData(data),
+    ))
+}
+
+/// Runs a dispatch whose data phase is a `BlockingData`.
+fn run_blocking<F: Future>(dispatch: F) -> F::Output {
+    let dispatch = pin!(dispatch);
+    // Blocking data phases finish every transfer before returning, so the
+    // dispatch runs to completion the first time it is polled.
+    match dispatch.poll(&mut Context::from_waker(Waker::noop())) {
+        Poll::Ready(result) => result,
+        Poll::Pending => unreachable!("blocking data phases never wait"),
+    }
+}
+
+/// The asynchronous version of `dispatch_command`, which awaits each transfer
+/// on `data`.
+pub(crate) async fn dispatch_command_async<R: ScsiResponder + ?Sized, D: AsyncDataPhase>(
+    responder: &mut R,
+    command: ScsiCommand,
+    expected: usize,
+    data: &mut D,
+) -> Result<(CommandStatusWrapper, usize), ScsiError> {
+    responder.check_access(command.access())?;
+    let (csw, transferred) = match command {
+        ScsiCommand::ReadCapacity(rcc) => {
+            let (response, csw) = responder.read_capacity(rcc)?;
+            send_response(data, expected, &response, csw).await?
+        }
+        ScsiCommand::ReadCapacity16(rcc) => {
+            let (response, csw) = responder.read_capacity16(rcc)?;
+            send_response(data, expected, &response, csw).await?
+        }
+        ScsiCommand::Inquiry(ic) => {
+            let (response, csw) = responder.inquiry(ic)?;
+            let standard = StandardInquiryData::new(response, responder.identification());
+            send_response(data, expected, &standard, csw).await?
+        }
+        ScsiCommand::VpdInquiry(vc) if vc.page_code == BlockLimitsPage::PAGE_CODE => {
+            let (response, csw) = responder.block_limits(vc)?;
+            send_response(data, expected, &response, csw).await?
+        }
+        ScsiCommand::VpdInquiry(_) => {
+            // Invalid field in CDB; Block Limits is the only page supported.
+            (fail_illegal_request(responder, 0x24), 0)
+        }
+        ScsiCommand::RequestSense(rc) => {
+            let (response, csw) = responder.sense_data(rc)?;
+            send_response(data, expected, &response, csw).await?
+        }
+        ScsiCommand::TestUnitReady(tc) => (responder.test_unit_ready(tc)?, 0),
+        ScsiCommand::StartStopUnit(sc) => (responder.start_stop_unit(sc)?, 0),
+        ScsiCommand::SynchronizeCache(sc) => (responder.synchronize_cache(sc)?, 0),
+        ScsiCommand::Unmap(uc) => {
+            let mut block = responder.memory_buffer();
+            let (list, received) = receive_parameters(data, expected, block.as_mut()).await?;
+            match parse_parameters(list, received, UnmapCommand::pull_parameter_list) {
+                Ok(descriptors) => (responder.unmap(uc, descriptors)?, received),
+                Err(asc) => (fail_illegal_request(responder, asc), received),
+            }
+        }
+        ScsiCommand::FormatUnit(fc) => {
+            let mut block = responder.memory_buffer();
+            let (list, received) = receive_parameters(data, expected, block.as_mut()).await?;
+            match parse_parameters(list, received, |list| fc.pull_parameter_list(list)) {
+                Ok(parameters) => (responder.format_unit(fc, parameters)?, received),
+                Err(asc) => (fail_illegal_request(responder, asc), received),
+            }
+        }
+        ScsiCommand::Sanitize(sc) => {
+            let mut block = responder.memory_buffer();
+            let (list, received) = receive_parameters(data, expected, block.as_mut()).await?;
+            match parse_parameters(list, received, |list| sc.pull_parameter_list(list)) {
+                Ok(parameters) => (responder.sanitize(sc, parameters)?, received),
+                Err(asc) => (fail_illegal_request(responder, asc), received),
+            }
+        }
+        ScsiCommand::WriteSame10(wc) => write_same(responder, wc.into(), expected, data).await?,
+        ScsiCommand::WriteSame16(wc) => write_same(responder, wc, expected, data).await?,
+        ScsiCommand::CompareAndWrite(cc) => {
+            compare_and_write(responder, cc, expected, data).await?
+        }
+        ScsiCommand::Verify10(vc) => verify(responder, vc.into(), expected, data).await?,
+        ScsiCommand::Verify16(vc) => verify(responder, vc, expected, data).await?,
+        ScsiCommand::PreventAllowMediumRemoval(pc) => {
+            (responder.prevent_allow_medium_removal(pc)?, 0)
+        }
+        ScsiCommand::PersistentReserveIn(pc) => {
+            let (response, csw) = responder.persistent_reserve_in(pc)?;
+            send_response(data, expected, &response, csw).await?
+        }
+        ScsiCommand::PersistentReserveOut(pc) => {
+            let mut block = responder.memory_buffer();
+            let (list, received) = receive_parameters(data, expected, block.as_mut()).await?;
+            match parse_parameters(
+                list,
+                received,
+                PersistentReserveOutParameters::pull_from_buffer,
+            ) {
+                Ok(parameters) => (responder.persistent_reserve_out(pc, parameters)?, received),
+                Err(asc) => (fail_illegal_request(responder, asc), received),
+            }
+        }
+        ScsiCommand::LogSense(lc) => {
+            let (response, csw) = responder.log_sense(lc)?;
+            send_response(data, expected, &response, csw).await?
+        }
+        ScsiCommand::LogSelect(lc) => {
+            let mut block = responder.memory_buffer();
+            let (list, received) = receive_parameters(data, expected, block.as_mut()).await?;
+            if received > list.len() {
+                // Parameter list length error.
+                (fail_illegal_request(responder, 0x1a), received)
+            } else {
+                (responder.log_select(lc, list)?, received)
+            }
+        }
+        ScsiCommand::Reserve6(rc) => (responder.reserve6(rc)?, 0),
+        ScsiCommand::Release6(rc) => (responder.release6(rc)?, 0),
+        ScsiCommand::WriteBuffer(wc) => write_buffer(responder, wc, expected, data).await?,
+        ScsiCommand::ReadBuffer(rc) => match rc.mode {
+            ReadBufferMode::Descriptor => {
+                let (response, csw) = responder.read_buffer_descriptor(rc)?;
+                send_response(data, expected, &response, csw).await?
+            }
+            ReadBufferMode::EchoBufferDescriptor => {
+                let (response, csw) = responder.read_echo_buffer_descriptor(rc)?;
+                send_response(data, expected, &response, csw).await?
+            }
+            _ => read_buffer(responder, rc, expected, data).await?,
+        },
+        ScsiCommand::ReadToc(tc) => {
+            let (response, csw) = responder.read_toc(tc)?;
+            send_response(data, expected, &response, csw).await?
+        }
+        ScsiCommand::GetConfiguration(gc) => {
+            let (response, csw) = responder.get_configuration(gc)?;
+            send_response(data, expected, &response, csw).await?
+        }
+        ScsiCommand::GetEventStatusNotification(gc) => {
+            let (response, csw) = responder.get_event_status_notification(gc)?;
+            send_response(data, expected, &response, csw).await?
+        }
+        ScsiCommand::ReadDiscInformation(dc) => {
+            let (response, csw) = responder.read_disc_information(dc)?;
+            send_response(data, expected, &response, csw).await?
+        }
+        ScsiCommand::Read10(rten) => {
+            responder.read10_start(rten)?;
+            send_blocks(responder, expected, data).await?
+        }
+        ScsiCommand::Read16(rc) => {
+            responder.read16_start(rc)?;
+            send_blocks(responder, expected, data).await?
+        }
+        ScsiCommand::Write10(wten) => {
+            responder.write10_start(wten)?;
+            receive_blocks(responder, expected, data).await?
+        }
+        ScsiCommand::Write16(wc) => {
+            responder.write16_start(wc)?;
+            receive_blocks(responder, expected, data).await?
+        }
+    };
+    Ok((csw, transferred))
+}
+
+/// Sends the blocks of a read started with `read10_start` or `read16_start`.
+async fn send_blocks<R: ScsiResponder + ?Sized, D: AsyncDataPhase>(
+    responder: &mut R,
+    expected: usize,
+    data: &mut D,
+) -> Result<(CommandStatusWrapper, usize), ScsiError> {
+    let mut block = responder.memory_buffer();
+    let block_ref = block.as_mut();
+    let mut sent = 0;
+    loop {
+        if let Some(csw) = responder.read_block(block_ref)? {
+            return Ok((csw, sent));
+        }
+        let to_send = block_ref.len().min(expected - sent);
+        if to_send > 0 {
+            sent += data.send(&block_ref[..to_send]).await?;
+        }
+    }
+}
+
+/// Receives the blocks of a write started with `write10_start` or
+/// `write16_start`.
+async fn receive_blocks<R: ScsiResponder + ?Sized, D: AsyncDataPhase>(
+    responder: &mut R,
+    expected: usize,
+    data: &mut D,
+) -> Result<(CommandStatusWrapper, usize), ScsiError> {
+    let mut block = responder.memory_buffer();
+    let block_ref = block.as_mut();
+    let mut received = 0;
+    let mut accepted = 0;
+    let mut status = None;
+    while received < expected {
+        let to_read = block_ref.len().min(expected - received);
+        let read = data.receive(&mut block_ref[..to_read]).await?;
+        if read == 0 {
             return Err(ScsiError::from_cause(ErrorCause::UsbTransferError {
                 direction: UsbTransferDirection::In,
             }));
         }
-        let cbw = CommandBlockWrapper::pull_from_buffer(&command_buffer)?;
-        let command = ScsiCommand::pull_from_buffer(&command_buffer)?;
-        let mut csw: CommandStatusWrapper = match command {
-            ScsiCommand::ReadCapacity(rcc) => {
-                let (response, csw) = self.read_capacity(rcc)?;
-                let _response_pushed = response.push_to_buffer(&mut command_buffer)?;
-                let _response_sent = channel.out_transfer(&command_buffer)?;
-                csw
-            }
-            ScsiCommand::Inquiry(ic) => {
-                let (response, csw) = self.inquiry(ic)?;
-                let _response_pushed = response.push_to_buffer(&mut command_buffer)?;
-                let _response_sent = channel.out_transfer(&command_buffer)?;
-                csw
-            }
-            ScsiCommand::RequestSense(rc) => self.request_sense(rc)?,
-            ScsiCommand::TestUnitReady(tc) => self.test_unit_ready(tc)?,
-            ScsiCommand::Read10(rten) => {
-                self.read10_start(rten)?;
-                let mut block = self.memory_buffer();
-                let mut block_ref = block.as_mut();
-                loop {
-                    let csw_opt = self.read_block(&mut block_ref)?;
-                    let _out_len = channel.out_transfer(&block_ref)?;
-                    if let Some(csw) = csw_opt {
-                        break csw;
-                    }
-                }
-            }
-            ScsiCommand::Write10(wten) => {
-                self.write10_start(wten)?;
-                let mut block = self.memory_buffer();
-                let mut block_ref = block.as_mut();
-                loop {
-                    let _out_len = channel.in_transfer(&mut block_ref)?;
-                    let csw_opt = self.write_block(&block_ref)?;
-                    if let Some(csw) = csw_opt {
-                        break csw;
-                    }
-                }
-            } //_ => unimplemented!()
+        received += read;
+        if status.is_none() {
+            status = responder.write_block(&block_ref[..read])?;
+            accepted += read;
+        }
+    }
+    let csw = match status {
+        Some(csw) => csw,
+        None => responder.write_end()?,
+    };
+    Ok((csw, accepted))
+}
+
+/// Receives the `expected` bytes the host sends along with a command other
+/// than a write, keeping as many of them as fit in `buffer`.
+///
+/// Returns the bytes kept along with the number of bytes received.
+async fn receive_parameters<'b, D: AsyncDataPhase>(
+    data: &mut D,
+    expected: usize,
+    buffer: &'b mut [u8],
+) -> Result<(&'b [u8], usize), ScsiError> {
+    let kept = expected.min(buffer.len());
+    let mut received = 0;
+    let mut overflow = [0; 64];
+    while received < expected {
+        let read = if received < kept {
+            data.receive(&mut buffer[received..kept]).await?
+        } else {
+            let length = overflow.len().min(expected - received);
+            data.receive(&mut overflow[..length]).await?
         };
-        csw.tag = cbw.tag;
-        let csw_pushed = csw.push_to_buffer(&mut command_buffer)?;
-        if csw_pushed != 13 {
-            return Err(ScsiError::from_cause(ErrorCause::BufferTooSmallError {
-                expected: 13,
-                actual: csw_pushed,
+        if read == 0 {
+            return Err(ScsiError::from_cause(ErrorCause::UsbTransferError {
+                direction: UsbTransferDirection::In,
+            }));
+        }
+        received += read;
+    }
+    Ok((&buffer[..kept], received))
+}
+
+/// Parses a parameter list kept by `receive_parameters` with `parse`.
+///
+/// Returns the additional sense code to fail the command with if the host
+/// sent more than was kept or the list is malformed.
+fn parse_parameters<'b, T>(
+    list: &'b [u8],
+    received: usize,
+    parse: impl FnOnce(&'b [u8]) -> Result<T, ScsiError>,
+) -> Result<T, u8> {
+    if received > list.len() {
+        // Parameter list length error.
+        return Err(0x1a);
+    }
+    parse(list).map_err(|err| match err.cause {
+        ErrorCause::BufferTooSmallError { .. } => 0x1a,
+        // Invalid field in parameter list.
+        _ => 0x26,
+    })
+}
+
+/// Receives the block sent along with a WRITE SAME command and passes it on
+/// to the responder.
+async fn write_same<R: ScsiResponder + ?Sized, D: AsyncDataPhase>(
+    responder: &mut R,
+    command: WriteSame16Command,
+    expected: usize,
+    data: &mut D,
+) -> Result<(CommandStatusWrapper, usize), ScsiError> {
+    let mut block = responder.memory_buffer();
+    let (block, received) = receive_parameters(data, expected, block.as_mut()).await?;
+    if received > block.len() {
+        // Parameter list length error.
+        return Ok((fail_illegal_request(responder, 0x1a), received));
+    }
+    Ok((responder.write_same(command, block)?, received))
+}
+
+/// Fails a command the responder cannot be asked to run, passing ILLEGAL
+/// REQUEST sense data with the additional sense code `asc` to its
+/// `command_rejected` hook.
+pub(crate) fn fail_illegal_request<R: ScsiResponder + ?Sized>(
+    responder: &mut R,
+    asc: u8,
+) -> CommandStatusWrapper {
+    responder.command_rejected(RequestSenseResponse::new(
+        RequestSenseResponse::ILLEGAL_REQUEST,
+        asc,
+        0,
+    ));
+    CommandStatusWrapper {
+        status: CommandStatusWrapper::COMMAND_FAILED,
+        ..Default::default()
+    }
+}
+
+/// Receives both halves of the data sent along with a COMPARE AND WRITE
+/// command and passes them on to the responder.
+async fn compare_and_write<R: ScsiResponder + ?Sized, D: AsyncDataPhase>(
+    responder: &mut R,
+    command: CompareAndWriteCommand,
+    expected: usize,
+    data: &mut D,
+) -> Result<(CommandStatusWrapper, usize), ScsiError> {
+    let half = expected / 2;
+    let mut compare_block = responder.memory_buffer();
+    let mut write_block = responder.memory_buffer();
+    let (compare, compare_received) =
+        receive_parameters(data, half, compare_block.as_mut()).await?;
+    let (new, new_received) =
+        receive_parameters(data, expected - half, write_block.as_mut()).await?;
+    if compare.len() < compare_received || new.len() < new_received {
+        // Invalid field in CDB; the blocks don't fit in the responder's buffer.
+        let csw = fail_illegal_request(responder, 0x24);
+        return Ok((csw, compare_received + new_received));
+    }
+    let csw = responder.compare_and_write(command, compare, new)?;
+    Ok((csw, compare_received + new_received))
+}
+
+/// Receives the data sent along with a VERIFY command, passing it on to the
+/// responder one block at a time.
+async fn verify<R: ScsiResponder + ?Sized, D: AsyncDataPhase>(
+    responder: &mut R,
+    command: Verify16Command,
+    expected: usize,
+    data: &mut D,
+) -> Result<(CommandStatusWrapper, usize), ScsiError> {
+    let mut block = responder.memory_buffer();
+    let block_ref = block.as_mut();
+    let repeats = match command.byte_check {
+        VerifyByteCheck::CompareSingleBlock => u64::from(command.transfer_blocks),
+        _ => 1,
+    };
+    let mut block_address = command.block_address;
+    let mut received = 0;
+    let mut status = None;
+    while received < expected {
+        let to_read = block_ref.len().min(expected - received);
+        let read = data.receive(&mut block_ref[..to_read]).await?;
+        if read == 0 {
+            return Err(ScsiError::from_cause(ErrorCause::UsbTransferError {
+                direction: UsbTransferDirection::In,
             }));
         }
-        let csw_sent = channel.out_transfer(&command_buffer)?;
-        if csw_sent != 31 {
-            return Err(ScsiError::from_cause(ErrorCause::BufferTooSmallError {
-                expected: 31,
-                actual: csw_sent,
+        received += read;
+        let mut compared = 0;
+        while status.is_none() && compared < repeats {
+            status = responder.verify(command, block_address, &block_ref[..read])?;
+            block_address += 1;
+            compared += 1;
+        }
+    }
+    let csw = match status {
+        Some(csw) => csw,
+        None => responder
+            .verify(command, block_address, &[])?
+            .unwrap_or_default(),
+    };
+    Ok((csw, received))
+}
+
+/// Receives the data sent along with a WRITE BUFFER command, passing it on to
+/// the responder one chunk at a time.
+async fn write_buffer<R: ScsiResponder + ?Sized, D: AsyncDataPhase>(
+    responder: &mut R,
+    command: WriteBufferCommand,
+    expected: usize,
+    data: &mut D,
+) -> Result<(CommandStatusWrapper, usize), ScsiError> {
+    let mut block = responder.memory_buffer();
+    let block_ref = block.as_mut();
+    let mut offset = command.buffer_offset;
+    let mut received = 0;
+    let mut status = None;
+    while received < expected {
+        let to_read = block_ref.len().min(expected - received);
+        let read = data.receive(&mut block_ref[..to_read]).await?;
+        if read == 0 {
+            return Err(ScsiError::from_cause(ErrorCause::UsbTransferError {
+                direction: UsbTransferDirection::In,
             }));
         }
-        Ok(())
+        received += read;
+        if status.is_none() {
+            status = responder.write_buffer(command, offset, &block_ref[..read])?;
+            offset += read as u32;
+        }
+    }
+    let csw = match status {
+        Some(csw) => csw,
+        None => responder
+            .write_buffer(command, offset, &[])?
+            .unwrap_or_default(),
+    };
+    Ok((csw, received))
+}
+
+/// Sends the contents of a buffer asked for by a READ BUFFER command to the
+/// host, pulling it from the responder one chunk at a time.
+async fn read_buffer<R: ScsiResponder + ?Sized, D: AsyncDataPhase>(
+    responder: &mut R,
+    command: ReadBufferCommand,
+    expected: usize,
+    data: &mut D,
+) -> Result<(CommandStatusWrapper, usize), ScsiError> {
+    let mut block = responder.memory_buffer();
+    let block_ref = block.as_mut();
+    let mut sent = 0;
+    while sent < expected {
+        let to_send = block_ref.len().min(expected - sent);
+        let offset = command.buffer_offset + sent as u32;
+        if let Some(csw) = responder.read_buffer(command, offset, &mut block_ref[..to_send])? {
+            return Ok((csw, sent));
+        }
+        let chunk_sent = data.send(&block_ref[..to_send]).await?;
+        sent += chunk_sent;
+        if chunk_sent < to_send {
+            // The data phase cannot take any more; the rest is left as residue.
+            break;
+        }
+    }
+    Ok((CommandStatusWrapper::default(), sent))
+}
+
+/// Sends a command's response struct to the host if `csw` reports success,
+/// truncating it to the `expected` length the host asked for.
+///
+/// Returns the CSW along with the number of bytes actually sent.
+async fn send_response<D: AsyncDataPhase, R: BufferPushable>(
+    data: &mut D,
+    expected: usize,
+    response: &R,
+    csw: CommandStatusWrapper,
+) -> Result<(CommandStatusWrapper, usize), ScsiError> {
+    if csw.status != CommandStatusWrapper::COMMAND_PASSED {
+        return Ok((csw, 0));
+    }
+    let mut response_buffer = [0; RESPONSE_BUFFER_SIZE];
+    let pushed = response.push_to_buffer(&mut response_buffer[..])?;
+    let to_send = pushed.min(expected);
+    if to_send == 0 {
+        return Ok((csw, 0));
+    }
+    let sent = data.send(&response_buffer[..to_send]).await?;
+    Ok((csw, sent))
+}
+
+/// Carries the data phase of a command between the responder and the host,
+/// so that the same dispatch code can serve every transport.
+pub(crate) trait DataPhase {
+    /// Sends `data` to the host, returning the number of bytes sent.
+    fn send(&mut self, data: &[u8]) -> Result<usize, ScsiError>;
+
+    /// Fills `buffer` with data from the host, returning the number of bytes
+    /// read.
+    fn receive(&mut self, buffer: &mut [u8]) -> Result<usize, ScsiError>;
+}
+
+/// The asynchronous counterpart of `DataPhase`.
+pub(crate) trait AsyncDataPhase {
+    /// Sends `data` to the host, returning the number of bytes sent.
+    async fn send(&mut self, data: &[u8]) -> Result<usize, ScsiError>;
+
+    /// Fills `buffer` with data from the host, returning the number of bytes
+    /// read.
+    async fn receive(&mut self, buffer: &mut [u8]) -> Result<usize, ScsiError>;
+}
+
+/// Runs a blocking `DataPhase` where an `AsyncDataPhase` is expected.
+struct BlockingData<'a, D: DataPhase>(&'a mut D);
+
+impl<'a, D: DataPhase> AsyncDataPhase for BlockingData<'a, D> {
+    async fn send(&mut self, data: &[u8]) -> Result<usize, ScsiError> {
+        self.0.send(data)
+    }
+
+    async fn receive(&mut self, buffer: &mut [u8]) -> Result<usize, ScsiError> {
+        self.0.receive(buffer)
+    }
+}
+
+/// Counts the bytes received through a data phase, so whatever the host has
+/// yet to send can be drained after a command fails.
+struct CountingData<'a, D: AsyncDataPhase> {
+    data: &'a mut D,
+    received: usize,
+}
+
+impl<'a, D: AsyncDataPhase> AsyncDataPhase for CountingData<'a, D> {
+    async fn send(&mut self, data: &[u8]) -> Result<usize, ScsiError> {
+        self.data.send(data).await
+    }
+
+    async fn receive(&mut self, buffer: &mut [u8]) -> Result<usize, ScsiError> {
+        let read = self.data.receive(buffer).await?;
+        self.received += read;
+        Ok(read)
+    }
+}
+
+/// The Bulk-Only data phase, which shares its channel with the CBW and CSW.
+struct BulkOnlyData<'a, C: CommunicationChannel + 'a>(&'a mut C);
+
+impl<'a, C: CommunicationChannel> DataPhase for BulkOnlyData<'a, C> {
+    fn send(&mut self, data: &[u8]) -> Result<usize, ScsiError> {
+        self.0.out_transfer(data)
+    }
+
+    fn receive(&mut self, buffer: &mut [u8]) -> Result<usize, ScsiError> {
+        fill_from_channel(self.0, buffer)
+    }
+}
+
+/// The Bulk-Only data phase over an `AsyncCommunicationChannel`.
+struct AsyncBulkOnlyData<'a, C: AsyncCommunicationChannel>(&'a mut C);
+
+impl<'a, C: AsyncCommunicationChannel> AsyncDataPhase for AsyncBulkOnlyData<'a, C> {
+    async fn send(&mut self, data: &[u8]) -> Result<usize, ScsiError> {
+        self.0.out_transfer(data).await
+    }
+
+    async fn receive(&mut self, buffer: &mut [u8]) -> Result<usize, ScsiError> {
+        let mut read = 0;
+        while read < buffer.len() {
+            let cur = self.0.in_transfer(&mut buffer[read..]).await?;
+            if cur == 0 {
+                return Err(ScsiError::from_cause(ErrorCause::UsbTransferError {
+                    direction: UsbTransferDirection::In,
+                }));
+            }
+            read += cur;
+        }
+        Ok(read)
     }
 }
+
+/// Reads from `channel` until `buffer` is full, returning the number of bytes read.
+pub(crate) fn fill_from_channel<C: CommunicationChannel>(
+    channel: &mut C,
+    buffer: &mut [u8],
+) -> Result<usize, ScsiError> {
+    let mut read = 0;
+    while read < buffer.len() {
+        let cur = channel.in_transfer(&mut buffer[read..])?;
+        if cur == 0 {
+            return Err(ScsiError::from_cause(ErrorCause::UsbTransferError {
+                direction: UsbTransferDirection::In,
+            }));
+        }
+        read += cur;
+    }
+    Ok(read)
+}
+
+/// Works out the length of the data phase of a read or write of `blocks`
+/// blocks, failing if it does not fit in a CBW.
+fn transfer_bytes(blocks: u32, block_size: u32) -> Result<u32, ScsiError> {
+    blocks.checked_mul(block_size).ok_or_else(|| {
+        ScsiError::from_cause(ErrorCause::TransferTooLargeError {
+            actual: blocks as usize,
+            max: (u32::MAX / block_size) as usize,
+        })
+    })
+}
+
 #[derive(Copy, Clone, Debug, Eq, PartialEq)]
-enum ScsiCommand {
+pub(crate) enum ScsiCommand {
+    CompareAndWrite(CompareAndWriteCommand),
+    FormatUnit(FormatUnitCommand),
+    GetConfiguration(GetConfigurationCommand),
+    GetEventStatusNotification(GetEventStatusNotificationCommand),
     Inquiry(InquiryCommand),
+    LogSelect(LogSelectCommand),
+    LogSense(LogSenseCommand),
+    PersistentReserveIn(PersistentReserveInCommand),
+    PersistentReserveOut(PersistentReserveOutCommand),
+    PreventAllowMediumRemoval(PreventAllowMediumRemovalCommand),
     Read10(Read10Command),
+    Read16(Read16Command),
+    ReadBuffer(ReadBufferCommand),
     ReadCapacity(ReadCapacityCommand),
+    ReadCapacity16(ReadCapacity16Command),
+    ReadDiscInformation(ReadDiscInformationCommand),
+    ReadToc(ReadTocCommand),
+    Release6(Release6Command),
     RequestSense(RequestSenseCommand),
+    Reserve6(Reserve6Command),
+    Sanitize(SanitizeCommand),
+    StartStopUnit(StartStopUnitCommand),
+    SynchronizeCache(SynchronizeCache10Command),
     TestUnitReady(TestUnitReady),
+    Unmap(UnmapCommand),
+    Verify10(Verify10Command),
+    Verify16(Verify16Command),
+    VpdInquiry(VpdInquiryCommand),
     Write10(Write10Command),
+    Write16(Write16Command),
+    WriteBuffer(WriteBufferCommand),
+    WriteSame10(WriteSame10Command),
+    WriteSame16(WriteSame16Command),
+}
+
+impl ScsiCommand {
+    /// Parses a bare command block, such as the one carried by a UAS
+    /// `CommandIu`, by wrapping it in the CBW a Bulk-Only host would have sent
+    /// along with it.
+    ///
+    /// `block_size` is used to work out the transfer length of reads and writes.
+    pub(crate) fn from_cdb(cdb: &[u8], block_size: u32) -> Result<ScsiCommand, ScsiError> {
+        if cdb.is_empty() {
+            return Err(ScsiError::from_cause(ErrorCause::ParseError));
+        }
+        let mut padded = [0; 16];
+        let cdb_length = cdb.len().min(padded.len());
+        padded[..cdb_length].copy_from_slice(&cdb[..cdb_length]);
+        let cdb = &padded[..];
+        let opcode = cdb[0];
+        let six_byte_allocation = u32::from(cdb[4]);
+        let ten_byte_allocation = u32::from(BE::read_u16(&cdb[7..]));
+        let (transfer_length, direction, cb_length) = if opcode == Read10Command::opcode() {
+            (
+                transfer_bytes(ten_byte_allocation, block_size)?,
+                Direction::IN,
+                Read10Command::length(),
+            )
+        } else if opcode == Write10Command::opcode() {
+            (
+                transfer_bytes(ten_byte_allocation, block_size)?,
+                Direction::OUT,
+                Write10Command::length(),
+            )
+        } else if opcode == Read16Command::opcode() {
+            (
+                transfer_bytes(BE::read_u32(&cdb[10..]), block_size)?,
+                Direction::IN,
+                Read16Command::length(),
+            )
+        } else if opcode == Write16Command::opcode() {
+            (
+                transfer_bytes(BE::read_u32(&cdb[10..]), block_size)?,
+                Direction::OUT,
+                Write16Command::length(),
+            )
+        } else if opcode == ReadCapacityCommand::opcode() {
+            (
+                ReadCapacityCommand::new().wrapper().data_transfer_length,
+                Direction::IN,
+                ReadCapacityCommand::length(),
+            )
+        } else if opcode == ReadCapacity16Command::opcode() {
+            (
+                BE::read_u32(&cdb[10..]),
+                Direction::IN,
+                ReadCapacity16Command::length(),
+            )
+        } else if opcode == InquiryCommand::opcode() {
+            (six_byte_allocation, Direction::IN, InquiryCommand::length())
+        } else if opcode == RequestSenseCommand::opcode() {
+            (
+                six_byte_allocation,
+                Direction::IN,
+                RequestSenseCommand::length(),
+            )
+        } else if opcode == GetConfigurationCommand::opcode() {
+            (
+                ten_byte_allocation,
+                Direction::IN,
+                GetConfigurationCommand::length(),
+            )
+        } else if opcode == GetEventStatusNotificationCommand::opcode() {
+            (
+                ten_byte_allocation,
+                Direction::IN,
+                GetEventStatusNotificationCommand::length(),
+            )
+        } else if opcode == ReadDiscInformationCommand::opcode() {
+            (
+                ten_byte_allocation,
+                Direction::IN,
+                ReadDiscInformationCommand::length(),
+            )
+        } else if opcode == ReadTocCommand::opcode() {
+            (ten_byte_allocation, Direction::IN, ReadTocCommand::length())
+        } else if opcode == TestUnitReady::opcode() {
+            (0, Direction::NONE, TestUnitReady::length())
+        } else if opcode == StartStopUnitCommand::opcode() {
+            (0, Direction::NONE, StartStopUnitCommand::length())
+        } else if opcode == SynchronizeCache10Command::opcode() {
+            (0, Direction::NONE, SynchronizeCache10Command::length())
+        } else if opcode == PreventAllowMediumRemovalCommand::opcode() {
+            (
+                0,
+                Direction::NONE,
+                PreventAllowMediumRemovalCommand::length(),
+            )
+        } else if opcode == Reserve6Command::opcode() {
+            (0, Direction::NONE, Reserve6Command::length())
+        } else if opcode == Release6Command::opcode() {
+            (0, Direction::NONE, Release6Command::length())
+        } else if opcode == WriteBufferCommand::opcode() {
+            let parameter_list_length = BE::read_u24(&cdb[6..]);
+            let direction = if parameter_list_length == 0 {
+                Direction::NONE
+            } else {
+                Direction::OUT
+            };
+            (
+                parameter_list_length,
+                direction,
+                WriteBufferCommand::length(),
+            )
+        } else if opcode == ReadBufferCommand::opcode() {
+            (
+                BE::read_u24(&cdb[6..]),
+                Direction::IN,
+                ReadBufferCommand::length(),
+            )
+        } else if opcode == FormatUnitCommand::opcode() {
+            // The parameter list length is not part of the command block, so
+            // only the header is expected.
+            let header_size = match cdb[1] & 0x30 {
+                0x10 => FormatUnitCommand::SHORT_HEADER_SIZE as u32,
+                0x30 => FormatUnitCommand::LONG_HEADER_SIZE as u32,
+                _ => 0,
+            };
+            let direction = if header_size == 0 {
+                Direction::NONE
+            } else {
+                Direction::OUT
+            };
+            (header_size, direction, FormatUnitCommand::length())
+        } else if opcode == SanitizeCommand::opcode() {
+            let direction = if ten_byte_allocation == 0 {
+                Direction::NONE
+            } else {
+                Direction::OUT
+            };
+            (ten_byte_allocation, direction, SanitizeCommand::length())
+        } else if opcode == UnmapCommand::opcode() {
+            (ten_byte_allocation, Direction::OUT, UnmapCommand::length())
+        } else if opcode == LogSenseCommand::opcode() {
+            (
+                ten_byte_allocation,
+                Direction::IN,
+                LogSenseCommand::length(),
+            )
+        } else if opcode == LogSelectCommand::opcode() {
+            let direction = if ten_byte_allocation == 0 {
+                Direction::NONE
+            } else {
+                Direction::OUT
+            };
+            (ten_byte_allocation, direction, LogSelectCommand::length())
+        } else if opcode == PersistentReserveInCommand::opcode() {
+            (
+                ten_byte_allocation,
+                Direction::IN,
+                PersistentReserveInCommand::length(),
+            )
+        } else if opcode == PersistentReserveOutCommand::opcode() {
+            (
+                BE::read_u32(&cdb[5..]),
+                Direction::OUT,
+                PersistentReserveOutCommand::length(),
+            )
+        } else if opcode == CompareAndWriteCommand::opcode() {
+            (
+                transfer_bytes(2 * u32::from(cdb[13]), block_size)?,
+                Direction::OUT,
+                CompareAndWriteCommand::length(),
+            )
+        } else if opcode == Verify10Command::opcode() {
+            let (length, direction) =
+                VerifyByteCheck::from_bits(cdb[1])?.data_phase(ten_byte_allocation, block_size);
+            (length, direction, Verify10Command::length())
+        } else if opcode == Verify16Command::opcode() {
+            let blocks = BE::read_u32(&cdb[10..]);
+            let (length, direction) =
+                VerifyByteCheck::from_bits(cdb[1])?.data_phase(blocks, block_size);
+            (length, direction, Verify16Command::length())
+        } else if opcode == WriteSame10Command::opcode() {
+            (block_size, Direction::OUT, WriteSame10Command::length())
+        } else if opcode == WriteSame16Command::opcode() && cdb[1] & 0x1 != 0 {
+            (0, Direction::NONE, WriteSame16Command::length())
+        } else if opcode == WriteSame16Command::opcode() {
+            (block_size, Direction::OUT, WriteSame16Command::length())
+        } else {
+            return Err(ScsiError::from_cause(ErrorCause::UnsupportedOperationError));
+        };
+        let wrapper = CommandBlockWrapper::new(transfer_length, direction, 0, cb_length);
+        let mut buffer = [0; 31];
+        let offset = wrapper.push_to_buffer(&mut buffer[..])?;
+        buffer[offset..].copy_from_slice(cdb);
+        ScsiCommand::pull_from_buffer(buffer)
+    }
+
+    /// How this command accesses the medium, which decides whether it
+    /// conflicts with a reservation.
+    pub(crate) fn access(&self) -> MediumAccess {
+        match self {
+            ScsiCommand::Read10(_)
+            | ScsiCommand::Read16(_)
+            | ScsiCommand::ReadBuffer(_)
+            | ScsiCommand::ReadDiscInformation(_)
+            | ScsiCommand::ReadToc(_)
+            | ScsiCommand::Verify10(_)
+            | ScsiCommand::Verify16(_) => MediumAccess::Read,
+            ScsiCommand::CompareAndWrite(_)
+            | ScsiCommand::FormatUnit(_)
+            | ScsiCommand::Sanitize(_)
+            | ScsiCommand::StartStopUnit(_)
+            | ScsiCommand::SynchronizeCache(_)
+            | ScsiCommand::Unmap(_)
+            | ScsiCommand::Write10(_)
+            | ScsiCommand::Write16(_)
+            | ScsiCommand::WriteBuffer(_)
+            | ScsiCommand::WriteSame10(_)
+            | ScsiCommand::WriteSame16(_) => MediumAccess::Write,
+            _ => MediumAccess::None,
+        }
+    }
+
+    /// The CBW a Bulk-Only host would send along with this command.
+    pub(crate) fn wrapper(&self) -> CommandBlockWrapper {
+        match self {
+            ScsiCommand::CompareAndWrite(c) => c.wrapper(),
+            ScsiCommand::FormatUnit(c) => c.wrapper(),
+            ScsiCommand::GetConfiguration(c) => c.wrapper(),
+            ScsiCommand::GetEventStatusNotification(c) => c.wrapper(),
+            ScsiCommand::Inquiry(c) => c.wrapper(),
+            ScsiCommand::LogSelect(c) => c.wrapper(),
+            ScsiCommand::LogSense(c) => c.wrapper(),
+            ScsiCommand::PersistentReserveIn(c) => c.wrapper(),
+            ScsiCommand::PersistentReserveOut(c) => c.wrapper(),
+            ScsiCommand::PreventAllowMediumRemoval(c) => c.wrapper(),
+            ScsiCommand::Read10(c) => c.wrapper(),
+            ScsiCommand::Read16(c) => c.wrapper(),
+            ScsiCommand::ReadBuffer(c) => c.wrapper(),
+            ScsiCommand::ReadCapacity(c) => c.wrapper(),
+            ScsiCommand::ReadCapacity16(c) => c.wrapper(),
+            ScsiCommand::ReadDiscInformation(c) => c.wrapper(),
+            ScsiCommand::ReadToc(c) => c.wrapper(),
+            ScsiCommand::Release6(c) => c.wrapper(),
+            ScsiCommand::RequestSense(c) => c.wrapper(),
+            ScsiCommand::Reserve6(c) => c.wrapper(),
+            ScsiCommand::Sanitize(c) => c.wrapper(),
+            ScsiCommand::StartStopUnit(c) => c.wrapper(),
+            ScsiCommand::SynchronizeCache(c) => c.wrapper(),
+            ScsiCommand::TestUnitReady(c) => c.wrapper(),
+            ScsiCommand::Unmap(c) => c.wrapper(),
+            ScsiCommand::Verify10(c) => c.wrapper(),
+            ScsiCommand::Verify16(c) => c.wrapper(),
+            ScsiCommand::VpdInquiry(c) => c.wrapper(),
+            ScsiCommand::Write10(c) => c.wrapper(),
+            ScsiCommand::Write16(c) => c.wrapper(),
+            ScsiCommand::WriteBuffer(c) => c.wrapper(),
+            ScsiCommand::WriteSame10(c) => c.wrapper(),
+            ScsiCommand::WriteSame16(c) => c.wrapper(),
+        }
+    }
 }
 
 impl BufferPullable for ScsiCommand {
     fn pull_from_buffer<T: AsRef<[u8]>>(buffer: T) -> Result<Self, ScsiError> {
         let buffer = buffer.as_ref();
         let opcode = buffer[15];
-        if opcode == InquiryCommand::opcode() {
+        if opcode == CompareAndWriteCommand::opcode() {
+            Ok(ScsiCommand::CompareAndWrite(
+                CompareAndWriteCommand::pull_from_buffer(buffer)?,
+            ))
+        } else if opcode == FormatUnitCommand::opcode() {
+            Ok(ScsiCommand::FormatUnit(
+                FormatUnitCommand::pull_from_buffer(buffer)?,
+            ))
+        } else if opcode == GetConfigurationCommand::opcode() {
+            Ok(ScsiCommand::GetConfiguration(
+                GetConfigurationCommand::pull_from_buffer(buffer)?,
+            ))
+        } else if opcode == GetEventStatusNotificationCommand::opcode() {
+            Ok(ScsiCommand::GetEventStatusNotification(
+                GetEventStatusNotificationCommand::pull_from_buffer(buffer)?,
+            ))
+        } else if opcode == InquiryCommand::opcode() {
+            if VpdInquiryCommand::is_vpd(buffer) {
+                return Ok(ScsiCommand::VpdInquiry(
+                    VpdInquiryCommand::pull_from_buffer(buffer)?,
+                ));
+            }
             Ok(ScsiCommand::Inquiry(InquiryCommand::pull_from_buffer(
                 buffer,
             )?))
+        } else if opcode == LogSelectCommand::opcode() {
+            Ok(ScsiCommand::LogSelect(LogSelectCommand::pull_from_buffer(
+                buffer,
+            )?))
+        } else if opcode == LogSenseCommand::opcode() {
+            Ok(ScsiCommand::LogSense(LogSenseCommand::pull_from_buffer(
+                buffer,
+            )?))
+        } else if opcode == PersistentReserveInCommand::opcode() {
+            Ok(ScsiCommand::PersistentReserveIn(
+                PersistentReserveInCommand::pull_from_buffer(buffer)?,
+            ))
+        } else if opcode == PersistentReserveOutCommand::opcode() {
+            Ok(ScsiCommand::PersistentReserveOut(
+                PersistentReserveOutCommand::pull_from_buffer(buffer)?,
+            ))
+        } else if opcode == PreventAllowMediumRemovalCommand::opcode() {
+            Ok(ScsiCommand::PreventAllowMediumRemoval(
+                PreventAllowMediumRemovalCommand::pull_from_buffer(buffer)?,
+            ))
         } else if opcode == Read10Command::opcode() {
             Ok(ScsiCommand::Read10(Read10Command::pull_from_buffer(
                 buffer,
             )?))
+        } else if opcode == Read16Command::opcode() {
+            Ok(ScsiCommand::Read16(Read16Command::pull_from_buffer(
+                buffer,
+            )?))
+        } else if opcode == ReadBufferCommand::opcode() {
+            Ok(ScsiCommand::ReadBuffer(
+                ReadBufferCommand::pull_from_buffer(buffer)?,
+            ))
         } else if opcode == ReadCapacityCommand::opcode() {
             Ok(ScsiCommand::ReadCapacity(
                 ReadCapacityCommand::pull_from_buffer(buffer)?,
             ))
+        } else if opcode == ReadCapacity16Command::opcode() {
+            Ok(ScsiCommand::ReadCapacity16(
+                ReadCapacity16Command::pull_from_buffer(buffer)?,
+            ))
+        } else if opcode == ReadDiscInformationCommand::opcode() {
+            Ok(ScsiCommand::ReadDiscInformation(
+                ReadDiscInformationCommand::pull_from_buffer(buffer)?,
+            ))
+        } else if opcode == ReadTocCommand::opcode() {
+            Ok(ScsiCommand::ReadToc(ReadTocCommand::pull_from_buffer(
+                buffer,
+            )?))
+        } else if opcode == Release6Command::opcode() {
+            Ok(ScsiCommand::Release6(Release6Command::pull_from_buffer(
+                buffer,
+            )?))
         } else if opcode == RequestSenseCommand::opcode() {
             Ok(ScsiCommand::RequestSense(
                 RequestSenseCommand::pull_from_buffer(buffer)?,
             ))
+        } else if opcode == Reserve6Command::opcode() {
+            Ok(ScsiCommand::Reserve6(Reserve6Command::pull_from_buffer(
+                buffer,
+            )?))
+        } else if opcode == SanitizeCommand::opcode() {
+            Ok(ScsiCommand::Sanitize(SanitizeCommand::pull_from_buffer(
+                buffer,
+            )?))
+        } else if opcode == StartStopUnitCommand::opcode() {
+            Ok(ScsiCommand::StartStopUnit(
+                StartStopUnitCommand::pull_from_buffer(buffer)?,
+            ))
+        } else if opcode == SynchronizeCache10Command::opcode() {
+            Ok(ScsiCommand::SynchronizeCache(
+                SynchronizeCache10Command::pull_from_buffer(buffer)?,
+            ))
         } else if opcode == TestUnitReady::opcode() {
             Ok(ScsiCommand::TestUnitReady(TestUnitReady::pull_from_buffer(
                 buffer,
             )?))
+        } else if opcode == UnmapCommand::opcode() {
+            Ok(ScsiCommand::Unmap(UnmapCommand::pull_from_buffer(buffer)?))
+        } else if opcode == Verify10Command::opcode() {
+            Ok(ScsiCommand::Verify10(Verify10Command::pull_from_buffer(
+                buffer,
+            )?))
+        } else if opcode == Verify16Command::opcode() {
+            Ok(ScsiCommand::Verify16(Verify16Command::pull_from_buffer(
+                buffer,
+            )?))
         } else if opcode == Write10Command::opcode() {
             Ok(ScsiCommand::Write10(Write10Command::pull_from_buffer(
                 buffer,
             )?))
+        } else if opcode == Write16Command::opcode() {
+            Ok(ScsiCommand::Write16(Write16Command::pull_from_buffer(
+                buffer,
+            )?))
+        } else if opcode == WriteBufferCommand::opcode() {
+            Ok(ScsiCommand::WriteBuffer(
+                WriteBufferCommand::pull_from_buffer(buffer)?,
+            ))
+        } else if opcode == WriteSame10Command::opcode() {
+            Ok(ScsiCommand::WriteSame10(
+                WriteSame10Command::pull_from_buffer(buffer)?,
+            ))
+        } else if opcode == WriteSame16Command::opcode() {
+            Ok(ScsiCommand::WriteSame16(
+                WriteSame16Command::pull_from_buffer(buffer)?,
+            ))
         } else {
             Err(ScsiError::from_cause(ErrorCause::UnsupportedOperationError))
         }
@@ -230,50 +1768,235 @@ impl BufferPullable for ScsiCommand {
 impl BufferPushable for ScsiCommand {
     fn push_to_buffer<T: AsMut<[u8]>>(&self, buffer: T) -> Result<usize, ScsiError> {
         match self {
+            ScsiCommand::CompareAndWrite(c) => c.push_to_buffer(buffer),
+            ScsiCommand::FormatUnit(c) => c.push_to_buffer(buffer),
+            ScsiCommand::GetConfiguration(c) => c.push_to_buffer(buffer),
+            ScsiCommand::GetEventStatusNotification(c) => c.push_to_buffer(buffer),
             ScsiCommand::Inquiry(c) => c.push_to_buffer(buffer),
+            ScsiCommand::LogSelect(c) => c.push_to_buffer(buffer),
+            ScsiCommand::LogSense(c) => c.push_to_buffer(buffer),
+            ScsiCommand::PersistentReserveIn(c) => c.push_to_buffer(buffer),
+            ScsiCommand::PersistentReserveOut(c) => c.push_to_buffer(buffer),
+            ScsiCommand::PreventAllowMediumRemoval(c) => c.push_to_buffer(buffer),
             ScsiCommand::Read10(c) => c.push_to_buffer(buffer),
+            ScsiCommand::Read16(c) => c.push_to_buffer(buffer),
+            ScsiCommand::ReadBuffer(c) => c.push_to_buffer(buffer),
             ScsiCommand::ReadCapacity(c) => c.push_to_buffer(buffer),
+            ScsiCommand::ReadCapacity16(c) => c.push_to_buffer(buffer),
+            ScsiCommand::ReadDiscInformation(c) => c.push_to_buffer(buffer),
+            ScsiCommand::ReadToc(c) => c.push_to_buffer(buffer),
+            ScsiCommand::Release6(c) => c.push_to_buffer(buffer),
             ScsiCommand::RequestSense(c) => c.push_to_buffer(buffer),
+            ScsiCommand::Reserve6(c) => c.push_to_buffer(buffer),
+            ScsiCommand::Sanitize(c) => c.push_to_buffer(buffer),
+            ScsiCommand::StartStopUnit(c) => c.push_to_buffer(buffer),
+            ScsiCommand::SynchronizeCache(c) => c.push_to_buffer(buffer),
             ScsiCommand::TestUnitReady(c) => c.push_to_buffer(buffer),
+            ScsiCommand::Unmap(c) => c.push_to_buffer(buffer),
+            ScsiCommand::Verify10(c) => c.push_to_buffer(buffer),
+            ScsiCommand::Verify16(c) => c.push_to_buffer(buffer),
+            ScsiCommand::VpdInquiry(c) => c.push_to_buffer(buffer),
             ScsiCommand::Write10(c) => c.push_to_buffer(buffer),
+            ScsiCommand::Write16(c) => c.push_to_buffer(buffer),
+            ScsiCommand::WriteBuffer(c) => c.push_to_buffer(buffer),
+            ScsiCommand::WriteSame10(c) => c.push_to_buffer(buffer),
+            ScsiCommand::WriteSame16(c) => c.push_to_buffer(buffer),
         }
     }
 }
 
 #[cfg(test)]
-mod tests {
+pub(crate) mod tests {
     use super::{
-        CommandStatusWrapper, CommunicationChannel, ErrorCause, InquiryCommand, InquiryResponse,
-        Read10Command, ReadCapacityCommand, ReadCapacityResponse, RequestSenseCommand, ScsiError,
-        ScsiResponder, TestUnitReady, Write10Command,
+        command_status, dispatch_command, BlockLimitsPage, BufferDescriptor, CommandBlockWrapper,
+        CommandStatusWrapper, CommunicationChannel, CompareAndWriteCommand, DataPhase, Direction,
+        EchoBufferDescriptor, ErrorCause, FormatParameters, FormatUnitCommand, InquiryCommand,
+        InquiryResponse, LogPage, LogSelectCommand, LogSenseCommand, OverwriteParameters,
+        PersistentReserveInCommand, PersistentReserveOutCommand, PersistentReserveOutParameters,
+        Read10Command, Read16Command, ReadBufferCommand, ReadBufferMode, ReadCapacity16Command,
+        ReadCapacity16Response, ReadCapacityCommand, ReadCapacityResponse, RequestSenseCommand,
+        RequestSenseResponse, SanitizeCommand, ScsiCommand, ScsiError, ScsiResponder, ScsiStatus,
+        TestUnitReady, UnmapBlockDescriptors, UnmapCommand, Verify16Command, VerifyByteCheck,
+        VpdInquiryCommand, Write10Command, Write16Command, WriteBufferCommand, WriteSame16Command,
     };
+    use crate::scsi::commands::{
+        ErrorCounterPage, PersistentReserveInServiceAction, PersistentReserveOutServiceAction,
+        SupportedLogPages, WriteBufferMode,
+    };
+    use crate::traits::{AsyncCommunicationChannel, BufferPullable, BufferPushable};
+    use core::future::Future;
+    use core::pin::{pin, Pin};
+    use core::task::{Context, Poll, Waker};
     use std::sync::{Arc, Mutex};
     use std::vec::Vec;
-    use traits::{BufferPullable, BufferPushable};
 
-    struct BlockType([u8; 256]);
+    pub(crate) struct BlockType([u8; 256]);
     impl AsRef<[u8]> for BlockType {
         fn as_ref(&self) -> &[u8] {
             &self.0
         }
-    }
-    impl AsMut<[u8]> for BlockType {
-        fn as_mut(&mut self) -> &mut [u8] {
-            &mut self.0
+    }
+    impl AsMut<[u8]> for BlockType {
+        fn as_mut(&mut self) -> &mut [u8] {
+            &mut self.0
+        }
+    }
+
+    struct TestResponder {
+        buffer: [u8; 256 * 1024],
+        read_cursor: usize,
+        read_size: u16,
+        write_cursor: usize,
+        write_size: u16,
+    }
+    impl Default for TestResponder {
+        fn default() -> Self {
+            TestResponder {
+                buffer: [0; 256 * 1024],
+                read_cursor: 0,
+                read_size: 0,
+                write_cursor: 0,
+                write_size: 0,
+            }
+        }
+    }
+
+    impl ScsiResponder for TestResponder {
+        type BlockType = BlockType;
+        fn read_capacity(
+            &mut self,
+            _command: ReadCapacityCommand,
+        ) -> Result<(ReadCapacityResponse, CommandStatusWrapper), ScsiError> {
+            let resp = ReadCapacityResponse {
+                logical_block_address: 0,
+                block_length: 256,
+            };
+            let csw = CommandStatusWrapper::default();
+            Ok((resp, csw))
+        }
+
+        fn inquiry(
+            &mut self,
+            _command: InquiryCommand,
+        ) -> Result<(InquiryResponse, CommandStatusWrapper), ScsiError> {
+            Ok((InquiryResponse::default(), CommandStatusWrapper::default()))
+        }
+        fn request_sense(
+            &mut self,
+            _command: RequestSenseCommand,
+        ) -> Result<CommandStatusWrapper, ScsiError> {
+            Ok(CommandStatusWrapper::default())
+        }
+        fn test_unit_ready(
+            &mut self,
+            _command: TestUnitReady,
+        ) -> Result<CommandStatusWrapper, ScsiError> {
+            Ok(CommandStatusWrapper::default())
+        }
+
+        fn read10_start(&mut self, command: Read10Command) -> Result<(), ScsiError> {
+            self.read_cursor = command.block_address as usize;
+            self.read_size = command.transfer_blocks;
+            Ok(())
+        }
+        fn read_block(
+            &mut self,
+            buffer: &mut [u8],
+        ) -> Result<Option<CommandStatusWrapper>, ScsiError> {
+            if self.read_size == 0 {
+                return Ok(Some(CommandStatusWrapper::default()));
+            }
+            if buffer.len() != 256 {
+                return Err(ScsiError::from_cause(ErrorCause::BufferTooSmallError {
+                    expected: 256,
+                    actual: buffer.len(),
+                }));
+            }
+            let read_slice = &self.buffer[256 * self.read_cursor..256 * (self.read_cursor + 1)];
+            (buffer).copy_from_slice(read_slice);
+            self.read_cursor += 1;
+            self.read_size -= 1;
+            Ok(None)
+        }
+
+        fn write10_start(&mut self, command: Write10Command) -> Result<(), ScsiError> {
+            self.write_cursor = command.block_address as usize;
+            self.write_size = command.transfer_blocks;
+            Ok(())
+        }
+        fn write_block(
+            &mut self,
+            buffer: &[u8],
+        ) -> Result<Option<CommandStatusWrapper>, ScsiError> {
+            if self.write_size == 0 {
+                return Ok(Some(CommandStatusWrapper::default()));
+            }
+            if buffer.len() != 256 {
+                return Err(ScsiError::from_cause(ErrorCause::BufferTooSmallError {
+                    expected: 256,
+                    actual: buffer.len(),
+                }));
+            }
+            let write_slice =
+                &mut self.buffer[256 * self.write_cursor..256 * (self.write_cursor + 1)];
+            write_slice.copy_from_slice(buffer);
+            self.write_cursor += 1;
+            self.write_size -= 1;
+            Ok(None)
+        }
+
+        fn memory_buffer(&mut self) -> Self::BlockType {
+            BlockType([0; 256])
         }
     }
 
-    struct TestResponder {
-        buffer: [u8; 256 * 1024],
+    /// A direct-access responder backed by 256 KiB of memory split into
+    /// 256 byte blocks, shared by the host-side tests of other modules.
+    ///
+    /// VERIFY commands fail on the blocks in `bad_blocks`, without saying
+    /// which block was bad if `vague_errors` is set.
+    ///
+    /// WRITE BUFFER downloads firmware into `staged_firmware`, recording each
+    /// chunk's command offset, offset and length in `firmware_chunks`, and
+    /// copies it to `active_firmware` once it is activated.
+    ///
+    /// LOG SENSE returns the pages in `log_pages`, and LOG SELECT replaces
+    /// them or resets their error counters.
+    pub(crate) struct MemoryResponder {
+        pub buffer: Vec<u8>,
+        /// The address of the first block held in `buffer`; a large one makes
+        /// the fixture pose as a device too big for READ CAPACITY(10).
+        pub base_lba: u64,
+        pub bad_blocks: Vec<u64>,
+        pub vague_errors: bool,
+        pub staged_firmware: Vec<u8>,
+        pub active_firmware: Vec<u8>,
+        pub firmware_chunks: Vec<(u32, u32, usize)>,
+        pub log_pages: Vec<LogPage>,
+        echo_buffer: Vec<u8>,
+        sense: RequestSenseResponse,
+        format_progress: Option<u16>,
+        sanitize_progress: Option<u16>,
         read_cursor: usize,
-        read_size: u16,
+        read_size: u32,
         write_cursor: usize,
-        write_size: u16,
+        write_size: u32,
     }
-    impl Default for TestResponder {
+    impl Default for MemoryResponder {
         fn default() -> Self {
-            TestResponder {
-                buffer: [0; 256 * 1024],
+            MemoryResponder {
+                buffer: vec![0; 256 * 1024],
+                base_lba: 0,
+                bad_blocks: Vec::new(),
+                vague_errors: false,
+                staged_firmware: Vec::new(),
+                active_firmware: Vec::new(),
+                firmware_chunks: Vec::new(),
+                log_pages: Vec::new(),
+                echo_buffer: Vec::new(),
+                sense: RequestSenseResponse::default(),
+                format_progress: None,
+                sanitize_progress: None,
                 read_cursor: 0,
                 read_size: 0,
                 write_cursor: 0,
@@ -282,20 +2005,62 @@ mod tests {
         }
     }
 
-    impl ScsiResponder for TestResponder {
+    impl MemoryResponder {
+        /// The firmware buffer, whose offsets must be multiples of 256 bytes.
+        const FIRMWARE_BUFFER: BufferDescriptor = BufferDescriptor {
+            offset_boundary: 8,
+            capacity: 0x1_0000,
+        };
+
+        /// The size of the echo buffer.
+        const ECHO_BUFFER_SIZE: u16 = 256;
+
+        /// The index in `buffer` of the block at `lba`.
+        fn block_index(&self, lba: u64) -> Result<usize, ScsiError> {
+            lba.checked_sub(self.base_lba)
+                .map(|index| index as usize)
+                .ok_or_else(|| {
+                    ScsiError::from_cause(ErrorCause::BlockAddressOutOfRangeError {
+                        block_address: lba,
+                        num_blocks: self.base_lba + 1024,
+                    })
+                })
+        }
+
+        fn fail(&mut self, sense: RequestSenseResponse) -> Option<CommandStatusWrapper> {
+            self.sense = sense;
+            Some(CommandStatusWrapper {
+                status: CommandStatusWrapper::COMMAND_FAILED,
+                ..Default::default()
+            })
+        }
+    }
+
+    impl ScsiResponder for MemoryResponder {
         type BlockType = BlockType;
         fn read_capacity(
             &mut self,
             _command: ReadCapacityCommand,
         ) -> Result<(ReadCapacityResponse, CommandStatusWrapper), ScsiError> {
             let resp = ReadCapacityResponse {
-                logical_block_address: 0,
+                logical_block_address: u32::try_from(self.base_lba + 1023).unwrap_or(u32::MAX),
                 block_length: 256,
             };
             let csw = CommandStatusWrapper::default();
             Ok((resp, csw))
         }
 
+        fn read_capacity16(
+            &mut self,
+            _command: ReadCapacity16Command,
+        ) -> Result<(ReadCapacity16Response, CommandStatusWrapper), ScsiError> {
+            let resp = ReadCapacity16Response {
+                logical_block_address: self.base_lba + 1023,
+                block_length: 256,
+            };
+            Ok((resp, CommandStatusWrapper::default()))
+        }
+
         fn inquiry(
             &mut self,
             _command: InquiryCommand,
@@ -308,15 +2073,342 @@ mod tests {
         ) -> Result<CommandStatusWrapper, ScsiError> {
             Ok(CommandStatusWrapper::default())
         }
+        fn sense_data(
+            &mut self,
+            _command: RequestSenseCommand,
+        ) -> Result<(RequestSenseResponse, CommandStatusWrapper), ScsiError> {
+            if let Some(progress) = self.format_progress {
+                // Each poll moves a background format halfway to completion.
+                self.format_progress = progress.checked_add(0x8000);
+                let sense = RequestSenseResponse::format_in_progress(progress);
+                return Ok((sense, CommandStatusWrapper::default()));
+            }
+            if let Some(progress) = self.sanitize_progress {
+                self.sanitize_progress = progress.checked_add(0x8000);
+                let sense = RequestSenseResponse::sanitize_in_progress(progress);
+                return Ok((sense, CommandStatusWrapper::default()));
+            }
+            let sense = core::mem::take(&mut self.sense);
+            Ok((sense, CommandStatusWrapper::default()))
+        }
+        fn command_rejected(&mut self, sense: RequestSenseResponse) {
+            self.sense = sense;
+        }
         fn test_unit_ready(
             &mut self,
             _command: TestUnitReady,
         ) -> Result<CommandStatusWrapper, ScsiError> {
             Ok(CommandStatusWrapper::default())
         }
+        fn block_limits(
+            &mut self,
+            _command: VpdInquiryCommand,
+        ) -> Result<(BlockLimitsPage, CommandStatusWrapper), ScsiError> {
+            let page = BlockLimitsPage {
+                max_transfer_length: 16,
+                max_unmap_lba_count: 8,
+                max_unmap_block_descriptor_count: 4,
+                optimal_unmap_granularity: 4,
+                unmap_granularity_alignment: Some(2),
+                max_write_same_length: 64,
+                ..Default::default()
+            };
+            Ok((page, CommandStatusWrapper::default()))
+        }
+
+        fn unmap(
+            &mut self,
+            _command: UnmapCommand,
+            descriptors: UnmapBlockDescriptors<'_>,
+        ) -> Result<CommandStatusWrapper, ScsiError> {
+            assert!(descriptors.len() <= 4);
+            for descriptor in descriptors {
+                assert!(descriptor.block_count <= 8);
+                let start = 256 * descriptor.block_address as usize;
+                let end = start + 256 * descriptor.block_count as usize;
+                self.buffer[start..end].fill(0);
+            }
+            Ok(CommandStatusWrapper::default())
+        }
+
+        fn write_same(
+            &mut self,
+            command: WriteSame16Command,
+            block: &[u8],
+        ) -> Result<CommandStatusWrapper, ScsiError> {
+            assert!(command.transfer_blocks <= 64);
+            let start = command.block_address as usize;
+            for lba in start..start + command.transfer_blocks as usize {
+                let target = &mut self.buffer[256 * lba..256 * (lba + 1)];
+                if command.no_data_out {
+                    target.fill(0);
+                } else {
+                    target.copy_from_slice(block);
+                }
+            }
+            Ok(CommandStatusWrapper::default())
+        }
+
+        fn format_unit(
+            &mut self,
+            _command: FormatUnitCommand,
+            parameters: Option<FormatParameters>,
+        ) -> Result<CommandStatusWrapper, ScsiError> {
+            self.buffer.fill(0);
+            if parameters.is_some_and(|parameters| parameters.immediate) {
+                self.format_progress = Some(0);
+            }
+            Ok(CommandStatusWrapper::default())
+        }
+
+        fn compare_and_write(
+            &mut self,
+            command: CompareAndWriteCommand,
+            expected: &[u8],
+            new: &[u8],
+        ) -> Result<CommandStatusWrapper, ScsiError> {
+            let start = 256 * command.block_address as usize;
+            let stored = &mut self.buffer[start..start + expected.len()];
+            if let Some(offset) = stored.iter().zip(expected).position(|(a, b)| a != b) {
+                return Ok(self
+                    .fail(RequestSenseResponse {
+                        valid: true,
+                        information: offset as u32,
+                        ..RequestSenseResponse::new(RequestSenseResponse::MISCOMPARE, 0x1d, 0)
+                    })
+                    .unwrap());
+            }
+            stored.copy_from_slice(new);
+            Ok(CommandStatusWrapper::default())
+        }
+
+        fn sanitize(
+            &mut self,
+            command: SanitizeCommand,
+            parameters: Option<OverwriteParameters<'_>>,
+        ) -> Result<CommandStatusWrapper, ScsiError> {
+            match parameters {
+                Some(parameters) => {
+                    for (byte, pattern) in self
+                        .buffer
+                        .iter_mut()
+                        .zip(parameters.pattern.iter().cycle())
+                    {
+                        *byte = *pattern;
+                    }
+                }
+                None => self.buffer.fill(0),
+            }
+            if command.immediate {
+                self.sanitize_progress = Some(0);
+            }
+            Ok(CommandStatusWrapper::default())
+        }
+
+        fn verify(
+            &mut self,
+            command: Verify16Command,
+            block_address: u64,
+            data: &[u8],
+        ) -> Result<Option<CommandStatusWrapper>, ScsiError> {
+            if !data.is_empty() {
+                let start = 256 * self.block_index(block_address)?;
+                let stored = &self.buffer[start..start + 256];
+                return Ok(match stored.iter().zip(data).position(|(a, b)| a != b) {
+                    Some(offset) => {
+                        let offset =
+                            256 * (block_address - command.block_address) as usize + offset;
+                        self.fail(RequestSenseResponse {
+                            valid: true,
+                            information: offset as u32,
+                            ..RequestSenseResponse::new(RequestSenseResponse::MISCOMPARE, 0x1d, 0)
+                        })
+                    }
+                    None => None,
+                });
+            }
+            if command.byte_check != VerifyByteCheck::None {
+                return Ok(None);
+            }
+            let range =
+                command.block_address..command.block_address + u64::from(command.transfer_blocks);
+            let bad = self
+                .bad_blocks
+                .iter()
+                .copied()
+                .find(|lba| range.contains(lba));
+            Ok(match bad {
+                Some(lba) => self.fail(RequestSenseResponse {
+                    valid: !self.vague_errors,
+                    information: if self.vague_errors { 0 } else { lba as u32 },
+                    ..RequestSenseResponse::new(RequestSenseResponse::MEDIUM_ERROR, 0x11, 0)
+                }),
+                None => None,
+            })
+        }
+
+        fn write_buffer(
+            &mut self,
+            command: WriteBufferCommand,
+            offset: u32,
+            data: &[u8],
+        ) -> Result<Option<CommandStatusWrapper>, ScsiError> {
+            let (buffer, capacity) = match command.mode {
+                WriteBufferMode::EchoBuffer => (
+                    &mut self.echo_buffer,
+                    u32::from(MemoryResponder::ECHO_BUFFER_SIZE),
+                ),
+                _ => (
+                    &mut self.staged_firmware,
+                    MemoryResponder::FIRMWARE_BUFFER.capacity,
+                ),
+            };
+            if command.buffer_offset % 256 != 0 || offset + data.len() as u32 > capacity {
+                // Invalid field in CDB.
+                let sense =
+                    RequestSenseResponse::new(RequestSenseResponse::ILLEGAL_REQUEST, 0x24, 0);
+                return Ok(self.fail(sense));
+            }
+            if !data.is_empty() {
+                let end = offset as usize + data.len();
+                if buffer.len() < end {
+                    buffer.resize(end, 0);
+                }
+                buffer[offset as usize..end].copy_from_slice(data);
+                if command.mode.is_microcode_download() {
+                    self.firmware_chunks
+                        .push((command.buffer_offset, offset, data.len()));
+                }
+                return Ok(None);
+            }
+            match command.mode {
+                WriteBufferMode::DownloadMicrocodeSaveAndActivate
+                | WriteBufferMode::DownloadMicrocodeWithOffsetsSaveAndActivate
+                | WriteBufferMode::ActivateDeferredMicrocode => {
+                    self.active_firmware = self.staged_firmware.clone();
+                }
+                _ => {}
+            }
+            Ok(None)
+        }
+
+        fn read_buffer(
+            &mut self,
+            command: ReadBufferCommand,
+            offset: u32,
+            buffer: &mut [u8],
+        ) -> Result<Option<CommandStatusWrapper>, ScsiError> {
+            let (source, capacity) = match command.mode {
+                ReadBufferMode::EchoBuffer => (
+                    &self.echo_buffer,
+                    u32::from(MemoryResponder::ECHO_BUFFER_SIZE),
+                ),
+                _ => (
+                    &self.staged_firmware,
+                    MemoryResponder::FIRMWARE_BUFFER.capacity,
+                ),
+            };
+            if offset + buffer.len() as u32 > capacity {
+                let sense =
+                    RequestSenseResponse::new(RequestSenseResponse::ILLEGAL_REQUEST, 0x24, 0);
+                return Ok(self.fail(sense));
+            }
+            for (idx, byte) in buffer.iter_mut().enumerate() {
+                *byte = source.get(offset as usize + idx).copied().unwrap_or(0);
+            }
+            Ok(None)
+        }
+
+        fn read_buffer_descriptor(
+            &mut self,
+            _command: ReadBufferCommand,
+        ) -> Result<(BufferDescriptor, CommandStatusWrapper), ScsiError> {
+            Ok((
+                MemoryResponder::FIRMWARE_BUFFER,
+                CommandStatusWrapper::default(),
+            ))
+        }
+
+        fn read_echo_buffer_descriptor(
+            &mut self,
+            _command: ReadBufferCommand,
+        ) -> Result<(EchoBufferDescriptor, CommandStatusWrapper), ScsiError> {
+            let descriptor = EchoBufferDescriptor {
+                overwritten_supported: false,
+                capacity: MemoryResponder::ECHO_BUFFER_SIZE,
+            };
+            Ok((descriptor, CommandStatusWrapper::default()))
+        }
+
+        fn log_sense(
+            &mut self,
+            command: LogSenseCommand,
+        ) -> Result<(LogPage, CommandStatusWrapper), ScsiError> {
+            if command.page_code == SupportedLogPages::PAGE_CODE {
+                let mut supported = SupportedLogPages::default();
+                supported.push_page(SupportedLogPages::PAGE_CODE)?;
+                for page in &self.log_pages {
+                    supported.push_page(page.page_code())?;
+                }
+                let csw = CommandStatusWrapper::default();
+                return Ok((LogPage::SupportedPages(supported), csw));
+            }
+            match self
+                .log_pages
+                .iter()
+                .find(|page| page.page_code() == command.page_code)
+            {
+                Some(page) => Ok((*page, CommandStatusWrapper::default())),
+                None => {
+                    let sense =
+                        RequestSenseResponse::new(RequestSenseResponse::ILLEGAL_REQUEST, 0x24, 0);
+                    let csw = self.fail(sense).unwrap_or_default();
+                    Ok((LogPage::SupportedPages(SupportedLogPages::default()), csw))
+                }
+            }
+        }
+
+        fn log_select(
+            &mut self,
+            command: LogSelectCommand,
+            parameters: &[u8],
+        ) -> Result<CommandStatusWrapper, ScsiError> {
+            if command.parameter_code_reset {
+                for page in &mut self.log_pages {
+                    if let LogPage::ErrorCounter(counters) = page {
+                        *counters = ErrorCounterPage {
+                            kind: counters.kind,
+                            ..Default::default()
+                        };
+                    }
+                }
+                return Ok(CommandStatusWrapper::default());
+            }
+            let replacement = LogPage::pull_from_buffer(parameters).ok();
+            let existing = replacement.and_then(|new| {
+                self.log_pages
+                    .iter_mut()
+                    .find(|page| page.page_code() == new.page_code())
+                    .map(|page| (page, new))
+            });
+            match existing {
+                Some((page, new)) => {
+                    *page = new;
+                    Ok(CommandStatusWrapper::default())
+                }
+                None => {
+                    let sense =
+                        RequestSenseResponse::new(RequestSenseResponse::ILLEGAL_REQUEST, 0x26, 0);
+                    Ok(self.fail(sense).unwrap_or_default())
+                }
+            }
+        }
 
         fn read10_start(&mut self, command: Read10Command) -> Result<(), ScsiError> {
-            self.read_cursor = command.block_address as usize;
+            self.read16_start(command.into())
+        }
+        fn read16_start(&mut self, command: Read16Command) -> Result<(), ScsiError> {
+            self.read_cursor = self.block_index(command.block_address)?;
             self.read_size = command.transfer_blocks;
             Ok(())
         }
@@ -334,14 +2426,17 @@ mod tests {
                 }));
             }
             let read_slice = &self.buffer[256 * self.read_cursor..256 * (self.read_cursor + 1)];
-            (buffer).copy_from_slice(&read_slice);
+            (buffer).copy_from_slice(read_slice);
             self.read_cursor += 1;
             self.read_size -= 1;
             Ok(None)
         }
 
         fn write10_start(&mut self, command: Write10Command) -> Result<(), ScsiError> {
-            self.write_cursor = command.block_address as usize;
+            self.write16_start(command.into())
+        }
+        fn write16_start(&mut self, command: Write16Command) -> Result<(), ScsiError> {
+            self.write_cursor = self.block_index(command.block_address)?;
             self.write_size = command.transfer_blocks;
             Ok(())
         }
@@ -360,7 +2455,7 @@ mod tests {
             }
             let write_slice =
                 &mut self.buffer[256 * self.write_cursor..256 * (self.write_cursor + 1)];
-            write_slice.copy_from_slice(&buffer);
+            write_slice.copy_from_slice(buffer);
             self.write_cursor += 1;
             self.write_size -= 1;
             Ok(None)
@@ -372,7 +2467,7 @@ mod tests {
     }
 
     #[derive(Clone, Default)]
-    struct TestDualChannel {
+    pub(crate) struct TestDualChannel {
         pub send_buff: Arc<Mutex<Vec<u8>>>,
         pub recv_buff: Arc<Mutex<Vec<u8>>>,
     }
@@ -423,6 +2518,144 @@ mod tests {
         }
     }
 
+    /// Connects a host-side `CommunicationChannel` directly to a
+    /// `ScsiResponder`, running the responder whenever the host waits on data
+    /// that has not been sent yet.
+    pub(crate) struct LoopbackChannel<R: ScsiResponder> {
+        pub responder: R,
+        pub commands: usize,
+        host: TestDualChannel,
+        device: TestDualChannel,
+    }
+
+    impl<R: ScsiResponder> LoopbackChannel<R> {
+        pub fn new(responder: R) -> LoopbackChannel<R> {
+            let host = TestDualChannel::default();
+            let device = host.reversed();
+            LoopbackChannel {
+                responder,
+                commands: 0,
+                host,
+                device,
+            }
+        }
+    }
+
+    impl<R: ScsiResponder> CommunicationChannel for LoopbackChannel<R> {
+        fn out_transfer<B: AsRef<[u8]>>(&mut self, bytes: B) -> Result<usize, ScsiError> {
+            self.host.out_transfer(bytes)
+        }
+
+        fn in_transfer<B: AsMut<[u8]>>(&mut self, buffer: B) -> Result<usize, ScsiError> {
+            let pending_response = self.host.recv_buff.lock().unwrap().is_empty();
+            let pending_command = !self.host.send_buff.lock().unwrap().is_empty();
+            if pending_response && pending_command {
+                self.responder.process_command(&mut self.device)?;
+                self.commands += 1;
+            }
+            self.host.in_transfer(buffer)
+        }
+    }
+
+    /// Makes every transfer on a channel wait once before it completes, the
+    /// way transfers on real hardware do.
+    pub(crate) struct YieldingChannel<C: CommunicationChannel> {
+        pub channel: C,
+        pub waits: usize,
+    }
+
+    /// A future that is pending the first time it is polled.
+    struct YieldOnce(bool);
+
+    impl Future for YieldOnce {
+        type Output = ();
+
+        fn poll(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<()> {
+            if self.0 {
+                return Poll::Ready(());
+            }
+            self.0 = true;
+            cx.waker().wake_by_ref();
+            Poll::Pending
+        }
+    }
+
+    impl<C: CommunicationChannel> AsyncCommunicationChannel for YieldingChannel<C> {
+        async fn out_transfer<B: AsRef<[u8]>>(&mut self, bytes: B) -> Result<usize, ScsiError> {
+            YieldOnce(false).await;
+            self.waits += 1;
+            self.channel.out_transfer(bytes)
+        }
+
+        async fn in_transfer<B: AsMut<[u8]>>(&mut self, buffer: B) -> Result<usize, ScsiError> {
+            YieldOnce(false).await;
+            self.waits += 1;
+            self.channel.in_transfer(buffer)
+        }
+    }
+
+    /// The asynchronous counterpart of `LoopbackChannel`, which runs the
+    /// responder with `process_command_async`.
+    pub(crate) struct AsyncLoopbackChannel<R: ScsiResponder> {
+        pub responder: R,
+        pub commands: usize,
+        host: YieldingChannel<TestDualChannel>,
+        device: YieldingChannel<TestDualChannel>,
+    }
+
+    impl<R: ScsiResponder> AsyncLoopbackChannel<R> {
+        pub fn new(responder: R) -> AsyncLoopbackChannel<R> {
+            let host = TestDualChannel::default();
+            let device = host.reversed();
+            AsyncLoopbackChannel {
+                responder,
+                commands: 0,
+                host: YieldingChannel {
+                    channel: host,
+                    waits: 0,
+                },
+                device: YieldingChannel {
+                    channel: device,
+                    waits: 0,
+                },
+            }
+        }
+
+        /// The number of times either side had to wait for a transfer.
+        pub fn waits(&self) -> usize {
+            self.host.waits + self.device.waits
+        }
+    }
+
+    impl<R: ScsiResponder> AsyncCommunicationChannel for AsyncLoopbackChannel<R> {
+        async fn out_transfer<B: AsRef<[u8]>>(&mut self, bytes: B) -> Result<usize, ScsiError> {
+            self.host.out_transfer(bytes).await
+        }
+
+        async fn in_transfer<B: AsMut<[u8]>>(&mut self, buffer: B) -> Result<usize, ScsiError> {
+            let pending_response = self.host.channel.recv_buff.lock().unwrap().is_empty();
+            let pending_command = !self.host.channel.send_buff.lock().unwrap().is_empty();
+            if pending_response && pending_command {
+                self.responder
+                    .process_command_async(&mut self.device)
+                    .await?;
+                self.commands += 1;
+            }
+            self.host.in_transfer(buffer).await
+        }
+    }
+
+    /// Runs a future to completion on the current thread.
+    pub(crate) fn block_on<F: Future>(future: F) -> F::Output {
+        let mut future = pin!(future);
+        let mut cx = Context::from_waker(Waker::noop());
+        loop {
+            if let Poll::Ready(output) = future.as_mut().poll(&mut cx) {
+                return output;
+            }
+        }
+    }
+
     #[test]
     fn test_exchange() {
         let mut forward = TestDualChannel::default();
@@ -433,7 +2666,7 @@ mod tests {
         let mut command_buff = [0; 31];
         let capacity_req = ReadCapacityCommand::new();
         assert_eq!(16, capacity_req.push_to_buffer(&mut command_buff).unwrap());
-        assert_eq!(31, forward.out_transfer(&command_buff).unwrap());
+        assert_eq!(31, forward.out_transfer(command_buff).unwrap());
         assert_eq!(31, forward.send_buff.lock().unwrap().len());
         assert_eq!(31, responder_side.recv_buff.lock().unwrap().len());
 
@@ -442,8 +2675,8 @@ mod tests {
         let (resp, csw) = {
             let buff_raw = forward.recv_buff.lock().unwrap();
             let buff: &Vec<u8> = buff_raw.as_ref();
-            let resp = ReadCapacityResponse::pull_from_buffer(&buff).unwrap();
-            let csw = CommandStatusWrapper::pull_from_buffer(&buff[31..]).unwrap();
+            let resp = ReadCapacityResponse::pull_from_buffer(buff).unwrap();
+            let csw = CommandStatusWrapper::pull_from_buffer(&buff[8..]).unwrap();
             (resp, csw)
         };
         assert_eq!(256, resp.block_length);
@@ -454,8 +2687,8 @@ mod tests {
         forward.clear();
         responder_side.clear();
         write_a.push_to_buffer(&mut command_buff).unwrap();
-        forward.out_transfer(&command_buff).unwrap();
-        forward.out_transfer(&block_buff).unwrap();
+        forward.out_transfer(command_buff).unwrap();
+        forward.out_transfer(block_buff).unwrap();
 
         dev.process_command(&mut responder_side).unwrap();
 
@@ -466,7 +2699,7 @@ mod tests {
         forward.clear();
         responder_side.clear();
         read_a.push_to_buffer(&mut command_buff).unwrap();
-        forward.out_transfer(&command_buff).unwrap();
+        forward.out_transfer(command_buff).unwrap();
 
         dev.process_command(&mut responder_side).unwrap();
 
@@ -474,4 +2707,346 @@ mod tests {
         assert_eq!(&bbuff_2, &block_buff);
     }
 
+    #[test]
+    fn test_bulk_only_framing() {
+        let mut forward = TestDualChannel::default();
+        let mut responder_side = forward.reversed();
+        let mut dev = TestResponder::default();
+        let mut command_buff = [0; 31];
+
+        // Responses are cut to the CBW length and followed by a bare 13-byte CSW.
+        RequestSenseCommand::new(8)
+            .push_to_buffer(&mut command_buff)
+            .unwrap();
+        command_buff[4] = 0x2a;
+        forward.out_transfer(command_buff).unwrap();
+        dev.process_command(&mut responder_side).unwrap();
+        {
+            let buff = forward.recv_buff.lock().unwrap();
+            assert_eq!(8 + 13, buff.len());
+            let csw = CommandStatusWrapper::pull_from_buffer(&buff[8..]).unwrap();
+            assert_eq!(0x2a, csw.tag);
+            assert_eq!(0, csw.data_residue);
+        }
+
+        // A read that ends before the host's expected length reports the shortfall.
+        forward.clear();
+        responder_side.clear();
+        Read10Command::new(0, 512, 256)
+            .unwrap()
+            .push_to_buffer(&mut command_buff)
+            .unwrap();
+        command_buff[8..12].copy_from_slice(&768u32.to_le_bytes());
+        forward.out_transfer(command_buff).unwrap();
+        dev.process_command(&mut responder_side).unwrap();
+        {
+            let buff = forward.recv_buff.lock().unwrap();
+            assert_eq!(512 + 13, buff.len());
+            let csw = CommandStatusWrapper::pull_from_buffer(&buff[512..]).unwrap();
+            assert_eq!(CommandStatusWrapper::COMMAND_PASSED, csw.status);
+            assert_eq!(256, csw.data_residue);
+        }
+
+        // Data sent after the block the responder returned a status for is
+        // still drained from the host and counted as residue.
+        forward.clear();
+        responder_side.clear();
+        Write10Command::new(0, 256, 256)
+            .unwrap()
+            .push_to_buffer(&mut command_buff)
+            .unwrap();
+        command_buff[8..12].copy_from_slice(&768u32.to_le_bytes());
+        forward.out_transfer(command_buff).unwrap();
+        forward.out_transfer([0x11; 768]).unwrap();
+        dev.process_command(&mut responder_side).unwrap();
+        assert!(responder_side.recv_buff.lock().unwrap().is_empty());
+        {
+            let buff = forward.recv_buff.lock().unwrap();
+            assert_eq!(13, buff.len());
+            let csw = CommandStatusWrapper::pull_from_buffer(&buff[..]).unwrap();
+            assert_eq!(256, csw.data_residue);
+        }
+        assert_eq!(&dev.buffer[0..256], &[0x11; 256][..]);
+        assert_eq!(&dev.buffer[256..512], &[0; 256][..]);
+    }
+
+    /// Runs `command` through `process_command`, returning the data and CSW
+    /// sent back to the host.
+    fn bulk_only_exchange<R: ScsiResponder>(
+        dev: &mut R,
+        command: &[u8; 31],
+        data_out: &[u8],
+    ) -> (Vec<u8>, CommandStatusWrapper) {
+        let mut host = TestDualChannel::default();
+        let mut device = host.reversed();
+        host.out_transfer(command).unwrap();
+        host.out_transfer(data_out).unwrap();
+        dev.process_command(&mut device).unwrap();
+        // All of the data the host sent was read, even if it was not wanted.
+        assert!(device.recv_buff.lock().unwrap().is_empty());
+        let mut output = host.recv_buff.lock().unwrap().clone();
+        let csw_start = output.len() - CommandStatusWrapper::SIZE as usize;
+        let csw = CommandStatusWrapper::pull_from_buffer(&output[csw_start..]).unwrap();
+        output.truncate(csw_start);
+        (output, csw)
+    }
+
+    /// A data phase that stops taking data after `capacity` bytes.
+    struct CappedData {
+        sent: usize,
+        capacity: usize,
+    }
+
+    impl DataPhase for CappedData {
+        fn send(&mut self, data: &[u8]) -> Result<usize, ScsiError> {
+            let sent = data.len().min(self.capacity - self.sent);
+            self.sent += sent;
+            Ok(sent)
+        }
+
+        fn receive(&mut self, _buffer: &mut [u8]) -> Result<usize, ScsiError> {
+            Ok(0)
+        }
+    }
+
+    #[test]
+    fn test_read_buffer_short_data_phase() {
+        let mut dev = MemoryResponder::default();
+        let mut data = CappedData {
+            sent: 0,
+            capacity: 512,
+        };
+        let command = ReadBufferCommand::new(ReadBufferMode::Data, 0, 0, 1024).unwrap();
+        let (csw, sent) =
+            dispatch_command(&mut dev, ScsiCommand::ReadBuffer(command), 1024, &mut data).unwrap();
+        assert_eq!(csw.status, CommandStatusWrapper::COMMAND_PASSED);
+        assert_eq!(sent, 512);
+    }
+
+    #[test]
+    fn test_command_status_rejections() {
+        let mut dev = MemoryResponder::default();
+        let err = ScsiError::from_cause(ErrorCause::UnsupportedOperationError);
+        let (status, sense, transferred) = command_status(&mut dev, Err(err)).unwrap();
+        assert_eq!(status, ScsiStatus::CheckCondition);
+        assert_eq!(transferred, 0);
+        // The responder is told about the rejection, as it is over Bulk-Only.
+        let expected = RequestSenseResponse::new(RequestSenseResponse::ILLEGAL_REQUEST, 0x20, 0);
+        assert_eq!(sense, Some(expected));
+        assert_eq!(dev.sense, expected);
+    }
+
+    #[test]
+    fn test_from_cdb_transfer_overflow() {
+        // READ(10) and WRITE(10) of 0xFFFF blocks of 128 KiB each.
+        for opcode in [0x28, 0x2a] {
+            let cdb = [opcode, 0, 0, 0, 0, 0, 0, 0xff, 0xff, 0];
+            let err = ScsiCommand::from_cdb(&cdb, 0x2_0000).unwrap_err();
+            assert!(matches!(
+                err.cause,
+                ErrorCause::TransferTooLargeError { .. }
+            ));
+        }
+
+        // COMPARE AND WRITE of 255 blocks of 16 MiB each, sent twice over.
+        let mut cdb = [0; 16];
+        cdb[0] = 0x89;
+        cdb[13] = 0xff;
+        let err = ScsiCommand::from_cdb(&cdb, 0x100_0000).unwrap_err();
+        assert!(matches!(
+            err.cause,
+            ErrorCause::TransferTooLargeError { .. }
+        ));
+    }
+
+    #[test]
+    fn test_empty_data_out() {
+        let mut dev = MemoryResponder::default();
+        let mut data = CappedData {
+            sent: 0,
+            capacity: 0,
+        };
+        let command = Write10Command::new(0, 512, 256).unwrap();
+        let err =
+            dispatch_command(&mut dev, ScsiCommand::Write10(command), 512, &mut data).unwrap_err();
+        assert!(matches!(err.cause, ErrorCause::UsbTransferError { .. }));
+
+        let mut command = Verify16Command::new(0, 2, 256);
+        command.byte_check = VerifyByteCheck::Compare;
+        let err =
+            dispatch_command(&mut dev, ScsiCommand::Verify16(command), 512, &mut data).unwrap_err();
+        assert!(matches!(err.cause, ErrorCause::UsbTransferError { .. }));
+
+        let command = WriteBufferCommand::new(WriteBufferMode::Data, 0, 0, 512).unwrap();
+        let err = dispatch_command(&mut dev, ScsiCommand::WriteBuffer(command), 512, &mut data)
+            .unwrap_err();
+        assert!(matches!(err.cause, ErrorCause::UsbTransferError { .. }));
+    }
+
+    /// Asks `dev` for its sense data over Bulk-Only, returning the sense key
+    /// and additional sense code.
+    fn bulk_only_sense<R: ScsiResponder>(dev: &mut R) -> (u8, u8) {
+        let mut command = [0; 31];
+        RequestSenseCommand::new(18)
+            .push_to_buffer(&mut command)
+            .unwrap();
+        let (data, csw) = bulk_only_exchange(dev, &command, &[]);
+        assert_eq!(csw.status, CommandStatusWrapper::COMMAND_PASSED);
+        let sense = RequestSenseResponse::pull_from_buffer(data).unwrap();
+        (sense.sense_key, sense.additional_sense_code)
+    }
+
+    #[test]
+    fn test_bulk_only_oversized_parameters() {
+        let mut dev = MemoryResponder::default();
+        let mut command = [0; 31];
+        let length_error = (RequestSenseResponse::ILLEGAL_REQUEST, 0x1a);
+        let invalid_parameter = (RequestSenseResponse::ILLEGAL_REQUEST, 0x26);
+
+        // A WRITE SAME block larger than the responder's block buffer.
+        WriteSame16Command::new(0, 4, 512)
+            .push_to_buffer(&mut command)
+            .unwrap();
+        let (_, csw) = bulk_only_exchange(&mut dev, &command, &[0x55; 512]);
+        assert_eq!(csw.status, CommandStatusWrapper::COMMAND_FAILED);
+        assert_eq!(csw.data_residue, 0);
+        assert_eq!(bulk_only_sense(&mut dev), length_error);
+        assert_eq!(&dev.buffer[..1024], &[0; 1024][..]);
+
+        // An UNMAP parameter list larger than the block buffer, and one whose
+        // descriptor length is not a whole number of descriptors.
+        UnmapCommand::new(40)
+            .unwrap()
+            .push_to_buffer(&mut command)
+            .unwrap();
+        let (_, csw) = bulk_only_exchange(&mut dev, &command, &[0; 648]);
+        assert_eq!(csw.status, CommandStatusWrapper::COMMAND_FAILED);
+        assert_eq!(csw.data_residue, 0);
+        assert_eq!(bulk_only_sense(&mut dev), length_error);
+
+        UnmapCommand::new(1)
+            .unwrap()
+            .push_to_buffer(&mut command)
+            .unwrap();
+        let mut parameters = [0; 24];
+        parameters[..4].copy_from_slice(&[0, 22, 0, 15]);
+        let (_, csw) = bulk_only_exchange(&mut dev, &command, &parameters);
+        assert_eq!(csw.status, CommandStatusWrapper::COMMAND_FAILED);
+        assert_eq!(bulk_only_sense(&mut dev), invalid_parameter);
+
+        // A SANITIZE overwrite pattern larger than the block buffer, and one
+        // that is empty.
+        let pattern = [0x5a; 508];
+        let overwrite = OverwriteParameters::new(&pattern[..]);
+        let mut parameters = [0; 512];
+        overwrite.push_to_buffer(&mut parameters[..]).unwrap();
+        SanitizeCommand::overwrite(&overwrite)
+            .push_to_buffer(&mut command)
+            .unwrap();
+        let (_, csw) = bulk_only_exchange(&mut dev, &command, &parameters);
+        assert_eq!(csw.status, CommandStatusWrapper::COMMAND_FAILED);
+        assert_eq!(csw.data_residue, 0);
+        assert_eq!(bulk_only_sense(&mut dev), length_error);
+
+        let sanitize = SanitizeCommand {
+            parameter_list_length: 4,
+            ..SanitizeCommand::overwrite(&overwrite)
+        };
+        sanitize.push_to_buffer(&mut command).unwrap();
+        let (_, csw) = bulk_only_exchange(&mut dev, &command, &[1, 0, 0, 0]);
+        assert_eq!(csw.status, CommandStatusWrapper::COMMAND_FAILED);
+        assert_eq!(bulk_only_sense(&mut dev), invalid_parameter);
+        assert!(dev.buffer.iter().all(|&byte| byte == 0));
+
+        // A PERSISTENT RESERVE OUT parameter list cut short.
+        PersistentReserveOutCommand::new(PersistentReserveOutServiceAction::Register, None)
+            .push_to_buffer(&mut command)
+            .unwrap();
+        command[8..12].copy_from_slice(&8u32.to_le_bytes());
+        let (_, csw) = bulk_only_exchange(&mut dev, &command, &[0; 8]);
+        assert_eq!(csw.status, CommandStatusWrapper::COMMAND_FAILED);
+        assert_eq!(csw.data_residue, 0);
+        assert_eq!(bulk_only_sense(&mut dev), length_error);
+
+        // A FORMAT UNIT defect list that runs past the block buffer.
+        let format = FormatUnitCommand {
+            format_data: true,
+            parameter_list_length: 512,
+            ..FormatUnitCommand::default()
+        };
+        format.push_to_buffer(&mut command).unwrap();
+        let (_, csw) = bulk_only_exchange(&mut dev, &command, &[0; 512]);
+        assert_eq!(csw.status, CommandStatusWrapper::COMMAND_FAILED);
+        assert_eq!(csw.data_residue, 0);
+        assert_eq!(bulk_only_sense(&mut dev), length_error);
+
+        // COMPARE AND WRITE halves larger than the block buffer.
+        CompareAndWriteCommand::new(0, 2, 256)
+            .push_to_buffer(&mut command)
+            .unwrap();
+        let (_, csw) = bulk_only_exchange(&mut dev, &command, &[0; 1024]);
+        assert_eq!(csw.status, CommandStatusWrapper::COMMAND_FAILED);
+        assert_eq!(csw.data_residue, 0);
+        assert_eq!(
+            bulk_only_sense(&mut dev),
+            (RequestSenseResponse::ILLEGAL_REQUEST, 0x24)
+        );
+
+        // A LOG SELECT parameter list larger than the block buffer.
+        LogSelectCommand::new(0x03, 512)
+            .push_to_buffer(&mut command)
+            .unwrap();
+        let (_, csw) = bulk_only_exchange(&mut dev, &command, &[0; 512]);
+        assert_eq!(csw.status, CommandStatusWrapper::COMMAND_FAILED);
+        assert_eq!(csw.data_residue, 0);
+        assert_eq!(bulk_only_sense(&mut dev), length_error);
+        assert!(dev.log_pages.is_empty());
+    }
+
+    #[test]
+    fn test_bulk_only_unsupported_commands() {
+        let mut dev = MemoryResponder::default();
+        let mut command = [0; 31];
+        let illegal_opcode = (RequestSenseResponse::ILLEGAL_REQUEST, 0x20);
+
+        // MODE SENSE(6), which no responder answers yet.
+        CommandBlockWrapper::new(192, Direction::IN, 0, 6)
+            .push_to_buffer(&mut command)
+            .unwrap();
+        command[15..21].copy_from_slice(&[0x1a, 0, 0x3f, 0, 192, 0]);
+        let (data, csw) = bulk_only_exchange(&mut dev, &command, &[]);
+        assert!(data.is_empty());
+        assert_eq!(csw.status, CommandStatusWrapper::COMMAND_FAILED);
+        assert_eq!(csw.data_residue, 192);
+        assert_eq!(bulk_only_sense(&mut dev), illegal_opcode);
+
+        // Hooks left at their defaults, with and without data from the host.
+        let read_keys = PersistentReserveInCommand::new(PersistentReserveInServiceAction::ReadKeys);
+        read_keys.push_to_buffer(&mut command).unwrap();
+        let (data, csw) = bulk_only_exchange(&mut dev, &command, &[]);
+        assert!(data.is_empty());
+        assert_eq!(csw.status, CommandStatusWrapper::COMMAND_FAILED);
+        assert_eq!(csw.data_residue, u32::from(read_keys.allocation_length));
+        assert_eq!(bulk_only_sense(&mut dev), illegal_opcode);
+
+        PersistentReserveOutCommand::new(PersistentReserveOutServiceAction::Register, None)
+            .push_to_buffer(&mut command)
+            .unwrap();
+        let mut parameters = [0; PersistentReserveOutParameters::SIZE];
+        PersistentReserveOutParameters::new(0, 0x1234)
+            .push_to_buffer(&mut parameters[..])
+            .unwrap();
+        let (_, csw) = bulk_only_exchange(&mut dev, &command, &parameters);
+        assert_eq!(csw.status, CommandStatusWrapper::COMMAND_FAILED);
+        assert_eq!(bulk_only_sense(&mut dev), illegal_opcode);
+
+        let mut dev = TestResponder::default();
+        LogSenseCommand::new(0x00, 64)
+            .push_to_buffer(&mut command)
+            .unwrap();
+        let (data, csw) = bulk_only_exchange(&mut dev, &command, &[]);
+        assert!(data.is_empty());
+        assert_eq!(csw.status, CommandStatusWrapper::COMMAND_FAILED);
+        assert_eq!(csw.data_residue, 64);
+    }
 }
@@ -0,0 +1,129 @@
+use core::cell::RefCell;
+
+use embedded_sdmmc::{Block, BlockCount, BlockDevice, BlockIdx};
+
+use crate::error::{ErrorCause, ScsiError};
+use crate::scsi::ScsiBlockDevice;
+use crate::traits::CommunicationChannel;
+
+/// The number of `embedded_sdmmc::Block`s transferred per SCSI command.
+const BLOCKS_PER_TRANSFER: usize = 8;
+
+/// Exposes an `ScsiBlockDevice` as an `embedded_sdmmc::BlockDevice`, so that
+/// its FAT volumes can be opened with an `embedded_sdmmc::VolumeManager`.
+///
+/// `embedded-sdmmc` always addresses the disk in 512 byte blocks, so only
+/// devices whose block size evenly divides 512 bytes are supported.
+pub struct SdmmcBlockDevice<CommType: CommunicationChannel> {
+    device: RefCell<ScsiBlockDevice<CommType>>,
+    num_blocks: u32,
+}
+
+impl<CommType: CommunicationChannel> SdmmcBlockDevice<CommType> {
+    /// Wraps `device`.
+    ///
+    /// # Errors
+    /// Returns a `NonBlocksizeMultipleLengthError` if the device's block size
+    /// does not evenly divide 512 bytes.
+    pub fn new(device: ScsiBlockDevice<CommType>) -> Result<Self, ScsiError> {
+        let block_size = device.block_size();
+        if block_size == 0 || Block::LEN_U32 % block_size != 0 {
+            return Err(ScsiError::from_cause(
+                ErrorCause::NonBlocksizeMultipleLengthError {
+                    actual: Block::LEN,
+                    block_size: block_size as usize,
+                },
+            ));
+        }
+        let num_blocks = device.capacity_bytes() / u64::from(Block::LEN_U32);
+        Ok(SdmmcBlockDevice {
+            device: RefCell::new(device),
+            num_blocks: num_blocks.min(u64::from(u32::MAX)) as u32,
+        })
+    }
+
+    /// Unwraps the underlying `ScsiBlockDevice`.
+    pub fn into_inner(self) -> ScsiBlockDevice<CommType> {
+        self.device.into_inner()
+    }
+}
+
+impl<CommType: CommunicationChannel> BlockDevice for SdmmcBlockDevice<CommType> {
+    type Error = ScsiError;
+
+    fn read(
+        &self,
+        blocks: &mut [Block],
+        start_block_idx: BlockIdx,
+        _reason: &str,
+    ) -> Result<(), ScsiError> {
+        let mut device = self.device.borrow_mut();
+        let ratio = Block::LEN_U32 / device.block_size();
+        let mut scratch = [0; BLOCKS_PER_TRANSFER * Block::LEN];
+        let mut idx = start_block_idx.0;
+        for chunk in blocks.chunks_mut(BLOCKS_PER_TRANSFER) {
+            let data = &mut scratch[..chunk.len() * Block::LEN];
+            let lba = u64::from(idx) * u64::from(ratio);
+            device.read_blocks(lba, chunk.len() as u32 * ratio, &mut *data)?;
+            for (block, bytes) in chunk.iter_mut().zip(data.chunks(Block::LEN)) {
+                block.contents.copy_from_slice(bytes);
+            }
+            idx += chunk.len() as u32;
+        }
+        Ok(())
+    }
+
+    fn write(&self, blocks: &[Block], start_block_idx: BlockIdx) -> Result<(), ScsiError> {
+        let mut device = self.device.borrow_mut();
+        let ratio = Block::LEN_U32 / device.block_size();
+        let mut scratch = [0; BLOCKS_PER_TRANSFER * Block::LEN];
+        let mut idx = start_block_idx.0;
+        for chunk in blocks.chunks(BLOCKS_PER_TRANSFER) {
+            let data = &mut scratch[..chunk.len() * Block::LEN];
+            for (block, bytes) in chunk.iter().zip(data.chunks_mut(Block::LEN)) {
+                bytes.copy_from_slice(&block.contents);
+            }
+            let lba = u64::from(idx) * u64::from(ratio);
+            device.write_blocks(lba, chunk.len() as u32 * ratio, &*data)?;
+            idx += chunk.len() as u32;
+        }
+        Ok(())
+    }
+
+    fn num_blocks(&self) -> Result<BlockCount, ScsiError> {
+        Ok(BlockCount(self.num_blocks))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::SdmmcBlockDevice;
+    use crate::scsi::responder::tests::{LoopbackChannel, MemoryResponder};
+    use crate::scsi::ScsiBlockDevice;
+    use embedded_sdmmc::{Block, BlockDevice, BlockIdx};
+    use std::vec::Vec;
+
+    #[test]
+    fn test_sdmmc_blocks() {
+        let channel = LoopbackChannel::new(MemoryResponder::default());
+        let mut scratch = [0; 64];
+        let device = ScsiBlockDevice::new(channel, &mut scratch).unwrap();
+        let adapter = SdmmcBlockDevice::new(device).unwrap();
+        assert_eq!(adapter.num_blocks().unwrap().0, 512);
+
+        let blocks: Vec<Block> = (0..10)
+            .map(|idx| Block {
+                contents: [idx as u8; Block::LEN],
+            })
+            .collect();
+        adapter.write(&blocks, BlockIdx(3)).unwrap();
+
+        let mut readback = vec![Block::new(); 12];
+        adapter.read(&mut readback, BlockIdx(2), "test").unwrap();
+        assert!(readback[0].contents.iter().all(|&b| b == 0));
+        assert!(readback[11].contents.iter().all(|&b| b == 0));
+        for (read, written) in readback[1..11].iter().zip(blocks.iter()) {
+            assert_eq!(&read.contents[..], &written.contents[..]);
+        }
+    }
+}
@@ -0,0 +1,298 @@
+use std::fs::{File, OpenOptions};
+use std::io;
+use std::mem;
+use std::os::unix::io::AsRawFd;
+use std::path::Path;
+use std::ptr;
+use std::time::Duration;
+
+use libc::{c_int, c_uchar, c_uint, c_ushort, c_void};
+
+use crate::error::{ErrorCause, ScsiError};
+use crate::scsi::commands::{RequestSenseResponse, ScsiStatus};
+use crate::scsi::passthrough::BulkOnlyEmulation;
+use crate::scsi::{CommandData, CommandExecutor, CommandOutcome};
+use crate::traits::{BufferPullable, CommunicationChannel};
+
+/// Runs a command described by an `SgIoHeader`.
+const SG_IO: u32 = 0x2285;
+
+/// Reads the version of the sg driver, which also tells us whether a file
+/// accepts `SG_IO` at all.
+const SG_GET_VERSION_NUM: u32 = 0x2282;
+
+/// The first sg driver version that understands version 3 headers.
+const SG_MIN_VERSION: c_int = 30000;
+
+/// Data transfer directions for `SgIoHeader::dxfer_direction`.
+const SG_DXFER_NONE: c_int = -1;
+const SG_DXFER_TO_DEV: c_int = -2;
+const SG_DXFER_FROM_DEV: c_int = -3;
+
+/// The part of `driver_status` holding the `DRIVER_*` code.
+const DRIVER_STATUS_MASK: c_ushort = 0x0f;
+
+/// The driver status reporting that sense data was written, which is not a
+/// failure in itself.
+const DRIVER_SENSE: c_ushort = 0x08;
+
+/// The size of the buffer sense data is collected in.
+const SENSE_BUFFER_SIZE: usize = 32;
+
+/// How long a command may take before the kernel aborts it, by default.
+const DEFAULT_TIMEOUT_MS: u32 = 30_000;
+
+/// The kernel's `struct sg_io_hdr`, the version 3 interface of `SG_IO`.
+#[repr(C)]
+#[allow(dead_code)]
+struct SgIoHeader {
+    interface_id: c_int,
+    dxfer_direction: c_int,
+    cmd_len: c_uchar,
+    mx_sb_len: c_uchar,
+    iovec_count: c_ushort,
+    dxfer_len: c_uint,
+    dxferp: *mut c_void,
+    cmdp: *const c_uchar,
+    sbp: *mut c_uchar,
+    timeout: c_uint,
+    flags: c_uint,
+    pack_id: c_int,
+    usr_ptr: *mut c_void,
+    status: c_uchar,
+    masked_status: c_uchar,
+    msg_status: c_uchar,
+    sb_len_wr: c_uchar,
+    host_status: c_ushort,
+    driver_status: c_ushort,
+    resid: c_int,
+    duration: c_uint,
+    info: c_uint,
+}
+
+/// A SCSI device reached through Linux's `SG_IO` ioctl, such as a `/dev/sg*`
+/// node or a whole-disk `/dev/sd*` node.
+///
+/// This works with any device the kernel's SCSI layer knows about, including
+/// USB drives, SATA disks behind libata and the `scsi_debug` module. Commands
+/// can be run directly through `CommandExecutor`, or the device can be handed
+/// to `ScsiBlockDevice` as its `CommunicationChannel`, in which case the
+/// Bulk-Only CBWs it writes are unwrapped and sent as bare commands.
+///
+/// Opening these nodes usually needs root, or membership of the `disk` group.
+pub struct SgDevice {
+    file: File,
+    timeout_ms: u32,
+    emulation: BulkOnlyEmulation,
+}
+
+impl SgDevice {
+    /// Opens the device node at `path`.
+    ///
+    /// # Errors
+    /// Returns an `InvalidDeviceError` if the file does not support `SG_IO`.
+    pub fn open<P: AsRef<Path>>(path: P) -> Result<SgDevice, ScsiError> {
+        let file = OpenOptions::new().read(true).write(true).open(path)?;
+        SgDevice::from_file(file)
+    }
+
+    /// Uses an already opened device node.
+    ///
+    /// # Errors
+    /// Returns an `InvalidDeviceError` if the file does not support `SG_IO`.
+    pub fn from_file(file: File) -> Result<SgDevice, ScsiError> {
+        let mut version: c_int = 0;
+        // SAFETY: SG_GET_VERSION_NUM only writes a single int.
+        let result = unsafe {
+            libc::ioctl(
+                file.as_raw_fd(),
+                SG_GET_VERSION_NUM as _,
+                &mut version as *mut c_int,
+            )
+        };
+        if result < 0 || version < SG_MIN_VERSION {
+            return Err(ScsiError::from_cause(ErrorCause::InvalidDeviceError));
+        }
+        Ok(SgDevice {
+            file,
+            timeout_ms: DEFAULT_TIMEOUT_MS,
+            emulation: BulkOnlyEmulation::default(),
+        })
+    }
+
+    /// How long a command may take before the kernel aborts it.
+    pub fn timeout(&self) -> Duration {
+        Duration::from_millis(u64::from(self.timeout_ms))
+    }
+
+    /// Sets how long a command may take before the kernel aborts it, which
+    /// is reported as a `HostAdapterError`. Defaults to 30 seconds.
+    pub fn set_timeout(&mut self, timeout: Duration) {
+        self.timeout_ms = timeout.as_millis().min(u128::from(u32::MAX)) as u32;
+    }
+
+    /// Returns a reference to the device node.
+    pub fn get_ref(&self) -> &File {
+        &self.file
+    }
+
+    /// Closes the channel, returning the device node.
+    pub fn into_inner(self) -> File {
+        self.file
+    }
+}
+
+impl CommandExecutor for SgDevice {
+    fn execute(&mut self, cdb: &[u8], data: CommandData) -> Result<CommandOutcome, ScsiError> {
+        if cdb.is_empty() || cdb.len() > 16 {
+            return Err(ScsiError::from_cause(ErrorCause::ParseError));
+        }
+        let (dxfer_direction, dxferp, length) = match data {
+            CommandData::None => (SG_DXFER_NONE, ptr::null_mut(), 0),
+            CommandData::In(buffer) => (
+                SG_DXFER_FROM_DEV,
+                buffer.as_mut_ptr() as *mut c_void,
+                buffer.len(),
+            ),
+            CommandData::Out(buffer) => (
+                SG_DXFER_TO_DEV,
+                buffer.as_ptr() as *mut c_void,
+                buffer.len(),
+            ),
+        };
+        if length > u32::MAX as usize {
+            return Err(ScsiError::from_cause(ErrorCause::TransferTooLargeError {
+                actual: length,
+                max: u32::MAX as usize,
+            }));
+        }
+        let mut sense = [0; SENSE_BUFFER_SIZE];
+        let mut header = SgIoHeader {
+            interface_id: c_int::from(b'S'),
+            dxfer_direction,
+            cmd_len: cdb.len() as c_uchar,
+            mx_sb_len: SENSE_BUFFER_SIZE as c_uchar,
+            iovec_count: 0,
+            dxfer_len: length as c_uint,
+            dxferp,
+            cmdp: cdb.as_ptr(),
+            sbp: sense.as_mut_ptr(),
+            timeout: self.timeout_ms,
+            flags: 0,
+            pack_id: 0,
+            usr_ptr: ptr::null_mut(),
+            status: 0,
+            masked_status: 0,
+            msg_status: 0,
+            sb_len_wr: 0,
+            host_status: 0,
+            driver_status: 0,
+            resid: 0,
+            duration: 0,
+            info: 0,
+        };
+        // SAFETY: the header only points at buffers that outlive the call,
+        // and the kernel never accesses more than the lengths it is given.
+        // Data is only written through `dxferp` for reads, where it points
+        // at a mutable buffer.
+        let result = unsafe {
+            libc::ioctl(
+                self.file.as_raw_fd(),
+                SG_IO as _,
+                &mut header as *mut SgIoHeader,
+            )
+        };
+        if result < 0 {
+            return Err(io::Error::last_os_error().into());
+        }
+        let driver_status = header.driver_status & DRIVER_STATUS_MASK;
+        if header.host_status != 0 || (driver_status != 0 && driver_status != DRIVER_SENSE) {
+            return Err(ScsiError::from_cause(ErrorCause::HostAdapterError {
+                host_status: header.host_status,
+                driver_status: header.driver_status,
+            }));
+        }
+        let status = ScsiStatus::from_code(header.status)?;
+        let sense = if status == ScsiStatus::CheckCondition && header.sb_len_wr > 0 {
+            RequestSenseResponse::pull_from_buffer(&sense[..]).ok()
+        } else {
+            None
+        };
+        Ok(CommandOutcome {
+            status,
+            sense,
+            residual: header.resid.max(0) as u32,
+        })
+    }
+}
+
+impl CommunicationChannel for SgDevice {
+    fn out_transfer<B: AsRef<[u8]>>(&mut self, bytes: B) -> Result<usize, ScsiError> {
+        let mut emulation = mem::take(&mut self.emulation);
+        let result = emulation.out_transfer(self, bytes.as_ref());
+        self.emulation = emulation;
+        result
+    }
+
+    fn in_transfer<B: AsMut<[u8]>>(&mut self, mut buffer: B) -> Result<usize, ScsiError> {
+        Ok(self.emulation.in_transfer(buffer.as_mut()))
+    }
+
+    fn scsi_status(&self) -> Option<ScsiStatus> {
+        self.emulation.scsi_status()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{SgDevice, SgIoHeader};
+    use crate::error::ErrorCause;
+    use crate::scsi::commands::ScsiStatus;
+    use crate::scsi::{CommandData, CommandExecutor, ScsiBlockDevice};
+    use std::env;
+    use std::mem;
+    use std::vec::Vec;
+
+    #[test]
+    fn test_sg_header_layout() {
+        let expected = if cfg!(target_pointer_width = "64") {
+            88
+        } else {
+            64
+        };
+        assert_eq!(mem::size_of::<SgIoHeader>(), expected);
+    }
+
+    #[test]
+    fn test_sg_rejects_other_files() {
+        let error = SgDevice::open("/dev/null").err().unwrap();
+        assert_eq!(error.cause, ErrorCause::InvalidDeviceError);
+    }
+
+    /// Runs against a real device, eg one created with
+    /// `modprobe scsi_debug dev_size_mb=8`. Point `SCSI_SG_DEVICE` at its
+    /// `/dev/sg*` node and run with `--ignored`.
+    #[test]
+    #[ignore = "needs root and a scratch SCSI device in SCSI_SG_DEVICE"]
+    fn test_sg_scsi_debug() {
+        let path = env::var("SCSI_SG_DEVICE").unwrap();
+        let channel = SgDevice::open(path).unwrap();
+        let mut scratch = [0; 512];
+        let mut device = ScsiBlockDevice::new(channel, &mut scratch[..]).unwrap();
+        let block_size = device.block_size() as usize;
+        let written: Vec<u8> = (0..block_size * 4).map(|idx| (idx % 251) as u8).collect();
+        device.write_blocks(8, 4, &written).unwrap();
+        let mut read = vec![0; written.len()];
+        device.read_blocks(8, 4, &mut read).unwrap();
+        assert_eq!(read, written);
+
+        // Reads past the end of the device fail with sense data.
+        let mut channel = device.comm_channel;
+        let cdb = [0x28, 0, 0xff, 0xff, 0xff, 0xf0, 0, 0, 1, 0];
+        let outcome = channel
+            .execute(&cdb, CommandData::In(&mut read[..block_size]))
+            .unwrap();
+        assert_eq!(outcome.status, ScsiStatus::CheckCondition);
+        assert_eq!(outcome.sense.unwrap().additional_sense_code, 0x21);
+    }
+}
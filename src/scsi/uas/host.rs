@@ -0,0 +1,809 @@
+use crate::error::{ErrorCause, ScsiError};
+use crate::scsi::commands::{Command, Direction, RequestSenseResponse, ScsiStatus};
+use crate::scsi::uas::iu::write_all;
+use crate::scsi::uas::{CommandIu, ResponseIu, StatusIu, TaskManagementIu};
+use crate::traits::{BufferPushable, CommunicationChannel};
+
+/// The largest number of commands a `UasHost` can keep in flight at once.
+pub const MAX_QUEUE_DEPTH: usize = 32;
+
+/// Something the device reported over the status pipe.
+#[derive(Clone, Copy, Eq, PartialEq, Debug)]
+pub enum UasEvent {
+    /// The device is ready to send the data for the command with the given
+    /// tag; it should be read with `UasHost::receive_data`.
+    ReadReady {
+        /// The tag of the command.
+        tag: u16,
+    },
+
+    /// The device is ready to receive the data for the command with the given
+    /// tag; it should be sent with `UasHost::send_data`.
+    WriteReady {
+        /// The tag of the command.
+        tag: u16,
+    },
+
+    /// The command with the given tag finished, and its tag may be reused.
+    Completed {
+        /// The tag of the command.
+        tag: u16,
+
+        /// The SCSI status of the command.
+        status: ScsiStatus,
+
+        /// The sense data describing a failed command, if any.
+        sense: Option<RequestSenseResponse>,
+    },
+
+    /// The device answered a task management request, or rejected the IU with
+    /// the given tag.
+    Response {
+        /// The tag of the IU being answered.
+        tag: u16,
+
+        /// The outcome; see the `ResponseIu::*` constants.
+        response_code: u8,
+
+        /// Additional information, such as the result of a query task function.
+        additional_response_info: [u8; 3],
+    },
+}
+
+/// A command that has been sent to the device but has not completed yet.
+#[derive(Clone, Copy, Eq, PartialEq, Debug)]
+struct InFlight {
+    tag: u16,
+    direction: Direction,
+    transfer_length: u32,
+}
+
+/// A task management request that has not been answered yet.
+#[derive(Clone, Copy, Eq, PartialEq, Debug)]
+struct PendingTaskManagement {
+    tag: u16,
+    function: u8,
+    task_tag: u16,
+}
+
+/// The data buffer belonging to a `UasRequest`.
+#[derive(Debug)]
+pub enum UasData<'a> {
+    /// The command transfers no data.
+    None,
+
+    /// The command reads data from the device into the buffer.
+    In(&'a mut [u8]),
+
+    /// The command writes the buffer's contents to the device.
+    Out(&'a [u8]),
+}
+
+/// A single command queued through `UasHost::execute`, along with its data
+/// buffer and, once the command completes, its outcome.
+#[derive(Debug)]
+pub struct UasRequest<'a> {
+    iu: CommandIu,
+    direction: Direction,
+    transfer_length: u32,
+    data: UasData<'a>,
+
+    /// The SCSI status of the command, or `None` if it has not completed.
+    pub status: Option<ScsiStatus>,
+
+    /// The sense data returned with a failed status, if any.
+    pub sense: Option<RequestSenseResponse>,
+
+    /// The number of bytes transferred to or from the data buffer.
+    pub transferred: usize,
+}
+
+impl<'a> UasRequest<'a> {
+    /// Constructs a request that runs `command`, transferring data to or from
+    /// `data`.
+    ///
+    /// # Errors
+    /// Returns a `BufferTooSmallError` if `data` does not match the direction of
+    /// `command` or is shorter than the amount of data `command` transfers.
+    pub fn new<C: Command>(command: &C, data: UasData<'a>) -> Result<UasRequest<'a>, ScsiError> {
+        let wrapper = command.wrapper();
+        let transfer_length = match wrapper.direction {
+            Direction::NONE => 0,
+            _ => wrapper.data_transfer_length,
+        };
+        let available = match (&data, wrapper.direction) {
+            (UasData::In(buffer), Direction::IN) => buffer.len(),
+            (UasData::Out(buffer), Direction::OUT) => buffer.len(),
+            _ => 0,
+        };
+        if available < transfer_length as usize {
+            return Err(ScsiError::from_cause(ErrorCause::BufferTooSmallError {
+                expected: transfer_length as usize,
+                actual: available,
+            }));
+        }
+        Ok(UasRequest {
+            iu: CommandIu::from_command(0, command)?,
+            direction: wrapper.direction,
+            transfer_length,
+            data,
+            status: None,
+            sense: None,
+            transferred: 0,
+        })
+    }
+
+    /// The tag the request was sent with, or 0 if it has not been sent yet.
+    pub fn tag(&self) -> u16 {
+        self.iu.tag
+    }
+
+    /// Whether the request completed with a `GOOD` or `CONDITION MET` status.
+    pub fn succeeded(&self) -> bool {
+        self.status.is_some_and(ScsiStatus::is_good)
+    }
+}
+
+/// The host side of a USB Attached SCSI session.
+///
+/// Unlike the Bulk-Only transport used by `ScsiBlockDevice`, UAS splits the
+/// conversation over four pipes and tags every command, so that several
+/// commands can be in flight at once and the device can complete them in any
+/// order:
+///
+/// * The command pipe carries `CommandIu`s and `TaskManagementIu`s to the device.
+/// * The status pipe carries `SenseIu`s, `ResponseIu`s and the Read and Write
+///   Ready IUs announcing each command's data phase back to the host.
+/// * The data-in and data-out pipes carry the data itself.
+///
+/// Commands can either be driven by hand with `submit`, `next_event`,
+/// `receive_data` and `send_data`, or handed over in bulk to `execute`.
+pub struct UasHost<CmdPipe, StatusPipe, InPipe, OutPipe>
+where
+    CmdPipe: CommunicationChannel,
+    StatusPipe: CommunicationChannel,
+    InPipe: CommunicationChannel,
+    OutPipe: CommunicationChannel,
+{
+    /// The pipe the host sends commands and task management requests over.
+    pub command_pipe: CmdPipe,
+
+    /// The pipe the device sends status IUs over.
+    pub status_pipe: StatusPipe,
+
+    /// The pipe the device sends data over.
+    pub data_in_pipe: InPipe,
+
+    /// The pipe the host sends data over.
+    pub data_out_pipe: OutPipe,
+
+    lun: u64,
+    queue_depth: usize,
+    next_tag: u16,
+    in_flight: [Option<InFlight>; MAX_QUEUE_DEPTH],
+    task_management: Option<PendingTaskManagement>,
+}
+
+impl<CmdPipe, StatusPipe, InPipe, OutPipe> UasHost<CmdPipe, StatusPipe, InPipe, OutPipe>
+where
+    CmdPipe: CommunicationChannel,
+    StatusPipe: CommunicationChannel,
+    InPipe: CommunicationChannel,
+    OutPipe: CommunicationChannel,
+{
+    /// Starts a new session with logical unit 0 over the given pipes.
+    pub fn new(
+        command_pipe: CmdPipe,
+        status_pipe: StatusPipe,
+        data_in_pipe: InPipe,
+        data_out_pipe: OutPipe,
+    ) -> Self {
+        UasHost {
+            command_pipe,
+            status_pipe,
+            data_in_pipe,
+            data_out_pipe,
+            lun: 0,
+            queue_depth: MAX_QUEUE_DEPTH,
+            next_tag: 1,
+            in_flight: [None; MAX_QUEUE_DEPTH],
+            task_management: None,
+        }
+    }
+
+    /// Addresses all future commands to the given logical unit, in SAM format.
+    pub fn set_lun(&mut self, lun: u64) {
+        self.lun = lun;
+    }
+
+    /// Limits the number of commands kept in flight at once, eg to match the
+    /// number of streams the device supports.
+    ///
+    /// The depth is clamped to between 1 and `MAX_QUEUE_DEPTH`.
+    pub fn set_queue_depth(&mut self, depth: usize) {
+        self.queue_depth = depth.clamp(1, MAX_QUEUE_DEPTH);
+    }
+
+    /// The number of commands kept in flight at once.
+    pub fn queue_depth(&self) -> usize {
+        self.queue_depth
+    }
+
+    /// The number of commands that have been submitted but have not completed.
+    pub fn in_flight(&self) -> usize {
+        self.in_flight.iter().filter(|slot| slot.is_some()).count()
+    }
+
+    /// Sends `command` to the device, returning the tag that identifies it in
+    /// later `UasEvent`s.
+    ///
+    /// # Errors
+    /// Returns a `QueueFullError` if `queue_depth` commands are already in flight.
+    pub fn submit<C: Command>(&mut self, command: &C) -> Result<u16, ScsiError> {
+        let wrapper = command.wrapper();
+        let transfer_length = match wrapper.direction {
+            Direction::NONE => 0,
+            _ => wrapper.data_transfer_length,
+        };
+        let iu = CommandIu::from_command(0, command)?;
+        self.submit_iu(iu, wrapper.direction, transfer_length)
+    }
+
+    fn submit_iu(
+        &mut self,
+        mut iu: CommandIu,
+        direction: Direction,
+        transfer_length: u32,
+    ) -> Result<u16, ScsiError> {
+        if self.in_flight() >= self.queue_depth {
+            return Err(ScsiError::from_cause(ErrorCause::QueueFullError {
+                depth: self.queue_depth,
+            }));
+        }
+        let slot = match self.in_flight.iter().position(|slot| slot.is_none()) {
+            Some(slot) => slot,
+            None => {
+                return Err(ScsiError::from_cause(ErrorCause::QueueFullError {
+                    depth: self.queue_depth,
+                }))
+            }
+        };
+        iu.tag = self.allocate_tag();
+        iu.lun = self.lun;
+        let mut buffer = [0; CommandIu::SIZE];
+        iu.push_to_buffer(&mut buffer[..])?;
+        write_all(&mut self.command_pipe, &buffer)?;
+        self.in_flight[slot] = Some(InFlight {
+            tag: iu.tag,
+            direction,
+            transfer_length,
+        });
+        Ok(iu.tag)
+    }
+
+    /// Asks the device to abort the command with the given tag, returning the
+    /// tag of the request.
+    ///
+    /// The device answers with a `UasEvent::Response`; if the abort succeeded,
+    /// the command is dropped without ever completing.
+    pub fn abort_task(&mut self, tag: u16) -> Result<u16, ScsiError> {
+        self.task_management(TaskManagementIu::ABORT_TASK, tag)
+    }
+
+    /// Asks the device to reset the logical unit, aborting every command in
+    /// flight, and returns the tag of the request.
+    ///
+    /// The device answers with a `UasEvent::Response`.
+    pub fn logical_unit_reset(&mut self) -> Result<u16, ScsiError> {
+        self.task_management(TaskManagementIu::LOGICAL_UNIT_RESET, 0)
+    }
+
+    /// Sends an arbitrary task management request, returning the tag of the
+    /// request.
+    ///
+    /// Only one request can be outstanding at a time.
+    ///
+    /// # Errors
+    /// Returns a `QueueFullError` if another request has not been answered yet.
+    pub fn task_management(&mut self, function: u8, task_tag: u16) -> Result<u16, ScsiError> {
+        if self.task_management.is_some() {
+            return Err(ScsiError::from_cause(ErrorCause::QueueFullError {
+                depth: 1,
+            }));
+        }
+        let mut iu = TaskManagementIu::new(self.allocate_tag(), function, task_tag);
+        iu.lun = self.lun;
+        let mut buffer = [0; TaskManagementIu::SIZE];
+        iu.push_to_buffer(&mut buffer[..])?;
+        write_all(&mut self.command_pipe, &buffer)?;
+        self.task_management = Some(PendingTaskManagement {
+            tag: iu.tag,
+            function,
+            task_tag,
+        });
+        Ok(iu.tag)
+    }
+
+    /// Waits for the next IU on the status pipe.
+    ///
+    /// # Errors
+    /// Returns a `FlagError` containing the tag if the device reports on a
+    /// command that is not in flight.
+    pub fn next_event(&mut self) -> Result<UasEvent, ScsiError> {
+        match StatusIu::read_from(&mut self.status_pipe)? {
+            StatusIu::ReadReady(iu) => {
+                self.find(iu.tag)?;
+                Ok(UasEvent::ReadReady { tag: iu.tag })
+            }
+            StatusIu::WriteReady(iu) => {
+                self.find(iu.tag)?;
+                Ok(UasEvent::WriteReady { tag: iu.tag })
+            }
+            StatusIu::Sense(iu) => {
+                let slot = self.find(iu.tag)?;
+                self.in_flight[slot] = None;
+                Ok(UasEvent::Completed {
+                    tag: iu.tag,
+                    status: iu.status,
+                    sense: iu.sense,
+                })
+            }
+            StatusIu::Response(iu) => {
+                self.handle_response(&iu)?;
+                Ok(UasEvent::Response {
+                    tag: iu.tag,
+                    response_code: iu.response_code,
+                    additional_response_info: iu.additional_response_info,
+                })
+            }
+        }
+    }
+
+    /// Reads the data for the command with the given tag into `buffer` after a
+    /// `UasEvent::ReadReady`, returning the number of bytes read.
+    ///
+    /// At most the command's transfer length is read; fewer bytes are read if
+    /// the device ends the transfer early.
+    pub fn receive_data(&mut self, tag: u16, buffer: &mut [u8]) -> Result<usize, ScsiError> {
+        let command = self.in_flight[self.find(tag)?].unwrap();
+        if command.direction != Direction::IN {
+            return Err(ScsiError::from_cause(ErrorCause::FlagError {
+                flags: u32::from(tag),
+            }));
+        }
+        let length = buffer.len().min(command.transfer_length as usize);
+        let mut read = 0;
+        while read < length {
+            let cur = self.data_in_pipe.in_transfer(&mut buffer[read..length])?;
+            if cur == 0 {
+                break;
+            }
+            read += cur;
+        }
+        Ok(read)
+    }
+
+    /// Sends the data for the command with the given tag from `buffer` after a
+    /// `UasEvent::WriteReady`, returning the number of bytes sent.
+    ///
+    /// At most the command's transfer length is sent.
+    pub fn send_data(&mut self, tag: u16, buffer: &[u8]) -> Result<usize, ScsiError> {
+        let command = self.in_flight[self.find(tag)?].unwrap();
+        if command.direction != Direction::OUT {
+            return Err(ScsiError::from_cause(ErrorCause::FlagError {
+                flags: u32::from(tag),
+            }));
+        }
+        let length = buffer.len().min(command.transfer_length as usize);
+        write_all(&mut self.data_out_pipe, &buffer[..length])?;
+        Ok(length)
+    }
+
+    /// Runs every request in `requests`, keeping up to `queue_depth` of them in
+    /// flight at once, and returns once all of them have completed.
+    ///
+    /// A request that fails does not stop the others; check each request's
+    /// `status` afterwards.
+    pub fn execute(&mut self, requests: &mut [UasRequest]) -> Result<(), ScsiError> {
+        let mut submitted = 0;
+        let mut completed = 0;
+        while completed < requests.len() {
+            while submitted < requests.len() && self.in_flight() < self.queue_depth {
+                let request = &mut requests[submitted];
+                let tag = self.submit_iu(request.iu, request.direction, request.transfer_length)?;
+                request.iu.tag = tag;
+                submitted += 1;
+            }
+            let event = self.next_event()?;
+            let tag = match event {
+                UasEvent::ReadReady { tag }
+                | UasEvent::WriteReady { tag }
+                | UasEvent::Completed { tag, .. } => tag,
+                UasEvent::Response { .. } => continue,
+            };
+            let request = match requests[..submitted]
+                .iter_mut()
+                .find(|request| request.iu.tag == tag && request.status.is_none())
+            {
+                Some(request) => request,
+                None => {
+                    return Err(ScsiError::from_cause(ErrorCause::FlagError {
+                        flags: u32::from(tag),
+                    }))
+                }
+            };
+            match (event, &mut request.data) {
+                (UasEvent::ReadReady { .. }, UasData::In(buffer)) => {
+                    request.transferred += self.receive_data(tag, &mut buffer[..])?;
+                }
+                (UasEvent::WriteReady { .. }, UasData::Out(buffer)) => {
+                    request.transferred += self.send_data(tag, buffer)?;
+                }
+                (UasEvent::Completed { status, sense, .. }, _) => {
+                    request.status = Some(status);
+                    request.sense = sense;
+                    completed += 1;
+                }
+                _ => {
+                    return Err(ScsiError::from_cause(ErrorCause::FlagError {
+                        flags: u32::from(tag),
+                    }))
+                }
+            }
+        }
+        Ok(())
+    }
+
+    /// Picks the next tag that is not in use, skipping 0.
+    fn allocate_tag(&mut self) -> u16 {
+        loop {
+            let tag = self.next_tag;
+            self.next_tag = self.next_tag.wrapping_add(1).max(1);
+            let in_use = self.find(tag).is_ok()
+                || self
+                    .task_management
+                    .is_some_and(|pending| pending.tag == tag);
+            if !in_use {
+                return tag;
+            }
+        }
+    }
+
+    /// Finds the slot of the in-flight command with the given tag.
+    fn find(&self, tag: u16) -> Result<usize, ScsiError> {
+        self.in_flight
+            .iter()
+            .position(|slot| slot.is_some_and(|command| command.tag == tag))
+            .ok_or_else(|| {
+                ScsiError::from_cause(ErrorCause::FlagError {
+                    flags: u32::from(tag),
+                })
+            })
+    }
+
+    /// Updates the in-flight commands after the device answers a task
+    /// management request or rejects a command.
+    fn handle_response(&mut self, iu: &ResponseIu) -> Result<(), ScsiError> {
+        let pending = match self.task_management {
+            Some(pending) if pending.tag == iu.tag => pending,
+            _ => {
+                // The device rejected one of our commands outright, so it will
+                // never complete.
+                let slot = self.find(iu.tag)?;
+                self.in_flight[slot] = None;
+                return Ok(());
+            }
+        };
+        self.task_management = None;
+        let succeeded = iu.response_code == ResponseIu::TASK_MANAGEMENT_FUNCTION_COMPLETE
+            || iu.response_code == ResponseIu::TASK_MANAGEMENT_FUNCTION_SUCCEEDED;
+        if !succeeded {
+            return Ok(());
+        }
+        match pending.function {
+            TaskManagementIu::ABORT_TASK => {
+                if let Ok(slot) = self.find(pending.task_tag) {
+                    self.in_flight[slot] = None;
+                }
+            }
+            TaskManagementIu::ABORT_TASK_SET
+            | TaskManagementIu::CLEAR_TASK_SET
+            | TaskManagementIu::LOGICAL_UNIT_RESET
+            | TaskManagementIu::I_T_NEXUS_RESET => {
+                self.in_flight = [None; MAX_QUEUE_DEPTH];
+            }
+            _ => {}
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{UasData, UasEvent, UasHost, UasRequest};
+    use crate::error::ErrorCause;
+    use crate::scsi::commands::{
+        InquiryCommand, Read10Command, RequestSenseResponse, ScsiStatus, TestUnitReady,
+        Write10Command,
+    };
+    use crate::scsi::responder::tests::TestDualChannel;
+    use crate::scsi::uas::{
+        CommandIu, HostIu, ReadReadyIu, ResponseIu, SenseIu, StatusIu, TaskManagementIu,
+        WriteReadyIu,
+    };
+    use crate::traits::{BufferPushable, CommunicationChannel};
+    use crate::ScsiError;
+    use byteorder::{ByteOrder, BE};
+    use std::vec::Vec;
+
+    const BLOCK_SIZE: usize = 512;
+
+    /// A UAS device with 64 blocks of memory that only understands READ(10),
+    /// WRITE(10) and TEST UNIT READY, and always completes the most recently
+    /// queued command first.
+    ///
+    /// The device runs whenever the host waits on the empty status pipe, which
+    /// this struct stands in for.
+    struct TestUasDevice {
+        memory: Vec<u8>,
+        queue: Vec<CommandIu>,
+        awaiting_data: Option<CommandIu>,
+        completed: Vec<u16>,
+        host_status: TestDualChannel,
+        command: TestDualChannel,
+        status: TestDualChannel,
+        data_in: TestDualChannel,
+        data_out: TestDualChannel,
+    }
+
+    type TestHost = UasHost<TestDualChannel, TestUasDevice, TestDualChannel, TestDualChannel>;
+
+    fn session() -> TestHost {
+        let command = TestDualChannel::default();
+        let host_status = TestDualChannel::default();
+        let data_in = TestDualChannel::default();
+        let data_out = TestDualChannel::default();
+        let device = TestUasDevice {
+            memory: (0..64 * BLOCK_SIZE)
+                .map(|idx| (idx / BLOCK_SIZE) as u8)
+                .collect(),
+            queue: Vec::new(),
+            awaiting_data: None,
+            completed: Vec::new(),
+            command: command.reversed(),
+            status: host_status.reversed(),
+            data_in: data_in.reversed(),
+            data_out: data_out.reversed(),
+            host_status,
+        };
+        UasHost::new(command, device, data_in, data_out)
+    }
+
+    impl TestUasDevice {
+        fn send(&mut self, iu: StatusIu) {
+            let mut buffer = [0; StatusIu::MAX_SIZE];
+            let len = iu.push_to_buffer(&mut buffer[..]).unwrap();
+            self.status.out_transfer(&buffer[..len]).unwrap();
+        }
+
+        fn complete(&mut self, tag: u16) {
+            self.completed.push(tag);
+            self.send(StatusIu::Sense(SenseIu::good(tag)));
+        }
+
+        fn step(&mut self) {
+            while !self.command.recv_buff.lock().unwrap().is_empty() {
+                match HostIu::read_from(&mut self.command).unwrap() {
+                    HostIu::Command(iu) => self.queue.push(iu),
+                    HostIu::TaskManagement(iu) => {
+                        match iu.function {
+                            TaskManagementIu::ABORT_TASK => {
+                                self.queue.retain(|command| command.tag != iu.task_tag)
+                            }
+                            TaskManagementIu::LOGICAL_UNIT_RESET => self.queue.clear(),
+                            _ => {}
+                        }
+                        self.send(StatusIu::Response(ResponseIu::new(
+                            iu.tag,
+                            ResponseIu::TASK_MANAGEMENT_FUNCTION_COMPLETE,
+                        )));
+                        return;
+                    }
+                }
+            }
+            if let Some(iu) = self.awaiting_data.take() {
+                let start = BE::read_u32(&iu.cdb[2..]) as usize * BLOCK_SIZE;
+                let len = usize::from(BE::read_u16(&iu.cdb[7..])) * BLOCK_SIZE;
+                let mut data = vec![0; len];
+                self.data_out.in_transfer(&mut data[..]).unwrap();
+                self.memory[start..start + len].copy_from_slice(&data);
+                self.complete(iu.tag);
+                return;
+            }
+            let iu = match self.queue.pop() {
+                Some(iu) => iu,
+                None => return,
+            };
+            match iu.opcode() {
+                0x28 => {
+                    let start = BE::read_u32(&iu.cdb[2..]) as usize * BLOCK_SIZE;
+                    let len = usize::from(BE::read_u16(&iu.cdb[7..])) * BLOCK_SIZE;
+                    let data = self.memory[start..start + len].to_vec();
+                    self.data_in.out_transfer(&data).unwrap();
+                    self.send(StatusIu::ReadReady(ReadReadyIu { tag: iu.tag }));
+                    self.complete(iu.tag);
+                }
+                0x2a => {
+                    self.send(StatusIu::WriteReady(WriteReadyIu { tag: iu.tag }));
+                    self.awaiting_data = Some(iu);
+                }
+                0x00 => self.complete(iu.tag),
+                _ => {
+                    let sense = RequestSenseResponse::new(
+                        RequestSenseResponse::ILLEGAL_REQUEST,
+                        0x20,
+                        0x00,
+                    );
+                    self.completed.push(iu.tag);
+                    self.send(StatusIu::Sense(SenseIu::check_condition(iu.tag, sense)));
+                }
+            }
+        }
+    }
+
+    impl CommunicationChannel for TestUasDevice {
+        fn out_transfer<B: AsRef<[u8]>>(&mut self, _bytes: B) -> Result<usize, ScsiError> {
+            Err(ScsiError::from_cause(ErrorCause::UnsupportedOperationError))
+        }
+
+        fn in_transfer<B: AsMut<[u8]>>(&mut self, buffer: B) -> Result<usize, ScsiError> {
+            if self.host_status.recv_buff.lock().unwrap().is_empty() {
+                self.step();
+            }
+            self.host_status.in_transfer(buffer)
+        }
+    }
+
+    #[test]
+    fn test_queued_reads() {
+        let mut host = session();
+        let mut buffers = vec![[0; 2 * BLOCK_SIZE]; 4];
+        let commands: Vec<Read10Command> = (0..4)
+            .map(|idx| {
+                let offset = (idx * 4 * BLOCK_SIZE) as u32;
+                Read10Command::new(offset, 2 * BLOCK_SIZE as u32, BLOCK_SIZE as u32).unwrap()
+            })
+            .collect();
+        let mut requests: Vec<UasRequest> = commands
+            .iter()
+            .zip(buffers.iter_mut())
+            .map(|(command, buffer)| {
+                UasRequest::new(command, UasData::In(&mut buffer[..])).unwrap()
+            })
+            .collect();
+        host.execute(&mut requests).unwrap();
+
+        let tags: Vec<u16> = requests.iter().map(|request| request.tag()).collect();
+        assert_eq!(tags, vec![1, 2, 3, 4]);
+        assert!(requests.iter().all(|request| request.succeeded()));
+        assert!(requests
+            .iter()
+            .all(|request| request.transferred == 2 * BLOCK_SIZE));
+        assert_eq!(host.status_pipe.completed, vec![4, 3, 2, 1]);
+        assert_eq!(host.in_flight(), 0);
+        drop(requests);
+
+        for (idx, buffer) in buffers.iter().enumerate() {
+            assert!(buffer[..BLOCK_SIZE].iter().all(|&b| b as usize == idx * 4));
+            assert!(buffer[BLOCK_SIZE..]
+                .iter()
+                .all(|&b| b as usize == idx * 4 + 1));
+        }
+    }
+
+    #[test]
+    fn test_queued_writes() {
+        let mut host = session();
+        host.set_queue_depth(2);
+        let data: Vec<u8> = (0..3 * BLOCK_SIZE).map(|idx| (idx % 251) as u8).collect();
+        let commands: Vec<Write10Command> = (0..3)
+            .map(|idx| {
+                let offset = ((10 + idx) * BLOCK_SIZE) as u32;
+                Write10Command::new(offset, BLOCK_SIZE as u32, BLOCK_SIZE as u32).unwrap()
+            })
+            .collect();
+        let mut requests: Vec<UasRequest> = commands
+            .iter()
+            .zip(data.chunks(BLOCK_SIZE))
+            .map(|(command, chunk)| UasRequest::new(command, UasData::Out(chunk)).unwrap())
+            .collect();
+        let unit_ready = TestUnitReady::new();
+        requests.push(UasRequest::new(&unit_ready, UasData::None).unwrap());
+        host.execute(&mut requests).unwrap();
+
+        assert!(requests.iter().all(|request| request.succeeded()));
+        // Each completion frees a slot that is refilled before the device runs
+        // again, so the first command waits until the end.
+        assert_eq!(host.status_pipe.completed, vec![2, 3, 4, 1]);
+        let memory = &host.status_pipe.memory;
+        assert_eq!(&memory[10 * BLOCK_SIZE..13 * BLOCK_SIZE], &data[..]);
+        assert!(memory[13 * BLOCK_SIZE..14 * BLOCK_SIZE]
+            .iter()
+            .all(|&b| b == 13));
+    }
+
+    #[test]
+    fn test_check_condition() {
+        let mut host = session();
+        let inquiry = InquiryCommand::new(36);
+        let mut buffer = [0; 36];
+        let mut requests = [UasRequest::new(&inquiry, UasData::In(&mut buffer[..])).unwrap()];
+        host.execute(&mut requests).unwrap();
+        assert_eq!(requests[0].status, Some(ScsiStatus::CheckCondition));
+        assert_eq!(requests[0].transferred, 0);
+        let sense = requests[0].sense.unwrap();
+        assert_eq!(sense.sense_key, RequestSenseResponse::ILLEGAL_REQUEST);
+        assert_eq!(sense.additional_sense_code, 0x20);
+
+        let read = Read10Command::new(0, 2 * BLOCK_SIZE as u32, BLOCK_SIZE as u32).unwrap();
+        let mut short = [0; BLOCK_SIZE];
+        assert!(UasRequest::new(&read, UasData::In(&mut short[..])).is_err());
+        assert!(UasRequest::new(&read, UasData::None).is_err());
+    }
+
+    #[test]
+    fn test_task_management() {
+        let mut host = session();
+        let read = Read10Command::new(0, BLOCK_SIZE as u32, BLOCK_SIZE as u32).unwrap();
+        let first = host.submit(&read).unwrap();
+        let second = host.submit(&read).unwrap();
+        assert_eq!(host.in_flight(), 2);
+
+        let abort = host.abort_task(second).unwrap();
+        assert!(host.logical_unit_reset().is_err());
+        match host.next_event().unwrap() {
+            UasEvent::Response {
+                tag, response_code, ..
+            } => {
+                assert_eq!(tag, abort);
+                assert_eq!(response_code, ResponseIu::TASK_MANAGEMENT_FUNCTION_COMPLETE);
+            }
+            other => panic!("Unexpected event {:?}", other),
+        }
+        assert_eq!(host.in_flight(), 1);
+
+        assert_eq!(
+            host.next_event().unwrap(),
+            UasEvent::ReadReady { tag: first }
+        );
+        let mut buffer = [0xff; BLOCK_SIZE];
+        assert_eq!(host.receive_data(first, &mut buffer).unwrap(), BLOCK_SIZE);
+        assert!(host.send_data(first, &buffer).is_err());
+        match host.next_event().unwrap() {
+            UasEvent::Completed { tag, status, .. } => {
+                assert_eq!(tag, first);
+                assert_eq!(status, ScsiStatus::Good);
+            }
+            other => panic!("Unexpected event {:?}", other),
+        }
+        assert!(host.receive_data(first, &mut buffer).is_err());
+
+        host.submit(&read).unwrap();
+        host.submit(&read).unwrap();
+        host.logical_unit_reset().unwrap();
+        match host.next_event().unwrap() {
+            UasEvent::Response { response_code, .. } => {
+                assert_eq!(response_code, ResponseIu::TASK_MANAGEMENT_FUNCTION_COMPLETE)
+            }
+            other => panic!("Unexpected event {:?}", other),
+        }
+        assert_eq!(host.in_flight(), 0);
+
+        host.set_queue_depth(1);
+        host.submit(&read).unwrap();
+        match host.submit(&read) {
+            Err(err) => assert_eq!(err.cause, ErrorCause::QueueFullError { depth: 1 }),
+            Ok(_) => panic!("Queued past the queue depth"),
+        }
+    }
+}
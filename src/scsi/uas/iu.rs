@@ -0,0 +1,707 @@
+use crate::error::{ErrorCause, ScsiError, UsbTransferDirection};
+use crate::scsi::commands::{command_block, Command, RequestSenseResponse, ScsiStatus};
+use crate::traits::{BufferPullable, BufferPushable, CommunicationChannel};
+use byteorder::{ByteOrder, BE};
+
+/// The size of the header shared by all information units: the IU ID, a
+/// reserved byte and the tag.
+const IU_HEADER_SIZE: usize = 4;
+
+/// Writes the header shared by all information units.
+fn push_header(buffer: &mut [u8], iu_id: u8, tag: u16, size: usize) -> Result<(), ScsiError> {
+    if buffer.len() < size {
+        return Err(ScsiError::from_cause(ErrorCause::BufferTooSmallError {
+            expected: size,
+            actual: buffer.len(),
+        }));
+    }
+    buffer[0] = iu_id;
+    buffer[1] = 0;
+    BE::write_u16(&mut buffer[2..], tag);
+    Ok(())
+}
+
+/// Checks the header shared by all information units, returning the tag.
+fn pull_header(buffer: &[u8], iu_id: u8, size: usize) -> Result<u16, ScsiError> {
+    if buffer.len() < size {
+        return Err(ScsiError::from_cause(ErrorCause::BufferTooSmallError {
+            expected: size,
+            actual: buffer.len(),
+        }));
+    }
+    if buffer[0] != iu_id {
+        return Err(ScsiError::from_cause(ErrorCause::ParseError));
+    }
+    Ok(BE::read_u16(&buffer[2..]))
+}
+
+/// Sent by the host over the command pipe to start a new SCSI command.
+#[derive(Clone, Copy, Eq, PartialEq, Debug, Default)]
+pub struct CommandIu {
+    /// The tag identifying the command until its `SenseIu` is received.
+    pub tag: u16,
+
+    /// The priority of the command, from 0 to 15.
+    pub priority: u8,
+
+    /// How the command should be queued; see the `CommandIu::TASK_*` constants.
+    pub task_attribute: u8,
+
+    /// The logical unit the command is addressed to, in SAM format.
+    pub lun: u64,
+
+    /// The command block itself; only the first `cdb_length` bytes are valid.
+    pub cdb: [u8; CommandIu::MAX_CDB_LENGTH],
+
+    /// The number of valid bytes in `cdb`.
+    pub cdb_length: u8,
+}
+
+impl CommandIu {
+    /// The IU ID of a Command IU.
+    pub const IU_ID: u8 = 0x01;
+
+    /// The size of a Command IU with a command block of up to 16 bytes.
+    pub const SIZE: usize = 32;
+
+    /// The largest command block supported.
+    pub const MAX_CDB_LENGTH: usize = 16;
+
+    /// The command may be reordered freely with other simple commands.
+    pub const TASK_SIMPLE: u8 = 0x0;
+    /// The command is run before any other queued command.
+    pub const TASK_HEAD_OF_QUEUE: u8 = 0x1;
+    /// The command is run after all previously queued commands complete.
+    pub const TASK_ORDERED: u8 = 0x2;
+    /// The command is part of auto contingent allegiance handling.
+    pub const TASK_ACA: u8 = 0x4;
+
+    /// Constructs a simple-queued `CommandIu` for logical unit 0 from one of
+    /// this crate's command structs.
+    pub fn from_command<C: Command>(tag: u16, command: &C) -> Result<CommandIu, ScsiError> {
+        let (cdb, cdb_length) = command_block(command)?;
+        Ok(CommandIu {
+            tag,
+            cdb,
+            cdb_length: cdb_length as u8,
+            ..Default::default()
+        })
+    }
+
+    /// The valid portion of the command block.
+    pub fn cdb(&self) -> &[u8] {
+        &self.cdb[..usize::from(self.cdb_length)]
+    }
+
+    /// The command block's opcode.
+    pub fn opcode(&self) -> u8 {
+        self.cdb[0]
+    }
+}
+
+impl BufferPushable for CommandIu {
+    fn push_to_buffer<B: AsMut<[u8]>>(&self, mut buffer: B) -> Result<usize, ScsiError> {
+        let buffer = buffer.as_mut();
+        push_header(buffer, CommandIu::IU_ID, self.tag, CommandIu::SIZE)?;
+        buffer[4] = ((self.priority & 0xf) << 3) | (self.task_attribute & 0x7);
+        buffer[5] = 0;
+        // No additional CDB bytes beyond the 16 in the IU itself.
+        buffer[6] = 0;
+        buffer[7] = 0;
+        BE::write_u64(&mut buffer[8..], self.lun);
+        buffer[16..32].copy_from_slice(&self.cdb);
+        Ok(CommandIu::SIZE)
+    }
+}
+
+impl BufferPullable for CommandIu {
+    fn pull_from_buffer<B: AsRef<[u8]>>(buffer: B) -> Result<Self, ScsiError> {
+        let buffer = buffer.as_ref();
+        let tag = pull_header(buffer, CommandIu::IU_ID, CommandIu::SIZE)?;
+        if buffer[6] >> 2 != 0 {
+            // Command blocks longer than 16 bytes are not supported.
+            return Err(ScsiError::from_cause(ErrorCause::UnsupportedOperationError));
+        }
+        let mut cdb = [0; CommandIu::MAX_CDB_LENGTH];
+        cdb.copy_from_slice(&buffer[16..32]);
+        Ok(CommandIu {
+            tag,
+            priority: (buffer[4] >> 3) & 0xf,
+            task_attribute: buffer[4] & 0x7,
+            lun: BE::read_u64(&buffer[8..]),
+            cdb_length: cdb_length(cdb[0]) as u8,
+            cdb,
+        })
+    }
+}
+
+/// The length of a command block, as implied by its opcode's group code.
+fn cdb_length(opcode: u8) -> usize {
+    match opcode >> 5 {
+        0 => 6,
+        1 | 2 => 10,
+        5 => 12,
+        _ => CommandIu::MAX_CDB_LENGTH,
+    }
+}
+
+/// Sent by the device over the status pipe when a command completes, taking
+/// the place of the Bulk-Only `CommandStatusWrapper`.
+#[derive(Clone, Copy, Eq, PartialEq, Debug, Default)]
+pub struct SenseIu {
+    /// The tag of the completed command.
+    pub tag: u16,
+
+    /// Additional status information.
+    pub status_qualifier: u16,
+
+    /// The SCSI status of the command.
+    pub status: ScsiStatus,
+
+    /// The sense data describing a failed command, if any.
+    pub sense: Option<RequestSenseResponse>,
+}
+
+impl SenseIu {
+    /// The IU ID of a Sense IU.
+    pub const IU_ID: u8 = 0x03;
+
+    /// The size of a Sense IU without any sense data.
+    pub const HEADER_SIZE: usize = 16;
+
+    /// Constructs a `SenseIu` for a command that completed successfully.
+    pub fn good(tag: u16) -> SenseIu {
+        SenseIu {
+            tag,
+            ..Default::default()
+        }
+    }
+
+    /// Constructs a `SenseIu` for a command that failed with the given sense data.
+    pub fn check_condition(tag: u16, sense: RequestSenseResponse) -> SenseIu {
+        SenseIu {
+            tag,
+            status: ScsiStatus::CheckCondition,
+            sense: Some(sense),
+            ..Default::default()
+        }
+    }
+
+    /// The total size of the IU, including the sense data.
+    pub fn size(&self) -> usize {
+        match self.sense {
+            Some(_) => SenseIu::HEADER_SIZE + RequestSenseResponse::SIZE,
+            None => SenseIu::HEADER_SIZE,
+        }
+    }
+}
+
+impl BufferPushable for SenseIu {
+    fn push_to_buffer<B: AsMut<[u8]>>(&self, mut buffer: B) -> Result<usize, ScsiError> {
+        let buffer = buffer.as_mut();
+        push_header(buffer, SenseIu::IU_ID, self.tag, self.size())?;
+        BE::write_u16(&mut buffer[4..], self.status_qualifier);
+        buffer[6] = self.status.code();
+        for byte in &mut buffer[7..14] {
+            *byte = 0;
+        }
+        let sense_length = match self.sense {
+            Some(ref sense) => sense.push_to_buffer(&mut buffer[SenseIu::HEADER_SIZE..])?,
+            None => 0,
+        };
+        BE::write_u16(&mut buffer[14..], sense_length as u16);
+        Ok(SenseIu::HEADER_SIZE + sense_length)
+    }
+}
+
+impl BufferPullable for SenseIu {
+    fn pull_from_buffer<B: AsRef<[u8]>>(buffer: B) -> Result<Self, ScsiError> {
+        let buffer = buffer.as_ref();
+        let tag = pull_header(buffer, SenseIu::IU_ID, SenseIu::HEADER_SIZE)?;
+        let sense_length = usize::from(BE::read_u16(&buffer[14..]));
+        let sense = if sense_length == 0 {
+            None
+        } else {
+            let end = (SenseIu::HEADER_SIZE + sense_length).min(buffer.len());
+            Some(RequestSenseResponse::pull_from_buffer(
+                &buffer[SenseIu::HEADER_SIZE..end],
+            )?)
+        };
+        Ok(SenseIu {
+            tag,
+            status_qualifier: BE::read_u16(&buffer[4..]),
+            status: ScsiStatus::from_code(buffer[6])?,
+            sense,
+        })
+    }
+}
+
+/// Sent by the device over the status pipe to report the outcome of a
+/// `TaskManagementIu`, or to reject an invalid IU.
+#[derive(Clone, Copy, Eq, PartialEq, Debug, Default)]
+pub struct ResponseIu {
+    /// The tag of the IU this is a response to.
+    pub tag: u16,
+
+    /// Additional information about the response, such as the result of a
+    /// query task function.
+    pub additional_response_info: [u8; 3],
+
+    /// The outcome; see the `ResponseIu::*` constants.
+    pub response_code: u8,
+}
+
+impl ResponseIu {
+    /// The IU ID of a Response IU.
+    pub const IU_ID: u8 = 0x04;
+
+    /// The size of a Response IU.
+    pub const SIZE: usize = 8;
+
+    /// The task management function completed.
+    pub const TASK_MANAGEMENT_FUNCTION_COMPLETE: u8 = 0x00;
+    /// The IU was invalid.
+    pub const INVALID_INFORMATION_UNIT: u8 = 0x02;
+    /// The task management function is not supported.
+    pub const TASK_MANAGEMENT_FUNCTION_NOT_SUPPORTED: u8 = 0x04;
+    /// The task management function failed.
+    pub const TASK_MANAGEMENT_FUNCTION_FAILED: u8 = 0x05;
+    /// The task management function succeeded.
+    pub const TASK_MANAGEMENT_FUNCTION_SUCCEEDED: u8 = 0x08;
+    /// The IU addressed a logical unit that does not exist.
+    pub const INCORRECT_LOGICAL_UNIT_NUMBER: u8 = 0x09;
+    /// The IU reused the tag of a command that is still in flight.
+    pub const OVERLAPPED_TAG_ATTEMPTED: u8 = 0x0a;
+
+    /// Constructs a new `ResponseIu` with the given response code.
+    pub fn new(tag: u16, response_code: u8) -> ResponseIu {
+        ResponseIu {
+            tag,
+            response_code,
+            ..Default::default()
+        }
+    }
+}
+
+impl BufferPushable for ResponseIu {
+    fn push_to_buffer<B: AsMut<[u8]>>(&self, mut buffer: B) -> Result<usize, ScsiError> {
+        let buffer = buffer.as_mut();
+        push_header(buffer, ResponseIu::IU_ID, self.tag, ResponseIu::SIZE)?;
+        buffer[4..7].copy_from_slice(&self.additional_response_info);
+        buffer[7] = self.response_code;
+        Ok(ResponseIu::SIZE)
+    }
+}
+
+impl BufferPullable for ResponseIu {
+    fn pull_from_buffer<B: AsRef<[u8]>>(buffer: B) -> Result<Self, ScsiError> {
+        let buffer = buffer.as_ref();
+        let tag = pull_header(buffer, ResponseIu::IU_ID, ResponseIu::SIZE)?;
+        let mut additional_response_info = [0; 3];
+        additional_response_info.copy_from_slice(&buffer[4..7]);
+        Ok(ResponseIu {
+            tag,
+            additional_response_info,
+            response_code: buffer[7],
+        })
+    }
+}
+
+/// Sent by the host over the command pipe to abort commands or reset the
+/// logical unit.
+#[derive(Clone, Copy, Eq, PartialEq, Debug, Default)]
+pub struct TaskManagementIu {
+    /// The tag of the task management request itself.
+    pub tag: u16,
+
+    /// The function to perform; see the `TaskManagementIu::*` constants.
+    pub function: u8,
+
+    /// The tag of the command the function applies to, for functions such as
+    /// `ABORT_TASK` that target a single command.
+    pub task_tag: u16,
+
+    /// The logical unit the function applies to, in SAM format.
+    pub lun: u64,
+}
+
+impl TaskManagementIu {
+    /// The IU ID of a Task Management IU.
+    pub const IU_ID: u8 = 0x05;
+
+    /// The size of a Task Management IU.
+    pub const SIZE: usize = 16;
+
+    /// Abort the command identified by `task_tag`.
+    pub const ABORT_TASK: u8 = 0x01;
+    /// Abort every command from this host.
+    pub const ABORT_TASK_SET: u8 = 0x02;
+    /// Abort every command from every host.
+    pub const CLEAR_TASK_SET: u8 = 0x04;
+    /// Reset the logical unit, aborting every command.
+    pub const LOGICAL_UNIT_RESET: u8 = 0x08;
+    /// Reset the connection between the host and the device.
+    pub const I_T_NEXUS_RESET: u8 = 0x10;
+    /// Clear an auto contingent allegiance condition.
+    pub const CLEAR_ACA: u8 = 0x40;
+    /// Ask whether the command identified by `task_tag` is still in flight.
+    pub const QUERY_TASK: u8 = 0x80;
+    /// Ask whether any commands from this host are still in flight.
+    pub const QUERY_TASK_SET: u8 = 0x81;
+    /// Ask whether there is a pending unit attention or deferred error.
+    pub const QUERY_ASYNCHRONOUS_EVENT: u8 = 0x82;
+
+    /// Constructs a new `TaskManagementIu` for logical unit 0.
+    pub fn new(tag: u16, function: u8, task_tag: u16) -> TaskManagementIu {
+        TaskManagementIu {
+            tag,
+            function,
+            task_tag,
+            lun: 0,
+        }
+    }
+}
+
+impl BufferPushable for TaskManagementIu {
+    fn push_to_buffer<B: AsMut<[u8]>>(&self, mut buffer: B) -> Result<usize, ScsiError> {
+        let buffer = buffer.as_mut();
+        push_header(
+            buffer,
+            TaskManagementIu::IU_ID,
+            self.tag,
+            TaskManagementIu::SIZE,
+        )?;
+        buffer[4] = self.function;
+        buffer[5] = 0;
+        BE::write_u16(&mut buffer[6..], self.task_tag);
+        BE::write_u64(&mut buffer[8..], self.lun);
+        Ok(TaskManagementIu::SIZE)
+    }
+}
+
+impl BufferPullable for TaskManagementIu {
+    fn pull_from_buffer<B: AsRef<[u8]>>(buffer: B) -> Result<Self, ScsiError> {
+        let buffer = buffer.as_ref();
+        let tag = pull_header(buffer, TaskManagementIu::IU_ID, TaskManagementIu::SIZE)?;
+        Ok(TaskManagementIu {
+            tag,
+            function: buffer[4],
+            task_tag: BE::read_u16(&buffer[6..]),
+            lun: BE::read_u64(&buffer[8..]),
+        })
+    }
+}
+
+/// Sent by the device over the status pipe when it is ready to send the data
+/// for the command with the given tag over the data-in pipe.
+#[derive(Clone, Copy, Eq, PartialEq, Debug, Default)]
+pub struct ReadReadyIu {
+    /// The tag of the command whose data is about to be sent.
+    pub tag: u16,
+}
+
+impl ReadReadyIu {
+    /// The IU ID of a Read Ready IU.
+    pub const IU_ID: u8 = 0x06;
+
+    /// The size of a Read Ready IU.
+    pub const SIZE: usize = IU_HEADER_SIZE;
+}
+
+impl BufferPushable for ReadReadyIu {
+    fn push_to_buffer<B: AsMut<[u8]>>(&self, mut buffer: B) -> Result<usize, ScsiError> {
+        push_header(
+            buffer.as_mut(),
+            ReadReadyIu::IU_ID,
+            self.tag,
+            ReadReadyIu::SIZE,
+        )?;
+        Ok(ReadReadyIu::SIZE)
+    }
+}
+
+impl BufferPullable for ReadReadyIu {
+    fn pull_from_buffer<B: AsRef<[u8]>>(buffer: B) -> Result<Self, ScsiError> {
+        let tag = pull_header(buffer.as_ref(), ReadReadyIu::IU_ID, ReadReadyIu::SIZE)?;
+        Ok(ReadReadyIu { tag })
+    }
+}
+
+/// Sent by the device over the status pipe when it is ready to receive the
+/// data for the command with the given tag over the data-out pipe.
+#[derive(Clone, Copy, Eq, PartialEq, Debug, Default)]
+pub struct WriteReadyIu {
+    /// The tag of the command whose data should be sent.
+    pub tag: u16,
+}
+
+impl WriteReadyIu {
+    /// The IU ID of a Write Ready IU.
+    pub const IU_ID: u8 = 0x07;
+
+    /// The size of a Write Ready IU.
+    pub const SIZE: usize = IU_HEADER_SIZE;
+}
+
+impl BufferPushable for WriteReadyIu {
+    fn push_to_buffer<B: AsMut<[u8]>>(&self, mut buffer: B) -> Result<usize, ScsiError> {
+        push_header(
+            buffer.as_mut(),
+            WriteReadyIu::IU_ID,
+            self.tag,
+            WriteReadyIu::SIZE,
+        )?;
+        Ok(WriteReadyIu::SIZE)
+    }
+}
+
+impl BufferPullable for WriteReadyIu {
+    fn pull_from_buffer<B: AsRef<[u8]>>(buffer: B) -> Result<Self, ScsiError> {
+        let tag = pull_header(buffer.as_ref(), WriteReadyIu::IU_ID, WriteReadyIu::SIZE)?;
+        Ok(WriteReadyIu { tag })
+    }
+}
+
+/// Any of the information units the device sends over the status pipe.
+#[derive(Clone, Copy, Eq, PartialEq, Debug)]
+pub enum StatusIu {
+    /// A command completed.
+    Sense(SenseIu),
+
+    /// A task management function completed, or an IU was rejected.
+    Response(ResponseIu),
+
+    /// The device is about to send a command's data.
+    ReadReady(ReadReadyIu),
+
+    /// The device is waiting for a command's data.
+    WriteReady(WriteReadyIu),
+}
+
+impl StatusIu {
+    /// The largest status IU this crate sends or receives.
+    pub const MAX_SIZE: usize = SenseIu::HEADER_SIZE + RequestSenseResponse::SIZE;
+
+    /// The tag the IU refers to.
+    pub fn tag(&self) -> u16 {
+        match self {
+            StatusIu::Sense(iu) => iu.tag,
+            StatusIu::Response(iu) => iu.tag,
+            StatusIu::ReadReady(iu) => iu.tag,
+            StatusIu::WriteReady(iu) => iu.tag,
+        }
+    }
+
+    /// Reads the next status IU from `channel`.
+    ///
+    /// Only as many bytes as the IU itself takes up are read, so IUs can be
+    /// read back to back from a byte stream.
+    pub fn read_from<C: CommunicationChannel>(channel: &mut C) -> Result<StatusIu, ScsiError> {
+        let mut buffer = [0; SenseIu::HEADER_SIZE + 252];
+        read_exact(channel, &mut buffer[..IU_HEADER_SIZE])?;
+        match buffer[0] {
+            SenseIu::IU_ID => {
+                read_exact(channel, &mut buffer[IU_HEADER_SIZE..SenseIu::HEADER_SIZE])?;
+                let sense_length = usize::from(BE::read_u16(&buffer[14..]));
+                let end = SenseIu::HEADER_SIZE + sense_length;
+                if end > buffer.len() {
+                    return Err(ScsiError::from_cause(ErrorCause::BufferTooSmallError {
+                        expected: end,
+                        actual: buffer.len(),
+                    }));
+                }
+                read_exact(channel, &mut buffer[SenseIu::HEADER_SIZE..end])?;
+                Ok(StatusIu::Sense(SenseIu::pull_from_buffer(&buffer[..end])?))
+            }
+            ResponseIu::IU_ID => {
+                read_exact(channel, &mut buffer[IU_HEADER_SIZE..ResponseIu::SIZE])?;
+                Ok(StatusIu::Response(ResponseIu::pull_from_buffer(
+                    &buffer[..ResponseIu::SIZE],
+                )?))
+            }
+            ReadReadyIu::IU_ID => Ok(StatusIu::ReadReady(ReadReadyIu::pull_from_buffer(
+                &buffer[..ReadReadyIu::SIZE],
+            )?)),
+            WriteReadyIu::IU_ID => Ok(StatusIu::WriteReady(WriteReadyIu::pull_from_buffer(
+                &buffer[..WriteReadyIu::SIZE],
+            )?)),
+            _ => Err(ScsiError::from_cause(ErrorCause::ParseError)),
+        }
+    }
+}
+
+impl BufferPushable for StatusIu {
+    fn push_to_buffer<B: AsMut<[u8]>>(&self, buffer: B) -> Result<usize, ScsiError> {
+        match self {
+            StatusIu::Sense(iu) => iu.push_to_buffer(buffer),
+            StatusIu::Response(iu) => iu.push_to_buffer(buffer),
+            StatusIu::ReadReady(iu) => iu.push_to_buffer(buffer),
+            StatusIu::WriteReady(iu) => iu.push_to_buffer(buffer),
+        }
+    }
+}
+
+/// Any of the information units the host sends over the command pipe.
+#[derive(Clone, Copy, Eq, PartialEq, Debug)]
+pub enum HostIu {
+    /// A new command.
+    Command(CommandIu),
+
+    /// A task management request.
+    TaskManagement(TaskManagementIu),
+}
+
+impl HostIu {
+    /// The largest IU the host sends.
+    pub const MAX_SIZE: usize = CommandIu::SIZE;
+
+    /// The tag of the IU.
+    pub fn tag(&self) -> u16 {
+        match self {
+            HostIu::Command(iu) => iu.tag,
+            HostIu::TaskManagement(iu) => iu.tag,
+        }
+    }
+
+    /// Reads the next IU from `channel`.
+    ///
+    /// Only as many bytes as the IU itself takes up are read, so IUs can be
+    /// read back to back from a byte stream.
+    pub fn read_from<C: CommunicationChannel>(channel: &mut C) -> Result<HostIu, ScsiError> {
+        let mut buffer = [0; HostIu::MAX_SIZE];
+        read_exact(channel, &mut buffer[..IU_HEADER_SIZE])?;
+        match buffer[0] {
+            CommandIu::IU_ID => {
+                read_exact(channel, &mut buffer[IU_HEADER_SIZE..CommandIu::SIZE])?;
+                Ok(HostIu::Command(CommandIu::pull_from_buffer(&buffer[..])?))
+            }
+            TaskManagementIu::IU_ID => {
+                read_exact(channel, &mut buffer[IU_HEADER_SIZE..TaskManagementIu::SIZE])?;
+                Ok(HostIu::TaskManagement(TaskManagementIu::pull_from_buffer(
+                    &buffer[..TaskManagementIu::SIZE],
+                )?))
+            }
+            _ => Err(ScsiError::from_cause(ErrorCause::ParseError)),
+        }
+    }
+}
+
+impl BufferPushable for HostIu {
+    fn push_to_buffer<B: AsMut<[u8]>>(&self, buffer: B) -> Result<usize, ScsiError> {
+        match self {
+            HostIu::Command(iu) => iu.push_to_buffer(buffer),
+            HostIu::TaskManagement(iu) => iu.push_to_buffer(buffer),
+        }
+    }
+}
+
+/// Reads from `channel` until `buffer` is full.
+pub(crate) fn read_exact<C: CommunicationChannel>(
+    channel: &mut C,
+    buffer: &mut [u8],
+) -> Result<(), ScsiError> {
+    let mut read = 0;
+    while read < buffer.len() {
+        let cur = channel.in_transfer(&mut buffer[read..])?;
+        if cur == 0 {
+            return Err(ScsiError::from_cause(ErrorCause::UsbTransferError {
+                direction: UsbTransferDirection::In,
+            }));
+        }
+        read += cur;
+    }
+    Ok(())
+}
+
+/// Writes all of `buffer` to `channel`.
+pub(crate) fn write_all<C: CommunicationChannel>(
+    channel: &mut C,
+    buffer: &[u8],
+) -> Result<(), ScsiError> {
+    let mut written = 0;
+    while written < buffer.len() {
+        let cur = channel.out_transfer(&buffer[written..])?;
+        if cur == 0 {
+            return Err(ScsiError::from_cause(ErrorCause::UsbTransferError {
+                direction: UsbTransferDirection::Out,
+            }));
+        }
+        written += cur;
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{
+        CommandIu, HostIu, ReadReadyIu, ResponseIu, SenseIu, StatusIu, TaskManagementIu,
+        WriteReadyIu,
+    };
+    use crate::scsi::commands::{Read10Command, RequestSenseResponse};
+    use crate::scsi::responder::tests::TestDualChannel;
+    use crate::{BufferPullable, BufferPushable, CommunicationChannel};
+
+    #[test]
+    pub fn test_commandiu() {
+        let expected: [u8; 32] = [
+            0x01, 0x00, 0x00, 0x07, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00,
+            0x00, 0x00, 0x28, 0x00, 0x00, 0x00, 0x00, 0x10, 0x00, 0x00, 0x02, 0x00, 0x00, 0x00,
+            0x00, 0x00, 0x00, 0x00,
+        ];
+        let mut buff = [0; 40];
+        let command = Read10Command::new(0x10 * 512, 2 * 512, 512).unwrap();
+        let iu = CommandIu::from_command(7, &command).unwrap();
+        assert_eq!(iu.cdb().len(), 10);
+        let pushed = iu.push_to_buffer(&mut buff[..]).unwrap();
+        assert_eq!(pushed, 32);
+        assert_eq!(&buff[..pushed], &expected);
+
+        let pulled = CommandIu::pull_from_buffer(&buff[..]).unwrap();
+        assert_eq!(pulled, iu);
+    }
+
+    #[test]
+    pub fn test_statusius() {
+        let mut device = TestDualChannel::default();
+        let mut host = device.reversed();
+        let sense = RequestSenseResponse::new(RequestSenseResponse::MEDIUM_ERROR, 0x11, 0x00);
+        let ius = [
+            StatusIu::ReadReady(ReadReadyIu { tag: 1 }),
+            StatusIu::WriteReady(WriteReadyIu { tag: 2 }),
+            StatusIu::Sense(SenseIu::good(1)),
+            StatusIu::Sense(SenseIu::check_condition(2, sense)),
+            StatusIu::Response(ResponseIu::new(
+                3,
+                ResponseIu::TASK_MANAGEMENT_FUNCTION_COMPLETE,
+            )),
+        ];
+        let mut buff = [0; StatusIu::MAX_SIZE];
+        for iu in ius.iter() {
+            let pushed = iu.push_to_buffer(&mut buff[..]).unwrap();
+            device.out_transfer(&buff[..pushed]).unwrap();
+        }
+        assert_eq!(device.send_buff.lock().unwrap().len(), 4 + 4 + 16 + 34 + 8);
+        for iu in ius.iter() {
+            assert_eq!(StatusIu::read_from(&mut host).unwrap(), *iu);
+        }
+    }
+
+    #[test]
+    pub fn test_hostius() {
+        let mut host = TestDualChannel::default();
+        let mut device = host.reversed();
+        let command = Read10Command::new(0, 512, 512).unwrap();
+        let ius = [
+            HostIu::Command(CommandIu::from_command(1, &command).unwrap()),
+            HostIu::TaskManagement(TaskManagementIu::new(2, TaskManagementIu::ABORT_TASK, 1)),
+        ];
+        let mut buff = [0; HostIu::MAX_SIZE];
+        for iu in ius.iter() {
+            let pushed = iu.push_to_buffer(&mut buff[..]).unwrap();
+            host.out_transfer(&buff[..pushed]).unwrap();
+        }
+        for iu in ius.iter() {
+            assert_eq!(HostIu::read_from(&mut device).unwrap(), *iu);
+        }
+    }
+}
@@ -0,0 +1,16 @@
+//! Support for the USB Attached SCSI (UAS) transport.
+//!
+//! UAS replaces the Bulk-Only transport's single command/data/status exchange
+//! with tagged information units spread over four pipes, which lets a host
+//! queue several commands with the device at once. The same command structs
+//! used with `ScsiBlockDevice` can be sent through a `UasHost`, and any
+//! `ScsiResponder` can be served over UAS with a `UasTarget`.
+
+mod iu;
+pub use self::iu::*;
+
+mod host;
+pub use self::host::*;
+
+mod target;
+pub use self::target::*;
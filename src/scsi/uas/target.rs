@@ -0,0 +1,583 @@
+use crate::error::ScsiError;
+use crate::scsi::commands::{Direction, ScsiStatus};
+use crate::scsi::responder::{
+    command_status, dispatch_command, fill_from_channel, reject_command, DataPhase, ScsiCommand,
+};
+use crate::scsi::uas::iu::write_all;
+use crate::scsi::uas::{
+    CommandIu, HostIu, ReadReadyIu, ResponseIu, SenseIu, StatusIu, TaskManagementIu, WriteReadyIu,
+    MAX_QUEUE_DEPTH,
+};
+use crate::scsi::ScsiResponder;
+use crate::traits::{BufferPushable, CommunicationChannel};
+
+/// A command that has been received from the host but has not been run yet.
+#[derive(Clone, Copy, Eq, PartialEq, Debug)]
+struct Queued {
+    iu: CommandIu,
+    sequence: u32,
+}
+
+/// The device side of a USB Attached SCSI session, which runs the commands
+/// it receives against a `ScsiResponder`.
+///
+/// Commands are read from the command pipe into a queue of up to
+/// `MAX_QUEUE_DEPTH` tagged commands, so that task management requests
+/// arriving in the meantime can abort them before they run. Each command's
+/// data phase is announced with a Read or Write Ready IU, and its outcome is
+/// reported with a `SenseIu` in place of the Bulk-Only `CommandStatusWrapper`.
+/// When a hook returns a failed CSW, the sense data for the `SenseIu` is
+/// collected by calling the responder's `sense_data` hook, and hooks that
+/// fail with an error standing for a status of its own, such as a
+/// `ReservationConflictError`, are reported with that status.
+pub struct UasTarget<CmdPipe, StatusPipe, InPipe, OutPipe>
+where
+    CmdPipe: CommunicationChannel,
+    StatusPipe: CommunicationChannel,
+    InPipe: CommunicationChannel,
+    OutPipe: CommunicationChannel,
+{
+    /// The pipe the host sends commands and task management requests over.
+    pub command_pipe: CmdPipe,
+
+    /// The pipe status IUs are sent to the host over.
+    pub status_pipe: StatusPipe,
+
+    /// The pipe data is sent to the host over.
+    pub data_in_pipe: InPipe,
+
+    /// The pipe the host sends data over.
+    pub data_out_pipe: OutPipe,
+
+    queue: [Option<Queued>; MAX_QUEUE_DEPTH],
+    next_sequence: u32,
+    block_size: Option<u32>,
+}
+
+impl<CmdPipe, StatusPipe, InPipe, OutPipe> UasTarget<CmdPipe, StatusPipe, InPipe, OutPipe>
+where
+    CmdPipe: CommunicationChannel,
+    StatusPipe: CommunicationChannel,
+    InPipe: CommunicationChannel,
+    OutPipe: CommunicationChannel,
+{
+    /// Starts a new session over the given pipes.
+    pub fn new(
+        command_pipe: CmdPipe,
+        status_pipe: StatusPipe,
+        data_in_pipe: InPipe,
+        data_out_pipe: OutPipe,
+    ) -> Self {
+        UasTarget {
+            command_pipe,
+            status_pipe,
+            data_in_pipe,
+            data_out_pipe,
+            queue: [None; MAX_QUEUE_DEPTH],
+            next_sequence: 0,
+            block_size: None,
+        }
+    }
+
+    /// The number of commands waiting to be run.
+    pub fn queued(&self) -> usize {
+        self.queue.iter().filter(|slot| slot.is_some()).count()
+    }
+
+    /// Receives a single IU from the host and then runs the next queued
+    /// command, if any.
+    pub fn process_iu<R: ScsiResponder>(&mut self, responder: &mut R) -> Result<(), ScsiError> {
+        self.receive_iu()?;
+        self.run_next(responder)?;
+        Ok(())
+    }
+
+    /// Receives a single IU from the host.
+    ///
+    /// Commands are queued until `run_next` is called, while task management
+    /// requests are answered immediately. Commands that cannot be queued are
+    /// rejected with a `ResponseIu`, or with a `TASK SET FULL` status if the
+    /// queue is full.
+    pub fn receive_iu(&mut self) -> Result<(), ScsiError> {
+        match HostIu::read_from(&mut self.command_pipe)? {
+            HostIu::Command(iu) => self.enqueue(iu),
+            HostIu::TaskManagement(iu) => self.task_management(&iu),
+        }
+    }
+
+    /// Runs the next queued command against `responder`, returning whether
+    /// there was a command to run.
+    ///
+    /// Commands are run in the order they were received, except that
+    /// head-of-queue commands go first.
+    ///
+    /// # Errors
+    /// Errors returned by the responder's hooks, other than an
+    /// `UnsupportedOperationError`, are passed through without sending a
+    /// status to the host, just like `ScsiResponder::process_command`.
+    pub fn run_next<R: ScsiResponder>(&mut self, responder: &mut R) -> Result<bool, ScsiError> {
+        let slot = self
+            .queue
+            .iter()
+            .enumerate()
+            .filter_map(|(idx, slot)| slot.map(|queued| (idx, queued)))
+            .min_by_key(|(_, queued)| {
+                let head_of_queue = queued.iu.task_attribute == CommandIu::TASK_HEAD_OF_QUEUE;
+                (!head_of_queue, queued.sequence)
+            });
+        let (slot, queued) = match slot {
+            Some(slot) => slot,
+            None => return Ok(false),
+        };
+        self.queue[slot] = None;
+        let iu = queued.iu;
+
+        let block_size = match self.block_size {
+            Some(block_size) => block_size,
+            None => {
+                let block_size = responder.memory_buffer().as_ref().len() as u32;
+                self.block_size = Some(block_size);
+                block_size
+            }
+        };
+        let command = match ScsiCommand::from_cdb(iu.cdb(), block_size) {
+            Ok(command) => command,
+            Err(err) => {
+                let sense = reject_command(responder, &err);
+                self.send_status(StatusIu::Sense(SenseIu::check_condition(iu.tag, sense)))?;
+                return Ok(true);
+            }
+        };
+        let wrapper = command.wrapper();
+        let expected = match wrapper.direction {
+            Direction::NONE => 0,
+            _ => wrapper.data_transfer_length as usize,
+        };
+        let mut data = UasDataPhase {
+            status_pipe: &mut self.status_pipe,
+            data_in_pipe: &mut self.data_in_pipe,
+            data_out_pipe: &mut self.data_out_pipe,
+            tag: iu.tag,
+            announced: false,
+        };
+        let result = dispatch_command(responder, command, expected, &mut data);
+        let (status, sense, _) = command_status(responder, result)?;
+        let status = SenseIu {
+            tag: iu.tag,
+            status,
+            sense,
+            ..Default::default()
+        };
+        self.send_status(StatusIu::Sense(status))?;
+        Ok(true)
+    }
+
+    fn enqueue(&mut self, iu: CommandIu) -> Result<(), ScsiError> {
+        if iu.lun != 0 {
+            let response = ResponseIu::new(iu.tag, ResponseIu::INCORRECT_LOGICAL_UNIT_NUMBER);
+            return self.send_status(StatusIu::Response(response));
+        }
+        if self.find(iu.tag).is_some() {
+            let response = ResponseIu::new(iu.tag, ResponseIu::OVERLAPPED_TAG_ATTEMPTED);
+            return self.send_status(StatusIu::Response(response));
+        }
+        let slot = match self.queue.iter().position(|slot| slot.is_none()) {
+            Some(slot) => slot,
+            None => {
+                let status = SenseIu {
+                    tag: iu.tag,
+                    status: ScsiStatus::TaskSetFull,
+                    ..Default::default()
+                };
+                return self.send_status(StatusIu::Sense(status));
+            }
+        };
+        self.queue[slot] = Some(Queued {
+            iu,
+            sequence: self.next_sequence,
+        });
+        self.next_sequence = self.next_sequence.wrapping_add(1);
+        Ok(())
+    }
+
+    fn task_management(&mut self, iu: &TaskManagementIu) -> Result<(), ScsiError> {
+        let response_code = if iu.lun != 0 {
+            ResponseIu::INCORRECT_LOGICAL_UNIT_NUMBER
+        } else {
+            match iu.function {
+                TaskManagementIu::ABORT_TASK => {
+                    if let Some(slot) = self.find(iu.task_tag) {
+                        self.queue[slot] = None;
+                    }
+                    ResponseIu::TASK_MANAGEMENT_FUNCTION_COMPLETE
+                }
+                TaskManagementIu::ABORT_TASK_SET
+                | TaskManagementIu::CLEAR_TASK_SET
+                | TaskManagementIu::LOGICAL_UNIT_RESET
+                | TaskManagementIu::I_T_NEXUS_RESET => {
+                    self.queue = [None; MAX_QUEUE_DEPTH];
+                    ResponseIu::TASK_MANAGEMENT_FUNCTION_COMPLETE
+                }
+                TaskManagementIu::QUERY_TASK => match self.find(iu.task_tag) {
+                    Some(_) => ResponseIu::TASK_MANAGEMENT_FUNCTION_SUCCEEDED,
+                    None => ResponseIu::TASK_MANAGEMENT_FUNCTION_COMPLETE,
+                },
+                _ => ResponseIu::TASK_MANAGEMENT_FUNCTION_NOT_SUPPORTED,
+            }
+        };
+        self.send_status(StatusIu::Response(ResponseIu::new(iu.tag, response_code)))
+    }
+
+    fn find(&self, tag: u16) -> Option<usize> {
+        self.queue
+            .iter()
+            .position(|slot| slot.is_some_and(|queued| queued.iu.tag == tag))
+    }
+
+    fn send_status(&mut self, iu: StatusIu) -> Result<(), ScsiError> {
+        send_status(&mut self.status_pipe, iu)
+    }
+}
+
+fn send_status<C: CommunicationChannel>(channel: &mut C, iu: StatusIu) -> Result<(), ScsiError> {
+    let mut buffer = [0; StatusIu::MAX_SIZE];
+    let pushed = iu.push_to_buffer(&mut buffer[..])?;
+    write_all(channel, &buffer[..pushed])
+}
+
+/// Moves a single command's data over the UAS data pipes, announcing the
+/// data phase on the status pipe before the first transfer.
+struct UasDataPhase<'a, S: 'a, I: 'a, O: 'a> {
+    status_pipe: &'a mut S,
+    data_in_pipe: &'a mut I,
+    data_out_pipe: &'a mut O,
+    tag: u16,
+    announced: bool,
+}
+
+impl<'a, S, I, O> DataPhase for UasDataPhase<'a, S, I, O>
+where
+    S: CommunicationChannel,
+    I: CommunicationChannel,
+    O: CommunicationChannel,
+{
+    fn send(&mut self, data: &[u8]) -> Result<usize, ScsiError> {
+        if !self.announced {
+            send_status(
+                self.status_pipe,
+                StatusIu::ReadReady(ReadReadyIu { tag: self.tag }),
+            )?;
+            self.announced = true;
+        }
+        write_all(self.data_in_pipe, data)?;
+        Ok(data.len())
+    }
+
+    fn receive(&mut self, buffer: &mut [u8]) -> Result<usize, ScsiError> {
+        if !self.announced {
+            send_status(
+                self.status_pipe,
+                StatusIu::WriteReady(WriteReadyIu { tag: self.tag }),
+            )?;
+            self.announced = true;
+        }
+        fill_from_channel(self.data_out_pipe, buffer)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::UasTarget;
+    use crate::error::ScsiError;
+    use crate::scsi::commands::{
+        BlockLimitsPage, Command, Read10Command, Read16Command, ReadCapacityCommand,
+        ReadCapacityResponse, RequestSenseResponse, ScsiStatus, TestUnitReady, VpdInquiryCommand,
+        Write10Command,
+    };
+    use crate::scsi::responder::tests::{MemoryResponder, TestDualChannel};
+    use crate::scsi::uas::{
+        CommandIu, HostIu, ReadReadyIu, ResponseIu, SenseIu, StatusIu, TaskManagementIu, UasData,
+        UasHost, UasRequest,
+    };
+    use crate::scsi::CdromResponder;
+    use crate::traits::{BufferPullable, BufferPushable, CommunicationChannel};
+    use std::collections::VecDeque;
+    use std::sync::{Arc, Condvar, Mutex};
+    use std::thread;
+    use std::time::Duration;
+    use std::vec::Vec;
+
+    /// A pipe shared between two threads, whose reads block until data
+    /// arrives or the pipe is closed.
+    #[derive(Clone, Default)]
+    struct BlockingPipe(Arc<(Mutex<PipeState>, Condvar)>);
+
+    #[derive(Default)]
+    struct PipeState {
+        data: VecDeque<u8>,
+        closed: bool,
+    }
+
+    impl BlockingPipe {
+        fn close(&self) {
+            let (ref lock, ref condvar) = *self.0;
+            lock.lock().unwrap().closed = true;
+            condvar.notify_all();
+        }
+    }
+
+    impl CommunicationChannel for BlockingPipe {
+        fn out_transfer<B: AsRef<[u8]>>(&mut self, bytes: B) -> Result<usize, ScsiError> {
+            let (ref lock, ref condvar) = *self.0;
+            lock.lock().unwrap().data.extend(bytes.as_ref());
+            condvar.notify_all();
+            Ok(bytes.as_ref().len())
+        }
+
+        fn in_transfer<B: AsMut<[u8]>>(&mut self, mut buffer: B) -> Result<usize, ScsiError> {
+            let (ref lock, ref condvar) = *self.0;
+            let (mut state, _) = condvar
+                .wait_timeout_while(lock.lock().unwrap(), Duration::from_secs(5), |state| {
+                    state.data.is_empty() && !state.closed
+                })
+                .unwrap();
+            let buffer = buffer.as_mut();
+            let len = buffer.len().min(state.data.len());
+            for (dst, src) in buffer.iter_mut().zip(state.data.drain(..len)) {
+                *dst = src;
+            }
+            Ok(len)
+        }
+    }
+
+    fn send_iu<I: BufferPushable>(channel: &mut TestDualChannel, iu: &I) {
+        let mut buffer = [0; CommandIu::SIZE];
+        let pushed = iu.push_to_buffer(&mut buffer[..]).unwrap();
+        channel.out_transfer(&buffer[..pushed]).unwrap();
+    }
+
+    #[test]
+    fn test_uas_target_session() {
+        let pipes: Vec<BlockingPipe> = (0..4).map(|_| BlockingPipe::default()).collect();
+        let mut target = UasTarget::new(
+            pipes[0].clone(),
+            pipes[1].clone(),
+            pipes[2].clone(),
+            pipes[3].clone(),
+        );
+        let device = thread::spawn(move || {
+            let mut responder = MemoryResponder::default();
+            while target.process_iu(&mut responder).is_ok() {}
+            target.queued()
+        });
+        let mut host = UasHost::new(
+            pipes[0].clone(),
+            pipes[1].clone(),
+            pipes[2].clone(),
+            pipes[3].clone(),
+        );
+
+        let data: Vec<u8> = (0..8 * 256).map(|idx| (idx % 253) as u8).collect();
+        let writes: Vec<Write10Command> = (0..4)
+            .map(|idx| Write10Command::new(idx * 2 * 256, 2 * 256, 256).unwrap())
+            .collect();
+        let mut requests: Vec<UasRequest> = writes
+            .iter()
+            .zip(data.chunks(2 * 256))
+            .map(|(command, chunk)| UasRequest::new(command, UasData::Out(chunk)).unwrap())
+            .collect();
+        let unit_ready = TestUnitReady::new();
+        requests.push(UasRequest::new(&unit_ready, UasData::None).unwrap());
+        host.execute(&mut requests).unwrap();
+        assert!(requests.iter().all(|request| request.succeeded()));
+        assert!(requests[..4]
+            .iter()
+            .all(|request| request.transferred == 2 * 256));
+
+        let mut readback = vec![0; 8 * 256];
+        let mut capacity = [0; 8];
+        let mut limits = [0; BlockLimitsPage::SIZE];
+        {
+            let reads: Vec<Read10Command> = (0..2)
+                .map(|idx| Read10Command::new(idx * 4 * 256, 4 * 256, 256).unwrap())
+                .collect();
+            let capacity_command = ReadCapacityCommand::new();
+            let limits_command =
+                VpdInquiryCommand::new(BlockLimitsPage::PAGE_CODE, BlockLimitsPage::SIZE as u8);
+            let mut requests: Vec<UasRequest> = reads
+                .iter()
+                .zip(readback.chunks_mut(4 * 256))
+                .map(|(command, chunk)| UasRequest::new(command, UasData::In(chunk)).unwrap())
+                .collect();
+            requests
+                .push(UasRequest::new(&capacity_command, UasData::In(&mut capacity[..])).unwrap());
+            requests.push(UasRequest::new(&limits_command, UasData::In(&mut limits[..])).unwrap());
+            host.execute(&mut requests).unwrap();
+            assert!(requests.iter().all(|request| request.succeeded()));
+        }
+        assert_eq!(readback, data);
+        let capacity = ReadCapacityResponse::pull_from_buffer(&capacity[..]).unwrap();
+        assert_eq!(capacity.logical_block_address, 1023);
+        assert_eq!(capacity.block_length, 256);
+        let limits = BlockLimitsPage::pull_from_buffer(&limits[..]).unwrap();
+        assert_eq!(limits.max_transfer_length, 16);
+
+        pipes[0].close();
+        assert_eq!(device.join().unwrap(), 0);
+    }
+
+    #[test]
+    fn test_uas_target_task_management() {
+        let mut host = [
+            TestDualChannel::default(),
+            TestDualChannel::default(),
+            TestDualChannel::default(),
+        ];
+        let mut target = UasTarget::new(
+            host[0].reversed(),
+            host[1].reversed(),
+            host[2].reversed(),
+            TestDualChannel::default(),
+        );
+        let mut responder = MemoryResponder::default();
+        let read = Read10Command::new(0, 256, 256).unwrap();
+        let mut iu = CommandIu::from_command(1, &read).unwrap();
+        send_iu(&mut host[0], &iu);
+        iu.tag = 2;
+        send_iu(&mut host[0], &iu);
+        send_iu(&mut host[0], &iu);
+        iu.tag = 3;
+        iu.lun = 1;
+        send_iu(&mut host[0], &iu);
+        send_iu(
+            &mut host[0],
+            &TaskManagementIu::new(4, TaskManagementIu::QUERY_TASK, 2),
+        );
+        send_iu(
+            &mut host[0],
+            &TaskManagementIu::new(5, TaskManagementIu::ABORT_TASK, 2),
+        );
+        send_iu(
+            &mut host[0],
+            &TaskManagementIu::new(6, TaskManagementIu::CLEAR_ACA, 0),
+        );
+        for _ in 0..7 {
+            target.receive_iu().unwrap();
+        }
+        assert_eq!(target.queued(), 1);
+        let expected = [
+            (2, ResponseIu::OVERLAPPED_TAG_ATTEMPTED),
+            (3, ResponseIu::INCORRECT_LOGICAL_UNIT_NUMBER),
+            (4, ResponseIu::TASK_MANAGEMENT_FUNCTION_SUCCEEDED),
+            (5, ResponseIu::TASK_MANAGEMENT_FUNCTION_COMPLETE),
+            (6, ResponseIu::TASK_MANAGEMENT_FUNCTION_NOT_SUPPORTED),
+        ];
+        for &(tag, response_code) in expected.iter() {
+            assert_eq!(
+                StatusIu::read_from(&mut host[1]).unwrap(),
+                StatusIu::Response(ResponseIu::new(tag, response_code))
+            );
+        }
+
+        assert!(target.run_next(&mut responder).unwrap());
+        assert!(!target.run_next(&mut responder).unwrap());
+        assert_eq!(
+            StatusIu::read_from(&mut host[1]).unwrap(),
+            StatusIu::ReadReady(ReadReadyIu { tag: 1 })
+        );
+        assert_eq!(host[2].recv_buff.lock().unwrap().len(), 256);
+        assert_eq!(
+            StatusIu::read_from(&mut host[1]).unwrap(),
+            StatusIu::Sense(SenseIu::good(1))
+        );
+
+        // A head-of-queue command jumps ahead, and a reset drops the rest.
+        let unit_ready = TestUnitReady::new();
+        let mut iu = CommandIu::from_command(7, &unit_ready).unwrap();
+        send_iu(&mut host[0], &iu);
+        iu.tag = 8;
+        iu.task_attribute = CommandIu::TASK_HEAD_OF_QUEUE;
+        send_iu(&mut host[0], &iu);
+        target.receive_iu().unwrap();
+        target.receive_iu().unwrap();
+        assert!(target.run_next(&mut responder).unwrap());
+        assert_eq!(
+            StatusIu::read_from(&mut host[1]).unwrap(),
+            StatusIu::Sense(SenseIu::good(8))
+        );
+        send_iu(
+            &mut host[0],
+            &TaskManagementIu::new(9, TaskManagementIu::LOGICAL_UNIT_RESET, 0),
+        );
+        target.receive_iu().unwrap();
+        assert_eq!(target.queued(), 0);
+        assert_eq!(
+            StatusIu::read_from(&mut host[1]).unwrap(),
+            StatusIu::Response(ResponseIu::new(
+                9,
+                ResponseIu::TASK_MANAGEMENT_FUNCTION_COMPLETE
+            ))
+        );
+    }
+
+    #[test]
+    fn test_uas_target_check_condition() {
+        let mut host = [TestDualChannel::default(), TestDualChannel::default()];
+        let mut target = UasTarget::new(
+            host[0].reversed(),
+            host[1].reversed(),
+            TestDualChannel::default(),
+            TestDualChannel::default(),
+        );
+        let mut responder = CdromResponder::<&[u8]>::new(None);
+
+        // With no disc inserted, the sense data comes from `sense_data`.
+        let iu = CommandIu::from_command(1, &TestUnitReady::new()).unwrap();
+        send_iu(&mut host[0], &iu);
+        target.process_iu(&mut responder).unwrap();
+        let sense = match StatusIu::read_from(&mut host[1]).unwrap() {
+            StatusIu::Sense(iu) => {
+                assert_eq!(iu.status, ScsiStatus::CheckCondition);
+                iu.sense.unwrap()
+            }
+            other => panic!("Unexpected IU {:?}", other),
+        };
+        assert_eq!(sense.sense_key, RequestSenseResponse::NOT_READY);
+        assert_eq!(sense.additional_sense_code, 0x3a);
+
+        // Unknown opcodes are rejected without reaching the responder.
+        let mut iu = HostIu::Command(CommandIu {
+            tag: 2,
+            cdb_length: 10,
+            ..Default::default()
+        });
+        if let HostIu::Command(ref mut command) = iu {
+            command.cdb[0] = 0xff;
+        }
+        send_iu(&mut host[0], &iu);
+        target.process_iu(&mut responder).unwrap();
+        let expected = RequestSenseResponse::new(RequestSenseResponse::ILLEGAL_REQUEST, 0x20, 0);
+        assert_eq!(
+            StatusIu::read_from(&mut host[1]).unwrap(),
+            StatusIu::Sense(SenseIu::check_condition(2, expected))
+        );
+
+        // So are reads longer than a data phase can describe.
+        let mut iu = HostIu::Command(CommandIu {
+            tag: 3,
+            cdb_length: 16,
+            ..Default::default()
+        });
+        if let HostIu::Command(ref mut command) = iu {
+            command.cdb[0] = Read16Command::opcode();
+            command.cdb[10..14].copy_from_slice(&[0xff; 4]);
+        }
+        send_iu(&mut host[0], &iu);
+        target.process_iu(&mut responder).unwrap();
+        let expected = RequestSenseResponse::new(RequestSenseResponse::ILLEGAL_REQUEST, 0x24, 0);
+        assert_eq!(
+            StatusIu::read_from(&mut host[1]).unwrap(),
+            StatusIu::Sense(SenseIu::check_condition(3, expected))
+        );
+    }
+}
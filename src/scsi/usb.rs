@@ -0,0 +1,760 @@
+use std::io;
+use std::time::Duration;
+use std::vec::Vec;
+
+use rusb::{
+    ConfigDescriptor, Device, DeviceHandle, Direction as UsbDirection, TransferType, UsbContext,
+};
+
+use crate::error::{ErrorCause, ScsiError, UsbTransferDirection};
+use crate::scsi::commands::{CommandBlockWrapper, CommandStatusWrapper, Direction};
+use crate::traits::{BufferPullable, CommunicationChannel};
+
+/// The interface class of USB Mass Storage devices.
+const MASS_STORAGE_CLASS: u8 = 0x08;
+
+/// The interface protocol of the Bulk-Only Transport.
+const BULK_ONLY_PROTOCOL: u8 = 0x50;
+
+/// Bulk-Only Mass Storage Reset, sent as a class request to the interface.
+const MASS_STORAGE_RESET: u8 = 0xff;
+
+/// Get Max LUN, sent as a class request to the interface.
+const GET_MAX_LUN: u8 = 0xfe;
+
+/// `bmRequestType` for class requests sent to an interface.
+const CLASS_INTERFACE_OUT: u8 = 0x21;
+const CLASS_INTERFACE_IN: u8 = 0xa1;
+
+/// The CSW status telling the host to run reset recovery.
+const PHASE_ERROR: u8 = 2;
+
+/// How long a single transfer may take, by default.
+const DEFAULT_TIMEOUT: Duration = Duration::from_secs(20);
+
+/// The subset of `rusb::DeviceHandle` that `RusbChannel` needs, so that the
+/// Bulk-Only logic can run against something other than real hardware.
+pub trait UsbHandle {
+    /// Reads from a bulk IN endpoint, returning the number of bytes read.
+    fn read_bulk(
+        &mut self,
+        endpoint: u8,
+        buffer: &mut [u8],
+        timeout: Duration,
+    ) -> rusb::Result<usize>;
+
+    /// Writes to a bulk OUT endpoint, returning the number of bytes written.
+    fn write_bulk(&mut self, endpoint: u8, buffer: &[u8], timeout: Duration)
+        -> rusb::Result<usize>;
+
+    /// Runs a control request with an IN data stage.
+    fn read_control(
+        &mut self,
+        request_type: u8,
+        request: u8,
+        value: u16,
+        index: u16,
+        buffer: &mut [u8],
+        timeout: Duration,
+    ) -> rusb::Result<usize>;
+
+    /// Runs a control request with an OUT or no data stage.
+    fn write_control(
+        &mut self,
+        request_type: u8,
+        request: u8,
+        value: u16,
+        index: u16,
+        buffer: &[u8],
+        timeout: Duration,
+    ) -> rusb::Result<usize>;
+
+    /// Clears a halt, or stall, on an endpoint.
+    fn clear_halt(&mut self, endpoint: u8) -> rusb::Result<()>;
+}
+
+impl<T: UsbContext> UsbHandle for DeviceHandle<T> {
+    fn read_bulk(
+        &mut self,
+        endpoint: u8,
+        buffer: &mut [u8],
+        timeout: Duration,
+    ) -> rusb::Result<usize> {
+        DeviceHandle::read_bulk(self, endpoint, buffer, timeout)
+    }
+
+    fn write_bulk(
+        &mut self,
+        endpoint: u8,
+        buffer: &[u8],
+        timeout: Duration,
+    ) -> rusb::Result<usize> {
+        DeviceHandle::write_bulk(self, endpoint, buffer, timeout)
+    }
+
+    fn read_control(
+        &mut self,
+        request_type: u8,
+        request: u8,
+        value: u16,
+        index: u16,
+        buffer: &mut [u8],
+        timeout: Duration,
+    ) -> rusb::Result<usize> {
+        DeviceHandle::read_control(self, request_type, request, value, index, buffer, timeout)
+    }
+
+    fn write_control(
+        &mut self,
+        request_type: u8,
+        request: u8,
+        value: u16,
+        index: u16,
+        buffer: &[u8],
+        timeout: Duration,
+    ) -> rusb::Result<usize> {
+        DeviceHandle::write_control(self, request_type, request, value, index, buffer, timeout)
+    }
+
+    fn clear_halt(&mut self, endpoint: u8) -> rusb::Result<()> {
+        DeviceHandle::clear_halt(self, endpoint)
+    }
+}
+
+/// A Bulk-Only Mass Storage interface and its pair of bulk endpoints.
+#[derive(Clone, Copy, Eq, PartialEq, Debug)]
+pub struct MassStorageInterface {
+    /// The interface number.
+    pub number: u8,
+
+    /// The alternate setting the endpoints belong to.
+    pub setting: u8,
+
+    /// The address of the bulk IN endpoint.
+    pub in_endpoint: u8,
+
+    /// The address of the bulk OUT endpoint.
+    pub out_endpoint: u8,
+}
+
+impl MassStorageInterface {
+    /// Looks for a Bulk-Only Mass Storage interface in a configuration.
+    pub fn from_config(config: &ConfigDescriptor) -> Option<MassStorageInterface> {
+        config
+            .interfaces()
+            .flat_map(|interface| interface.descriptors())
+            .filter(|descriptor| {
+                descriptor.class_code() == MASS_STORAGE_CLASS
+                    && descriptor.protocol_code() == BULK_ONLY_PROTOCOL
+            })
+            .find_map(|descriptor| {
+                let bulk_endpoint = |direction| {
+                    descriptor
+                        .endpoint_descriptors()
+                        .find(|endpoint| {
+                            endpoint.transfer_type() == TransferType::Bulk
+                                && endpoint.direction() == direction
+                        })
+                        .map(|endpoint| endpoint.address())
+                };
+                Some(MassStorageInterface {
+                    number: descriptor.interface_number(),
+                    setting: descriptor.setting_number(),
+                    in_endpoint: bulk_endpoint(UsbDirection::In)?,
+                    out_endpoint: bulk_endpoint(UsbDirection::Out)?,
+                })
+            })
+    }
+
+    /// Looks for a Bulk-Only Mass Storage interface in a device's active
+    /// configuration.
+    pub fn find<T: UsbContext>(
+        device: &Device<T>,
+    ) -> Result<Option<MassStorageInterface>, ScsiError> {
+        let config = device.active_config_descriptor().map_err(usb_error)?;
+        Ok(MassStorageInterface::from_config(&config))
+    }
+}
+
+/// Lists the devices on a context that have a Bulk-Only Mass Storage
+/// interface.
+pub fn find_mass_storage_devices<T: UsbContext>(context: &T) -> Result<Vec<Device<T>>, ScsiError> {
+    let mut found = Vec::new();
+    for device in context.devices().map_err(usb_error)?.iter() {
+        // Devices whose descriptors can't be read are just skipped.
+        if let Ok(Some(_)) = MassStorageInterface::find(&device) {
+            found.push(device);
+        }
+    }
+    Ok(found)
+}
+
+/// Where a `RusbChannel` is in the Bulk-Only command cycle.
+#[derive(Clone, Copy, Eq, PartialEq, Debug)]
+enum Phase {
+    Command,
+    DataIn { remaining: usize, ended: bool },
+    DataOut { remaining: usize, stalled: bool },
+    Status,
+}
+
+/// A `CommunicationChannel` talking to a USB Mass Storage device through
+/// libusb, for use with `ScsiBlockDevice`.
+///
+/// The channel follows the Bulk-Only command cycle as the CBWs, data and CSWs
+/// pass through it. A device that stalls its data phase has the halt cleared
+/// and the rest of the phase padded out, leaving the CSW's residue to report
+/// the shortfall, and a device that sends an invalid CSW is put through reset
+/// recovery.
+pub struct RusbChannel<H: UsbHandle> {
+    handle: H,
+    interface: MassStorageInterface,
+    timeout: Duration,
+    phase: Phase,
+    tag: u32,
+}
+
+impl<T: UsbContext> RusbChannel<DeviceHandle<T>> {
+    /// Opens a device and claims its Bulk-Only Mass Storage interface,
+    /// detaching any kernel driver bound to it where the platform allows.
+    ///
+    /// # Errors
+    /// Returns an `InvalidDeviceError` if the device has no such interface.
+    pub fn open(device: &Device<T>) -> Result<RusbChannel<DeviceHandle<T>>, ScsiError> {
+        let interface = MassStorageInterface::find(device)?
+            .ok_or_else(|| ScsiError::from_cause(ErrorCause::InvalidDeviceError))?;
+        let handle = device.open().map_err(usb_error)?;
+        // Not every platform supports detaching; claiming the interface fails
+        // below if a driver is still in the way.
+        let _ = handle.set_auto_detach_kernel_driver(true);
+        handle
+            .claim_interface(interface.number)
+            .map_err(usb_error)?;
+        if interface.setting != 0 {
+            handle
+                .set_alternate_setting(interface.number, interface.setting)
+                .map_err(usb_error)?;
+        }
+        Ok(RusbChannel::from_parts(handle, interface))
+    }
+}
+
+impl<H: UsbHandle> RusbChannel<H> {
+    /// Uses a handle whose interface has already been claimed.
+    pub fn from_parts(handle: H, interface: MassStorageInterface) -> RusbChannel<H> {
+        RusbChannel {
+            handle,
+            interface,
+            timeout: DEFAULT_TIMEOUT,
+            phase: Phase::Command,
+            tag: 0,
+        }
+    }
+
+    /// The interface the channel talks to.
+    pub fn interface(&self) -> MassStorageInterface {
+        self.interface
+    }
+
+    /// How long a single transfer may take.
+    pub fn timeout(&self) -> Duration {
+        self.timeout
+    }
+
+    /// Sets how long a single transfer may take. Defaults to 20 seconds.
+    pub fn set_timeout(&mut self, timeout: Duration) {
+        self.timeout = timeout;
+    }
+
+    /// Returns a reference to the device handle.
+    pub fn get_ref(&self) -> &H {
+        &self.handle
+    }
+
+    /// Closes the channel, returning the device handle.
+    pub fn into_inner(self) -> H {
+        self.handle
+    }
+
+    /// Sends a Bulk-Only Mass Storage Reset, which readies the device for the
+    /// next CBW. The bulk endpoints keep any halts they had.
+    pub fn reset(&mut self) -> Result<(), ScsiError> {
+        self.handle
+            .write_control(
+                CLASS_INTERFACE_OUT,
+                MASS_STORAGE_RESET,
+                0,
+                u16::from(self.interface.number),
+                &[],
+                self.timeout,
+            )
+            .map_err(usb_error)?;
+        self.phase = Phase::Command;
+        Ok(())
+    }
+
+    /// Asks for the highest logical unit number on the device. Devices with
+    /// a single LUN may stall the request, which is reported as 0.
+    pub fn get_max_lun(&mut self) -> Result<u8, ScsiError> {
+        let mut max_lun = [0];
+        match self.handle.read_control(
+            CLASS_INTERFACE_IN,
+            GET_MAX_LUN,
+            0,
+            u16::from(self.interface.number),
+            &mut max_lun,
+            self.timeout,
+        ) {
+            Ok(1) => Ok(max_lun[0]),
+            Ok(_) | Err(rusb::Error::Pipe) => Ok(0),
+            Err(err) => Err(usb_error(err)),
+        }
+    }
+
+    /// Clears a halt on one of the interface's endpoints.
+    pub fn clear_halt(&mut self, endpoint: u8) -> Result<(), ScsiError> {
+        self.handle.clear_halt(endpoint).map_err(usb_error)
+    }
+
+    /// Runs the reset recovery sequence: a Bulk-Only Mass Storage Reset
+    /// followed by clearing halts on both bulk endpoints.
+    pub fn reset_recovery(&mut self) -> Result<(), ScsiError> {
+        self.reset()?;
+        let (in_endpoint, out_endpoint) = (self.interface.in_endpoint, self.interface.out_endpoint);
+        self.clear_halt(in_endpoint)?;
+        self.clear_halt(out_endpoint)
+    }
+
+    /// Gives up on the current command after an unexpected error.
+    fn abort(&mut self, err: rusb::Error) -> ScsiError {
+        // The original error is the more useful one to report.
+        let _ = self.reset_recovery();
+        self.phase = Phase::Command;
+        usb_error(err)
+    }
+
+    fn write_all(&mut self, bytes: &[u8]) -> rusb::Result<()> {
+        let mut written = 0;
+        while written < bytes.len() {
+            let cur = self.handle.write_bulk(
+                self.interface.out_endpoint,
+                &bytes[written..],
+                self.timeout,
+            )?;
+            if cur == 0 {
+                return Err(rusb::Error::Io);
+            }
+            written += cur;
+        }
+        Ok(())
+    }
+
+    fn send_command(&mut self, bytes: &[u8]) -> Result<usize, ScsiError> {
+        if bytes.len() != 31 {
+            return Err(ScsiError::from_cause(ErrorCause::UsbTransferError {
+                direction: UsbTransferDirection::Out,
+            }));
+        }
+        let wrapper = CommandBlockWrapper::pull_from_buffer(bytes)?;
+        if let Err(err) = self.write_all(bytes) {
+            return Err(self.abort(err));
+        }
+        let remaining = wrapper.data_transfer_length as usize;
+        self.tag = wrapper.tag;
+        self.phase = if remaining == 0 {
+            Phase::Status
+        } else if wrapper.direction == Direction::IN {
+            Phase::DataIn {
+                remaining,
+                ended: false,
+            }
+        } else {
+            Phase::DataOut {
+                remaining,
+                stalled: false,
+            }
+        };
+        Ok(bytes.len())
+    }
+
+    fn receive_data(
+        &mut self,
+        buffer: &mut [u8],
+        remaining: usize,
+        mut ended: bool,
+    ) -> Result<usize, ScsiError> {
+        let length = buffer.len().min(remaining);
+        let buffer = &mut buffer[..length];
+        let mut read = 0;
+        if !ended {
+            match self
+                .handle
+                .read_bulk(self.interface.in_endpoint, buffer, self.timeout)
+            {
+                Ok(cur) => {
+                    // A short packet ends the data phase early.
+                    read = cur;
+                    ended = cur < length;
+                }
+                Err(rusb::Error::Pipe) => {
+                    let in_endpoint = self.interface.in_endpoint;
+                    self.clear_halt(in_endpoint)?;
+                    ended = true;
+                }
+                Err(err) => return Err(self.abort(err)),
+            }
+        }
+        if ended {
+            for byte in &mut buffer[read..] {
+                *byte = 0;
+            }
+            read = length;
+        }
+        let remaining = remaining - read;
+        self.phase = if remaining == 0 {
+            Phase::Status
+        } else {
+            Phase::DataIn { remaining, ended }
+        };
+        Ok(read)
+    }
+
+    fn send_data(
+        &mut self,
+        bytes: &[u8],
+        remaining: usize,
+        mut stalled: bool,
+    ) -> Result<usize, ScsiError> {
+        let length = bytes.len().min(remaining);
+        if !stalled {
+            match self.write_all(&bytes[..length]) {
+                Ok(()) => {}
+                Err(rusb::Error::Pipe) => {
+                    let out_endpoint = self.interface.out_endpoint;
+                    self.clear_halt(out_endpoint)?;
+                    stalled = true;
+                }
+                Err(err) => return Err(self.abort(err)),
+            }
+        }
+        let remaining = remaining - length;
+        self.phase = if remaining == 0 {
+            Phase::Status
+        } else {
+            Phase::DataOut { remaining, stalled }
+        };
+        Ok(length)
+    }
+
+    fn receive_status(&mut self, buffer: &mut [u8]) -> Result<usize, ScsiError> {
+        let in_endpoint = self.interface.in_endpoint;
+        let mut csw = [0; CommandStatusWrapper::SIZE as usize];
+        let mut result = self.handle.read_bulk(in_endpoint, &mut csw, self.timeout);
+        if let Err(rusb::Error::Pipe) = result {
+            // A stall here may be left over from the data phase, so the CSW
+            // is worth asking for a second time.
+            self.clear_halt(in_endpoint)?;
+            result = self.handle.read_bulk(in_endpoint, &mut csw, self.timeout);
+        }
+        let read = match result {
+            Ok(read) => read,
+            Err(err) => return Err(self.abort(err)),
+        };
+        self.phase = Phase::Command;
+        let valid = read == csw.len()
+            && CommandStatusWrapper::pull_from_buffer(&csw[..])
+                .is_ok_and(|status| status.tag == self.tag && status.status <= PHASE_ERROR);
+        if !valid {
+            self.reset_recovery()?;
+            return Err(ScsiError::from_cause(ErrorCause::UsbTransferError {
+                direction: UsbTransferDirection::In,
+            }));
+        }
+        if csw[12] == PHASE_ERROR {
+            self.reset_recovery()?;
+        }
+        let length = buffer.len().min(read);
+        buffer[..length].copy_from_slice(&csw[..length]);
+        Ok(length)
+    }
+}
+
+impl<H: UsbHandle> CommunicationChannel for RusbChannel<H> {
+    fn out_transfer<B: AsRef<[u8]>>(&mut self, bytes: B) -> Result<usize, ScsiError> {
+        match self.phase {
+            Phase::DataOut { remaining, stalled } => {
+                self.send_data(bytes.as_ref(), remaining, stalled)
+            }
+            _ => self.send_command(bytes.as_ref()),
+        }
+    }
+
+    fn in_transfer<B: AsMut<[u8]>>(&mut self, mut buffer: B) -> Result<usize, ScsiError> {
+        match self.phase {
+            Phase::DataIn { remaining, ended } => {
+                self.receive_data(buffer.as_mut(), remaining, ended)
+            }
+            Phase::Status => self.receive_status(buffer.as_mut()),
+            Phase::Command | Phase::DataOut { .. } => {
+                Err(ScsiError::from_cause(ErrorCause::UsbTransferError {
+                    direction: UsbTransferDirection::In,
+                }))
+            }
+        }
+    }
+}
+
+fn usb_error(err: rusb::Error) -> ScsiError {
+    let kind = match err {
+        rusb::Error::NotSupported => {
+            return ScsiError::from_cause(ErrorCause::UnsupportedOperationError)
+        }
+        rusb::Error::Timeout => io::ErrorKind::TimedOut,
+        rusb::Error::Access => io::ErrorKind::PermissionDenied,
+        rusb::Error::NoDevice | rusb::Error::NotFound => io::ErrorKind::NotFound,
+        rusb::Error::InvalidParam => io::ErrorKind::InvalidInput,
+        rusb::Error::Interrupted => io::ErrorKind::Interrupted,
+        rusb::Error::NoMem => io::ErrorKind::OutOfMemory,
+        _ => io::ErrorKind::Other,
+    };
+    ScsiError::from_cause(ErrorCause::IoError { kind })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{MassStorageInterface, RusbChannel, UsbHandle};
+    use crate::error::ErrorCause;
+    use crate::scsi::commands::{
+        Command, CommandStatusWrapper, Read10Command, TestUnitReady, Write10Command,
+    };
+    use crate::scsi::responder::tests::{LoopbackChannel, MemoryResponder};
+    use crate::scsi::ScsiBlockDevice;
+    use crate::traits::{BufferPullable, BufferPushable, CommunicationChannel};
+    use std::collections::VecDeque;
+    use std::time::Duration;
+    use std::vec::Vec;
+
+    const INTERFACE: MassStorageInterface = MassStorageInterface {
+        number: 0,
+        setting: 0,
+        in_endpoint: 0x81,
+        out_endpoint: 0x02,
+    };
+
+    /// Stands in for a device handle, either backed by a `MemoryResponder` or
+    /// replaying scripted replies.
+    #[derive(Default)]
+    struct MockHandle {
+        device: Option<LoopbackChannel<MemoryResponder>>,
+        reads: VecDeque<rusb::Result<Vec<u8>>>,
+        writes: Vec<Vec<u8>>,
+        stall_write: Option<usize>,
+        max_lun: Option<u8>,
+        requests: Vec<u8>,
+        halts: Vec<u8>,
+    }
+
+    impl UsbHandle for MockHandle {
+        fn read_bulk(
+            &mut self,
+            endpoint: u8,
+            buffer: &mut [u8],
+            _: Duration,
+        ) -> rusb::Result<usize> {
+            assert_eq!(endpoint, INTERFACE.in_endpoint);
+            if let Some(device) = self.device.as_mut() {
+                return device.in_transfer(buffer).map_err(|_| rusb::Error::Io);
+            }
+            let data = self.reads.pop_front().unwrap()?;
+            buffer[..data.len()].copy_from_slice(&data);
+            Ok(data.len())
+        }
+
+        fn write_bulk(&mut self, endpoint: u8, buffer: &[u8], _: Duration) -> rusb::Result<usize> {
+            assert_eq!(endpoint, INTERFACE.out_endpoint);
+            if self.stall_write == Some(self.writes.len()) {
+                self.stall_write = None;
+                return Err(rusb::Error::Pipe);
+            }
+            self.writes.push(buffer.to_vec());
+            match self.device.as_mut() {
+                Some(device) => device.out_transfer(buffer).map_err(|_| rusb::Error::Io),
+                None => Ok(buffer.len()),
+            }
+        }
+
+        fn read_control(
+            &mut self,
+            request_type: u8,
+            request: u8,
+            _: u16,
+            index: u16,
+            buffer: &mut [u8],
+            _: Duration,
+        ) -> rusb::Result<usize> {
+            assert_eq!((request_type, index), (0xa1, 0));
+            self.requests.push(request);
+            buffer[0] = self.max_lun.ok_or(rusb::Error::Pipe)?;
+            Ok(1)
+        }
+
+        fn write_control(
+            &mut self,
+            request_type: u8,
+            request: u8,
+            _: u16,
+            index: u16,
+            buffer: &[u8],
+            _: Duration,
+        ) -> rusb::Result<usize> {
+            assert_eq!((request_type, index), (0x21, 0));
+            self.requests.push(request);
+            Ok(buffer.len())
+        }
+
+        fn clear_halt(&mut self, endpoint: u8) -> rusb::Result<()> {
+            self.halts.push(endpoint);
+            Ok(())
+        }
+    }
+
+    fn csw(tag: u32, data_residue: u32) -> Vec<u8> {
+        let mut buffer = vec![0; CommandStatusWrapper::SIZE as usize];
+        CommandStatusWrapper {
+            tag,
+            data_residue,
+            status: CommandStatusWrapper::COMMAND_PASSED,
+        }
+        .push_to_buffer(&mut buffer[..])
+        .unwrap();
+        buffer
+    }
+
+    #[test]
+    fn test_rusb_block_device() {
+        let handle = MockHandle {
+            device: Some(LoopbackChannel::new(MemoryResponder::default())),
+            ..MockHandle::default()
+        };
+        let mut channel = RusbChannel::from_parts(handle, INTERFACE);
+        assert_eq!(channel.get_max_lun().unwrap(), 0);
+
+        let mut scratch = [0; 512];
+        let mut device = ScsiBlockDevice::new(channel, &mut scratch[..]).unwrap();
+        let written: Vec<u8> = (0..256 * 4).map(|idx| (idx % 251) as u8).collect();
+        device.write_blocks(8, 4, &written).unwrap();
+        let mut read = vec![0; written.len()];
+        device.read_blocks(8, 4, &mut read).unwrap();
+        assert_eq!(read, written);
+
+        channel = device.comm_channel;
+        let mut handle = channel.into_inner();
+        assert!(handle.halts.is_empty());
+        assert_eq!(handle.requests, [0xfe]);
+        handle.max_lun = Some(3);
+        let mut channel = RusbChannel::from_parts(handle, INTERFACE);
+        assert_eq!(channel.get_max_lun().unwrap(), 3);
+    }
+
+    #[test]
+    fn test_rusb_stall_recovery() {
+        let mut channel = RusbChannel::from_parts(MockHandle::default(), INTERFACE);
+        let mut command = [0; 31];
+
+        // A short read and a stalled data phase are padded out, and a stalled
+        // CSW is read again after clearing the halt.
+        let read = Read10Command::new(0, 512, 256).unwrap();
+        read.push_to_buffer(&mut command[..]).unwrap();
+        let tag = read.wrapper().tag;
+        channel.handle.reads.extend(vec![
+            Ok(vec![0xaa; 200]),
+            Err(rusb::Error::Pipe),
+            Ok(csw(tag, 312)),
+        ]);
+        channel.out_transfer(&command[..]).unwrap();
+        let mut data = [0xff; 512];
+        assert_eq!(channel.in_transfer(&mut data[..256]).unwrap(), 256);
+        assert_eq!(channel.in_transfer(&mut data[256..]).unwrap(), 256);
+        assert_eq!(&data[..200], &[0xaa; 200][..]);
+        assert!(data[200..].iter().all(|&byte| byte == 0));
+        let mut status = [0; 13];
+        assert_eq!(channel.in_transfer(&mut status[..]).unwrap(), 13);
+        let csw_read = CommandStatusWrapper::pull_from_buffer(&status[..]).unwrap();
+        assert_eq!(csw_read.data_residue, 312);
+        assert_eq!(channel.handle.halts, [0x81]);
+
+        channel
+            .handle
+            .reads
+            .extend(vec![Err(rusb::Error::Pipe), Ok(csw(tag, 512))]);
+        channel.out_transfer(&command[..]).unwrap();
+        assert_eq!(channel.in_transfer(&mut data[..]).unwrap(), 512);
+        channel.in_transfer(&mut status[..]).unwrap();
+        assert_eq!(channel.handle.halts, [0x81, 0x81]);
+
+        // A stalled write is cleared and the rest of its data dropped.
+        let write = Write10Command::new(0, 512, 256).unwrap();
+        write.push_to_buffer(&mut command[..]).unwrap();
+        let tag = write.wrapper().tag;
+        channel.handle.stall_write = Some(channel.handle.writes.len() + 1);
+        channel.handle.reads.push_back(Ok(csw(tag, 512)));
+        channel.out_transfer(&command[..]).unwrap();
+        assert_eq!(channel.out_transfer(&data[..256]).unwrap(), 256);
+        assert_eq!(channel.out_transfer(&data[256..]).unwrap(), 256);
+        channel.in_transfer(&mut status[..]).unwrap();
+        assert_eq!(channel.handle.halts, [0x81, 0x81, 0x02]);
+        assert_eq!(channel.handle.writes.last().unwrap().len(), 31);
+
+        // An invalid CSW triggers reset recovery.
+        let unit_ready = TestUnitReady::new();
+        unit_ready.push_to_buffer(&mut command[..]).unwrap();
+        let tag = unit_ready.wrapper().tag;
+        channel.handle.reads.push_back(Ok(csw(tag + 1, 0)));
+        channel.out_transfer(&command[..]).unwrap();
+        let err = channel.in_transfer(&mut status[..]).unwrap_err();
+        assert!(matches!(err.cause, ErrorCause::UsbTransferError { .. }));
+        assert_eq!(channel.handle.requests, [0xff]);
+        assert_eq!(channel.handle.halts[3..], [0x81, 0x02]);
+
+        // Data is only read once a command asks for it.
+        assert!(channel.in_transfer(&mut data[..]).is_err());
+    }
+
+    #[test]
+    fn test_rusb_disconnect() {
+        let mut channel = RusbChannel::from_parts(MockHandle::default(), INTERFACE);
+        let mut command = [0; 31];
+        let read = Read10Command::new(0, 512, 256).unwrap();
+        read.push_to_buffer(&mut command[..]).unwrap();
+        let tag = read.wrapper().tag;
+
+        // A device unplugged mid-transfer is reported as gone, and the
+        // channel waits for a new command rather than the rest of the data.
+        channel.handle.reads.push_back(Err(rusb::Error::NoDevice));
+        channel.out_transfer(&command[..]).unwrap();
+        let mut data = [0; 512];
+        let err = channel.in_transfer(&mut data[..]).unwrap_err();
+        assert_eq!(
+            err.cause,
+            ErrorCause::IoError {
+                kind: std::io::ErrorKind::NotFound
+            }
+        );
+        assert_eq!(channel.handle.requests, [0xff]);
+        assert!(channel.in_transfer(&mut data[..]).is_err());
+
+        // A truncated CSW is as bad as one with the wrong tag.
+        let mut truncated = csw(tag, 0);
+        truncated.truncate(12);
+        channel
+            .handle
+            .reads
+            .extend(vec![Ok(vec![0; 512]), Ok(truncated)]);
+        channel.out_transfer(&command[..]).unwrap();
+        assert_eq!(channel.in_transfer(&mut data[..]).unwrap(), 512);
+        let mut status = [0; 13];
+        let err = channel.in_transfer(&mut status[..]).unwrap_err();
+        assert!(matches!(err.cause, ErrorCause::UsbTransferError { .. }));
+        assert_eq!(channel.handle.requests, [0xff, 0xff]);
+    }
+}
@@ -0,0 +1,506 @@
+use usb_device::bus::{InterfaceNumber, UsbBus, UsbBusAllocator};
+use usb_device::class::{ControlIn, ControlOut, UsbClass};
+use usb_device::control::{Recipient, RequestType};
+use usb_device::descriptor::DescriptorWriter;
+use usb_device::endpoint::{EndpointAddress, EndpointIn, EndpointOut};
+use usb_device::UsbError;
+
+use crate::scsi::{BulkOnlyTransport, ScsiResponder};
+
+/// The interface class of USB Mass Storage devices.
+const MASS_STORAGE_CLASS: u8 = 0x08;
+
+/// The interface subclass of devices speaking the SCSI transparent command set.
+const SCSI_TRANSPARENT_SUBCLASS: u8 = 0x06;
+
+/// The interface protocol of the Bulk-Only Transport.
+const BULK_ONLY_PROTOCOL: u8 = 0x50;
+
+/// Bulk-Only Mass Storage Reset, sent as a class request to the interface.
+const MASS_STORAGE_RESET: u8 = 0xff;
+
+/// Get Max LUN, sent as a class request to the interface.
+const GET_MAX_LUN: u8 = 0xfe;
+
+/// The largest packet a bulk endpoint can carry, at high speed.
+const MAX_PACKET_SIZE: usize = 512;
+
+/// A `usb_device::class::UsbClass` presenting an `ScsiResponder` as a USB
+/// Mass Storage device using the Bulk-Only Transport.
+///
+/// The class allocates one interface with a bulk OUT and a bulk IN endpoint,
+/// answers the Bulk-Only Mass Storage Reset and Get Max LUN requests, and
+/// runs every command through a `BulkOnlyTransport` as its packets arrive, so
+/// the responder is only ever called from `UsbDevice::poll`. Both endpoints
+/// are stalled after an invalid CBW, until the host performs a Reset Recovery.
+///
+/// Only a single logical unit is exposed.
+pub struct UsbMassStorageClass<'a, B: UsbBus, R: ScsiResponder> {
+    interface: InterfaceNumber,
+    read_ep: EndpointOut<'a, B>,
+    write_ep: EndpointIn<'a, B>,
+    responder: R,
+    transport: BulkOnlyTransport<R>,
+    packet: [u8; MAX_PACKET_SIZE],
+    /// The length of the packet in `packet` still waiting to be written.
+    pending: Option<usize>,
+}
+
+impl<'a, B: UsbBus, R: ScsiResponder> UsbMassStorageClass<'a, B, R> {
+    /// Allocates the class's interface and endpoints from `alloc`, with the
+    /// given maximum packet size: 64 bytes for full speed devices and 512 for
+    /// high speed ones.
+    ///
+    /// # Panics
+    /// Panics if `max_packet_size` is larger than 512 bytes, or if the bus
+    /// cannot allocate the endpoints.
+    pub fn new(
+        alloc: &'a UsbBusAllocator<B>,
+        max_packet_size: u16,
+        mut responder: R,
+    ) -> UsbMassStorageClass<'a, B, R> {
+        assert!(usize::from(max_packet_size) <= MAX_PACKET_SIZE);
+        UsbMassStorageClass {
+            interface: alloc.interface(),
+            read_ep: alloc.bulk(max_packet_size),
+            write_ep: alloc.bulk(max_packet_size),
+            transport: BulkOnlyTransport::new(&mut responder, usize::from(max_packet_size)),
+            responder,
+            packet: [0; MAX_PACKET_SIZE],
+            pending: None,
+        }
+    }
+
+    /// Returns a reference to the responder.
+    pub fn get_ref(&self) -> &R {
+        &self.responder
+    }
+
+    /// Returns a mutable reference to the responder.
+    pub fn get_mut(&mut self) -> &mut R {
+        &mut self.responder
+    }
+
+    /// Writes as many packets to the bulk IN endpoint as it will take.
+    fn flush(&mut self) {
+        loop {
+            if let Some(length) = self.pending {
+                match self.write_ep.write(&self.packet[..length]) {
+                    Ok(_) => self.pending = None,
+                    Err(UsbError::WouldBlock) => return,
+                    Err(_) => {
+                        self.pending = None;
+                        self.stall();
+                        return;
+                    }
+                }
+            }
+            match self
+                .transport
+                .next_packet(&mut self.responder, &mut self.packet)
+            {
+                Ok(Some(length)) => self.pending = Some(length),
+                _ => return,
+            }
+        }
+    }
+
+    /// Stalls both bulk endpoints, so that the host runs a Reset Recovery.
+    fn stall(&self) {
+        self.read_ep.stall();
+        self.write_ep.stall();
+    }
+
+    /// Whether a class request is addressed to this class's interface.
+    fn is_ours(&self, request_type: RequestType, recipient: Recipient, index: u16) -> bool {
+        request_type == RequestType::Class
+            && recipient == Recipient::Interface
+            && index == u16::from(u8::from(self.interface))
+    }
+}
+
+impl<B: UsbBus, R: ScsiResponder> UsbClass<B> for UsbMassStorageClass<'_, B, R> {
+    fn get_configuration_descriptors(
+        &self,
+        writer: &mut DescriptorWriter,
+    ) -> usb_device::Result<()> {
+        writer.interface(
+            self.interface,
+            MASS_STORAGE_CLASS,
+            SCSI_TRANSPARENT_SUBCLASS,
+            BULK_ONLY_PROTOCOL,
+        )?;
+        writer.endpoint(&self.read_ep)?;
+        writer.endpoint(&self.write_ep)?;
+        Ok(())
+    }
+
+    fn reset(&mut self) {
+        self.transport.reset();
+        self.pending = None;
+    }
+
+    fn poll(&mut self) {
+        // The host clears endpoint halts on its own; keep them stalled until
+        // it gets around to the Bulk-Only reset as well.
+        if self.transport.is_stalled() {
+            self.stall();
+        }
+        self.flush();
+    }
+
+    fn control_out(&mut self, xfer: ControlOut<B>) {
+        let req = *xfer.request();
+        if !self.is_ours(req.request_type, req.recipient, req.index) {
+            return;
+        }
+        if req.request == MASS_STORAGE_RESET && req.value == 0 && req.length == 0 {
+            self.transport.reset();
+            self.pending = None;
+            xfer.accept().ok();
+        } else {
+            xfer.reject().ok();
+        }
+    }
+
+    fn control_in(&mut self, xfer: ControlIn<B>) {
+        let req = *xfer.request();
+        if !self.is_ours(req.request_type, req.recipient, req.index) {
+            return;
+        }
+        if req.request == GET_MAX_LUN && req.value == 0 && req.length == 1 {
+            xfer.accept_with(&[0]).ok();
+        } else {
+            xfer.reject().ok();
+        }
+    }
+
+    fn endpoint_out(&mut self, addr: EndpointAddress) {
+        if addr != self.read_ep.address() {
+            return;
+        }
+        let mut packet = [0; MAX_PACKET_SIZE];
+        let length = match self.read_ep.read(&mut packet) {
+            Ok(length) => length,
+            Err(_) => return,
+        };
+        if self
+            .transport
+            .receive_packet(&mut self.responder, &packet[..length])
+            .is_err()
+        {
+            self.stall();
+        }
+        self.flush();
+    }
+
+    fn endpoint_in_complete(&mut self, addr: EndpointAddress) {
+        if addr == self.write_ep.address() {
+            self.flush();
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::UsbMassStorageClass;
+    use crate::error::{ErrorCause, ScsiError, UsbTransferDirection};
+    use crate::scsi::commands::{Command, CommandStatusWrapper, Read10Command};
+    use crate::scsi::responder::tests::MemoryResponder;
+    use crate::scsi::ScsiBlockDevice;
+    use crate::traits::{BufferPullable, BufferPushable, CommunicationChannel};
+    use byteorder::{ByteOrder, LE};
+    use std::collections::VecDeque;
+    use std::sync::{Arc, Mutex};
+    use std::vec::Vec;
+    use usb_device::bus::{PollResult, UsbBus, UsbBusAllocator};
+    use usb_device::device::{UsbDevice, UsbDeviceBuilder, UsbVidPid};
+    use usb_device::endpoint::{EndpointAddress, EndpointType};
+    use usb_device::{UsbDirection, UsbError};
+
+    const OUT_EP: usize = 1;
+    const IN_EP: usize = 2;
+
+    /// The endpoint buffers of a `TestBus`, shared with the test acting as
+    /// the host.
+    #[derive(Default)]
+    struct BusState {
+        setup: Option<Vec<u8>>,
+        out: [VecDeque<Vec<u8>>; 3],
+        in_packets: [Option<Vec<u8>>; 3],
+        in_complete: u16,
+        stalled: u16,
+        next_index: usize,
+        bus_reset: bool,
+    }
+
+    impl BusState {
+        /// Takes the packet the device wrote to an IN endpoint, as the host
+        /// controller would.
+        fn take_in(&mut self, index: usize) -> Option<Vec<u8>> {
+            let packet = self.in_packets[index].take()?;
+            self.in_complete |= 1 << index;
+            Some(packet)
+        }
+
+        fn is_stalled(&self, index: usize) -> bool {
+            self.stalled & (1 << index) != 0
+        }
+    }
+
+    struct TestBus(Arc<Mutex<BusState>>);
+
+    impl UsbBus for TestBus {
+        fn alloc_ep(
+            &mut self,
+            ep_dir: UsbDirection,
+            ep_addr: Option<EndpointAddress>,
+            _ep_type: EndpointType,
+            _max_packet_size: u16,
+            _interval: u8,
+        ) -> usb_device::Result<EndpointAddress> {
+            if let Some(addr) = ep_addr {
+                return Ok(addr);
+            }
+            let mut state = self.0.lock().unwrap();
+            state.next_index += 1;
+            Ok(EndpointAddress::from_parts(state.next_index, ep_dir))
+        }
+        fn enable(&mut self) {}
+        fn reset(&self) {
+            let mut state = self.0.lock().unwrap();
+            state.out = Default::default();
+            state.in_packets = Default::default();
+            state.stalled = 0;
+        }
+        fn set_device_address(&self, _addr: u8) {}
+        fn write(&self, ep_addr: EndpointAddress, buf: &[u8]) -> usb_device::Result<usize> {
+            let mut state = self.0.lock().unwrap();
+            let slot = &mut state.in_packets[ep_addr.index()];
+            if slot.is_some() {
+                return Err(UsbError::WouldBlock);
+            }
+            *slot = Some(buf.to_vec());
+            Ok(buf.len())
+        }
+        fn read(&self, ep_addr: EndpointAddress, buf: &mut [u8]) -> usb_device::Result<usize> {
+            let mut state = self.0.lock().unwrap();
+            let packet = match ep_addr.index() {
+                0 => state.setup.take(),
+                index => state.out[index].pop_front(),
+            };
+            let packet = packet.ok_or(UsbError::WouldBlock)?;
+            buf[..packet.len()].copy_from_slice(&packet);
+            Ok(packet.len())
+        }
+        fn set_stalled(&self, ep_addr: EndpointAddress, stalled: bool) {
+            let mut state = self.0.lock().unwrap();
+            let bit = 1 << ep_addr.index();
+            if stalled {
+                state.stalled |= bit;
+            } else {
+                state.stalled &= !bit;
+            }
+        }
+        fn is_stalled(&self, ep_addr: EndpointAddress) -> bool {
+            self.0.lock().unwrap().is_stalled(ep_addr.index())
+        }
+        fn suspend(&self) {}
+        fn resume(&self) {}
+        fn poll(&self) -> PollResult {
+            let mut state = self.0.lock().unwrap();
+            if core::mem::take(&mut state.bus_reset) {
+                return PollResult::Reset;
+            }
+            let ep_setup = u16::from(state.setup.is_some());
+            let ep_out = (1..3)
+                .filter(|&index| !state.out[index].is_empty())
+                .fold(0, |bits, index| bits | 1 << index);
+            let ep_in_complete = core::mem::take(&mut state.in_complete);
+            if ep_setup | ep_out | ep_in_complete == 0 {
+                return PollResult::None;
+            }
+            PollResult::Data {
+                ep_out,
+                ep_in_complete,
+                ep_setup,
+            }
+        }
+    }
+
+    /// The host side of a `UsbMassStorageClass` on a `TestBus`.
+    struct TestHost<'a> {
+        state: Arc<Mutex<BusState>>,
+        device: UsbDevice<'a, TestBus>,
+        class: UsbMassStorageClass<'a, TestBus, MemoryResponder>,
+        max_packet_size: usize,
+    }
+
+    impl TestHost<'_> {
+        fn poll(&mut self) {
+            while self.device.poll(&mut [&mut self.class]) {}
+        }
+
+        /// Runs a control transfer, returning whatever the device wrote to
+        /// endpoint 0 in response.
+        fn control(&mut self, setup: [u8; 8]) -> Option<Vec<u8>> {
+            self.state.lock().unwrap().setup = Some(setup.to_vec());
+            self.poll();
+            self.state.lock().unwrap().take_in(0)
+        }
+
+        /// Sends CLEAR_FEATURE(ENDPOINT_HALT) to both bulk endpoints.
+        fn clear_halts(&mut self) {
+            self.control([0x02, 1, 0, 0, OUT_EP as u8, 0, 0, 0]);
+            self.control([0x02, 1, 0, 0, 0x80 | IN_EP as u8, 0, 0, 0]);
+        }
+    }
+
+    impl CommunicationChannel for TestHost<'_> {
+        fn out_transfer<B: AsRef<[u8]>>(&mut self, bytes: B) -> Result<usize, ScsiError> {
+            let bytes = bytes.as_ref();
+            for packet in bytes.chunks(self.max_packet_size) {
+                if self.state.lock().unwrap().is_stalled(OUT_EP) {
+                    return Err(ScsiError::from_cause(ErrorCause::UsbTransferError {
+                        direction: UsbTransferDirection::Out,
+                    }));
+                }
+                self.state.lock().unwrap().out[OUT_EP].push_back(packet.to_vec());
+                self.poll();
+            }
+            Ok(bytes.len())
+        }
+
+        fn in_transfer<B: AsMut<[u8]>>(&mut self, mut buffer: B) -> Result<usize, ScsiError> {
+            let buffer = buffer.as_mut();
+            let mut read = 0;
+            while read < buffer.len() {
+                self.poll();
+                let packet =
+                    self.state
+                        .lock()
+                        .unwrap()
+                        .take_in(IN_EP)
+                        .ok_or(ScsiError::from_cause(ErrorCause::UsbTransferError {
+                            direction: UsbTransferDirection::In,
+                        }))?;
+                buffer[read..read + packet.len()].copy_from_slice(&packet);
+                read += packet.len();
+                if packet.len() < self.max_packet_size {
+                    break;
+                }
+            }
+            Ok(read)
+        }
+    }
+
+    fn test_host(alloc: &UsbBusAllocator<TestBus>, state: Arc<Mutex<BusState>>) -> TestHost<'_> {
+        let class = UsbMassStorageClass::new(alloc, 64, MemoryResponder::default());
+        let device = UsbDeviceBuilder::new(alloc, UsbVidPid(0x1234, 0x5678))
+            .max_packet_size_0(64)
+            .unwrap()
+            .build();
+        TestHost {
+            state,
+            device,
+            class,
+            max_packet_size: 64,
+        }
+    }
+
+    #[test]
+    fn test_usb_mass_storage_class() {
+        let state = Arc::new(Mutex::new(BusState::default()));
+        let alloc = UsbBusAllocator::new(TestBus(state.clone()));
+        let mut host = test_host(&alloc, state.clone());
+
+        // GET_DESCRIPTOR(CONFIGURATION)
+        let config = host.control([0x80, 6, 0, 2, 0, 0, 64, 0]).unwrap();
+        assert_eq!(config.len(), 9 + 9 + 7 + 7);
+        assert_eq!(&config[9..18], &[9, 4, 0, 0, 2, 0x08, 0x06, 0x50, 0]);
+        assert_eq!(&config[18..25], &[7, 5, 0x01, 2, 64, 0, 0]);
+        assert_eq!(&config[25..32], &[7, 5, 0x82, 2, 64, 0, 0]);
+
+        // Get Max LUN
+        assert_eq!(host.control([0xa1, 0xfe, 0, 0, 0, 0, 1, 0]), Some(vec![0]));
+
+        let mut scratch = [0; 64];
+        let mut device = ScsiBlockDevice::new(host, &mut scratch[..]).unwrap();
+        let data: Vec<u8> = (0..20 * 256).map(|idx| (idx % 251) as u8).collect();
+        assert_eq!(device.write_blocks(3, 20, &data).unwrap(), data.len());
+        let mut readback = vec![0; data.len()];
+        assert_eq!(
+            device.read_blocks(3, 20, &mut readback).unwrap(),
+            data.len()
+        );
+        assert_eq!(readback, data);
+        let mut host = device.comm_channel;
+
+        // An invalid CBW stalls both endpoints until a Bulk-Only reset.
+        host.out_transfer([0; 31]).unwrap();
+        assert!(state.lock().unwrap().is_stalled(OUT_EP));
+        assert!(state.lock().unwrap().is_stalled(IN_EP));
+        host.clear_halts();
+        assert!(state.lock().unwrap().is_stalled(OUT_EP));
+        assert_eq!(host.control([0x21, 0xff, 0, 0, 0, 0, 0, 0]), Some(vec![]));
+        host.clear_halts();
+        assert!(!state.lock().unwrap().is_stalled(OUT_EP));
+        assert!(!state.lock().unwrap().is_stalled(IN_EP));
+
+        // Requests to other interfaces are left alone.
+        assert_eq!(host.control([0xa1, 0xfe, 0, 0, 1, 0, 1, 0]), None);
+
+        let mut scratch = [0; 64];
+        let mut device = ScsiBlockDevice::new(host, &mut scratch[..]).unwrap();
+        assert_eq!(device.read_blocks(3, 1, &mut readback[..256]).unwrap(), 256);
+        assert_eq!(&readback[..256], &data[..256]);
+    }
+
+    #[test]
+    fn test_usb_mass_storage_errors() {
+        let state = Arc::new(Mutex::new(BusState::default()));
+        let alloc = UsbBusAllocator::new(TestBus(state.clone()));
+        let mut host = test_host(&alloc, state.clone());
+
+        // A host that asks for more than the command returns gets a
+        // zero-length packet after the last full one, and the shortfall in
+        // the residue.
+        let mut command = [0; 31];
+        let read = Read10Command::new(0, 256, 256).unwrap();
+        read.push_to_buffer(&mut command[..]).unwrap();
+        LE::write_u32(&mut command[8..], 512);
+        host.out_transfer(command).unwrap();
+        let mut data = [0xff; 1024];
+        assert_eq!(host.in_transfer(&mut data[..512]).unwrap(), 256);
+        let mut status = [0; 13];
+        assert_eq!(host.in_transfer(&mut status[..]).unwrap(), 13);
+        let csw = CommandStatusWrapper::pull_from_buffer(&status[..]).unwrap();
+        assert_eq!(
+            (csw.tag, csw.data_residue, csw.status),
+            (read.wrapper().tag, 256, 0)
+        );
+
+        // A CBW split over two packets is not a CBW.
+        host.out_transfer(&command[..20]).unwrap();
+        assert!(host.out_transfer(&command[20..]).is_err());
+        assert!(state.lock().unwrap().is_stalled(IN_EP));
+        assert_eq!(host.control([0x21, 0xff, 0, 0, 0, 0, 0, 0]), Some(vec![]));
+        host.clear_halts();
+
+        // A host that goes away partway through a read leaves the rest of
+        // the data behind, and the device starts afresh after the bus reset.
+        Read10Command::new(0, 1024, 256)
+            .unwrap()
+            .push_to_buffer(&mut command[..])
+            .unwrap();
+        host.out_transfer(command).unwrap();
+        assert_eq!(host.in_transfer(&mut data[..64]).unwrap(), 64);
+        state.lock().unwrap().bus_reset = true;
+        host.poll();
+        assert!(host.in_transfer(&mut data[..]).is_err());
+
+        let mut scratch = [0; 64];
+        let mut device = ScsiBlockDevice::new(host, &mut scratch[..]).unwrap();
+        assert_eq!(device.read_blocks(0, 4, &mut data[..]).unwrap(), 1024);
+    }
+}
@@ -0,0 +1,165 @@
+use std::io::{self, Read, Write};
+use std::vec::Vec;
+
+use byteorder::{ByteOrder, BE};
+
+use crate::error::{ErrorCause, ScsiError};
+
+/// The protocol version sent in every operation header.
+pub(crate) const USBIP_VERSION: u16 = 0x0111;
+
+/// Operation codes exchanged before a device is imported.
+pub(crate) const OP_REQ_DEVLIST: u16 = 0x8005;
+pub(crate) const OP_REP_DEVLIST: u16 = 0x0005;
+pub(crate) const OP_REQ_IMPORT: u16 = 0x8003;
+pub(crate) const OP_REP_IMPORT: u16 = 0x0003;
+
+/// Commands exchanged once a device is imported.
+pub(crate) const CMD_SUBMIT: u32 = 1;
+pub(crate) const CMD_UNLINK: u32 = 2;
+pub(crate) const RET_SUBMIT: u32 = 3;
+pub(crate) const RET_UNLINK: u32 = 4;
+
+/// Transfer directions, as seen from the host.
+pub(crate) const DIR_OUT: u32 = 0;
+pub(crate) const DIR_IN: u32 = 1;
+
+/// The size of every URB header.
+pub(crate) const URB_HEADER_SIZE: usize = 48;
+
+/// The size of the device description sent in the device list and import
+/// replies.
+pub(crate) const DEVICE_INFO_SIZE: usize = 312;
+
+/// The largest transfer a client may submit.
+const MAX_TRANSFER_LENGTH: usize = 16 * 1024 * 1024;
+
+/// The header opening every operation request and reply.
+#[derive(Clone, Copy, Eq, PartialEq, Debug)]
+pub(crate) struct OpHeader {
+    pub(crate) code: u16,
+    pub(crate) status: u32,
+}
+
+impl OpHeader {
+    pub(crate) const SIZE: usize = 8;
+
+    pub(crate) fn read_from<R: Read>(reader: &mut R) -> Result<OpHeader, ScsiError> {
+        let mut buffer = [0; OpHeader::SIZE];
+        reader.read_exact(&mut buffer)?;
+        if BE::read_u16(&buffer) != USBIP_VERSION {
+            return Err(ScsiError::from_cause(ErrorCause::ParseError));
+        }
+        Ok(OpHeader {
+            code: BE::read_u16(&buffer[2..]),
+            status: BE::read_u32(&buffer[4..]),
+        })
+    }
+
+    pub(crate) fn to_bytes(self) -> [u8; OpHeader::SIZE] {
+        let mut buffer = [0; OpHeader::SIZE];
+        BE::write_u16(&mut buffer, USBIP_VERSION);
+        BE::write_u16(&mut buffer[2..], self.code);
+        BE::write_u32(&mut buffer[4..], self.status);
+        buffer
+    }
+}
+
+/// A `USBIP_CMD_SUBMIT` request, asking for a single transfer.
+#[derive(Clone, Eq, PartialEq, Debug)]
+pub(crate) struct Submit {
+    pub(crate) seqnum: u32,
+    pub(crate) direction: u32,
+    pub(crate) endpoint: u32,
+    pub(crate) length: usize,
+    pub(crate) setup: [u8; 8],
+    /// The data sent along with an OUT transfer.
+    pub(crate) data: Vec<u8>,
+}
+
+/// A request sent by the client once the device is imported.
+#[derive(Clone, Eq, PartialEq, Debug)]
+pub(crate) enum UrbRequest {
+    Submit(Submit),
+    Unlink { seqnum: u32, unlink_seqnum: u32 },
+}
+
+impl UrbRequest {
+    /// Reads the next request, returning `None` if the client closed the
+    /// connection in between requests.
+    pub(crate) fn read_from<R: Read>(reader: &mut R) -> Result<Option<UrbRequest>, ScsiError> {
+        let mut header = [0; URB_HEADER_SIZE];
+        let read = reader.read(&mut header)?;
+        if read == 0 {
+            return Ok(None);
+        }
+        reader.read_exact(&mut header[read..])?;
+        let seqnum = BE::read_u32(&header[4..]);
+        match BE::read_u32(&header) {
+            CMD_SUBMIT => {
+                let direction = BE::read_u32(&header[12..]);
+                let length = BE::read_u32(&header[24..]) as usize;
+                if length > MAX_TRANSFER_LENGTH {
+                    return Err(ScsiError::from_cause(ErrorCause::TransferTooLargeError {
+                        actual: length,
+                        max: MAX_TRANSFER_LENGTH,
+                    }));
+                }
+                let mut data = Vec::new();
+                if direction == DIR_OUT {
+                    data.resize(length, 0);
+                    reader.read_exact(&mut data)?;
+                }
+                let mut setup = [0; 8];
+                setup.copy_from_slice(&header[40..]);
+                Ok(Some(UrbRequest::Submit(Submit {
+                    seqnum,
+                    direction,
+                    endpoint: BE::read_u32(&header[16..]),
+                    length,
+                    setup,
+                    data,
+                })))
+            }
+            CMD_UNLINK => Ok(Some(UrbRequest::Unlink {
+                seqnum,
+                unlink_seqnum: BE::read_u32(&header[20..]),
+            })),
+            _ => Err(ScsiError::from_cause(ErrorCause::ParseError)),
+        }
+    }
+}
+
+/// Sends a `USBIP_RET_SUBMIT` reply, followed by `data` for IN transfers.
+pub(crate) fn write_ret_submit<W: Write>(
+    writer: &mut W,
+    seqnum: u32,
+    status: i32,
+    actual_length: usize,
+    data: &[u8],
+) -> io::Result<()> {
+    // The header and data go out in a single write, so that they are not
+    // held apart by Nagle's algorithm.
+    let mut reply = vec![0; URB_HEADER_SIZE + data.len()];
+    BE::write_u32(&mut reply, RET_SUBMIT);
+    BE::write_u32(&mut reply[4..], seqnum);
+    BE::write_i32(&mut reply[20..], status);
+    BE::write_u32(&mut reply[24..], actual_length as u32);
+    reply[URB_HEADER_SIZE..].copy_from_slice(data);
+    writer.write_all(&reply)?;
+    writer.flush()
+}
+
+/// Sends a `USBIP_RET_UNLINK` reply.
+pub(crate) fn write_ret_unlink<W: Write>(
+    writer: &mut W,
+    seqnum: u32,
+    status: i32,
+) -> io::Result<()> {
+    let mut header = [0; URB_HEADER_SIZE];
+    BE::write_u32(&mut header, RET_UNLINK);
+    BE::write_u32(&mut header[4..], seqnum);
+    BE::write_i32(&mut header[20..], status);
+    writer.write_all(&header)?;
+    writer.flush()
+}
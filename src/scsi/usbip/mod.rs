@@ -0,0 +1,15 @@
+//! Support for exporting a `ScsiResponder` as a virtual USB device with
+//! USB/IP.
+//!
+//! USB/IP carries USB request blocks over a TCP connection, so a host can
+//! attach a device that lives on another machine, or in another process, as
+//! if it had been plugged in locally. A `UsbIpServer` presents a Bulk-Only
+//! Mass Storage device backed by a `ScsiResponder`; on Linux, attaching it
+//! with `usbip attach -r 127.0.0.1 -b 1-1` after loading `vhci-hcd` runs the
+//! kernel's own `usb-storage` driver against the responder without any
+//! hardware.
+
+mod message;
+
+mod server;
+pub use self::server::*;
@@ -0,0 +1,870 @@
+use std::io::{self, Read, Write};
+use std::string::String;
+use std::vec::Vec;
+
+use byteorder::{ByteOrder, BE, LE};
+
+use crate::error::{ErrorCause, ScsiError, UsbTransferDirection};
+use crate::scsi::bulkonly::BulkOnlyTransport;
+use crate::scsi::commands::{CommandBlockWrapper, Direction};
+use crate::scsi::usbip::message::{
+    write_ret_submit, write_ret_unlink, OpHeader, Submit, UrbRequest, DEVICE_INFO_SIZE, DIR_IN,
+    DIR_OUT, OP_REP_DEVLIST, OP_REP_IMPORT, OP_REQ_DEVLIST, OP_REQ_IMPORT,
+};
+use crate::scsi::ScsiResponder;
+use crate::traits::BufferPullable;
+
+/// The bulk endpoints of the virtual device.
+const BULK_IN_ENDPOINT: u32 = 1;
+const BULK_OUT_ENDPOINT: u32 = 2;
+
+/// The USB/IP status of a transfer that stalled, `-EPIPE`.
+const STALL: i32 = -32;
+
+/// The USB/IP status of an import request for a device we do not have.
+const DEVICE_NOT_AVAILABLE: u32 = 1;
+
+/// The speed reported for the device, `USB_SPEED_HIGH`.
+const HIGH_SPEED: u32 = 3;
+
+/// The maximum packet size of the bulk endpoints at high speed.
+const MAX_PACKET_SIZE: u16 = 512;
+
+/// Standard requests.
+const GET_STATUS: u8 = 0x00;
+const CLEAR_FEATURE: u8 = 0x01;
+const SET_FEATURE: u8 = 0x03;
+const SET_ADDRESS: u8 = 0x05;
+const GET_DESCRIPTOR: u8 = 0x06;
+const GET_CONFIGURATION: u8 = 0x08;
+const SET_CONFIGURATION: u8 = 0x09;
+const GET_INTERFACE: u8 = 0x0a;
+const SET_INTERFACE: u8 = 0x0b;
+
+/// Mass Storage class requests.
+const MASS_STORAGE_RESET: u8 = 0xff;
+const GET_MAX_LUN: u8 = 0xfe;
+
+/// `bmRequestType` for class requests sent to an interface.
+const CLASS_INTERFACE_OUT: u8 = 0x21;
+const CLASS_INTERFACE_IN: u8 = 0xa1;
+
+/// Descriptor types.
+const DEVICE_DESCRIPTOR: u8 = 1;
+const CONFIGURATION_DESCRIPTOR: u8 = 2;
+const STRING_DESCRIPTOR: u8 = 3;
+const DEVICE_QUALIFIER_DESCRIPTOR: u8 = 6;
+
+/// The interface class, subclass and protocol of a Bulk-Only SCSI device.
+const MASS_STORAGE_CLASS: u8 = 0x08;
+const SCSI_TRANSPARENT_SUBCLASS: u8 = 0x06;
+const BULK_ONLY_PROTOCOL: u8 = 0x50;
+
+/// The parameters a server uses to describe its device.
+#[derive(Clone, Eq, PartialEq, Debug)]
+pub struct UsbIpOptions {
+    /// The bus ID clients import the device by, eg `1-1`.
+    pub bus_id: String,
+
+    /// The vendor ID in the device descriptor.
+    pub vendor_id: u16,
+
+    /// The product ID in the device descriptor.
+    pub product_id: u16,
+
+    /// The manufacturer string descriptor.
+    pub manufacturer: String,
+
+    /// The product string descriptor.
+    pub product: String,
+
+    /// The serial number string descriptor, which the Bulk-Only
+    /// specification requires to be at least 12 hexadecimal digits.
+    pub serial_number: String,
+}
+
+impl UsbIpOptions {
+    /// Constructs options for a device with the given bus ID, using the
+    /// `pid.codes` test vendor and product IDs.
+    pub fn new(bus_id: &str) -> UsbIpOptions {
+        UsbIpOptions {
+            bus_id: bus_id.to_string(),
+            vendor_id: 0x1209,
+            product_id: 0x0001,
+            manufacturer: "scsi-rs".to_string(),
+            product: "USB/IP Mass Storage".to_string(),
+            serial_number: "000000000001".to_string(),
+        }
+    }
+
+    /// Uses the given vendor and product IDs in the device descriptor.
+    pub fn with_ids(mut self, vendor_id: u16, product_id: u16) -> UsbIpOptions {
+        self.vendor_id = vendor_id;
+        self.product_id = product_id;
+        self
+    }
+
+    /// Uses the given serial number string.
+    pub fn with_serial_number(mut self, serial_number: &str) -> UsbIpOptions {
+        self.serial_number = serial_number.to_string();
+        self
+    }
+}
+
+/// The server side of a single USB/IP connection, which presents a virtual
+/// Bulk-Only Mass Storage device backed by a `ScsiResponder`.
+///
+/// Connections open with either a device list request, which is answered
+/// before the connection is closed, or an import request for the device's bus
+/// ID, after which the client sends USB request blocks for the default
+/// control pipe and the two bulk endpoints. Control requests are answered
+/// from the device's descriptors, and each CBW is run to completion before
+/// the next request is read, through a `BulkOnlyTransport` that behaves as it
+/// would on a real bus: commands failing or sending the host less than the
+/// CBW asked for end their data phase early, with the shortfall reported in
+/// the CSW's residue, and commands the responder does not implement fail
+/// with ILLEGAL REQUEST sense data passed to its `command_rejected` hook.
+///
+/// Only logical unit 0 is served.
+pub struct UsbIpServer<S: Read + Write> {
+    stream: S,
+    options: UsbIpOptions,
+    imported: bool,
+    configuration: u8,
+    reset: bool,
+}
+
+impl<S: Read + Write> UsbIpServer<S> {
+    /// Starts serving a newly accepted connection.
+    pub fn new(stream: S, options: UsbIpOptions) -> UsbIpServer<S> {
+        UsbIpServer {
+            stream,
+            options,
+            imported: false,
+            configuration: 0,
+            reset: false,
+        }
+    }
+
+    /// Whether the client has imported the device.
+    pub fn is_imported(&self) -> bool {
+        self.imported
+    }
+
+    /// Returns a reference to the underlying stream.
+    pub fn get_ref(&self) -> &S {
+        &self.stream
+    }
+
+    /// Returns a mutable reference to the underlying stream.
+    pub fn get_mut(&mut self) -> &mut S {
+        &mut self.stream
+    }
+
+    /// Answers the client's opening request, then runs its transfers
+    /// against `responder` until it closes the connection.
+    pub fn serve<R: ScsiResponder>(&mut self, responder: &mut R) -> Result<(), ScsiError> {
+        if !self.imported && !self.answer_request()? {
+            return Ok(());
+        }
+        while self.process_urb(responder)? {}
+        Ok(())
+    }
+
+    /// Answers the device list or import request that opens a connection.
+    ///
+    /// Returns whether the client imported the device; if not, the
+    /// connection is finished with.
+    pub fn answer_request(&mut self) -> Result<bool, ScsiError> {
+        let request = OpHeader::read_from(&mut self.stream)?;
+        match request.code {
+            OP_REQ_DEVLIST => {
+                let mut reply = Vec::with_capacity(OpHeader::SIZE + 4 + DEVICE_INFO_SIZE + 4);
+                let header = OpHeader {
+                    code: OP_REP_DEVLIST,
+                    status: 0,
+                };
+                reply.extend_from_slice(&header.to_bytes());
+                reply.extend_from_slice(&[0, 0, 0, 1]);
+                reply.extend_from_slice(&self.device_info());
+                reply.extend_from_slice(&[
+                    MASS_STORAGE_CLASS,
+                    SCSI_TRANSPARENT_SUBCLASS,
+                    BULK_ONLY_PROTOCOL,
+                    0,
+                ]);
+                self.stream.write_all(&reply)?;
+                self.stream.flush()?;
+                Ok(false)
+            }
+            OP_REQ_IMPORT => {
+                let mut bus_id = [0; 32];
+                self.stream.read_exact(&mut bus_id)?;
+                let length = bus_id.iter().position(|&byte| byte == 0).unwrap_or(32);
+                self.imported = &bus_id[..length] == self.options.bus_id.as_bytes();
+                let header = OpHeader {
+                    code: OP_REP_IMPORT,
+                    status: if self.imported {
+                        0
+                    } else {
+                        DEVICE_NOT_AVAILABLE
+                    },
+                };
+                self.stream.write_all(&header.to_bytes())?;
+                if self.imported {
+                    self.stream.write_all(&self.device_info())?;
+                }
+                self.stream.flush()?;
+                Ok(self.imported)
+            }
+            _ => Err(ScsiError::from_cause(ErrorCause::UnsupportedOperationError)),
+        }
+    }
+
+    /// Reads and answers a single request from the client, running the
+    /// whole command if it carries a CBW.
+    ///
+    /// Returns `false` once the client has closed the connection.
+    pub fn process_urb<R: ScsiResponder>(&mut self, responder: &mut R) -> Result<bool, ScsiError> {
+        let request = match UrbRequest::read_from(&mut self.stream)? {
+            Some(request) => request,
+            None => return Ok(false),
+        };
+        match request {
+            // Every transfer is finished before the next request is read, so
+            // there is never anything left to unlink.
+            UrbRequest::Unlink { seqnum, .. } => write_ret_unlink(&mut self.stream, seqnum, 0)?,
+            UrbRequest::Submit(submit) => {
+                if submit.endpoint == 0 {
+                    self.control(&submit)?;
+                } else if submit.endpoint == BULK_OUT_ENDPOINT && submit.direction == DIR_OUT {
+                    self.command(responder, &submit)?;
+                } else {
+                    // There is no data to send outside of a command.
+                    self.stall(&submit)?;
+                }
+            }
+        }
+        self.reset = false;
+        Ok(true)
+    }
+
+    /// Runs the command in a CBW through a `BulkOnlyTransport`, answering
+    /// the bulk transfers the client makes for it through to the CSW.
+    fn command<R: ScsiResponder>(
+        &mut self,
+        responder: &mut R,
+        submit: &Submit,
+    ) -> Result<(), ScsiError> {
+        let max_packet_size = usize::from(MAX_PACKET_SIZE);
+        let mut transport = BulkOnlyTransport::new(responder, max_packet_size);
+        let cbw = match CommandBlockWrapper::pull_from_buffer(&submit.data) {
+            Ok(cbw) if transport.receive_packet(responder, &submit.data).is_ok() => cbw,
+            _ => return self.stall(submit),
+        };
+        self.complete(submit, &[])?;
+
+        let mut remaining = if cbw.direction == Direction::OUT {
+            cbw.data_transfer_length as usize
+        } else {
+            0
+        };
+        while !transport.is_idle() {
+            let direction = if remaining > 0 { DIR_OUT } else { DIR_IN };
+            let submit = match self.next_transfer(direction) {
+                Ok(submit) => submit,
+                // The reset abandons the command along with its transport.
+                Err(_) if self.reset => return Ok(()),
+                Err(err) => return Err(err),
+            };
+            if direction == DIR_OUT {
+                let data = &submit.data[..submit.data.len().min(remaining)];
+                for packet in data.chunks(max_packet_size) {
+                    transport.receive_packet(responder, packet)?;
+                }
+                remaining -= data.len();
+                self.complete(&submit, &[])?;
+            } else {
+                // A transfer ends once it is full or the transport sends a
+                // short packet.
+                let mut data = Vec::with_capacity(submit.length);
+                let mut packet = [0; MAX_PACKET_SIZE as usize];
+                while data.len() < submit.length {
+                    let length = match transport.next_packet(responder, &mut packet)? {
+                        Some(length) => length,
+                        None => break,
+                    };
+                    let room = submit.length - data.len();
+                    data.extend_from_slice(&packet[..length.min(room)]);
+                    if length < max_packet_size {
+                        break;
+                    }
+                }
+                self.complete(&submit, &data)?;
+            }
+        }
+        Ok(())
+    }
+
+    /// Reads requests until the client asks for a transfer on the bulk
+    /// endpoint for `direction`, answering anything else in the meantime.
+    ///
+    /// # Errors
+    /// Returns a `UsbTransferError` if the host resets the device while it
+    /// waits, abandoning the command.
+    fn next_transfer(&mut self, direction: u32) -> Result<Submit, ScsiError> {
+        let endpoint = if direction == DIR_IN {
+            BULK_IN_ENDPOINT
+        } else {
+            BULK_OUT_ENDPOINT
+        };
+        loop {
+            let request = UrbRequest::read_from(&mut self.stream)?
+                .ok_or_else(|| ScsiError::from(io::Error::from(io::ErrorKind::UnexpectedEof)))?;
+            match request {
+                UrbRequest::Unlink { seqnum, .. } => write_ret_unlink(&mut self.stream, seqnum, 0)?,
+                UrbRequest::Submit(submit) if submit.endpoint == 0 => {
+                    self.control(&submit)?;
+                    if self.reset {
+                        return Err(ScsiError::from_cause(ErrorCause::UsbTransferError {
+                            direction: if direction == DIR_IN {
+                                UsbTransferDirection::Out
+                            } else {
+                                UsbTransferDirection::In
+                            },
+                        }));
+                    }
+                }
+                UrbRequest::Submit(submit)
+                    if submit.endpoint == endpoint && submit.direction == direction =>
+                {
+                    return Ok(submit)
+                }
+                UrbRequest::Submit(submit) => self.stall(&submit)?,
+            }
+        }
+    }
+
+    /// Answers a request on the default control pipe.
+    fn control(&mut self, submit: &Submit) -> Result<(), ScsiError> {
+        let setup = &submit.setup;
+        let (request_type, request) = (setup[0], setup[1]);
+        let value = LE::read_u16(&setup[2..]);
+        let length = usize::from(LE::read_u16(&setup[6..]));
+        let response = match (request_type, request) {
+            (0x80, GET_DESCRIPTOR) => self.descriptor(value),
+            (0x80, GET_CONFIGURATION) => Some(vec![self.configuration]),
+            (0x80..=0x82, GET_STATUS) => Some(vec![0, 0]),
+            (0x81, GET_INTERFACE) => Some(vec![0]),
+            (0x00, SET_CONFIGURATION) => {
+                self.configuration = value as u8;
+                Some(Vec::new())
+            }
+            (0x00, SET_ADDRESS)
+            | (0x00..=0x02, CLEAR_FEATURE)
+            | (0x00..=0x02, SET_FEATURE)
+            | (0x01, SET_INTERFACE) => Some(Vec::new()),
+            (CLASS_INTERFACE_OUT, MASS_STORAGE_RESET) => {
+                self.reset = true;
+                Some(Vec::new())
+            }
+            (CLASS_INTERFACE_IN, GET_MAX_LUN) => Some(vec![0]),
+            _ => None,
+        };
+        match response {
+            Some(mut response) => {
+                response.truncate(length.min(submit.length));
+                self.complete(submit, &response)
+            }
+            None => self.stall(submit),
+        }
+    }
+
+    /// Builds the descriptor a GET_DESCRIPTOR request asks for.
+    fn descriptor(&self, value: u16) -> Option<Vec<u8>> {
+        let options = &self.options;
+        let index = value as u8;
+        match (value >> 8) as u8 {
+            DEVICE_DESCRIPTOR => {
+                let mut descriptor = vec![
+                    18,
+                    DEVICE_DESCRIPTOR,
+                    0x00,
+                    0x02,
+                    0,
+                    0,
+                    0,
+                    64,
+                    0,
+                    0,
+                    0,
+                    0,
+                    0x00,
+                    0x01,
+                    1,
+                    2,
+                    3,
+                    1,
+                ];
+                LE::write_u16(&mut descriptor[8..], options.vendor_id);
+                LE::write_u16(&mut descriptor[10..], options.product_id);
+                Some(descriptor)
+            }
+            DEVICE_QUALIFIER_DESCRIPTOR => Some(vec![
+                10,
+                DEVICE_QUALIFIER_DESCRIPTOR,
+                0x00,
+                0x02,
+                0,
+                0,
+                0,
+                64,
+                1,
+                0,
+            ]),
+            CONFIGURATION_DESCRIPTOR if index == 0 => {
+                let [packet_low, packet_high] = MAX_PACKET_SIZE.to_le_bytes();
+                Some(vec![
+                    // Configuration
+                    9,
+                    CONFIGURATION_DESCRIPTOR,
+                    32,
+                    0,
+                    1,
+                    1,
+                    0,
+                    0x80,
+                    50,
+                    // Interface
+                    9,
+                    4,
+                    0,
+                    0,
+                    2,
+                    MASS_STORAGE_CLASS,
+                    SCSI_TRANSPARENT_SUBCLASS,
+                    BULK_ONLY_PROTOCOL,
+                    0,
+                    // Bulk IN endpoint
+                    7,
+                    5,
+                    0x80 | BULK_IN_ENDPOINT as u8,
+                    2,
+                    packet_low,
+                    packet_high,
+                    0,
+                    // Bulk OUT endpoint
+                    7,
+                    5,
+                    BULK_OUT_ENDPOINT as u8,
+                    2,
+                    packet_low,
+                    packet_high,
+                    0,
+                ])
+            }
+            STRING_DESCRIPTOR => {
+                let string = match index {
+                    // The only supported language, US English.
+                    0 => return Some(vec![4, STRING_DESCRIPTOR, 0x09, 0x04]),
+                    1 => &options.manufacturer,
+                    2 => &options.product,
+                    3 => &options.serial_number,
+                    _ => return None,
+                };
+                let mut descriptor = vec![0, STRING_DESCRIPTOR];
+                for unit in string.encode_utf16().take(126) {
+                    descriptor.extend_from_slice(&unit.to_le_bytes());
+                }
+                descriptor[0] = descriptor.len() as u8;
+                Some(descriptor)
+            }
+            _ => None,
+        }
+    }
+
+    /// The description of the device sent in device list and import replies.
+    fn device_info(&self) -> [u8; DEVICE_INFO_SIZE] {
+        let mut info = [0; DEVICE_INFO_SIZE];
+        let path = format!("/sys/devices/usbip/{}", self.options.bus_id);
+        let path_length = path.len().min(255);
+        info[..path_length].copy_from_slice(&path.as_bytes()[..path_length]);
+        let bus_id = self.options.bus_id.as_bytes();
+        let bus_id_length = bus_id.len().min(31);
+        info[256..256 + bus_id_length].copy_from_slice(&bus_id[..bus_id_length]);
+        BE::write_u32(&mut info[288..], 1);
+        BE::write_u32(&mut info[292..], 2);
+        BE::write_u32(&mut info[296..], HIGH_SPEED);
+        BE::write_u16(&mut info[300..], self.options.vendor_id);
+        BE::write_u16(&mut info[302..], self.options.product_id);
+        BE::write_u16(&mut info[304..], 0x0100);
+        // The class is given by the interface, and there is one configuration
+        // holding one interface.
+        info[309] = 1;
+        info[310] = 1;
+        info[311] = 1;
+        info
+    }
+
+    /// Completes a transfer successfully; `data` is only sent for IN
+    /// transfers.
+    fn complete(&mut self, submit: &Submit, data: &[u8]) -> Result<(), ScsiError> {
+        if submit.direction == DIR_IN {
+            write_ret_submit(&mut self.stream, submit.seqnum, 0, data.len(), data)?;
+        } else {
+            write_ret_submit(&mut self.stream, submit.seqnum, 0, submit.data.len(), &[])?;
+        }
+        Ok(())
+    }
+
+    fn stall(&mut self, submit: &Submit) -> Result<(), ScsiError> {
+        write_ret_submit(&mut self.stream, submit.seqnum, STALL, 0, &[])?;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{UsbIpOptions, UsbIpServer};
+    use crate::error::{ErrorCause, ScsiError};
+    use crate::scsi::commands::{
+        Command, CommandBlockWrapper, CommandStatusWrapper, Direction, Read10Command,
+        RequestSenseCommand, RequestSenseResponse, TestUnitReady, Write10Command,
+    };
+    use crate::scsi::responder::tests::MemoryResponder;
+    use crate::scsi::usbip::message::{
+        OpHeader, CMD_SUBMIT, CMD_UNLINK, DEVICE_INFO_SIZE, DIR_IN, DIR_OUT, OP_REP_DEVLIST,
+        OP_REP_IMPORT, OP_REQ_DEVLIST, OP_REQ_IMPORT, RET_SUBMIT, RET_UNLINK, URB_HEADER_SIZE,
+    };
+    use crate::scsi::ScsiBlockDevice;
+    use crate::traits::{BufferPullable, BufferPushable, CommunicationChannel};
+    use byteorder::{ByteOrder, BE, LE};
+    use std::io::{Read, Write};
+    use std::net::{Shutdown, TcpListener, TcpStream};
+    use std::thread::{self, JoinHandle};
+    use std::vec::Vec;
+
+    /// The host side of an imported device.
+    struct TestClient {
+        stream: TcpStream,
+        seqnum: u32,
+    }
+
+    impl TestClient {
+        fn submit(
+            &mut self,
+            direction: u32,
+            endpoint: u32,
+            setup: [u8; 8],
+            length: usize,
+            data: &[u8],
+        ) -> (i32, Vec<u8>) {
+            self.seqnum += 1;
+            let mut header = vec![0; URB_HEADER_SIZE];
+            BE::write_u32(&mut header, CMD_SUBMIT);
+            BE::write_u32(&mut header[4..], self.seqnum);
+            BE::write_u32(&mut header[8..], 0x0001_0002);
+            BE::write_u32(&mut header[12..], direction);
+            BE::write_u32(&mut header[16..], endpoint);
+            BE::write_u32(&mut header[24..], length as u32);
+            header[40..].copy_from_slice(&setup);
+            header.extend_from_slice(data);
+            self.stream.write_all(&header).unwrap();
+            header.truncate(URB_HEADER_SIZE);
+
+            self.stream.read_exact(&mut header).unwrap();
+            assert_eq!(BE::read_u32(&header), RET_SUBMIT);
+            assert_eq!(BE::read_u32(&header[4..]), self.seqnum);
+            let status = BE::read_i32(&header[20..]);
+            let actual_length = BE::read_u32(&header[24..]) as usize;
+            let mut data = vec![0; actual_length];
+            if direction == DIR_IN {
+                self.stream.read_exact(&mut data).unwrap();
+            }
+            (status, data)
+        }
+
+        fn control(&mut self, setup: [u8; 8]) -> (i32, Vec<u8>) {
+            let direction = if setup[0] & 0x80 != 0 {
+                DIR_IN
+            } else {
+                DIR_OUT
+            };
+            let length = usize::from(LE::read_u16(&setup[6..]));
+            self.submit(direction, 0, setup, length, &[])
+        }
+    }
+
+    impl CommunicationChannel for TestClient {
+        fn out_transfer<B: AsRef<[u8]>>(&mut self, bytes: B) -> Result<usize, ScsiError> {
+            let bytes = bytes.as_ref();
+            // Split writes up the way a host controller driver would.
+            let length = bytes.len().min(4096);
+            let (status, _) = self.submit(DIR_OUT, 2, [0; 8], length, &bytes[..length]);
+            assert_eq!(status, 0);
+            Ok(length)
+        }
+
+        fn in_transfer<B: AsMut<[u8]>>(&mut self, mut buffer: B) -> Result<usize, ScsiError> {
+            let buffer = buffer.as_mut();
+            let length = buffer.len().min(4096);
+            let (status, data) = self.submit(DIR_IN, 1, [0; 8], length, &[]);
+            assert_eq!(status, 0);
+            buffer[..data.len()].copy_from_slice(&data);
+            Ok(data.len())
+        }
+    }
+
+    fn spawn_server(connections: usize) -> (String, JoinHandle<Vec<Result<bool, ErrorCause>>>) {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let address = listener.local_addr().unwrap().to_string();
+        let server = thread::spawn(move || {
+            // Reads and writes before the first block fail with an error
+            // rather than sense data.
+            let mut responder = MemoryResponder::default();
+            responder.base_lba = 16;
+            (0..connections)
+                .map(|_| {
+                    let (stream, _) = listener.accept().unwrap();
+                    let mut server = UsbIpServer::new(stream, UsbIpOptions::new("1-1"));
+                    server.serve(&mut responder).map_err(|err| err.cause)?;
+                    Ok(server.is_imported())
+                })
+                .collect()
+        });
+        (address, server)
+    }
+
+    fn import(address: &str, bus_id: &str) -> (u32, TcpStream) {
+        let mut stream = TcpStream::connect(address).unwrap();
+        let header = OpHeader {
+            code: OP_REQ_IMPORT,
+            status: 0,
+        };
+        let mut request = header.to_bytes().to_vec();
+        request.extend_from_slice(bus_id.as_bytes());
+        request.resize(request.len() + 32 - bus_id.len(), 0);
+        stream.write_all(&request).unwrap();
+        let reply = OpHeader::read_from(&mut stream).unwrap();
+        assert_eq!(reply.code, OP_REP_IMPORT);
+        if reply.status == 0 {
+            let mut info = [0; DEVICE_INFO_SIZE];
+            stream.read_exact(&mut info).unwrap();
+            assert_eq!(&info[256..260], b"1-1\0");
+        }
+        (reply.status, stream)
+    }
+
+    #[test]
+    fn test_usbip_device_list() {
+        let (address, server) = spawn_server(2);
+        let mut stream = TcpStream::connect(&address).unwrap();
+        let header = OpHeader {
+            code: OP_REQ_DEVLIST,
+            status: 0,
+        };
+        stream.write_all(&header.to_bytes()).unwrap();
+        let reply = OpHeader::read_from(&mut stream).unwrap();
+        assert_eq!((reply.code, reply.status), (OP_REP_DEVLIST, 0));
+        let mut devices = [0; 4 + DEVICE_INFO_SIZE + 4];
+        stream.read_exact(&mut devices).unwrap();
+        assert_eq!(BE::read_u32(&devices), 1);
+        let info = &devices[4..];
+        assert_eq!(&info[256..260], b"1-1\0");
+        assert_eq!(BE::read_u16(&info[300..]), 0x1209);
+        assert_eq!(info[311], 1);
+        assert_eq!(&devices[4 + DEVICE_INFO_SIZE..], &[0x08, 0x06, 0x50, 0]);
+
+        let (status, _) = import(&address, "2-1");
+        assert_eq!(status, 1);
+        assert_eq!(server.join().unwrap(), vec![Ok(false), Ok(false)]);
+    }
+
+    #[test]
+    fn test_usbip_session() {
+        let (address, server) = spawn_server(1);
+        let (status, stream) = import(&address, "1-1");
+        assert_eq!(status, 0);
+        let mut client = TestClient { stream, seqnum: 0 };
+
+        // Enumeration.
+        let (status, device) = client.control([0x80, 0x06, 0, 1, 0, 0, 64, 0]);
+        assert_eq!((status, device.len()), (0, 18));
+        assert_eq!(LE::read_u16(&device[8..]), 0x1209);
+        let (_, config) = client.control([0x80, 0x06, 0, 2, 0, 0, 9, 0]);
+        assert_eq!(LE::read_u16(&config[2..]), 32);
+        let (_, config) = client.control([0x80, 0x06, 0, 2, 0, 0, 32, 0]);
+        assert_eq!(&config[14..17], &[0x08, 0x06, 0x50]);
+        assert_eq!(config[20], 0x81);
+        assert_eq!(config[27], 0x02);
+        let (_, product) = client.control([0x80, 0x06, 2, 3, 0x09, 0x04, 255, 0]);
+        assert_eq!(usize::from(product[0]), 2 + 2 * "USB/IP Mass Storage".len());
+        assert_eq!(client.control([0x00, 0x09, 1, 0, 0, 0, 0, 0]).0, 0);
+        assert_eq!(client.control([0xa1, 0xfe, 0, 0, 0, 0, 1, 0]), (0, vec![0]));
+        assert_eq!(client.control([0xc0, 0x33, 0, 0, 0, 0, 4, 0]).0, -32);
+
+        // Bulk-Only commands, through the same code as a real host.
+        let mut scratch = [0; 512];
+        let mut device = ScsiBlockDevice::new(client, &mut scratch[..]).unwrap();
+        assert_eq!(device.block_size(), 256);
+        let written: Vec<u8> = (0..256 * 40).map(|idx| (idx % 253) as u8).collect();
+        device.write_blocks(100, 40, &written).unwrap();
+        let mut read = vec![0; written.len()];
+        device.read_blocks(100, 40, &mut read).unwrap();
+        assert_eq!(read, written);
+        let mut client = device.comm_channel;
+
+        // MODE SENSE is not implemented, so its data phase ends with a
+        // zero-length packet and the responder keeps the sense data for the
+        // REQUEST SENSE that follows.
+        let mut cbw = CommandBlockWrapper::new(192, Direction::IN, 0, 6);
+        cbw.tag = 77;
+        let mut command = [0; 31];
+        cbw.push_to_buffer(&mut command[..]).unwrap();
+        command[15..21].copy_from_slice(&[0x1a, 0, 0x3f, 0, 192, 0]);
+        client.out_transfer(&command[..]).unwrap();
+        let mut data = [0xff; 192];
+        assert_eq!(client.in_transfer(&mut data[..]).unwrap(), 0);
+        let mut status = [0; 13];
+        client.in_transfer(&mut status[..]).unwrap();
+        let csw = CommandStatusWrapper::pull_from_buffer(&status[..]).unwrap();
+        assert_eq!((csw.tag, csw.status), (77, 1));
+        assert_eq!(csw.data_residue, 192);
+
+        RequestSenseCommand::new(18)
+            .push_to_buffer(&mut command[..])
+            .unwrap();
+        client.out_transfer(&command[..]).unwrap();
+        client.in_transfer(&mut data[..18]).unwrap();
+        let sense = RequestSenseResponse::pull_from_buffer(&data[..18]).unwrap();
+        assert_eq!(sense.sense_key, RequestSenseResponse::ILLEGAL_REQUEST);
+        assert_eq!(sense.additional_sense_code, 0x20);
+        client.in_transfer(&mut status[..]).unwrap();
+
+        // So are commands for any logical unit but the first, whatever data
+        // the host sends along with them.
+        let mut cbw = CommandBlockWrapper::new(512, Direction::OUT, 1, 10);
+        cbw.tag = 78;
+        cbw.push_to_buffer(&mut command[..]).unwrap();
+        client.out_transfer(&command[..]).unwrap();
+        client.out_transfer(&[0; 512][..]).unwrap();
+        client.in_transfer(&mut status[..]).unwrap();
+        let csw = CommandStatusWrapper::pull_from_buffer(&status[..]).unwrap();
+        assert_eq!((csw.tag, csw.status, csw.data_residue), (78, 1, 512));
+        RequestSenseCommand::new(18)
+            .push_to_buffer(&mut command[..])
+            .unwrap();
+        client.out_transfer(&command[..]).unwrap();
+        client.in_transfer(&mut data[..18]).unwrap();
+        let sense = RequestSenseResponse::pull_from_buffer(&data[..18]).unwrap();
+        assert_eq!(sense.additional_sense_code, 0x25);
+        client.in_transfer(&mut status[..]).unwrap();
+
+        // Other responder errors fail the command rather than the connection.
+        let read = Read10Command::new(0, 256, 256).unwrap();
+        read.push_to_buffer(&mut command[..]).unwrap();
+        client.out_transfer(&command[..]).unwrap();
+        assert_eq!(client.in_transfer(&mut data[..]).unwrap(), 0);
+        client.in_transfer(&mut status[..]).unwrap();
+        let csw = CommandStatusWrapper::pull_from_buffer(&status[..]).unwrap();
+        assert_eq!((csw.tag, csw.status), (read.wrapper().tag, 1));
+
+        // IN transfers outside of a command stall, and invalid CBWs are
+        // refused.
+        assert_eq!(client.submit(DIR_IN, 1, [0; 8], 13, &[]).0, -32);
+        assert_eq!(client.submit(DIR_OUT, 2, [0; 8], 4, &[0; 4]).0, -32);
+
+        let mut unlink = [0; URB_HEADER_SIZE];
+        BE::write_u32(&mut unlink, CMD_UNLINK);
+        BE::write_u32(&mut unlink[4..], 1000);
+        BE::write_u32(&mut unlink[20..], client.seqnum);
+        client.stream.write_all(&unlink).unwrap();
+        client.stream.read_exact(&mut unlink).unwrap();
+        assert_eq!(BE::read_u32(&unlink), RET_UNLINK);
+        assert_eq!(BE::read_u32(&unlink[4..]), 1000);
+        assert_eq!(BE::read_i32(&unlink[20..]), 0);
+
+        drop(client);
+        assert_eq!(server.join().unwrap(), vec![Ok(true)]);
+    }
+
+    #[test]
+    fn test_usbip_protocol_errors() {
+        let (address, server) = spawn_server(5);
+
+        // Requests from another protocol version, and ones the server does
+        // not know, end the connection.
+        let mut stream = TcpStream::connect(&address).unwrap();
+        stream
+            .write_all(&[0x01, 0x06, 0x80, 0x05, 0, 0, 0, 0])
+            .unwrap();
+        drop(stream);
+        let mut stream = TcpStream::connect(&address).unwrap();
+        let header = OpHeader {
+            code: 0x8004,
+            status: 0,
+        };
+        stream.write_all(&header.to_bytes()).unwrap();
+        drop(stream);
+
+        // A reset abandons the command in progress.
+        let (_, stream) = import(&address, "1-1");
+        let mut client = TestClient { stream, seqnum: 0 };
+        let mut command = [0; 31];
+        Read10Command::new(0, 512, 256)
+            .unwrap()
+            .push_to_buffer(&mut command[..])
+            .unwrap();
+        client.out_transfer(&command[..]).unwrap();
+        assert_eq!(client.control([0x21, 0xff, 0, 0, 0, 0, 0, 0]).0, 0);
+        let unit_ready = TestUnitReady::new();
+        unit_ready.push_to_buffer(&mut command[..]).unwrap();
+        client.out_transfer(&command[..]).unwrap();
+        let mut status = [0; 13];
+        assert_eq!(client.in_transfer(&mut status[..]).unwrap(), 13);
+        let csw = CommandStatusWrapper::pull_from_buffer(&status[..]).unwrap();
+        assert_eq!((csw.tag, csw.status), (unit_ready.wrapper().tag, 0));
+        drop(client);
+
+        // A host that goes away in the middle of a write.
+        let (_, stream) = import(&address, "1-1");
+        let mut client = TestClient { stream, seqnum: 0 };
+        Write10Command::new(0, 512, 256)
+            .unwrap()
+            .push_to_buffer(&mut command[..])
+            .unwrap();
+        client.out_transfer(&command[..]).unwrap();
+        let mut submit = [0; URB_HEADER_SIZE + 256];
+        BE::write_u32(&mut submit, CMD_SUBMIT);
+        BE::write_u32(&mut submit[12..], DIR_OUT);
+        BE::write_u32(&mut submit[16..], 2);
+        BE::write_u32(&mut submit[24..], 256);
+        client.stream.write_all(&submit).unwrap();
+        // Close only our side and wait for the server to hang up, so the
+        // unread reply to the URB cannot turn the end of the stream into a
+        // reset.
+        client.stream.shutdown(Shutdown::Write).unwrap();
+        client.stream.read_to_end(&mut Vec::new()).unwrap();
+        drop(client);
+
+        // URBs with an unknown command.
+        let (_, mut stream) = import(&address, "1-1");
+        let mut urb = [0; URB_HEADER_SIZE];
+        BE::write_u32(&mut urb, 7);
+        stream.write_all(&urb).unwrap();
+        drop(stream);
+
+        assert_eq!(
+            server.join().unwrap(),
+            vec![
+                Err(ErrorCause::ParseError),
+                Err(ErrorCause::UnsupportedOperationError),
+                Ok(true),
+                Err(ErrorCause::IoError {
+                    kind: std::io::ErrorKind::UnexpectedEof
+                }),
+                Err(ErrorCause::ParseError),
+            ]
+        );
+    }
+}
@@ -1,4 +1,5 @@
-use error::ScsiError;
+use crate::error::ScsiError;
+use crate::scsi::commands::ScsiStatus;
 ///
 /// The trait that all communication devices should implement if they are to be
 /// used to transfer SCSI information.
@@ -10,6 +11,43 @@ pub trait CommunicationChannel {
     /// Reads bytes from the channel up to the point where the buffer is filled.
     /// Returns the number of bytes successfully read.
     fn in_transfer<B: AsMut<[u8]>>(&mut self, buffer: B) -> Result<usize, ScsiError>;
+
+    /// The full SCSI status of the command whose CSW was read last, for
+    /// channels on top of transports that report more than Bulk-Only's pass
+    /// or fail, such as RESERVATION CONFLICT. Hosts use it instead of the
+    /// CSW's status when it is given.
+    ///
+    /// Returns `None` by default.
+    fn scsi_status(&self) -> Option<ScsiStatus> {
+        None
+    }
+}
+
+/// The asynchronous counterpart of `CommunicationChannel`, for channels that
+/// are driven by an executor such as `embassy` or `tokio` rather than blocking
+/// the calling thread.
+///
+/// The returned futures are not required to be `Send`, so that channels built
+/// on single-threaded executors can implement the trait.
+#[allow(async_fn_in_trait)]
+pub trait AsyncCommunicationChannel {
+    /// Sends the bytes currently stored in a buffer over the communication channel.
+    /// Returns the number of bytes sent.
+    async fn out_transfer<B: AsRef<[u8]>>(&mut self, bytes: B) -> Result<usize, ScsiError>;
+
+    /// Reads bytes from the channel up to the point where the buffer is filled.
+    /// Returns the number of bytes successfully read.
+    async fn in_transfer<B: AsMut<[u8]>>(&mut self, buffer: B) -> Result<usize, ScsiError>;
+
+    /// The full SCSI status of the command whose CSW was read last, for
+    /// channels on top of transports that report more than Bulk-Only's pass
+    /// or fail, such as RESERVATION CONFLICT. Hosts use it instead of the
+    /// CSW's status when it is given.
+    ///
+    /// Returns `None` by default.
+    fn scsi_status(&self) -> Option<ScsiStatus> {
+        None
+    }
 }
 
 /// Allows a struct to serialize itself to a raw byte buffer.